@@ -1,3 +1,7 @@
+use axerrno::AxResult;
+
+use super::as_ax_err;
+
 pub use axdisplay::DisplayInfo as AxDisplayInfo;
 
 /// Gets the framebuffer information.
@@ -9,3 +13,18 @@ pub fn ax_framebuffer_info() -> AxDisplayInfo {
 pub fn ax_framebuffer_flush() {
     axdisplay::framebuffer_flush()
 }
+
+/// Flushes a rectangle of the framebuffer to the screen.
+pub fn ax_framebuffer_flush_region(x: u32, y: u32, width: u32, height: u32) -> AxResult {
+    axdisplay::framebuffer_flush_region(x, y, width, height).map_err(as_ax_err)
+}
+
+/// Gets the information of the back buffer.
+pub fn ax_framebuffer_back_info() -> AxDisplayInfo {
+    axdisplay::back_buffer_info()
+}
+
+/// Shows the back buffer on the screen, and swaps it with the framebuffer.
+pub fn ax_framebuffer_present() -> AxResult {
+    axdisplay::present().map_err(as_ax_err)
+}
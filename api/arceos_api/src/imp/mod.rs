@@ -16,6 +16,39 @@ cfg_display! {
     pub use display::*;
 }
 
+cfg_sound! {
+    mod sound;
+    pub use sound::*;
+}
+
+cfg_pci! {
+    pub use axdriver::pci::{
+        enumerate as ax_pci_enumerate, PciBar as AxPciBar, PciBarKind as AxPciBarKind,
+        PciDeviceInfo as AxPciDeviceInfo,
+    };
+}
+
+cfg_input! {
+    pub use axruntime::input::{read_event as ax_read_input_event, InputEvent as AxInputEvent};
+}
+
+/// Converts a device error into the error of the APIs.
+#[cfg(any(feature = "display", feature = "sound"))]
+const fn as_ax_err(e: axdriver::prelude::DevError) -> axerrno::AxError {
+    use axdriver::prelude::DevError;
+    use axerrno::AxError;
+    match e {
+        DevError::AlreadyExists => AxError::AlreadyExists,
+        DevError::Again => AxError::WouldBlock,
+        DevError::BadState => AxError::BadState,
+        DevError::InvalidParam => AxError::InvalidInput,
+        DevError::Io => AxError::Io,
+        DevError::NoMemory => AxError::NoMemory,
+        DevError::ResourceBusy => AxError::ResourceBusy,
+        DevError::Unsupported => AxError::Unsupported,
+    }
+}
+
 mod stdio {
     use core::fmt;
 
@@ -37,6 +70,10 @@ mod stdio {
     pub fn ax_console_write_fmt(args: fmt::Arguments) -> fmt::Result {
         axlog::print_fmt(args)
     }
+
+    pub fn ax_console_wait_readable() -> bool {
+        axhal::console::wait_readable()
+    }
 }
 
 mod time {
@@ -50,5 +87,8 @@ pub use self::stdio::*;
 pub use self::task::*;
 pub use self::time::*;
 
-pub use axhal::misc::terminate as ax_terminate;
+pub use axhal::misc::{
+    random_u64 as ax_random_u64, reboot as ax_reboot, terminate as ax_terminate,
+};
 pub use axio::PollState as AxPollState;
+pub use axruntime::random::fill_random as ax_fill_random;
@@ -2,6 +2,9 @@ use crate::io::AxPollState;
 use axerrno::AxResult;
 use axnet::{UdpSocket, TcpSocket};
 use core::net::{IpAddr, SocketAddr};
+use core::time::Duration;
+
+pub use axnet::NetStats as AxNetStats;
 
 /// A handle to a TCP socket.
 pub struct AxTcpSocketHandle(TcpSocket);
@@ -30,6 +33,48 @@ pub fn ax_tcp_set_nonblocking(socket: &AxTcpSocketHandle, nonblocking: bool) ->
     Ok(())
 }
 
+pub fn ax_tcp_set_reuse_addr(socket: &AxTcpSocketHandle, reuse_addr: bool) -> AxResult {
+    socket.0.set_reuse_addr(reuse_addr);
+    Ok(())
+}
+
+pub fn ax_tcp_reuse_addr(socket: &AxTcpSocketHandle) -> AxResult<bool> {
+    Ok(socket.0.reuse_addr())
+}
+
+pub fn ax_tcp_set_nodelay(socket: &AxTcpSocketHandle, nodelay: bool) -> AxResult {
+    socket.0.set_nodelay(nodelay);
+    Ok(())
+}
+
+pub fn ax_tcp_nodelay(socket: &AxTcpSocketHandle) -> AxResult<bool> {
+    Ok(socket.0.nodelay())
+}
+
+pub fn ax_tcp_set_ttl(socket: &AxTcpSocketHandle, ttl: u8) -> AxResult {
+    socket.0.set_ttl(ttl)
+}
+
+pub fn ax_tcp_ttl(socket: &AxTcpSocketHandle) -> AxResult<u8> {
+    Ok(socket.0.ttl())
+}
+
+pub fn ax_tcp_set_recv_buffer_size(socket: &AxTcpSocketHandle, size: usize) -> AxResult {
+    socket.0.set_recv_buffer_size(size)
+}
+
+pub fn ax_tcp_recv_buffer_size(socket: &AxTcpSocketHandle) -> AxResult<usize> {
+    Ok(socket.0.recv_buffer_size())
+}
+
+pub fn ax_tcp_set_send_buffer_size(socket: &AxTcpSocketHandle, size: usize) -> AxResult {
+    socket.0.set_send_buffer_size(size)
+}
+
+pub fn ax_tcp_send_buffer_size(socket: &AxTcpSocketHandle) -> AxResult<usize> {
+    Ok(socket.0.send_buffer_size())
+}
+
 pub fn ax_tcp_connect(socket: &AxTcpSocketHandle, addr: SocketAddr) -> AxResult {
     socket.0.connect(addr)
 }
@@ -85,6 +130,48 @@ pub fn ax_udp_set_nonblocking(socket: &AxUdpSocketHandle, nonblocking: bool) ->
     Ok(())
 }
 
+pub fn ax_udp_set_reuse_addr(socket: &AxUdpSocketHandle, reuse_addr: bool) -> AxResult {
+    socket.0.set_reuse_addr(reuse_addr);
+    Ok(())
+}
+
+pub fn ax_udp_reuse_addr(socket: &AxUdpSocketHandle) -> AxResult<bool> {
+    Ok(socket.0.reuse_addr())
+}
+
+pub fn ax_udp_set_read_timeout(socket: &AxUdpSocketHandle, timeout: Option<Duration>) -> AxResult {
+    socket.0.set_read_timeout(timeout)
+}
+
+pub fn ax_udp_read_timeout(socket: &AxUdpSocketHandle) -> AxResult<Option<Duration>> {
+    Ok(socket.0.read_timeout())
+}
+
+pub fn ax_udp_set_write_timeout(socket: &AxUdpSocketHandle, timeout: Option<Duration>) -> AxResult {
+    socket.0.set_write_timeout(timeout)
+}
+
+pub fn ax_udp_write_timeout(socket: &AxUdpSocketHandle) -> AxResult<Option<Duration>> {
+    Ok(socket.0.write_timeout())
+}
+
+pub fn ax_udp_set_broadcast(socket: &AxUdpSocketHandle, broadcast: bool) -> AxResult {
+    socket.0.set_broadcast(broadcast);
+    Ok(())
+}
+
+pub fn ax_udp_broadcast(socket: &AxUdpSocketHandle) -> AxResult<bool> {
+    Ok(socket.0.broadcast())
+}
+
+pub fn ax_udp_set_ttl(socket: &AxUdpSocketHandle, ttl: u8) -> AxResult {
+    socket.0.set_ttl(ttl)
+}
+
+pub fn ax_udp_ttl(socket: &AxUdpSocketHandle) -> AxResult<u8> {
+    Ok(socket.0.ttl())
+}
+
 pub fn ax_udp_bind(socket: &AxUdpSocketHandle, addr: SocketAddr) -> AxResult {
     socket.0.bind(addr)
 }
@@ -129,3 +216,7 @@ pub fn ax_poll_interfaces() -> AxResult {
     axnet::poll_interfaces();
     Ok(())
 }
+
+pub fn ax_net_interface_stats() -> alloc::vec::Vec<(alloc::string::String, AxNetStats)> {
+    axnet::interface_stats()
+}
@@ -0,0 +1,20 @@
+use axerrno::AxResult;
+use axruntime::sound;
+
+use super::as_ax_err;
+
+pub use axruntime::sound::{
+    PcmFormat as AxPcmFormat, PcmParams as AxPcmParams, PcmRate as AxPcmRate,
+};
+
+pub fn ax_sound_start(params: &AxPcmParams) -> AxResult {
+    sound::start_playback(params).map_err(as_ax_err)
+}
+
+pub fn ax_sound_write(data: &[u8]) -> AxResult {
+    sound::write_period(data).map_err(as_ax_err)
+}
+
+pub fn ax_sound_stop() -> AxResult {
+    sound::stop_playback().map_err(as_ax_err)
+}
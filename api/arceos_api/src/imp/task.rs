@@ -42,6 +42,9 @@ cfg_task! {
     /// A mask to specify the CPU affinity.
     pub use axtask::AxCpuMask;
 
+    /// The information of a task, see [`ax_for_each_task`].
+    pub use axtask::TaskInfo as AxTaskInfo;
+
     /// A handle to a wait queue.
     ///
     /// A wait queue is used to store sleeping tasks waiting for a certain event
@@ -59,15 +62,25 @@ cfg_task! {
         axtask::current().id().as_u64()
     }
 
-    pub fn ax_spawn<F>(f: F, name: alloc::string::String, stack_size: usize) -> AxTaskHandle
+    pub fn ax_spawn<F>(
+        f: F,
+        name: alloc::string::String,
+        stack_size: usize,
+    ) -> crate::AxResult<AxTaskHandle>
     where
         F: FnOnce() + Send + 'static,
     {
-        let inner = axtask::spawn_raw(f, name, stack_size);
-        AxTaskHandle {
+        if stack_size < axtask::MIN_STACK_SIZE {
+            return axerrno::ax_err!(InvalidInput, "ax_spawn: stack size too small");
+        }
+        let inner = axtask::Builder::new()
+            .name(name)
+            .stack_size(stack_size)
+            .spawn(f);
+        Ok(AxTaskHandle {
             id: inner.id().as_u64(),
             inner,
-        }
+        })
     }
 
     pub fn ax_wait_for_exit(task: AxTaskHandle) -> Option<i32> {
@@ -75,7 +88,7 @@ cfg_task! {
     }
 
     pub fn ax_set_current_priority(prio: isize) -> crate::AxResult {
-        if axtask::set_priority(prio) {
+        if axtask::set_current_priority(prio) {
             Ok(())
         } else {
             axerrno::ax_err!(
@@ -85,6 +98,18 @@ cfg_task! {
         }
     }
 
+    pub fn ax_set_priority(task: &AxTaskHandle, prio: isize) -> crate::AxResult {
+        if axtask::set_priority(&task.inner, prio) {
+            Ok(())
+        } else {
+            axerrno::ax_err!(BadState, "ax_set_priority: failed to set task priority")
+        }
+    }
+
+    pub fn ax_current_priority() -> isize {
+        axtask::current_priority()
+    }
+
     pub fn ax_set_current_affinity(cpumask: AxCpuMask) -> crate::AxResult {
         if axtask::set_current_affinity(cpumask) {
             Ok(())
@@ -96,10 +121,22 @@ cfg_task! {
         }
     }
 
+    pub fn ax_set_affinity(task: &AxTaskHandle, cpumask: AxCpuMask) -> crate::AxResult {
+        if axtask::set_affinity(&task.inner, cpumask) {
+            Ok(())
+        } else {
+            axerrno::ax_err!(InvalidInput, "ax_set_affinity: no online CPU in the mask")
+        }
+    }
+
+    pub fn ax_for_each_task(f: impl FnMut(&AxTaskInfo)) {
+        axtask::for_each_task(f)
+    }
+
     pub fn ax_wait_queue_wait(wq: &AxWaitQueueHandle, timeout: Option<Duration>) -> bool {
         #[cfg(feature = "irq")]
         if let Some(dur) = timeout {
-            return wq.0.wait_timeout(dur);
+            return wq.0.wait_timeout(dur).timed_out();
         }
 
         if timeout.is_some() {
@@ -116,13 +153,15 @@ cfg_task! {
     ) -> bool {
         #[cfg(feature = "irq")]
         if let Some(dur) = timeout {
-            return wq.0.wait_timeout_until(dur, until_condition);
+            return wq.0
+                .wait_timeout_until(dur, until_condition)
+                .timed_out();
         }
 
         if timeout.is_some() {
             axlog::warn!("ax_wait_queue_wait_until: the `timeout` argument is ignored without the `irq` feature");
         }
-        wq.0.wait_until(until_condition);
+        wq.0.wait_until_noncancelable(until_condition);
         false
     }
 
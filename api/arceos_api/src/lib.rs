@@ -32,6 +32,8 @@ pub mod sys {
     define_api! {
         /// Shutdown the whole system and all CPUs.
         pub fn ax_terminate() -> !;
+        /// Reboot the whole system and all CPUs.
+        pub fn ax_reboot() -> !;
     }
 }
 
@@ -49,6 +51,21 @@ pub mod time {
     }
 }
 
+/// Random number generation.
+pub mod random {
+    define_api! {
+        /// Fills `buf` with cryptographically secure random bytes.
+        ///
+        /// The generator is seeded from the random number generator of the
+        /// CPU or the device if present, otherwise the output may be
+        /// predictable across boots.
+        pub fn ax_fill_random(buf: &mut [u8]);
+        /// Returns a random number from the random number generator of the
+        /// CPU, or `None` if there is none.
+        pub fn ax_random_u64() -> Option<u64>;
+    }
+}
+
 /// Memory management.
 pub mod mem {
     use core::{alloc::Layout, ptr::NonNull};
@@ -112,6 +129,9 @@ pub mod stdio {
         pub fn ax_console_write_bytes(buf: &[u8]) -> crate::AxResult<usize>;
         /// Writes a formatted string to the console.
         pub fn ax_console_write_fmt(args: fmt::Arguments) -> fmt::Result;
+        /// Blocks until there is console input. Returns `false` immediately
+        /// if the input can not be waited for, then the caller should poll.
+        pub fn ax_console_wait_readable() -> bool;
     }
 }
 
@@ -122,10 +142,12 @@ pub mod task {
         pub type AxTaskHandle;
         pub type AxWaitQueueHandle;
         pub type AxCpuMask;
+        pub type AxTaskInfo;
     }
 
     define_api! {
-        /// Current task is going to sleep, it will be woken up at the given deadline.
+        /// Current task is going to sleep, it will be woken up at the given deadline
+        /// of the monotonic clock.
         ///
         /// If the feature `multitask` is not enabled, it uses busy-wait instead
         pub fn ax_sleep_until(deadline: crate::time::AxTimeValue);
@@ -146,18 +168,31 @@ pub mod task {
         /// Returns the current task's ID.
         pub fn ax_current_task_id() -> u64;
         /// Spawns a new task with the given entry point and other arguments.
+        ///
+        /// Returns an error if the stack size is less than the minimum.
         pub fn ax_spawn(
             f: impl FnOnce() + Send + 'static,
             name: alloc::string::String,
             stack_size: usize
-        ) -> AxTaskHandle;
+        ) -> crate::AxResult<AxTaskHandle>;
         /// Waits for the given task to exit, and returns its exit code (the
         /// argument of [`ax_exit`]).
         pub fn ax_wait_for_exit(task: AxTaskHandle) -> Option<i32>;
         /// Sets the priority of the current task.
         pub fn ax_set_current_priority(prio: isize) -> crate::AxResult;
+        /// Sets the priority of the given task.
+        ///
+        /// The range and meaning of the priority depend on the scheduler.
+        pub fn ax_set_priority(task: &AxTaskHandle, prio: isize) -> crate::AxResult;
+        /// Returns the priority of the current task.
+        pub fn ax_current_priority() -> isize;
         /// Sets the cpu affinity of the current task.
         pub fn ax_set_current_affinity(cpumask: AxCpuMask) -> crate::AxResult;
+        /// Sets the cpu affinity of the given task.
+        pub fn ax_set_affinity(task: &AxTaskHandle, cpumask: AxCpuMask) -> crate::AxResult;
+        /// Calls `f` with the information of each task alive, in ascending
+        /// order of the task ID.
+        pub fn ax_for_each_task(f: impl FnMut(&AxTaskInfo));
         /// Blocks the current task and put it into the wait queue, until
         /// other tasks notify the wait queue, or the the given duration has
         /// elapsed (if specified).
@@ -267,11 +302,13 @@ pub mod fs {
 pub mod net {
     use crate::{io::AxPollState, AxResult};
     use core::net::{IpAddr, SocketAddr};
+    use core::time::Duration;
 
     define_api_type! {
         @cfg "net";
         pub type AxTcpSocketHandle;
         pub type AxUdpSocketHandle;
+        pub type AxNetStats;
     }
 
     define_api! {
@@ -287,6 +324,29 @@ pub mod net {
         pub fn ax_tcp_peer_addr(socket: &AxTcpSocketHandle) -> AxResult<SocketAddr>;
         /// Moves this TCP socket into or out of nonblocking mode.
         pub fn ax_tcp_set_nonblocking(socket: &AxTcpSocketHandle, nonblocking: bool) -> AxResult;
+        /// Allows or forbids the TCP socket to bind an address in use, i.e.,
+        /// `SO_REUSEADDR`.
+        pub fn ax_tcp_set_reuse_addr(socket: &AxTcpSocketHandle, reuse_addr: bool) -> AxResult;
+        /// Returns whether the TCP socket may bind an address in use.
+        pub fn ax_tcp_reuse_addr(socket: &AxTcpSocketHandle) -> AxResult<bool>;
+        /// Disables or enables the Nagle algorithm on the TCP socket.
+        pub fn ax_tcp_set_nodelay(socket: &AxTcpSocketHandle, nodelay: bool) -> AxResult;
+        /// Returns whether the Nagle algorithm is disabled on the TCP socket.
+        pub fn ax_tcp_nodelay(socket: &AxTcpSocketHandle) -> AxResult<bool>;
+        /// Sets the time-to-live of the IP packets sent from the TCP socket.
+        pub fn ax_tcp_set_ttl(socket: &AxTcpSocketHandle, ttl: u8) -> AxResult;
+        /// Returns the time-to-live of the IP packets sent from the TCP socket.
+        pub fn ax_tcp_ttl(socket: &AxTcpSocketHandle) -> AxResult<u8>;
+        /// Sets the size of the receive buffer of the TCP socket, before it is
+        /// connected or listening.
+        pub fn ax_tcp_set_recv_buffer_size(socket: &AxTcpSocketHandle, size: usize) -> AxResult;
+        /// Returns the size of the receive buffer of the TCP socket.
+        pub fn ax_tcp_recv_buffer_size(socket: &AxTcpSocketHandle) -> AxResult<usize>;
+        /// Sets the size of the send buffer of the TCP socket, before it is
+        /// connected or listening.
+        pub fn ax_tcp_set_send_buffer_size(socket: &AxTcpSocketHandle, size: usize) -> AxResult;
+        /// Returns the size of the send buffer of the TCP socket.
+        pub fn ax_tcp_send_buffer_size(socket: &AxTcpSocketHandle) -> AxResult<usize>;
 
         /// Connects the TCP socket to the given address and port.
         pub fn ax_tcp_connect(handle: &AxTcpSocketHandle, addr: SocketAddr) -> AxResult;
@@ -320,6 +380,27 @@ pub mod net {
         pub fn ax_udp_peer_addr(socket: &AxUdpSocketHandle) -> AxResult<SocketAddr>;
         /// Moves this UDP socket into or out of nonblocking mode.
         pub fn ax_udp_set_nonblocking(socket: &AxUdpSocketHandle, nonblocking: bool) -> AxResult;
+        /// Allows or forbids the UDP socket to bind an address in use, i.e.,
+        /// `SO_REUSEADDR`.
+        pub fn ax_udp_set_reuse_addr(socket: &AxUdpSocketHandle, reuse_addr: bool) -> AxResult;
+        /// Returns whether the UDP socket may bind an address in use.
+        pub fn ax_udp_reuse_addr(socket: &AxUdpSocketHandle) -> AxResult<bool>;
+        /// Sets the read timeout of the UDP socket, `None` to block indefinitely.
+        pub fn ax_udp_set_read_timeout(socket: &AxUdpSocketHandle, timeout: Option<Duration>) -> AxResult;
+        /// Returns the read timeout of the UDP socket.
+        pub fn ax_udp_read_timeout(socket: &AxUdpSocketHandle) -> AxResult<Option<Duration>>;
+        /// Sets the write timeout of the UDP socket, `None` to block indefinitely.
+        pub fn ax_udp_set_write_timeout(socket: &AxUdpSocketHandle, timeout: Option<Duration>) -> AxResult;
+        /// Returns the write timeout of the UDP socket.
+        pub fn ax_udp_write_timeout(socket: &AxUdpSocketHandle) -> AxResult<Option<Duration>>;
+        /// Allows or forbids the UDP socket to send to the broadcast address.
+        pub fn ax_udp_set_broadcast(socket: &AxUdpSocketHandle, broadcast: bool) -> AxResult;
+        /// Returns whether the UDP socket may send to the broadcast address.
+        pub fn ax_udp_broadcast(socket: &AxUdpSocketHandle) -> AxResult<bool>;
+        /// Sets the time-to-live of the IP packets sent from the UDP socket.
+        pub fn ax_udp_set_ttl(socket: &AxUdpSocketHandle, ttl: u8) -> AxResult;
+        /// Returns the time-to-live of the IP packets sent from the UDP socket.
+        pub fn ax_udp_ttl(socket: &AxUdpSocketHandle) -> AxResult<u8>;
 
         /// Binds the UDP socket to the given address and port.
         pub fn ax_udp_bind(socket: &AxUdpSocketHandle, addr: SocketAddr) -> AxResult;
@@ -355,6 +436,9 @@ pub mod net {
         /// It may receive packets from the NIC and process them, and transmit queued
         /// packets to the NIC.
         pub fn ax_poll_interfaces() -> AxResult;
+        /// Returns the names and the packet and error counters of all network
+        /// interfaces.
+        pub fn ax_net_interface_stats() -> alloc::vec::Vec<(alloc::string::String, AxNetStats)>;
     }
 }
 
@@ -371,6 +455,69 @@ pub mod display {
         pub fn ax_framebuffer_info() -> AxDisplayInfo;
         /// Flushes the framebuffer, i.e. show on the screen.
         pub fn ax_framebuffer_flush();
+        /// Flushes a rectangle of the framebuffer to the screen.
+        pub fn ax_framebuffer_flush_region(x: u32, y: u32, width: u32, height: u32) -> crate::AxResult;
+        /// Gets the information of the back buffer, in which the next frame is
+        /// drawn.
+        pub fn ax_framebuffer_back_info() -> AxDisplayInfo;
+        /// Shows the back buffer on the screen, and swaps it with the
+        /// framebuffer.
+        pub fn ax_framebuffer_present() -> crate::AxResult;
+    }
+}
+
+/// Keyboard and mouse input.
+pub mod input {
+    define_api_type! {
+        @cfg "input";
+        pub type AxInputEvent;
+    }
+
+    define_api! {
+        @cfg "input";
+        /// Pops the oldest pending event from the input devices, returns
+        /// [`None`] if there is no pending event.
+        pub fn ax_read_input_event() -> Option<AxInputEvent>;
+    }
+}
+
+/// Sound playback.
+pub mod sound {
+    define_api_type! {
+        @cfg "sound";
+        pub type AxPcmParams;
+        pub type AxPcmFormat;
+        pub type AxPcmRate;
+    }
+
+    define_api! {
+        @cfg "sound";
+        /// Opens an output stream with the given parameters for playback.
+        pub fn ax_sound_start(params: &AxPcmParams) -> crate::AxResult;
+        /// Plays one period of frames, waiting while the device queue is full.
+        ///
+        /// Returns [`AxError::BadState`](crate::AxError::BadState) if an
+        /// underrun happened before this period, the period is played anyway.
+        pub fn ax_sound_write(data: &[u8]) -> crate::AxResult;
+        /// Stops the playback and releases the stream.
+        pub fn ax_sound_stop() -> crate::AxResult;
+    }
+}
+
+/// Listing of the PCI functions.
+pub mod pci {
+    define_api_type! {
+        @cfg "pci";
+        pub type AxPciDeviceInfo;
+        pub type AxPciBar;
+        pub type AxPciBarKind;
+    }
+
+    define_api! {
+        @cfg "pci";
+        /// Walks the PCI hierarchy through the bridges and lists all functions,
+        /// with their BARs and the drivers that claimed them.
+        pub fn ax_pci_enumerate() -> alloc::vec::Vec<AxPciDeviceInfo>;
     }
 }
 
@@ -398,7 +545,14 @@ pub mod modules {
     pub use axdisplay;
     #[cfg(feature = "dma")]
     pub use axdma;
-    #[cfg(any(feature = "fs", feature = "net", feature = "display"))]
+    #[cfg(any(
+        feature = "fs",
+        feature = "net",
+        feature = "display",
+        feature = "input",
+        feature = "sound",
+        feature = "pci"
+    ))]
     pub use axdriver;
     #[cfg(feature = "fs")]
     pub use axfs;
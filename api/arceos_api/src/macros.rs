@@ -105,6 +105,18 @@ macro_rules! cfg_display {
     ($($item:item)*) => { _cfg_common!{ "display" $($item)* } }
 }
 
+macro_rules! cfg_input {
+    ($($item:item)*) => { _cfg_common!{ "input" $($item)* } }
+}
+
+macro_rules! cfg_sound {
+    ($($item:item)*) => { _cfg_common!{ "sound" $($item)* } }
+}
+
+macro_rules! cfg_pci {
+    ($($item:item)*) => { _cfg_common!{ "pci" $($item)* } }
+}
+
 macro_rules! cfg_task {
     ($($item:item)*) => { _cfg_common!{ "multitask" $($item)* } }
 }
@@ -4,10 +4,14 @@ fn main() {
     fn gen_pthread_mutex(out_file: &str) -> std::io::Result<()> {
         // TODO: generate size and initial content automatically.
         let (mutex_size, mutex_init) = if cfg!(feature = "multitask") {
-            if cfg!(feature = "smp") {
-                (6, "{0, 0, 8, 0, 0, 0}") // core::mem::transmute::<_, [usize; 6]>(axsync::Mutex::new(()))
+            if cfg!(all(feature = "smp", feature = "deadlock-detect")) {
+                (8, "{0, 0, 8, 0, 0, 0, 0, 0}") // core::mem::transmute::<_, [usize; 8]>(axsync::Mutex::new(()))
+            } else if cfg!(feature = "smp") {
+                (7, "{0, 0, 8, 0, 0, 0, 0}") // core::mem::transmute::<_, [usize; 7]>(axsync::Mutex::new(()))
+            } else if cfg!(feature = "deadlock-detect") {
+                (7, "{0, 8, 0, 0, 0, 0, 0}") // core::mem::transmute::<_, [usize; 7]>(axsync::Mutex::new(()))
             } else {
-                (5, "{0, 8, 0, 0, 0}") // core::mem::transmute::<_, [usize; 5]>(axsync::Mutex::new(()))
+                (6, "{0, 8, 0, 0, 0, 0}") // core::mem::transmute::<_, [usize; 6]>(axsync::Mutex::new(()))
             }
         } else {
             (1, "{0}")
@@ -53,6 +57,8 @@ typedef struct {{
             "clockid_t",
             "rlimit",
             "aibuf",
+            "cpu_set_t",
+            "sched_param",
         ];
         let allow_vars = [
             "CLOCK_.*",
@@ -60,12 +66,18 @@ typedef struct {{
             "AF_.*",
             "SOCK_.*",
             "IPPROTO_.*",
+            "IP_.*",
+            "SOL_.*",
+            "SO_.*",
+            "TCP_.*",
             "FD_.*",
             "F_.*",
             "_SC_.*",
             "EPOLL_CTL_.*",
             "EPOLL.*",
             "RLIMIT_.*",
+            "PRIO_.*",
+            "SCHED_.*",
             "EAI_.*",
             "MAXADDRS",
         ];
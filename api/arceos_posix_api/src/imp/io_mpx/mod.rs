@@ -5,6 +5,9 @@
 //! * [`epoll_ctl`](epoll::sys_epoll_ctl)
 //! * [`epoll_wait`](epoll::sys_epoll_wait)
 
+use axerrno::LinuxResult;
+use axhal::time::TimeValue;
+
 #[cfg(feature = "epoll")]
 mod epoll;
 #[cfg(feature = "select")]
@@ -14,3 +17,38 @@ mod select;
 pub use self::epoll::{sys_epoll_create, sys_epoll_ctl, sys_epoll_wait};
 #[cfg(feature = "select")]
 pub use self::select::sys_select;
+
+/// Polls the network, returns the number of network events so far to be
+/// passed to [`wait`] after polling the file descriptors.
+fn poll_net() -> usize {
+    #[cfg(feature = "net")]
+    {
+        let events = axnet::net_events();
+        axnet::poll_interfaces();
+        events
+    }
+    #[cfg(not(feature = "net"))]
+    0
+}
+
+/// Waits before polling the file descriptors again, no later than `deadline`.
+///
+/// With the network, it sleeps until a socket may become ready, e.g., a
+/// connection is established, otherwise it just yields the CPU.
+#[cfg_attr(not(feature = "net"), allow(unused_variables))]
+fn wait(events: usize, deadline: Option<TimeValue>) -> LinuxResult {
+    #[cfg(feature = "net")]
+    {
+        let timeout = deadline.map(|ddl| ddl.saturating_sub(axhal::time::monotonic_time()));
+        match axnet::wait_interfaces(events, timeout) {
+            // The caller checks the deadline after polling again.
+            Ok(()) | Err(axerrno::AxError::TimedOut) => Ok(()),
+            Err(_) => Err(axerrno::LinuxError::EINTR),
+        }
+    }
+    #[cfg(not(feature = "net"))]
+    {
+        crate::sys_sched_yield();
+        Ok(())
+    }
+}
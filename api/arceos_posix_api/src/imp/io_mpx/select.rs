@@ -1,7 +1,7 @@
 use core::ffi::c_int;
 
 use axerrno::{LinuxError, LinuxResult};
-use axhal::time::wall_time;
+use axhal::time::monotonic_time;
 
 use crate::{ctypes, imp::fd_ops::get_file_like};
 
@@ -124,7 +124,7 @@ pub unsafe fn sys_select(
             return Err(LinuxError::EINVAL);
         }
         let nfds = (nfds as usize).min(FD_SETSIZE);
-        let deadline = unsafe { timeout.as_ref().map(|t| wall_time() + (*t).into()) };
+        let deadline = unsafe { timeout.as_ref().map(|t| monotonic_time() + (*t).into()) };
         let fd_sets = FdSets::from(nfds, readfds, writefds, exceptfds);
 
         unsafe {
@@ -134,18 +134,17 @@ pub unsafe fn sys_select(
         }
 
         loop {
-            #[cfg(feature = "net")]
-            axnet::poll_interfaces();
+            let net_events = super::poll_net();
             let res = fd_sets.poll_all(readfds, writefds, exceptfds)?;
             if res > 0 {
                 return Ok(res);
             }
 
-            if deadline.map_or(false, |ddl| wall_time() >= ddl) {
+            if deadline.map_or(false, |ddl| monotonic_time() >= ddl) {
                 debug!("    timeout!");
                 return Ok(0);
             }
-            crate::sys_sched_yield();
+            super::wait(net_events, deadline)?;
         }
     })
 }
@@ -2,10 +2,11 @@ use alloc::{sync::Arc, vec, vec::Vec};
 use core::ffi::{c_char, c_int, c_void};
 use core::mem::size_of;
 use core::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use core::time::Duration;
 
-use axerrno::{LinuxError, LinuxResult};
+use axerrno::{AxError, LinuxError, LinuxResult};
 use axio::PollState;
-use axnet::{TcpSocket, UdpSocket};
+use axnet::{ConnectError, TcpSocket, UdpSocket};
 use axsync::Mutex;
 
 use super::fd_ops::FileLike;
@@ -74,7 +75,32 @@ impl Socket {
     fn connect(&self, addr: SocketAddr) -> LinuxResult {
         match self {
             Socket::Udp(udpsocket) => Ok(udpsocket.lock().connect(addr)?),
-            Socket::Tcp(tcpsocket) => Ok(tcpsocket.lock().connect(addr)?),
+            Socket::Tcp(tcpsocket) => {
+                let tcpsocket = tcpsocket.lock();
+                match tcpsocket.poll_connect() {
+                    Ok(false) => return Err(LinuxError::EALREADY),
+                    Ok(true) => return Err(LinuxError::EISCONN),
+                    // The error of an earlier nonblocking connect is reported once.
+                    Err(_) => {
+                        if let Some(err) = tcpsocket.take_error() {
+                            return Err(connect_errno(err));
+                        }
+                    }
+                }
+                match tcpsocket.connect(addr) {
+                    Ok(()) => Ok(()),
+                    Err(AxError::WouldBlock) => Err(LinuxError::EINPROGRESS),
+                    Err(e) => Err(tcpsocket.take_error().map_or(e.into(), connect_errno)),
+                }
+            }
+        }
+    }
+
+    /// Returns and clears the pending error, i.e., `SO_ERROR`.
+    fn take_error(&self) -> Option<LinuxError> {
+        match self {
+            Socket::Udp(_) => None,
+            Socket::Tcp(tcpsocket) => tcpsocket.lock().take_error().map(connect_errno),
         }
     }
 
@@ -268,7 +294,9 @@ pub fn sys_bind(
 
 /// Connects the socket to the address specified.
 ///
-/// Return 0 if success.
+/// Return 0 if success. A nonblocking TCP socket returns `EINPROGRESS`, then
+/// it becomes writable when the connection completes, and the result is got
+/// by `getsockopt(SO_ERROR)`.
 pub fn sys_connect(
     socket_fd: c_int,
     socket_addr: *const ctypes::sockaddr,
@@ -469,7 +497,10 @@ pub unsafe fn sys_getaddrinfo(
             if let Ok(a) = domain.parse::<IpAddr>() {
                 vec![a]
             } else {
-                axnet::dns_query(domain)?
+                // Only IPv4 is supported.
+                let mut addrs = axnet::dns_query(domain)?;
+                addrs.retain(IpAddr::is_ipv4);
+                addrs
             }
         } else {
             vec![Ipv4Addr::LOCALHOST.into()]
@@ -579,3 +610,160 @@ pub unsafe fn sys_getpeername(
         Ok(0)
     })
 }
+
+/// Set options on the socket.
+///
+/// The supported options are `SO_RCVTIMEO`, `SO_SNDTIMEO` and `SO_BROADCAST`
+/// at the `SOL_SOCKET` level on UDP sockets, `SO_RCVBUF` and `SO_SNDBUF` at
+/// the `SOL_SOCKET` level and `TCP_NODELAY` at the `IPPROTO_TCP` level on TCP
+/// sockets, and `SO_REUSEADDR` at the `SOL_SOCKET` level and `IP_TTL` at the
+/// `IPPROTO_IP` level on both. The others are ignored.
+pub unsafe fn sys_setsockopt(
+    sock_fd: c_int,
+    level: c_int,
+    optname: c_int,
+    optval: *const c_void,
+    optlen: ctypes::socklen_t,
+) -> c_int {
+    debug!(
+        "sys_setsockopt <= {} {} {} {:#x} {}",
+        sock_fd, level, optname, optval as usize, optlen
+    );
+    syscall_body!(sys_setsockopt, {
+        let socket = Socket::from_fd(sock_fd)?;
+        match (level as u32, optname as u32, &*socket) {
+            (ctypes::SOL_SOCKET, ctypes::SO_RCVTIMEO, Socket::Udp(udpsocket)) => {
+                let timeout = timeval_to_timeout(unsafe { read_optval(optval, optlen)? })?;
+                udpsocket.lock().set_read_timeout(timeout)?;
+            }
+            (ctypes::SOL_SOCKET, ctypes::SO_SNDTIMEO, Socket::Udp(udpsocket)) => {
+                let timeout = timeval_to_timeout(unsafe { read_optval(optval, optlen)? })?;
+                udpsocket.lock().set_write_timeout(timeout)?;
+            }
+            (ctypes::SOL_SOCKET, ctypes::SO_BROADCAST, Socket::Udp(udpsocket)) => {
+                let broadcast: c_int = unsafe { read_optval(optval, optlen)? };
+                udpsocket.lock().set_broadcast(broadcast != 0);
+            }
+            (ctypes::SOL_SOCKET, ctypes::SO_REUSEADDR, Socket::Udp(udpsocket)) => {
+                let reuse_addr: c_int = unsafe { read_optval(optval, optlen)? };
+                udpsocket.lock().set_reuse_addr(reuse_addr != 0);
+            }
+            (ctypes::IPPROTO_IP, ctypes::IP_TTL, Socket::Udp(udpsocket)) => {
+                let ttl: c_int = unsafe { read_optval(optval, optlen)? };
+                let ttl = u8::try_from(ttl).map_err(|_| LinuxError::EINVAL)?;
+                udpsocket.lock().set_ttl(ttl)?;
+            }
+            (ctypes::SOL_SOCKET, ctypes::SO_REUSEADDR, Socket::Tcp(tcpsocket)) => {
+                let reuse_addr: c_int = unsafe { read_optval(optval, optlen)? };
+                tcpsocket.lock().set_reuse_addr(reuse_addr != 0);
+            }
+            (ctypes::SOL_SOCKET, ctypes::SO_RCVBUF, Socket::Tcp(tcpsocket)) => {
+                let size: c_int = unsafe { read_optval(optval, optlen)? };
+                let size = usize::try_from(size).map_err(|_| LinuxError::EINVAL)?;
+                tcpsocket.lock().set_recv_buffer_size(size)?;
+            }
+            (ctypes::SOL_SOCKET, ctypes::SO_SNDBUF, Socket::Tcp(tcpsocket)) => {
+                let size: c_int = unsafe { read_optval(optval, optlen)? };
+                let size = usize::try_from(size).map_err(|_| LinuxError::EINVAL)?;
+                tcpsocket.lock().set_send_buffer_size(size)?;
+            }
+            (ctypes::IPPROTO_TCP, ctypes::TCP_NODELAY, Socket::Tcp(tcpsocket)) => {
+                let nodelay: c_int = unsafe { read_optval(optval, optlen)? };
+                tcpsocket.lock().set_nodelay(nodelay != 0);
+            }
+            (ctypes::IPPROTO_IP, ctypes::IP_TTL, Socket::Tcp(tcpsocket)) => {
+                let ttl: c_int = unsafe { read_optval(optval, optlen)? };
+                let ttl = u8::try_from(ttl).map_err(|_| LinuxError::EINVAL)?;
+                tcpsocket.lock().set_ttl(ttl)?;
+            }
+            _ => warn!(
+                "sys_setsockopt: unsupported option {} at level {}, ignored",
+                optname, level
+            ),
+        }
+        Ok(0)
+    })
+}
+
+/// Get options on the socket.
+///
+/// Only `SO_ERROR` at the `SOL_SOCKET` level is supported, which returns and
+/// clears the error of a failed nonblocking `connect`.
+pub unsafe fn sys_getsockopt(
+    sock_fd: c_int,
+    level: c_int,
+    optname: c_int,
+    optval: *mut c_void,
+    optlen: *mut ctypes::socklen_t,
+) -> c_int {
+    debug!(
+        "sys_getsockopt <= {} {} {} {:#x}",
+        sock_fd, level, optname, optval as usize
+    );
+    syscall_body!(sys_getsockopt, {
+        let socket = Socket::from_fd(sock_fd)?;
+        match (level as u32, optname as u32) {
+            (ctypes::SOL_SOCKET, ctypes::SO_ERROR) => {
+                let err = socket.take_error().map_or(0, |err| err.code());
+                unsafe { write_optval::<c_int>(optval, optlen, err)? };
+            }
+            _ => {
+                warn!(
+                    "sys_getsockopt: unsupported option {} at level {}",
+                    optname, level
+                );
+                return Err(LinuxError::ENOPROTOOPT);
+            }
+        }
+        Ok(0)
+    })
+}
+
+/// Reads the value of a socket option.
+unsafe fn read_optval<T: Copy>(optval: *const c_void, optlen: ctypes::socklen_t) -> LinuxResult<T> {
+    if optval.is_null() {
+        return Err(LinuxError::EFAULT);
+    }
+    if (optlen as usize) < size_of::<T>() {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(unsafe { (optval as *const T).read_unaligned() })
+}
+
+/// Writes the value of a socket option, and its length.
+unsafe fn write_optval<T: Copy>(
+    optval: *mut c_void,
+    optlen: *mut ctypes::socklen_t,
+    value: T,
+) -> LinuxResult {
+    if optval.is_null() || optlen.is_null() {
+        return Err(LinuxError::EFAULT);
+    }
+    if (unsafe { *optlen } as usize) < size_of::<T>() {
+        return Err(LinuxError::EINVAL);
+    }
+    unsafe {
+        (optval as *mut T).write_unaligned(value);
+        *optlen = size_of::<T>() as _;
+    }
+    Ok(())
+}
+
+/// Converts the reason of a failed connection to the error number.
+fn connect_errno(err: ConnectError) -> LinuxError {
+    match err {
+        ConnectError::Refused => LinuxError::ECONNREFUSED,
+        ConnectError::TimedOut => LinuxError::ETIMEDOUT,
+        ConnectError::Unreachable => LinuxError::ENETUNREACH,
+    }
+}
+
+/// Converts the value of `SO_RCVTIMEO` or `SO_SNDTIMEO`, where zero means no
+/// timeout.
+fn timeval_to_timeout(tv: ctypes::timeval) -> LinuxResult<Option<Duration>> {
+    if tv.tv_sec < 0 || !(0..1_000_000).contains(&tv.tv_usec) {
+        return Err(LinuxError::EDOM);
+    }
+    let timeout = Duration::from(tv);
+    Ok((!timeout.is_zero()).then_some(timeout))
+}
@@ -1,6 +1,6 @@
 use crate::ctypes;
 use axerrno::LinuxError;
-use core::ffi::c_int;
+use core::ffi::{c_int, c_uint};
 
 /// Get resource limitations
 ///
@@ -49,3 +49,55 @@ pub unsafe fn sys_setrlimit(resource: c_int, rlimits: *mut crate::ctypes::rlimit
         Ok(0)
     })
 }
+
+/// The range of nice values.
+const NICE_RANGE: core::ops::RangeInclusive<c_int> = -20..=19;
+
+/// Checks that the target of `getpriority`/`setpriority` is the current task,
+/// the only one supported.
+fn check_priority_target(which: c_int, who: c_uint) -> Result<(), LinuxError> {
+    if which as u32 != ctypes::PRIO_PROCESS {
+        return Err(LinuxError::EINVAL);
+    }
+    if who != 0 && who as c_int != super::task::sys_getpid() {
+        return Err(LinuxError::ESRCH);
+    }
+    Ok(())
+}
+
+/// Get the nice value of the current task
+///
+/// Returns `20 - nice` (from 1 to 40) as the Linux system call does, which is
+/// never negative so that it is not taken as an error.
+pub fn sys_getpriority(which: c_int, who: c_uint) -> c_int {
+    debug!("sys_getpriority <= {} {}", which, who);
+    syscall_body!(sys_getpriority, {
+        check_priority_target(which, who)?;
+        #[cfg(feature = "multitask")]
+        let nice = axtask::current_priority() as c_int;
+        #[cfg(not(feature = "multitask"))]
+        let nice = 0;
+        Ok(20 - nice)
+    })
+}
+
+/// Set the nice value of the current task
+///
+/// Nice values out of range are clamped to it. Fails with `EPERM` if the
+/// scheduler does not support nice values.
+pub fn sys_setpriority(which: c_int, who: c_uint, prio: c_int) -> c_int {
+    debug!("sys_setpriority <= {} {} {}", which, who, prio);
+    syscall_body!(sys_setpriority, {
+        check_priority_target(which, who)?;
+        let nice = prio.clamp(*NICE_RANGE.start(), *NICE_RANGE.end());
+        #[cfg(feature = "multitask")]
+        let ok = axtask::set_nice(axtask::current().as_task_ref(), nice as isize);
+        #[cfg(not(feature = "multitask"))]
+        let ok = nice == 0;
+        if ok {
+            Ok(0)
+        } else {
+            Err(LinuxError::EPERM)
+        }
+    })
+}
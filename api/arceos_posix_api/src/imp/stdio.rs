@@ -65,7 +65,9 @@ impl Stdin {
             if read_len > 0 {
                 return Ok(read_len);
             }
-            crate::sys_sched_yield();
+            if !axhal::console::wait_readable() {
+                crate::sys_sched_yield();
+            }
         }
     }
 }
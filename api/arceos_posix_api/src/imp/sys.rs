@@ -14,7 +14,9 @@ pub fn sys_sysconf(name: c_int) -> c_long {
             // Page size
             ctypes::_SC_PAGE_SIZE => Ok(PAGE_SIZE_4K),
             // Total physical pages
-            ctypes::_SC_PHYS_PAGES => Ok(axconfig::PHYS_MEMORY_SIZE / PAGE_SIZE_4K),
+            ctypes::_SC_PHYS_PAGES => Ok(axhal::mem::ram_regions()
+                .map(|(_, size)| size / PAGE_SIZE_4K)
+                .sum::<usize>()),
             // Number of processors in use
             ctypes::_SC_NPROCESSORS_ONLN => Ok(axconfig::SMP),
             // Avaliable physical pages
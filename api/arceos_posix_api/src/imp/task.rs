@@ -1,5 +1,9 @@
 use core::ffi::c_int;
 
+use axerrno::LinuxError;
+
+use crate::ctypes;
+
 /// Relinquish the CPU, and switches to another task.
 ///
 /// For single-threaded configuration (`multitask` feature is disabled), we just
@@ -38,3 +42,162 @@ pub fn sys_exit(exit_code: c_int) -> ! {
     #[cfg(not(feature = "multitask"))]
     axhal::misc::terminate();
 }
+
+/// Checks that `pid` is the current thread, the only one supported by the
+/// affinity calls.
+fn check_current_pid(pid: c_int) -> Result<(), LinuxError> {
+    if pid == 0 || pid == sys_getpid() {
+        Ok(())
+    } else {
+        Err(LinuxError::ESRCH)
+    }
+}
+
+/// Returns the task of `pid`, the current one if it is 0.
+#[cfg(feature = "multitask")]
+fn find_task(pid: c_int) -> Result<axtask::AxTaskRef, LinuxError> {
+    if pid == 0 {
+        return Ok(axtask::current().as_task_ref().clone());
+    }
+    let id = u64::try_from(pid).map_err(|_| LinuxError::ESRCH)?;
+    axtask::find_task(id).ok_or(LinuxError::ESRCH)
+}
+
+/// Set the scheduling policy and the real-time priority of a thread
+///
+/// `SCHED_FIFO` needs the `sched_rt` feature of `axtask`, and a priority from
+/// 1 to 99, while `SCHED_OTHER` needs the priority 0. `SCHED_RR` is not
+/// supported. Fails with `EINVAL` otherwise.
+pub unsafe fn sys_sched_setscheduler(
+    pid: c_int,
+    policy: c_int,
+    param: *const ctypes::sched_param,
+) -> c_int {
+    debug!(
+        "sys_sched_setscheduler <= {} {} {:#x}",
+        pid, policy, param as usize
+    );
+    syscall_body!(sys_sched_setscheduler, {
+        if param.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let prio = unsafe { (*param).sched_priority };
+        #[cfg(feature = "multitask")]
+        {
+            let task = find_task(pid)?;
+            let policy = match (policy as u32, u8::try_from(prio)) {
+                (ctypes::SCHED_OTHER, Ok(0)) => axtask::Policy::Normal,
+                (ctypes::SCHED_FIFO, Ok(prio)) => axtask::Policy::Fifo(prio),
+                _ => return Err(LinuxError::EINVAL),
+            };
+            if !axtask::set_scheduler(&task, policy) {
+                return Err(LinuxError::EINVAL);
+            }
+        }
+        #[cfg(not(feature = "multitask"))]
+        {
+            check_current_pid(pid)?;
+            if policy as u32 != ctypes::SCHED_OTHER || prio != 0 {
+                return Err(LinuxError::EINVAL);
+            }
+        }
+        Ok(0)
+    })
+}
+
+/// Get the scheduling policy of a thread
+pub fn sys_sched_getscheduler(pid: c_int) -> c_int {
+    debug!("sys_sched_getscheduler <= {}", pid);
+    syscall_body!(sys_sched_getscheduler, {
+        #[cfg(feature = "multitask")]
+        let policy = match find_task(pid)?.policy() {
+            axtask::Policy::Normal => ctypes::SCHED_OTHER,
+            axtask::Policy::Fifo(_) => ctypes::SCHED_FIFO,
+        };
+        #[cfg(not(feature = "multitask"))]
+        let policy = {
+            check_current_pid(pid)?;
+            ctypes::SCHED_OTHER
+        };
+        Ok(policy as c_int)
+    })
+}
+
+/// Set the CPU affinity of the current thread
+///
+/// The current thread is migrated to an allowed CPU if it is running on an
+/// excluded one. CPUs beyond the configured ones are ignored, and it fails
+/// with `EINVAL` if no online CPU is allowed.
+pub unsafe fn sys_sched_setaffinity(
+    pid: c_int,
+    cpusetsize: usize,
+    mask: *const ctypes::cpu_set_t,
+) -> c_int {
+    debug!(
+        "sys_sched_setaffinity <= {} {} {:#x}",
+        pid, cpusetsize, mask as usize
+    );
+    syscall_body!(sys_sched_setaffinity, {
+        check_current_pid(pid)?;
+        if mask.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let bytes = unsafe { core::slice::from_raw_parts(mask as *const u8, cpusetsize) };
+        let is_set = |cpu: usize| {
+            bytes
+                .get(cpu / 8)
+                .is_some_and(|b| b & (1 << (cpu % 8)) != 0)
+        };
+        #[cfg(feature = "multitask")]
+        {
+            let mut cpumask = axtask::AxCpuMask::new();
+            for cpu in (0..axconfig::SMP).filter(|&cpu| is_set(cpu)) {
+                cpumask.set(cpu, true);
+            }
+            if !axtask::set_current_affinity(cpumask) {
+                return Err(LinuxError::EINVAL);
+            }
+        }
+        #[cfg(not(feature = "multitask"))]
+        if !is_set(axhal::cpu::this_cpu_id()) {
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(0)
+    })
+}
+
+/// Get the CPU affinity of the current thread
+///
+/// The mask is zeroed beyond the configured CPUs. It fails with `EINVAL` if
+/// `cpusetsize` is too small for them.
+pub unsafe fn sys_sched_getaffinity(
+    pid: c_int,
+    cpusetsize: usize,
+    mask: *mut ctypes::cpu_set_t,
+) -> c_int {
+    debug!(
+        "sys_sched_getaffinity <= {} {} {:#x}",
+        pid, cpusetsize, mask as usize
+    );
+    syscall_body!(sys_sched_getaffinity, {
+        check_current_pid(pid)?;
+        if mask.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        if cpusetsize * 8 < axconfig::SMP {
+            return Err(LinuxError::EINVAL);
+        }
+        let bytes = unsafe { core::slice::from_raw_parts_mut(mask as *mut u8, cpusetsize) };
+        bytes.fill(0);
+        #[cfg(feature = "multitask")]
+        let cpumask = axtask::current().cpumask();
+        #[cfg(feature = "multitask")]
+        let cpus = (0..axconfig::SMP).filter(|&cpu| cpumask.get(cpu));
+        #[cfg(not(feature = "multitask"))]
+        let cpus = core::iter::once(axhal::cpu::this_cpu_id());
+        for cpu in cpus {
+            bytes[cpu / 8] |= 1 << (cpu % 8);
+        }
+        Ok(0)
+    })
+}
@@ -12,6 +12,8 @@
 //!     - `alloc-tlsf`: Use the TLSF allocator.
 //!     - `alloc-slab`: Use the slab allocator.
 //!     - `alloc-buddy`: Use the buddy system allocator.
+//!     - `numa`: Allocate the memory of each NUMA node separately, and DMA
+//!       memory on the node of the allocating CPU.
 //!     - `paging`: Enable page table manipulation.
 //!     - `tls`: Enable thread-local storage.
 //! - Task management
@@ -19,17 +21,34 @@
 //!     - `sched_fifo`: Use the FIFO cooperative scheduler.
 //!     - `sched_rr`: Use the Round-robin preemptive scheduler.
 //!     - `sched_cfs`: Use the Completely Fair Scheduler (CFS) preemptive scheduler.
+//!     - `sched_prio`: Use the strict priority preemptive scheduler.
+//!     - `sched_rt`: Add the real-time FIFO class above the chosen scheduler.
+//!     - `tickless`: Stop the periodic timer interrupt while CPUs are idle.
+//!     - `dyntick`: Also stop it while CPUs run a single task.
 //! - Upperlayer stacks (fs, net, display)
 //!     - `fs`: Enable file system support.
 //!     - `myfs`: Allow users to define their custom filesystems to override the default.
 //!     - `net`: Enable networking support.
 //!     - `display`: Enable graphics support.
+//!     - `input`: Enable keyboard and mouse input support.
+//!     - `sound`: Enable sound playback support.
+//!     - `virtio-console`: Use the VirtIO console as the system console if present.
+//!     - `virtio-rng`: Use the VirtIO entropy device to seed the random number generator.
+//!     - `virtio-balloon`: Use the VirtIO memory balloon to return memory to the host.
 //! - Device drivers
 //!     - `bus-mmio`: Use device tree to probe all MMIO devices.
 //!     - `bus-pci`: Use PCI bus to probe all PCI devices.
 //!     - `driver-ramdisk`: Use the RAM disk to emulate the block device.
 //!     - `driver-ixgbe`: Enable the Intel 82599 10Gbit NIC driver.
 //!     - `driver-bcm2835-sdhci`: Enable the BCM2835 SDHCI driver (Raspberry Pi SD card).
+//!     - `driver-e1000`: Enable the Intel 8254x (e1000) gigabit NIC driver.
+//!     - `driver-rtl8139`: Enable the Realtek RTL8139 NIC driver (x86_64 only).
+//!     - `driver-nvme`: Enable the NVMe storage controller driver.
+//! - Boot
+//!     - `uefi`: Make the x86_64 kernel image bootable as a UEFI application too.
+//! - Debugging
+//!     - `ksyms`: Embed the kernel symbol table to print function names in backtraces.
+//!     - `latency-trace`: Trace the regions with preemption or IRQs disabled.
 //! - Logging
 //!     - `log-level-off`: Disable all logging.
 //!     - `log-level-error`, `log-level-warn`, `log-level-info`, `log-level-debug`,
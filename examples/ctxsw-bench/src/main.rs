@@ -0,0 +1,57 @@
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+
+#[cfg(feature = "axstd")]
+use std::os::arceos::modules::axhal::perf::{self, PerfEvent};
+#[cfg(feature = "axstd")]
+use std::thread;
+
+const NUM_SWITCHES: u64 = 100_000;
+
+/// Switches between two tasks by yielding, and returns the cycles and the
+/// instructions (if they can be counted) per switch.
+#[cfg(feature = "axstd")]
+fn measure() -> (u64, Option<u64>) {
+    // Fails on CPUs without a PMU, then `read_event` fails too.
+    let _ = perf::start_event(PerfEvent::Instructions);
+    let other = thread::spawn(|| {
+        for _ in 0..NUM_SWITCHES / 2 {
+            thread::yield_now();
+        }
+    });
+    let start = perf::read_cycles();
+    for _ in 0..NUM_SWITCHES / 2 {
+        thread::yield_now();
+    }
+    let cycles = perf::read_cycles() - start;
+    let instructions = perf::read_event().ok();
+    perf::stop_event();
+    other.join().unwrap();
+    (
+        cycles / NUM_SWITCHES,
+        instructions.map(|n| n / NUM_SWITCHES),
+    )
+}
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    #[cfg(feature = "axstd")]
+    {
+        let (cycles, instructions) = measure();
+        println!(
+            "context switch: {} cycles ({} ns)",
+            cycles,
+            perf::cycles_to_nanos(cycles)
+        );
+        match instructions {
+            Some(n) => println!("context switch: {} instructions", n),
+            None => println!("context switch: instruction counter unsupported"),
+        }
+    }
+    #[cfg(not(feature = "axstd"))]
+    println!("The benchmark only runs on ArceOS.");
+}
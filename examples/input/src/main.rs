@@ -0,0 +1,30 @@
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+
+#[cfg(feature = "axstd")]
+use std::os::arceos::api::input::ax_read_input_event;
+
+#[cfg(feature = "axstd")]
+const EV_KEY: u16 = 1;
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    println!("Press keys in the QEMU window...");
+    #[cfg(feature = "axstd")]
+    loop {
+        match ax_read_input_event() {
+            Some(ev) if ev.ev_type == EV_KEY => {
+                let action = if ev.value == 0 { "released" } else { "pressed" };
+                println!("key {} {}", ev.code, action);
+            }
+            Some(ev) => println!("event {:?}", ev),
+            None => std::thread::yield_now(),
+        }
+    }
+    #[cfg(not(feature = "axstd"))]
+    println!("Input devices are only supported on ArceOS.");
+}
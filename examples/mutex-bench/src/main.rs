@@ -0,0 +1,76 @@
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+
+#[cfg(feature = "axstd")]
+use std::os::arceos::modules::{axconfig, axsync::Mutex};
+#[cfg(feature = "axstd")]
+use std::{thread, time::Duration, time::Instant, vec::Vec};
+
+#[cfg(feature = "axstd")]
+const NUM_ITERS: u64 = 100_000;
+/// The iterations of the work in the critical section, a few hundred
+/// nanoseconds.
+#[cfg(feature = "axstd")]
+const CRITICAL_SECTION_LEN: u64 = 100;
+
+#[cfg(feature = "axstd")]
+static PLAIN: Mutex<u64> = Mutex::new(0);
+#[cfg(feature = "axstd")]
+static ADAPTIVE: Mutex<u64> = Mutex::new_adaptive(0);
+
+/// Locks and unlocks the mutex from a task per CPU at once, with a short
+/// critical section, and returns the time it takes.
+#[cfg(feature = "axstd")]
+fn measure(mutex: &'static Mutex<u64>) -> Duration {
+    let start = Instant::now();
+    let tasks: Vec<_> = (0..axconfig::SMP)
+        .map(|_| {
+            thread::spawn(move || {
+                for _ in 0..NUM_ITERS {
+                    let mut val = mutex.lock();
+                    for _ in 0..CRITICAL_SECTION_LEN {
+                        *val = core::hint::black_box(*val + 1);
+                    }
+                }
+            })
+        })
+        .collect();
+    for t in tasks {
+        t.join().unwrap();
+    }
+    let elapsed = start.elapsed();
+    assert_eq!(
+        *mutex.lock(),
+        NUM_ITERS * CRITICAL_SECTION_LEN * axconfig::SMP as u64
+    );
+    elapsed
+}
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    #[cfg(feature = "axstd")]
+    {
+        let ops = NUM_ITERS * axconfig::SMP as u64;
+        println!("{} tasks contending for a mutex", axconfig::SMP);
+        let plain = measure(&PLAIN);
+        let adaptive = measure(&ADAPTIVE);
+        for (name, elapsed) in [("plain", plain), ("adaptive", adaptive)] {
+            println!(
+                "{}: {:?}, {} lock/unlock per second",
+                name,
+                elapsed,
+                (ops as f64 / elapsed.as_secs_f64()) as u64
+            );
+        }
+        println!(
+            "speedup: {:.2}x",
+            plain.as_secs_f64() / adaptive.as_secs_f64()
+        );
+    }
+    #[cfg(not(feature = "axstd"))]
+    println!("The benchmark only runs on ArceOS.");
+}
@@ -0,0 +1 @@
+app-objs := nbconnect.o
@@ -0,0 +1,177 @@
+#include <arpa/inet.h>
+#include <errno.h>
+#include <fcntl.h>
+#include <netinet/in.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/select.h>
+#include <sys/socket.h>
+#include <unistd.h>
+
+#define NUM_CONNECTS   50
+#define NUM_ACCEPTED   24
+#define LISTEN_PORT    5555
+#define CLOSED_PORT    5556
+#define WAIT_SECONDS   90
+
+/*
+ * The first `NUM_ACCEPTED` sockets connect to the listener, and should succeed.
+ * The others connect to a closed port, and should be refused, except the last
+ * one, which connects to an address on the loopback subnet that nobody owns,
+ * so its SYNs are dropped and it should time out after 75 seconds.
+ */
+static int expected_error(int i)
+{
+    if (i < NUM_ACCEPTED)
+        return 0;
+    else if (i < NUM_CONNECTS - 1)
+        return ECONNREFUSED;
+    else
+        return ETIMEDOUT;
+}
+
+static void target_addr(int i, struct sockaddr_in *addr)
+{
+    memset(addr, 0, sizeof(*addr));
+    addr->sin_family = AF_INET;
+    if (i < NUM_CONNECTS - 1) {
+        inet_pton(AF_INET, "127.0.0.1", &addr->sin_addr);
+        addr->sin_port = htons(i < NUM_ACCEPTED ? LISTEN_PORT : CLOSED_PORT);
+    } else {
+        inet_pton(AF_INET, "127.0.0.2", &addr->sin_addr);
+        addr->sin_port = htons(LISTEN_PORT);
+    }
+}
+
+static int get_so_error(int sock)
+{
+    int err = -1;
+    socklen_t len = sizeof(err);
+    if (getsockopt(sock, SOL_SOCKET, SO_ERROR, &err, &len) != 0) {
+        perror("getsockopt() error");
+        return -1;
+    }
+    return err;
+}
+
+int main()
+{
+    puts("Hello, ArceOS C nonblocking connect test!");
+
+    struct sockaddr_in local;
+    memset(&local, 0, sizeof(local));
+    local.sin_family = AF_INET;
+    local.sin_port = htons(LISTEN_PORT);
+    inet_pton(AF_INET, "127.0.0.1", &local.sin_addr);
+    int listener = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP);
+    if (listener == -1) {
+        perror("socket() error");
+        return -1;
+    }
+    if (bind(listener, (struct sockaddr *)&local, sizeof(local)) != 0) {
+        perror("bind() error");
+        return -1;
+    }
+    if (listen(listener, NUM_CONNECTS) != 0) {
+        perror("listen() error");
+        return -1;
+    }
+
+    int socks[NUM_CONNECTS];
+    int done[NUM_CONNECTS] = {};
+    int pending = NUM_CONNECTS;
+    for (int i = 0; i < NUM_CONNECTS; i++) {
+        socks[i] = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP);
+        if (socks[i] == -1) {
+            perror("socket() error");
+            return -1;
+        }
+        if (fcntl(socks[i], F_SETFL, O_NONBLOCK) != 0) {
+            perror("fcntl() error");
+            return -1;
+        }
+        struct sockaddr_in addr;
+        int err;
+        target_addr(i, &addr);
+        // A loopback connection may complete or be refused at once.
+        if (connect(socks[i], (struct sockaddr *)&addr, sizeof(addr)) == 0) {
+            err = 0;
+        } else if (errno == EINPROGRESS) {
+            continue;
+        } else {
+            err = errno;
+        }
+        if (err != expected_error(i)) {
+            printf("connect() #%d: error is %d, expected %d\n", i, err, expected_error(i));
+            return -1;
+        }
+        done[i] = 1;
+        pending--;
+    }
+    printf("%d connects in progress\n", pending);
+
+    // Another connect() on the pending one reports `EALREADY`.
+    struct sockaddr_in addr;
+    target_addr(NUM_CONNECTS - 1, &addr);
+    if (done[NUM_CONNECTS - 1] ||
+        connect(socks[NUM_CONNECTS - 1], (struct sockaddr *)&addr, sizeof(addr)) == 0 ||
+        errno != EALREADY) {
+        printf("connect() #%d should be in progress\n", NUM_CONNECTS - 1);
+        return -1;
+    }
+
+    while (pending > 0) {
+        fd_set wfds;
+        int nfds = 0;
+        FD_ZERO(&wfds);
+        for (int i = 0; i < NUM_CONNECTS; i++) {
+            if (!done[i]) {
+                FD_SET(socks[i], &wfds);
+                if (socks[i] >= nfds)
+                    nfds = socks[i] + 1;
+            }
+        }
+        struct timeval tv = {.tv_sec = WAIT_SECONDS, .tv_usec = 0};
+        int n = select(nfds, NULL, &wfds, NULL, &tv);
+        if (n < 0) {
+            perror("select() error");
+            return -1;
+        }
+        if (n == 0) {
+            printf("%d connects are still pending after %d seconds\n", pending, WAIT_SECONDS);
+            return -1;
+        }
+        for (int i = 0; i < NUM_CONNECTS; i++) {
+            if (done[i] || !FD_ISSET(socks[i], &wfds))
+                continue;
+            int err = get_so_error(socks[i]);
+            if (err != expected_error(i)) {
+                printf("connect() #%d: SO_ERROR is %d, expected %d\n", i, err,
+                       expected_error(i));
+                return -1;
+            }
+            // The error is reported only once.
+            if (err != 0 && get_so_error(socks[i]) != 0) {
+                printf("connect() #%d: SO_ERROR is not cleared\n", i);
+                return -1;
+            }
+            printf("connect() #%d: %s\n", i, err ? strerror(err) : "connected");
+            done[i] = 1;
+            pending--;
+        }
+    }
+
+    for (int i = 0; i < NUM_ACCEPTED; i++) {
+        int conn = accept(listener, NULL, NULL);
+        if (conn == -1) {
+            perror("accept() error");
+            return -1;
+        }
+        close(conn);
+    }
+    for (int i = 0; i < NUM_CONNECTS; i++) close(socks[i]);
+    close(listener);
+
+    puts("Nonblocking connect tests run OK!");
+    return 0;
+}
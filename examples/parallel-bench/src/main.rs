@@ -0,0 +1,56 @@
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+
+#[cfg(feature = "axstd")]
+use std::{thread, time::Instant, vec::Vec};
+
+#[cfg(feature = "axstd")]
+const NUM_TASKS: usize = 64;
+#[cfg(feature = "axstd")]
+const NUM_ITERS: u64 = 10_000_000;
+
+/// A CPU-bound job that never blocks.
+#[cfg(feature = "axstd")]
+fn job(seed: u64) -> u64 {
+    let mut x = seed;
+    for _ in 0..NUM_ITERS {
+        x = x
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+    }
+    x
+}
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    #[cfg(feature = "axstd")]
+    {
+        let start = Instant::now();
+        core::hint::black_box(job(0));
+        let serial = start.elapsed() * NUM_TASKS as u32;
+
+        // Spawn them all from one CPU, for the load balancing to spread them.
+        let start = Instant::now();
+        let tasks: Vec<_> = (0..NUM_TASKS as u64)
+            .map(|i| thread::spawn(move || job(i)))
+            .collect();
+        for t in tasks {
+            core::hint::black_box(t.join().unwrap());
+        }
+        let parallel = start.elapsed();
+
+        println!("{} CPU-bound tasks", NUM_TASKS);
+        println!("serial (estimated): {:?}", serial);
+        println!("parallel: {:?}", parallel);
+        println!(
+            "speedup: {:.2}x",
+            serial.as_secs_f64() / parallel.as_secs_f64()
+        );
+    }
+    #[cfg(not(feature = "axstd"))]
+    println!("The benchmark only runs on ArceOS.");
+}
@@ -22,8 +22,10 @@ const CMD_TABLE: &[(&str, CmdHandler)] = &[
     ("echo", do_echo),
     ("exit", do_exit),
     ("help", do_help),
+    ("latency", do_latency),
     ("ls", do_ls),
     ("mkdir", do_mkdir),
+    ("ps", do_ps),
     ("pwd", do_pwd),
     ("rm", do_rm),
     ("uname", do_uname),
@@ -243,6 +245,51 @@ fn do_pwd(_args: &str) {
     println!("{}", path_to_str!(pwd));
 }
 
+#[cfg(feature = "axstd")]
+fn do_ps(_args: &str) {
+    use std::os::arceos::api::task::ax_for_each_task;
+
+    println!(
+        "{:>4} {:<16} {:<8} {:>3} {:>4} {:>17} {:>10}",
+        "ID", "NAME", "STATE", "CPU", "PRIO", "STACK", "TIME(ms)"
+    );
+    ax_for_each_task(|task| {
+        let state = std::format!("{:?}", task.state);
+        let stack = std::format!("{}/{}", task.stack_used, task.stack_size);
+        println!(
+            "{:>4} {:<16} {:<8} {:>3} {:>4} {:>17} {:>10}",
+            task.id,
+            task.name,
+            state,
+            task.cpu_id,
+            task.priority,
+            stack,
+            task.stats.run_time.as_millis()
+        );
+    });
+}
+
+#[cfg(not(feature = "axstd"))]
+fn do_ps(_args: &str) {
+    print_err!("ps", "not supported on this platform");
+}
+
+#[cfg(feature = "latency-trace")]
+fn do_latency(args: &str) {
+    use std::os::arceos::modules::axtask;
+
+    match args {
+        "" => print!("{}", axtask::latency_report()),
+        "reset" => axtask::reset_latency_report(),
+        _ => print_err!("latency", format_args!("invalid argument '{args}'")),
+    }
+}
+
+#[cfg(not(feature = "latency-trace"))]
+fn do_latency(_args: &str) {
+    print_err!("latency", "not enabled in this build");
+}
+
 fn do_uname(_args: &str) {
     let arch = option_env!("AX_ARCH").unwrap_or("");
     let platform = option_env!("AX_PLATFORM").unwrap_or("");
@@ -0,0 +1,92 @@
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+
+#[cfg(feature = "axstd")]
+use std::os::arceos::api::{
+    sound::{self as api, AxPcmFormat, AxPcmParams, AxPcmRate},
+    AxError,
+};
+
+const SAMPLE_RATE: f32 = 48000.0;
+const FREQUENCY: f32 = 440.0;
+const AMPLITUDE: f32 = 8000.0;
+const PERIOD_FRAMES: usize = 1200; // 25 ms
+const NUM_PERIODS: usize = 120; // 3 s
+
+/// Generates a sine wave with the recurrence
+/// `y[n] = 2 cos(w) * y[n-1] - y[n-2]`, so no `sin` is needed.
+struct SineWave {
+    coeff: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl SineWave {
+    fn new(freq: f32, rate: f32) -> Self {
+        let w = 2.0 * core::f32::consts::PI * freq / rate;
+        // Taylor series, accurate enough for small `w`.
+        let w2 = w * w;
+        let cos_w = 1.0 - w2 / 2.0 + w2 * w2 / 24.0 - w2 * w2 * w2 / 720.0;
+        let sin_w = w - w * w2 / 6.0 + w * w2 * w2 / 120.0;
+        Self {
+            coeff: 2.0 * cos_w,
+            y1: 0.0,
+            y2: -sin_w,
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let y = self.coeff * self.y1 - self.y2;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    let mut wave = SineWave::new(FREQUENCY, SAMPLE_RATE);
+    let mut period = [0u8; PERIOD_FRAMES * 2];
+
+    #[cfg(feature = "axstd")]
+    {
+        let params = AxPcmParams {
+            buffer_bytes: (period.len() * 4) as u32,
+            period_bytes: period.len() as u32,
+            channels: 1,
+            format: AxPcmFormat::S16,
+            rate: AxPcmRate::Rate48000,
+        };
+        if let Err(e) = api::ax_sound_start(&params) {
+            println!("Failed to start playback: {:?}", e);
+            return;
+        }
+    }
+
+    println!("Playing a {} Hz sine wave...", FREQUENCY);
+    for _ in 0..NUM_PERIODS {
+        for frame in period.chunks_exact_mut(2) {
+            let sample = (wave.next_sample() * AMPLITUDE) as i16;
+            frame.copy_from_slice(&sample.to_le_bytes());
+        }
+        #[cfg(feature = "axstd")]
+        match api::ax_sound_write(&period) {
+            Ok(()) => {}
+            Err(AxError::BadState) => println!("underrun"),
+            Err(e) => {
+                println!("Failed to play: {:?}", e);
+                break;
+            }
+        }
+    }
+
+    #[cfg(feature = "axstd")]
+    api::ax_sound_stop().ok();
+    #[cfg(not(feature = "axstd"))]
+    println!("Sound devices are only supported on ArceOS.");
+    println!("Done.");
+}
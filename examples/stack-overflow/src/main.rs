@@ -0,0 +1,42 @@
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+
+#[cfg(feature = "axstd")]
+use std::thread;
+
+/// The stack size of the task that overflows its stack.
+#[cfg(feature = "axstd")]
+const STACK_SIZE: usize = 0x4000;
+
+/// Recurses with a large frame, never returns.
+#[cfg(feature = "axstd")]
+#[allow(unconditional_recursion)]
+fn recurse(depth: usize) -> usize {
+    let frame = core::hint::black_box([depth; 64]);
+    recurse(depth + 1) + frame[depth % 64]
+}
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    #[cfg(feature = "axstd")]
+    {
+        let bystander = thread::spawn(|| (0..1000u64).sum::<u64>());
+
+        let overflow = thread::Builder::new()
+            .name("recurse".into())
+            .stack_size(STACK_SIZE)
+            .spawn(|| recurse(0))
+            .unwrap();
+        // It is terminated instead of corrupting the memory below its stack.
+        assert!(overflow.join().is_err());
+
+        assert_eq!(bystander.join().unwrap(), 499500);
+        println!("Stack overflow test OK!");
+    }
+    #[cfg(not(feature = "axstd"))]
+    println!("The test only runs on ArceOS.");
+}
@@ -0,0 +1,173 @@
+//! TCP echo, option and latency tests over the loopback interface.
+//!
+//! Set `ECHO_PEER` at build time to also echo through a server outside, e.g.,
+//! over the IPv6 prefix of the QEMU user netdev:
+//!
+//! ```
+//! make A=examples/tcp-loopback NET=y IP6=fec0::15 ECHO_PEER=[fec0::2]:5555 run
+//! ```
+
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+
+use std::io::{self, prelude::*};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DUAL_STACK_PORT: u16 = 5555;
+const IPV4_ONLY_PORT: u16 = 5556;
+const PING_PONG_PORT: u16 = 5557;
+const OPTIONS_PORT: u16 = 5558;
+
+const MESSAGE: &[u8] = b"Hello, ArceOS TCP loopback!";
+
+/// The number of round trips of each ping-pong test.
+const PING_PONG_ROUNDS: u32 = 20;
+
+fn echo_server(mut stream: TcpStream) -> io::Result<()> {
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        stream.write_all(&buf[..n])?;
+    }
+}
+
+fn spawn_echo_server(listener: TcpListener) {
+    thread::spawn(move || loop {
+        let (stream, _) = listener.accept().expect("accept failed");
+        thread::spawn(move || echo_server(stream).expect("echo server failed"));
+    });
+}
+
+fn echo(addr: &str) -> io::Result<()> {
+    let mut stream = TcpStream::connect(addr)?;
+    println!("{} -> {}", stream.local_addr()?, stream.peer_addr()?);
+    stream.write_all(MESSAGE)?;
+    let mut buf = [0u8; MESSAGE.len()];
+    stream.read_exact(&mut buf)?;
+    assert_eq!(buf, MESSAGE);
+    Ok(())
+}
+
+/// Answers each "ping" with a "pong", until the client closes.
+fn pong_server(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut buf = [0u8; 4];
+    while stream.read_exact(&mut buf).is_ok() {
+        assert_eq!(&buf, b"ping");
+        stream.write_all(b"pong")?;
+    }
+    Ok(())
+}
+
+/// Returns the average round trip time of pings, each sent by two writes.
+///
+/// With the Nagle algorithm, the second write is held until the first one is
+/// acknowledged, which the server delays as it waits for the rest.
+fn ping_pong(nodelay: bool) -> io::Result<Duration> {
+    let mut stream = TcpStream::connect(("127.0.0.1", PING_PONG_PORT))?;
+    stream.set_nodelay(nodelay)?;
+    let mut buf = [0u8; 4];
+    let start = Instant::now();
+    for _ in 0..PING_PONG_ROUNDS {
+        stream.write_all(b"pi")?;
+        stream.write_all(b"ng")?;
+        stream.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"pong");
+    }
+    Ok(start.elapsed() / PING_PONG_ROUNDS)
+}
+
+fn test_options() -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", OPTIONS_PORT))?;
+    assert!(listener.set_ttl(0).is_err());
+    assert!(listener.set_ttl(256).is_err());
+    listener.set_ttl(17)?;
+    assert_eq!(listener.ttl()?, 17);
+
+    let stream = TcpStream::connect(("127.0.0.1", OPTIONS_PORT))?;
+    assert!(!stream.nodelay()?);
+    stream.set_nodelay(true)?;
+    assert!(stream.nodelay()?);
+    stream.set_nodelay(false)?;
+    assert!(!stream.nodelay()?);
+    assert_eq!(stream.ttl()?, 64);
+    stream.set_ttl(100)?;
+    assert_eq!(stream.ttl()?, 100);
+
+    // The accepted stream inherits the TTL of the listener.
+    let (accepted, _) = listener.accept()?;
+    assert_eq!(accepted.ttl()?, 17);
+
+    #[cfg(feature = "axstd")]
+    test_buffer_sizes()?;
+    Ok(())
+}
+
+#[cfg(feature = "axstd")]
+fn test_buffer_sizes() -> io::Result<()> {
+    use std::os::arceos::api::net::*;
+
+    let socket = ax_tcp_socket();
+    assert_eq!(ax_tcp_recv_buffer_size(&socket)?, 64 * 1024);
+    ax_tcp_set_recv_buffer_size(&socket, 8192)?;
+    assert_eq!(ax_tcp_recv_buffer_size(&socket)?, 8192);
+    ax_tcp_set_send_buffer_size(&socket, 4096)?;
+    assert_eq!(ax_tcp_send_buffer_size(&socket)?, 4096);
+
+    // The sizes are capped at 1 MiB, and must not be zero.
+    ax_tcp_set_recv_buffer_size(&socket, 4 * 1024 * 1024)?;
+    assert_eq!(ax_tcp_recv_buffer_size(&socket)?, 1024 * 1024);
+    assert!(ax_tcp_set_send_buffer_size(&socket, 0).is_err());
+    assert_eq!(ax_tcp_send_buffer_size(&socket)?, 4096);
+
+    // They can not be changed once connected.
+    ax_tcp_connect(&socket, ([127, 0, 0, 1], DUAL_STACK_PORT).into())?;
+    assert!(ax_tcp_set_recv_buffer_size(&socket, 8192).is_err());
+    assert_eq!(ax_tcp_recv_buffer_size(&socket)?, 1024 * 1024);
+    Ok(())
+}
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    println!("Hello, ArceOS TCP loopback test!");
+
+    // A listener on `::` accepts both IPv4 and IPv6 connections, but one on
+    // `0.0.0.0` accepts only IPv4 ones.
+    spawn_echo_server(TcpListener::bind(("::", DUAL_STACK_PORT)).unwrap());
+    spawn_echo_server(TcpListener::bind(("0.0.0.0", IPV4_ONLY_PORT)).unwrap());
+
+    echo(&format!("[::1]:{}", DUAL_STACK_PORT)).expect("echo over [::1] failed");
+    echo(&format!("127.0.0.1:{}", DUAL_STACK_PORT)).expect("echo over 127.0.0.1 failed");
+    echo(&format!("127.0.0.1:{}", IPV4_ONLY_PORT)).expect("echo over 127.0.0.1 failed");
+    assert!(echo(&format!("[::1]:{}", IPV4_ONLY_PORT)).is_err());
+
+    if let Some(peer) = option_env!("ECHO_PEER") {
+        echo(peer).expect("echo with the peer failed");
+    }
+
+    test_options().expect("TCP option tests failed");
+
+    let listener = TcpListener::bind(("127.0.0.1", PING_PONG_PORT)).unwrap();
+    thread::spawn(move || loop {
+        let (stream, _) = listener.accept().expect("accept failed");
+        thread::spawn(move || pong_server(stream).expect("pong server failed"));
+    });
+    let nagle_rtt = ping_pong(false).expect("ping-pong with Nagle failed");
+    let nodelay_rtt = ping_pong(true).expect("ping-pong with TCP_NODELAY failed");
+    println!(
+        "average RTT: {:?} with Nagle, {:?} with TCP_NODELAY",
+        nagle_rtt, nodelay_rtt
+    );
+    assert!(nodelay_rtt < nagle_rtt);
+
+    println!("TCP loopback tests run OK!");
+}
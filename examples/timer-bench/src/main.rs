@@ -0,0 +1,127 @@
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+
+#[cfg(feature = "axstd")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "axstd")]
+use std::os::arceos::modules::axhal::{self, perf};
+#[cfg(feature = "axstd")]
+use std::os::arceos::modules::axtask::{self, WaitQueue};
+#[cfg(feature = "axstd")]
+use std::{thread, time::Duration};
+
+const NUM_SLEEPERS: usize = 10_000;
+const NUM_TICKS: u64 = 1000;
+/// Small enough for all the sleepers to fit in the memory of QEMU.
+const SLEEPER_STACK_SIZE: usize = 0x1000;
+
+#[cfg(feature = "axstd")]
+static WQ: WaitQueue = WaitQueue::new();
+#[cfg(feature = "axstd")]
+static NUM_WAITING: AtomicUsize = AtomicUsize::new(0);
+
+/// Runs the timer tick handler, and returns the cycles per tick.
+#[cfg(feature = "axstd")]
+fn tick_cycles() -> u64 {
+    let start = perf::read_cycles();
+    for _ in 0..NUM_TICKS {
+        axhal::arch::disable_irqs();
+        axtask::on_timer_tick();
+        axhal::arch::enable_irqs();
+    }
+    (perf::read_cycles() - start) / NUM_TICKS
+}
+
+/// Spawns the tasks waiting with a timeout far away, which keeps their
+/// timers pending, and returns the cycles per task to set the timers.
+#[cfg(feature = "axstd")]
+fn spawn_sleepers() -> u64 {
+    let start = perf::read_cycles();
+    for _ in 0..NUM_SLEEPERS {
+        axtask::spawn_raw(
+            || {
+                NUM_WAITING.fetch_add(1, Ordering::Relaxed);
+                WQ.wait_timeout(Duration::from_secs(3600));
+                NUM_WAITING.fetch_sub(1, Ordering::Relaxed);
+            },
+            "sleeper".into(),
+            SLEEPER_STACK_SIZE,
+        );
+    }
+    while NUM_WAITING.load(Ordering::Relaxed) < NUM_SLEEPERS {
+        thread::yield_now();
+    }
+    // Let the last ones block.
+    thread::yield_now();
+    (perf::read_cycles() - start) / NUM_SLEEPERS as u64
+}
+
+/// Wakes up the sleepers before their timeouts, which cancels their timers,
+/// and returns the cycles per task.
+#[cfg(feature = "axstd")]
+fn wake_sleepers() -> u64 {
+    let start = perf::read_cycles();
+    WQ.notify_all(false);
+    while NUM_WAITING.load(Ordering::Relaxed) > 0 {
+        thread::yield_now();
+    }
+    (perf::read_cycles() - start) / NUM_SLEEPERS as u64
+}
+
+/// Runs `f`, and returns the timer interrupts and all the interrupts per
+/// second meanwhile.
+#[cfg(feature = "axstd")]
+fn interrupts_per_sec<F: FnOnce()>(f: F) -> (u64, u64) {
+    let start = axhal::time::monotonic_time();
+    let (timer, all) = (axhal::irq::timer_irq_count(), axhal::irq::irq_count());
+    f();
+    let timer = axhal::irq::timer_irq_count() - timer;
+    let all = axhal::irq::irq_count() - all;
+    let nanos = (axhal::time::monotonic_time() - start).as_nanos().max(1) as u64;
+    (
+        timer * axhal::time::NANOS_PER_SEC / nanos,
+        all * axhal::time::NANOS_PER_SEC / nanos,
+    )
+}
+
+/// Reports the interrupts per second while the CPUs idle, and while this
+/// task runs alone, to compare the builds with different `HZ`, and with the
+/// `tickless` or `dyntick` feature.
+#[cfg(feature = "axstd")]
+fn report_interrupt_rates() {
+    println!("{} Hz ticks:", axhal::time::tick_frequency());
+    let (timer, all) = interrupts_per_sec(|| thread::sleep(Duration::from_secs(1)));
+    println!("idle: {} timer interrupts/s, {} interrupts/s", timer, all);
+    let (timer, all) = interrupts_per_sec(|| axhal::time::busy_wait(Duration::from_secs(1)));
+    println!("busy: {} timer interrupts/s, {} interrupts/s", timer, all);
+}
+
+#[cfg(feature = "axstd")]
+fn report(what: &str, cycles: u64) {
+    println!(
+        "{}: {} cycles ({} ns)",
+        what,
+        cycles,
+        perf::cycles_to_nanos(cycles)
+    );
+}
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    #[cfg(feature = "axstd")]
+    {
+        report_interrupt_rates();
+        println!("{} sleepers", NUM_SLEEPERS);
+        report("timer tick, no sleepers", tick_cycles());
+        report("spawn and set timer, per sleeper", spawn_sleepers());
+        report("timer tick, all sleeping", tick_cycles());
+        report("wakeup and cancel timer, per sleeper", wake_sleepers());
+        report("timer tick, after wakeups", tick_cycles());
+    }
+    #[cfg(not(feature = "axstd"))]
+    println!("The benchmark only runs on ArceOS.");
+}
@@ -0,0 +1,74 @@
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+
+#[cfg(feature = "axstd")]
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "axstd")]
+use std::os::arceos::modules::axtask::{WaitQueue, WaitResult};
+#[cfg(feature = "axstd")]
+use std::{thread, time::Duration, time::Instant};
+
+/// The time budget of the wait.
+#[cfg(feature = "axstd")]
+const BUDGET: Duration = Duration::from_millis(100);
+/// The interval of the wakeups that do not end the wait.
+#[cfg(feature = "axstd")]
+const WAKEUP_INTERVAL: Duration = Duration::from_millis(7);
+/// How late the wait may end, for the resolution of the timers.
+#[cfg(feature = "axstd")]
+const SLACK: Duration = Duration::from_millis(20);
+
+#[cfg(feature = "axstd")]
+static WQ: WaitQueue = WaitQueue::new();
+#[cfg(feature = "axstd")]
+static DONE: AtomicBool = AtomicBool::new(false);
+
+/// Waits for the rest of the budget after each spurious wakeup, and returns
+/// how the wait ended and the number of the wakeups.
+#[cfg(feature = "axstd")]
+fn wait_for_budget() -> (WaitResult, usize) {
+    let mut budget = BUDGET;
+    let mut wakeups = 0;
+    loop {
+        let (result, remaining) = WQ.wait_timeout_remaining(budget);
+        if result != WaitResult::Notified {
+            return (result, wakeups);
+        }
+        wakeups += 1;
+        budget = remaining;
+    }
+}
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    #[cfg(feature = "axstd")]
+    {
+        let waker = thread::spawn(|| {
+            while !DONE.load(Ordering::Acquire) {
+                thread::sleep(WAKEUP_INTERVAL);
+                WQ.notify_one(true);
+            }
+        });
+
+        let start = Instant::now();
+        let (result, wakeups) = wait_for_budget();
+        let elapsed = start.elapsed();
+        DONE.store(true, Ordering::Release);
+        waker.join().unwrap();
+
+        println!(
+            "woken up {} times, timed out after {:?} of {:?}",
+            wakeups, elapsed, BUDGET
+        );
+        assert_eq!(result, WaitResult::TimedOut);
+        assert!(wakeups > 0);
+        assert!(elapsed >= BUDGET && elapsed < BUDGET + SLACK);
+        println!("Wait timeout test OK!");
+    }
+    #[cfg(not(feature = "axstd"))]
+    println!("The test only runs on ArceOS.");
+}
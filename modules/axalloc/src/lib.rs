@@ -13,6 +13,9 @@ extern crate alloc;
 
 mod page;
 
+#[cfg(feature = "numa")]
+mod numa;
+
 use allocator::{AllocResult, BaseAllocator, BitmapPageAllocator, ByteAllocator, PageAllocator};
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr::NonNull;
@@ -23,6 +26,9 @@ const MIN_HEAP_SIZE: usize = 0x8000; // 32 K
 
 pub use page::GlobalPage;
 
+#[cfg(feature = "numa")]
+pub use numa::MAX_NUMA_NODES;
+
 cfg_if::cfg_if! {
     if #[cfg(feature = "slab")] {
         /// The default byte allocator.
@@ -46,10 +52,16 @@ cfg_if::cfg_if! {
 /// Currently, [`TlsfByteAllocator`] is used as the byte allocator, while
 /// [`BitmapPageAllocator`] is used as the page allocator.
 ///
+/// With the `numa` feature, the memory of other NUMA nodes than the one of
+/// the initial region is in per-node page allocators, see
+/// [`add_node_memory`](GlobalAllocator::add_node_memory).
+///
 /// [`TlsfByteAllocator`]: allocator::TlsfByteAllocator
 pub struct GlobalAllocator {
     balloc: SpinNoIrq<DefaultByteAllocator>,
     palloc: SpinNoIrq<BitmapPageAllocator<PAGE_SIZE>>,
+    #[cfg(feature = "numa")]
+    nodes: SpinNoIrq<numa::NodePages>,
 }
 
 impl GlobalAllocator {
@@ -58,6 +70,8 @@ impl GlobalAllocator {
         Self {
             balloc: SpinNoIrq::new(DefaultByteAllocator::new()),
             palloc: SpinNoIrq::new(BitmapPageAllocator::new()),
+            #[cfg(feature = "numa")]
+            nodes: SpinNoIrq::new(numa::NodePages::new()),
         }
     }
 
@@ -89,6 +103,15 @@ impl GlobalAllocator {
         self.balloc.lock().init(heap_ptr, init_heap_size);
     }
 
+    /// Initializes the allocator as [`init`](GlobalAllocator::init), with a
+    /// region on the NUMA node `node`, which becomes the home node, whose
+    /// pages are given by the main page allocator.
+    #[cfg(feature = "numa")]
+    pub fn init_on_node(&self, node: usize, start_vaddr: usize, size: usize) {
+        self.nodes.lock().set_home(node);
+        self.init(start_vaddr, size);
+    }
+
     /// Add the given region to the allocator.
     ///
     /// It will add the whole region to the byte allocator.
@@ -96,6 +119,19 @@ impl GlobalAllocator {
         self.balloc.lock().add_memory(start_vaddr, size)
     }
 
+    /// Adds a region on the NUMA node `node` to the allocator.
+    ///
+    /// The first region of a node other than the home node becomes the page
+    /// allocator of the node. Other regions are added to the byte allocator
+    /// as [`add_memory`](GlobalAllocator::add_memory).
+    #[cfg(feature = "numa")]
+    pub fn add_node_memory(&self, node: usize, start_vaddr: usize, size: usize) -> AllocResult {
+        if self.nodes.lock().add_region(node, start_vaddr, size) {
+            return Ok(());
+        }
+        self.add_memory(start_vaddr, size)
+    }
+
     /// Allocate arbitrary number of bytes. Returns the left bound of the
     /// allocated region.
     ///
@@ -143,7 +179,33 @@ impl GlobalAllocator {
     /// `align_pow2` must be a power of 2, and the returned region bound will be
     /// aligned to it.
     pub fn alloc_pages(&self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
-        self.palloc.lock().alloc_pages(num_pages, align_pow2)
+        let res = self.palloc.lock().alloc_pages(num_pages, align_pow2);
+        #[cfg(feature = "numa")]
+        if res.is_err() {
+            return self.nodes.lock().alloc_pages_any(num_pages, align_pow2);
+        }
+        res
+    }
+
+    /// Allocates contiguous pages as [`alloc_pages`], preferably on the NUMA
+    /// node `node`, or on other nodes if it has no free memory.
+    ///
+    /// [`alloc_pages`]: GlobalAllocator::alloc_pages
+    #[cfg(feature = "numa")]
+    pub fn alloc_pages_on_node(
+        &self,
+        node: usize,
+        num_pages: usize,
+        align_pow2: usize,
+    ) -> AllocResult<usize> {
+        let mut nodes = self.nodes.lock();
+        if node != nodes.home() {
+            if let Ok(pos) = nodes.alloc_pages(node, num_pages, align_pow2) {
+                return Ok(pos);
+            }
+        }
+        drop(nodes);
+        self.alloc_pages(num_pages, align_pow2)
     }
 
     /// Gives back the allocated pages starts from `pos` to the page allocator.
@@ -154,6 +216,10 @@ impl GlobalAllocator {
     ///
     /// [`alloc_pages`]: GlobalAllocator::alloc_pages
     pub fn dealloc_pages(&self, pos: usize, num_pages: usize) {
+        #[cfg(feature = "numa")]
+        if self.nodes.lock().dealloc_pages(pos, num_pages) {
+            return;
+        }
         self.palloc.lock().dealloc_pages(pos, num_pages)
     }
 
@@ -169,12 +235,20 @@ impl GlobalAllocator {
 
     /// Returns the number of allocated pages in the page allocator.
     pub fn used_pages(&self) -> usize {
-        self.palloc.lock().used_pages()
+        #[cfg(feature = "numa")]
+        let node_pages = self.nodes.lock().used_pages();
+        #[cfg(not(feature = "numa"))]
+        let node_pages = 0;
+        self.palloc.lock().used_pages() + node_pages
     }
 
     /// Returns the number of available pages in the page allocator.
     pub fn available_pages(&self) -> usize {
-        self.palloc.lock().available_pages()
+        #[cfg(feature = "numa")]
+        let node_pages = self.nodes.lock().available_pages();
+        #[cfg(not(feature = "numa"))]
+        let node_pages = 0;
+        self.palloc.lock().available_pages() + node_pages
     }
 }
 
@@ -217,6 +291,19 @@ pub fn global_init(start_vaddr: usize, size: usize) {
     GLOBAL_ALLOCATOR.init(start_vaddr, size);
 }
 
+/// Initializes the global allocator as [`global_init`], with a memory region
+/// on the NUMA node `node`.
+#[cfg(feature = "numa")]
+pub fn global_init_on_node(node: usize, start_vaddr: usize, size: usize) {
+    debug!(
+        "initialize global allocator at: [{:#x}, {:#x}) on node {}",
+        start_vaddr,
+        start_vaddr + size,
+        node
+    );
+    GLOBAL_ALLOCATOR.init_on_node(node, start_vaddr, size);
+}
+
 /// Add the given memory region to the global allocator.
 ///
 /// Users should ensure that the region is valid and not being used by others,
@@ -231,3 +318,16 @@ pub fn global_add_memory(start_vaddr: usize, size: usize) -> AllocResult {
     );
     GLOBAL_ALLOCATOR.add_memory(start_vaddr, size)
 }
+
+/// Add the given memory region on the NUMA node `node` to the global
+/// allocator, see [`GlobalAllocator::add_node_memory`].
+#[cfg(feature = "numa")]
+pub fn global_add_node_memory(node: usize, start_vaddr: usize, size: usize) -> AllocResult {
+    debug!(
+        "add a memory region to global allocator: [{:#x}, {:#x}) on node {}",
+        start_vaddr,
+        start_vaddr + size,
+        node
+    );
+    GLOBAL_ALLOCATOR.add_node_memory(node, start_vaddr, size)
+}
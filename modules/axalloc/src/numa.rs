@@ -0,0 +1,115 @@
+use allocator::{AllocError, AllocResult, BaseAllocator, BitmapPageAllocator, PageAllocator};
+
+use crate::PAGE_SIZE;
+
+/// Maximum number of NUMA nodes with their own page allocators.
+pub const MAX_NUMA_NODES: usize = 8;
+
+/// The page allocator of the memory of a NUMA node.
+struct NodeAllocator {
+    start: usize,
+    end: usize,
+    palloc: BitmapPageAllocator<PAGE_SIZE>,
+}
+
+const NO_NODE: Option<NodeAllocator> = None;
+
+/// The page allocators of the NUMA nodes other than the home node, whose
+/// memory is in the main page allocator of [`GlobalAllocator`].
+///
+/// [`GlobalAllocator`]: crate::GlobalAllocator
+pub(crate) struct NodePages {
+    /// The node of the region the global allocator is initialized with.
+    home: usize,
+    nodes: [Option<NodeAllocator>; MAX_NUMA_NODES],
+}
+
+impl NodePages {
+    pub const fn new() -> Self {
+        Self {
+            home: 0,
+            nodes: [NO_NODE; MAX_NUMA_NODES],
+        }
+    }
+
+    pub fn home(&self) -> usize {
+        self.home
+    }
+
+    pub fn set_home(&mut self, node: usize) {
+        self.home = node;
+    }
+
+    /// Creates the page allocator of `node` with the region. Returns `false`
+    /// if the node is the home node, or already has a region, or is beyond
+    /// [`MAX_NUMA_NODES`].
+    pub fn add_region(&mut self, node: usize, start: usize, size: usize) -> bool {
+        if node == self.home || node >= MAX_NUMA_NODES || self.nodes[node].is_some() {
+            return false;
+        }
+        let mut palloc = BitmapPageAllocator::new();
+        palloc.init(start, size);
+        self.nodes[node] = Some(NodeAllocator {
+            start,
+            end: start + size,
+            palloc,
+        });
+        true
+    }
+
+    /// Allocates pages from `node`.
+    pub fn alloc_pages(
+        &mut self,
+        node: usize,
+        num_pages: usize,
+        align: usize,
+    ) -> AllocResult<usize> {
+        match self.nodes.get_mut(node) {
+            Some(Some(n)) => n.palloc.alloc_pages(num_pages, align),
+            _ => Err(AllocError::NoMemory),
+        }
+    }
+
+    /// Allocates pages from any node, for when the home node has no free
+    /// memory.
+    pub fn alloc_pages_any(&mut self, num_pages: usize, align: usize) -> AllocResult<usize> {
+        self.nodes
+            .iter_mut()
+            .flatten()
+            .find_map(|n| n.palloc.alloc_pages(num_pages, align).ok())
+            .ok_or(AllocError::NoMemory)
+    }
+
+    /// Gives back the pages to the allocator of their node. Returns `false`
+    /// if they are not in any node here, i.e., are of the home node.
+    pub fn dealloc_pages(&mut self, pos: usize, num_pages: usize) -> bool {
+        let node = self
+            .nodes
+            .iter_mut()
+            .flatten()
+            .find(|n| (n.start..n.end).contains(&pos));
+        match node {
+            Some(n) => {
+                n.palloc.dealloc_pages(pos, num_pages);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn used_pages(&self) -> usize {
+        self.nodes
+            .iter()
+            .flatten()
+            .map(|n| n.palloc.used_pages())
+            .sum()
+    }
+
+    pub fn available_pages(&self) -> usize {
+        self.nodes
+            .iter()
+            .flatten()
+            .map(|n| n.palloc.available_pages())
+            .sum()
+    }
+}
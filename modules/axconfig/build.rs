@@ -101,6 +101,53 @@ fn gen_config_rs(config_path: &Path) -> Result<Vec<u8>> {
         toml_edit::value(std::env::var("AX_SMP").unwrap_or("1".into())),
         Some("# Number of CPUs"),
     );
+    if let Ok(mac) = std::env::var("AX_MAC") {
+        if !mac.is_empty() {
+            let comments = get_comments(&config, "net-mac").map(String::from);
+            add_config(
+                &mut config,
+                "net-mac",
+                toml_edit::value(mac),
+                comments.as_deref(),
+            );
+        }
+    }
+
+    if let Ok(cpu) = std::env::var("AX_NET_IRQ_CPU") {
+        if !cpu.is_empty() {
+            let comments = get_comments(&config, "net-irq-cpu").map(String::from);
+            add_config(
+                &mut config,
+                "net-irq-cpu",
+                toml_edit::value(cpu),
+                comments.as_deref(),
+            );
+        }
+    }
+
+    if let Ok(hz) = std::env::var("AX_HZ") {
+        if !hz.is_empty() {
+            let comments = get_comments(&config, "ticks-per-sec").map(String::from);
+            add_config(
+                &mut config,
+                "ticks-per-sec",
+                toml_edit::value(hz),
+                comments.as_deref(),
+            );
+        }
+    }
+
+    if let Ok(panic) = std::env::var("AX_PANIC") {
+        if !panic.is_empty() {
+            let comments = get_comments(&config, "panic").map(String::from);
+            add_config(
+                &mut config,
+                "panic",
+                toml_edit::value(panic),
+                comments.as_deref(),
+            );
+        }
+    }
 
     // Generate config.rs
     let mut output = Vec::new();
@@ -137,7 +184,10 @@ fn gen_config_rs(config_path: &Path) -> Result<Vec<u8>> {
                         }
                         writeln!(output, "];")?;
                     }
-                    "mmio-regions" | "virtio-mmio-regions" | "pci-ranges" => {
+                    "mmio-regions"
+                    | "virtio-mmio-regions"
+                    | "sdhci-mmio-regions"
+                    | "pci-ranges" => {
                         writeln!(output, "{comments}")?;
                         writeln!(output, "pub const {var_name}: &[(usize, usize)] = &[")?;
                         for r in regions.iter() {
@@ -178,5 +228,9 @@ fn main() -> Result<()> {
     println!("cargo:rerun-if-changed={}", config_path.display());
     println!("cargo:rerun-if-env-changed=AX_PLATFORM");
     println!("cargo:rerun-if-env-changed=AX_SMP");
+    println!("cargo:rerun-if-env-changed=AX_MAC");
+    println!("cargo:rerun-if-env-changed=AX_NET_IRQ_CPU");
+    println!("cargo:rerun-if-env-changed=AX_PANIC");
+    println!("cargo:rerun-if-env-changed=AX_HZ");
     Ok(())
 }
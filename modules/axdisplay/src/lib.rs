@@ -1,6 +1,8 @@
 //! [ArceOS](https://github.com/arceos-org/arceos) graphics module.
 //!
-//! Currently only supports direct writing to the framebuffer.
+//! Supports direct writing to the framebuffer. Drawing can be flushed to the
+//! screen partially, or done in a back buffer and then presented at once,
+//! if the device supports double buffering.
 
 #![no_std]
 
@@ -34,3 +36,24 @@ pub fn framebuffer_info() -> DisplayInfo {
 pub fn framebuffer_flush() {
     MAIN_DISPLAY.lock().flush().unwrap();
 }
+
+/// Flushes a rectangle of the framebuffer to the screen.
+pub fn framebuffer_flush_region(x: u32, y: u32, width: u32, height: u32) -> DevResult {
+    MAIN_DISPLAY.lock().flush_region(x, y, width, height)
+}
+
+/// Gets the information of the back buffer, in which the next frame is
+/// drawn.
+///
+/// It is the framebuffer itself if the device is single-buffered.
+pub fn back_buffer_info() -> DisplayInfo {
+    MAIN_DISPLAY.lock().back_buffer_info()
+}
+
+/// Shows the back buffer on the screen, and swaps it with the framebuffer.
+///
+/// The frame is on the screen when it returns, so the new back buffer can be
+/// drawn without tearing.
+pub fn present() -> DevResult {
+    MAIN_DISPLAY.lock().present()
+}
@@ -1,3 +1,4 @@
+use alloc::vec::Vec;
 use core::{alloc::Layout, ptr::NonNull};
 
 use allocator::{AllocError, AllocResult, BaseAllocator, ByteAllocator};
@@ -7,39 +8,52 @@ use kspin::SpinNoIrq;
 use log::{debug, error};
 use memory_addr::{va, VirtAddr, PAGE_SIZE_4K};
 
-use crate::{phys_to_bus, BusAddr, DMAInfo};
+use crate::{phys_to_bus, BusAddr, DMAInfo, DmaMask};
 
 pub(crate) static ALLOCATOR: SpinNoIrq<DmaAllocator> = SpinNoIrq::new(DmaAllocator::new());
 
 pub(crate) struct DmaAllocator {
     alloc: DefaultByteAllocator,
+    /// The byte allocator of the memory within [`DmaMask::Bits32`].
+    alloc32: DefaultByteAllocator,
+    /// The regions added to `alloc32`, to find the allocator to free to.
+    regions32: Vec<(usize, usize)>,
 }
 
 impl DmaAllocator {
     pub const fn new() -> Self {
         Self {
             alloc: DefaultByteAllocator::new(),
+            alloc32: DefaultByteAllocator::new(),
+            regions32: Vec::new(),
         }
     }
 
-    /// Allocate arbitrary number of bytes. Returns the left bound of the
-    /// allocated region.
+    /// Allocate arbitrary number of bytes within the `mask`. Returns the left
+    /// bound of the allocated region.
     ///
-    /// It firstly tries to allocate from the coherent byte allocator. If there is no
-    /// memory, it asks the global page allocator for more memory and adds it to the
-    /// byte allocator.
-    pub unsafe fn alloc_coherent(&mut self, layout: Layout) -> AllocResult<DMAInfo> {
+    /// It firstly tries to allocate from the coherent byte allocator of the
+    /// mask. If there is no memory, it asks the global page allocator for more
+    /// memory and adds it to the byte allocator.
+    pub unsafe fn alloc_coherent(&mut self, layout: Layout, mask: DmaMask) -> AllocResult<DMAInfo> {
         if layout.size() >= PAGE_SIZE_4K {
-            self.alloc_coherent_pages(layout)
+            self.alloc_coherent_pages(layout, mask)
         } else {
-            self.alloc_coherent_bytes(layout)
+            self.alloc_coherent_bytes(layout, mask)
         }
     }
 
-    fn alloc_coherent_bytes(&mut self, layout: Layout) -> AllocResult<DMAInfo> {
+    fn byte_allocator(&mut self, mask: DmaMask) -> &mut DefaultByteAllocator {
+        match mask {
+            DmaMask::Bits32 => &mut self.alloc32,
+            DmaMask::Bits64 => &mut self.alloc,
+        }
+    }
+
+    fn alloc_coherent_bytes(&mut self, layout: Layout, mask: DmaMask) -> AllocResult<DMAInfo> {
         let mut is_expanded = false;
         loop {
-            if let Ok(data) = self.alloc.alloc(layout) {
+            if let Ok(data) = self.byte_allocator(mask).alloc(layout) {
                 let cpu_addr = va!(data.as_ptr() as usize);
                 return Ok(DMAInfo {
                     cpu_addr: data,
@@ -54,25 +68,27 @@ impl DmaAllocator {
                 // 4 pages or available pages.
                 let num_pages = 4.min(available_pages);
                 let expand_size = num_pages * PAGE_SIZE_4K;
-                let vaddr_raw = global_allocator().alloc_pages(num_pages, PAGE_SIZE_4K)?;
+                let vaddr_raw = alloc_pages_within(num_pages, PAGE_SIZE_4K, mask)?;
                 let vaddr = va!(vaddr_raw);
                 self.update_flags(
                     vaddr,
                     num_pages,
                     MappingFlags::READ | MappingFlags::WRITE | MappingFlags::UNCACHED,
                 )?;
-                self.alloc
+                self.byte_allocator(mask)
                     .add_memory(vaddr_raw, expand_size)
                     .inspect_err(|e| error!("add memory fail: {e:?}"))?;
+                if mask == DmaMask::Bits32 {
+                    self.regions32.push((vaddr_raw, expand_size));
+                }
                 debug!("expand memory @{vaddr:#X}, size: {expand_size:#X} bytes");
             }
         }
     }
 
-    fn alloc_coherent_pages(&mut self, layout: Layout) -> AllocResult<DMAInfo> {
+    fn alloc_coherent_pages(&mut self, layout: Layout, mask: DmaMask) -> AllocResult<DMAInfo> {
         let num_pages = layout_pages(&layout);
-        let vaddr_raw =
-            global_allocator().alloc_pages(num_pages, PAGE_SIZE_4K.max(layout.align()))?;
+        let vaddr_raw = alloc_pages_within(num_pages, PAGE_SIZE_4K.max(layout.align()), mask)?;
         let vaddr = va!(vaddr_raw);
         self.update_flags(
             vaddr,
@@ -113,12 +129,50 @@ impl DmaAllocator {
                 MappingFlags::READ | MappingFlags::WRITE,
             );
         } else {
-            self.alloc.dealloc(dma.cpu_addr, layout)
+            let addr = dma.cpu_addr.as_ptr() as usize;
+            let in_region32 = self
+                .regions32
+                .iter()
+                .any(|&(start, size)| (start..start + size).contains(&addr));
+            if in_region32 {
+                self.alloc32.dealloc(dma.cpu_addr, layout)
+            } else {
+                self.alloc.dealloc(dma.cpu_addr, layout)
+            }
         }
     }
 }
 
-const fn virt_to_bus(addr: VirtAddr) -> BusAddr {
+/// Allocates contiguous pages from the global allocator, which are all within
+/// the `mask` on the bus.
+///
+/// The global allocator gives the lowest free pages that fit, so the pages are
+/// only checked once. If they are out of the mask, there is no free region
+/// within it, and [`AllocError::NoMemory`] is returned.
+///
+/// With the `numa` feature, the pages on the NUMA node of the current CPU are
+/// preferred, and are checked first.
+fn alloc_pages_within(num_pages: usize, align: usize, mask: DmaMask) -> AllocResult<usize> {
+    #[cfg(feature = "numa")]
+    {
+        let node = axhal::numa::node_of_cpu(axhal::cpu::this_cpu_id());
+        let vaddr_raw = global_allocator().alloc_pages_on_node(node, num_pages, align)?;
+        if mask.contains(virt_to_bus(va!(vaddr_raw)), num_pages * PAGE_SIZE_4K) {
+            return Ok(vaddr_raw);
+        }
+        global_allocator().dealloc_pages(vaddr_raw, num_pages);
+    }
+    let vaddr_raw = global_allocator().alloc_pages(num_pages, align)?;
+    let bus_addr = virt_to_bus(va!(vaddr_raw));
+    if !mask.contains(bus_addr, num_pages * PAGE_SIZE_4K) {
+        global_allocator().dealloc_pages(vaddr_raw, num_pages);
+        debug!("no free pages within {mask:?}, got {bus_addr:?}");
+        return Err(AllocError::NoMemory);
+    }
+    Ok(vaddr_raw)
+}
+
+pub(crate) const fn virt_to_bus(addr: VirtAddr) -> BusAddr {
     let paddr = virt_to_phys(addr);
     phys_to_bus(paddr)
 }
@@ -9,9 +9,12 @@ mod dma;
 use core::{alloc::Layout, ptr::NonNull};
 
 use allocator::AllocResult;
-use memory_addr::PhysAddr;
+use memory_addr::{va, PhysAddr};
 
-use self::dma::ALLOCATOR;
+use self::dma::{virt_to_bus, ALLOCATOR};
+
+/// Alignment of the bounce buffers of [`map_single`], enough for a cache line.
+const BOUNCE_ALIGN: usize = 64;
 
 /// Converts a physical address to a bus address.
 ///
@@ -34,7 +37,18 @@ pub const fn phys_to_bus(paddr: PhysAddr) -> BusAddr {
 /// # Safety
 /// This function is unsafe because it directly interacts with the global allocator, which can potentially cause memory leaks or other issues if not used correctly.
 pub unsafe fn alloc_coherent(layout: Layout) -> AllocResult<DMAInfo> {
-    ALLOCATOR.lock().alloc_coherent(layout)
+    ALLOCATOR.lock().alloc_coherent(layout, DmaMask::Bits64)
+}
+
+/// Allocates **coherent** memory like [`alloc_coherent`], whose bus addresses
+/// are all within the `mask`, for devices that cannot address the whole bus.
+///
+/// Returns [`AllocError::NoMemory`](allocator::AllocError::NoMemory) if there
+/// is no free memory within the mask.
+/// # Safety
+/// See [`alloc_coherent`].
+pub unsafe fn alloc_coherent_with_mask(layout: Layout, mask: DmaMask) -> AllocResult<DMAInfo> {
+    ALLOCATOR.lock().alloc_coherent(layout, mask)
 }
 
 /// Frees coherent memory previously allocated.
@@ -48,6 +62,194 @@ pub unsafe fn dealloc_coherent(dma: DMAInfo, layout: Layout) {
     ALLOCATOR.lock().dealloc_coherent(dma, layout)
 }
 
+/// Maps a buffer of the caller for a single DMA transfer of a device with the
+/// given `mask`.
+///
+/// If the buffer is within the mask, the device accesses it directly.
+/// Otherwise a bounce buffer is allocated within the mask, and the data is
+/// copied through it: into it here for [`DmaDirection::ToDevice`], and back to
+/// the buffer by [`unmap_single`] for [`DmaDirection::FromDevice`]. Both are
+/// done for [`DmaDirection::Bidirectional`].
+///
+/// The caches are maintained as [`map_for_device`] if the buffer is mapped
+/// directly, while bounce buffers are coherent.
+///
+/// Returns [`AllocError::NoMemory`](allocator::AllocError::NoMemory) if a
+/// bounce buffer is needed but there is no free memory within the mask.
+/// # Safety
+/// The buffer must be valid for `size` bytes, physically contiguous (e.g., in
+/// the kernel heap), and not be accessed by the CPU until it is unmapped.
+pub unsafe fn map_single(
+    cpu_addr: NonNull<u8>,
+    size: usize,
+    dir: DmaDirection,
+    mask: DmaMask,
+) -> AllocResult<DmaMapping> {
+    let bus_addr = virt_to_bus(va!(cpu_addr.as_ptr() as usize));
+    if mask.contains(bus_addr, size) {
+        map_for_device(cpu_addr, size, dir);
+        return Ok(DmaMapping {
+            cpu_addr,
+            size,
+            dir,
+            bus_addr,
+            bounce: None,
+        });
+    }
+    let layout = Layout::from_size_align(size, BOUNCE_ALIGN)
+        .map_err(|_| allocator::AllocError::InvalidParam)?;
+    let bounce = alloc_coherent_with_mask(layout, mask)?;
+    if dir != DmaDirection::FromDevice {
+        core::ptr::copy_nonoverlapping(cpu_addr.as_ptr(), bounce.cpu_addr.as_ptr(), size);
+    }
+    Ok(DmaMapping {
+        cpu_addr,
+        size,
+        dir,
+        bus_addr: bounce.bus_addr,
+        bounce: Some((bounce, layout)),
+    })
+}
+
+/// Unmaps a buffer mapped by [`map_single`] after the DMA transfer is done.
+///
+/// If the data went through a bounce buffer and the device may have written
+/// it, it is copied back to the buffer of the caller.
+/// # Safety
+/// The device must no longer access the buffer.
+pub unsafe fn unmap_single(mapping: DmaMapping) {
+    match mapping.bounce {
+        Some((bounce, layout)) => {
+            if mapping.dir != DmaDirection::ToDevice {
+                core::ptr::copy_nonoverlapping(
+                    bounce.cpu_addr.as_ptr(),
+                    mapping.cpu_addr.as_ptr(),
+                    mapping.size,
+                );
+            }
+            dealloc_coherent(bounce, layout);
+        }
+        None => unmap_from_device(mapping.cpu_addr, mapping.size, mapping.dir),
+    }
+}
+
+/// Hands over a buffer in cacheable memory to the device for a DMA transfer
+/// (streaming DMA), and returns its bus address.
+///
+/// The cached data is written back for the device to read, and is discarded
+/// for [`DmaDirection::FromDevice`] and [`DmaDirection::Bidirectional`], so
+/// that no dirty line is evicted over the data written by the device. It is a
+/// no-op if DMA is coherent with the caches.
+///
+/// Unlike [`map_single`], the buffer must be within the DMA mask of the
+/// device, and no memory is allocated, so it is cheap to call around each
+/// access of descriptors and packet buffers. The buffer should be aligned to
+/// the cache lines, see [`axhal::cache::invalidate_dcache_range`].
+/// # Safety
+/// The buffer must be valid for `size` bytes, physically contiguous, and not
+/// be accessed by the CPU until [`unmap_from_device`] is called.
+pub unsafe fn map_for_device(cpu_addr: NonNull<u8>, size: usize, dir: DmaDirection) -> BusAddr {
+    let vaddr = va!(cpu_addr.as_ptr() as usize);
+    match dir {
+        DmaDirection::ToDevice => axhal::cache::flush_dcache_range(vaddr, size),
+        DmaDirection::FromDevice | DmaDirection::Bidirectional => {
+            axhal::cache::flush_invalidate_range(vaddr, size)
+        }
+    }
+    virt_to_bus(vaddr)
+}
+
+/// Takes back a buffer handed over by [`map_for_device`] after the DMA
+/// transfer is done.
+///
+/// If the device may have written the buffer, the cached data is discarded,
+/// including lines speculatively fetched during the transfer, so that the
+/// CPU reads the data of the device.
+/// # Safety
+/// The device must no longer access the buffer.
+pub unsafe fn unmap_from_device(cpu_addr: NonNull<u8>, size: usize, dir: DmaDirection) {
+    if dir != DmaDirection::ToDevice {
+        axhal::cache::invalidate_dcache_range(va!(cpu_addr.as_ptr() as usize), size);
+    }
+}
+
+/// The range of bus addresses that a device can access by DMA.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DmaMask {
+    /// Only the addresses below 4 GiB, e.g., of devices with 32-bit address
+    /// registers.
+    Bits32,
+    /// All addresses.
+    #[default]
+    Bits64,
+}
+
+impl DmaMask {
+    /// The highest bus address within the mask.
+    pub const fn max_addr(self) -> u64 {
+        match self {
+            Self::Bits32 => u32::MAX as u64,
+            Self::Bits64 => u64::MAX,
+        }
+    }
+
+    /// Whether the `size` bytes from `addr` are all within the mask.
+    pub const fn contains(self, addr: BusAddr, size: usize) -> bool {
+        if size == 0 {
+            return true;
+        }
+        match addr.as_u64().checked_add(size as u64 - 1) {
+            Some(end) => end <= self.max_addr(),
+            None => false,
+        }
+    }
+}
+
+/// The direction of the data in a DMA transfer of [`map_single`] or
+/// [`map_for_device`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaDirection {
+    /// The device reads the buffer.
+    ToDevice,
+    /// The device writes the buffer.
+    FromDevice,
+    /// The device both reads and writes the buffer.
+    Bidirectional,
+}
+
+/// A buffer mapped for a DMA transfer by [`map_single`].
+///
+/// It must be passed to [`unmap_single`] after the transfer, otherwise the
+/// bounce buffer is leaked and the data is not copied back.
+#[derive(Debug)]
+#[must_use]
+pub struct DmaMapping {
+    cpu_addr: NonNull<u8>,
+    size: usize,
+    dir: DmaDirection,
+    bus_addr: BusAddr,
+    /// The bounce buffer and its layout, if the buffer is out of the mask.
+    bounce: Option<(DMAInfo, Layout)>,
+}
+
+impl DmaMapping {
+    /// The bus address for the device to access, which is of the bounce
+    /// buffer if any.
+    pub const fn bus_addr(&self) -> BusAddr {
+        self.bus_addr
+    }
+
+    /// The size of the buffer in bytes.
+    pub const fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Whether the data goes through a bounce buffer.
+    pub const fn is_bounced(&self) -> bool {
+        self.bounce.is_some()
+    }
+}
+
 /// A bus memory address.
 ///
 /// It's a wrapper type around an [`u64`].
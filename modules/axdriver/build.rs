@@ -1,6 +1,20 @@
-const NET_DEV_FEATURES: &[&str] = &["igb", "ixgbe", "virtio-net"];
-const BLOCK_DEV_FEATURES: &[&str] = &["ramdisk", "bcm2835-sdhci", "virtio-blk"];
+const NET_DEV_FEATURES: &[&str] = &["igb", "ixgbe", "e1000", "rtl8139", "virtio-net", "loopback"];
+const BLOCK_DEV_FEATURES: &[&str] = &[
+    "ramdisk",
+    "bcm2835-sdhci",
+    "nvme",
+    "sdhci",
+    "usb-storage",
+    "virtio-blk",
+];
 const DISPLAY_DEV_FEATURES: &[&str] = &["virtio-gpu"];
+const CHAR_DEV_FEATURES: &[&str] = &["virtio-console"];
+const RNG_DEV_FEATURES: &[&str] = &["virtio-rng"];
+const _9P_DEV_FEATURES: &[&str] = &["virtio-9p"];
+const BALLOON_DEV_FEATURES: &[&str] = &["virtio-balloon"];
+const INPUT_DEV_FEATURES: &[&str] = &["virtio-input"];
+const SOUND_DEV_FEATURES: &[&str] = &["virtio-sound"];
+const PCI_ONLY_FEATURES: &[&str] = &["rtl8139"];
 
 fn make_cfg_values(str_list: &[&str]) -> String {
     str_list
@@ -23,10 +37,11 @@ fn enable_cfg(key: &str, value: &str) {
 }
 
 fn main() {
-    if has_feature("bus-mmio") {
-        enable_cfg("bus", "mmio");
-    } else {
+    let is_pci = !has_feature("bus-mmio");
+    if is_pci {
         enable_cfg("bus", "pci");
+    } else {
+        enable_cfg("bus", "mmio");
     }
 
     // Generate cfgs like `net_dev="virtio-net"`. if `dyn` is not enabled, only one device is
@@ -36,6 +51,12 @@ fn main() {
         ("net", NET_DEV_FEATURES),
         ("block", BLOCK_DEV_FEATURES),
         ("display", DISPLAY_DEV_FEATURES),
+        ("char", CHAR_DEV_FEATURES),
+        ("rng", RNG_DEV_FEATURES),
+        ("_9p", _9P_DEV_FEATURES),
+        ("balloon", BALLOON_DEV_FEATURES),
+        ("input", INPUT_DEV_FEATURES),
+        ("sound", SOUND_DEV_FEATURES),
     ] {
         if !has_feature(dev_kind) {
             continue;
@@ -43,6 +64,10 @@ fn main() {
 
         let mut selected = false;
         for feat in feat_list {
+            // PCI-only drivers are not built without the PCI bus.
+            if !is_pci && PCI_ONLY_FEATURES.contains(feat) {
+                continue;
+            }
             if has_feature(feat) {
                 enable_cfg(&format!("{dev_kind}_dev"), feat);
                 selected = true;
@@ -56,6 +81,19 @@ fn main() {
         }
     }
 
+    // Link the initial image of the RAM disk if `AX_RAMDISK_IMG` is given.
+    println!("cargo:rerun-if-env-changed=AX_RAMDISK_IMG");
+    if has_feature("ramdisk") {
+        if let Some(path) = std::env::var_os("AX_RAMDISK_IMG").filter(|p| !p.is_empty()) {
+            let path = std::fs::canonicalize(&path)
+                .unwrap_or_else(|e| panic!("invalid AX_RAMDISK_IMG {:?}: {}", path, e));
+            println!("cargo:rerun-if-changed={}", path.display());
+            println!("cargo:rustc-env=AX_RAMDISK_IMG={}", path.display());
+            println!("cargo:rustc-cfg=ramdisk_img");
+        }
+    }
+
+    println!("cargo::rustc-check-cfg=cfg(ramdisk_img)");
     println!(
         "cargo::rustc-check-cfg=cfg(bus, values({}))",
         make_cfg_values(&["pci", "mmio"])
@@ -72,4 +110,28 @@ fn main() {
         "cargo::rustc-check-cfg=cfg(display_dev, values({}, \"dummy\"))",
         make_cfg_values(DISPLAY_DEV_FEATURES)
     );
+    println!(
+        "cargo::rustc-check-cfg=cfg(char_dev, values({}, \"dummy\"))",
+        make_cfg_values(CHAR_DEV_FEATURES)
+    );
+    println!(
+        "cargo::rustc-check-cfg=cfg(rng_dev, values({}, \"dummy\"))",
+        make_cfg_values(RNG_DEV_FEATURES)
+    );
+    println!(
+        "cargo::rustc-check-cfg=cfg(_9p_dev, values({}, \"dummy\"))",
+        make_cfg_values(_9P_DEV_FEATURES)
+    );
+    println!(
+        "cargo::rustc-check-cfg=cfg(balloon_dev, values({}, \"dummy\"))",
+        make_cfg_values(BALLOON_DEV_FEATURES)
+    );
+    println!(
+        "cargo::rustc-check-cfg=cfg(input_dev, values({}, \"dummy\"))",
+        make_cfg_values(INPUT_DEV_FEATURES)
+    );
+    println!(
+        "cargo::rustc-check-cfg=cfg(sound_dev, values({}, \"dummy\"))",
+        make_cfg_values(SOUND_DEV_FEATURES)
+    );
 }
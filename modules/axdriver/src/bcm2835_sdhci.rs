@@ -0,0 +1,28 @@
+//! The SD card of the Raspberry Pi 4, behind the EMMC2 controller of the
+//! BCM2711.
+//!
+//! EMMC2 is a standard SDHCI controller driven by [`SdhciDev`], so transfers
+//! of consecutive blocks go out as single multi-block commands, SDHC/SDXC
+//! cards are addressed in blocks, and the SD clock is raised to the default
+//! speed after identification. Two quirks are worked around: the base clock
+//! is set up by the firmware instead of being reported in the capabilities
+//! register, and the card detect line is not wired to the controller.
+
+use axdriver_base::DevResult;
+
+use crate::sdhci::{SdhciDev, SdhciQuirks};
+
+/// Physical address of the EMMC2 registers.
+const EMMC2_PADDR: usize = 0xfe34_0000;
+/// Frequency of the EMMC2 clock set up by the firmware.
+const EMMC2_CLOCK_HZ: u32 = 100_000_000;
+
+/// Initializes the SD card behind the EMMC2 controller.
+pub fn init() -> DevResult<SdhciDev> {
+    let quirks = SdhciQuirks {
+        base_clock: Some(EMMC2_CLOCK_HZ),
+        broken_card_detect: true,
+    };
+    let base_vaddr = axhal::mem::phys_to_virt(EMMC2_PADDR.into());
+    SdhciDev::init_with_quirks(base_vaddr.as_usize(), quirks)
+}
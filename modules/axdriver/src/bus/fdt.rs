@@ -0,0 +1,310 @@
+//! Discovery of MMIO devices, PCI host bridge windows and PCI interrupt
+//! routing in the flattened device tree (FDT).
+//!
+//! Only the structure block is walked, without building a tree. The `reg`
+//! addresses are assumed to be physical addresses, i.e., the `ranges` of the
+//! parent buses are identity mappings, as on the QEMU virt machines.
+
+use alloc::vec::Vec;
+
+#[cfg(all(bus = "pci", feature = "irq"))]
+use axdriver_pci::DeviceFunction;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// Compatible strings of the nodes to be probed.
+#[cfg(bus = "mmio")]
+const MMIO_COMPATIBLES: &[&str] = &["virtio,mmio"];
+
+/// An MMIO device found in the device tree.
+#[cfg(bus = "mmio")]
+pub(crate) struct FdtMmioDevice {
+    /// Base physical address of the first `reg` range.
+    pub base: usize,
+    /// Size of the first `reg` range.
+    pub size: usize,
+    /// The first interrupt, if any.
+    pub irq: Option<usize>,
+}
+
+/// What is collected about a node while walking its properties.
+#[allow(dead_code)]
+struct Node<'a> {
+    /// `#address-cells` for the children, 2 if not given.
+    address_cells: usize,
+    /// `#size-cells` for the children, 1 if not given.
+    size_cells: usize,
+    /// Whether `#address-cells` is given, as it is taken as 0 in the unit
+    /// addresses of interrupt parents if not.
+    has_address_cells: bool,
+    /// `#interrupt-cells` of interrupt controllers and nexuses, 1 if not given.
+    interrupt_cells: usize,
+    phandle: Option<u32>,
+    compatible: &'a [u8],
+    device_type: &'a [u8],
+    disabled: bool,
+    reg: Option<&'a [u8]>,
+    interrupts: Option<&'a [u8]>,
+    ranges: Option<&'a [u8]>,
+    interrupt_map: Option<&'a [u8]>,
+    interrupt_map_mask: Option<&'a [u8]>,
+}
+
+impl Node<'_> {
+    const fn new() -> Self {
+        Self {
+            address_cells: 2,
+            size_cells: 1,
+            has_address_cells: false,
+            interrupt_cells: 1,
+            phandle: None,
+            compatible: &[],
+            device_type: &[],
+            disabled: false,
+            reg: None,
+            interrupts: None,
+            ranges: None,
+            interrupt_map: None,
+            interrupt_map_mask: None,
+        }
+    }
+
+    /// Whether any of the `compatible` strings is in `list`.
+    #[allow(dead_code)]
+    fn is_compatible(&self, list: &[&str]) -> bool {
+        self.compatible
+            .split(|&b| b == 0)
+            .any(|c| list.iter().any(|m| c == m.as_bytes()))
+    }
+}
+
+fn be32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// Reads a number of `cells` big-endian 32-bit cells.
+fn read_cells(data: &[u8], cells: usize) -> Option<u64> {
+    (0..cells).try_fold(0u64, |val, i| Some(val << 32 | be32(data, i * 4)? as u64))
+}
+
+/// Reads a NUL-terminated string at `offset`.
+fn read_str(data: &[u8], offset: usize) -> Option<&[u8]> {
+    let bytes = data.get(offset..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    Some(&bytes[..len])
+}
+
+const fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+/// Decodes the first interrupt of the `interrupts` property.
+///
+/// The number of cells of the interrupt controller is guessed from the
+/// property length: three cells are taken as a GIC interrupt specifier, and
+/// one cell as a plain IRQ number, e.g., of the RISC-V PLIC.
+#[cfg(any(bus = "mmio", all(bus = "pci", feature = "irq")))]
+fn decode_irq(interrupts: &[u8]) -> Option<usize> {
+    const GIC_SPI: u32 = 0;
+    const GIC_PPI: u32 = 1;
+    if interrupts.len() % 12 == 0 && !interrupts.is_empty() {
+        let num = be32(interrupts, 4)? as usize;
+        match be32(interrupts, 0)? {
+            GIC_SPI => Some(num + 32),
+            GIC_PPI => Some(num + 16),
+            _ => None,
+        }
+    } else {
+        be32(interrupts, 0).map(|irq| irq as usize)
+    }
+}
+
+/// Walks the structure block, and calls `f` with each node except the root
+/// and its parent, after all properties of the node are read.
+///
+/// Returns `None` if the blob is not a valid device tree.
+fn walk_nodes<'a>(dtb: &'a [u8], mut f: impl FnMut(&Node<'a>, &Node<'a>)) -> Option<()> {
+    if be32(dtb, 0)? != FDT_MAGIC {
+        return None;
+    }
+    let struct_off = be32(dtb, 8)? as usize;
+    let strings_off = be32(dtb, 12)? as usize;
+
+    let mut stack: Vec<Node> = Vec::new();
+    let mut pos = struct_off;
+    loop {
+        let token = be32(dtb, pos)?;
+        pos += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = read_str(dtb, pos)?;
+                pos = align4(pos + name.len() + 1);
+                stack.push(Node::new());
+            }
+            FDT_END_NODE => {
+                let node = stack.pop()?;
+                if let Some(parent) = stack.last() {
+                    f(&node, parent);
+                }
+            }
+            FDT_PROP => {
+                let len = be32(dtb, pos)? as usize;
+                let name = read_str(dtb, strings_off + be32(dtb, pos + 4)? as usize)?;
+                let value = dtb.get(pos + 8..pos + 8 + len)?;
+                pos = align4(pos + 8 + len);
+                let node = stack.last_mut()?;
+                match name {
+                    b"#address-cells" => {
+                        node.address_cells = be32(value, 0)? as usize;
+                        node.has_address_cells = true;
+                    }
+                    b"#size-cells" => node.size_cells = be32(value, 0)? as usize,
+                    b"#interrupt-cells" => node.interrupt_cells = be32(value, 0)? as usize,
+                    b"phandle" => node.phandle = Some(be32(value, 0)?),
+                    b"compatible" => node.compatible = value,
+                    b"device_type" => node.device_type = value,
+                    // "okay" or "ok" if enabled.
+                    b"status" => node.disabled = !value.starts_with(b"ok"),
+                    b"reg" => node.reg = Some(value),
+                    b"interrupts" => node.interrupts = Some(value),
+                    b"ranges" => node.ranges = Some(value),
+                    b"interrupt-map" => node.interrupt_map = Some(value),
+                    b"interrupt-map-mask" => node.interrupt_map_mask = Some(value),
+                    _ => {}
+                }
+            }
+            FDT_NOP => {}
+            FDT_END => return Some(()),
+            _ => return None,
+        }
+    }
+}
+
+/// Returns the enabled MMIO devices compatible with the drivers, ordered by
+/// their base addresses, or `None` if the blob is not a valid device tree.
+#[cfg(bus = "mmio")]
+pub(crate) fn mmio_devices(dtb: &[u8]) -> Option<Vec<FdtMmioDevice>> {
+    let mut devices = Vec::new();
+    let mut valid = true;
+    walk_nodes(dtb, |node, parent| {
+        // The `reg` of a node is in the cells of its parent.
+        let (Some(reg), true) = (node.reg, node.is_compatible(MMIO_COMPATIBLES)) else {
+            return;
+        };
+        let (Some(base), Some(size)) = (
+            read_cells(reg, parent.address_cells),
+            reg.get(parent.address_cells * 4..)
+                .and_then(|r| read_cells(r, parent.size_cells)),
+        ) else {
+            valid = false;
+            return;
+        };
+        if node.disabled {
+            debug!("FDT: skip disabled device at {:#x}", base);
+            return;
+        }
+        devices.push(FdtMmioDevice {
+            base: base as usize,
+            size: size as usize,
+            irq: node.interrupts.and_then(decode_irq),
+        });
+    })?;
+    if !valid {
+        return None;
+    }
+    devices.sort_by_key(|dev| dev.base);
+    Some(devices)
+}
+
+/// Returns the physical address and the size of the I/O space window of the
+/// first PCI host bridge, from its `ranges` property.
+///
+/// Each range is a 3-cell PCI address, whose space code in bits 24-25 of the
+/// first cell is 1 for I/O space, the CPU address in the cells of the parent,
+/// and a 2-cell size. The PCI address of the window is assumed to be 0.
+#[cfg(bus = "pci")]
+#[cfg_attr(target_arch = "x86_64", allow(dead_code))]
+pub(crate) fn pci_io_window(dtb: &[u8]) -> Option<(usize, usize)> {
+    const PCI_SPACE_IO: u32 = 1;
+    let mut window = None;
+    walk_nodes(dtb, |node, parent| {
+        let Some(ranges) = node.ranges.filter(|_| node.device_type == b"pci\0") else {
+            return;
+        };
+        if window.is_some() || node.disabled {
+            return;
+        }
+        let entry_cells = 3 + parent.address_cells + 2;
+        for entry in ranges.chunks_exact(entry_cells * 4) {
+            if be32(entry, 0).is_some_and(|hi| (hi >> 24) & 0b11 == PCI_SPACE_IO) {
+                let cpu_addr = read_cells(&entry[12..], parent.address_cells);
+                let size = read_cells(&entry[12 + parent.address_cells * 4..], 2);
+                window = cpu_addr.zip(size).map(|(a, s)| (a as usize, s as usize));
+                return;
+            }
+        }
+    })?;
+    window
+}
+
+/// Returns the IRQ of the legacy INTx `pin` (1 for INTA) of the PCI function,
+/// from the `interrupt-map` of the first PCI host bridge.
+///
+/// Each entry of the map is a 3-cell PCI address and a 1-cell pin, masked by
+/// `interrupt-map-mask`, followed by the phandle of the interrupt parent, a
+/// unit address and an interrupt specifier in the cells of the parent.
+/// Functions behind PCI bridges are only found if the map covers their bus,
+/// as the pins are not swizzled.
+#[cfg(all(bus = "pci", feature = "irq"))]
+pub(crate) fn pci_intx_irq(dtb: &[u8], bdf: DeviceFunction, pin: u8) -> Option<usize> {
+    let mut map = None;
+    // Phandle, `#address-cells` and `#interrupt-cells` of interrupt parents.
+    let mut parents = Vec::new();
+    walk_nodes(dtb, |node, _| {
+        if let Some(phandle) = node.phandle {
+            let address_cells = if node.has_address_cells {
+                node.address_cells
+            } else {
+                0
+            };
+            parents.push((phandle, address_cells, node.interrupt_cells));
+        }
+        if map.is_none() && node.device_type == b"pci\0" && !node.disabled {
+            map = node.interrupt_map.zip(node.interrupt_map_mask);
+        }
+    })?;
+    let (map, mask) = map?;
+
+    let child = [
+        (bdf.bus as u32) << 16 | (bdf.device as u32) << 11 | (bdf.function as u32) << 8,
+        0,
+        0,
+        pin as u32,
+    ];
+    let matches = |entry: &[u8]| {
+        (0..4).all(|i| {
+            let mask = be32(mask, i * 4).unwrap_or(0);
+            be32(entry, i * 4).is_some_and(|cell| cell & mask == child[i] & mask)
+        })
+    };
+
+    let mut pos = 0;
+    while pos < map.len() {
+        let phandle = be32(map, pos + 16)?;
+        let &(_, address_cells, interrupt_cells) = parents.iter().find(|p| p.0 == phandle)?;
+        let spec = pos + 20 + address_cells * 4;
+        let end = spec + interrupt_cells * 4;
+        if matches(&map[pos..]) {
+            return decode_irq(map.get(spec..end)?);
+        }
+        pos = end;
+    }
+    None
+}
@@ -1,23 +1,103 @@
 #[allow(unused_imports)]
-use crate::{prelude::*, AllDevices};
+use crate::drivers::DriverEntry;
+#[allow(unused_imports)]
+use crate::{prelude::*, AxDeviceEnum};
 
-impl AllDevices {
-    pub(crate) fn probe_bus_devices(&mut self) {
-        // TODO: parse device tree
-        #[cfg(feature = "virtio")]
-        for reg in axconfig::VIRTIO_MMIO_REGIONS {
-            for_each_drivers!(type Driver, {
-                if let Some(dev) = Driver::probe_mmio(reg.0, reg.1) {
-                    info!(
-                        "registered a new {:?} device at [PA:{:#x}, PA:{:#x}): {:?}",
-                        dev.device_type(),
-                        reg.0, reg.0 + reg.1,
-                        dev.device_name(),
-                    );
-                    self.add_device(dev);
-                    continue; // skip to the next device
+/// Probes the MMIO devices with the drivers in the given order, and passes
+/// each device found to `add` with the driver that claims it.
+///
+/// The devices are found in the device tree passed by the bootloader. Without
+/// one, the static regions in the platform config are probed instead if the
+/// `mmio-static` feature is enabled.
+#[allow(unused_variables, unused_mut)]
+pub(crate) fn probe_bus_devices(
+    drivers: &[DriverEntry],
+    mut add: impl FnMut(&DriverEntry, AxDeviceEnum),
+) {
+    match axhal::dtb::dtb().and_then(super::fdt::mmio_devices) {
+        Some(devices) => {
+            for dev in devices {
+                if super::map_mmio(dev.base, dev.size) {
+                    probe_mmio_region(drivers, dev.base, dev.size, dev.irq, &mut add);
                 }
-            });
+            }
+        }
+        None => probe_static_regions(drivers, &mut add),
+    }
+    // The controller is not described by the device tree on the supported
+    // boards, so its configured regions are always probed.
+    #[cfg(block_dev = "sdhci")]
+    for reg in axconfig::SDHCI_MMIO_REGIONS {
+        probe_mmio_region(drivers, reg.0, reg.1, None, &mut add);
+    }
+}
+
+/// Probes the VirtIO MMIO regions in the platform config. The IRQ of each
+/// device is [`axconfig::VIRTIO_MMIO_IRQ_BASE`] plus its index.
+#[cfg(feature = "mmio-static")]
+#[allow(unused_variables)]
+fn probe_static_regions(drivers: &[DriverEntry], add: &mut impl FnMut(&DriverEntry, AxDeviceEnum)) {
+    info!("no device tree found, probing the static MMIO regions");
+    #[cfg(feature = "virtio")]
+    for (i, reg) in axconfig::VIRTIO_MMIO_REGIONS.iter().enumerate() {
+        let irq = match axconfig::VIRTIO_MMIO_IRQ_BASE {
+            0 => None,
+            base => Some(base + i),
+        };
+        probe_mmio_region(drivers, reg.0, reg.1, irq, add);
+    }
+}
+
+#[cfg(not(feature = "mmio-static"))]
+fn probe_static_regions(
+    _drivers: &[DriverEntry],
+    _add: &mut impl FnMut(&DriverEntry, AxDeviceEnum),
+) {
+    warn!("no device tree found, enable feature `mmio-static` to probe the static MMIO regions");
+}
+
+/// Probes the device in the given MMIO region with the drivers in order,
+/// until one of them succeeds.
+#[allow(dead_code)]
+fn probe_mmio_region(
+    drivers: &[DriverEntry],
+    mmio_base: usize,
+    mmio_size: usize,
+    irq: Option<usize>,
+    add: &mut impl FnMut(&DriverEntry, AxDeviceEnum),
+) {
+    for driver in drivers {
+        match driver.with_dma_mask(|| (driver.probe_mmio)(mmio_base, mmio_size, irq)) {
+            Ok(Some(dev)) => {
+                info!(
+                    "registered a new {:?} device at [PA:{:#x}, PA:{:#x}) by {}: {:?}",
+                    dev.device_type(),
+                    mmio_base,
+                    mmio_base + mmio_size,
+                    driver.name,
+                    dev.device_name(),
+                );
+                add(driver, dev);
+                return; // skip to the next device
+            }
+            Ok(None) => debug!(
+                "MMIO [PA:{:#x}, PA:{:#x}): declined by {}",
+                mmio_base,
+                mmio_base + mmio_size,
+                driver.name
+            ),
+            // Try the next driver, a failed device must not stop the boot.
+            Err(e) => warn!(
+                "MMIO [PA:{:#x}, PA:{:#x}): {}",
+                mmio_base,
+                mmio_base + mmio_size,
+                e
+            ),
         }
     }
+    debug!(
+        "MMIO [PA:{:#x}, PA:{:#x}): no driver claims it",
+        mmio_base,
+        mmio_base + mmio_size
+    );
 }
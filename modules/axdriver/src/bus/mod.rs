@@ -1,4 +1,72 @@
+#[cfg(any(bus = "mmio", bus = "pci"))]
+mod fdt;
 #[cfg(bus = "mmio")]
 mod mmio;
 #[cfg(bus = "pci")]
 mod pci;
+
+#[cfg(bus = "mmio")]
+pub(crate) use self::mmio::probe_bus_devices;
+#[cfg(bus = "pci")]
+pub use self::pci::{bar_info, map_bar, map_io_bar, BarHandle, IoBar, IoValue, MappedBar};
+#[cfg(bus = "pci")]
+pub(crate) use self::pci::{probe_bus_devices, walk_pci_hierarchy};
+
+#[cfg(all(bus = "pci", feature = "irq"))]
+mod msix;
+
+#[cfg(all(bus = "pci", feature = "irq"))]
+pub(crate) use self::msix::capabilities;
+#[cfg(all(bus = "pci", feature = "irq"))]
+pub use self::msix::PciMsixExt;
+#[cfg(all(bus = "pci", feature = "irq"))]
+pub(crate) use self::pci::config_read;
+#[cfg(all(bus = "pci", feature = "irq"))]
+pub use self::pci::intx_irq;
+
+/// Makes sure the physical range is mapped in the linear mapping, which is
+/// not the case for device memory outside of [`axconfig::MMIO_REGIONS`], e.g.,
+/// 64-bit PCI BARs above 4 GiB, or MMIO devices only found in the device tree.
+#[cfg(feature = "paging")]
+pub(crate) fn map_mmio(paddr: usize, size: usize) -> bool {
+    use alloc::vec::Vec;
+    use axhal::{mem::phys_to_virt, paging::MappingFlags};
+    use kspin::SpinNoIrq;
+
+    // Ranges mapped by previous calls.
+    static MAPPED: SpinNoIrq<Vec<(usize, usize)>> = SpinNoIrq::new(Vec::new());
+
+    let start = paddr & !0xfff;
+    let end = (paddr + size + 0xfff) & !0xfff;
+    let contains = |&(base, len): &(usize, usize)| base <= start && end <= base + len;
+    if axconfig::MMIO_REGIONS.iter().any(contains) {
+        return true;
+    }
+    let mut mapped = MAPPED.lock();
+    if mapped.iter().any(contains) {
+        return true;
+    }
+    let flags = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::DEVICE;
+    let res = axmm::kernel_aspace().lock().map_linear(
+        phys_to_virt(start.into()),
+        start.into(),
+        end - start,
+        flags,
+    );
+    match res {
+        Ok(_) => {
+            debug!("mapped MMIO [{:#x}, {:#x})", start, end);
+            mapped.push((start, end - start));
+            true
+        }
+        Err(e) => {
+            warn!("failed to map MMIO [{:#x}, {:#x}): {:?}", start, end, e);
+            false
+        }
+    }
+}
+
+#[cfg(not(feature = "paging"))]
+pub(crate) fn map_mmio(_paddr: usize, _size: usize) -> bool {
+    true
+}
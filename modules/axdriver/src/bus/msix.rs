@@ -0,0 +1,258 @@
+//! MSI-X support for PCI devices.
+
+use alloc::vec::Vec;
+
+use axdriver_base::{DevError, DevResult};
+use axdriver_pci::{Command, DeviceFunction, PciRoot};
+use axhal::irq::{alloc_msi, free_msi, msi_message, MsiAllocation, MsiMessage};
+
+use super::pci::{config_read, config_write};
+
+const PCI_STATUS_COMMAND: u8 = 0x04;
+const PCI_CAP_POINTER: u8 = 0x34;
+const PCI_STATUS_CAP_LIST: u32 = 1 << (16 + 4);
+
+const PCI_CAP_ID_MSIX: u8 = 0x11;
+
+const MSIX_CTRL_TABLE_SIZE_MASK: u16 = 0x7ff;
+const MSIX_CTRL_FUNC_MASK: u16 = 1 << 14;
+const MSIX_CTRL_ENABLE: u16 = 1 << 15;
+
+const MSIX_ENTRY_SIZE: usize = 16;
+const MSIX_ENTRY_ADDR_LO: usize = 0;
+const MSIX_ENTRY_ADDR_HI: usize = 4;
+const MSIX_ENTRY_DATA: usize = 8;
+const MSIX_ENTRY_CTRL: usize = 12;
+const MSIX_ENTRY_CTRL_MASKED: u32 = 1;
+
+/// Walks the capability list, returns the ID and the offset of each
+/// capability.
+pub(crate) fn capabilities(bdf: DeviceFunction) -> impl Iterator<Item = (u8, u8)> {
+    let mut offset = if config_read(bdf, PCI_STATUS_COMMAND) & PCI_STATUS_CAP_LIST != 0 {
+        config_read(bdf, PCI_CAP_POINTER) as u8 & !3
+    } else {
+        0
+    };
+    // at most 48 capabilities fit in the configuration space, guard against loops
+    core::iter::from_fn(move || {
+        if offset == 0 {
+            return None;
+        }
+        let header = config_read(bdf, offset);
+        let cap = (header as u8, offset);
+        offset = (header >> 8) as u8 & !3;
+        Some(cap)
+    })
+    .take(48)
+}
+
+/// Returns the offset of the first capability with the given ID.
+fn find_capability(bdf: DeviceFunction, cap_id: u8) -> Option<u8> {
+    capabilities(bdf).find_map(|(id, offset)| (id == cap_id).then_some(offset))
+}
+
+/// The MSI-X capability of a PCI function, with the table and the pending bit
+/// array mapped.
+struct MsixCap {
+    bdf: DeviceFunction,
+    offset: u8,
+    table_size: usize,
+    table_vaddr: usize,
+    pba_vaddr: usize,
+}
+
+impl MsixCap {
+    fn probe(root: &mut PciRoot, bdf: DeviceFunction) -> DevResult<Self> {
+        let offset = find_capability(bdf, PCI_CAP_ID_MSIX).ok_or(DevError::Unsupported)?;
+        let mut cap = Self {
+            bdf,
+            offset,
+            table_size: 0,
+            table_vaddr: 0,
+            pba_vaddr: 0,
+        };
+        cap.table_size = (cap.control() & MSIX_CTRL_TABLE_SIZE_MASK) as usize + 1;
+        cap.table_vaddr = Self::map(root, bdf, config_read(bdf, offset + 4))?;
+        cap.pba_vaddr = Self::map(root, bdf, config_read(bdf, offset + 8))?;
+        Ok(cap)
+    }
+
+    /// Maps the structure described by a table/PBA offset register, whose low
+    /// 3 bits are the BAR indicator.
+    fn map(root: &mut PciRoot, bdf: DeviceFunction, reg: u32) -> DevResult<usize> {
+        let bar = (reg & 7) as u8;
+        match super::map_bar(root, bdf, bar) {
+            Some(mapped) => Ok(mapped.vaddr + (reg & !7) as usize),
+            None => {
+                warn!("MSI-X: BAR {} of {} is not a memory BAR", bar, bdf);
+                Err(DevError::BadState)
+            }
+        }
+    }
+
+    fn control(&self) -> u16 {
+        (config_read(self.bdf, self.offset) >> 16) as u16
+    }
+
+    fn set_control(&self, control: u16) {
+        let header = config_read(self.bdf, self.offset) & 0xffff;
+        config_write(self.bdf, self.offset, (control as u32) << 16 | header);
+    }
+
+    fn entry_reg(&self, index: usize, reg: usize) -> *mut u32 {
+        (self.table_vaddr + index * MSIX_ENTRY_SIZE + reg) as _
+    }
+
+    fn check_index(&self, index: usize) -> DevResult {
+        if index < self.table_size {
+            Ok(())
+        } else {
+            Err(DevError::InvalidParam)
+        }
+    }
+
+    fn set_masked(&self, index: usize, masked: bool) {
+        let ctrl = self.entry_reg(index, MSIX_ENTRY_CTRL);
+        unsafe {
+            let value = ctrl.read_volatile();
+            if masked {
+                ctrl.write_volatile(value | MSIX_ENTRY_CTRL_MASKED);
+            } else {
+                ctrl.write_volatile(value & !MSIX_ENTRY_CTRL_MASKED);
+            }
+        }
+    }
+
+    fn write_message(&self, index: usize, msg: MsiMessage) {
+        unsafe {
+            let addr_lo = self.entry_reg(index, MSIX_ENTRY_ADDR_LO);
+            let addr_hi = self.entry_reg(index, MSIX_ENTRY_ADDR_HI);
+            addr_lo.write_volatile(msg.address as u32);
+            addr_hi.write_volatile((msg.address >> 32) as u32);
+            self.entry_reg(index, MSIX_ENTRY_DATA)
+                .write_volatile(msg.data);
+        }
+    }
+
+    fn is_masked(&self, index: usize) -> bool {
+        let ctrl = self.entry_reg(index, MSIX_ENTRY_CTRL);
+        unsafe { ctrl.read_volatile() & MSIX_ENTRY_CTRL_MASKED != 0 }
+    }
+
+    fn pending(&self, index: usize) -> bool {
+        let word = (self.pba_vaddr as *const u64).wrapping_add(index / 64);
+        unsafe { word.read_volatile() & (1 << (index % 64)) != 0 }
+    }
+}
+
+/// MSI-X operations of PCI functions.
+pub trait PciMsixExt {
+    /// Allocates `count` interrupt vectors for the MSI-X table entries
+    /// `0..count` of the function, and enables MSI-X.
+    ///
+    /// Returns the IRQ number of each entry. The entries are left masked:
+    /// register the handlers with [`axhal::irq::register_handler`] first,
+    /// then unmask them with [`set_msix_masked`](Self::set_msix_masked).
+    fn alloc_msix(&mut self, bdf: DeviceFunction, count: usize) -> DevResult<Vec<usize>>;
+
+    /// Disables MSI-X of the function and frees the vectors returned by
+    /// [`alloc_msix`](Self::alloc_msix).
+    fn free_msix(&mut self, bdf: DeviceFunction, irqs: &[usize]) -> DevResult;
+
+    /// Masks or unmasks the given MSI-X table entry.
+    fn set_msix_masked(&mut self, bdf: DeviceFunction, index: usize, masked: bool) -> DevResult;
+
+    /// Routes the interrupt of the given MSI-X table entry, whose IRQ is `irq`,
+    /// to the CPU `cpu_id`, e.g., to spread the queues of a NIC over CPUs.
+    ///
+    /// The message of the entry is rewritten with the entry masked, as the
+    /// destination is in the message on some platforms.
+    fn set_msix_affinity(
+        &mut self,
+        bdf: DeviceFunction,
+        index: usize,
+        irq: usize,
+        cpu_id: usize,
+    ) -> DevResult;
+
+    /// Whether the given MSI-X table entry has a pending interrupt, which is
+    /// delivered once the entry is unmasked.
+    fn msix_pending(&mut self, bdf: DeviceFunction, index: usize) -> DevResult<bool>;
+}
+
+impl PciMsixExt for PciRoot {
+    fn alloc_msix(&mut self, bdf: DeviceFunction, count: usize) -> DevResult<Vec<usize>> {
+        let cap = MsixCap::probe(self, bdf)?;
+        if count == 0 || count > cap.table_size {
+            return Err(DevError::InvalidParam);
+        }
+
+        // Program the table with the whole function masked.
+        cap.set_control(cap.control() | MSIX_CTRL_ENABLE | MSIX_CTRL_FUNC_MASK);
+        let Some(alloc) = alloc_msi(count) else {
+            warn!("MSI-X: out of vectors for {}", bdf);
+            cap.set_control(cap.control() & !(MSIX_CTRL_ENABLE | MSIX_CTRL_FUNC_MASK));
+            return Err(DevError::NoMemory);
+        };
+        let irqs: Vec<usize> = (0..count).map(|index| alloc.irq(index)).collect();
+        for index in 0..count {
+            cap.set_masked(index, true);
+            cap.write_message(index, alloc.message(index));
+        }
+        cap.set_control(cap.control() & !MSIX_CTRL_FUNC_MASK);
+
+        // Legacy INTx must not be used together with MSI-X.
+        let (_status, cmd) = self.get_status_command(bdf);
+        self.set_command(bdf, cmd | Command::INTERRUPT_DISABLE);
+        debug!("MSI-X: allocated IRQs {:?} for {}", irqs, bdf);
+        Ok(irqs)
+    }
+
+    fn free_msix(&mut self, bdf: DeviceFunction, irqs: &[usize]) -> DevResult {
+        let cap = MsixCap::probe(self, bdf)?;
+        cap.set_control(cap.control() | MSIX_CTRL_FUNC_MASK);
+        for index in 0..irqs.len().min(cap.table_size) {
+            cap.set_masked(index, true);
+        }
+        cap.set_control(cap.control() & !(MSIX_CTRL_ENABLE | MSIX_CTRL_FUNC_MASK));
+        for &irq in irqs {
+            free_msi(MsiAllocation {
+                first_irq: irq,
+                count: 1,
+            });
+        }
+        Ok(())
+    }
+
+    fn set_msix_masked(&mut self, bdf: DeviceFunction, index: usize, masked: bool) -> DevResult {
+        let cap = MsixCap::probe(self, bdf)?;
+        cap.check_index(index)?;
+        cap.set_masked(index, masked);
+        Ok(())
+    }
+
+    fn set_msix_affinity(
+        &mut self,
+        bdf: DeviceFunction,
+        index: usize,
+        irq: usize,
+        cpu_id: usize,
+    ) -> DevResult {
+        let cap = MsixCap::probe(self, bdf)?;
+        cap.check_index(index)?;
+        if !axhal::irq::set_affinity(irq, cpu_id) {
+            return Err(DevError::Unsupported);
+        }
+        let masked = cap.is_masked(index);
+        cap.set_masked(index, true);
+        cap.write_message(index, msi_message(irq));
+        cap.set_masked(index, masked);
+        Ok(())
+    }
+
+    fn msix_pending(&mut self, bdf: DeviceFunction, index: usize) -> DevResult<bool> {
+        let cap = MsixCap::probe(self, bdf)?;
+        cap.check_index(index)?;
+        Ok(cap.pending(index))
+    }
+}
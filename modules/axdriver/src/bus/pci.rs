@@ -1,4 +1,8 @@
-use crate::{prelude::*, AllDevices};
+use alloc::vec::Vec;
+
+use crate::drivers::DriverEntry;
+use crate::pci::{PciBar, PciBarKind, PciDeviceInfo};
+use crate::{prelude::*, AxDeviceEnum};
 use axdriver_pci::{
     BarInfo, Cam, Command, DeviceFunction, HeaderType, MemoryBarType, PciRangeAllocator, PciRoot,
 };
@@ -6,15 +10,343 @@ use axhal::mem::phys_to_virt;
 
 const PCI_BAR_NUM: u8 = 6;
 
+const PCI_HEADER_TYPE: u8 = 0x0c;
+const PCI_HEADER_MULTIFUNCTION: u32 = 0x80 << 16;
+/// Primary, secondary and subordinate bus numbers of bridges.
+const PCI_BRIDGE_BUS_NUMBERS: u8 = 0x18;
+const PCI_INTERRUPT: u8 = 0x3c;
+
+/// Vendor and device ID of the host bridge of QEMU q35 (Intel 82G33 MCH).
+#[cfg(all(target_arch = "x86_64", feature = "irq"))]
+const Q35_HOST_BRIDGE_ID: u32 = 0x29c0_8086;
+
+fn config_ptr(bdf: DeviceFunction, offset: u8) -> *mut u32 {
+    let offset = (bdf.bus as usize) << 20
+        | (bdf.device as usize) << 15
+        | (bdf.function as usize) << 12
+        | (offset & !3) as usize;
+    phys_to_virt(axhal::mem::pci_ecam().0 + offset).as_mut_ptr() as _
+}
+
+/// Reads the 32-bit register of the configuration space containing `offset`.
+pub(crate) fn config_read(bdf: DeviceFunction, offset: u8) -> u32 {
+    unsafe { config_ptr(bdf, offset).read_volatile() }
+}
+
+#[cfg(feature = "irq")]
+pub(crate) fn config_write(bdf: DeviceFunction, offset: u8, value: u32) {
+    unsafe { config_ptr(bdf, offset).write_volatile(value) }
+}
+
+/// Returns the IRQ number of the legacy INTx interrupt of the function, or
+/// `None` if the function uses no interrupt pin or the pin is not routed.
+///
+/// On x86_64, the GSI of the pin follows the routing of QEMU q35 or the
+/// interrupt line register, and is routed as a level-triggered interrupt.
+/// On other architectures, the pin is looked up in the `interrupt-map` of the
+/// PCI host bridge in the device tree.
+///
+/// Drivers preferring MSI-X call it when [`PciMsixExt::alloc_msix`] fails.
+///
+/// [`PciMsixExt::alloc_msix`]: super::PciMsixExt::alloc_msix
+#[cfg(feature = "irq")]
+pub fn intx_irq(bdf: DeviceFunction) -> Option<usize> {
+    let interrupt = config_read(bdf, PCI_INTERRUPT);
+    let pin = (interrupt >> 8) as u8;
+    if pin == 0 {
+        return None;
+    }
+    #[cfg(target_arch = "x86_64")]
+    let irq = x86_intx_gsi(bdf, pin, interrupt as u8).and_then(|gsi| {
+        use axhal::irq::{Polarity, TriggerMode};
+        // PCI INTx lines are level-triggered, and active high as QEMU
+        // describes them in ACPI.
+        let irq = axhal::irq::gsi_irq(gsi)?;
+        let cpu_id = axhal::cpu::this_cpu_id();
+        axhal::irq::route_gsi(gsi, irq, cpu_id, TriggerMode::Level, Polarity::ActiveHigh)
+            .then_some(irq)
+    });
+    #[cfg(not(target_arch = "x86_64"))]
+    let irq = axhal::dtb::dtb().and_then(|dtb| super::fdt::pci_intx_irq(dtb, bdf, pin));
+    if irq.is_none() {
+        debug!("PCI {}: INT{} is not routed", bdf, (b'A' + pin - 1) as char);
+    }
+    irq
+}
+
+/// Returns the device number on bus 0 and the pin that the INTx `pin` of the
+/// function arrives at, through the swizzle of the PCI-to-PCI bridges.
+#[cfg(all(target_arch = "x86_64", feature = "irq"))]
+fn swizzle_to_root(bdf: DeviceFunction, pin: u8) -> Option<(u8, u8)> {
+    let (mut bus, mut device, mut pin) = (bdf.bus, bdf.device, pin);
+    if bus == 0 {
+        return Some((device, pin));
+    }
+    let functions = walk_pci_hierarchy();
+    while bus != 0 {
+        let bridge = functions.iter().find(|f| f.secondary_bus == Some(bus))?;
+        pin = (pin - 1 + device) % 4 + 1;
+        (bus, device) = (bridge.bus, bridge.device);
+    }
+    Some((device, pin))
+}
+
+/// Returns the GSI of the INTx `pin` of the function on x86_64.
+///
+/// The `_PRT` methods in ACPI are not interpreted, so on QEMU q35 the routing
+/// of the chipset is computed: INTA to INTD of the devices below 0x18 on bus 0
+/// go to PIRQE to PIRQH, i.e., GSIs 20 to 23, rotated by the device number.
+/// Otherwise, it is the interrupt line register written by the firmware.
+#[cfg(all(target_arch = "x86_64", feature = "irq"))]
+fn x86_intx_gsi(bdf: DeviceFunction, pin: u8, line: u8) -> Option<usize> {
+    let host_bridge = DeviceFunction {
+        bus: 0,
+        device: 0,
+        function: 0,
+    };
+    let is_q35 = config_read(host_bridge, 0) == Q35_HOST_BRIDGE_ID;
+    match swizzle_to_root(bdf, pin) {
+        Some((device, pin)) if is_q35 && device < 0x18 => {
+            Some(20 + (device as usize + pin as usize - 1) % 4)
+        }
+        _ => (line != 0xff).then_some(line as usize),
+    }
+}
+
+/// A memory BAR mapped into the kernel address space.
+#[derive(Debug, Clone, Copy)]
+pub struct MappedBar {
+    /// The physical address of the BAR.
+    pub paddr: usize,
+    /// The virtual address the BAR is mapped to.
+    pub vaddr: usize,
+    /// The size of the BAR in bytes.
+    pub size: usize,
+}
+
+/// Ports below it are not assigned to I/O BARs, as port 0 means unassigned.
+#[cfg(not(target_arch = "x86_64"))]
+const PCI_IO_PORT_START: u64 = 0x1000;
+
+/// An I/O BAR, whose registers are in the PCI I/O space.
+///
+/// On x86_64, the registers are accessed by port I/O instructions. On other
+/// architectures, the I/O space is a window in the physical memory, which is
+/// given by the `ranges` of the PCI host bridge in the device tree, or the
+/// first range of [`axconfig::PCI_RANGES`] if there is no device tree.
+#[derive(Debug, Clone, Copy)]
+pub struct IoBar {
+    /// The address of the BAR in the PCI I/O space.
+    pub port: usize,
+    /// The size of the BAR in bytes.
+    pub size: usize,
+    /// The virtual address the BAR is mapped to.
+    #[cfg(not(target_arch = "x86_64"))]
+    vaddr: usize,
+}
+
+/// Values that can be read from or written to an [`IoBar`].
+pub trait IoValue: Copy {
+    #[cfg(target_arch = "x86_64")]
+    #[doc(hidden)]
+    unsafe fn port_read(port: u16) -> Self;
+    #[cfg(target_arch = "x86_64")]
+    #[doc(hidden)]
+    unsafe fn port_write(port: u16, val: Self);
+}
+
+macro_rules! impl_io_value {
+    ($ty:ty, $reg:literal) => {
+        impl IoValue for $ty {
+            #[cfg(target_arch = "x86_64")]
+            unsafe fn port_read(port: u16) -> Self {
+                let val: Self;
+                core::arch::asm!(concat!("in ", $reg, ", dx"), out($reg) val, in("dx") port,
+                    options(nomem, nostack, preserves_flags));
+                val
+            }
+
+            #[cfg(target_arch = "x86_64")]
+            unsafe fn port_write(port: u16, val: Self) {
+                core::arch::asm!(concat!("out dx, ", $reg), in("dx") port, in($reg) val,
+                    options(nomem, nostack, preserves_flags));
+            }
+        }
+    };
+}
+
+impl_io_value!(u8, "al");
+impl_io_value!(u16, "ax");
+impl_io_value!(u32, "eax");
+
+impl IoBar {
+    fn check(&self, offset: usize, len: usize) {
+        assert!(
+            offset.checked_add(len).is_some_and(|end| end <= self.size),
+            "I/O BAR access out of range: {:#x}",
+            offset
+        );
+    }
+
+    /// Reads the register at `offset` from the start of the BAR.
+    ///
+    /// Panics if the register is not within the BAR.
+    pub fn read_io<T: IoValue>(&self, offset: usize) -> T {
+        self.check(offset, core::mem::size_of::<T>());
+        #[cfg(target_arch = "x86_64")]
+        unsafe {
+            T::port_read((self.port + offset) as u16)
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        unsafe {
+            ((self.vaddr + offset) as *const T).read_volatile()
+        }
+    }
+
+    /// Writes the register at `offset` from the start of the BAR.
+    ///
+    /// Panics if the register is not within the BAR.
+    pub fn write_io<T: IoValue>(&self, offset: usize, val: T) {
+        self.check(offset, core::mem::size_of::<T>());
+        #[cfg(target_arch = "x86_64")]
+        unsafe {
+            T::port_write((self.port + offset) as u16, val)
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        unsafe {
+            ((self.vaddr + offset) as *mut T).write_volatile(val)
+        }
+    }
+}
+
+/// A BAR prepared for access, see [`bar_info`].
+#[derive(Debug, Clone, Copy)]
+pub enum BarHandle {
+    /// A memory BAR, mapped by [`map_bar`].
+    Memory(MappedBar),
+    /// An I/O BAR, mapped by [`map_io_bar`].
+    Io(IoBar),
+}
+
+/// Returns the CPU physical address and the size of the PCI I/O space window.
+#[cfg(not(target_arch = "x86_64"))]
+fn io_window() -> Option<(usize, usize)> {
+    axhal::dtb::dtb()
+        .and_then(super::fdt::pci_io_window)
+        .or_else(|| axconfig::PCI_RANGES.first().copied())
+}
+
+/// Whether `index` is a BAR slot, i.e., not the upper slot of a 64-bit BAR.
+fn is_bar_slot(root: &mut PciRoot, bdf: DeviceFunction, index: u8) -> Option<bool> {
+    let mut bar = 0;
+    while bar < index {
+        bar += if root.bar_info(bdf, bar).ok()?.takes_two_entries() {
+            2
+        } else {
+            1
+        };
+    }
+    Some(bar == index)
+}
+
+/// Prepares the BAR with the given index for access, whether it is a memory
+/// or an I/O BAR, see [`map_bar`] and [`map_io_bar`].
+pub fn bar_info(root: &mut PciRoot, bdf: DeviceFunction, index: u8) -> Option<BarHandle> {
+    match root.bar_info(bdf, index).ok()? {
+        BarInfo::Memory { .. } => map_bar(root, bdf, index).map(BarHandle::Memory),
+        BarInfo::IO { .. } => map_io_bar(root, bdf, index).map(BarHandle::Io),
+    }
+}
+
+/// Maps the I/O BAR with the given index, and enables I/O decoding and bus
+/// mastering of the device.
+///
+/// Returns `None` for memory BARs, unassigned BARs, and BARs outside of the
+/// I/O space window.
+pub fn map_io_bar(root: &mut PciRoot, bdf: DeviceFunction, index: u8) -> Option<IoBar> {
+    let (port, size) = match root.bar_info(bdf, index).ok()? {
+        BarInfo::IO { address, size } if address != 0 && size != 0 => {
+            (address as usize, size as usize)
+        }
+        _ => return None,
+    };
+
+    #[cfg(target_arch = "x86_64")]
+    let bar = IoBar { port, size };
+    #[cfg(not(target_arch = "x86_64"))]
+    let bar = {
+        let Some((window, window_size)) = io_window() else {
+            warn!("PCI {}: no I/O space window for BAR {}", bdf, index);
+            return None;
+        };
+        if port + size > window_size || !super::map_mmio(window + port, size) {
+            warn!("PCI {}: I/O BAR {} is not accessible", bdf, index);
+            return None;
+        }
+        IoBar {
+            port,
+            size,
+            vaddr: phys_to_virt((window + port).into()).as_usize(),
+        }
+    };
+
+    let (_status, cmd) = root.get_status_command(bdf);
+    root.set_command(bdf, cmd | Command::IO_SPACE | Command::BUS_MASTER);
+    Some(bar)
+}
+
+/// Maps the memory BAR with the given index, and enables memory decoding and
+/// bus mastering of the device.
+///
+/// 64-bit BARs take two slots, the index of the lower slot must be given.
+/// Returns `None` for I/O BARs, unassigned BARs, and the upper slots of
+/// 64-bit BARs.
+pub fn map_bar(root: &mut PciRoot, bdf: DeviceFunction, index: u8) -> Option<MappedBar> {
+    if !is_bar_slot(root, bdf, index)? {
+        return None;
+    }
+
+    let (address, size) = match root.bar_info(bdf, index).ok()? {
+        BarInfo::Memory { address, size, .. } if address != 0 && size != 0 => {
+            (address as usize, size as usize)
+        }
+        BarInfo::IO { .. } => {
+            debug!("PCI {}: BAR {} is of I/O type", bdf, index);
+            return None;
+        }
+        _ => return None,
+    };
+    if !super::map_mmio(address, size) {
+        return None;
+    }
+
+    let (_status, cmd) = root.get_status_command(bdf);
+    root.set_command(bdf, cmd | Command::MEMORY_SPACE | Command::BUS_MASTER);
+    Some(MappedBar {
+        paddr: address,
+        vaddr: phys_to_virt(address.into()).as_usize(),
+        size,
+    })
+}
+
 fn config_pci_device(
     root: &mut PciRoot,
     bdf: DeviceFunction,
     allocator: &mut Option<PciRangeAllocator>,
+    io_allocator: &mut Option<PciRangeAllocator>,
 ) -> DevResult {
     let mut bar = 0;
     while bar < PCI_BAR_NUM {
         let info = root.bar_info(bdf, bar).unwrap();
-        if let BarInfo::Memory {
+        if let BarInfo::IO { address: 0, size } = info {
+            // Only assigned if the firmware has not, i.e., not on x86.
+            if size > 0 {
+                if let Some(io_allocator) = io_allocator.as_mut() {
+                    let new_port = io_allocator.alloc(size as _).ok_or(DevError::NoMemory)?;
+                    root.set_bar_32(bdf, bar, new_port as _);
+                }
+            }
+        } else if let BarInfo::Memory {
             address_type,
             address,
             size,
@@ -82,40 +414,159 @@ fn config_pci_device(
     Ok(())
 }
 
-impl AllDevices {
-    pub(crate) fn probe_bus_devices(&mut self) {
-        let base_vaddr = phys_to_virt(axconfig::PCI_ECAM_BASE.into());
-        let mut root = unsafe { PciRoot::new(base_vaddr.as_mut_ptr(), Cam::Ecam) };
+/// Reads the assigned BARs of a normal function.
+fn read_bars(root: &mut PciRoot, bdf: DeviceFunction) -> Vec<PciBar> {
+    let mut bars = Vec::new();
+    let mut index = 0;
+    while index < PCI_BAR_NUM {
+        let Ok(info) = root.bar_info(bdf, index) else {
+            break;
+        };
+        let bar = match info {
+            BarInfo::IO { address, size } => PciBar {
+                index,
+                kind: PciBarKind::Io,
+                prefetchable: false,
+                address: address as u64,
+                size: size as u64,
+            },
+            BarInfo::Memory {
+                address_type,
+                prefetchable,
+                address,
+                size,
+            } => PciBar {
+                index,
+                kind: if address_type == MemoryBarType::Width64 {
+                    PciBarKind::Memory64
+                } else {
+                    PciBarKind::Memory32
+                },
+                prefetchable,
+                address,
+                size: size as u64,
+            },
+        };
+        if bar.address != 0 && bar.size != 0 {
+            bars.push(bar);
+        }
+        index += if info.takes_two_entries() { 2 } else { 1 };
+    }
+    bars
+}
+
+/// Lists the functions from bus 0 and the buses behind the bridges, without
+/// BARs and drivers.
+pub(crate) fn walk_pci_hierarchy() -> Vec<PciDeviceInfo> {
+    let (ecam_base, bus_end) = axhal::mem::pci_ecam();
+    let base_vaddr = phys_to_virt(ecam_base);
+    let root = unsafe { PciRoot::new(base_vaddr.as_mut_ptr(), Cam::Ecam) };
+
+    let mut list = Vec::new();
+    let mut visited = [false; 256];
+    let mut pending = alloc::vec![0u8];
+    while let Some(bus) = pending.pop() {
+        if core::mem::replace(&mut visited[bus as usize], true) {
+            continue;
+        }
+        for (bdf, dev_info) in root.enumerate_bus(bus) {
+            // Only follow bridges to buses that are assigned and within the ECAM space.
+            let secondary_bus = if dev_info.header_type == HeaderType::PciPciBridge {
+                let bus_numbers = config_read(bdf, PCI_BRIDGE_BUS_NUMBERS);
+                Some((bus_numbers >> 8) as u8).filter(|&b| b > bus && b as usize <= bus_end)
+            } else {
+                None
+            };
+            if let Some(b) = secondary_bus {
+                pending.push(b);
+            }
+            let header = config_read(bdf, PCI_HEADER_TYPE);
+            list.push(PciDeviceInfo {
+                bus: bdf.bus,
+                device: bdf.device,
+                function: bdf.function,
+                vendor_id: dev_info.vendor_id,
+                device_id: dev_info.device_id,
+                class: dev_info.class,
+                subclass: dev_info.subclass,
+                prog_if: dev_info.prog_if,
+                revision: dev_info.revision,
+                header_type: (header >> 16) as u8 & 0x7f,
+                multifunction: header & PCI_HEADER_MULTIFUNCTION != 0,
+                interrupt_pin: (config_read(bdf, PCI_INTERRUPT) >> 8) as u8,
+                secondary_bus,
+                bars: Vec::new(),
+                probed_by: None,
+            });
+        }
+    }
+    list
+}
+
+/// Probes the PCI devices with the drivers in the given order, and passes
+/// each device found to `add` with the driver that claims it.
+pub(crate) fn probe_bus_devices(
+    drivers: &[DriverEntry],
+    mut add: impl FnMut(&DriverEntry, AxDeviceEnum),
+) {
+    let (ecam_base, bus_end) = axhal::mem::pci_ecam();
+    let base_vaddr = phys_to_virt(ecam_base);
+    let mut root = unsafe { PciRoot::new(base_vaddr.as_mut_ptr(), Cam::Ecam) };
 
-        // PCI 32-bit MMIO space
-        let mut allocator = axconfig::PCI_RANGES
-            .get(1)
-            .map(|range| PciRangeAllocator::new(range.0 as u64, range.1 as u64));
+    // PCI 32-bit MMIO space
+    let mut allocator = axconfig::PCI_RANGES
+        .get(1)
+        .map(|range| PciRangeAllocator::new(range.0 as u64, range.1 as u64));
+    // PCI I/O space, whose addresses start from 0 in the window.
+    #[cfg(not(target_arch = "x86_64"))]
+    let mut io_allocator = io_window()
+        .map(|(_, size)| size as u64)
+        .filter(|&size| size > PCI_IO_PORT_START)
+        .map(|size| PciRangeAllocator::new(PCI_IO_PORT_START, size - PCI_IO_PORT_START));
+    #[cfg(target_arch = "x86_64")]
+    let mut io_allocator = None;
 
-        for bus in 0..=axconfig::PCI_BUS_END as u8 {
-            for (bdf, dev_info) in root.enumerate_bus(bus) {
-                debug!("PCI {}: {}", bdf, dev_info);
-                if dev_info.header_type != HeaderType::Standard {
-                    continue;
+    for bus in 0..=bus_end as u8 {
+        for (bdf, dev_info) in root.enumerate_bus(bus) {
+            debug!("PCI {}: {}", bdf, dev_info);
+            if dev_info.header_type != HeaderType::Standard {
+                continue;
+            }
+            if let Err(e) = config_pci_device(&mut root, bdf, &mut allocator, &mut io_allocator) {
+                warn!(
+                    "failed to enable PCI device at {}({}): {:?}",
+                    bdf, dev_info, e
+                );
+                continue;
+            }
+            crate::pci::record_bars(bdf, read_bars(&mut root, bdf));
+            let claimed = drivers.iter().find_map(|driver| {
+                match driver.with_dma_mask(|| (driver.probe_pci)(&mut root, bdf, &dev_info)) {
+                    Ok(Some(dev)) => Some((driver, dev)),
+                    Ok(None) => {
+                        debug!("PCI {}: declined by {}", bdf, driver.name);
+                        None
+                    }
+                    // Try the next driver, a failed device must not stop the boot.
+                    Err(e) => {
+                        warn!("PCI {}({}): {}", bdf, dev_info, e);
+                        None
+                    }
                 }
-                match config_pci_device(&mut root, bdf, &mut allocator) {
-                    Ok(_) => for_each_drivers!(type Driver, {
-                        if let Some(dev) = Driver::probe_pci(&mut root, bdf, &dev_info) {
-                            info!(
-                                "registered a new {:?} device at {}: {:?}",
-                                dev.device_type(),
-                                bdf,
-                                dev.device_name(),
-                            );
-                            self.add_device(dev);
-                            continue; // skip to the next device
-                        }
-                    }),
-                    Err(e) => warn!(
-                        "failed to enable PCI device at {}({}): {:?}",
-                        bdf, dev_info, e
-                    ),
+            });
+            match claimed {
+                Some((driver, dev)) => {
+                    info!(
+                        "registered a new {:?} device at {} by {}: {:?}",
+                        dev.device_type(),
+                        bdf,
+                        driver.name,
+                        dev.device_name(),
+                    );
+                    crate::pci::record_driver(bdf, dev.device_name());
+                    add(driver, dev);
                 }
+                None => debug!("PCI {}: no driver claims it", bdf),
             }
         }
     }
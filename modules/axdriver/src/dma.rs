@@ -0,0 +1,46 @@
+//! DMA memory allocation limited by the mask of the driver being probed.
+//!
+//! Each driver declares the bus addresses its devices can access in
+//! [`DriverProbe::DMA_MASK`], and the mask is in effect while the driver
+//! probes a device. Drivers allocate the DMA memory of a device when probing
+//! it, so the memory is within the mask without the drivers passing it
+//! around. Allocations outside a probe are not limited.
+//!
+//! [`DriverProbe::DMA_MASK`]: crate::drivers::DriverProbe::DMA_MASK
+
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use axdma::{DMAInfo, DmaMask};
+use axdriver_base::{DevError, DevResult};
+
+static PROBE_MASK: AtomicU8 = AtomicU8::new(DmaMask::Bits64 as u8);
+
+/// Runs `f` with the DMA mask in effect.
+pub(crate) fn with_mask<R>(mask: DmaMask, f: impl FnOnce() -> R) -> R {
+    let old = PROBE_MASK.swap(mask as u8, Ordering::Relaxed);
+    let ret = f();
+    PROBE_MASK.store(old, Ordering::Relaxed);
+    ret
+}
+
+/// Returns the DMA mask of the driver being probed, or [`DmaMask::Bits64`]
+/// outside a probe.
+pub(crate) fn probe_mask() -> DmaMask {
+    if PROBE_MASK.load(Ordering::Relaxed) == DmaMask::Bits32 as u8 {
+        DmaMask::Bits32
+    } else {
+        DmaMask::Bits64
+    }
+}
+
+/// Allocates coherent DMA memory within the mask of the driver being probed.
+///
+/// Returns [`DevError::NoMemory`] if there is no free memory within the mask.
+///
+/// # Safety
+///
+/// See [`axdma::alloc_coherent`].
+pub(crate) unsafe fn alloc_coherent(layout: Layout) -> DevResult<DMAInfo> {
+    axdma::alloc_coherent_with_mask(layout, probe_mask()).map_err(|_| DevError::NoMemory)
+}
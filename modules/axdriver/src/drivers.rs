@@ -2,8 +2,11 @@
 
 #![allow(unused_imports, dead_code)]
 
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
 use crate::AxDeviceEnum;
-use axdriver_base::DeviceType;
+use axdriver_base::{DevError, DeviceType};
 
 #[cfg(feature = "virtio")]
 use crate::virtio::{self, VirtIoDevMeta};
@@ -13,24 +16,154 @@ use axdriver_pci::{DeviceFunction, DeviceFunctionInfo, PciRoot};
 
 pub use super::dummy::*;
 
+/// Probe priority of a driver, set by the `register_*_driver!` macros with an
+/// optional `priority = N` argument, which is 0 by default.
+///
+/// Drivers with lower values are tried first on each bus device, and their
+/// devices come first in [`AllDevices`](crate::AllDevices). Devices of drivers
+/// with equal priority are kept in the order they are probed, i.e., global
+/// devices first, then the bus order.
+pub trait DriverPriority {
+    const PRIORITY: u8;
+}
+
+/// The error of a driver that matched a device but failed to initialize it.
+#[derive(Debug)]
+pub struct ProbeError {
+    /// The full type name of the driver.
+    driver: &'static str,
+    /// The error returned by the driver.
+    pub error: DevError,
+}
+
+impl ProbeError {
+    /// Creates an error of the driver `D`.
+    pub fn new<D: DriverProbe + ?Sized>(error: DevError) -> Self {
+        Self {
+            driver: core::any::type_name::<D>(),
+            error,
+        }
+    }
+
+    /// The name of the driver, as in the probe logs.
+    pub fn driver_name(&self) -> String {
+        short_type_name(self.driver)
+    }
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed: {:?}", self.driver_name(), self.error)
+    }
+}
+
+/// The result of a probe function: the device if the driver claims it,
+/// `Ok(None)` if the driver does not match the device, or the error if the
+/// driver matches but fails to initialize the device.
+pub type ProbeResult = Result<Option<AxDeviceEnum>, ProbeError>;
+
 pub trait DriverProbe {
-    fn probe_global() -> Option<AxDeviceEnum> {
-        None
+    /// The bus addresses that the devices of the driver can access by DMA.
+    ///
+    /// The DMA memory allocated while the driver probes a device is within
+    /// the mask, see [`alloc_coherent`](crate::dma::alloc_coherent).
+    #[cfg(feature = "dma")]
+    const DMA_MASK: axdma::DmaMask = axdma::DmaMask::Bits64;
+
+    fn probe_global() -> ProbeResult {
+        Ok(None)
     }
 
+    /// Probes the MMIO device at `mmio_base`, whose IRQ is `irq` if it has
+    /// one, as given by the device tree or the platform config.
     #[cfg(bus = "mmio")]
-    fn probe_mmio(_mmio_base: usize, _mmio_size: usize) -> Option<AxDeviceEnum> {
-        None
+    fn probe_mmio(_mmio_base: usize, _mmio_size: usize, _irq: Option<usize>) -> ProbeResult {
+        Ok(None)
     }
 
+    /// Probes the PCI function at `bdf`. Drivers using interrupts allocate
+    /// MSI-X vectors, or fall back to the INTx pin with
+    /// [`intx_irq`](crate::bus::intx_irq) if `irq` is enabled.
     #[cfg(bus = "pci")]
     fn probe_pci(
         _root: &mut PciRoot,
         _bdf: DeviceFunction,
         _dev_info: &DeviceFunctionInfo,
-    ) -> Option<AxDeviceEnum> {
-        None
+    ) -> ProbeResult {
+        Ok(None)
+    }
+}
+
+/// The probe functions of a driver.
+pub(crate) struct DriverEntry {
+    pub name: String,
+    pub priority: u8,
+    #[cfg(feature = "dma")]
+    pub dma_mask: axdma::DmaMask,
+    pub probe_global: fn() -> ProbeResult,
+    #[cfg(bus = "mmio")]
+    pub probe_mmio: fn(usize, usize, Option<usize>) -> ProbeResult,
+    #[cfg(bus = "pci")]
+    pub probe_pci: fn(&mut PciRoot, DeviceFunction, &DeviceFunctionInfo) -> ProbeResult,
+}
+
+impl DriverEntry {
+    fn new<D: DriverProbe + DriverPriority>() -> Self {
+        Self {
+            name: short_type_name(core::any::type_name::<D>()),
+            priority: D::PRIORITY,
+            #[cfg(feature = "dma")]
+            dma_mask: D::DMA_MASK,
+            probe_global: D::probe_global,
+            #[cfg(bus = "mmio")]
+            probe_mmio: D::probe_mmio,
+            #[cfg(bus = "pci")]
+            probe_pci: D::probe_pci,
+        }
+    }
+
+    /// Runs `probe`, which calls one of the probe functions, with the DMA mask
+    /// of the driver in effect.
+    pub fn with_dma_mask<R>(&self, probe: impl FnOnce() -> R) -> R {
+        #[cfg(feature = "dma")]
+        {
+            crate::dma::with_mask(self.dma_mask, probe)
+        }
+        #[cfg(not(feature = "dma"))]
+        {
+            probe()
+        }
+    }
+}
+
+/// Strips the module paths from a type name, e.g., `VirtIoDriver<VirtIoNet>`.
+fn short_type_name(name: &str) -> String {
+    let mut parts = name.split("::").peekable();
+    let mut short = String::new();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_some() {
+            // Keep the generic brackets before the path.
+            let keep = part
+                .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .map_or(0, |i| i + 1);
+            short.push_str(&part[..keep]);
+        } else {
+            short.push_str(part);
+        }
     }
+    short
+}
+
+/// Returns all enabled drivers, sorted by their priorities.
+pub(crate) fn probe_table() -> Vec<DriverEntry> {
+    #[allow(unused_mut)]
+    let mut table = Vec::new();
+    for_each_drivers!(type Driver, {
+        table.push(DriverEntry::new::<Driver>());
+    });
+    // The sort is stable, drivers of equal priority keep the listed order.
+    table.sort_by_key(|driver| driver.priority);
+    table
 }
 
 #[cfg(net_dev = "virtio-net")]
@@ -51,17 +184,52 @@ register_display_driver!(
     <virtio::VirtIoGpu as VirtIoDevMeta>::Device
 );
 
+#[cfg(char_dev = "virtio-console")]
+register_char_driver!(
+    <virtio::VirtIoConsole as VirtIoDevMeta>::Driver,
+    <virtio::VirtIoConsole as VirtIoDevMeta>::Device
+);
+
+#[cfg(rng_dev = "virtio-rng")]
+register_rng_driver!(
+    <virtio::VirtIoRng as VirtIoDevMeta>::Driver,
+    <virtio::VirtIoRng as VirtIoDevMeta>::Device
+);
+
+#[cfg(_9p_dev = "virtio-9p")]
+register_9p_driver!(
+    <virtio::VirtIo9p as VirtIoDevMeta>::Driver,
+    <virtio::VirtIo9p as VirtIoDevMeta>::Device
+);
+
+#[cfg(balloon_dev = "virtio-balloon")]
+register_balloon_driver!(
+    <virtio::VirtIoBalloon as VirtIoDevMeta>::Driver,
+    <virtio::VirtIoBalloon as VirtIoDevMeta>::Device
+);
+
+#[cfg(input_dev = "virtio-input")]
+register_input_driver!(
+    <virtio::VirtIoInput as VirtIoDevMeta>::Driver,
+    <virtio::VirtIoInput as VirtIoDevMeta>::Device
+);
+
+#[cfg(sound_dev = "virtio-sound")]
+register_sound_driver!(
+    <virtio::VirtIoSound as VirtIoDevMeta>::Driver,
+    <virtio::VirtIoSound as VirtIoDevMeta>::Device
+);
+
 cfg_if::cfg_if! {
     if #[cfg(block_dev = "ramdisk")] {
         pub struct RamDiskDriver;
-        register_block_driver!(RamDiskDriver, axdriver_block::ramdisk::RamDisk);
+        // After the disks on buses, so that they are used first when present.
+        register_block_driver!(RamDiskDriver, axdriver_block::ramdisk::RamDisk, priority = 10);
+        impl crate::ops::BlockDiscardOps for axdriver_block::ramdisk::RamDisk {}
 
         impl DriverProbe for RamDiskDriver {
-            fn probe_global() -> Option<AxDeviceEnum> {
-                // TODO: format RAM disk
-                Some(AxDeviceEnum::from_block(
-                    axdriver_block::ramdisk::RamDisk::new(0x100_0000), // 16 MiB
-                ))
+            fn probe_global() -> ProbeResult {
+                Ok(Some(AxDeviceEnum::from_block(crate::ramdisk::create())))
             }
         }
     }
@@ -70,12 +238,13 @@ cfg_if::cfg_if! {
 cfg_if::cfg_if! {
     if #[cfg(block_dev = "bcm2835-sdhci")]{
         pub struct BcmSdhciDriver;
-        register_block_driver!(MmckDriver, axdriver_block::bcm2835sdhci::SDHCIDriver);
+        register_block_driver!(BcmSdhciDriver, crate::sdhci::SdhciDev);
 
         impl DriverProbe for BcmSdhciDriver {
-            fn probe_global() -> Option<AxDeviceEnum> {
+            fn probe_global() -> ProbeResult {
                 debug!("mmc probe");
-                axdriver_block::bcm2835sdhci::SDHCIDriver::try_new().ok().map(AxDeviceEnum::from_block)
+                let dev = crate::bcm2835_sdhci::init().map_err(ProbeError::new::<Self>)?;
+                Ok(Some(AxDeviceEnum::from_block(dev)))
             }
         }
     }
@@ -83,47 +252,29 @@ cfg_if::cfg_if! {
 
 cfg_if::cfg_if! {
     if #[cfg(net_dev = "ixgbe")] {
-        use crate::ixgbe::IxgbeHalImpl;
-        use axhal::mem::phys_to_virt;
+        use crate::ixgbe::IxgbeNic;
         pub struct IxgbeDriver;
-        register_net_driver!(IxgbeDriver, axdriver_net::ixgbe::IxgbeNic<IxgbeHalImpl, 1024, 1>);
+        register_net_driver!(IxgbeDriver, IxgbeNic);
         impl DriverProbe for IxgbeDriver {
             #[cfg(bus = "pci")]
             fn probe_pci(
-                    root: &mut axdriver_pci::PciRoot,
-                    bdf: axdriver_pci::DeviceFunction,
-                    dev_info: &axdriver_pci::DeviceFunctionInfo,
-                ) -> Option<crate::AxDeviceEnum> {
-                    use axdriver_net::ixgbe::{INTEL_82599, INTEL_VEND, IxgbeNic};
-                    if dev_info.vendor_id == INTEL_VEND && dev_info.device_id == INTEL_82599 {
-                        // Intel 10Gb Network
-                        info!("ixgbe PCI device found at {:?}", bdf);
-
-                        // Initialize the device
-                        // These can be changed according to the requirments specified in the ixgbe init function.
-                        const QN: u16 = 1;
-                        const QS: usize = 1024;
-                        let bar_info = root.bar_info(bdf, 0).unwrap();
-                        match bar_info {
-                            axdriver_pci::BarInfo::Memory {
-                                address,
-                                size,
-                                ..
-                            } => {
-                                let ixgbe_nic = IxgbeNic::<IxgbeHalImpl, QS, QN>::init(
-                                    phys_to_virt((address as usize).into()).into(),
-                                    size as usize
-                                )
-                                .expect("failed to initialize ixgbe device");
-                                return Some(AxDeviceEnum::from_net(ixgbe_nic));
-                            }
-                            axdriver_pci::BarInfo::IO { .. } => {
-                                error!("ixgbe: BAR0 is of I/O type");
-                                return None;
-                            }
-                        }
-                    }
-                    None
+                root: &mut axdriver_pci::PciRoot,
+                bdf: axdriver_pci::DeviceFunction,
+                dev_info: &axdriver_pci::DeviceFunctionInfo,
+            ) -> ProbeResult {
+                use crate::ixgbe::{INTEL_82599, INTEL_VEND};
+                if dev_info.vendor_id == INTEL_VEND && dev_info.device_id == INTEL_82599 {
+                    // Intel 10Gb Network
+                    info!("ixgbe PCI device found at {:?}", bdf);
+
+                    let Some(bar) = crate::bus::map_bar(root, bdf, 0) else {
+                        warn!("ixgbe: BAR0 is not a memory BAR");
+                        return Err(ProbeError::new::<Self>(DevError::BadState));
+                    };
+                    let nic = IxgbeNic::init(bar.vaddr, bar.size).map_err(ProbeError::new::<Self>)?;
+                    return Ok(Some(AxDeviceEnum::from_net(nic)));
+                }
+                Ok(None)
             }
         }
     }
@@ -131,79 +282,210 @@ cfg_if::cfg_if! {
 
 cfg_if::cfg_if! {
     if #[cfg(net_dev = "igb")] {
-        use axdma::{alloc_coherent, dealloc_coherent, BusAddr, DMAInfo};
-        use igb_driver::IgbHal;
-        use axhal::mem::{phys_to_virt, virt_to_phys};
-        use core::{alloc::Layout, ptr::NonNull};
-        pub struct IgbHalImpl;
-        unsafe impl IgbHal for IgbHalImpl {
-            fn dma_alloc(size: usize) -> (usize, NonNull<u8>) {
-                let layout = Layout::from_size_align(size, 8).unwrap();
-                match unsafe { alloc_coherent(layout) } {
-                    Ok(dma_info) => (dma_info.bus_addr.as_u64() as usize, dma_info.cpu_addr),
-                    Err(_) => (0, NonNull::dangling()),
+        use crate::igb::IgbNic;
+        pub struct IgbDriver;
+        register_net_driver!(IgbDriver, IgbNic);
+        impl DriverProbe for IgbDriver {
+            #[cfg(bus = "pci")]
+            fn probe_pci(
+                root: &mut axdriver_pci::PciRoot,
+                bdf: axdriver_pci::DeviceFunction,
+                dev_info: &axdriver_pci::DeviceFunctionInfo,
+            ) -> ProbeResult {
+                use crate::igb::{INTEL_82576, INTEL_VEND};
+                if dev_info.vendor_id == INTEL_VEND && dev_info.device_id == INTEL_82576 {
+                    info!("igb PCI device found at {:?}", bdf);
+
+                    let Some(bar) = crate::bus::map_bar(root, bdf, 0) else {
+                        warn!("igb: BAR0 is not a memory BAR");
+                        return Err(ProbeError::new::<Self>(DevError::BadState));
+                    };
+                    let nic = IgbNic::init(bar.vaddr, bar.size).map_err(ProbeError::new::<Self>)?;
+                    return Ok(Some(AxDeviceEnum::from_net(nic)));
                 }
+                Ok(None)
             }
+        }
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(net_dev = "e1000")] {
+        use crate::e1000::E1000Nic;
+        pub struct E1000Driver;
+        register_net_driver!(E1000Driver, E1000Nic);
+        impl DriverProbe for E1000Driver {
+            #[cfg(bus = "pci")]
+            fn probe_pci(
+                root: &mut axdriver_pci::PciRoot,
+                bdf: axdriver_pci::DeviceFunction,
+                dev_info: &axdriver_pci::DeviceFunctionInfo,
+            ) -> ProbeResult {
+                use crate::e1000::{INTEL_82540EM, INTEL_VEND};
+                if dev_info.vendor_id == INTEL_VEND && dev_info.device_id == INTEL_82540EM {
+                    info!("e1000 PCI device found at {:?}", bdf);
 
-            unsafe fn dma_dealloc(paddr: usize, vaddr: NonNull<u8>, size: usize) -> i32 {
-                let layout = Layout::from_size_align(size, 8).unwrap();
-                let dma_info = DMAInfo {
-                    cpu_addr: vaddr,
-                    bus_addr: BusAddr::from(paddr as u64),
-                };
-                unsafe { dealloc_coherent(dma_info, layout) };
-                0
+                    let Some(bar) = crate::bus::map_bar(root, bdf, 0) else {
+                        warn!("e1000: BAR0 is not a memory BAR");
+                        return Err(ProbeError::new::<Self>(DevError::BadState));
+                    };
+                    let nic = E1000Nic::init(bar.vaddr, bar.size).map_err(ProbeError::new::<Self>)?;
+                    return Ok(Some(AxDeviceEnum::from_net(nic)));
+                }
+                Ok(None)
             }
+        }
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(all(net_dev = "rtl8139", bus = "pci"))] {
+        use crate::rtl8139::Rtl8139Nic;
+        pub struct Rtl8139Driver;
+        register_net_driver!(Rtl8139Driver, Rtl8139Nic);
+        impl DriverProbe for Rtl8139Driver {
+            // The buffer addresses are programmed in 32-bit registers.
+            const DMA_MASK: axdma::DmaMask = axdma::DmaMask::Bits32;
 
-            unsafe fn mmio_phys_to_virt(paddr: usize, _size: usize) -> NonNull<u8> {
-                NonNull::new(phys_to_virt(paddr.into()).as_mut_ptr()).unwrap()
+            fn probe_pci(
+                root: &mut axdriver_pci::PciRoot,
+                bdf: axdriver_pci::DeviceFunction,
+                dev_info: &axdriver_pci::DeviceFunctionInfo,
+            ) -> ProbeResult {
+                use crate::rtl8139::{REALTEK_8139, REALTEK_VEND};
+                if dev_info.vendor_id == REALTEK_VEND && dev_info.device_id == REALTEK_8139 {
+                    info!("rtl8139 PCI device found at {:?}", bdf);
+
+                    // Unlike other NICs, the registers of RTL8139 are accessed through the I/O BAR.
+                    let Some(bar) = crate::bus::map_io_bar(root, bdf, 0) else {
+                        warn!("rtl8139: BAR0 is not an accessible I/O BAR");
+                        return Err(ProbeError::new::<Self>(DevError::BadState));
+                    };
+                    let nic = Rtl8139Nic::init(bar).map_err(ProbeError::new::<Self>)?;
+                    return Ok(Some(AxDeviceEnum::from_net(nic)));
+                }
+                Ok(None)
             }
+        }
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(net_dev = "loopback")] {
+        use crate::loopback::LoopbackDev;
+        pub struct LoopbackDriver;
+        // After the NICs, so that `eth0` is still a real NIC.
+        register_net_driver!(LoopbackDriver, LoopbackDev, priority = 10);
 
-            unsafe fn mmio_virt_to_phys(vaddr: NonNull<u8>, _size: usize) -> usize {
-                virt_to_phys((vaddr.as_ptr() as usize).into()).into()
+        impl DriverProbe for LoopbackDriver {
+            fn probe_global() -> ProbeResult {
+                Ok(Some(AxDeviceEnum::from_net(LoopbackDev::new())))
             }
+        }
+    }
+}
 
-            fn wait_until(duration: core::time::Duration) -> Result<(), &'static str> {
-                axhal::time::busy_wait_until(duration);
-                Ok(())
+cfg_if::cfg_if! {
+    if #[cfg(block_dev = "nvme")] {
+        use crate::nvme::NvmeDev;
+        pub struct NvmeDriver;
+        register_block_driver!(NvmeDriver, NvmeDev);
+        impl DriverProbe for NvmeDriver {
+            #[cfg(bus = "pci")]
+            fn probe_pci(
+                root: &mut axdriver_pci::PciRoot,
+                bdf: axdriver_pci::DeviceFunction,
+                dev_info: &axdriver_pci::DeviceFunctionInfo,
+            ) -> ProbeResult {
+                use crate::nvme::{NVME_CLASS, NVME_SUBCLASS};
+                if dev_info.class == NVME_CLASS && dev_info.subclass == NVME_SUBCLASS {
+                    info!("NVMe PCI device found at {:?}", bdf);
+
+                    let Some(bar) = crate::bus::map_bar(root, bdf, 0) else {
+                        warn!("nvme: BAR0 is not a memory BAR");
+                        return Err(ProbeError::new::<Self>(DevError::BadState));
+                    };
+                    let dev = NvmeDev::init(bar.vaddr).map_err(ProbeError::new::<Self>)?;
+                    return Ok(Some(AxDeviceEnum::from_block(dev)));
+                }
+                Ok(None)
             }
         }
+    }
+}
 
-        pub struct IgbDriver;
-        const QN: u16 = 1;
-        const QS: usize = 1024;
-        register_net_driver!(IgbDriver, igb_driver::IgbNic<IgbHalImpl, QS, QN>);
-        impl DriverProbe for IgbDriver {
+cfg_if::cfg_if! {
+    if #[cfg(block_dev = "usb-storage")] {
+        use crate::usb_storage::UsbStorageDev;
+        pub struct UsbStorageDriver;
+        register_block_driver!(UsbStorageDriver, UsbStorageDev);
+        impl DriverProbe for UsbStorageDriver {
             #[cfg(bus = "pci")]
             fn probe_pci(
                 root: &mut axdriver_pci::PciRoot,
                 bdf: axdriver_pci::DeviceFunction,
                 dev_info: &axdriver_pci::DeviceFunctionInfo,
-            ) -> Option<crate::AxDeviceEnum> {
-                use igb_driver::{INTEL_82576, INTEL_VEND};
-                use igb_driver::IgbNic;
-                if dev_info.vendor_id == INTEL_VEND && dev_info.device_id == INTEL_82576 {
-                    info!("igb PCI device found at {:?}", bdf);
+            ) -> ProbeResult {
+                use crate::xhci::{XHCI_CLASS, XHCI_PROG_IF, XHCI_SUBCLASS};
+                if dev_info.class == XHCI_CLASS
+                    && dev_info.subclass == XHCI_SUBCLASS
+                    && dev_info.prog_if == XHCI_PROG_IF
+                {
+                    info!("xHCI PCI device found at {:?}", bdf);
+
+                    let Some(bar) = crate::bus::map_bar(root, bdf, 0) else {
+                        warn!("usb-storage: BAR0 is not a memory BAR");
+                        return Err(ProbeError::new::<Self>(DevError::BadState));
+                    };
+                    let dev = UsbStorageDev::init(bar.vaddr).map_err(ProbeError::new::<Self>)?;
+                    return Ok(Some(AxDeviceEnum::from_block(dev)));
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(block_dev = "sdhci")] {
+        use crate::sdhci::SdhciDev;
+        pub struct SdhciDriver;
+        register_block_driver!(SdhciDriver, SdhciDev);
+        impl DriverProbe for SdhciDriver {
+            #[cfg(bus = "mmio")]
+            fn probe_mmio(
+                mmio_base: usize,
+                _mmio_size: usize,
+                _irq: Option<usize>,
+            ) -> ProbeResult {
+                // The controller cannot be detected, only probe the configured regions.
+                if !axconfig::SDHCI_MMIO_REGIONS.iter().any(|reg| reg.0 == mmio_base) {
+                    return Ok(None);
+                }
+                let base_vaddr = axhal::mem::phys_to_virt(mmio_base.into());
+                let dev = SdhciDev::init(base_vaddr.as_usize()).map_err(ProbeError::new::<Self>)?;
+                Ok(Some(AxDeviceEnum::from_block(dev)))
+            }
+
+            #[cfg(bus = "pci")]
+            fn probe_pci(
+                root: &mut axdriver_pci::PciRoot,
+                bdf: axdriver_pci::DeviceFunction,
+                dev_info: &axdriver_pci::DeviceFunctionInfo,
+            ) -> ProbeResult {
+                use crate::sdhci::{SDHCI_CLASS, SDHCI_SUBCLASS};
+                if dev_info.class == SDHCI_CLASS && dev_info.subclass == SDHCI_SUBCLASS {
+                    info!("SDHCI PCI device found at {:?}", bdf);
 
-                    // Initialize the device
-                    // These can be changed according to the requirements specified in the igb init function.
-                    let bar_info = root.bar_info(bdf, 0).unwrap();
-                    match bar_info {
-                        axdriver_pci::BarInfo::Memory { address, size, .. } => {
-                            let igb_nic = IgbNic::<IgbHalImpl, QS, QN>::init(
-                                phys_to_virt((address as usize).into()).into(),
-                                size as usize
-                            )
-                            .expect("failed to initialize igb device");
-                            return Some(AxDeviceEnum::from_net(igb_nic));
-                        }
-                        axdriver_pci::BarInfo::IO { .. } => {
-                            error!("igb: BAR0 is of I/O type");
-                            return None;
-                        }
-                    }
+                    // BAR0 holds the registers of the first slot.
+                    let Some(bar) = crate::bus::map_bar(root, bdf, 0) else {
+                        warn!("sdhci: BAR0 is not a memory BAR");
+                        return Err(ProbeError::new::<Self>(DevError::BadState));
+                    };
+                    let dev = SdhciDev::init(bar.vaddr).map_err(ProbeError::new::<Self>)?;
+                    return Ok(Some(AxDeviceEnum::from_block(dev)));
                 }
-                None
+                Ok(None)
             }
         }
     }
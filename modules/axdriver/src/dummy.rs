@@ -11,7 +11,7 @@ cfg_if! {
         use axdriver_net::{EthernetAddress, NetBuf, NetBufBox, NetBufPool, NetBufPtr};
 
         pub struct DummyNetDev;
-        pub struct DummyNetDrvier;
+        pub struct DummyNetDriver;
         register_net_driver!(DummyNetDriver, DummyNetDev);
 
         impl BaseDriverOps for DummyNetDev {
@@ -31,6 +31,8 @@ cfg_if! {
             fn receive(&mut self) -> DevResult<NetBufPtr> { Err(DevError::Unsupported) }
             fn alloc_tx_buffer(&mut self, _: usize) -> DevResult<NetBufPtr> { Err(DevError::Unsupported) }
         }
+
+        impl NetIrqOps for DummyNetDev {}
     }
 }
 
@@ -66,6 +68,201 @@ cfg_if! {
                 Err(DevError::Unsupported)
             }
         }
+
+        impl BlockDiscardOps for DummyBlockDev {}
+    }
+}
+
+cfg_if! {
+    if #[cfg(char_dev = "dummy")] {
+        pub struct DummyCharDev;
+        pub struct DummyCharDriver;
+        register_char_driver!(DummyCharDriver, DummyCharDev);
+
+        impl BaseDriverOps for DummyCharDev {
+            fn device_type(&self) -> DeviceType {
+                DeviceType::Char
+            }
+            fn device_name(&self) -> &str {
+                "dummy-char"
+            }
+        }
+
+        impl CharDriverOps for DummyCharDev {
+            fn read(&mut self, _: &mut [u8]) -> DevResult<usize> {
+                Err(DevError::Unsupported)
+            }
+            fn write(&mut self, _: &[u8]) -> DevResult<usize> {
+                Err(DevError::Unsupported)
+            }
+            fn poll_readable(&mut self) -> bool {
+                false
+            }
+            fn poll_writable(&mut self) -> bool {
+                false
+            }
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(rng_dev = "dummy")] {
+        pub struct DummyRngDev;
+        pub struct DummyRngDriver;
+        register_rng_driver!(DummyRngDriver, DummyRngDev);
+
+        impl BaseDriverOps for DummyRngDev {
+            fn device_type(&self) -> DeviceType {
+                DeviceType::Char
+            }
+            fn device_name(&self) -> &str {
+                "dummy-rng"
+            }
+        }
+
+        impl RngDriverOps for DummyRngDev {
+            fn fill_bytes(&mut self, _: &mut [u8]) -> DevResult<usize> {
+                Err(DevError::Unsupported)
+            }
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(_9p_dev = "dummy")] {
+        pub struct Dummy9pDev;
+        pub struct Dummy9pDriver;
+        register_9p_driver!(Dummy9pDriver, Dummy9pDev);
+
+        impl BaseDriverOps for Dummy9pDev {
+            fn device_type(&self) -> DeviceType {
+                DeviceType::Char
+            }
+            fn device_name(&self) -> &str {
+                "dummy-9p"
+            }
+        }
+
+        impl _9pDriverOps for Dummy9pDev {
+            fn mount_tag(&self) -> &str {
+                ""
+            }
+            fn msize(&self) -> u32 {
+                0
+            }
+            fn send_with_recv(&mut self, _: &[u8], _: &mut [u8]) -> DevResult<usize> {
+                Err(DevError::Unsupported)
+            }
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(balloon_dev = "dummy")] {
+        pub struct DummyBalloonDev;
+        pub struct DummyBalloonDriver;
+        register_balloon_driver!(DummyBalloonDriver, DummyBalloonDev);
+
+        impl BaseDriverOps for DummyBalloonDev {
+            fn device_type(&self) -> DeviceType {
+                DeviceType::Char
+            }
+            fn device_name(&self) -> &str {
+                "dummy-balloon"
+            }
+        }
+
+        impl BalloonDriverOps for DummyBalloonDev {
+            fn target_pages(&self) -> usize {
+                0
+            }
+            fn num_pages(&self) -> usize {
+                0
+            }
+            fn update(&mut self) -> DevResult<usize> {
+                Err(DevError::Unsupported)
+            }
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(input_dev = "dummy")] {
+        pub struct DummyInputDev;
+        pub struct DummyInputDriver;
+        register_input_driver!(DummyInputDriver, DummyInputDev);
+
+        impl BaseDriverOps for DummyInputDev {
+            fn device_type(&self) -> DeviceType {
+                DeviceType::Char
+            }
+            fn device_name(&self) -> &str {
+                "dummy-input"
+            }
+        }
+
+        impl InputDriverOps for DummyInputDev {
+            fn poll_events(&mut self) {}
+            fn read_event(&mut self) -> Option<InputEvent> {
+                None
+            }
+            fn dropped_events(&self) -> usize {
+                0
+            }
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(sound_dev = "dummy")] {
+        pub struct DummySoundDev;
+        pub struct DummySoundDriver;
+        register_sound_driver!(DummySoundDriver, DummySoundDev);
+
+        impl BaseDriverOps for DummySoundDev {
+            fn device_type(&self) -> DeviceType {
+                DeviceType::Char
+            }
+            fn device_name(&self) -> &str {
+                "dummy-sound"
+            }
+        }
+
+        impl SoundDriverOps for DummySoundDev {
+            fn num_jacks(&self) -> u32 {
+                0
+            }
+            fn jack_info(&mut self, _: u32) -> DevResult<JackInfo> {
+                Err(DevError::Unsupported)
+            }
+            fn num_streams(&self) -> u32 {
+                0
+            }
+            fn stream_info(&mut self, _: u32) -> DevResult<PcmStreamInfo> {
+                Err(DevError::Unsupported)
+            }
+            fn set_params(&mut self, _: u32, _: &PcmParams) -> DevResult {
+                Err(DevError::Unsupported)
+            }
+            fn prepare(&mut self, _: u32) -> DevResult {
+                Err(DevError::Unsupported)
+            }
+            fn start(&mut self, _: u32) -> DevResult {
+                Err(DevError::Unsupported)
+            }
+            fn stop(&mut self, _: u32) -> DevResult {
+                Err(DevError::Unsupported)
+            }
+            fn release(&mut self, _: u32) -> DevResult {
+                Err(DevError::Unsupported)
+            }
+            fn submit_period(&mut self, _: u32, _: &[u8]) -> DevResult {
+                Err(DevError::Unsupported)
+            }
+            fn poll_completed(&mut self) -> DevResult<usize> {
+                Err(DevError::Unsupported)
+            }
+        }
     }
 }
 
@@ -98,5 +295,7 @@ cfg_if! {
                 Err(DevError::Unsupported)
             }
         }
+
+        impl DisplayBufferOps for DummyDisplayDev {}
     }
 }
@@ -0,0 +1,513 @@
+//! Driver for the Intel 8254x (e1000) family of gigabit NICs.
+//!
+//! Only the legacy descriptor format is used. Both descriptor rings and all
+//! packet buffers are allocated as coherent DMA memory.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+use core::time::Duration;
+
+use axdma::{dealloc_coherent, DMAInfo};
+use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
+use axdriver_net::{EthernetAddress, NetBufPtr, NetDriverOps};
+
+use crate::dma::alloc_coherent;
+use crate::ops::NetIrqOps;
+
+/// Intel vendor ID.
+pub const INTEL_VEND: u16 = 0x8086;
+/// Device ID of the 82540EM, emulated by QEMU as `-device e1000`.
+pub const INTEL_82540EM: u16 = 0x100e;
+
+const RING_SIZE: usize = 256;
+const BUF_SIZE: usize = 2048;
+const RING_ALIGN: usize = 128;
+/// How long an EEPROM read may take, far more than the few microseconds needed.
+const EEPROM_TIMEOUT: Duration = Duration::from_millis(10);
+/// How long the reset may take, far more than the microsecond needed.
+const RESET_TIMEOUT: Duration = Duration::from_millis(10);
+
+// Register offsets.
+const REG_CTRL: usize = 0x0000;
+const REG_STATUS: usize = 0x0008;
+const REG_EERD: usize = 0x0014;
+const REG_ICR: usize = 0x00c0;
+const REG_IMC: usize = 0x00d8;
+const REG_RCTL: usize = 0x0100;
+const REG_TCTL: usize = 0x0400;
+const REG_TIPG: usize = 0x0410;
+const REG_RDBAL: usize = 0x2800;
+const REG_RDBAH: usize = 0x2804;
+const REG_RDLEN: usize = 0x2808;
+const REG_RDH: usize = 0x2810;
+const REG_RDT: usize = 0x2818;
+const REG_TDBAL: usize = 0x3800;
+const REG_TDBAH: usize = 0x3804;
+const REG_TDLEN: usize = 0x3808;
+const REG_TDH: usize = 0x3810;
+const REG_TDT: usize = 0x3818;
+const REG_MTA: usize = 0x5200;
+const REG_RAL0: usize = 0x5400;
+const REG_RAH0: usize = 0x5404;
+
+const CTRL_SLU: u32 = 1 << 6;
+const CTRL_RST: u32 = 1 << 26;
+const STATUS_LU: u32 = 1 << 1;
+const STATUS_SPEED_SHIFT: u32 = 6;
+const STATUS_SPEED_MASK: u32 = 0b11;
+const EERD_START: u32 = 1 << 0;
+const EERD_DONE: u32 = 1 << 4;
+const RAH_AV: u32 = 1 << 31;
+
+const RCTL_EN: u32 = 1 << 1;
+const RCTL_BAM: u32 = 1 << 15;
+/// Buffer size of 2048 bytes is encoded as `BSIZE = 00` with `BSEX = 0`.
+const RCTL_BSIZE_2048: u32 = 0;
+const RCTL_SECRC: u32 = 1 << 26;
+
+const TCTL_EN: u32 = 1 << 1;
+const TCTL_PSP: u32 = 1 << 3;
+const TCTL_CT: u32 = 0x10 << 4;
+const TCTL_COLD: u32 = 0x40 << 12;
+
+const TIPG_DEFAULT: u32 = 10 | (8 << 10) | (6 << 20);
+
+const DESC_STA_DD: u8 = 1 << 0;
+const RX_DESC_STA_EOP: u8 = 1 << 1;
+const TX_DESC_CMD_EOP: u8 = 1 << 0;
+const TX_DESC_CMD_IFCS: u8 = 1 << 1;
+const TX_DESC_CMD_RS: u8 = 1 << 3;
+
+/// Legacy receive descriptor.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct RxDesc {
+    addr: u64,
+    length: u16,
+    checksum: u16,
+    status: u8,
+    errors: u8,
+    special: u16,
+}
+
+/// Legacy transmit descriptor.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct TxDesc {
+    addr: u64,
+    length: u16,
+    cso: u8,
+    cmd: u8,
+    status: u8,
+    css: u8,
+    special: u16,
+}
+
+/// A descriptor ring together with its packet buffers, both in coherent DMA memory.
+struct DmaRing<D: Copy + Default> {
+    descs: DMAInfo,
+    bufs: DMAInfo,
+    /// Which buffer is attached to each descriptor.
+    desc_buf: [usize; RING_SIZE],
+    /// Buffers owned by the driver and not attached to any descriptor.
+    free_bufs: [usize; RING_SIZE],
+    num_free: usize,
+    _marker: core::marker::PhantomData<D>,
+}
+
+impl<D: Copy + Default> DmaRing<D> {
+    const DESC_LAYOUT: Layout =
+        match Layout::from_size_align(RING_SIZE * core::mem::size_of::<D>(), RING_ALIGN) {
+            Ok(layout) => layout,
+            Err(_) => panic!("invalid descriptor ring layout"),
+        };
+    const BUF_LAYOUT: Layout = match Layout::from_size_align(RING_SIZE * BUF_SIZE, BUF_SIZE) {
+        Ok(layout) => layout,
+        Err(_) => panic!("invalid packet buffer layout"),
+    };
+
+    fn new() -> DevResult<Self> {
+        let descs = unsafe { alloc_coherent(Self::DESC_LAYOUT)? };
+        let bufs = match unsafe { alloc_coherent(Self::BUF_LAYOUT) } {
+            Ok(bufs) => bufs,
+            Err(_) => {
+                unsafe { dealloc_coherent(descs, Self::DESC_LAYOUT) };
+                return Err(DevError::NoMemory);
+            }
+        };
+        let mut ring = Self {
+            descs,
+            bufs,
+            desc_buf: [0; RING_SIZE],
+            free_bufs: [0; RING_SIZE],
+            num_free: RING_SIZE,
+            _marker: core::marker::PhantomData,
+        };
+        for i in 0..RING_SIZE {
+            ring.free_bufs[i] = RING_SIZE - 1 - i;
+            ring.write_desc(i, D::default());
+        }
+        Ok(ring)
+    }
+
+    fn desc_ptr(&self, idx: usize) -> *mut D {
+        unsafe { (self.descs.cpu_addr.as_ptr() as *mut D).add(idx) }
+    }
+
+    fn read_desc(&self, idx: usize) -> D {
+        unsafe { self.desc_ptr(idx).read_volatile() }
+    }
+
+    fn write_desc(&mut self, idx: usize, desc: D) {
+        unsafe { self.desc_ptr(idx).write_volatile(desc) }
+    }
+
+    fn buf_ptr(&self, buf: usize) -> NonNull<u8> {
+        unsafe { NonNull::new_unchecked(self.bufs.cpu_addr.as_ptr().add(buf * BUF_SIZE)) }
+    }
+
+    fn buf_bus_addr(&self, buf: usize) -> u64 {
+        self.bufs.bus_addr.as_u64() + (buf * BUF_SIZE) as u64
+    }
+
+    /// Finds the buffer index from a pointer returned by [`DmaRing::buf_ptr`].
+    fn buf_index(&self, ptr: *const u8) -> DevResult<usize> {
+        let base = self.bufs.cpu_addr.as_ptr() as usize;
+        let offset = (ptr as usize).wrapping_sub(base);
+        if offset < RING_SIZE * BUF_SIZE && offset % BUF_SIZE == 0 {
+            Ok(offset / BUF_SIZE)
+        } else {
+            Err(DevError::InvalidParam)
+        }
+    }
+
+    fn pop_free_buf(&mut self) -> Option<usize> {
+        if self.num_free == 0 {
+            None
+        } else {
+            self.num_free -= 1;
+            Some(self.free_bufs[self.num_free])
+        }
+    }
+
+    fn push_free_buf(&mut self, buf: usize) {
+        self.free_bufs[self.num_free] = buf;
+        self.num_free += 1;
+    }
+
+    fn bus_addr(&self) -> u64 {
+        self.descs.bus_addr.as_u64()
+    }
+}
+
+impl<D: Copy + Default> Drop for DmaRing<D> {
+    fn drop(&mut self) {
+        unsafe {
+            dealloc_coherent(self.descs, Self::DESC_LAYOUT);
+            dealloc_coherent(self.bufs, Self::BUF_LAYOUT);
+        }
+    }
+}
+
+/// The Intel 8254x NIC driver.
+pub struct E1000Nic {
+    mmio_base: usize,
+    mac: EthernetAddress,
+    rx: DmaRing<RxDesc>,
+    tx: DmaRing<TxDesc>,
+    /// Next RX descriptor to be checked for a received packet.
+    rx_next: usize,
+    /// Value of the RDT register, the first descriptor not owned by hardware.
+    rx_tail: usize,
+    /// Next TX descriptor to be cleaned after transmission.
+    tx_clean: usize,
+    /// Value of the TDT register, the next descriptor to be used for transmission.
+    tx_tail: usize,
+}
+
+unsafe impl Send for E1000Nic {}
+unsafe impl Sync for E1000Nic {}
+
+impl E1000Nic {
+    /// Resets and initializes the NIC whose registers are mapped at `mmio_base`.
+    pub fn init(mmio_base: usize, _mmio_size: usize) -> DevResult<Self> {
+        let mut nic = Self {
+            mmio_base,
+            mac: EthernetAddress([0; 6]),
+            rx: DmaRing::new()?,
+            tx: DmaRing::new()?,
+            rx_next: 0,
+            rx_tail: 0,
+            tx_clean: 0,
+            tx_tail: 0,
+        };
+        nic.reset()?;
+        nic.mac = nic.read_mac_address()?;
+        nic.init_rx();
+        nic.init_tx();
+        nic.write_reg(REG_CTRL, nic.read_reg(REG_CTRL) | CTRL_SLU);
+        info!(
+            "e1000: MAC {}, link {}",
+            nic.mac,
+            if nic.link_up() { "up" } else { "down" }
+        );
+        Ok(nic)
+    }
+
+    fn read_reg(&self, reg: usize) -> u32 {
+        unsafe { ((self.mmio_base + reg) as *const u32).read_volatile() }
+    }
+
+    fn write_reg(&self, reg: usize, val: u32) {
+        unsafe { ((self.mmio_base + reg) as *mut u32).write_volatile(val) }
+    }
+
+    fn reset(&mut self) -> DevResult {
+        // Mask all interrupts, the driver works in polling mode.
+        self.write_reg(REG_IMC, u32::MAX);
+        self.write_reg(REG_CTRL, self.read_reg(REG_CTRL) | CTRL_RST);
+        let deadline = axhal::time::monotonic_time() + RESET_TIMEOUT;
+        while self.read_reg(REG_CTRL) & CTRL_RST != 0 {
+            if axhal::time::monotonic_time() > deadline {
+                error!("e1000: timed out resetting the NIC");
+                return Err(DevError::Io);
+            }
+            core::hint::spin_loop();
+        }
+        self.write_reg(REG_IMC, u32::MAX);
+        self.read_reg(REG_ICR);
+        Ok(())
+    }
+
+    fn read_eeprom(&self, word: u8) -> DevResult<u16> {
+        self.write_reg(REG_EERD, ((word as u32) << 8) | EERD_START);
+        let deadline = axhal::time::monotonic_time() + EEPROM_TIMEOUT;
+        loop {
+            let val = self.read_reg(REG_EERD);
+            if val & EERD_DONE != 0 {
+                return Ok((val >> 16) as u16);
+            }
+            if axhal::time::monotonic_time() > deadline {
+                error!("e1000: timed out reading EEPROM word {}", word);
+                return Err(DevError::Io);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn read_mac_address(&self) -> DevResult<EthernetAddress> {
+        let rah = self.read_reg(REG_RAH0);
+        let mut mac = [0; 6];
+        if rah & RAH_AV != 0 {
+            mac[..4].copy_from_slice(&self.read_reg(REG_RAL0).to_le_bytes());
+            mac[4..].copy_from_slice(&(rah as u16).to_le_bytes());
+        } else {
+            for i in 0..3 {
+                let word = self.read_eeprom(i as u8)?;
+                mac[i * 2..i * 2 + 2].copy_from_slice(&word.to_le_bytes());
+            }
+            self.write_mac_address(EthernetAddress(mac));
+        }
+        Ok(EthernetAddress(mac))
+    }
+
+    /// Programs the address into the first receive address registers.
+    fn write_mac_address(&self, mac: EthernetAddress) {
+        let mac = mac.0;
+        self.write_reg(
+            REG_RAL0,
+            u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]),
+        );
+        self.write_reg(
+            REG_RAH0,
+            u16::from_le_bytes([mac[4], mac[5]]) as u32 | RAH_AV,
+        );
+    }
+
+    fn init_rx(&mut self) {
+        for i in 0..128 {
+            self.write_reg(REG_MTA + i * 4, 0);
+        }
+        // Leave one descriptor unused so that a full ring can be told from an empty one.
+        self.rx_tail = 0;
+        self.refill_rx();
+
+        let addr = self.rx.bus_addr();
+        self.write_reg(REG_RDBAL, addr as u32);
+        self.write_reg(REG_RDBAH, (addr >> 32) as u32);
+        self.write_reg(
+            REG_RDLEN,
+            (RING_SIZE * core::mem::size_of::<RxDesc>()) as u32,
+        );
+        self.write_reg(REG_RDH, 0);
+        self.write_reg(REG_RDT, self.rx_tail as u32);
+        self.write_reg(REG_RCTL, RCTL_EN | RCTL_BAM | RCTL_BSIZE_2048 | RCTL_SECRC);
+    }
+
+    fn init_tx(&mut self) {
+        let addr = self.tx.bus_addr();
+        self.write_reg(REG_TDBAL, addr as u32);
+        self.write_reg(REG_TDBAH, (addr >> 32) as u32);
+        self.write_reg(
+            REG_TDLEN,
+            (RING_SIZE * core::mem::size_of::<TxDesc>()) as u32,
+        );
+        self.write_reg(REG_TDH, 0);
+        self.write_reg(REG_TDT, 0);
+        self.write_reg(REG_TIPG, TIPG_DEFAULT);
+        self.write_reg(REG_TCTL, TCTL_EN | TCTL_PSP | TCTL_CT | TCTL_COLD);
+    }
+
+    /// Attaches free buffers to the RX descriptors between the tail and the
+    /// next descriptor to be received, and hands them over to the hardware.
+    fn refill_rx(&mut self) {
+        let old_tail = self.rx_tail;
+        while (self.rx_tail + 1) % RING_SIZE != self.rx_next {
+            let Some(buf) = self.rx.pop_free_buf() else {
+                break;
+            };
+            let desc = RxDesc {
+                addr: self.rx.buf_bus_addr(buf),
+                ..Default::default()
+            };
+            self.rx.desc_buf[self.rx_tail] = buf;
+            self.rx.write_desc(self.rx_tail, desc);
+            self.rx_tail = (self.rx_tail + 1) % RING_SIZE;
+        }
+        if self.rx_tail != old_tail {
+            self.write_reg(REG_RDT, self.rx_tail as u32);
+        }
+    }
+}
+
+impl BaseDriverOps for E1000Nic {
+    fn device_name(&self) -> &str {
+        "e1000"
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Net
+    }
+}
+
+impl NetDriverOps for E1000Nic {
+    fn mac_address(&self) -> EthernetAddress {
+        self.mac
+    }
+
+    fn can_transmit(&self) -> bool {
+        self.tx.num_free > 0 && (self.tx_tail + 1) % RING_SIZE != self.tx_clean
+    }
+
+    fn can_receive(&self) -> bool {
+        self.rx_next != self.rx_tail && self.rx.read_desc(self.rx_next).status & DESC_STA_DD != 0
+    }
+
+    fn rx_queue_size(&self) -> usize {
+        RING_SIZE
+    }
+
+    fn tx_queue_size(&self) -> usize {
+        RING_SIZE
+    }
+
+    fn recycle_rx_buffer(&mut self, rx_buf: NetBufPtr) -> DevResult {
+        let buf = self.rx.buf_index(rx_buf.raw_ptr::<u8>())?;
+        self.rx.push_free_buf(buf);
+        self.refill_rx();
+        Ok(())
+    }
+
+    fn recycle_tx_buffers(&mut self) -> DevResult {
+        while self.tx_clean != self.tx_tail {
+            if self.tx.read_desc(self.tx_clean).status & DESC_STA_DD == 0 {
+                break;
+            }
+            let buf = self.tx.desc_buf[self.tx_clean];
+            self.tx.push_free_buf(buf);
+            self.tx_clean = (self.tx_clean + 1) % RING_SIZE;
+        }
+        Ok(())
+    }
+
+    fn transmit(&mut self, tx_buf: NetBufPtr) -> DevResult {
+        let buf = self.tx.buf_index(tx_buf.raw_ptr::<u8>())?;
+        if (self.tx_tail + 1) % RING_SIZE == self.tx_clean {
+            // The buffer is not attached to a descriptor, take it back.
+            self.tx.push_free_buf(buf);
+            return Err(DevError::Again);
+        }
+        let desc = TxDesc {
+            addr: self.tx.buf_bus_addr(buf),
+            length: tx_buf.packet_len() as u16,
+            cmd: TX_DESC_CMD_EOP | TX_DESC_CMD_IFCS | TX_DESC_CMD_RS,
+            ..Default::default()
+        };
+        self.tx.desc_buf[self.tx_tail] = buf;
+        self.tx.write_desc(self.tx_tail, desc);
+        self.tx_tail = (self.tx_tail + 1) % RING_SIZE;
+        self.write_reg(REG_TDT, self.tx_tail as u32);
+        Ok(())
+    }
+
+    fn receive(&mut self) -> DevResult<NetBufPtr> {
+        if !self.can_receive() {
+            return Err(DevError::Again);
+        }
+        let desc = self.rx.read_desc(self.rx_next);
+        let buf = self.rx.desc_buf[self.rx_next];
+        self.rx_next = (self.rx_next + 1) % RING_SIZE;
+        if desc.status & RX_DESC_STA_EOP == 0 || desc.errors != 0 {
+            // Drop packets spanning multiple descriptors or with errors.
+            warn!(
+                "e1000: dropped bad RX packet (status {:#x}, errors {:#x})",
+                desc.status, desc.errors
+            );
+            self.rx.push_free_buf(buf);
+            self.refill_rx();
+            return Err(DevError::Again);
+        }
+        let ptr = self.rx.buf_ptr(buf);
+        Ok(NetBufPtr::new(ptr, ptr, desc.length as usize))
+    }
+
+    fn alloc_tx_buffer(&mut self, size: usize) -> DevResult<NetBufPtr> {
+        if size > BUF_SIZE {
+            return Err(DevError::InvalidParam);
+        }
+        let buf = self.tx.pop_free_buf().ok_or(DevError::NoMemory)?;
+        let ptr = self.tx.buf_ptr(buf);
+        Ok(NetBufPtr::new(ptr, ptr, size))
+    }
+}
+
+impl NetIrqOps for E1000Nic {
+    fn link_up(&self) -> bool {
+        self.read_reg(REG_STATUS) & STATUS_LU != 0
+    }
+
+    fn link_speed(&self) -> Option<u32> {
+        let status = self.read_reg(REG_STATUS);
+        if status & STATUS_LU == 0 {
+            return None;
+        }
+        match (status >> STATUS_SPEED_SHIFT) & STATUS_SPEED_MASK {
+            0b00 => Some(10),
+            0b01 => Some(100),
+            _ => Some(1000),
+        }
+    }
+
+    fn set_mac_address(&mut self, mac: EthernetAddress) -> DevResult {
+        self.write_mac_address(mac);
+        self.mac = mac;
+        Ok(())
+    }
+}
+
+impl Drop for E1000Nic {
+    fn drop(&mut self) {
+        self.write_reg(REG_RCTL, 0);
+        self.write_reg(REG_TCTL, 0);
+    }
+}
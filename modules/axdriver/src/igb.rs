@@ -0,0 +1,182 @@
+//! Driver for the Intel 82576 gigabit NIC.
+//!
+//! The data path is provided by [`igb_driver`]. This wrapper adds the
+//! statistics read from the registers of the NIC.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use axdma::{dealloc_coherent, BusAddr, DMAInfo};
+use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
+use axdriver_net::{EthernetAddress, NetBufPtr, NetDriverOps};
+use axhal::mem::{phys_to_virt, virt_to_phys};
+use igb_driver::IgbHal;
+use kspin::SpinNoIrq;
+
+use crate::dma::alloc_coherent;
+use crate::ops::{NetIrqOps, NetStats};
+
+pub use igb_driver::{INTEL_82576, INTEL_VEND};
+
+/// Number of RX and TX queue pairs.
+const QN: u16 = 1;
+/// Number of descriptors of each queue.
+const QS: usize = 1024;
+
+// Statistics registers, all cleared on read.
+const REG_CRCERRS: usize = 0x04000;
+const REG_MPC: usize = 0x04010;
+const REG_GPRC: usize = 0x04074;
+const REG_GPTC: usize = 0x04080;
+const REG_GORCL: usize = 0x04088;
+const REG_GORCH: usize = 0x0408c;
+const REG_GOTCL: usize = 0x04090;
+const REG_GOTCH: usize = 0x04094;
+
+pub struct IgbHalImpl;
+
+unsafe impl IgbHal for IgbHalImpl {
+    fn dma_alloc(size: usize) -> (usize, NonNull<u8>) {
+        let Ok(layout) = Layout::from_size_align(size, 8) else {
+            return (0, NonNull::dangling());
+        };
+        match unsafe { alloc_coherent(layout) } {
+            Ok(dma_info) => (dma_info.bus_addr.as_u64() as usize, dma_info.cpu_addr),
+            Err(_) => (0, NonNull::dangling()),
+        }
+    }
+
+    unsafe fn dma_dealloc(paddr: usize, vaddr: NonNull<u8>, size: usize) -> i32 {
+        let Ok(layout) = Layout::from_size_align(size, 8) else {
+            return -1;
+        };
+        let dma_info = DMAInfo {
+            cpu_addr: vaddr,
+            bus_addr: BusAddr::from(paddr as u64),
+        };
+        unsafe { dealloc_coherent(dma_info, layout) };
+        0
+    }
+
+    unsafe fn mmio_phys_to_virt(paddr: usize, _size: usize) -> NonNull<u8> {
+        NonNull::new(phys_to_virt(paddr.into()).as_mut_ptr()).unwrap()
+    }
+
+    unsafe fn mmio_virt_to_phys(vaddr: NonNull<u8>, _size: usize) -> usize {
+        virt_to_phys((vaddr.as_ptr() as usize).into()).into()
+    }
+
+    fn wait_until(duration: core::time::Duration) -> Result<(), &'static str> {
+        axhal::time::busy_wait_until(duration);
+        Ok(())
+    }
+}
+
+/// The 82576 NIC driver.
+pub struct IgbNic {
+    base: usize,
+    inner: igb_driver::IgbNic<IgbHalImpl, QS, QN>,
+    /// The hardware counters accumulated so far, as they are cleared on read.
+    stats: SpinNoIrq<NetStats>,
+}
+
+impl IgbNic {
+    /// Initializes the NIC whose registers are mapped at `base`.
+    pub fn init(base: usize, len: usize) -> DevResult<Self> {
+        let inner = igb_driver::IgbNic::<IgbHalImpl, QS, QN>::init(base, len).map_err(|err| {
+            error!("igb: failed to initialize device: {:?}", err);
+            DevError::BadState
+        })?;
+        // Start counting from zero.
+        read_hw_stats(base);
+        Ok(Self {
+            base,
+            inner,
+            stats: SpinNoIrq::new(NetStats::default()),
+        })
+    }
+}
+
+/// Reads and clears the hardware counters. The queue-full events are not
+/// counted by the hardware.
+fn read_hw_stats(base: usize) -> NetStats {
+    let read_reg = |reg: usize| unsafe { ((base + reg) as *const u32).read_volatile() } as u64;
+    // The low half must be read first.
+    let rx_bytes = read_reg(REG_GORCL);
+    let tx_bytes = read_reg(REG_GOTCL);
+    NetStats {
+        rx_packets: read_reg(REG_GPRC),
+        tx_packets: read_reg(REG_GPTC),
+        rx_bytes: rx_bytes | read_reg(REG_GORCH) << 32,
+        tx_bytes: tx_bytes | read_reg(REG_GOTCH) << 32,
+        rx_overruns: read_reg(REG_MPC),
+        tx_queue_full: 0,
+        rx_crc_errors: read_reg(REG_CRCERRS),
+    }
+}
+
+impl BaseDriverOps for IgbNic {
+    fn device_name(&self) -> &str {
+        self.inner.device_name()
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Net
+    }
+}
+
+impl NetDriverOps for IgbNic {
+    fn mac_address(&self) -> EthernetAddress {
+        self.inner.mac_address()
+    }
+
+    fn rx_queue_size(&self) -> usize {
+        self.inner.rx_queue_size()
+    }
+
+    fn tx_queue_size(&self) -> usize {
+        self.inner.tx_queue_size()
+    }
+
+    fn can_receive(&self) -> bool {
+        self.inner.can_receive()
+    }
+
+    fn can_transmit(&self) -> bool {
+        self.inner.can_transmit()
+    }
+
+    fn recycle_rx_buffer(&mut self, rx_buf: NetBufPtr) -> DevResult {
+        self.inner.recycle_rx_buffer(rx_buf)
+    }
+
+    fn recycle_tx_buffers(&mut self) -> DevResult {
+        self.inner.recycle_tx_buffers()
+    }
+
+    fn transmit(&mut self, tx_buf: NetBufPtr) -> DevResult {
+        let full = !self.inner.can_transmit();
+        let res = self.inner.transmit(tx_buf);
+        if res.is_err() && full {
+            self.stats.lock().tx_queue_full += 1;
+        }
+        res
+    }
+
+    fn receive(&mut self) -> DevResult<NetBufPtr> {
+        self.inner.receive()
+    }
+
+    fn alloc_tx_buffer(&mut self, size: usize) -> DevResult<NetBufPtr> {
+        self.inner.alloc_tx_buffer(size)
+    }
+}
+
+// `igb_driver` does not expose the link state, it is reported as always up.
+impl NetIrqOps for IgbNic {
+    fn stats(&self) -> NetStats {
+        let mut stats = self.stats.lock();
+        *stats += read_hw_stats(self.base);
+        *stats
+    }
+}
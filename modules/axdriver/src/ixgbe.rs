@@ -1,13 +1,119 @@
-use axdma::{alloc_coherent, dealloc_coherent, BusAddr, DMAInfo};
-use axdriver_net::ixgbe::{IxgbeHal, PhysAddr as IxgbePhysAddr};
+//! Driver for the Intel 82599 10 Gigabit NIC.
+//!
+//! Incoming flows are spread across the RX queues by RSS (Receive Side
+//! Scaling). Each CPU transmits on its own queue, and drains its own RX queue
+//! before the others.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::alloc::Layout;
+use core::mem::ManuallyDrop;
+use core::ptr::NonNull;
+
+use axdma::{dealloc_coherent, BusAddr, DMAInfo};
+use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
+use axdriver_net::{EthernetAddress, NetBufPtr, NetDriverOps};
 use axhal::mem::{phys_to_virt, virt_to_phys};
-use core::{alloc::Layout, ptr::NonNull};
+use ixgbe_driver::{
+    IxgbeDevice, IxgbeError, IxgbeHal, IxgbeNetBuf, MemPool, NicDevice, PhysAddr as IxgbePhysAddr,
+};
+
+use kspin::SpinNoIrq;
+
+use crate::dma::alloc_coherent;
+use crate::ops::{NetIrqOps, NetStats};
+
+pub use ixgbe_driver::{INTEL_82599, INTEL_VEND};
+
+/// The 82599 distributes packets to at most 16 queues by RSS.
+const MAX_RSS_QUEUES: usize = 16;
+
+/// Number of RX and TX queue pairs: `ixgbe-queues` in the platform config,
+/// or the number of CPUs if it is 0, capped by the NIC.
+pub const NUM_QUEUES: u16 = {
+    let n = if axconfig::IXGBE_QUEUES == 0 {
+        axconfig::SMP
+    } else {
+        axconfig::IXGBE_QUEUES
+    };
+    if n > MAX_RSS_QUEUES {
+        MAX_RSS_QUEUES as u16
+    } else {
+        n as u16
+    }
+};
+
+/// Number of descriptors of each queue, smaller with more queues to bound
+/// the memory of the buffer pool.
+pub const QUEUE_SIZE: usize = if NUM_QUEUES == 1 { 1024 } else { 512 };
+
+/// Enough buffers to fill all RX queues, with the same number in flight.
+const MEM_POOL: usize = {
+    let n = 2 * NUM_QUEUES as usize * QUEUE_SIZE;
+    if n < 4096 {
+        4096
+    } else {
+        n
+    }
+};
+const MEM_POOL_ENTRY_SIZE: usize = 2048;
+const RECV_BATCH_SIZE: usize = 64;
+
+const REG_RAL0: usize = 0x0a200;
+const REG_RAH0: usize = 0x0a204;
+const RAH_AV: u32 = 1 << 31;
+const REG_LINKS: usize = 0x042a4;
+const LINKS_UP: u32 = 1 << 30;
+const LINKS_SPEED_SHIFT: u32 = 28;
+const LINKS_SPEED_MASK: u32 = 0b11;
+
+// Statistics registers, all cleared on read.
+const REG_CRCERRS: usize = 0x04000;
+const REG_GPRC: usize = 0x04074;
+const REG_GPTC: usize = 0x04080;
+const REG_GORCL: usize = 0x04088;
+const REG_GORCH: usize = 0x0408c;
+const REG_GOTCL: usize = 0x04090;
+const REG_GOTCH: usize = 0x04094;
+const REG_XEC: usize = 0x04120;
+/// One missed packets counter per packet buffer.
+const REG_MPC_BASE: usize = 0x03fa0;
+const NUM_MPC: usize = 8;
+
+// RSS registers.
+const REG_RXCTRL: usize = 0x03000;
+const REG_RXCSUM: usize = 0x05000;
+const REG_MRQC: usize = 0x05818;
+const REG_RSSRK_BASE: usize = 0x05c80;
+const REG_RETA_BASE: usize = 0x0eb00;
+
+const RXCTRL_RXEN: u32 = 1 << 0;
+/// Report the RSS hash instead of the fragment checksum in RX descriptors.
+const RXCSUM_PCSD: u32 = 1 << 13;
+const MRQC_RSS_EN: u32 = 0x1;
+const MRQC_RSS_FIELD_IPV4_TCP: u32 = 1 << 16;
+const MRQC_RSS_FIELD_IPV4: u32 = 1 << 17;
+const MRQC_RSS_FIELD_IPV6: u32 = 1 << 20;
+const MRQC_RSS_FIELD_IPV6_TCP: u32 = 1 << 21;
+const MRQC_RSS_FIELD_IPV4_UDP: u32 = 1 << 22;
+const MRQC_RSS_FIELD_IPV6_UDP: u32 = 1 << 23;
+/// The redirection table has 128 one-byte entries in 32 registers.
+const RETA_ENTRIES: usize = 128;
+
+/// The commonly used Toeplitz hash key, from the Microsoft RSS specification.
+const RSS_KEY: [u8; 40] = [
+    0x6d, 0x5a, 0x56, 0xda, 0x25, 0x5b, 0x0e, 0xc2, 0x41, 0x67, 0x25, 0x3d, 0x43, 0xa3, 0x8f, 0xb0,
+    0xd0, 0xca, 0x2b, 0xcb, 0xae, 0x7b, 0x30, 0xb4, 0x77, 0xcb, 0x2d, 0xa3, 0x80, 0x30, 0xf2, 0x0c,
+    0x6a, 0x42, 0xb7, 0x3b, 0xbe, 0xac, 0x01, 0xfa,
+];
 
 pub struct IxgbeHalImpl;
 
 unsafe impl IxgbeHal for IxgbeHalImpl {
     fn dma_alloc(size: usize) -> (IxgbePhysAddr, NonNull<u8>) {
-        let layout = Layout::from_size_align(size, 8).unwrap();
+        let Ok(layout) = Layout::from_size_align(size, 8) else {
+            return (0, NonNull::dangling());
+        };
         match unsafe { alloc_coherent(layout) } {
             Ok(dma_info) => (dma_info.bus_addr.as_u64() as usize, dma_info.cpu_addr),
             Err(_) => (0, NonNull::dangling()),
@@ -15,7 +121,9 @@ unsafe impl IxgbeHal for IxgbeHalImpl {
     }
 
     unsafe fn dma_dealloc(paddr: IxgbePhysAddr, vaddr: NonNull<u8>, size: usize) -> i32 {
-        let layout = Layout::from_size_align(size, 8).unwrap();
+        let Ok(layout) = Layout::from_size_align(size, 8) else {
+            return -1;
+        };
         let dma_info = DMAInfo {
             cpu_addr: vaddr,
             bus_addr: BusAddr::from(paddr as u64),
@@ -37,3 +145,261 @@ unsafe impl IxgbeHal for IxgbeHalImpl {
         Ok(())
     }
 }
+
+/// The queue of the current CPU.
+fn this_queue() -> u16 {
+    (axhal::cpu::this_cpu_id() % NUM_QUEUES as usize) as u16
+}
+
+/// The 82599 NIC driver, with [`NUM_QUEUES`] queue pairs.
+pub struct IxgbeNic {
+    base: usize,
+    inner: IxgbeDevice<IxgbeHalImpl, QUEUE_SIZE>,
+    mem_pool: Arc<MemPool>,
+    rx_buffer_queue: VecDeque<NetBufPtr>,
+    /// The hardware counters accumulated so far, as they are cleared on read.
+    stats: SpinNoIrq<NetStats>,
+}
+
+unsafe impl Send for IxgbeNic {}
+unsafe impl Sync for IxgbeNic {}
+
+impl IxgbeNic {
+    /// Initializes the NIC whose registers are mapped at `base`, and enables
+    /// RSS if there is more than one queue.
+    pub fn init(base: usize, len: usize) -> DevResult<Self> {
+        let mem_pool = MemPool::allocate::<IxgbeHalImpl>(MEM_POOL, MEM_POOL_ENTRY_SIZE)
+            .map_err(|_| DevError::NoMemory)?;
+        let inner = IxgbeDevice::<IxgbeHalImpl, QUEUE_SIZE>::init(
+            base, len, NUM_QUEUES, NUM_QUEUES, &mem_pool,
+        )
+        .map_err(|err| {
+            error!("ixgbe: failed to initialize device: {:?}", err);
+            DevError::BadState
+        })?;
+        if NUM_QUEUES > 1 {
+            setup_rss(base);
+        }
+        // Start counting from zero.
+        read_hw_stats(base);
+        info!(
+            "ixgbe: {} queue pairs of {} descriptors",
+            NUM_QUEUES, QUEUE_SIZE
+        );
+        Ok(Self {
+            base,
+            inner,
+            mem_pool,
+            rx_buffer_queue: VecDeque::with_capacity(RECV_BATCH_SIZE),
+            stats: SpinNoIrq::new(NetStats::default()),
+        })
+    }
+
+    fn read_reg(&self, reg: usize) -> u32 {
+        unsafe { ((self.base + reg) as *const u32).read_volatile() }
+    }
+
+    fn write_reg(&self, reg: usize, val: u32) {
+        unsafe { ((self.base + reg) as *mut u32).write_volatile(val) }
+    }
+
+    /// Moves the received packets of the given queue into the buffer queue,
+    /// returns the number of packets.
+    fn receive_queue(&mut self, qid: u16) -> DevResult<usize> {
+        let queue = &mut self.rx_buffer_queue;
+        match self.inner.receive_packets(qid, RECV_BATCH_SIZE, |rx_buf| {
+            queue.push_back(buf_to_ptr(rx_buf))
+        }) {
+            Ok(n) => Ok(n),
+            Err(IxgbeError::NotReady) => Ok(0),
+            Err(_) => Err(DevError::BadState),
+        }
+    }
+}
+
+/// Programs the hash key and the redirection table, and enables RSS on
+/// TCP/UDP ports and IP addresses. The receiver is stopped meanwhile.
+fn setup_rss(base: usize) {
+    let read_reg = |reg: usize| unsafe { ((base + reg) as *const u32).read_volatile() };
+    let write_reg =
+        |reg: usize, val: u32| unsafe { ((base + reg) as *mut u32).write_volatile(val) };
+
+    let rxctrl = read_reg(REG_RXCTRL);
+    write_reg(REG_RXCTRL, rxctrl & !RXCTRL_RXEN);
+    for (i, key) in RSS_KEY.chunks_exact(4).enumerate() {
+        write_reg(
+            REG_RSSRK_BASE + i * 4,
+            u32::from_le_bytes(key.try_into().unwrap()),
+        );
+    }
+    for i in 0..RETA_ENTRIES / 4 {
+        let entry = |j: usize| ((i * 4 + j) % NUM_QUEUES as usize) as u32;
+        let reta = entry(0) | entry(1) << 8 | entry(2) << 16 | entry(3) << 24;
+        write_reg(REG_RETA_BASE + i * 4, reta);
+    }
+    write_reg(REG_RXCSUM, read_reg(REG_RXCSUM) | RXCSUM_PCSD);
+    write_reg(
+        REG_MRQC,
+        MRQC_RSS_EN
+            | MRQC_RSS_FIELD_IPV4
+            | MRQC_RSS_FIELD_IPV4_TCP
+            | MRQC_RSS_FIELD_IPV4_UDP
+            | MRQC_RSS_FIELD_IPV6
+            | MRQC_RSS_FIELD_IPV6_TCP
+            | MRQC_RSS_FIELD_IPV6_UDP,
+    );
+    write_reg(REG_RXCTRL, rxctrl);
+}
+
+/// Reads and clears the hardware counters. The queue-full events are not
+/// counted by the hardware.
+fn read_hw_stats(base: usize) -> NetStats {
+    let read_reg = |reg: usize| unsafe { ((base + reg) as *const u32).read_volatile() } as u64;
+    // The low half must be read first.
+    let rx_bytes = read_reg(REG_GORCL);
+    let tx_bytes = read_reg(REG_GOTCL);
+    NetStats {
+        rx_packets: read_reg(REG_GPRC),
+        tx_packets: read_reg(REG_GPTC),
+        rx_bytes: rx_bytes | read_reg(REG_GORCH) << 32,
+        tx_bytes: tx_bytes | read_reg(REG_GOTCH) << 32,
+        rx_overruns: (0..NUM_MPC).map(|i| read_reg(REG_MPC_BASE + i * 4)).sum(),
+        tx_queue_full: 0,
+        rx_crc_errors: read_reg(REG_CRCERRS) + read_reg(REG_XEC),
+    }
+}
+
+/// Converts a buffer from the pool into a [`NetBufPtr`], whose raw pointer is
+/// the pool entry plus one, as it must not be null.
+fn buf_to_ptr(buf: IxgbeNetBuf) -> NetBufPtr {
+    let mut buf = ManuallyDrop::new(buf);
+    let buf_ptr = buf.packet_mut().as_mut_ptr();
+    NetBufPtr::new(
+        NonNull::new((buf.pool_entry() + 1) as *mut u8).unwrap(),
+        NonNull::new(buf_ptr).unwrap(),
+        buf.packet_len(),
+    )
+}
+
+fn ptr_to_buf(ptr: NetBufPtr, pool: &Arc<MemPool>) -> DevResult<IxgbeNetBuf> {
+    IxgbeNetBuf::construct(ptr.raw_ptr::<u8>() as usize - 1, pool, ptr.packet_len())
+        .map_err(|_| DevError::BadState)
+}
+
+impl BaseDriverOps for IxgbeNic {
+    fn device_name(&self) -> &str {
+        self.inner.get_driver_name()
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Net
+    }
+}
+
+impl NetDriverOps for IxgbeNic {
+    fn mac_address(&self) -> EthernetAddress {
+        EthernetAddress(self.inner.get_mac_addr())
+    }
+
+    fn rx_queue_size(&self) -> usize {
+        QUEUE_SIZE
+    }
+
+    fn tx_queue_size(&self) -> usize {
+        QUEUE_SIZE
+    }
+
+    fn can_receive(&self) -> bool {
+        !self.rx_buffer_queue.is_empty()
+            || (0..NUM_QUEUES).any(|qid| self.inner.can_receive(qid).unwrap_or(false))
+    }
+
+    fn can_transmit(&self) -> bool {
+        self.inner.can_send(this_queue()).unwrap_or(false)
+    }
+
+    fn recycle_rx_buffer(&mut self, rx_buf: NetBufPtr) -> DevResult {
+        // Dropping the buffer returns it to the pool.
+        drop(ptr_to_buf(rx_buf, &self.mem_pool)?);
+        Ok(())
+    }
+
+    fn recycle_tx_buffers(&mut self) -> DevResult {
+        for qid in 0..NUM_QUEUES {
+            self.inner
+                .recycle_tx_buffers(qid)
+                .map_err(|_| DevError::BadState)?;
+        }
+        Ok(())
+    }
+
+    fn transmit(&mut self, tx_buf: NetBufPtr) -> DevResult {
+        let tx_buf = ptr_to_buf(tx_buf, &self.mem_pool)?;
+        match self.inner.send(this_queue(), tx_buf) {
+            Ok(_) => Ok(()),
+            Err(IxgbeError::QueueFull) => {
+                self.stats.lock().tx_queue_full += 1;
+                Err(DevError::Again)
+            }
+            Err(_) => Err(DevError::BadState),
+        }
+    }
+
+    fn receive(&mut self) -> DevResult<NetBufPtr> {
+        if self.rx_buffer_queue.is_empty() {
+            // Start from the queue of this CPU, but drain the others as well,
+            // as their CPUs may not be polling.
+            let first = this_queue();
+            for i in 0..NUM_QUEUES {
+                if self.receive_queue((first + i) % NUM_QUEUES)? > 0 {
+                    break;
+                }
+            }
+        }
+        self.rx_buffer_queue.pop_front().ok_or(DevError::Again)
+    }
+
+    fn alloc_tx_buffer(&mut self, size: usize) -> DevResult<NetBufPtr> {
+        let tx_buf = IxgbeNetBuf::alloc(&self.mem_pool, size).map_err(|_| DevError::NoMemory)?;
+        Ok(buf_to_ptr(tx_buf))
+    }
+}
+
+impl NetIrqOps for IxgbeNic {
+    fn link_up(&self) -> bool {
+        self.read_reg(REG_LINKS) & LINKS_UP != 0
+    }
+
+    fn link_speed(&self) -> Option<u32> {
+        let links = self.read_reg(REG_LINKS);
+        if links & LINKS_UP == 0 {
+            return None;
+        }
+        match (links >> LINKS_SPEED_SHIFT) & LINKS_SPEED_MASK {
+            0b01 => Some(100),
+            0b10 => Some(1000),
+            0b11 => Some(10000),
+            _ => None,
+        }
+    }
+
+    fn set_mac_address(&mut self, mac: EthernetAddress) -> DevResult {
+        // `mac_address` reads the address back from these registers.
+        let mac = mac.0;
+        self.write_reg(
+            REG_RAL0,
+            u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]),
+        );
+        self.write_reg(
+            REG_RAH0,
+            u16::from_le_bytes([mac[4], mac[5]]) as u32 | RAH_AV,
+        );
+        Ok(())
+    }
+
+    fn stats(&self) -> NetStats {
+        let mut stats = self.stats.lock();
+        *stats += read_hw_stats(self.base);
+        *stats
+    }
+}
@@ -8,8 +8,10 @@
 //! driver they want.
 //!
 //! For each device category (i.e., net, block, display, etc.), an unified type
-//! is used to represent all devices in that category. Currently, there are 3
-//! categories: [`AxNetDevice`], [`AxBlockDevice`], and [`AxDisplayDevice`].
+//! is used to represent all devices in that category. Currently, there are 9
+//! categories: [`AxNetDevice`], [`AxBlockDevice`], [`AxDisplayDevice`],
+//! [`AxCharDevice`], [`AxRngDevice`], [`Ax9pDevice`], [`AxBalloonDevice`],
+//! [`AxInputDevice`], and [`AxSoundDevice`].
 //!
 //! # Concepts
 //!
@@ -19,38 +21,94 @@
 //!   time by corresponding cargo features. For example, [`AxNetDevice`] will be
 //!   an alias of [`VirtioNetDev`] if the `virtio-net` feature is enabled. This
 //!   model provides the best performance as it avoids dynamic dispatch. But on
-//!   limitation, only one device instance is supported for each device category.
+//!   limitation, only one driver is supported for each device category, though
+//!   there can be multiple devices of that driver.
 //! - **Dynamic**: All device instance is using [trait objects] and wrapped in a
-//!   `Box<dyn Trait>`. For example, [`AxNetDevice`] will be [`Box<dyn NetDriverOps>`].
+//!   `Box<dyn Trait>`. For example, [`AxNetDevice`] will be [`Box<dyn NetIrqOps>`].
 //!   When call a method provided by the device, it uses [dynamic dispatch][dyn]
 //!   that may introduce a little overhead. But on the other hand, it is more
 //!   flexible, multiple instances of each device category are supported.
 //!
+//! All devices of a category are kept in an [`AxDeviceContainer`], in which
+//! each device is named after its driver and its index among the devices of
+//! that driver, e.g., `virtio-blk0` and `virtio-blk1`. Subsystems can take a
+//! device by name with [`AxDeviceContainer::take_by_name`].
+//!
+//! Block devices are wrapped in [`BlockPartition`]s. The partitions in the MBR
+//! or GPT of a disk are registered after it, named after the disk and the
+//! partition number, e.g., `virtio-blk0p1` (see [`partition_name`]).
+//!
+//! Each driver is registered with a priority, 0 by default. Drivers with lower
+//! values are tried first on each bus device, and their devices come first in
+//! the containers, e.g., the RAM disk (priority 10) comes after the disks on
+//! buses. Set `LOG=debug` to see the drivers that declined each bus device.
+//! A driver that matches a device but fails to initialize it is logged as a
+//! warning, then the next driver is tried, so a failed device never stops the
+//! boot.
+//!
 //! # Supported Devices
 //!
 //! | Device Category | Cargo Feature | Description |
 //! |-|-|-|
-//! | Block | `ramdisk` | A RAM disk that stores data in a vector |
-//! | Block | `virtio-blk` | VirtIO block device |
-//! | Network | `virtio-net` | VirtIO network device |
-//! | Display | `virtio-gpu` | VirtIO graphics device |
+//! | Block | `ramdisk` | A RAM disk that stores data in a vector, optionally with an initial image |
+//! | Block | `nvme` | NVMe storage controller |
+//! | Block | `sdhci` | SD card behind a standard SDHCI controller (PCI or MMIO) |
+//! | Block | `bcm2835-sdhci` | SD card of the Raspberry Pi 4, behind the EMMC2 controller |
+//! | Block | `usb-storage` | USB mass storage (Bulk-Only) device behind an xHCI controller |
+//! | Block | `virtio-blk` | VirtIO block device, with flush and discard |
+//! | Network | `e1000` | Intel 8254x gigabit NIC |
+//! | Network | `ixgbe` | Intel 82599 10 gigabit NIC (RSS over one queue per CPU) |
+//! | Network | `rtl8139` | Realtek RTL8139 NIC (PCI only) |
+//! | Network | `loopback` | Software loopback device, which receives the frames it transmits |
+//! | Network | `virtio-net` | VirtIO network device (multi-queue if supported) |
+//! | Display | `virtio-gpu` | VirtIO graphics device, with partial flush and double buffering |
+//! | Char | `virtio-console` | VirtIO console device |
+//! | RNG | `virtio-rng` | VirtIO entropy device |
+//! | 9P | `virtio-9p` | VirtIO 9P transport device |
+//! | Balloon | `virtio-balloon` | VirtIO memory balloon device |
+//! | Input | `virtio-input` | VirtIO keyboard, mouse or tablet |
+//! | Sound | `virtio-sound` | VirtIO sound device (playback only) |
 //!
 //! # Other Cargo Features
 //!
 //! - `dyn`: use the dynamic device model (see above).
-//! - `bus-mmio`: use device tree to probe all MMIO devices.
+//! - `bus-mmio`: use device tree to probe all MMIO devices. Nodes compatible
+//!    with `virtio,mmio` are probed, except the disabled ones. Both legacy
+//!    (version 1) and modern (version 2) VirtIO MMIO devices are supported.
+//! - `mmio-static`: probe the MMIO regions in the platform config when the
+//!    bootloader passes no device tree. Implies `bus-mmio`.
 //! - `bus-pci`: use PCI bus to probe all PCI devices. This feature is
-//!    enabeld by default.
+//!    enabeld by default. The functions found can be listed with
+//!    [`pci::enumerate`].
+//! - `paging`: map device memory that is not in the configured MMIO regions,
+//!    e.g., 64-bit PCI BARs above 4 GiB.
+//! - `dma`: allocate DMA memory with `axdma`, within the DMA mask declared by
+//!    each driver, e.g., below 4 GiB for `rtl8139`. This is enabled by the
+//!    drivers of devices doing DMA by themselves.
+//! - `irq`: enable interrupt support of devices, e.g., MSI-X vectors of PCI
+//!    devices (see [`PciMsixExt`]) and the RX interrupt of `virtio-net`. MMIO
+//!    drivers get the IRQ of each device from the device tree or the platform
+//!    config, and PCI drivers can resolve the INTx pin with [`intx_irq`].
+//!    Handlers of probed devices are registered by [`register_irq_handler`].
 //! - `virtio`: use VirtIO devices. This is enabled if any of `virtio-blk`,
 //!   `virtio-net` or `virtio-gpu` is enabled.
 //! - `net`: use network devices. This is enabled if any feature of network
 //!    devices is selected. If this feature is enabled without any network device
-//!    features, a dummy struct is used for [`AxNetDevice`].
+//!    features, a dummy struct is used for [`AxNetDevice`]. The MAC address
+//!    of the first NIC can be overridden by `net-mac` in the platform config,
+//!    see [`mac_override`]. With `irq`, the interrupts of NICs are routed to
+//!    `net-irq-cpu`, see [`net_irq_cpu`].
 //! - `block`: use block storage devices. Similar to the `net` feature.
 //! - `display`: use graphics display devices. Similar to the `net` feature.
+//! - `char`: use character devices. Similar to the `net` feature.
+//! - `rng`: use random number generator devices. Similar to the `net` feature.
+//! - `_9p`: use 9P transport devices. Similar to the `net` feature.
+//! - `balloon`: use memory balloon devices. Similar to the `net` feature.
+//! - `input`: use input devices. Similar to the `net` feature.
+//! - `sound`: use sound devices. Similar to the `net` feature.
 //!
 //! [`VirtioNetDev`]: axdriver_virtio::VirtIoNetDev
-//! [`Box<dyn NetDriverOps>`]: axdriver_net::NetDriverOps
+//! [`Box<dyn NetIrqOps>`]: prelude::NetIrqOps
 //! [trait objects]: https://doc.rust-lang.org/book/ch17-02-trait-objects.html
 //! [dyn]: https://doc.rust-lang.org/std/keyword.dyn.html
 
@@ -61,7 +119,6 @@
 #[macro_use]
 extern crate log;
 
-#[cfg(feature = "dyn")]
 extern crate alloc;
 
 #[macro_use]
@@ -70,26 +127,123 @@ mod macros;
 mod bus;
 mod drivers;
 mod dummy;
+mod ops;
 mod structs;
 
+#[cfg(feature = "dma")]
+mod dma;
+
 #[cfg(feature = "virtio")]
 mod virtio;
 
+#[cfg(block_dev = "virtio-blk")]
+mod virtio_blk;
+
+#[cfg(display_dev = "virtio-gpu")]
+mod virtio_gpu;
+
+#[cfg(net_dev = "virtio-net")]
+mod virtio_net;
+
+#[cfg(char_dev = "virtio-console")]
+mod virtio_console;
+
+#[cfg(rng_dev = "virtio-rng")]
+mod virtio_rng;
+
+#[cfg(_9p_dev = "virtio-9p")]
+mod virtio_9p;
+#[cfg(any(test, feature = "virtio-9p"))]
+mod virtio_9p_msg;
+
+#[cfg(balloon_dev = "virtio-balloon")]
+mod virtio_balloon;
+
+#[cfg(input_dev = "virtio-input")]
+mod virtio_input;
+
+#[cfg(sound_dev = "virtio-sound")]
+mod virtio_sound;
+
 #[cfg(feature = "ixgbe")]
 mod ixgbe;
 
+#[cfg(feature = "igb")]
+mod igb;
+
+#[cfg(feature = "e1000")]
+mod e1000;
+
+#[cfg(feature = "nvme")]
+mod nvme;
+
+#[cfg(block_dev = "ramdisk")]
+mod ramdisk;
+
+#[cfg(feature = "usb-storage")]
+mod usb_storage;
+#[cfg(feature = "usb-storage")]
+mod xhci;
+
+#[cfg(any(feature = "sdhci", feature = "bcm2835-sdhci"))]
+mod sdhci;
+
+#[cfg(feature = "bcm2835-sdhci")]
+mod bcm2835_sdhci;
+
+#[cfg(all(feature = "rtl8139", bus = "pci"))]
+mod rtl8139;
+
+#[cfg(feature = "loopback")]
+mod loopback;
+
+#[cfg(feature = "net")]
+mod mac;
+
+#[cfg(feature = "block")]
+mod partition;
+
+#[cfg(feature = "bus-pci")]
+pub mod pci;
 pub mod prelude;
 
+use alloc::{string::String, vec::Vec};
+
 #[allow(unused_imports)]
 use self::prelude::*;
+pub use self::ops::AxDeviceType;
 pub use self::structs::{AxDeviceContainer, AxDeviceEnum};
 
+#[cfg(feature = "loopback")]
+pub use self::loopback::LoopbackDev;
+#[cfg(feature = "net")]
+pub use self::mac::mac_override;
+#[cfg(feature = "block")]
+pub use self::partition::{partition_name, partition_number, BlockPartition};
+
+#[cfg(bus = "pci")]
+pub use self::bus::{bar_info, map_bar, map_io_bar, BarHandle, IoBar, IoValue, MappedBar};
+#[cfg(all(bus = "pci", feature = "irq"))]
+pub use self::bus::{intx_irq, PciMsixExt};
+
+#[cfg(feature = "_9p")]
+pub use self::structs::Ax9pDevice;
+#[cfg(feature = "balloon")]
+pub use self::structs::AxBalloonDevice;
 #[cfg(feature = "block")]
 pub use self::structs::AxBlockDevice;
+#[cfg(feature = "char")]
+pub use self::structs::AxCharDevice;
 #[cfg(feature = "display")]
 pub use self::structs::AxDisplayDevice;
+#[cfg(feature = "input")]
+pub use self::structs::AxInputDevice;
 #[cfg(feature = "net")]
 pub use self::structs::AxNetDevice;
+#[cfg(feature = "rng")]
+pub use self::structs::AxRngDevice;
+#[cfg(feature = "sound")]
+pub use self::structs::AxSoundDevice;
 
 /// A structure that contains all device drivers, organized by their category.
 #[derive(Default)]
@@ -103,6 +257,24 @@ pub struct AllDevices {
     /// All graphics device drivers.
     #[cfg(feature = "display")]
     pub display: AxDeviceContainer<AxDisplayDevice>,
+    /// All character device drivers.
+    #[cfg(feature = "char")]
+    pub char: AxDeviceContainer<AxCharDevice>,
+    /// All random number generator device drivers.
+    #[cfg(feature = "rng")]
+    pub rng: AxDeviceContainer<AxRngDevice>,
+    /// All 9P transport device drivers.
+    #[cfg(feature = "_9p")]
+    pub _9p: AxDeviceContainer<Ax9pDevice>,
+    /// All memory balloon device drivers.
+    #[cfg(feature = "balloon")]
+    pub balloon: AxDeviceContainer<AxBalloonDevice>,
+    /// All input device drivers.
+    #[cfg(feature = "input")]
+    pub input: AxDeviceContainer<AxInputDevice>,
+    /// All sound device drivers.
+    #[cfg(feature = "sound")]
+    pub sound: AxDeviceContainer<AxSoundDevice>,
 }
 
 impl AllDevices {
@@ -117,32 +289,62 @@ impl AllDevices {
         }
     }
 
-    /// Probes all supported devices.
+    /// Probes all supported devices, with the drivers in the order of their
+    /// priorities.
     fn probe(&mut self) {
-        for_each_drivers!(type Driver, {
-            if let Some(dev) = Driver::probe_global() {
-                info!(
-                    "registered a new {:?} device: {:?}",
-                    dev.device_type(),
-                    dev.device_name(),
-                );
-                self.add_device(dev);
+        let drivers = drivers::probe_table();
+        for driver in drivers.iter() {
+            debug!("driver {} (priority {})", driver.name, driver.priority);
+        }
+
+        let mut probed = Vec::new();
+        for driver in drivers.iter() {
+            match driver.with_dma_mask(driver.probe_global) {
+                Ok(Some(dev)) => {
+                    info!(
+                        "registered a new {:?} device by {}: {:?}",
+                        dev.device_type(),
+                        driver.name,
+                        dev.device_name(),
+                    );
+                    probed.push((driver.priority, dev));
+                }
+                Ok(None) => {}
+                Err(e) => warn!("{}", e),
             }
-        });
+        }
+        bus::probe_bus_devices(&drivers, |driver, dev| probed.push((driver.priority, dev)));
 
-        self.probe_bus_devices();
+        // The sort is stable, devices of equal priority keep the probe order.
+        probed.sort_by_key(|(priority, _)| *priority);
+        for (_, dev) in probed {
+            self.add_device(dev);
+        }
     }
 
     /// Adds one device into the corresponding container, according to its device category.
     #[allow(dead_code)]
     fn add_device(&mut self, dev: AxDeviceEnum) {
+        let name = String::from(dev.device_name());
         match dev {
             #[cfg(feature = "net")]
-            AxDeviceEnum::Net(dev) => self.net.push(dev),
+            AxDeviceEnum::Net(dev) => self.net.push(&name, dev),
             #[cfg(feature = "block")]
-            AxDeviceEnum::Block(dev) => self.block.push(dev),
+            AxDeviceEnum::Block(dev) => partition::add_disk(&mut self.block, &name, dev),
             #[cfg(feature = "display")]
-            AxDeviceEnum::Display(dev) => self.display.push(dev),
+            AxDeviceEnum::Display(dev) => self.display.push(&name, dev),
+            #[cfg(feature = "char")]
+            AxDeviceEnum::Char(dev) => self.char.push(&name, dev),
+            #[cfg(feature = "rng")]
+            AxDeviceEnum::Rng(dev) => self.rng.push(&name, dev),
+            #[cfg(feature = "_9p")]
+            AxDeviceEnum::_9P(dev) => self._9p.push(&name, dev),
+            #[cfg(feature = "balloon")]
+            AxDeviceEnum::Balloon(dev) => self.balloon.push(&name, dev),
+            #[cfg(feature = "input")]
+            AxDeviceEnum::Input(dev) => self.input.push(&name, dev),
+            #[cfg(feature = "sound")]
+            AxDeviceEnum::Sound(dev) => self.sound.push(&name, dev),
         }
     }
 }
@@ -158,27 +360,101 @@ pub fn init_drivers() -> AllDevices {
     #[cfg(feature = "net")]
     {
         debug!("number of NICs: {}", all_devs.net.len());
-        for (i, dev) in all_devs.net.iter().enumerate() {
+        for (name, dev) in all_devs.net.iter() {
             assert_eq!(dev.device_type(), DeviceType::Net);
-            debug!("  NIC {}: {:?}", i, dev.device_name());
+            info!("  NIC: {}", name);
         }
+        mac::apply(&mut all_devs.net);
     }
     #[cfg(feature = "block")]
     {
         debug!("number of block devices: {}", all_devs.block.len());
-        for (i, dev) in all_devs.block.iter().enumerate() {
+        for (name, dev) in all_devs.block.iter() {
             assert_eq!(dev.device_type(), DeviceType::Block);
-            debug!("  block device {}: {:?}", i, dev.device_name());
+            info!("  block device: {}", name);
         }
     }
     #[cfg(feature = "display")]
     {
         debug!("number of graphics devices: {}", all_devs.display.len());
-        for (i, dev) in all_devs.display.iter().enumerate() {
+        for (name, dev) in all_devs.display.iter() {
             assert_eq!(dev.device_type(), DeviceType::Display);
-            debug!("  graphics device {}: {:?}", i, dev.device_name());
+            info!("  graphics device: {}", name);
+        }
+    }
+    #[cfg(feature = "char")]
+    {
+        debug!("number of character devices: {}", all_devs.char.len());
+        for (name, dev) in all_devs.char.iter() {
+            assert_eq!(dev.device_type(), DeviceType::Char);
+            info!("  character device: {}", name);
+        }
+    }
+    #[cfg(feature = "rng")]
+    {
+        debug!("number of RNG devices: {}", all_devs.rng.len());
+        for (name, _) in all_devs.rng.iter() {
+            info!("  RNG device: {}", name);
+        }
+    }
+    #[cfg(feature = "_9p")]
+    {
+        debug!("number of 9P devices: {}", all_devs._9p.len());
+        for (name, _) in all_devs._9p.iter() {
+            info!("  9P device: {}", name);
+        }
+    }
+    #[cfg(feature = "balloon")]
+    {
+        debug!("number of balloon devices: {}", all_devs.balloon.len());
+        for (name, _) in all_devs.balloon.iter() {
+            info!("  balloon device: {}", name);
+        }
+    }
+    #[cfg(feature = "input")]
+    {
+        debug!("number of input devices: {}", all_devs.input.len());
+        for (name, _) in all_devs.input.iter() {
+            info!("  input device: {}", name);
+        }
+    }
+    #[cfg(feature = "sound")]
+    {
+        debug!("number of sound devices: {}", all_devs.sound.len());
+        for (name, _) in all_devs.sound.iter() {
+            info!("  sound device: {}", name);
         }
     }
 
     all_devs
 }
+
+/// Registers `handler` for the IRQ of the device, and enables the IRQ.
+///
+/// Returns `false` if the device raises no interrupts or the registration
+/// fails, then the device keeps working in polled mode.
+#[cfg(feature = "irq")]
+pub fn register_irq_handler(dev: &AxDeviceEnum, handler: axhal::irq::IrqHandler) -> bool {
+    let Some(irq) = dev.irq() else {
+        return false;
+    };
+    let ok = axhal::irq::register_handler(irq, handler);
+    if !ok {
+        warn!("failed to register IRQ {} of {}", irq, dev.device_name());
+    }
+    ok
+}
+
+/// Returns the CPU that the interrupts of NICs are routed to, `net-irq-cpu`
+/// in the platform config.
+///
+/// It falls back to CPU 0 if the configured CPU does not exist.
+#[cfg(all(feature = "net", feature = "irq"))]
+pub fn net_irq_cpu() -> usize {
+    let cpu_id = axconfig::NET_IRQ_CPU;
+    if cpu_id >= axconfig::SMP {
+        warn!("`net-irq-cpu` {} is not a CPU, use CPU 0", cpu_id);
+        return 0;
+    }
+    cpu_id
+}
@@ -0,0 +1,148 @@
+//! Software loopback network device.
+//!
+//! Transmitted frames are queued in memory and received back in order. The
+//! TX buffer of a frame becomes its RX buffer, so no frame is copied. As on a
+//! NIC whose RX FIFO overruns, a frame transmitted while the queue is full is
+//! dropped and counted in [`NetStats::rx_overruns`], the transmission itself
+//! never fails.
+
+use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
+use core::ptr::NonNull;
+
+use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
+use axdriver_net::{EthernetAddress, NetBufPtr, NetDriverOps};
+
+use crate::ops::{NetIrqOps, NetStats};
+
+/// Size of each frame buffer, enough for an MTU-sized Ethernet frame.
+const BUF_SIZE: usize = 1536;
+/// Maximum number of frames waiting to be received.
+const QUEUE_SIZE: usize = 256;
+
+type FrameBuf = Box<[u8; BUF_SIZE]>;
+
+/// The software loopback device, which receives the frames it transmits.
+pub struct LoopbackDev {
+    /// Frames transmitted but not received yet, with their lengths.
+    queue: VecDeque<(FrameBuf, usize)>,
+    /// Buffers recycled for later transmissions.
+    free: Vec<FrameBuf>,
+    stats: NetStats,
+}
+
+impl LoopbackDev {
+    /// Creates a loopback device with an empty queue.
+    pub fn new() -> Self {
+        Self {
+            queue: VecDeque::with_capacity(QUEUE_SIZE),
+            free: Vec::new(),
+            stats: NetStats::default(),
+        }
+    }
+
+    /// Takes back a buffer handed out by [`alloc_tx_buffer`] or [`receive`].
+    ///
+    /// [`alloc_tx_buffer`]: NetDriverOps::alloc_tx_buffer
+    /// [`receive`]: NetDriverOps::receive
+    fn take_buf(buf: NetBufPtr) -> FrameBuf {
+        // SAFETY: the network stack only passes back the buffers allocated
+        // by this device, whose raw pointers come from `Box::into_raw`.
+        unsafe { Box::from_raw(buf.raw_ptr::<[u8; BUF_SIZE]>()) }
+    }
+
+    /// Hands out a buffer as a [`NetBufPtr`] of the given length.
+    fn leak_buf(buf: FrameBuf, len: usize) -> NetBufPtr {
+        let ptr = NonNull::new(Box::into_raw(buf) as *mut u8).unwrap();
+        NetBufPtr::new(ptr, ptr, len)
+    }
+
+    fn recycle(&mut self, buf: FrameBuf) {
+        if self.free.len() < QUEUE_SIZE {
+            self.free.push(buf);
+        }
+    }
+}
+
+impl Default for LoopbackDev {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BaseDriverOps for LoopbackDev {
+    fn device_name(&self) -> &str {
+        "loopback"
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Net
+    }
+}
+
+impl NetDriverOps for LoopbackDev {
+    fn mac_address(&self) -> EthernetAddress {
+        EthernetAddress([0; 6])
+    }
+
+    fn can_transmit(&self) -> bool {
+        true
+    }
+
+    fn can_receive(&self) -> bool {
+        !self.queue.is_empty()
+    }
+
+    fn rx_queue_size(&self) -> usize {
+        QUEUE_SIZE
+    }
+
+    fn tx_queue_size(&self) -> usize {
+        QUEUE_SIZE
+    }
+
+    fn recycle_rx_buffer(&mut self, rx_buf: NetBufPtr) -> DevResult {
+        let buf = Self::take_buf(rx_buf);
+        self.recycle(buf);
+        Ok(())
+    }
+
+    fn recycle_tx_buffers(&mut self) -> DevResult {
+        // The buffers are moved to the queue on transmission.
+        Ok(())
+    }
+
+    fn transmit(&mut self, tx_buf: NetBufPtr) -> DevResult {
+        let len = tx_buf.packet_len();
+        let buf = Self::take_buf(tx_buf);
+        self.stats.tx_packets += 1;
+        self.stats.tx_bytes += len as u64;
+        if self.queue.len() >= QUEUE_SIZE {
+            self.stats.rx_overruns += 1;
+            self.recycle(buf);
+        } else {
+            self.queue.push_back((buf, len));
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self) -> DevResult<NetBufPtr> {
+        let (buf, len) = self.queue.pop_front().ok_or(DevError::Again)?;
+        self.stats.rx_packets += 1;
+        self.stats.rx_bytes += len as u64;
+        Ok(Self::leak_buf(buf, len))
+    }
+
+    fn alloc_tx_buffer(&mut self, size: usize) -> DevResult<NetBufPtr> {
+        if size > BUF_SIZE {
+            return Err(DevError::InvalidParam);
+        }
+        let buf = self.free.pop().unwrap_or_else(|| Box::new([0; BUF_SIZE]));
+        Ok(Self::leak_buf(buf, size))
+    }
+}
+
+impl NetIrqOps for LoopbackDev {
+    fn stats(&self) -> NetStats {
+        self.stats
+    }
+}
@@ -0,0 +1,65 @@
+//! Overriding the MAC address of the first NIC by `net-mac` in the platform
+//! config, or `MAC` when building with `make`.
+
+use axdriver_net::EthernetAddress;
+
+use crate::prelude::*;
+use crate::AxDeviceContainer;
+
+/// Parses a MAC address in the form `xx:xx:xx:xx:xx:xx`.
+fn parse(s: &str) -> Option<EthernetAddress> {
+    let mut mac = [0u8; 6];
+    let mut parts = s.split(':');
+    for b in mac.iter_mut() {
+        let part = parts.next().filter(|p| p.len() == 2)?;
+        *b = u8::from_str_radix(part, 16).ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(EthernetAddress(mac))
+}
+
+/// Returns the MAC address configured for the first NIC, if any.
+///
+/// # Panics
+///
+/// Panics if the configured address is malformed, or is a multicast or
+/// broadcast address.
+pub fn mac_override() -> Option<EthernetAddress> {
+    let s = axconfig::NET_MAC;
+    if s.is_empty() {
+        return None;
+    }
+    let Some(mac) = parse(s) else {
+        panic!("invalid MAC address {:?} in `net-mac`", s);
+    };
+    // The I/G bit is also set in the broadcast address.
+    if mac.0[0] & 1 != 0 {
+        panic!("MAC address {} in `net-mac` is not a unicast address", s);
+    }
+    Some(mac)
+}
+
+/// Programs the configured MAC address into the first NIC.
+///
+/// If the device cannot change its address, it is only used by the network
+/// stack, which relies on the NIC accepting all unicast packets.
+pub(crate) fn apply(net_devs: &mut AxDeviceContainer<AxNetDevice>) {
+    let Some(mac) = mac_override() else {
+        return;
+    };
+    let Some((name, dev)) = net_devs.iter_mut().next() else {
+        return;
+    };
+    match dev.set_mac_address(mac) {
+        Ok(()) => info!("  NIC {}: MAC address set to {}", name, mac),
+        Err(DevError::Unsupported) => {
+            warn!(
+                "  NIC {}: MAC address {} is only used in software",
+                name, mac
+            )
+        }
+        Err(e) => panic!("failed to set the MAC address of {}: {:?}", name, e),
+    }
+}
@@ -2,27 +2,123 @@
 
 #![allow(unused_macros)]
 
+/// Sets the probe priority of a driver, see [`DriverPriority`].
+///
+/// [`DriverPriority`]: crate::drivers::DriverPriority
+macro_rules! register_driver_priority {
+    ($driver_type:ty, $priority:expr) => {
+        impl crate::drivers::DriverPriority for $driver_type {
+            const PRIORITY: u8 = $priority;
+        }
+    };
+}
+
 macro_rules! register_net_driver {
     ($driver_type:ty, $device_type:ty) => {
+        register_net_driver!($driver_type, $device_type, priority = 0);
+    };
+    ($driver_type:ty, $device_type:ty, priority = $priority:expr) => {
         /// The unified type of the NIC devices.
         #[cfg(not(feature = "dyn"))]
         pub type AxNetDevice = $device_type;
+        register_driver_priority!($driver_type, $priority);
     };
 }
 
 macro_rules! register_block_driver {
     ($driver_type:ty, $device_type:ty) => {
-        /// The unified type of the NIC devices.
+        register_block_driver!($driver_type, $device_type, priority = 0);
+    };
+    ($driver_type:ty, $device_type:ty, priority = $priority:expr) => {
+        /// The type of the block storage devices created by the driver, which
+        /// are wrapped in [`BlockPartition`](crate::BlockPartition).
         #[cfg(not(feature = "dyn"))]
-        pub type AxBlockDevice = $device_type;
+        pub type AxRawBlockDevice = $device_type;
+        register_driver_priority!($driver_type, $priority);
     };
 }
 
 macro_rules! register_display_driver {
     ($driver_type:ty, $device_type:ty) => {
+        register_display_driver!($driver_type, $device_type, priority = 0);
+    };
+    ($driver_type:ty, $device_type:ty, priority = $priority:expr) => {
         /// The unified type of the NIC devices.
         #[cfg(not(feature = "dyn"))]
         pub type AxDisplayDevice = $device_type;
+        register_driver_priority!($driver_type, $priority);
+    };
+}
+
+macro_rules! register_char_driver {
+    ($driver_type:ty, $device_type:ty) => {
+        register_char_driver!($driver_type, $device_type, priority = 0);
+    };
+    ($driver_type:ty, $device_type:ty, priority = $priority:expr) => {
+        /// The unified type of the character devices.
+        #[cfg(not(feature = "dyn"))]
+        pub type AxCharDevice = $device_type;
+        register_driver_priority!($driver_type, $priority);
+    };
+}
+
+macro_rules! register_rng_driver {
+    ($driver_type:ty, $device_type:ty) => {
+        register_rng_driver!($driver_type, $device_type, priority = 0);
+    };
+    ($driver_type:ty, $device_type:ty, priority = $priority:expr) => {
+        /// The unified type of the random number generator devices.
+        #[cfg(not(feature = "dyn"))]
+        pub type AxRngDevice = $device_type;
+        register_driver_priority!($driver_type, $priority);
+    };
+}
+
+macro_rules! register_9p_driver {
+    ($driver_type:ty, $device_type:ty) => {
+        register_9p_driver!($driver_type, $device_type, priority = 0);
+    };
+    ($driver_type:ty, $device_type:ty, priority = $priority:expr) => {
+        /// The unified type of the 9P transport devices.
+        #[cfg(not(feature = "dyn"))]
+        pub type Ax9pDevice = $device_type;
+        register_driver_priority!($driver_type, $priority);
+    };
+}
+
+macro_rules! register_balloon_driver {
+    ($driver_type:ty, $device_type:ty) => {
+        register_balloon_driver!($driver_type, $device_type, priority = 0);
+    };
+    ($driver_type:ty, $device_type:ty, priority = $priority:expr) => {
+        /// The unified type of the memory balloon devices.
+        #[cfg(not(feature = "dyn"))]
+        pub type AxBalloonDevice = $device_type;
+        register_driver_priority!($driver_type, $priority);
+    };
+}
+
+macro_rules! register_input_driver {
+    ($driver_type:ty, $device_type:ty) => {
+        register_input_driver!($driver_type, $device_type, priority = 0);
+    };
+    ($driver_type:ty, $device_type:ty, priority = $priority:expr) => {
+        /// The unified type of the input devices.
+        #[cfg(not(feature = "dyn"))]
+        pub type AxInputDevice = $device_type;
+        register_driver_priority!($driver_type, $priority);
+    };
+}
+
+macro_rules! register_sound_driver {
+    ($driver_type:ty, $device_type:ty) => {
+        register_sound_driver!($driver_type, $device_type, priority = 0);
+    };
+    ($driver_type:ty, $device_type:ty, priority = $priority:expr) => {
+        /// The unified type of the sound devices.
+        #[cfg(not(feature = "dyn"))]
+        pub type AxSoundDevice = $device_type;
+        register_driver_priority!($driver_type, $priority);
     };
 }
 
@@ -49,6 +145,36 @@ macro_rules! for_each_drivers {
             type $drv_type = <virtio::VirtIoGpu as VirtIoDevMeta>::Driver;
             $code
         }
+        #[cfg(char_dev = "virtio-console")]
+        {
+            type $drv_type = <virtio::VirtIoConsole as VirtIoDevMeta>::Driver;
+            $code
+        }
+        #[cfg(rng_dev = "virtio-rng")]
+        {
+            type $drv_type = <virtio::VirtIoRng as VirtIoDevMeta>::Driver;
+            $code
+        }
+        #[cfg(_9p_dev = "virtio-9p")]
+        {
+            type $drv_type = <virtio::VirtIo9p as VirtIoDevMeta>::Driver;
+            $code
+        }
+        #[cfg(balloon_dev = "virtio-balloon")]
+        {
+            type $drv_type = <virtio::VirtIoBalloon as VirtIoDevMeta>::Driver;
+            $code
+        }
+        #[cfg(input_dev = "virtio-input")]
+        {
+            type $drv_type = <virtio::VirtIoInput as VirtIoDevMeta>::Driver;
+            $code
+        }
+        #[cfg(sound_dev = "virtio-sound")]
+        {
+            type $drv_type = <virtio::VirtIoSound as VirtIoDevMeta>::Driver;
+            $code
+        }
         #[cfg(block_dev = "ramdisk")]
         {
             type $drv_type = crate::drivers::RamDiskDriver;
@@ -59,6 +185,21 @@ macro_rules! for_each_drivers {
             type $drv_type = crate::drivers::BcmSdhciDriver;
             $code
         }
+        #[cfg(block_dev = "nvme")]
+        {
+            type $drv_type = crate::drivers::NvmeDriver;
+            $code
+        }
+        #[cfg(block_dev = "sdhci")]
+        {
+            type $drv_type = crate::drivers::SdhciDriver;
+            $code
+        }
+        #[cfg(block_dev = "usb-storage")]
+        {
+            type $drv_type = crate::drivers::UsbStorageDriver;
+            $code
+        }
         #[cfg(net_dev = "ixgbe")]
         {
             type $drv_type = crate::drivers::IxgbeDriver;
@@ -69,5 +210,20 @@ macro_rules! for_each_drivers {
             type $drv_type = crate::drivers::IgbDriver;
             $code
         }
+        #[cfg(net_dev = "e1000")]
+        {
+            type $drv_type = crate::drivers::E1000Driver;
+            $code
+        }
+        #[cfg(all(net_dev = "rtl8139", bus = "pci"))]
+        {
+            type $drv_type = crate::drivers::Rtl8139Driver;
+            $code
+        }
+        #[cfg(net_dev = "loopback")]
+        {
+            type $drv_type = crate::drivers::LoopbackDriver;
+            $code
+        }
     }};
 }
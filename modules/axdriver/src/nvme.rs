@@ -0,0 +1,474 @@
+//! Driver for NVMe (NVM Express) storage controllers.
+//!
+//! This first version uses an admin queue and one I/O queue pair, and polls
+//! for command completion. Only the first active namespace is exposed.
+
+use core::alloc::Layout;
+use core::time::Duration;
+
+use axdma::{dealloc_coherent, DMAInfo};
+use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
+use axdriver_block::BlockDriverOps;
+
+use crate::dma::alloc_coherent;
+use crate::ops::BlockDiscardOps;
+
+/// PCI class code of NVMe controllers (mass storage, non-volatile memory).
+pub const NVME_CLASS: u8 = 0x01;
+/// PCI subclass code of NVMe controllers.
+pub const NVME_SUBCLASS: u8 = 0x08;
+
+const PAGE_SIZE: usize = 4096;
+const QUEUE_SIZE: u16 = 64;
+const ADMIN_QID: u16 = 0;
+const IO_QID: u16 = 1;
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Controller registers.
+const REG_CAP: usize = 0x00;
+const REG_VS: usize = 0x08;
+const REG_INTMS: usize = 0x0c;
+const REG_CC: usize = 0x14;
+const REG_CSTS: usize = 0x1c;
+const REG_AQA: usize = 0x24;
+const REG_ASQ: usize = 0x28;
+const REG_ACQ: usize = 0x30;
+const REG_DOORBELL_BASE: usize = 0x1000;
+
+const CC_EN: u32 = 1 << 0;
+/// I/O submission queue entry size: 2^6 = 64 bytes.
+const CC_IOSQES: u32 = 6 << 16;
+/// I/O completion queue entry size: 2^4 = 16 bytes.
+const CC_IOCQES: u32 = 4 << 20;
+const CSTS_RDY: u32 = 1 << 0;
+const CSTS_CFS: u32 = 1 << 1;
+
+// Admin command opcodes.
+const ADMIN_CREATE_IO_SQ: u8 = 0x01;
+const ADMIN_CREATE_IO_CQ: u8 = 0x05;
+const ADMIN_IDENTIFY: u8 = 0x06;
+
+// NVM command opcodes.
+const NVM_FLUSH: u8 = 0x00;
+const NVM_WRITE: u8 = 0x01;
+const NVM_READ: u8 = 0x02;
+
+// Identify CNS values.
+const IDENTIFY_NAMESPACE: u32 = 0x00;
+const IDENTIFY_CONTROLLER: u32 = 0x01;
+const IDENTIFY_ACTIVE_NS_LIST: u32 = 0x02;
+
+/// Submission queue entry.
+#[repr(C)]
+#[allow(dead_code)]
+#[derive(Clone, Copy, Default)]
+struct Command {
+    cdw0: u32,
+    nsid: u32,
+    cdw2: u32,
+    cdw3: u32,
+    mptr: u64,
+    prp1: u64,
+    prp2: u64,
+    cdw10: u32,
+    cdw11: u32,
+    cdw12: u32,
+    cdw13: u32,
+    cdw14: u32,
+    cdw15: u32,
+}
+
+/// Completion queue entry.
+#[repr(C)]
+#[allow(dead_code)]
+#[derive(Clone, Copy, Default)]
+struct Completion {
+    dw0: u32,
+    dw1: u32,
+    sq_head: u16,
+    sq_id: u16,
+    cid: u16,
+    status: u16,
+}
+
+/// A coherent DMA region that is freed on drop.
+struct DmaRegion {
+    info: DMAInfo,
+    layout: Layout,
+}
+
+impl DmaRegion {
+    fn new(size: usize) -> DevResult<Self> {
+        let layout =
+            Layout::from_size_align(size, PAGE_SIZE).map_err(|_| DevError::InvalidParam)?;
+        let info = unsafe { alloc_coherent(layout)? };
+        unsafe { core::ptr::write_bytes(info.cpu_addr.as_ptr(), 0, size) };
+        Ok(Self { info, layout })
+    }
+
+    fn as_ptr<T>(&self) -> *mut T {
+        self.info.cpu_addr.as_ptr() as *mut T
+    }
+
+    fn bus_addr(&self) -> u64 {
+        self.info.bus_addr.as_u64()
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.as_ptr(), self.layout.size()) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl Drop for DmaRegion {
+    fn drop(&mut self) {
+        unsafe { dealloc_coherent(self.info, self.layout) };
+    }
+}
+
+/// A submission/completion queue pair.
+struct QueuePair {
+    qid: u16,
+    sq: DmaRegion,
+    cq: DmaRegion,
+    sq_tail: u16,
+    cq_head: u16,
+    cq_phase: bool,
+    next_cid: u16,
+}
+
+impl QueuePair {
+    fn new(qid: u16) -> DevResult<Self> {
+        Ok(Self {
+            qid,
+            sq: DmaRegion::new(QUEUE_SIZE as usize * core::mem::size_of::<Command>())?,
+            cq: DmaRegion::new(QUEUE_SIZE as usize * core::mem::size_of::<Completion>())?,
+            sq_tail: 0,
+            cq_head: 0,
+            cq_phase: true,
+            next_cid: 0,
+        })
+    }
+}
+
+/// The NVMe controller driver, exposing one namespace as a block device.
+pub struct NvmeDev {
+    mmio_base: usize,
+    doorbell_stride: usize,
+    admin: QueuePair,
+    io: QueuePair,
+    nsid: u32,
+    block_size: usize,
+    num_blocks: u64,
+    /// Bounce buffer of one page, used for all data transfers.
+    buffer: DmaRegion,
+}
+
+unsafe impl Send for NvmeDev {}
+unsafe impl Sync for NvmeDev {}
+
+impl NvmeDev {
+    /// Resets and initializes the controller whose registers are mapped at `mmio_base`.
+    pub fn init(mmio_base: usize) -> DevResult<Self> {
+        let mut dev = Self {
+            mmio_base,
+            doorbell_stride: 4,
+            admin: QueuePair::new(ADMIN_QID)?,
+            io: QueuePair::new(IO_QID)?,
+            nsid: 0,
+            block_size: 0,
+            num_blocks: 0,
+            buffer: DmaRegion::new(PAGE_SIZE)?,
+        };
+
+        let cap = dev.read_reg64(REG_CAP);
+        let max_entries = (cap & 0xffff) as u16 + 1;
+        if max_entries < QUEUE_SIZE {
+            warn!("nvme: queue size {} is not supported", QUEUE_SIZE);
+            return Err(DevError::Unsupported);
+        }
+        dev.doorbell_stride = 4 << ((cap >> 32) & 0xf);
+        // CAP.TO is in units of 500 milliseconds.
+        let ready_timeout = Duration::from_millis(((cap >> 24) & 0xff).max(1) * 500);
+        let vs = dev.read_reg(REG_VS);
+        info!("nvme: version {}.{}", vs >> 16, (vs >> 8) & 0xff);
+
+        // Disable the controller before configuring the admin queue.
+        dev.write_reg(REG_CC, dev.read_reg(REG_CC) & !CC_EN);
+        dev.wait_ready(false, ready_timeout)?;
+
+        // Polling mode, mask all interrupts.
+        dev.write_reg(REG_INTMS, u32::MAX);
+        let aqa = (QUEUE_SIZE as u32 - 1) << 16 | (QUEUE_SIZE as u32 - 1);
+        dev.write_reg(REG_AQA, aqa);
+        dev.write_reg64(REG_ASQ, dev.admin.sq.bus_addr());
+        dev.write_reg64(REG_ACQ, dev.admin.cq.bus_addr());
+        dev.write_reg(REG_CC, CC_IOSQES | CC_IOCQES | CC_EN);
+        dev.wait_ready(true, ready_timeout)?;
+
+        dev.identify_controller()?;
+        dev.create_io_queues()?;
+        dev.identify_namespace()?;
+        info!(
+            "nvme: namespace {}: {} blocks of {} bytes",
+            dev.nsid, dev.num_blocks, dev.block_size
+        );
+        Ok(dev)
+    }
+
+    fn read_reg(&self, reg: usize) -> u32 {
+        unsafe { ((self.mmio_base + reg) as *const u32).read_volatile() }
+    }
+
+    fn write_reg(&self, reg: usize, val: u32) {
+        unsafe { ((self.mmio_base + reg) as *mut u32).write_volatile(val) }
+    }
+
+    fn read_reg64(&self, reg: usize) -> u64 {
+        self.read_reg(reg) as u64 | (self.read_reg(reg + 4) as u64) << 32
+    }
+
+    fn write_reg64(&self, reg: usize, val: u64) {
+        self.write_reg(reg, val as u32);
+        self.write_reg(reg + 4, (val >> 32) as u32);
+    }
+
+    fn wait_ready(&self, ready: bool, timeout: Duration) -> DevResult {
+        let deadline = axhal::time::monotonic_time() + timeout;
+        loop {
+            let csts = self.read_reg(REG_CSTS);
+            if csts & CSTS_CFS != 0 {
+                error!("nvme: controller fatal status");
+                return Err(DevError::Io);
+            }
+            if (csts & CSTS_RDY != 0) == ready {
+                return Ok(());
+            }
+            if axhal::time::monotonic_time() > deadline {
+                error!("nvme: timed out waiting for CSTS.RDY = {}", ready as u8);
+                return Err(DevError::Io);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Submits a command to the admin (`io == false`) or I/O queue, and polls
+    /// until it completes.
+    fn submit_and_wait(&mut self, io: bool, mut cmd: Command) -> DevResult<Completion> {
+        let (mmio_base, stride) = (self.mmio_base, self.doorbell_stride);
+        let queue = if io { &mut self.io } else { &mut self.admin };
+
+        let cid = queue.next_cid;
+        queue.next_cid = queue.next_cid.wrapping_add(1);
+        cmd.cdw0 |= (cid as u32) << 16;
+        unsafe {
+            queue
+                .sq
+                .as_ptr::<Command>()
+                .add(queue.sq_tail as usize)
+                .write_volatile(cmd)
+        };
+        queue.sq_tail = (queue.sq_tail + 1) % QUEUE_SIZE;
+        let sq_doorbell = mmio_base + REG_DOORBELL_BASE + (2 * queue.qid as usize) * stride;
+        unsafe { (sq_doorbell as *mut u32).write_volatile(queue.sq_tail as u32) };
+
+        let deadline = axhal::time::monotonic_time() + COMMAND_TIMEOUT;
+        let cqe = loop {
+            let cqe = unsafe {
+                queue
+                    .cq
+                    .as_ptr::<Completion>()
+                    .add(queue.cq_head as usize)
+                    .read_volatile()
+            };
+            if (cqe.status & 1 != 0) == queue.cq_phase {
+                break cqe;
+            }
+            if axhal::time::monotonic_time() > deadline {
+                error!("nvme: command {:#x} timed out", cmd.cdw0 & 0xff);
+                return Err(DevError::Io);
+            }
+            core::hint::spin_loop();
+        };
+        queue.cq_head += 1;
+        if queue.cq_head == QUEUE_SIZE {
+            queue.cq_head = 0;
+            queue.cq_phase = !queue.cq_phase;
+        }
+        let cq_doorbell = mmio_base + REG_DOORBELL_BASE + (2 * queue.qid as usize + 1) * stride;
+        unsafe { (cq_doorbell as *mut u32).write_volatile(queue.cq_head as u32) };
+
+        let status = cqe.status >> 1;
+        if cqe.cid != cid {
+            error!("nvme: unexpected completion for command {}", cqe.cid);
+            return Err(DevError::BadState);
+        }
+        if status != 0 {
+            warn!(
+                "nvme: command {:#x} failed with status {:#x}",
+                cmd.cdw0 & 0xff,
+                status
+            );
+            return Err(DevError::Io);
+        }
+        Ok(cqe)
+    }
+
+    fn identify(&mut self, cns: u32, nsid: u32) -> DevResult {
+        let cmd = Command {
+            cdw0: ADMIN_IDENTIFY as u32,
+            nsid,
+            prp1: self.buffer.bus_addr(),
+            cdw10: cns,
+            ..Default::default()
+        };
+        self.submit_and_wait(false, cmd).map(|_| ())
+    }
+
+    fn identify_controller(&mut self) -> DevResult {
+        self.identify(IDENTIFY_CONTROLLER, 0)?;
+        let data = self.buffer.as_slice();
+        let model = core::str::from_utf8(&data[24..64]).unwrap_or("").trim();
+        info!("nvme: model {:?}", model);
+        Ok(())
+    }
+
+    fn identify_namespace(&mut self) -> DevResult {
+        self.identify(IDENTIFY_ACTIVE_NS_LIST, 0)?;
+        let data = self.buffer.as_slice();
+        self.nsid = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        if self.nsid == 0 {
+            warn!("nvme: no active namespace");
+            return Err(DevError::Unsupported);
+        }
+
+        self.identify(IDENTIFY_NAMESPACE, self.nsid)?;
+        let data = self.buffer.as_slice();
+        let nsze = u64::from_le_bytes(data[0..8].try_into().unwrap());
+        let flbas = (data[26] & 0xf) as usize;
+        let lbaf_offset = 128 + flbas * 4;
+        let lbaf = u32::from_le_bytes(data[lbaf_offset..lbaf_offset + 4].try_into().unwrap());
+        let block_size = 1usize << ((lbaf >> 16) & 0xff);
+        if block_size > PAGE_SIZE {
+            warn!("nvme: block size {} is not supported", block_size);
+            return Err(DevError::Unsupported);
+        }
+        self.num_blocks = nsze;
+        self.block_size = block_size;
+        Ok(())
+    }
+
+    fn create_io_queues(&mut self) -> DevResult {
+        let qsize = (QUEUE_SIZE as u32 - 1) << 16;
+        let cmd = Command {
+            cdw0: ADMIN_CREATE_IO_CQ as u32,
+            prp1: self.io.cq.bus_addr(),
+            cdw10: qsize | IO_QID as u32,
+            // Physically contiguous, interrupts disabled.
+            cdw11: 1,
+            ..Default::default()
+        };
+        self.submit_and_wait(false, cmd)?;
+        let cmd = Command {
+            cdw0: ADMIN_CREATE_IO_SQ as u32,
+            prp1: self.io.sq.bus_addr(),
+            cdw10: qsize | IO_QID as u32,
+            // Completion queue ID and physically contiguous.
+            cdw11: (IO_QID as u32) << 16 | 1,
+            ..Default::default()
+        };
+        self.submit_and_wait(false, cmd)?;
+        Ok(())
+    }
+
+    /// Reads or writes `count` blocks starting at `block_id` through the bounce buffer.
+    fn rw_blocks(&mut self, opcode: u8, block_id: u64, count: usize) -> DevResult {
+        let cmd = Command {
+            cdw0: opcode as u32,
+            nsid: self.nsid,
+            prp1: self.buffer.bus_addr(),
+            cdw10: block_id as u32,
+            cdw11: (block_id >> 32) as u32,
+            // Number of logical blocks, 0's based.
+            cdw12: count as u32 - 1,
+            ..Default::default()
+        };
+        self.submit_and_wait(true, cmd).map(|_| ())
+    }
+
+    fn check_request(&self, block_id: u64, len: usize) -> DevResult {
+        if len == 0 || len % self.block_size != 0 {
+            return Err(DevError::InvalidParam);
+        }
+        let end = block_id.checked_add((len / self.block_size) as u64);
+        match end {
+            Some(end) if end <= self.num_blocks => Ok(()),
+            _ => Err(DevError::InvalidParam),
+        }
+    }
+}
+
+impl BaseDriverOps for NvmeDev {
+    fn device_name(&self) -> &str {
+        "nvme"
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Block
+    }
+}
+
+impl BlockDriverOps for NvmeDev {
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> DevResult {
+        self.check_request(block_id, buf.len())?;
+        let mut block_id = block_id;
+        for chunk in buf.chunks_mut(PAGE_SIZE) {
+            let count = chunk.len() / self.block_size;
+            self.rw_blocks(NVM_READ, block_id, count)?;
+            chunk.copy_from_slice(&self.buffer.as_slice()[..chunk.len()]);
+            block_id += count as u64;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, block_id: u64, buf: &[u8]) -> DevResult {
+        self.check_request(block_id, buf.len())?;
+        let mut block_id = block_id;
+        for chunk in buf.chunks(PAGE_SIZE) {
+            let count = chunk.len() / self.block_size;
+            self.buffer.as_mut_slice()[..chunk.len()].copy_from_slice(chunk);
+            self.rw_blocks(NVM_WRITE, block_id, count)?;
+            block_id += count as u64;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> DevResult {
+        let cmd = Command {
+            cdw0: NVM_FLUSH as u32,
+            nsid: self.nsid,
+            ..Default::default()
+        };
+        self.submit_and_wait(true, cmd).map(|_| ())
+    }
+}
+
+impl BlockDiscardOps for NvmeDev {}
+
+impl Drop for NvmeDev {
+    fn drop(&mut self) {
+        // Disable the controller so that it no longer accesses the queues.
+        self.write_reg(REG_CC, self.read_reg(REG_CC) & !CC_EN);
+    }
+}
@@ -0,0 +1,439 @@
+//! Operation traits of the device categories that are not provided by
+//! [axdriver_crates](https://github.com/arceos-org/axdriver_crates).
+
+#![allow(unused_imports)]
+
+use crate::prelude::*;
+
+/// Operations to quiesce a device before it is released.
+///
+/// Dropping a device releases it, e.g., resets a VirtIO device and frees its
+/// queues. [`shutdown`](ShutdownOps::shutdown) should be called before that to
+/// finish the pending work.
+pub trait ShutdownOps {
+    /// Finishes the pending work of the device, e.g., flushes the write cache
+    /// of a block device. Calling it more than once is harmless.
+    fn shutdown(&mut self) -> DevResult {
+        Ok(())
+    }
+}
+
+/// Device categories of [`AxDeviceEnum`](crate::AxDeviceEnum).
+///
+/// It extends [`DeviceType`] with the categories that axdriver_crates does not
+/// have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AxDeviceType {
+    /// Block storage device.
+    Block,
+    /// Character device.
+    Char,
+    /// Network device.
+    Net,
+    /// Graphic display device.
+    Display,
+    /// Random number generator device.
+    Rng,
+    /// 9P transport device.
+    _9P,
+    /// Memory balloon device.
+    Balloon,
+    /// Input device.
+    Input,
+    /// Sound device.
+    Sound,
+}
+
+impl From<DeviceType> for AxDeviceType {
+    fn from(ty: DeviceType) -> Self {
+        match ty {
+            DeviceType::Block => Self::Block,
+            DeviceType::Char => Self::Char,
+            DeviceType::Net => Self::Net,
+            DeviceType::Display => Self::Display,
+        }
+    }
+}
+
+/// Packet and error counters of a NIC device.
+///
+/// The counters are monotonic since the device is probed, and are kept when
+/// the link goes down and up. Counters the device cannot report stay zero.
+#[cfg(feature = "net")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetStats {
+    /// Packets received.
+    pub rx_packets: u64,
+    /// Packets transmitted.
+    pub tx_packets: u64,
+    /// Bytes received, excluding the CRC.
+    pub rx_bytes: u64,
+    /// Bytes transmitted, excluding the CRC.
+    pub tx_bytes: u64,
+    /// Packets dropped on receive because no RX buffer was available.
+    pub rx_overruns: u64,
+    /// Transmissions rejected because the TX queue was full.
+    pub tx_queue_full: u64,
+    /// Packets received with a bad checksum or CRC.
+    pub rx_crc_errors: u64,
+}
+
+#[cfg(feature = "net")]
+impl core::ops::AddAssign for NetStats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.rx_packets += rhs.rx_packets;
+        self.tx_packets += rhs.tx_packets;
+        self.rx_bytes += rhs.rx_bytes;
+        self.tx_bytes += rhs.tx_bytes;
+        self.rx_overruns += rhs.rx_overruns;
+        self.tx_queue_full += rhs.tx_queue_full;
+        self.rx_crc_errors += rhs.rx_crc_errors;
+    }
+}
+
+/// Interrupt, link and statistics operations of NIC devices, in addition to
+/// [`NetDriverOps`].
+///
+/// Drivers without interrupt support use the default implementations, then
+/// the network stack keeps polling the device. Drivers that cannot read the
+/// link state report the link as always up, and drivers without counters
+/// report zeros.
+#[cfg(feature = "net")]
+pub trait NetIrqOps: NetDriverOps {
+    /// The IRQ number raised when packets are received, if any.
+    fn irq_num(&self) -> Option<usize> {
+        None
+    }
+
+    /// Enables or disables the interrupt on received packets.
+    fn enable_rx_interrupt(&mut self, _enable: bool) -> DevResult {
+        Err(DevError::Unsupported)
+    }
+
+    /// Acknowledges the interrupt on the device side, so that a level-triggered
+    /// IRQ line is deasserted.
+    ///
+    /// Returns whether the device has raised an interrupt since the last call.
+    fn ack_interrupt(&mut self) -> bool {
+        false
+    }
+
+    /// Whether the link is up, i.e., the cable is connected or the virtual
+    /// link is set up by the host.
+    fn link_up(&self) -> bool {
+        true
+    }
+
+    /// The negotiated link speed in Mbps, or `None` if it is unknown or the
+    /// link is down.
+    fn link_speed(&self) -> Option<u32> {
+        None
+    }
+
+    /// Programs the unicast MAC address that the device receives packets for,
+    /// after which [`NetDriverOps::mac_address`] returns the new address.
+    ///
+    /// Returns [`DevError::Unsupported`] if the address cannot be changed.
+    fn set_mac_address(&mut self, _mac: axdriver_net::EthernetAddress) -> DevResult {
+        Err(DevError::Unsupported)
+    }
+
+    /// Returns the packet and error counters of the device.
+    fn stats(&self) -> NetStats {
+        NetStats::default()
+    }
+}
+
+/// Discard operation of block devices, in addition to [`BlockDriverOps`].
+///
+/// Drivers of devices without discard support use the default
+/// implementation, which does nothing.
+#[cfg(feature = "block")]
+pub trait BlockDiscardOps: BlockDriverOps {
+    /// Tells the device that the given blocks are no longer used, so that the
+    /// storage behind them can be released, e.g., the clusters of a sparse
+    /// disk image on the host.
+    ///
+    /// The contents of the discarded blocks are undefined afterwards.
+    fn discard(&mut self, _blocks: core::ops::Range<u64>) -> DevResult {
+        Ok(())
+    }
+}
+
+/// Partial flush and double buffering operations of display devices, in
+/// addition to [`DisplayDriverOps`].
+///
+/// Single-buffered drivers use the default implementations, where the back
+/// buffer is the framebuffer itself, and presenting it is a whole-screen
+/// [`flush`](DisplayDriverOps::flush).
+#[cfg(feature = "display")]
+pub trait DisplayBufferOps: DisplayDriverOps {
+    /// Flushes a rectangle of the framebuffer to the screen.
+    ///
+    /// Returns [`DevError::InvalidParam`] if the rectangle is not within the
+    /// screen.
+    fn flush_region(&mut self, x: u32, y: u32, width: u32, height: u32) -> DevResult {
+        let info = self.info();
+        match (x.checked_add(width), y.checked_add(height)) {
+            (Some(right), Some(bottom)) if right <= info.width && bottom <= info.height => {
+                self.flush()
+            }
+            _ => Err(DevError::InvalidParam),
+        }
+    }
+
+    /// Gets the information of the back buffer, in which the next frame is
+    /// drawn.
+    fn back_buffer_info(&self) -> axdriver_display::DisplayInfo {
+        self.info()
+    }
+
+    /// Shows the back buffer on the screen. When it returns, the frame is on
+    /// the screen, and the front and back buffers are swapped.
+    fn present(&mut self) -> DevResult {
+        self.flush()
+    }
+}
+
+/// Operations that require a character device driver to implement.
+#[cfg(feature = "char")]
+pub trait CharDriverOps: BaseDriverOps {
+    /// Reads available bytes into `buf` without blocking.
+    ///
+    /// Returns the number of bytes read, which may be zero.
+    fn read(&mut self, buf: &mut [u8]) -> DevResult<usize>;
+
+    /// Writes bytes from `buf` to the device.
+    ///
+    /// Returns the number of bytes written.
+    fn write(&mut self, buf: &[u8]) -> DevResult<usize>;
+
+    /// Whether there is any byte ready to be read.
+    fn poll_readable(&mut self) -> bool;
+
+    /// Whether the device can accept more bytes to write.
+    fn poll_writable(&mut self) -> bool;
+}
+
+/// Operations that require a random number generator driver to implement.
+#[cfg(feature = "rng")]
+pub trait RngDriverOps: BaseDriverOps {
+    /// Fills `buf` with random bytes from the device.
+    ///
+    /// Returns the number of bytes filled, which may be less than the length
+    /// of `buf` if the device does not have enough entropy at the moment.
+    fn fill_bytes(&mut self, buf: &mut [u8]) -> DevResult<usize>;
+}
+
+/// Operations that require a 9P transport driver to implement.
+#[cfg(feature = "_9p")]
+pub trait _9pDriverOps: BaseDriverOps {
+    /// The tag used to identify the shared file system when mounting.
+    fn mount_tag(&self) -> &str;
+
+    /// The maximum message size, as negotiated by the last `Tversion`.
+    fn msize(&self) -> u32;
+
+    /// Sends a 9P request message and waits for the reply.
+    ///
+    /// `request` must contain exactly one message. The reply is written to
+    /// `response` and its length is returned. Fails if the reply is malformed
+    /// or its tag does not match the request.
+    fn send_with_recv(&mut self, request: &[u8], response: &mut [u8]) -> DevResult<usize>;
+}
+
+/// Operations that require a memory balloon driver to implement.
+#[cfg(feature = "balloon")]
+pub trait BalloonDriverOps: BaseDriverOps {
+    /// The number of 4K pages the host asks the balloon to hold.
+    fn target_pages(&self) -> usize;
+
+    /// The number of 4K pages currently held by the balloon.
+    fn num_pages(&self) -> usize;
+
+    /// Inflates or deflates the balloon towards the target.
+    ///
+    /// Returns the number of pages held afterwards, which is less than the
+    /// target if there is not enough free memory to inflate.
+    fn update(&mut self) -> DevResult<usize>;
+}
+
+/// An input event, in the format of Linux `evdev` events.
+#[cfg(feature = "input")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputEvent {
+    /// Event type, e.g. `EV_KEY` (1) or `EV_REL` (2).
+    pub ev_type: u16,
+    /// Event code, e.g. the key code for `EV_KEY`.
+    pub code: u16,
+    /// Event value, e.g. 1 for key press and 0 for key release.
+    pub value: u32,
+}
+
+/// Operations that require an input device driver to implement.
+#[cfg(feature = "input")]
+pub trait InputDriverOps: BaseDriverOps {
+    /// Moves the events received by the device into the driver buffer.
+    ///
+    /// The buffer is bounded, events that do not fit are dropped and counted
+    /// in [`dropped_events`](InputDriverOps::dropped_events).
+    fn poll_events(&mut self);
+
+    /// Pops the oldest buffered event, polling the device first.
+    fn read_event(&mut self) -> Option<InputEvent>;
+
+    /// The number of events dropped because the buffer was full.
+    fn dropped_events(&self) -> usize;
+
+    /// The IRQ number raised when events are received, if any.
+    ///
+    /// Its handler should call [`poll_events`](InputDriverOps::poll_events),
+    /// so that the events are moved out of the device queue before it fills
+    /// up. Without it, the events are only moved when they are read.
+    fn irq_num(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// PCM sample formats, numbered as in the VirtIO sound device specification.
+#[cfg(feature = "sound")]
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcmFormat {
+    /// Signed 8 bits.
+    S8 = 3,
+    /// Unsigned 8 bits.
+    U8 = 4,
+    /// Signed 16 bits, little endian.
+    S16 = 5,
+    /// Unsigned 16 bits, little endian.
+    U16 = 6,
+    /// Signed 32 bits, little endian.
+    S32 = 17,
+    /// Unsigned 32 bits, little endian.
+    U32 = 18,
+    /// 32-bit IEEE 754 floating point.
+    Float = 19,
+}
+
+/// PCM frame rates, numbered as in the VirtIO sound device specification.
+#[cfg(feature = "sound")]
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcmRate {
+    /// 8000 Hz.
+    Rate8000 = 1,
+    /// 11025 Hz.
+    Rate11025 = 2,
+    /// 16000 Hz.
+    Rate16000 = 3,
+    /// 22050 Hz.
+    Rate22050 = 4,
+    /// 32000 Hz.
+    Rate32000 = 5,
+    /// 44100 Hz.
+    Rate44100 = 6,
+    /// 48000 Hz.
+    Rate48000 = 7,
+    /// 96000 Hz.
+    Rate96000 = 10,
+}
+
+/// Direction of a PCM stream.
+#[cfg(feature = "sound")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcmDirection {
+    /// Playback.
+    Output,
+    /// Capture.
+    Input,
+}
+
+/// Information about a jack.
+#[cfg(feature = "sound")]
+#[derive(Debug, Clone, Copy)]
+pub struct JackInfo {
+    /// Whether a device is plugged into the jack.
+    pub connected: bool,
+    /// The HDA pin configuration default register.
+    pub hda_reg_defconf: u32,
+    /// The HDA pin capabilities register.
+    pub hda_reg_caps: u32,
+}
+
+/// Information about a PCM stream.
+#[cfg(feature = "sound")]
+#[derive(Debug, Clone, Copy)]
+pub struct PcmStreamInfo {
+    /// Whether the stream is for playback or capture.
+    pub direction: PcmDirection,
+    /// Supported formats, bit `n` is set if [`PcmFormat`] `n` is supported.
+    pub formats: u64,
+    /// Supported rates, bit `n` is set if [`PcmRate`] `n` is supported.
+    pub rates: u64,
+    /// Minimum number of channels.
+    pub channels_min: u8,
+    /// Maximum number of channels.
+    pub channels_max: u8,
+}
+
+/// Parameters of a PCM stream.
+#[cfg(feature = "sound")]
+#[derive(Debug, Clone, Copy)]
+pub struct PcmParams {
+    /// Size of the whole ring buffer in bytes.
+    pub buffer_bytes: u32,
+    /// Size of a period in bytes, i.e. the unit submitted to the device.
+    pub period_bytes: u32,
+    /// Number of channels.
+    pub channels: u8,
+    /// Sample format.
+    pub format: PcmFormat,
+    /// Frame rate.
+    pub rate: PcmRate,
+}
+
+/// Operations that require a sound device driver to implement.
+#[cfg(feature = "sound")]
+pub trait SoundDriverOps: BaseDriverOps {
+    /// The number of jacks.
+    fn num_jacks(&self) -> u32;
+
+    /// Gets the information of the given jack.
+    fn jack_info(&mut self, jack_id: u32) -> DevResult<JackInfo>;
+
+    /// The number of PCM streams.
+    fn num_streams(&self) -> u32;
+
+    /// Gets the information of the given PCM stream.
+    fn stream_info(&mut self, stream_id: u32) -> DevResult<PcmStreamInfo>;
+
+    /// Sets the parameters of a PCM stream.
+    fn set_params(&mut self, stream_id: u32, params: &PcmParams) -> DevResult;
+
+    /// Prepares a PCM stream after its parameters are set.
+    fn prepare(&mut self, stream_id: u32) -> DevResult;
+
+    /// Starts a prepared PCM stream.
+    fn start(&mut self, stream_id: u32) -> DevResult;
+
+    /// Stops a running PCM stream.
+    fn stop(&mut self, stream_id: u32) -> DevResult;
+
+    /// Releases the resources of a stopped PCM stream.
+    fn release(&mut self, stream_id: u32) -> DevResult;
+
+    /// Queues one period of frames for playback on an output stream.
+    ///
+    /// The length of `data` must be the period size of the stream. Returns
+    /// [`DevError::Again`] if the queue is full.
+    fn submit_period(&mut self, stream_id: u32, data: &[u8]) -> DevResult;
+
+    /// Collects the periods that have been played.
+    ///
+    /// Returns the number of periods completed since the last call, or
+    /// [`DevError::BadState`] once if all queued periods have been played
+    /// while a stream is running, i.e., an underrun happened. The stream keeps
+    /// running and more periods can be submitted after an underrun.
+    fn poll_completed(&mut self) -> DevResult<usize>;
+}
@@ -0,0 +1,458 @@
+//! MBR and GPT partition tables on block devices.
+//!
+//! After a block device is probed, its partition table is parsed, and each
+//! partition is registered as another block device after the disk. It is
+//! named after the disk and the partition number, like `vda1` in Linux, with
+//! a `p` in between as the disk names end with a digit, e.g., `virtio-blk0p1`.
+//!
+//! The disk and its partitions are all [`BlockPartition`]s sharing the disk,
+//! the one of the disk spans the whole disk.
+//!
+//! A GPT is used if the MBR has a protective entry (type `0xee`), then the
+//! other entries of a hybrid MBR are ignored. They are only used if both the
+//! primary and the backup GPT are corrupted. Logical partitions in extended
+//! MBR partitions are not supported.
+//!
+//! The LBAs in the partition tables are in 512-byte sectors. A GPT in blocks
+//! of the disk is also found if the disk has larger blocks. Partitions not
+//! aligned to the block size of the disk have 512-byte blocks, and partial
+//! blocks of the disk are accessed by read-modify-write.
+
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use core::ops::Range;
+
+use kspin::SpinNoIrq;
+
+use crate::prelude::*;
+use crate::AxDeviceContainer;
+
+const SECTOR_SIZE: usize = 512;
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const MBR_TABLE_OFFSET: usize = 446;
+const MBR_TYPE_EMPTY: u8 = 0x00;
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xee;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_MIN_HEADER_SIZE: usize = 92;
+const GPT_MIN_ENTRY_SIZE: usize = 128;
+/// Upper bound of the size of the entry array, to reject garbage headers.
+const GPT_MAX_ENTRIES_SIZE: usize = 1024 * 1024;
+
+/// The block device under the partitions.
+#[cfg(feature = "dyn")]
+type Disk = AxBlockDevice;
+#[cfg(not(feature = "dyn"))]
+type Disk = crate::drivers::AxRawBlockDevice;
+
+/// A contiguous range of a block device, either a partition or the whole
+/// disk.
+///
+/// Blocks are accessed on the disk shared by all partitions, with a lock.
+pub struct BlockPartition {
+    disk: Arc<SpinNoIrq<Disk>>,
+    name: String,
+    /// Byte offset on the disk.
+    offset: u64,
+    num_blocks: u64,
+    block_size: usize,
+    disk_block_size: usize,
+}
+
+/// A partition found in the partition table, in bytes.
+struct PartitionEntry {
+    number: usize,
+    start: u64,
+    size: u64,
+}
+
+impl BlockPartition {
+    /// Wraps a block device as a partition spanning the whole disk.
+    pub(crate) fn whole(disk: Disk) -> Self {
+        let block_size = disk.block_size();
+        Self {
+            name: String::from(disk.device_name()),
+            offset: 0,
+            num_blocks: disk.num_blocks(),
+            block_size,
+            disk_block_size: block_size,
+            disk: Arc::new(SpinNoIrq::new(disk)),
+        }
+    }
+
+    /// The byte offset of the partition on the disk.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Creates a partition at the byte offset `start` of the disk, clamped to
+    /// the end of the disk.
+    fn partition(&self, start: u64, size: u64) -> Option<Self> {
+        let disk_size = self.num_blocks * self.block_size as u64;
+        if start >= disk_size || start % SECTOR_SIZE as u64 != 0 {
+            return None;
+        }
+        let size = size.min(disk_size - start);
+        let block_size = if start % self.disk_block_size as u64 == 0 {
+            self.disk_block_size
+        } else {
+            SECTOR_SIZE
+        };
+        Some(Self {
+            disk: self.disk.clone(),
+            name: self.name.clone(),
+            offset: start,
+            num_blocks: size / block_size as u64,
+            block_size,
+            disk_block_size: self.disk_block_size,
+        })
+    }
+
+    /// Parses the partition table of the disk, and returns the partitions
+    /// with their numbers.
+    fn partitions(&self) -> Vec<(usize, Self)> {
+        let entries = parse_table(&mut self.disk.lock());
+        let mut parts = Vec::with_capacity(entries.len());
+        for entry in entries {
+            match self.partition(entry.start, entry.size) {
+                Some(part) => parts.push((entry.number, part)),
+                None => warn!(
+                    "partition {} of {} at byte {:#x} is out of the disk",
+                    entry.number, self.name, entry.start
+                ),
+            }
+        }
+        parts
+    }
+
+    /// Checks the request and returns its byte offset on the disk.
+    fn check_request(&self, block_id: u64, len: usize) -> DevResult<u64> {
+        if len % self.block_size != 0 {
+            return Err(DevError::InvalidParam);
+        }
+        match block_id.checked_add((len / self.block_size) as u64) {
+            Some(end) if end <= self.num_blocks => {
+                Ok(self.offset + block_id * self.block_size as u64)
+            }
+            _ => Err(DevError::InvalidParam),
+        }
+    }
+
+    fn is_disk_aligned(&self, pos: u64, len: usize) -> bool {
+        pos % self.disk_block_size as u64 == 0 && len % self.disk_block_size == 0
+    }
+}
+
+impl BaseDriverOps for BlockPartition {
+    fn device_name(&self) -> &str {
+        &self.name
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Block
+    }
+}
+
+impl BlockDriverOps for BlockPartition {
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> DevResult {
+        let pos = self.check_request(block_id, buf.len())?;
+        let mut disk = self.disk.lock();
+        if self.is_disk_aligned(pos, buf.len()) {
+            disk.read_block(pos / self.disk_block_size as u64, buf)
+        } else {
+            read_at(&mut disk, pos, buf)
+        }
+    }
+
+    fn write_block(&mut self, block_id: u64, buf: &[u8]) -> DevResult {
+        let pos = self.check_request(block_id, buf.len())?;
+        let mut disk = self.disk.lock();
+        if self.is_disk_aligned(pos, buf.len()) {
+            disk.write_block(pos / self.disk_block_size as u64, buf)
+        } else {
+            write_at(&mut disk, pos, buf)
+        }
+    }
+
+    fn flush(&mut self) -> DevResult {
+        self.disk.lock().flush()
+    }
+}
+
+impl BlockDiscardOps for BlockPartition {
+    fn discard(&mut self, blocks: Range<u64>) -> DevResult {
+        if blocks.start > blocks.end || blocks.end > self.num_blocks {
+            return Err(DevError::InvalidParam);
+        }
+        // Only the blocks of the disk within the range are discarded.
+        let (bs, disk_bs) = (self.block_size as u64, self.disk_block_size as u64);
+        let start = (self.offset + blocks.start * bs).div_ceil(disk_bs);
+        let end = (self.offset + blocks.end * bs) / disk_bs;
+        if start >= end {
+            return Ok(());
+        }
+        self.disk.lock().discard(start..end)
+    }
+}
+
+/// Reads bytes at the byte offset `pos` of the disk.
+fn read_at(disk: &mut Disk, pos: u64, buf: &mut [u8]) -> DevResult {
+    let bs = disk.block_size();
+    let mut block = vec![0u8; bs];
+    let mut done = 0;
+    while done < buf.len() {
+        let cur = pos + done as u64;
+        let (block_id, off) = (cur / bs as u64, (cur % bs as u64) as usize);
+        let n = (bs - off).min(buf.len() - done);
+        if n == bs {
+            disk.read_block(block_id, &mut buf[done..done + n])?;
+        } else {
+            disk.read_block(block_id, &mut block)?;
+            buf[done..done + n].copy_from_slice(&block[off..off + n]);
+        }
+        done += n;
+    }
+    Ok(())
+}
+
+/// Writes bytes at the byte offset `pos` of the disk. Partial blocks of the
+/// disk are read first.
+fn write_at(disk: &mut Disk, pos: u64, buf: &[u8]) -> DevResult {
+    let bs = disk.block_size();
+    let mut block = vec![0u8; bs];
+    let mut done = 0;
+    while done < buf.len() {
+        let cur = pos + done as u64;
+        let (block_id, off) = (cur / bs as u64, (cur % bs as u64) as usize);
+        let n = (bs - off).min(buf.len() - done);
+        if n == bs {
+            disk.write_block(block_id, &buf[done..done + n])?;
+        } else {
+            disk.read_block(block_id, &mut block)?;
+            block[off..off + n].copy_from_slice(&buf[done..done + n]);
+            disk.write_block(block_id, &block)?;
+        }
+        done += n;
+    }
+    Ok(())
+}
+
+fn le16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn le32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn le64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// CRC-32 (IEEE 802.3) as used by GPT.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Whether the sector is the boot sector of a FAT file system on the whole
+/// disk, which also ends with the MBR signature.
+fn is_fat_boot_sector(sector: &[u8]) -> bool {
+    let jump = (sector[0] == 0xeb && sector[2] == 0x90) || sector[0] == 0xe9;
+    jump && (sector[54..57] == *b"FAT" || sector[82..87] == *b"FAT32")
+        && le16(sector, 11) as usize % SECTOR_SIZE == 0
+}
+
+/// Parses the partition table of the disk. Returns an empty list if there is
+/// none.
+fn parse_table(disk: &mut Disk) -> Vec<PartitionEntry> {
+    let mut mbr = [0u8; SECTOR_SIZE];
+    if read_at(disk, 0, &mut mbr).is_err() || mbr[510..] != MBR_SIGNATURE {
+        return Vec::new();
+    }
+    if is_fat_boot_sector(&mbr) {
+        debug!("partition: FAT file system on the whole disk");
+        return Vec::new();
+    }
+
+    let slots: Vec<&[u8]> = mbr[MBR_TABLE_OFFSET..510].chunks(16).collect();
+    // The boot indicator is 0x00 or 0x80 in a valid MBR, otherwise the
+    // sector is likely a boot sector of another file system.
+    if slots.iter().any(|slot| slot[0] & 0x7f != 0) {
+        return Vec::new();
+    }
+    if slots.iter().any(|slot| slot[4] == MBR_TYPE_GPT_PROTECTIVE) {
+        if let Some(entries) = parse_gpt(disk) {
+            return entries;
+        }
+        warn!("partition: both GPTs are corrupted, using the MBR");
+    }
+
+    let mut entries = Vec::new();
+    for (i, slot) in slots.iter().enumerate() {
+        let (ty, start, count) = (slot[4], le32(slot, 8), le32(slot, 12));
+        if ty == MBR_TYPE_EMPTY || ty == MBR_TYPE_GPT_PROTECTIVE || count == 0 {
+            continue;
+        }
+        if MBR_TYPES_EXTENDED.contains(&ty) {
+            debug!("partition: skip extended partition {}", i + 1);
+            continue;
+        }
+        entries.push(PartitionEntry {
+            number: i + 1,
+            start: start as u64 * SECTOR_SIZE as u64,
+            size: count as u64 * SECTOR_SIZE as u64,
+        });
+    }
+    entries
+}
+
+/// Parses the primary GPT, or the backup one at the last LBA if the primary
+/// one is corrupted, in 512-byte sectors and then in blocks of the disk.
+fn parse_gpt(disk: &mut Disk) -> Option<Vec<PartitionEntry>> {
+    let disk_size = disk.num_blocks() * disk.block_size() as u64;
+    let mut lba_sizes = vec![SECTOR_SIZE];
+    if disk.block_size() > SECTOR_SIZE {
+        lba_sizes.push(disk.block_size());
+    }
+    for lba_size in lba_sizes {
+        let Some(last_lba) = (disk_size / lba_size as u64).checked_sub(1) else {
+            continue;
+        };
+        for header_lba in [1, last_lba] {
+            if let Some(entries) = parse_gpt_at(disk, lba_size, header_lba) {
+                if header_lba != 1 {
+                    warn!("partition: the primary GPT is corrupted, using the backup");
+                }
+                return Some(entries);
+            }
+        }
+    }
+    None
+}
+
+/// Parses the GPT with the header at `header_lba`, checking the CRCs.
+fn parse_gpt_at(disk: &mut Disk, lba_size: usize, header_lba: u64) -> Option<Vec<PartitionEntry>> {
+    let mut header = [0u8; SECTOR_SIZE];
+    read_at(disk, header_lba * lba_size as u64, &mut header).ok()?;
+    let header_size = le32(&header, 12) as usize;
+    if &header[..8] != GPT_SIGNATURE
+        || !(GPT_MIN_HEADER_SIZE..=SECTOR_SIZE).contains(&header_size)
+        || le64(&header, 24) != header_lba
+    {
+        return None;
+    }
+    let header_crc = le32(&header, 16);
+    header[16..20].fill(0);
+    if crc32(&header[..header_size]) != header_crc {
+        return None;
+    }
+
+    let entries_lba = le64(&header, 72);
+    let num_entries = le32(&header, 80) as usize;
+    let entry_size = le32(&header, 84) as usize;
+    let entries_size = num_entries.checked_mul(entry_size)?;
+    if entry_size < GPT_MIN_ENTRY_SIZE || entry_size % 8 != 0 || entries_size > GPT_MAX_ENTRIES_SIZE
+    {
+        return None;
+    }
+    let mut array = vec![0u8; entries_size];
+    read_at(disk, entries_lba.checked_mul(lba_size as u64)?, &mut array).ok()?;
+    if crc32(&array) != le32(&header, 88) {
+        return None;
+    }
+
+    let mut entries = Vec::new();
+    for (i, entry) in array.chunks(entry_size).enumerate() {
+        // An all-zero type GUID marks an unused entry.
+        if entry[..16].iter().all(|&b| b == 0) {
+            continue;
+        }
+        let (first, last) = (le64(entry, 32), le64(entry, 40));
+        if last < first {
+            continue;
+        }
+        entries.push(PartitionEntry {
+            number: i + 1,
+            start: first * lba_size as u64,
+            size: (last - first + 1) * lba_size as u64,
+        });
+    }
+    Some(entries)
+}
+
+/// Returns the name of a partition of the disk, e.g., `virtio-blk0p1`.
+pub fn partition_name(disk: &str, number: usize) -> String {
+    if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("{}p{}", disk, number)
+    } else {
+        format!("{}{}", disk, number)
+    }
+}
+
+/// Returns the partition number if `name` is a partition of the disk.
+pub fn partition_number(disk: &str, name: &str) -> Option<usize> {
+    let rest = name.strip_prefix(disk)?;
+    let rest = if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        rest.strip_prefix('p')?
+    } else {
+        rest
+    };
+    rest.parse().ok()
+}
+
+/// Adds a probed block device and its partitions into the container.
+pub(crate) fn add_disk(
+    devs: &mut AxDeviceContainer<AxBlockDevice>,
+    driver_name: &str,
+    dev: AxBlockDevice,
+) {
+    #[cfg(feature = "dyn")]
+    let disk = BlockPartition::whole(dev);
+    #[cfg(not(feature = "dyn"))]
+    let disk = dev;
+
+    let parts = disk.partitions();
+    let disk_name = devs.next_name(driver_name);
+    devs.push_named(disk_name.clone(), into_device(disk));
+    for (number, part) in parts {
+        let name = partition_name(&disk_name, number);
+        info!(
+            "  partition {}: [{:#x}, {:#x}), block size {}",
+            name,
+            part.offset,
+            part.offset + part.num_blocks * part.block_size as u64,
+            part.block_size
+        );
+        devs.push_named(name, into_device(part));
+    }
+}
+
+#[cfg(feature = "dyn")]
+fn into_device(part: BlockPartition) -> AxBlockDevice {
+    alloc::boxed::Box::new(part)
+}
+
+#[cfg(not(feature = "dyn"))]
+fn into_device(part: BlockPartition) -> AxBlockDevice {
+    part
+}
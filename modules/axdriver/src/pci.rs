@@ -0,0 +1,172 @@
+//! Listing of the functions on the PCI bus, e.g., to find out why a device is
+//! not probed.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use axdriver_pci::DeviceFunction;
+use kspin::SpinNoIrq;
+
+/// Type of a PCI base address register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PciBarKind {
+    /// I/O space.
+    Io,
+    /// 32-bit memory space.
+    Memory32,
+    /// 64-bit memory space, taking two slots.
+    Memory64,
+}
+
+/// An assigned base address register (BAR) of a PCI function.
+#[derive(Debug, Clone, Copy)]
+pub struct PciBar {
+    /// Index of the BAR, the lower slot for 64-bit BARs.
+    pub index: u8,
+    /// Type of the BAR.
+    pub kind: PciBarKind,
+    /// Whether the memory is prefetchable.
+    pub prefetchable: bool,
+    /// Bus address of the BAR.
+    pub address: u64,
+    /// Size of the BAR in bytes.
+    pub size: u64,
+}
+
+/// Information about a PCI function, as listed by [`enumerate`].
+#[derive(Debug, Clone)]
+pub struct PciDeviceInfo {
+    /// Bus number.
+    pub bus: u8,
+    /// Device number.
+    pub device: u8,
+    /// Function number.
+    pub function: u8,
+    /// Vendor ID.
+    pub vendor_id: u16,
+    /// Device ID.
+    pub device_id: u16,
+    /// Class code.
+    pub class: u8,
+    /// Subclass code.
+    pub subclass: u8,
+    /// Programming interface.
+    pub prog_if: u8,
+    /// Revision ID.
+    pub revision: u8,
+    /// Header type: 0 for a normal function, 1 for a PCI-to-PCI bridge and 2
+    /// for a CardBus bridge.
+    pub header_type: u8,
+    /// Whether the device has more than one function.
+    pub multifunction: bool,
+    /// Interrupt pin: 0 for none, 1 to 4 for INTA# to INTD#.
+    pub interrupt_pin: u8,
+    /// The bus behind a PCI-to-PCI bridge, if assigned.
+    pub secondary_bus: Option<u8>,
+    /// The assigned BARs of normal functions, as recorded when probing.
+    pub bars: Vec<PciBar>,
+    /// Name of the driver that claimed the function, if any.
+    pub probed_by: Option<String>,
+}
+
+impl fmt::Display for PciDeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:02x}:{:02x}.{} [{:04x}:{:04x}] class {:02x}{:02x}{:02x} rev {:02x}",
+            self.bus,
+            self.device,
+            self.function,
+            self.vendor_id,
+            self.device_id,
+            self.class,
+            self.subclass,
+            self.prog_if,
+            self.revision
+        )?;
+        if let Some(bus) = self.secondary_bus {
+            write!(f, ", bridge to bus {:02x}", bus)?;
+        }
+        if self.interrupt_pin != 0 {
+            write!(f, ", INT{}#", (b'A' + self.interrupt_pin - 1) as char)?;
+        }
+        match &self.probed_by {
+            Some(driver) => write!(f, ", driver {}", driver)?,
+            None => write!(f, ", no driver")?,
+        }
+        for bar in &self.bars {
+            let kind = match bar.kind {
+                PciBarKind::Io => "IO ",
+                PciBarKind::Memory32 => "MEM",
+                PciBarKind::Memory64 => "MEM 64bit",
+            };
+            write!(
+                f,
+                "\n  BAR {}: {} [{:#x}, {:#x}){}",
+                bar.index,
+                kind,
+                bar.address,
+                bar.address + bar.size,
+                if bar.prefetchable { " pref" } else { "" },
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// What is recorded about a function when probing.
+struct ProbeRecord {
+    bdf: DeviceFunction,
+    bars: Vec<PciBar>,
+    probed_by: Option<String>,
+}
+
+static PROBE_RECORDS: SpinNoIrq<Vec<ProbeRecord>> = SpinNoIrq::new(Vec::new());
+
+/// Records the BARs of a function after they are assigned.
+#[cfg(bus = "pci")]
+pub(crate) fn record_bars(bdf: DeviceFunction, bars: Vec<PciBar>) {
+    PROBE_RECORDS.lock().push(ProbeRecord {
+        bdf,
+        bars,
+        probed_by: None,
+    });
+}
+
+/// Records the driver that claimed a function.
+#[cfg(bus = "pci")]
+pub(crate) fn record_driver(bdf: DeviceFunction, driver: &str) {
+    if let Some(record) = PROBE_RECORDS.lock().iter_mut().find(|r| r.bdf == bdf) {
+        record.probed_by = Some(String::from(driver));
+    }
+}
+
+/// Walks the PCI hierarchy from bus 0 through the bridges, and lists all
+/// functions found, ordered by their addresses.
+///
+/// The functions are read again from the configuration space, but the BARs
+/// and drivers are as recorded when probing, as sizing the BARs would disturb
+/// the running devices. Returns an empty list if the devices are probed on
+/// the MMIO bus.
+pub fn enumerate() -> Vec<PciDeviceInfo> {
+    #[cfg(bus = "pci")]
+    let mut list = crate::bus::walk_pci_hierarchy();
+    #[cfg(not(bus = "pci"))]
+    let mut list: Vec<PciDeviceInfo> = Vec::new();
+
+    let records = PROBE_RECORDS.lock();
+    for info in list.iter_mut() {
+        let bdf = DeviceFunction {
+            bus: info.bus,
+            device: info.device,
+            function: info.function,
+        };
+        if let Some(record) = records.iter().find(|r| r.bdf == bdf) {
+            info.bars = record.bars.clone();
+            info.probed_by = record.probed_by.clone();
+        }
+    }
+    list.sort_by_key(|info| (info.bus, info.device, info.function));
+    list
+}
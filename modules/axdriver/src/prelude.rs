@@ -1,10 +1,39 @@
 //! Device driver prelude that includes some traits and types.
 
+pub use crate::ops::{AxDeviceType, ShutdownOps};
 pub use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
 
+#[cfg(feature = "balloon")]
+pub use {crate::ops::BalloonDriverOps, crate::structs::AxBalloonDevice};
 #[cfg(feature = "block")]
-pub use {crate::structs::AxBlockDevice, axdriver_block::BlockDriverOps};
+pub use {
+    crate::ops::BlockDiscardOps, crate::structs::AxBlockDevice, axdriver_block::BlockDriverOps,
+};
+#[cfg(feature = "char")]
+pub use {crate::ops::CharDriverOps, crate::structs::AxCharDevice};
 #[cfg(feature = "display")]
-pub use {crate::structs::AxDisplayDevice, axdriver_display::DisplayDriverOps};
+pub use {
+    crate::ops::DisplayBufferOps, crate::structs::AxDisplayDevice,
+    axdriver_display::DisplayDriverOps,
+};
+#[cfg(feature = "rng")]
+pub use {crate::ops::RngDriverOps, crate::structs::AxRngDevice};
+#[cfg(feature = "sound")]
+pub use {
+    crate::ops::SoundDriverOps,
+    crate::ops::{JackInfo, PcmDirection, PcmFormat, PcmParams, PcmRate, PcmStreamInfo},
+    crate::structs::AxSoundDevice,
+};
+#[cfg(feature = "_9p")]
+pub use {crate::ops::_9pDriverOps, crate::structs::Ax9pDevice};
+#[cfg(feature = "input")]
+pub use {
+    crate::ops::{InputDriverOps, InputEvent},
+    crate::structs::AxInputDevice,
+};
 #[cfg(feature = "net")]
-pub use {crate::structs::AxNetDevice, axdriver_net::NetDriverOps};
+pub use {
+    crate::ops::{NetIrqOps, NetStats},
+    crate::structs::AxNetDevice,
+    axdriver_net::NetDriverOps,
+};
@@ -0,0 +1,154 @@
+//! Initial contents of the RAM disk.
+//!
+//! The disk image is either linked into the kernel, by setting the
+//! `AX_RAMDISK_IMG` environment variable to its path at build time, or passed
+//! by QEMU with `-fw_cfg name=opt/arceos/ramdisk,file=<path>`. The linked
+//! image takes precedence. The size of the RAM disk is the image size rounded
+//! up to the block size.
+
+use axdriver_block::ramdisk::RamDisk;
+use axdriver_block::BlockDriverOps;
+
+const BLOCK_SIZE: usize = 512;
+/// Size of the RAM disk if no image is provided.
+const DEFAULT_SIZE: usize = 0x100_0000; // 16 MiB
+const FW_CFG_FILE_NAME: &str = "opt/arceos/ramdisk";
+
+/// The image linked into the kernel, if any.
+#[cfg(ramdisk_img)]
+static LINKED_IMAGE: Option<&[u8]> = Some(include_bytes!(env!("AX_RAMDISK_IMG")));
+#[cfg(not(ramdisk_img))]
+static LINKED_IMAGE: Option<&[u8]> = None;
+
+/// Creates the RAM disk and copies the initial image into it, if any.
+pub fn create() -> RamDisk {
+    if let Some(image) = LINKED_IMAGE {
+        info!("ramdisk: linked image of {} bytes", image.len());
+        let mut chunks = image.chunks(BLOCK_SIZE);
+        return load(image.len(), |buf| {
+            buf.copy_from_slice(chunks.next().unwrap())
+        });
+    }
+    if let Some(file) = fw_cfg::find_file(FW_CFG_FILE_NAME) {
+        info!("ramdisk: fw_cfg image of {} bytes", file.size);
+        file.select();
+        return load(file.size as usize, fw_cfg::read);
+    }
+    RamDisk::new(DEFAULT_SIZE)
+}
+
+/// Creates a RAM disk of `size` bytes rounded up to the block size, and fills
+/// it with `read`, which is called with consecutive chunks of the image.
+fn load(size: usize, mut read: impl FnMut(&mut [u8])) -> RamDisk {
+    let mut disk = RamDisk::new(size);
+    let mut buf = [0u8; BLOCK_SIZE];
+    let mut block_id = 0;
+    for offset in (0..size).step_by(BLOCK_SIZE) {
+        let len = BLOCK_SIZE.min(size - offset);
+        buf[len..].fill(0);
+        read(&mut buf[..len]);
+        disk.write_block(block_id, &buf)
+            .expect("failed to load the RAM disk image");
+        block_id += 1;
+    }
+    disk
+}
+
+/// Access to the QEMU firmware configuration interface.
+mod fw_cfg {
+    const SELECT_SIGNATURE: u16 = 0x0000;
+    const SELECT_FILE_DIR: u16 = 0x0019;
+    const SIGNATURE: &[u8; 4] = b"QEMU";
+
+    /// An entry of the fw_cfg file directory.
+    pub struct FwCfgFile {
+        pub size: u32,
+        select: u16,
+    }
+
+    impl FwCfgFile {
+        /// Selects the file, the following reads return its contents.
+        pub fn select(&self) {
+            select(self.select);
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn select(key: u16) {
+        const SELECTOR_PORT: u16 = 0x510;
+        unsafe {
+            core::arch::asm!("out dx, ax", in("dx") SELECTOR_PORT, in("ax") key,
+                options(nomem, nostack, preserves_flags));
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn read_byte() -> u8 {
+        const DATA_PORT: u16 = 0x511;
+        let val: u8;
+        unsafe {
+            core::arch::asm!("in al, dx", out("al") val, in("dx") DATA_PORT,
+                options(nomem, nostack, preserves_flags));
+        }
+        val
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn base() -> usize {
+        axhal::mem::phys_to_virt(axconfig::FW_CFG_PADDR.into()).as_usize()
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn select(key: u16) {
+        // The selector register is big-endian in the MMIO interface.
+        unsafe { ((base() + 8) as *mut u16).write_volatile(key.to_be()) }
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn read_byte() -> u8 {
+        unsafe { (base() as *const u8).read_volatile() }
+    }
+
+    /// Reads the next bytes of the selected item.
+    pub fn read(buf: &mut [u8]) {
+        buf.iter_mut().for_each(|b| *b = read_byte());
+    }
+
+    fn read_be32() -> u32 {
+        let mut buf = [0u8; 4];
+        read(&mut buf);
+        u32::from_be_bytes(buf)
+    }
+
+    fn is_present() -> bool {
+        if cfg!(not(target_arch = "x86_64")) && axconfig::FW_CFG_PADDR == 0 {
+            return false;
+        }
+        let mut signature = [0u8; 4];
+        select(SELECT_SIGNATURE);
+        read(&mut signature);
+        signature == *SIGNATURE
+    }
+
+    /// Looks up the file of the given name in the file directory.
+    pub fn find_file(name: &str) -> Option<FwCfgFile> {
+        if !is_present() {
+            return None;
+        }
+        select(SELECT_FILE_DIR);
+        let count = read_be32();
+        for _ in 0..count {
+            let mut entry = [0u8; 64];
+            read(&mut entry);
+            // size (4), select (2), reserved (2), and a NUL-terminated name (56).
+            let name_len = entry[8..].iter().position(|&b| b == 0).unwrap_or(56);
+            if entry[8..8 + name_len] == *name.as_bytes() {
+                return Some(FwCfgFile {
+                    size: u32::from_be_bytes(entry[0..4].try_into().unwrap()),
+                    select: u16::from_be_bytes(entry[4..6].try_into().unwrap()),
+                });
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,432 @@
+//! Driver for the Realtek RTL8139 fast Ethernet controller.
+//!
+//! The registers are accessed through the I/O BAR. Received packets are
+//! copied out of the continuous RX ring, so the driver can handle packets that
+//! wrap around its end.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use axdma::{dealloc_coherent, DMAInfo};
+use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
+use axdriver_net::{EthernetAddress, NetBufPtr, NetDriverOps};
+
+use crate::bus::IoBar;
+use crate::dma::alloc_coherent;
+use crate::ops::NetIrqOps;
+
+/// Realtek vendor ID.
+pub const REALTEK_VEND: u16 = 0x10ec;
+/// Device ID of the RTL8139.
+pub const REALTEK_8139: u16 = 0x8139;
+
+/// Length of the RX ring (8K), selected by `RCR.RBLEN = 00`.
+const RX_RING_LEN: usize = 8192;
+/// The RX ring needs 16 extra bytes for the packet header.
+const RX_RING_ALLOC: usize = RX_RING_LEN + 16;
+/// Number of buffers that received packets are copied into.
+const RX_BUF_NUM: usize = 64;
+/// Number of TX descriptors, fixed by the hardware.
+const TX_DESC_NUM: usize = 4;
+const BUF_SIZE: usize = 2048;
+/// Maximum size of a packet sent in one descriptor.
+const TX_MAX_SIZE: usize = 1792;
+
+// Register offsets in the I/O space.
+const REG_IDR0: u16 = 0x00;
+const REG_MAR0: u16 = 0x08;
+const REG_TSD0: u16 = 0x10;
+const REG_TSAD0: u16 = 0x20;
+const REG_RBSTART: u16 = 0x30;
+const REG_CR: u16 = 0x37;
+const REG_CAPR: u16 = 0x38;
+const REG_IMR: u16 = 0x3c;
+const REG_ISR: u16 = 0x3e;
+const REG_TCR: u16 = 0x40;
+const REG_RCR: u16 = 0x44;
+const REG_9346CR: u16 = 0x50;
+const REG_CONFIG1: u16 = 0x52;
+const REG_BMCR: u16 = 0x62;
+const REG_BMSR: u16 = 0x64;
+
+const CR_BUFE: u8 = 1 << 0;
+const CR_TE: u8 = 1 << 2;
+const CR_RE: u8 = 1 << 3;
+const CR_RST: u8 = 1 << 4;
+
+/// Unlocks the configuration registers, including `IDR0-5`, for writing.
+const CR9346_CONFIG_WRITE: u8 = 0b11 << 6;
+
+const BMCR_SPEED_100: u16 = 1 << 13;
+const BMSR_LINK: u16 = 1 << 2;
+
+const RCR_APM: u32 = 1 << 1;
+const RCR_AM: u32 = 1 << 2;
+const RCR_AB: u32 = 1 << 3;
+/// Max DMA burst size per RX DMA burst is unlimited.
+const RCR_MXDMA_UNLIMITED: u32 = 0b111 << 8;
+/// RX FIFO threshold: no threshold.
+const RCR_RXFTH_NONE: u32 = 0b111 << 13;
+
+/// Max DMA burst size per TX DMA burst is 1024 bytes.
+const TCR_MXDMA_1024: u32 = 0b110 << 8;
+const TCR_IFG_NORMAL: u32 = 0b11 << 24;
+
+const TSD_OWN: u32 = 1 << 13;
+const TSD_TUN: u32 = 1 << 14;
+const TSD_TOK: u32 = 1 << 15;
+const TSD_TABT: u32 = 1 << 30;
+
+const RX_STATUS_ROK: u16 = 1 << 0;
+
+/// Access to the device registers in the I/O BAR.
+#[derive(Clone, Copy)]
+struct IoPorts {
+    bar: IoBar,
+}
+
+impl IoPorts {
+    fn read8(&self, reg: u16) -> u8 {
+        self.bar.read_io(reg as usize)
+    }
+
+    fn read16(&self, reg: u16) -> u16 {
+        self.bar.read_io(reg as usize)
+    }
+
+    fn read32(&self, reg: u16) -> u32 {
+        self.bar.read_io(reg as usize)
+    }
+
+    fn write8(&self, reg: u16, val: u8) {
+        self.bar.write_io(reg as usize, val)
+    }
+
+    fn write16(&self, reg: u16, val: u16) {
+        self.bar.write_io(reg as usize, val)
+    }
+
+    fn write32(&self, reg: u16, val: u32) {
+        self.bar.write_io(reg as usize, val)
+    }
+}
+
+/// A coherent DMA region that is freed on drop.
+struct DmaRegion {
+    info: DMAInfo,
+    layout: Layout,
+}
+
+impl DmaRegion {
+    fn new(size: usize, align: usize) -> DevResult<Self> {
+        let layout = Layout::from_size_align(size, align).map_err(|_| DevError::InvalidParam)?;
+        // Within 4 GiB, as declared by the driver.
+        let info = unsafe { alloc_coherent(layout)? };
+        Ok(Self { info, layout })
+    }
+
+    fn ptr(&self, offset: usize) -> *mut u8 {
+        unsafe { self.info.cpu_addr.as_ptr().add(offset) }
+    }
+
+    fn bus_addr(&self, offset: usize) -> u32 {
+        (self.info.bus_addr.as_u64() + offset as u64) as u32
+    }
+
+    /// Finds the buffer index from a pointer to the start of a buffer.
+    fn buf_index(&self, ptr: *const u8, num: usize) -> DevResult<usize> {
+        let offset = (ptr as usize).wrapping_sub(self.info.cpu_addr.as_ptr() as usize);
+        if offset < num * BUF_SIZE && offset % BUF_SIZE == 0 {
+            Ok(offset / BUF_SIZE)
+        } else {
+            Err(DevError::InvalidParam)
+        }
+    }
+}
+
+impl Drop for DmaRegion {
+    fn drop(&mut self) {
+        unsafe { dealloc_coherent(self.info, self.layout) };
+    }
+}
+
+/// The RTL8139 NIC driver.
+pub struct Rtl8139Nic {
+    io: IoPorts,
+    mac: EthernetAddress,
+    rx_ring: DmaRegion,
+    /// Offset in the RX ring of the next packet to be read.
+    rx_offset: usize,
+    rx_bufs: DmaRegion,
+    rx_free: [usize; RX_BUF_NUM],
+    rx_num_free: usize,
+    tx_bufs: DmaRegion,
+    /// Whether a TX buffer is allocated or in flight.
+    tx_busy: [bool; TX_DESC_NUM],
+    /// The next descriptor to be used for transmission.
+    tx_next: usize,
+    /// The oldest descriptor not yet reclaimed.
+    tx_clean: usize,
+    tx_in_flight: usize,
+}
+
+unsafe impl Send for Rtl8139Nic {}
+unsafe impl Sync for Rtl8139Nic {}
+
+impl Rtl8139Nic {
+    /// Resets and initializes the NIC whose registers are in the I/O BAR `bar`.
+    pub fn init(bar: IoBar) -> DevResult<Self> {
+        let mut nic = Self {
+            io: IoPorts { bar },
+            mac: EthernetAddress([0; 6]),
+            rx_ring: DmaRegion::new(RX_RING_ALLOC, 16)?,
+            rx_offset: 0,
+            rx_bufs: DmaRegion::new(RX_BUF_NUM * BUF_SIZE, BUF_SIZE)?,
+            rx_free: [0; RX_BUF_NUM],
+            rx_num_free: RX_BUF_NUM,
+            tx_bufs: DmaRegion::new(TX_DESC_NUM * BUF_SIZE, BUF_SIZE)?,
+            tx_busy: [false; TX_DESC_NUM],
+            tx_next: 0,
+            tx_clean: 0,
+            tx_in_flight: 0,
+        };
+        for (i, buf) in nic.rx_free.iter_mut().enumerate() {
+            *buf = i;
+        }
+
+        let io = nic.io;
+        // Power on and software reset.
+        io.write8(REG_CONFIG1, 0);
+        io.write8(REG_CR, CR_RST);
+        while io.read8(REG_CR) & CR_RST != 0 {
+            core::hint::spin_loop();
+        }
+
+        let mut mac = [0; 6];
+        for (i, b) in mac.iter_mut().enumerate() {
+            *b = io.read8(REG_IDR0 + i as u16);
+        }
+        nic.mac = EthernetAddress(mac);
+
+        io.write32(REG_RBSTART, nic.rx_ring.bus_addr(0));
+        for i in 0..TX_DESC_NUM {
+            io.write32(REG_TSAD0 + 4 * i as u16, nic.tx_bufs.bus_addr(i * BUF_SIZE));
+        }
+        // Accept all multicast packets.
+        io.write32(REG_MAR0, u32::MAX);
+        io.write32(REG_MAR0 + 4, u32::MAX);
+        // Polling mode, mask all interrupts.
+        io.write16(REG_IMR, 0);
+        io.write16(REG_ISR, 0xffff);
+
+        io.write8(REG_CR, CR_RE | CR_TE);
+        // `RCR.WRAP` is left cleared so the hardware wraps packets to the
+        // start of the ring, which is handled in `receive`.
+        io.write32(
+            REG_RCR,
+            RCR_APM | RCR_AM | RCR_AB | RCR_MXDMA_UNLIMITED | RCR_RXFTH_NONE,
+        );
+        io.write32(REG_TCR, TCR_MXDMA_1024 | TCR_IFG_NORMAL);
+        io.write16(REG_CAPR, (RX_RING_LEN - 16) as u16);
+
+        info!("rtl8139: MAC {}", nic.mac);
+        Ok(nic)
+    }
+
+    /// Copies `len` bytes starting at `offset` out of the RX ring, wrapping
+    /// around its end if needed.
+    fn copy_from_rx_ring(&self, offset: usize, dst: &mut [u8]) {
+        let ring =
+            unsafe { core::slice::from_raw_parts(self.rx_ring.ptr(0) as *const u8, RX_RING_LEN) };
+        let offset = offset % RX_RING_LEN;
+        let first = dst.len().min(RX_RING_LEN - offset);
+        dst[..first].copy_from_slice(&ring[offset..offset + first]);
+        let rest = dst.len() - first;
+        dst[first..].copy_from_slice(&ring[..rest]);
+    }
+
+    fn read_rx_header(&self) -> (u16, u16) {
+        let mut hdr = [0u8; 4];
+        self.copy_from_rx_ring(self.rx_offset, &mut hdr);
+        (
+            u16::from_le_bytes([hdr[0], hdr[1]]),
+            u16::from_le_bytes([hdr[2], hdr[3]]),
+        )
+    }
+
+    /// Moves past the current packet and tells the hardware how far it has been read.
+    fn advance_rx(&mut self, len: usize) {
+        self.rx_offset = ((self.rx_offset + len + 4 + 3) & !3) % RX_RING_LEN;
+        // CAPR is offset by 16 bytes for unknown (hardware) reasons.
+        self.io.write16(
+            REG_CAPR,
+            ((self.rx_offset + RX_RING_LEN - 16) % RX_RING_LEN) as u16,
+        );
+    }
+
+    /// Resets the receiver after a broken packet.
+    fn reset_rx(&mut self) {
+        self.io.write8(REG_CR, CR_TE);
+        self.rx_offset = 0;
+        self.io.write32(REG_RBSTART, self.rx_ring.bus_addr(0));
+        self.io.write8(REG_CR, CR_RE | CR_TE);
+        self.io.write16(REG_CAPR, (RX_RING_LEN - 16) as u16);
+    }
+}
+
+impl BaseDriverOps for Rtl8139Nic {
+    fn device_name(&self) -> &str {
+        "rtl8139"
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Net
+    }
+}
+
+impl NetDriverOps for Rtl8139Nic {
+    fn mac_address(&self) -> EthernetAddress {
+        self.mac
+    }
+
+    fn can_transmit(&self) -> bool {
+        !self.tx_busy[self.tx_next]
+    }
+
+    fn can_receive(&self) -> bool {
+        self.rx_num_free > 0 && self.io.read8(REG_CR) & CR_BUFE == 0
+    }
+
+    fn rx_queue_size(&self) -> usize {
+        RX_BUF_NUM
+    }
+
+    fn tx_queue_size(&self) -> usize {
+        TX_DESC_NUM
+    }
+
+    fn recycle_rx_buffer(&mut self, rx_buf: NetBufPtr) -> DevResult {
+        let buf = self.rx_bufs.buf_index(rx_buf.raw_ptr(), RX_BUF_NUM)?;
+        self.rx_free[self.rx_num_free] = buf;
+        self.rx_num_free += 1;
+        Ok(())
+    }
+
+    fn recycle_tx_buffers(&mut self) -> DevResult {
+        while self.tx_in_flight > 0 {
+            let tsd = self.io.read32(REG_TSD0 + 4 * self.tx_clean as u16);
+            if tsd & (TSD_TOK | TSD_TUN | TSD_TABT) == 0 {
+                break;
+            }
+            if tsd & TSD_TOK == 0 {
+                warn!("rtl8139: transmit failed (TSD {:#x})", tsd);
+            }
+            self.tx_busy[self.tx_clean] = false;
+            self.tx_clean = (self.tx_clean + 1) % TX_DESC_NUM;
+            self.tx_in_flight -= 1;
+        }
+        Ok(())
+    }
+
+    fn transmit(&mut self, tx_buf: NetBufPtr) -> DevResult {
+        let idx = self.tx_bufs.buf_index(tx_buf.raw_ptr(), TX_DESC_NUM)?;
+        // Descriptors must be used in round-robin order.
+        if idx != self.tx_next {
+            return Err(DevError::BadState);
+        }
+        let len = tx_buf.packet_len().max(60);
+        if len > TX_MAX_SIZE {
+            // The buffer is not sent, so free the descriptor for the next one.
+            self.tx_busy[idx] = false;
+            return Err(DevError::InvalidParam);
+        }
+        // Pad short frames to the minimum Ethernet frame size.
+        unsafe {
+            core::ptr::write_bytes(
+                self.tx_bufs.ptr(idx * BUF_SIZE + tx_buf.packet_len()),
+                0,
+                len - tx_buf.packet_len(),
+            )
+        };
+        // Writing the size with `OWN` cleared starts the transmission.
+        self.io
+            .write32(REG_TSD0 + 4 * idx as u16, len as u32 & !TSD_OWN);
+        self.tx_next = (self.tx_next + 1) % TX_DESC_NUM;
+        self.tx_in_flight += 1;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> DevResult<NetBufPtr> {
+        if !self.can_receive() {
+            return Err(DevError::Again);
+        }
+        let (status, len) = self.read_rx_header();
+        let len = len as usize;
+        if status & RX_STATUS_ROK == 0 || !(64..=BUF_SIZE).contains(&len) {
+            warn!(
+                "rtl8139: bad RX packet (status {:#x}, len {}), resetting receiver",
+                status, len
+            );
+            self.reset_rx();
+            return Err(DevError::Again);
+        }
+
+        let buf = self.rx_free[self.rx_num_free - 1];
+        self.rx_num_free -= 1;
+        // The length includes the 4-byte CRC.
+        let pkt_len = len - 4;
+        let ptr = self.rx_bufs.ptr(buf * BUF_SIZE);
+        let dst = unsafe { core::slice::from_raw_parts_mut(ptr, pkt_len) };
+        self.copy_from_rx_ring(self.rx_offset + 4, dst);
+        self.advance_rx(len);
+
+        let ptr = unsafe { NonNull::new_unchecked(ptr) };
+        Ok(NetBufPtr::new(ptr, ptr, pkt_len))
+    }
+
+    fn alloc_tx_buffer(&mut self, size: usize) -> DevResult<NetBufPtr> {
+        if size > TX_MAX_SIZE {
+            return Err(DevError::InvalidParam);
+        }
+        if self.tx_busy[self.tx_next] {
+            return Err(DevError::NoMemory);
+        }
+        self.tx_busy[self.tx_next] = true;
+        let ptr = unsafe { NonNull::new_unchecked(self.tx_bufs.ptr(self.tx_next * BUF_SIZE)) };
+        Ok(NetBufPtr::new(ptr, ptr, size))
+    }
+}
+
+impl NetIrqOps for Rtl8139Nic {
+    fn link_up(&self) -> bool {
+        self.io.read16(REG_BMSR) & BMSR_LINK != 0
+    }
+
+    fn link_speed(&self) -> Option<u32> {
+        if !self.link_up() {
+            return None;
+        }
+        if self.io.read16(REG_BMCR) & BMCR_SPEED_100 != 0 {
+            Some(100)
+        } else {
+            Some(10)
+        }
+    }
+
+    fn set_mac_address(&mut self, mac: EthernetAddress) -> DevResult {
+        // `IDR0-5` only accept 32-bit writes.
+        let (io, m) = (self.io, mac.0);
+        io.write8(REG_9346CR, CR9346_CONFIG_WRITE);
+        io.write32(REG_IDR0, u32::from_le_bytes([m[0], m[1], m[2], m[3]]));
+        io.write32(REG_IDR0 + 4, u16::from_le_bytes([m[4], m[5]]) as u32);
+        io.write8(REG_9346CR, 0);
+        self.mac = mac;
+        Ok(())
+    }
+}
+
+impl Drop for Rtl8139Nic {
+    fn drop(&mut self) {
+        self.io.write8(REG_CR, 0);
+    }
+}
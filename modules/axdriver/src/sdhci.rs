@@ -0,0 +1,552 @@
+//! Driver for SD cards behind a standard SDHCI (SD Host Controller Interface)
+//! controller.
+//!
+//! Data is transferred by PIO through the buffer data port, and the driver
+//! polls for completion. Only one slot and SD memory cards are supported.
+
+use core::time::Duration;
+
+use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
+use axdriver_block::BlockDriverOps;
+
+use crate::ops::BlockDiscardOps;
+
+/// PCI class code of SD host controllers (base system peripheral).
+pub const SDHCI_CLASS: u8 = 0x08;
+/// PCI subclass code of SD host controllers.
+pub const SDHCI_SUBCLASS: u8 = 0x05;
+
+const BLOCK_SIZE: usize = 512;
+/// The block count register is 16 bits.
+const MAX_BLOCKS_PER_COMMAND: usize = u16::MAX as usize;
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(1);
+const DATA_TIMEOUT: Duration = Duration::from_secs(5);
+/// The card may take up to one second to power up.
+const POWER_UP_TIMEOUT: Duration = Duration::from_secs(1);
+
+const INIT_CLOCK_HZ: u32 = 400_000;
+const DEFAULT_SPEED_CLOCK_HZ: u32 = 25_000_000;
+
+// Host controller registers. They are only accessed in 32 bits, as some
+// controllers require.
+/// Block size (low 16 bits) and block count (high 16 bits).
+const REG_BLOCK: usize = 0x04;
+const REG_ARGUMENT: usize = 0x08;
+/// Transfer mode (low 16 bits) and command (high 16 bits).
+const REG_COMMAND: usize = 0x0c;
+const REG_RESPONSE: usize = 0x10;
+const REG_BUFFER_DATA: usize = 0x20;
+const REG_PRESENT_STATE: usize = 0x24;
+/// Host control 1 (bits 0..8) and power control (bits 8..16).
+const REG_HOST_CONTROL: usize = 0x28;
+/// Clock control (bits 0..16), timeout control (bits 16..24) and software
+/// reset (bits 24..32).
+const REG_CLOCK_CONTROL: usize = 0x2c;
+/// Normal (low 16 bits) and error (high 16 bits) interrupt status.
+const REG_INT_STATUS: usize = 0x30;
+const REG_INT_STATUS_ENABLE: usize = 0x34;
+const REG_INT_SIGNAL_ENABLE: usize = 0x38;
+const REG_CAPABILITIES: usize = 0x40;
+/// Host controller version in bits 16..24.
+const REG_VERSION: usize = 0xfc;
+
+const TM_BLOCK_COUNT_EN: u32 = 1 << 1;
+const TM_AUTO_CMD12: u32 = 1 << 2;
+const TM_READ: u32 = 1 << 4;
+const TM_MULTI_BLOCK: u32 = 1 << 5;
+
+const CMD_RESP_NONE: u32 = 0;
+const CMD_RESP_136: u32 = 1;
+const CMD_RESP_48: u32 = 2;
+const CMD_RESP_48_BUSY: u32 = 3;
+const CMD_CRC_CHECK: u32 = 1 << 3;
+const CMD_INDEX_CHECK: u32 = 1 << 4;
+const CMD_DATA: u32 = 1 << 5;
+
+const PS_CMD_INHIBIT: u32 = 1 << 0;
+const PS_DAT_INHIBIT: u32 = 1 << 1;
+const PS_CARD_INSERTED: u32 = 1 << 16;
+
+const HC_DATA_4BIT: u32 = 1 << 1;
+const PWR_ON: u32 = 1 << 8;
+const PWR_330: u32 = 0b111 << 9;
+
+const CLK_INTERNAL_EN: u32 = 1 << 0;
+const CLK_INTERNAL_STABLE: u32 = 1 << 1;
+const CLK_SD_EN: u32 = 1 << 2;
+/// Data timeout of TMCLK * 2^27.
+const TIMEOUT_MAX: u32 = 0xe << 16;
+const RESET_ALL: u32 = 1 << 24;
+const RESET_CMD: u32 = 1 << 25;
+const RESET_DAT: u32 = 1 << 26;
+
+const INT_CMD_COMPLETE: u32 = 1 << 0;
+const INT_XFER_COMPLETE: u32 = 1 << 1;
+const INT_BUF_WRITE_READY: u32 = 1 << 4;
+const INT_BUF_READ_READY: u32 = 1 << 5;
+const INT_ERROR: u32 = 1 << 15;
+
+// SD card commands.
+const SD_GO_IDLE_STATE: u8 = 0;
+const SD_ALL_SEND_CID: u8 = 2;
+const SD_SEND_RELATIVE_ADDR: u8 = 3;
+const SD_SELECT_CARD: u8 = 7;
+const SD_SEND_IF_COND: u8 = 8;
+const SD_SEND_CSD: u8 = 9;
+const SD_SET_BLOCKLEN: u8 = 16;
+const SD_READ_SINGLE_BLOCK: u8 = 17;
+const SD_READ_MULTIPLE_BLOCK: u8 = 18;
+const SD_WRITE_BLOCK: u8 = 24;
+const SD_WRITE_MULTIPLE_BLOCK: u8 = 25;
+const SD_APP_CMD: u8 = 55;
+// Application specific commands, preceded by `SD_APP_CMD`.
+const SD_APP_SET_BUS_WIDTH: u8 = 6;
+const SD_APP_SEND_OP_COND: u8 = 41;
+
+/// Supplied voltage of 2.7-3.6V and the check pattern of `SD_SEND_IF_COND`.
+const IF_COND_PATTERN: u32 = 0x1aa;
+/// Supported voltage window of 2.7-3.6V.
+const OCR_VOLTAGE_WINDOW: u32 = 0x00ff_8000;
+/// Card capacity status: SDHC or SDXC.
+const OCR_CCS: u32 = 1 << 30;
+/// Cleared while the card is powering up.
+const OCR_POWER_UP_DONE: u32 = 1 << 31;
+
+/// Response types of SD commands.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Response {
+    None,
+    R1,
+    R1b,
+    R2,
+    R3,
+    R6,
+    R7,
+}
+
+impl Response {
+    fn command_flags(self) -> u32 {
+        match self {
+            Self::None => CMD_RESP_NONE,
+            Self::R1 | Self::R6 | Self::R7 => CMD_RESP_48 | CMD_CRC_CHECK | CMD_INDEX_CHECK,
+            Self::R1b => CMD_RESP_48_BUSY | CMD_CRC_CHECK | CMD_INDEX_CHECK,
+            Self::R2 => CMD_RESP_136 | CMD_CRC_CHECK,
+            Self::R3 => CMD_RESP_48,
+        }
+    }
+}
+
+/// Deviations of a controller from the specification.
+#[derive(Clone, Copy, Default)]
+pub struct SdhciQuirks {
+    /// Base clock frequency in Hz, for controllers that report none or a
+    /// wrong one in the capabilities register.
+    pub base_clock: Option<u32>,
+    /// The card inserted bit of the present state register is unreliable,
+    /// and a card is assumed to be present.
+    pub broken_card_detect: bool,
+}
+
+/// The SDHCI controller driver, exposing the SD card in its first slot as a
+/// block device.
+pub struct SdhciDev {
+    mmio_base: usize,
+    version: u32,
+    /// Base clock frequency in Hz.
+    base_clock: u32,
+    /// Relative card address.
+    rca: u32,
+    /// SDHC and SDXC cards are addressed in blocks, SDSC cards in bytes.
+    high_capacity: bool,
+    num_blocks: u64,
+}
+
+impl SdhciDev {
+    /// Resets the controller whose registers are mapped at `mmio_base`, and
+    /// initializes the inserted card.
+    #[cfg_attr(not(feature = "sdhci"), allow(dead_code))]
+    pub fn init(mmio_base: usize) -> DevResult<Self> {
+        Self::init_with_quirks(mmio_base, SdhciQuirks::default())
+    }
+
+    /// Like [`SdhciDev::init`], but works around the `quirks` of the
+    /// controller.
+    pub fn init_with_quirks(mmio_base: usize, quirks: SdhciQuirks) -> DevResult<Self> {
+        let mut dev = Self {
+            mmio_base,
+            version: 0,
+            base_clock: 0,
+            rca: 0,
+            high_capacity: false,
+            num_blocks: 0,
+        };
+        dev.version = (dev.read_reg(REG_VERSION) >> 16) & 0xff;
+        info!("sdhci: host controller version {}.00", dev.version + 1);
+
+        dev.reset(RESET_ALL)?;
+        if !quirks.broken_card_detect && dev.read_reg(REG_PRESENT_STATE) & PS_CARD_INSERTED == 0 {
+            warn!("sdhci: no card inserted");
+            return Err(DevError::Unsupported);
+        }
+
+        // The base clock is 8 bits since version 3.00, 6 bits before.
+        let caps = dev.read_reg(REG_CAPABILITIES);
+        let mask = if dev.version >= 2 { 0xff } else { 0x3f };
+        dev.base_clock = quirks
+            .base_clock
+            .unwrap_or(((caps >> 8) & mask) * 1_000_000);
+        if dev.base_clock == 0 {
+            warn!("sdhci: base clock frequency is unknown");
+            return Err(DevError::Unsupported);
+        }
+
+        // Polling mode, report all status bits but signal no interrupts.
+        dev.write_reg(REG_INT_STATUS_ENABLE, u32::MAX);
+        dev.write_reg(REG_INT_SIGNAL_ENABLE, 0);
+
+        // Select 3.3V before turning on the bus power.
+        let hc = dev.read_reg(REG_HOST_CONTROL) & !(0xff << 8);
+        dev.write_reg(REG_HOST_CONTROL, hc | PWR_330);
+        dev.write_reg(REG_HOST_CONTROL, hc | PWR_330 | PWR_ON);
+        dev.set_clock(INIT_CLOCK_HZ)?;
+
+        dev.init_card()?;
+        dev.set_clock(DEFAULT_SPEED_CLOCK_HZ)?;
+        let kind = if dev.high_capacity {
+            "SDHC/SDXC"
+        } else {
+            "SDSC"
+        };
+        info!(
+            "sdhci: {} card, {} blocks of {} bytes",
+            kind, dev.num_blocks, BLOCK_SIZE
+        );
+        Ok(dev)
+    }
+
+    fn read_reg(&self, reg: usize) -> u32 {
+        unsafe { ((self.mmio_base + reg) as *const u32).read_volatile() }
+    }
+
+    fn write_reg(&self, reg: usize, val: u32) {
+        unsafe { ((self.mmio_base + reg) as *mut u32).write_volatile(val) }
+    }
+
+    /// Polls until `cond` holds, or fails after `timeout`.
+    fn wait_until(&self, timeout: Duration, what: &str, cond: impl Fn(&Self) -> bool) -> DevResult {
+        let deadline = axhal::time::monotonic_time() + timeout;
+        while !cond(self) {
+            if axhal::time::monotonic_time() > deadline {
+                error!("sdhci: timed out waiting for {}", what);
+                return Err(DevError::Io);
+            }
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    fn reset(&self, mask: u32) -> DevResult {
+        let ctrl = self.read_reg(REG_CLOCK_CONTROL) & !(RESET_ALL | RESET_CMD | RESET_DAT);
+        self.write_reg(REG_CLOCK_CONTROL, ctrl | mask);
+        // The reset bits are cleared by the controller when it is done.
+        self.wait_until(COMMAND_TIMEOUT, "reset", |dev| {
+            dev.read_reg(REG_CLOCK_CONTROL) & mask == 0
+        })
+    }
+
+    /// Sets the SD clock to the highest frequency not above `hz`.
+    fn set_clock(&self, hz: u32) -> DevResult {
+        let div_bits = if self.version >= 2 {
+            // 10-bit divisor N, the SD clock is base / 2N, or base if N = 0.
+            let n = self.base_clock.div_ceil(2 * hz).min(0x3ff);
+            (n & 0xff) << 8 | (n >> 8) << 6
+        } else {
+            // 8-bit divisor N that is a power of 2, the SD clock is base / 2N,
+            // or base if N = 0.
+            let mut n = 0u32;
+            while n < 0x80 && self.base_clock / (2 * n).max(1) > hz {
+                n = (2 * n).max(1);
+            }
+            n << 8
+        };
+        self.write_reg(REG_CLOCK_CONTROL, 0);
+        self.write_reg(REG_CLOCK_CONTROL, TIMEOUT_MAX | div_bits | CLK_INTERNAL_EN);
+        self.wait_until(COMMAND_TIMEOUT, "internal clock", |dev| {
+            dev.read_reg(REG_CLOCK_CONTROL) & CLK_INTERNAL_STABLE != 0
+        })?;
+        self.write_reg(
+            REG_CLOCK_CONTROL,
+            TIMEOUT_MAX | div_bits | CLK_INTERNAL_EN | CLK_SD_EN,
+        );
+        Ok(())
+    }
+
+    /// Waits for any of the `mask` bits in the interrupt status, and clears
+    /// them. Resets the command and data lines on errors.
+    fn wait_int(&self, mask: u32, timeout: Duration) -> DevResult {
+        let deadline = axhal::time::monotonic_time() + timeout;
+        loop {
+            let status = self.read_reg(REG_INT_STATUS);
+            if status & INT_ERROR != 0 {
+                debug!("sdhci: error interrupt status {:#x}", status >> 16);
+                self.write_reg(REG_INT_STATUS, status);
+                self.reset(RESET_CMD | RESET_DAT)?;
+                return Err(DevError::Io);
+            }
+            if status & mask != 0 {
+                self.write_reg(REG_INT_STATUS, status & mask);
+                return Ok(());
+            }
+            if axhal::time::monotonic_time() > deadline {
+                error!("sdhci: timed out waiting for interrupt status {:#x}", mask);
+                self.reset(RESET_CMD | RESET_DAT)?;
+                return Err(DevError::Io);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Sends a command and waits for its response, returns the first word of
+    /// the response. `mode` is the transfer mode of commands with data.
+    fn send_command(
+        &self,
+        index: u8,
+        arg: u32,
+        resp: Response,
+        mode: Option<u32>,
+    ) -> DevResult<u32> {
+        let mut inhibit = PS_CMD_INHIBIT;
+        if mode.is_some() || resp == Response::R1b {
+            inhibit |= PS_DAT_INHIBIT;
+        }
+        self.wait_until(COMMAND_TIMEOUT, "command inhibit", |dev| {
+            dev.read_reg(REG_PRESENT_STATE) & inhibit == 0
+        })?;
+
+        let mut cmd = (index as u32) << 8 | resp.command_flags();
+        if mode.is_some() {
+            cmd |= CMD_DATA;
+        }
+        self.write_reg(REG_INT_STATUS, u32::MAX);
+        self.write_reg(REG_ARGUMENT, arg);
+        // Writing the command register issues the command.
+        self.write_reg(REG_COMMAND, cmd << 16 | mode.unwrap_or(0));
+        self.wait_int(INT_CMD_COMPLETE, COMMAND_TIMEOUT)
+            .inspect_err(|_| debug!("sdhci: CMD{} failed", index))?;
+        if resp == Response::R1b {
+            self.wait_int(INT_XFER_COMPLETE, DATA_TIMEOUT)?;
+        }
+        Ok(self.read_reg(REG_RESPONSE))
+    }
+
+    fn send_app_command(&self, index: u8, arg: u32, resp: Response) -> DevResult<u32> {
+        self.send_command(SD_APP_CMD, self.rca << 16, Response::R1, None)?;
+        self.send_command(index, arg, resp, None)
+    }
+
+    /// Reads the 136-bit response, without the CRC byte.
+    fn long_response(&self) -> [u32; 4] {
+        core::array::from_fn(|i| self.read_reg(REG_RESPONSE + i * 4))
+    }
+
+    fn init_card(&mut self) -> DevResult {
+        self.send_command(SD_GO_IDLE_STATE, 0, Response::None, None)?;
+
+        // Only cards of version 2.00 or later respond to `SD_SEND_IF_COND`.
+        let v2 = match self.send_command(SD_SEND_IF_COND, IF_COND_PATTERN, Response::R7, None) {
+            Ok(resp) if resp & 0xfff == IF_COND_PATTERN => true,
+            Ok(_) => {
+                warn!("sdhci: card does not support 3.3V");
+                return Err(DevError::Unsupported);
+            }
+            Err(_) => false,
+        };
+
+        let mut op_cond = OCR_VOLTAGE_WINDOW;
+        if v2 {
+            op_cond |= OCR_CCS;
+        }
+        let deadline = axhal::time::monotonic_time() + POWER_UP_TIMEOUT;
+        let ocr = loop {
+            let ocr = self.send_app_command(SD_APP_SEND_OP_COND, op_cond, Response::R3)?;
+            if ocr & OCR_POWER_UP_DONE != 0 {
+                break ocr;
+            }
+            if axhal::time::monotonic_time() > deadline {
+                error!("sdhci: timed out waiting for the card to power up");
+                return Err(DevError::Io);
+            }
+            axhal::time::busy_wait(Duration::from_millis(10));
+        };
+        self.high_capacity = ocr & OCR_CCS != 0;
+
+        self.send_command(SD_ALL_SEND_CID, 0, Response::R2, None)?;
+        self.rca = self.send_command(SD_SEND_RELATIVE_ADDR, 0, Response::R6, None)? >> 16;
+        self.send_command(SD_SEND_CSD, self.rca << 16, Response::R2, None)?;
+        self.num_blocks = csd_num_blocks(&self.long_response())?;
+
+        self.send_command(SD_SELECT_CARD, self.rca << 16, Response::R1b, None)?;
+        if !self.high_capacity {
+            self.send_command(SD_SET_BLOCKLEN, BLOCK_SIZE as u32, Response::R1, None)?;
+        }
+
+        // Switch both the card and the controller to the 4-bit bus.
+        self.send_app_command(SD_APP_SET_BUS_WIDTH, 2, Response::R1)?;
+        let hc = self.read_reg(REG_HOST_CONTROL);
+        self.write_reg(REG_HOST_CONTROL, hc | HC_DATA_4BIT);
+        Ok(())
+    }
+
+    /// Issues a read or write command of `count` blocks starting at `block_id`.
+    fn start_transfer(&self, read: bool, block_id: u64, count: usize) -> DevResult {
+        self.write_reg(REG_BLOCK, (count as u32) << 16 | BLOCK_SIZE as u32);
+        let mut mode = TM_BLOCK_COUNT_EN;
+        if read {
+            mode |= TM_READ;
+        }
+        let index = if count > 1 {
+            // Let the controller stop the transmission after the last block.
+            mode |= TM_MULTI_BLOCK | TM_AUTO_CMD12;
+            if read {
+                SD_READ_MULTIPLE_BLOCK
+            } else {
+                SD_WRITE_MULTIPLE_BLOCK
+            }
+        } else if read {
+            SD_READ_SINGLE_BLOCK
+        } else {
+            SD_WRITE_BLOCK
+        };
+        let addr = if self.high_capacity {
+            block_id
+        } else {
+            block_id * BLOCK_SIZE as u64
+        };
+        self.send_command(index, addr as u32, Response::R1, Some(mode))
+            .map(|_| ())
+    }
+
+    fn read_chunk(&self, block_id: u64, buf: &mut [u8]) -> DevResult {
+        self.start_transfer(true, block_id, buf.len() / BLOCK_SIZE)?;
+        for block in buf.chunks_exact_mut(BLOCK_SIZE) {
+            self.wait_int(INT_BUF_READ_READY, DATA_TIMEOUT)?;
+            for word in block.chunks_exact_mut(4) {
+                word.copy_from_slice(&self.read_reg(REG_BUFFER_DATA).to_le_bytes());
+            }
+        }
+        self.wait_int(INT_XFER_COMPLETE, DATA_TIMEOUT)
+    }
+
+    fn write_chunk(&self, block_id: u64, buf: &[u8]) -> DevResult {
+        self.start_transfer(false, block_id, buf.len() / BLOCK_SIZE)?;
+        for block in buf.chunks_exact(BLOCK_SIZE) {
+            self.wait_int(INT_BUF_WRITE_READY, DATA_TIMEOUT)?;
+            for word in block.chunks_exact(4) {
+                self.write_reg(
+                    REG_BUFFER_DATA,
+                    u32::from_le_bytes(word.try_into().unwrap()),
+                );
+            }
+        }
+        self.wait_int(INT_XFER_COMPLETE, DATA_TIMEOUT)
+    }
+
+    fn check_request(&self, block_id: u64, len: usize) -> DevResult {
+        if len == 0 || len % BLOCK_SIZE != 0 {
+            return Err(DevError::InvalidParam);
+        }
+        let end = block_id.checked_add((len / BLOCK_SIZE) as u64);
+        match end {
+            Some(end) if end <= self.num_blocks => Ok(()),
+            _ => Err(DevError::InvalidParam),
+        }
+    }
+}
+
+/// Extracts bits `[hi:lo]` of the CSD register, the response registers hold
+/// its bits `[127:8]`.
+fn csd_bits(resp: &[u32; 4], hi: usize, lo: usize) -> u32 {
+    (lo..=hi).rev().fold(0, |val, bit| {
+        let n = bit - 8;
+        val << 1 | (resp[n / 32] >> (n % 32)) & 1
+    })
+}
+
+/// Computes the number of 512-byte blocks from the CSD register.
+fn csd_num_blocks(resp: &[u32; 4]) -> DevResult<u64> {
+    match csd_bits(resp, 127, 126) {
+        0 => {
+            // CSD version 1.0: (C_SIZE + 1) * 2^(C_SIZE_MULT + 2) * 2^READ_BL_LEN bytes.
+            let c_size = csd_bits(resp, 73, 62) as u64;
+            let c_size_mult = csd_bits(resp, 49, 47);
+            let read_bl_len = csd_bits(resp, 83, 80);
+            Ok((c_size + 1) << (c_size_mult + 2 + read_bl_len) >> 9)
+        }
+        1 => {
+            // CSD version 2.0: (C_SIZE + 1) * 512 KiB.
+            Ok((csd_bits(resp, 69, 48) as u64 + 1) << 10)
+        }
+        v => {
+            warn!("sdhci: CSD version {} is not supported", v + 1);
+            Err(DevError::Unsupported)
+        }
+    }
+}
+
+impl BaseDriverOps for SdhciDev {
+    fn device_name(&self) -> &str {
+        "sdhci"
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Block
+    }
+}
+
+impl BlockDriverOps for SdhciDev {
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> DevResult {
+        self.check_request(block_id, buf.len())?;
+        let mut block_id = block_id;
+        for chunk in buf.chunks_mut(MAX_BLOCKS_PER_COMMAND * BLOCK_SIZE) {
+            self.read_chunk(block_id, chunk)
+                .inspect_err(|_| warn!("sdhci: failed to read block {}", block_id))?;
+            block_id += (chunk.len() / BLOCK_SIZE) as u64;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, block_id: u64, buf: &[u8]) -> DevResult {
+        self.check_request(block_id, buf.len())?;
+        let mut block_id = block_id;
+        for chunk in buf.chunks(MAX_BLOCKS_PER_COMMAND * BLOCK_SIZE) {
+            self.write_chunk(block_id, chunk)
+                .inspect_err(|_| warn!("sdhci: failed to write block {}", block_id))?;
+            block_id += (chunk.len() / BLOCK_SIZE) as u64;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> DevResult {
+        // Writes are finished when the transfer completes.
+        Ok(())
+    }
+}
+
+impl BlockDiscardOps for SdhciDev {}
+
+impl Drop for SdhciDev {
+    fn drop(&mut self) {
+        // Stop the SD clock and turn off the bus power.
+        self.write_reg(REG_CLOCK_CONTROL, 0);
+        let hc = self.read_reg(REG_HOST_CONTROL);
+        self.write_reg(REG_HOST_CONTROL, hc & !PWR_ON);
+    }
+}
@@ -1,85 +1,88 @@
 #![allow(unused_imports)]
 
 use crate::prelude::*;
-use alloc::{boxed::Box, vec, vec::Vec};
+use alloc::boxed::Box;
 
 /// The unified type of the NIC devices.
 #[cfg(feature = "net")]
-pub type AxNetDevice = Box<dyn NetDriverOps>;
+pub type AxNetDevice = Box<dyn NetIrqOps>;
 /// The unified type of the block storage devices.
 #[cfg(feature = "block")]
-pub type AxBlockDevice = Box<dyn BlockDriverOps>;
+pub type AxBlockDevice = Box<dyn BlockDiscardOps>;
 /// The unified type of the graphics display devices.
 #[cfg(feature = "display")]
-pub type AxDisplayDevice = Box<dyn DisplayDriverOps>;
+pub type AxDisplayDevice = Box<dyn DisplayBufferOps>;
+/// The unified type of the character devices.
+#[cfg(feature = "char")]
+pub type AxCharDevice = Box<dyn CharDriverOps>;
+/// The unified type of the random number generator devices.
+#[cfg(feature = "rng")]
+pub type AxRngDevice = Box<dyn RngDriverOps>;
+/// The unified type of the 9P transport devices.
+#[cfg(feature = "_9p")]
+pub type Ax9pDevice = Box<dyn _9pDriverOps>;
+/// The unified type of the memory balloon devices.
+#[cfg(feature = "balloon")]
+pub type AxBalloonDevice = Box<dyn BalloonDriverOps>;
+/// The unified type of the input devices.
+#[cfg(feature = "input")]
+pub type AxInputDevice = Box<dyn InputDriverOps>;
+/// The unified type of the sound devices.
+#[cfg(feature = "sound")]
+pub type AxSoundDevice = Box<dyn SoundDriverOps>;
 
 impl super::AxDeviceEnum {
     /// Constructs a network device.
     #[cfg(feature = "net")]
-    pub fn from_net(dev: impl NetDriverOps + 'static) -> Self {
+    pub fn from_net(dev: impl NetIrqOps + 'static) -> Self {
         Self::Net(Box::new(dev))
     }
 
     /// Constructs a block device.
     #[cfg(feature = "block")]
-    pub fn from_block(dev: impl BlockDriverOps + 'static) -> Self {
+    pub fn from_block(dev: impl BlockDiscardOps + 'static) -> Self {
         Self::Block(Box::new(dev))
     }
 
     /// Constructs a display device.
     #[cfg(feature = "display")]
-    pub fn from_display(dev: impl DisplayDriverOps + 'static) -> Self {
+    pub fn from_display(dev: impl DisplayBufferOps + 'static) -> Self {
         Self::Display(Box::new(dev))
     }
-}
-
-/// A structure that contains all device drivers of a certain category.
-///
-/// If the feature `dyn` is enabled, the inner type is [`Vec<D>`]. Otherwise,
-/// the inner type is [`Option<D>`] and at most one device can be contained.
-pub struct AxDeviceContainer<D>(Vec<D>);
-
-impl<D> AxDeviceContainer<D> {
-    /// Returns number of devices in this container.
-    pub fn len(&self) -> usize {
-        self.0.len()
-    }
 
-    /// Returns whether the container is empty.
-    pub fn is_empty(&self) -> bool {
-        self.len() == 0
+    /// Constructs a character device.
+    #[cfg(feature = "char")]
+    pub fn from_char(dev: impl CharDriverOps + 'static) -> Self {
+        Self::Char(Box::new(dev))
     }
 
-    /// Takes one device out of the container (will remove it from the container).
-    pub fn take_one(&mut self) -> Option<D> {
-        if self.is_empty() {
-            None
-        } else {
-            Some(self.0.remove(0))
-        }
+    /// Constructs a random number generator device.
+    #[cfg(feature = "rng")]
+    pub fn from_rng(dev: impl RngDriverOps + 'static) -> Self {
+        Self::Rng(Box::new(dev))
     }
 
-    /// Constructs the container from one device.
-    pub fn from_one(dev: D) -> Self {
-        Self(vec![dev])
+    /// Constructs a 9P transport device.
+    #[cfg(feature = "_9p")]
+    pub fn from_9p(dev: impl _9pDriverOps + 'static) -> Self {
+        Self::_9P(Box::new(dev))
     }
 
-    /// Adds one device into the container.
-    #[allow(dead_code)]
-    pub(crate) fn push(&mut self, dev: D) {
-        self.0.push(dev);
+    /// Constructs a memory balloon device.
+    #[cfg(feature = "balloon")]
+    pub fn from_balloon(dev: impl BalloonDriverOps + 'static) -> Self {
+        Self::Balloon(Box::new(dev))
     }
-}
 
-impl<D> core::ops::Deref for AxDeviceContainer<D> {
-    type Target = Vec<D>;
-    fn deref(&self) -> &Self::Target {
-        &self.0
+    /// Constructs a input device.
+    #[cfg(feature = "input")]
+    pub fn from_input(dev: impl InputDriverOps + 'static) -> Self {
+        Self::Input(Box::new(dev))
     }
-}
 
-impl<D> Default for AxDeviceContainer<D> {
-    fn default() -> Self {
-        Self(Default::default())
+    /// Constructs a sound device.
+    #[cfg(feature = "sound")]
+    pub fn from_sound(dev: impl SoundDriverOps + 'static) -> Self {
+        Self::Sound(Box::new(dev))
     }
 }
@@ -2,10 +2,117 @@
 #[cfg_attr(not(feature = "dyn"), path = "static.rs")]
 mod imp;
 
-use axdriver_base::{BaseDriverOps, DeviceType};
+use alloc::{format, string::String, vec, vec::Vec};
+
+use axdriver_base::{BaseDriverOps, DevResult};
+
+use crate::ops::{AxDeviceType, ShutdownOps};
+
+#[cfg(feature = "input")]
+use crate::ops::InputDriverOps;
+#[cfg(feature = "net")]
+use crate::ops::NetIrqOps;
+
+#[cfg(feature = "block")]
+use axdriver_block::BlockDriverOps;
 
 pub use imp::*;
 
+/// A structure that contains all device drivers of a certain category.
+///
+/// Each device has a stable name, which is the driver name followed by the
+/// index among the devices of that driver, e.g., `virtio-blk0`, `virtio-blk1`
+/// and `ixgbe0`. Devices are kept in the order they are probed.
+pub struct AxDeviceContainer<D>(Vec<(String, D)>);
+
+impl<D> AxDeviceContainer<D> {
+    /// Returns number of devices in this container.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether the container is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Takes one device out of the container (will remove it from the container).
+    pub fn take_one(&mut self) -> Option<D> {
+        self.take_by_index(0)
+    }
+
+    /// Takes the device at the given index out of the container.
+    pub fn take_by_index(&mut self, index: usize) -> Option<D> {
+        if index < self.len() {
+            Some(self.0.remove(index).1)
+        } else {
+            None
+        }
+    }
+
+    /// Takes the device with the given name out of the container.
+    pub fn take_by_name(&mut self, name: &str) -> Option<D> {
+        let index = self.0.iter().position(|(n, _)| n == name)?;
+        self.take_by_index(index)
+    }
+
+    /// Returns an iterator over the names and the devices.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &D)> {
+        self.0.iter().map(|(name, dev)| (name.as_str(), dev))
+    }
+
+    /// Returns an iterator over the names and the mutable devices.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&str, &mut D)> {
+        self.0.iter_mut().map(|(name, dev)| (name.as_str(), dev))
+    }
+
+    /// Constructs the container from one device, which is named `dev0`.
+    pub fn from_one(dev: D) -> Self {
+        Self(vec![(String::from("dev0"), dev)])
+    }
+
+    /// Adds one device into the container, naming it after the driver name.
+    pub(crate) fn push(&mut self, driver_name: &str, dev: D) {
+        let name = self.next_name(driver_name);
+        self.0.push((name, dev));
+    }
+
+    /// Adds one device into the container with the given name.
+    #[allow(dead_code)]
+    pub(crate) fn push_named(&mut self, name: String, dev: D) {
+        self.0.push((name, dev));
+    }
+
+    /// Returns the name of the next device added by the driver, which is the
+    /// driver name followed by the number of its devices.
+    pub(crate) fn next_name(&self, driver_name: &str) -> String {
+        let index = self
+            .0
+            .iter()
+            .filter(|(name, _)| {
+                name.strip_prefix(driver_name)
+                    .is_some_and(|idx| idx.parse::<usize>().is_ok())
+            })
+            .count();
+        format!("{}{}", driver_name, index)
+    }
+}
+
+impl<D> IntoIterator for AxDeviceContainer<D> {
+    type Item = (String, D);
+    type IntoIter = vec::IntoIter<(String, D)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<D> Default for AxDeviceContainer<D> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
 /// A unified enum that represents different categories of devices.
 #[allow(clippy::large_enum_variant)]
 pub enum AxDeviceEnum {
@@ -18,26 +125,109 @@ pub enum AxDeviceEnum {
     /// Graphic display device.
     #[cfg(feature = "display")]
     Display(AxDisplayDevice),
+    /// Character device.
+    #[cfg(feature = "char")]
+    Char(AxCharDevice),
+    /// Random number generator device.
+    #[cfg(feature = "rng")]
+    Rng(AxRngDevice),
+    /// 9P transport device.
+    #[cfg(feature = "_9p")]
+    _9P(Ax9pDevice),
+    /// Memory balloon device.
+    #[cfg(feature = "balloon")]
+    Balloon(AxBalloonDevice),
+    /// Input device.
+    #[cfg(feature = "input")]
+    Input(AxInputDevice),
+    /// Sound device.
+    #[cfg(feature = "sound")]
+    Sound(AxSoundDevice),
+}
+
+#[cfg(feature = "block")]
+impl ShutdownOps for AxBlockDevice {
+    fn shutdown(&mut self) -> DevResult {
+        self.flush()
+    }
+}
+
+#[cfg(feature = "net")]
+impl ShutdownOps for AxNetDevice {}
+#[cfg(feature = "display")]
+impl ShutdownOps for AxDisplayDevice {}
+#[cfg(feature = "char")]
+impl ShutdownOps for AxCharDevice {}
+#[cfg(feature = "rng")]
+impl ShutdownOps for AxRngDevice {}
+#[cfg(feature = "_9p")]
+impl ShutdownOps for Ax9pDevice {}
+#[cfg(feature = "balloon")]
+impl ShutdownOps for AxBalloonDevice {}
+#[cfg(feature = "input")]
+impl ShutdownOps for AxInputDevice {}
+#[cfg(feature = "sound")]
+impl ShutdownOps for AxSoundDevice {}
+
+impl ShutdownOps for AxDeviceEnum {
+    #[allow(unreachable_patterns)]
+    fn shutdown(&mut self) -> DevResult {
+        match self {
+            #[cfg(feature = "net")]
+            Self::Net(dev) => dev.shutdown(),
+            #[cfg(feature = "block")]
+            Self::Block(dev) => dev.shutdown(),
+            #[cfg(feature = "display")]
+            Self::Display(dev) => dev.shutdown(),
+            #[cfg(feature = "char")]
+            Self::Char(dev) => dev.shutdown(),
+            #[cfg(feature = "rng")]
+            Self::Rng(dev) => dev.shutdown(),
+            #[cfg(feature = "_9p")]
+            Self::_9P(dev) => dev.shutdown(),
+            #[cfg(feature = "balloon")]
+            Self::Balloon(dev) => dev.shutdown(),
+            #[cfg(feature = "input")]
+            Self::Input(dev) => dev.shutdown(),
+            #[cfg(feature = "sound")]
+            Self::Sound(dev) => dev.shutdown(),
+            _ => unreachable!(),
+        }
+    }
 }
 
-impl BaseDriverOps for AxDeviceEnum {
+impl AxDeviceEnum {
+    /// The category of the device.
     #[inline]
     #[allow(unreachable_patterns)]
-    fn device_type(&self) -> DeviceType {
+    pub fn device_type(&self) -> AxDeviceType {
         match self {
             #[cfg(feature = "net")]
-            Self::Net(_) => DeviceType::Net,
+            Self::Net(_) => AxDeviceType::Net,
             #[cfg(feature = "block")]
-            Self::Block(_) => DeviceType::Block,
+            Self::Block(_) => AxDeviceType::Block,
             #[cfg(feature = "display")]
-            Self::Display(_) => DeviceType::Display,
+            Self::Display(_) => AxDeviceType::Display,
+            #[cfg(feature = "char")]
+            Self::Char(_) => AxDeviceType::Char,
+            #[cfg(feature = "rng")]
+            Self::Rng(_) => AxDeviceType::Rng,
+            #[cfg(feature = "_9p")]
+            Self::_9P(_) => AxDeviceType::_9P,
+            #[cfg(feature = "balloon")]
+            Self::Balloon(_) => AxDeviceType::Balloon,
+            #[cfg(feature = "input")]
+            Self::Input(_) => AxDeviceType::Input,
+            #[cfg(feature = "sound")]
+            Self::Sound(_) => AxDeviceType::Sound,
             _ => unreachable!(),
         }
     }
 
+    /// The name of the device driver.
     #[inline]
     #[allow(unreachable_patterns)]
-    fn device_name(&self) -> &str {
+    pub fn device_name(&self) -> &str {
         match self {
             #[cfg(feature = "net")]
             Self::Net(dev) => dev.device_name(),
@@ -45,7 +235,45 @@ impl BaseDriverOps for AxDeviceEnum {
             Self::Block(dev) => dev.device_name(),
             #[cfg(feature = "display")]
             Self::Display(dev) => dev.device_name(),
+            #[cfg(feature = "char")]
+            Self::Char(dev) => dev.device_name(),
+            #[cfg(feature = "rng")]
+            Self::Rng(dev) => dev.device_name(),
+            #[cfg(feature = "_9p")]
+            Self::_9P(dev) => dev.device_name(),
+            #[cfg(feature = "balloon")]
+            Self::Balloon(dev) => dev.device_name(),
+            #[cfg(feature = "input")]
+            Self::Input(dev) => dev.device_name(),
+            #[cfg(feature = "sound")]
+            Self::Sound(dev) => dev.device_name(),
             _ => unreachable!(),
         }
     }
+
+    /// The IRQ number raised by the device, if its driver handles interrupts.
+    ///
+    /// Devices without interrupts return `None`, and are polled.
+    #[allow(unreachable_patterns)]
+    pub fn irq(&self) -> Option<usize> {
+        match self {
+            #[cfg(feature = "net")]
+            Self::Net(dev) => dev.irq_num(),
+            #[cfg(feature = "input")]
+            Self::Input(dev) => dev.irq_num(),
+            _ => None,
+        }
+    }
+}
+
+impl<D: ShutdownOps> AxDeviceContainer<D> {
+    /// Shuts down and releases all devices in the container.
+    pub fn shutdown_all(&mut self) {
+        for (name, mut dev) in self.0.drain(..) {
+            if let Err(e) = dev.shutdown() {
+                warn!("failed to shut down device {}: {:?}", name, e);
+            }
+            debug!("device {} released", name);
+        }
+    }
 }
@@ -1,9 +1,24 @@
-#[cfg(feature = "block")]
-pub use crate::drivers::AxBlockDevice;
+#[cfg(feature = "_9p")]
+pub use crate::drivers::Ax9pDevice;
+#[cfg(feature = "balloon")]
+pub use crate::drivers::AxBalloonDevice;
+#[cfg(feature = "char")]
+pub use crate::drivers::AxCharDevice;
 #[cfg(feature = "display")]
 pub use crate::drivers::AxDisplayDevice;
+#[cfg(feature = "input")]
+pub use crate::drivers::AxInputDevice;
 #[cfg(feature = "net")]
 pub use crate::drivers::AxNetDevice;
+#[cfg(feature = "rng")]
+pub use crate::drivers::AxRngDevice;
+#[cfg(feature = "sound")]
+pub use crate::drivers::AxSoundDevice;
+
+/// The unified type of the block storage devices, the disks and their
+/// partitions.
+#[cfg(feature = "block")]
+pub type AxBlockDevice = crate::partition::BlockPartition;
 
 impl super::AxDeviceEnum {
     /// Constructs a network device.
@@ -12,10 +27,10 @@ impl super::AxDeviceEnum {
         Self::Net(dev)
     }
 
-    /// Constructs a block device.
+    /// Constructs a block device, which spans the whole disk.
     #[cfg(feature = "block")]
-    pub const fn from_block(dev: AxBlockDevice) -> Self {
-        Self::Block(dev)
+    pub fn from_block(dev: crate::drivers::AxRawBlockDevice) -> Self {
+        Self::Block(crate::partition::BlockPartition::whole(dev))
     }
 
     /// Constructs a display device.
@@ -23,57 +38,40 @@ impl super::AxDeviceEnum {
     pub const fn from_display(dev: AxDisplayDevice) -> Self {
         Self::Display(dev)
     }
-}
-
-/// A structure that contains all device drivers of a certain category.
-///
-/// If the feature `dyn` is enabled, the inner type is [`Vec<D>`]. Otherwise,
-/// the inner type is [`Option<D>`] and at most one device can be contained.
-pub struct AxDeviceContainer<D>(Option<D>);
 
-impl<D> AxDeviceContainer<D> {
-    /// Returns number of devices in this container.
-    pub const fn len(&self) -> usize {
-        if self.0.is_some() {
-            1
-        } else {
-            0
-        }
+    /// Constructs a character device.
+    #[cfg(feature = "char")]
+    pub const fn from_char(dev: AxCharDevice) -> Self {
+        Self::Char(dev)
     }
 
-    /// Returns whether the container is empty.
-    pub const fn is_empty(&self) -> bool {
-        self.len() == 0
+    /// Constructs a random number generator device.
+    #[cfg(feature = "rng")]
+    pub const fn from_rng(dev: AxRngDevice) -> Self {
+        Self::Rng(dev)
     }
 
-    /// Takes one device out of the container (will remove it from the container).
-    pub fn take_one(&mut self) -> Option<D> {
-        self.0.take()
+    /// Constructs a 9P transport device.
+    #[cfg(feature = "_9p")]
+    pub const fn from_9p(dev: Ax9pDevice) -> Self {
+        Self::_9P(dev)
     }
 
-    /// Constructs the container from one device.
-    pub const fn from_one(dev: D) -> Self {
-        Self(Some(dev))
+    /// Constructs a memory balloon device.
+    #[cfg(feature = "balloon")]
+    pub const fn from_balloon(dev: AxBalloonDevice) -> Self {
+        Self::Balloon(dev)
     }
 
-    /// Adds one device into the container.
-    #[allow(dead_code)]
-    pub(crate) fn push(&mut self, dev: D) {
-        if self.0.is_none() {
-            self.0 = Some(dev);
-        }
+    /// Constructs a input device.
+    #[cfg(feature = "input")]
+    pub const fn from_input(dev: AxInputDevice) -> Self {
+        Self::Input(dev)
     }
-}
-
-impl<D> core::ops::Deref for AxDeviceContainer<D> {
-    type Target = Option<D>;
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
 
-impl<D> Default for AxDeviceContainer<D> {
-    fn default() -> Self {
-        Self(Default::default())
+    /// Constructs a sound device.
+    #[cfg(feature = "sound")]
+    pub const fn from_sound(dev: AxSoundDevice) -> Self {
+        Self::Sound(dev)
     }
 }
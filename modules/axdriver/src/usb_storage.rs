@@ -0,0 +1,354 @@
+//! USB mass storage devices using the Bulk-Only Transport, behind an xHCI
+//! controller.
+//!
+//! Each SCSI command is wrapped in a command block wrapper (CBW) sent to the
+//! bulk OUT endpoint, followed by the data stage through a bounce buffer, and
+//! the command status wrapper (CSW) read from the bulk IN endpoint. Only LUN 0
+//! is exposed.
+
+use core::time::Duration;
+
+use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
+use axdriver_block::BlockDriverOps;
+
+use crate::ops::BlockDiscardOps;
+use crate::xhci::{DmaRegion, Xhci};
+
+/// Mass storage class.
+const CLASS_MASS_STORAGE: u8 = 0x08;
+/// SCSI transparent command set.
+const SUBCLASS_SCSI: u8 = 0x06;
+/// Bulk-Only Transport.
+const PROTOCOL_BOT: u8 = 0x50;
+
+/// Size of the bounce buffer. It is aligned to its size, so that it does not
+/// cross a 64 KiB boundary.
+const BUFFER_SIZE: usize = 0x10000;
+/// Times to poll the unit until it is ready, e.g., after a unit attention.
+const READY_RETRIES: usize = 10;
+const READY_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CBW_SIZE: usize = 31;
+const CBW_FLAG_IN: u8 = 0x80;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+const CSW_SIZE: usize = 13;
+/// Offset of the CSW in the command buffer.
+const CSW_OFFSET: usize = 64;
+
+const CSW_STATUS_PASSED: u8 = 0;
+const CSW_STATUS_FAILED: u8 = 1;
+
+// SCSI commands.
+const SCSI_TEST_UNIT_READY: u8 = 0x00;
+const SCSI_REQUEST_SENSE: u8 = 0x03;
+const SCSI_READ_CAPACITY_10: u8 = 0x25;
+const SCSI_READ_10: u8 = 0x28;
+const SCSI_WRITE_10: u8 = 0x2a;
+const SCSI_SYNCHRONIZE_CACHE_10: u8 = 0x35;
+
+const SENSE_ILLEGAL_REQUEST: u8 = 0x05;
+
+/// Direction and length of the data stage of a command.
+#[derive(Clone, Copy)]
+enum Data {
+    None,
+    In(usize),
+    Out(usize),
+}
+
+/// The USB mass storage driver, exposing LUN 0 as a block device.
+pub struct UsbStorageDev {
+    xhci: Xhci,
+    bulk_in: u8,
+    bulk_out: u8,
+    tag: u32,
+    block_size: usize,
+    num_blocks: u64,
+    /// Buffer of the CBW and the CSW.
+    command_buf: DmaRegion,
+    /// Bounce buffer of the data stages.
+    buffer: DmaRegion,
+}
+
+unsafe impl Send for UsbStorageDev {}
+unsafe impl Sync for UsbStorageDev {}
+
+impl UsbStorageDev {
+    /// Initializes the xHCI controller whose registers are mapped at
+    /// `mmio_base`, and the mass storage device connected to it.
+    pub fn init(mmio_base: usize) -> DevResult<Self> {
+        let mut xhci = Xhci::init(mmio_base)?;
+        let config = xhci.configuration()?;
+        let Some(iface) = config.interfaces.iter().find(|iface| {
+            iface.class == CLASS_MASS_STORAGE
+                && iface.subclass == SUBCLASS_SCSI
+                && iface.protocol == PROTOCOL_BOT
+        }) else {
+            warn!("usb-storage: no Bulk-Only SCSI interface");
+            return Err(DevError::Unsupported);
+        };
+        let find_bulk = |is_in: bool| {
+            iface
+                .endpoints
+                .iter()
+                .find(|ep| ep.is_bulk() && ep.is_in() == is_in)
+                .copied()
+        };
+        let (Some(bulk_in), Some(bulk_out)) = (find_bulk(true), find_bulk(false)) else {
+            warn!(
+                "usb-storage: interface {} has no bulk endpoints",
+                iface.number
+            );
+            return Err(DevError::Unsupported);
+        };
+        xhci.configure(&config, &[bulk_in, bulk_out])?;
+
+        let mut dev = Self {
+            xhci,
+            bulk_in: bulk_in.address,
+            bulk_out: bulk_out.address,
+            tag: 0,
+            block_size: 0,
+            num_blocks: 0,
+            command_buf: DmaRegion::new(128, 128)?,
+            buffer: DmaRegion::new(BUFFER_SIZE, BUFFER_SIZE)?,
+        };
+        dev.wait_ready()?;
+        dev.read_capacity()?;
+        info!(
+            "usb-storage: {} blocks of {} bytes",
+            dev.num_blocks, dev.block_size
+        );
+        Ok(dev)
+    }
+
+    /// Performs a bulk transfer, which is retried once if the endpoint
+    /// stalled.
+    fn bulk(&mut self, endpoint: u8, bus_addr: u64, len: usize) -> DevResult<usize> {
+        match self.xhci.bulk_transfer(endpoint, bus_addr, len) {
+            Err(DevError::Again) => self.xhci.bulk_transfer(endpoint, bus_addr, len),
+            res => res,
+        }
+    }
+
+    /// Sends a command and its data, and returns the status in the CSW and
+    /// the number of bytes transferred.
+    fn transport(&mut self, cb: &[u8], data: Data) -> DevResult<(u8, usize)> {
+        self.tag = self.tag.wrapping_add(1);
+        let (len, flags) = match data {
+            Data::None => (0, 0),
+            Data::In(len) => (len, CBW_FLAG_IN),
+            Data::Out(len) => (len, 0),
+        };
+        let cbw = &mut self.command_buf.as_mut_slice()[..CBW_SIZE];
+        cbw.fill(0);
+        cbw[0..4].copy_from_slice(&CBW_SIGNATURE.to_le_bytes());
+        cbw[4..8].copy_from_slice(&self.tag.to_le_bytes());
+        cbw[8..12].copy_from_slice(&(len as u32).to_le_bytes());
+        cbw[12] = flags;
+        // LUN 0.
+        cbw[14] = cb.len() as u8;
+        cbw[15..15 + cb.len()].copy_from_slice(cb);
+        let command_addr = self.command_buf.bus_addr();
+        self.xhci
+            .bulk_transfer(self.bulk_out, command_addr, CBW_SIZE)?;
+
+        // A stalled data stage is cleared, and the status is still read.
+        let buffer_addr = self.buffer.bus_addr();
+        let transferred = match data {
+            Data::None => Ok(0),
+            Data::In(len) => self.xhci.bulk_transfer(self.bulk_in, buffer_addr, len),
+            Data::Out(len) => self.xhci.bulk_transfer(self.bulk_out, buffer_addr, len),
+        };
+        let transferred = match transferred {
+            Err(DevError::Again) => 0,
+            res => res?,
+        };
+
+        let csw_addr = command_addr + CSW_OFFSET as u64;
+        if self.bulk(self.bulk_in, csw_addr, CSW_SIZE)? != CSW_SIZE {
+            error!("usb-storage: short CSW");
+            return Err(DevError::Io);
+        }
+        let csw = &self.command_buf.as_slice()[CSW_OFFSET..CSW_OFFSET + CSW_SIZE];
+        let signature = u32::from_le_bytes(csw[0..4].try_into().unwrap());
+        let tag = u32::from_le_bytes(csw[4..8].try_into().unwrap());
+        if signature != CSW_SIGNATURE || tag != self.tag {
+            error!("usb-storage: invalid CSW for command {:#x}", cb[0]);
+            return Err(DevError::Io);
+        }
+        Ok((csw[12], transferred))
+    }
+
+    /// Runs a SCSI command, and returns the number of bytes transferred.
+    ///
+    /// If the command fails, the sense data is requested and logged, and
+    /// [`DevError::Unsupported`] is returned for illegal requests.
+    fn command(&mut self, cb: &[u8], data: Data) -> DevResult<usize> {
+        match self.transport(cb, data)? {
+            (CSW_STATUS_PASSED, transferred) => Ok(transferred),
+            (CSW_STATUS_FAILED, _) => {
+                let sense_key = self.request_sense()?;
+                debug!(
+                    "usb-storage: command {:#x} failed with sense key {:#x}",
+                    cb[0], sense_key
+                );
+                if sense_key == SENSE_ILLEGAL_REQUEST {
+                    Err(DevError::Unsupported)
+                } else {
+                    Err(DevError::Io)
+                }
+            }
+            (status, _) => {
+                error!(
+                    "usb-storage: command {:#x} failed with status {}",
+                    cb[0], status
+                );
+                Err(DevError::Io)
+            }
+        }
+    }
+
+    /// Requests the sense data of the last failed command, and returns the
+    /// sense key.
+    fn request_sense(&mut self) -> DevResult<u8> {
+        const SENSE_LEN: usize = 18;
+        let cb = [SCSI_REQUEST_SENSE, 0, 0, 0, SENSE_LEN as u8, 0];
+        match self.transport(&cb, Data::In(SENSE_LEN))? {
+            (CSW_STATUS_PASSED, len) if len > 2 => {
+                let sense = self.buffer.as_slice();
+                debug!(
+                    "usb-storage: sense key {:#x}, ASC {:#x}, ASCQ {:#x}",
+                    sense[2] & 0xf,
+                    sense[12],
+                    sense[13]
+                );
+                Ok(sense[2] & 0xf)
+            }
+            _ => Err(DevError::Io),
+        }
+    }
+
+    /// Polls the unit until it is ready, which reports a unit attention after
+    /// it is reset.
+    fn wait_ready(&mut self) -> DevResult {
+        let cb = [SCSI_TEST_UNIT_READY, 0, 0, 0, 0, 0];
+        for _ in 0..READY_RETRIES {
+            if self.command(&cb, Data::None).is_ok() {
+                return Ok(());
+            }
+            axhal::time::busy_wait(READY_RETRY_DELAY);
+        }
+        warn!("usb-storage: unit not ready");
+        Err(DevError::Io)
+    }
+
+    fn read_capacity(&mut self) -> DevResult {
+        let cb = [SCSI_READ_CAPACITY_10, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        if self.command(&cb, Data::In(8))? < 8 {
+            return Err(DevError::Io);
+        }
+        let data = self.buffer.as_slice();
+        let last_lba = u32::from_be_bytes(data[0..4].try_into().unwrap());
+        let block_size = u32::from_be_bytes(data[4..8].try_into().unwrap()) as usize;
+        if last_lba == u32::MAX {
+            // Needs READ CAPACITY (16) and 16-byte commands.
+            warn!("usb-storage: devices larger than 2^32 blocks are not supported");
+            return Err(DevError::Unsupported);
+        }
+        if block_size == 0 || BUFFER_SIZE % block_size != 0 {
+            warn!("usb-storage: block size {} is not supported", block_size);
+            return Err(DevError::Unsupported);
+        }
+        self.num_blocks = last_lba as u64 + 1;
+        self.block_size = block_size;
+        Ok(())
+    }
+
+    /// Reads or writes `count` blocks starting at `block_id` through the
+    /// bounce buffer.
+    fn rw_blocks(&mut self, opcode: u8, block_id: u64, count: usize) -> DevResult {
+        let lba = (block_id as u32).to_be_bytes();
+        let blocks = (count as u16).to_be_bytes();
+        let cb = [
+            opcode, 0, lba[0], lba[1], lba[2], lba[3], 0, blocks[0], blocks[1], 0,
+        ];
+        let len = count * self.block_size;
+        let data = if opcode == SCSI_READ_10 {
+            Data::In(len)
+        } else {
+            Data::Out(len)
+        };
+        if self.command(&cb, data)? != len {
+            warn!("usb-storage: short transfer at block {}", block_id);
+            return Err(DevError::Io);
+        }
+        Ok(())
+    }
+
+    fn check_request(&self, block_id: u64, len: usize) -> DevResult {
+        if len == 0 || len % self.block_size != 0 {
+            return Err(DevError::InvalidParam);
+        }
+        match block_id.checked_add((len / self.block_size) as u64) {
+            Some(end) if end <= self.num_blocks => Ok(()),
+            _ => Err(DevError::InvalidParam),
+        }
+    }
+}
+
+impl BaseDriverOps for UsbStorageDev {
+    fn device_name(&self) -> &str {
+        "usb-storage"
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Block
+    }
+}
+
+impl BlockDriverOps for UsbStorageDev {
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> DevResult {
+        self.check_request(block_id, buf.len())?;
+        let mut block_id = block_id;
+        for chunk in buf.chunks_mut(BUFFER_SIZE) {
+            let count = chunk.len() / self.block_size;
+            self.rw_blocks(SCSI_READ_10, block_id, count)?;
+            chunk.copy_from_slice(&self.buffer.as_slice()[..chunk.len()]);
+            block_id += count as u64;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, block_id: u64, buf: &[u8]) -> DevResult {
+        self.check_request(block_id, buf.len())?;
+        let mut block_id = block_id;
+        for chunk in buf.chunks(BUFFER_SIZE) {
+            let count = chunk.len() / self.block_size;
+            self.buffer.as_mut_slice()[..chunk.len()].copy_from_slice(chunk);
+            self.rw_blocks(SCSI_WRITE_10, block_id, count)?;
+            block_id += count as u64;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> DevResult {
+        let cb = [SCSI_SYNCHRONIZE_CACHE_10, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        match self.command(&cb, Data::None) {
+            // Devices without a write cache may not support the command.
+            Err(DevError::Unsupported) => Ok(()),
+            res => res.map(|_| ()),
+        }
+    }
+}
+
+impl BlockDiscardOps for UsbStorageDev {}
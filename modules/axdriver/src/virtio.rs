@@ -2,16 +2,19 @@ use core::marker::PhantomData;
 use core::ptr::NonNull;
 
 use axalloc::global_allocator;
-use axdriver_base::{BaseDriverOps, DevResult, DeviceType};
+use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
 use axdriver_virtio::{BufferDirection, PhysAddr, VirtIoHal};
 use axhal::mem::{phys_to_virt, virt_to_phys};
 use cfg_if::cfg_if;
+use virtio_drivers::transport::{DeviceStatus, DeviceType as VirtIoDevType, Transport};
 
-use crate::{drivers::DriverProbe, AxDeviceEnum};
+use crate::drivers::{DriverProbe, ProbeError, ProbeResult};
+use crate::AxDeviceEnum;
 
 cfg_if! {
     if #[cfg(bus = "pci")] {
         use axdriver_pci::{PciRoot, DeviceFunction, DeviceFunctionInfo};
+        use virtio_drivers::transport::pci::virtio_device_type;
         type VirtIoTransport = axdriver_virtio::PciTransport;
     } else if #[cfg(bus =  "mmio")] {
         type VirtIoTransport = axdriver_virtio::MmioTransport;
@@ -21,11 +24,15 @@ cfg_if! {
 /// A trait for VirtIO device meta information.
 pub trait VirtIoDevMeta {
     const DEVICE_TYPE: DeviceType;
+    const VIRTIO_TYPE: VirtIoDevType;
+
+    /// Whether the driver uses the IRQ of the device.
+    const USES_IRQ: bool = false;
 
     type Device: BaseDriverOps;
     type Driver = VirtIoDriver<Self>;
 
-    fn try_new(transport: VirtIoTransport) -> DevResult<AxDeviceEnum>;
+    fn try_new(transport: VirtIoTransport, irq: Option<usize>) -> DevResult<AxDeviceEnum>;
 }
 
 cfg_if! {
@@ -34,10 +41,12 @@ cfg_if! {
 
         impl VirtIoDevMeta for VirtIoNet {
             const DEVICE_TYPE: DeviceType = DeviceType::Net;
-            type Device = axdriver_virtio::VirtIoNetDev<VirtIoHalImpl, VirtIoTransport, 64>;
+            const VIRTIO_TYPE: VirtIoDevType = VirtIoDevType::Network;
+            const USES_IRQ: bool = true;
+            type Device = crate::virtio_net::VirtIoNetMqDev<VirtIoHalImpl, VirtIoTransport, 64>;
 
-            fn try_new(transport: VirtIoTransport) -> DevResult<AxDeviceEnum> {
-                Ok(AxDeviceEnum::from_net(Self::Device::try_new(transport)?))
+            fn try_new(transport: VirtIoTransport, irq: Option<usize>) -> DevResult<AxDeviceEnum> {
+                Ok(AxDeviceEnum::from_net(Self::Device::try_new(transport, irq)?))
             }
         }
     }
@@ -49,9 +58,10 @@ cfg_if! {
 
         impl VirtIoDevMeta for VirtIoBlk {
             const DEVICE_TYPE: DeviceType = DeviceType::Block;
-            type Device = axdriver_virtio::VirtIoBlkDev<VirtIoHalImpl, VirtIoTransport>;
+            const VIRTIO_TYPE: VirtIoDevType = VirtIoDevType::Block;
+            type Device = crate::virtio_blk::VirtIoBlkDev<VirtIoHalImpl, VirtIoTransport>;
 
-            fn try_new(transport: VirtIoTransport) -> DevResult<AxDeviceEnum> {
+            fn try_new(transport: VirtIoTransport, _irq: Option<usize>) -> DevResult<AxDeviceEnum> {
                 Ok(AxDeviceEnum::from_block(Self::Device::try_new(transport)?))
             }
         }
@@ -64,41 +74,188 @@ cfg_if! {
 
         impl VirtIoDevMeta for VirtIoGpu {
             const DEVICE_TYPE: DeviceType = DeviceType::Display;
-            type Device = axdriver_virtio::VirtIoGpuDev<VirtIoHalImpl, VirtIoTransport>;
+            const VIRTIO_TYPE: VirtIoDevType = VirtIoDevType::GPU;
+            type Device = crate::virtio_gpu::VirtIoGpuDev<VirtIoHalImpl, VirtIoTransport>;
 
-            fn try_new(transport: VirtIoTransport) -> DevResult<AxDeviceEnum> {
+            fn try_new(transport: VirtIoTransport, _irq: Option<usize>) -> DevResult<AxDeviceEnum> {
                 Ok(AxDeviceEnum::from_display(Self::Device::try_new(transport)?))
             }
         }
     }
 }
 
+cfg_if! {
+    if #[cfg(char_dev = "virtio-console")] {
+        pub struct VirtIoConsole;
+
+        impl VirtIoDevMeta for VirtIoConsole {
+            const DEVICE_TYPE: DeviceType = DeviceType::Char;
+            const VIRTIO_TYPE: VirtIoDevType = VirtIoDevType::Console;
+            type Device = crate::virtio_console::VirtIoConsoleDev<VirtIoHalImpl, VirtIoTransport>;
+
+            fn try_new(transport: VirtIoTransport, _irq: Option<usize>) -> DevResult<AxDeviceEnum> {
+                Ok(AxDeviceEnum::from_char(Self::Device::try_new(transport)?))
+            }
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(rng_dev = "virtio-rng")] {
+        pub struct VirtIoRng;
+
+        impl VirtIoDevMeta for VirtIoRng {
+            const DEVICE_TYPE: DeviceType = DeviceType::Char;
+            const VIRTIO_TYPE: VirtIoDevType = VirtIoDevType::EntropySource;
+            type Device = crate::virtio_rng::VirtIoRngDev<VirtIoHalImpl, VirtIoTransport>;
+
+            fn try_new(transport: VirtIoTransport, _irq: Option<usize>) -> DevResult<AxDeviceEnum> {
+                Ok(AxDeviceEnum::from_rng(Self::Device::try_new(transport)?))
+            }
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(_9p_dev = "virtio-9p")] {
+        pub struct VirtIo9p;
+
+        impl VirtIoDevMeta for VirtIo9p {
+            const DEVICE_TYPE: DeviceType = DeviceType::Char;
+            const VIRTIO_TYPE: VirtIoDevType = VirtIoDevType::_9P;
+            type Device = crate::virtio_9p::VirtIo9pDev<VirtIoHalImpl, VirtIoTransport>;
+
+            fn try_new(transport: VirtIoTransport, _irq: Option<usize>) -> DevResult<AxDeviceEnum> {
+                Ok(AxDeviceEnum::from_9p(Self::Device::try_new(transport)?))
+            }
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(balloon_dev = "virtio-balloon")] {
+        pub struct VirtIoBalloon;
+
+        impl VirtIoDevMeta for VirtIoBalloon {
+            const DEVICE_TYPE: DeviceType = DeviceType::Char;
+            const VIRTIO_TYPE: VirtIoDevType = VirtIoDevType::MemoryBallooning;
+            type Device = crate::virtio_balloon::VirtIoBalloonDev<VirtIoHalImpl, VirtIoTransport>;
+
+            fn try_new(transport: VirtIoTransport, _irq: Option<usize>) -> DevResult<AxDeviceEnum> {
+                Ok(AxDeviceEnum::from_balloon(Self::Device::try_new(transport)?))
+            }
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(input_dev = "virtio-input")] {
+        pub struct VirtIoInput;
+
+        impl VirtIoDevMeta for VirtIoInput {
+            const DEVICE_TYPE: DeviceType = DeviceType::Char;
+            const VIRTIO_TYPE: VirtIoDevType = VirtIoDevType::Input;
+            const USES_IRQ: bool = true;
+            type Device = crate::virtio_input::VirtIoInputDev<VirtIoHalImpl, VirtIoTransport>;
+
+            fn try_new(transport: VirtIoTransport, irq: Option<usize>) -> DevResult<AxDeviceEnum> {
+                Ok(AxDeviceEnum::from_input(Self::Device::try_new(transport, irq)?))
+            }
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(sound_dev = "virtio-sound")] {
+        pub struct VirtIoSound;
+
+        impl VirtIoDevMeta for VirtIoSound {
+            const DEVICE_TYPE: DeviceType = DeviceType::Char;
+            const VIRTIO_TYPE: VirtIoDevType = VirtIoDevType::Sound;
+            type Device = crate::virtio_sound::VirtIoSoundDev<VirtIoHalImpl, VirtIoTransport>;
+
+            fn try_new(transport: VirtIoTransport, _irq: Option<usize>) -> DevResult<AxDeviceEnum> {
+                Ok(AxDeviceEnum::from_sound(Self::Device::try_new(transport)?))
+            }
+        }
+    }
+}
+
+/// Converts errors of `virtio_drivers` to [`DevError`].
+pub(crate) const fn as_dev_err(e: virtio_drivers::Error) -> DevError {
+    use virtio_drivers::Error::*;
+    match e {
+        QueueFull => DevError::BadState,
+        NotReady => DevError::Again,
+        WrongToken => DevError::BadState,
+        AlreadyUsed => DevError::AlreadyExists,
+        InvalidParam => DevError::InvalidParam,
+        DmaError => DevError::NoMemory,
+        IoError => DevError::Io,
+        Unsupported => DevError::Unsupported,
+        ConfigSpaceTooSmall => DevError::BadState,
+        ConfigSpaceMissing => DevError::BadState,
+        _ => DevError::BadState,
+    }
+}
+
+/// Resets the device and negotiates the features, returns the features in
+/// `supported` that are also offered by the device.
+///
+/// Legacy devices (virtio-mmio version 1) only have 32 feature bits and no
+/// `FEATURES_OK` status, and their queues are located by the guest page size
+/// set here. For modern devices, setting the page size does nothing.
+pub(crate) fn negotiate_features<T: Transport>(transport: &mut T, supported: u64) -> u64 {
+    transport.set_status(DeviceStatus::empty());
+    transport.set_status(DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER);
+    let legacy = transport.requires_legacy_layout();
+    let mut features = transport.read_device_features() & supported;
+    if legacy {
+        features &= u32::MAX as u64;
+    }
+    transport.write_driver_features(features);
+    if !legacy {
+        transport.set_status(
+            DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER | DeviceStatus::FEATURES_OK,
+        );
+    }
+    transport.set_guest_page_size(virtio_drivers::PAGE_SIZE as u32);
+    features
+}
+
 /// A common driver for all VirtIO devices that implements [`DriverProbe`].
 pub struct VirtIoDriver<D: VirtIoDevMeta + ?Sized>(PhantomData<D>);
 
 impl<D: VirtIoDevMeta> DriverProbe for VirtIoDriver<D> {
     #[cfg(bus = "mmio")]
-    fn probe_mmio(mmio_base: usize, mmio_size: usize) -> Option<AxDeviceEnum> {
+    fn probe_mmio(mmio_base: usize, _mmio_size: usize, irq: Option<usize>) -> ProbeResult {
+        use virtio_drivers::transport::mmio::{MmioTransport, MmioVersion, VirtIOHeader};
+
         let base_vaddr = phys_to_virt(mmio_base.into());
-        if let Some((ty, transport)) =
-            axdriver_virtio::probe_mmio_device(base_vaddr.as_mut_ptr(), mmio_size)
-        {
-            if ty == D::DEVICE_TYPE {
-                match D::try_new(transport) {
-                    Ok(dev) => return Some(dev),
-                    Err(e) => {
-                        warn!(
-                            "failed to initialize MMIO device at [PA:{:#x}, PA:{:#x}): {:?}",
-                            mmio_base,
-                            mmio_base + mmio_size,
-                            e
-                        );
-                        return None;
-                    }
-                }
-            }
+        let Some(header) = NonNull::new(base_vaddr.as_mut_ptr() as *mut VirtIOHeader) else {
+            return Ok(None);
+        };
+        // An error means there is no valid device at this address.
+        let Ok(transport) = (unsafe { MmioTransport::new(header) }) else {
+            return Ok(None);
+        };
+        if transport.device_type() != D::VIRTIO_TYPE {
+            return Ok(None);
         }
-        None
+        // Both versions are driven through the same `Transport` interface,
+        // see `negotiate_features` for the differences.
+        info!(
+            "virtio-mmio {:?} at PA:{:#x}: {} transport",
+            D::VIRTIO_TYPE,
+            mmio_base,
+            match transport.version() {
+                MmioVersion::Legacy => "legacy (version 1)",
+                MmioVersion::Modern => "modern (version 2)",
+            }
+        );
+        let irq = irq.filter(|_| D::USES_IRQ && cfg!(feature = "irq"));
+        let dev = D::try_new(transport, irq).map_err(ProbeError::new::<Self>)?;
+        Ok(Some(dev))
     }
 
     #[cfg(bus = "pci")]
@@ -106,35 +263,109 @@ impl<D: VirtIoDevMeta> DriverProbe for VirtIoDriver<D> {
         root: &mut PciRoot,
         bdf: DeviceFunction,
         dev_info: &DeviceFunctionInfo,
-    ) -> Option<AxDeviceEnum> {
-        if dev_info.vendor_id != 0x1af4 {
-            return None;
-        }
-        match (D::DEVICE_TYPE, dev_info.device_id) {
-            (DeviceType::Net, 0x1000) | (DeviceType::Net, 0x1041) => {}
-            (DeviceType::Block, 0x1001) | (DeviceType::Block, 0x1042) => {}
-            (DeviceType::Display, 0x1050) => {}
-            _ => return None,
+    ) -> ProbeResult {
+        if dev_info.vendor_id != 0x1af4 || virtio_device_type(dev_info) != Some(D::VIRTIO_TYPE) {
+            return Ok(None);
         }
 
-        if let Some((ty, transport)) =
-            axdriver_virtio::probe_pci_device::<VirtIoHalImpl>(root, bdf, dev_info)
-        {
-            if ty == D::DEVICE_TYPE {
-                match D::try_new(transport) {
-                    Ok(dev) => return Some(dev),
-                    Err(e) => {
-                        warn!(
-                            "failed to initialize PCI device at {}({}): {:?}",
-                            bdf, dev_info, e
-                        );
-                        return None;
-                    }
+        // The capabilities may refer to any BAR, map all of them.
+        for bar in 0..6 {
+            crate::bus::map_bar(root, bdf, bar);
+        }
+        #[cfg(feature = "irq")]
+        let irq = if D::USES_IRQ {
+            alloc_pci_irq(root, bdf)
+        } else {
+            None
+        };
+        #[cfg(not(feature = "irq"))]
+        let irq = None;
+        let res = axdriver_virtio::PciTransport::new::<VirtIoHalImpl>(root, bdf)
+            .map_err(|e| {
+                warn!("failed to create VirtIO PCI transport: {:?}", e);
+                DevError::Io
+            })
+            .and_then(|transport| D::try_new(transport, irq));
+        match res {
+            Ok(dev) => {
+                // The routing is cleared by the device reset in `try_new`.
+                #[cfg(feature = "irq")]
+                if irq.is_some() && route_queue_irqs(root, bdf).is_err() {
+                    warn!("failed to route the interrupts of VirtIO device at {}", bdf);
                 }
+                Ok(Some(dev))
+            }
+            Err(e) => {
+                #[cfg(feature = "irq")]
+                if let Some(irq) = irq {
+                    use crate::bus::PciMsixExt;
+                    root.free_msix(bdf, &[irq]).ok();
+                }
+                Err(ProbeError::new::<Self>(e))
+            }
+        }
+    }
+}
+
+/// Allocates an MSI-X vector for the VirtIO PCI device, returns its IRQ.
+///
+/// Table entry 0 is used and left unmasked, the device raises it only after
+/// [`route_queue_irqs`] is called.
+#[cfg(all(bus = "pci", feature = "irq"))]
+fn alloc_pci_irq(root: &mut PciRoot, bdf: DeviceFunction) -> Option<usize> {
+    use crate::bus::PciMsixExt;
+
+    let irq = match root.alloc_msix(bdf, 1) {
+        Ok(irqs) => irqs[0],
+        Err(e) => {
+            warn!("failed to allocate MSI-X vector for {}: {:?}", bdf, e);
+            return None;
+        }
+    };
+    root.set_msix_masked(bdf, 0, false).ok()?;
+    Some(irq)
+}
+
+/// Routes the interrupts of all virtqueues to MSI-X table entry 0, through
+/// the common configuration structure of the device.
+#[cfg(all(bus = "pci", feature = "irq"))]
+fn route_queue_irqs(root: &mut PciRoot, bdf: DeviceFunction) -> DevResult {
+    use crate::bus::{capabilities, config_read};
+
+    const PCI_CAP_ID_VNDR: u8 = 0x09;
+    const VIRTIO_PCI_CAP_COMMON_CFG: u8 = 1;
+    // Offsets in `virtio_pci_common_cfg`.
+    const MSIX_CONFIG: usize = 0x10;
+    const NUM_QUEUES: usize = 0x12;
+    const QUEUE_SELECT: usize = 0x16;
+    const QUEUE_MSIX_VECTOR: usize = 0x1a;
+    const NO_VECTOR: u16 = 0xffff;
+
+    let (bar, offset) = capabilities(bdf)
+        .filter(|&(id, _)| id == PCI_CAP_ID_VNDR)
+        .find_map(|(_, cap)| {
+            let header = config_read(bdf, cap);
+            ((header >> 24) as u8 == VIRTIO_PCI_CAP_COMMON_CFG)
+                .then(|| (config_read(bdf, cap + 4) as u8, config_read(bdf, cap + 8)))
+        })
+        .ok_or(DevError::Unsupported)?;
+    let bar = crate::bus::map_bar(root, bdf, bar).ok_or(DevError::Io)?;
+    let cfg = bar.vaddr + offset as usize;
+    let reg = |off: usize| (cfg + off) as *mut u16;
+
+    // Safe because the common configuration structure is mapped.
+    unsafe {
+        reg(MSIX_CONFIG).write_volatile(NO_VECTOR);
+        for queue in 0..reg(NUM_QUEUES).read_volatile() {
+            reg(QUEUE_SELECT).write_volatile(queue);
+            reg(QUEUE_MSIX_VECTOR).write_volatile(0);
+            // The device reads back `NO_VECTOR` if it fails to use the vector.
+            if reg(QUEUE_MSIX_VECTOR).read_volatile() == NO_VECTOR {
+                return Err(DevError::NoMemory);
             }
         }
-        None
     }
+    Ok(())
 }
 
 pub struct VirtIoHalImpl;
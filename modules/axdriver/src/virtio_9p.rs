@@ -0,0 +1,114 @@
+//! VirtIO 9P transport device.
+//!
+//! The device only moves 9P messages between the guest and the host, the
+//! protocol itself is left to the file system. Each request is sent on the
+//! request queue together with a buffer for the reply, and the driver checks
+//! that the reply is a well-formed message with the same tag. The maximum
+//! message size (`msize`) negotiated by `Tversion` is recorded and enforced for
+//! the following requests.
+
+use alloc::string::String;
+
+use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
+use virtio_drivers::queue::VirtQueue;
+use virtio_drivers::transport::{DeviceStatus, Transport};
+use virtio_drivers::Hal;
+
+use crate::ops::_9pDriverOps;
+use crate::virtio::{as_dev_err, negotiate_features};
+use crate::virtio_9p_msg::{round_trip, DEFAULT_MSIZE};
+
+const QUEUE_REQUEST: u16 = 0;
+const QUEUE_SIZE: usize = 16;
+
+const VIRTIO_9P_MOUNT_TAG: u64 = 1 << 0;
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// The VirtIO 9P transport device driver.
+pub struct VirtIo9pDev<H: Hal, T: Transport> {
+    transport: T,
+    queue: VirtQueue<H, QUEUE_SIZE>,
+    mount_tag: String,
+    msize: u32,
+}
+
+unsafe impl<H: Hal, T: Transport> Send for VirtIo9pDev<H, T> {}
+unsafe impl<H: Hal, T: Transport> Sync for VirtIo9pDev<H, T> {}
+
+impl<H: Hal, T: Transport> VirtIo9pDev<H, T> {
+    /// Creates a new driver instance and initializes the device, or returns
+    /// an error if any step fails.
+    pub fn try_new(mut transport: T) -> DevResult<Self> {
+        let features = negotiate_features(&mut transport, VIRTIO_9P_MOUNT_TAG | VIRTIO_F_VERSION_1);
+
+        let mount_tag = if features & VIRTIO_9P_MOUNT_TAG != 0 {
+            Self::read_mount_tag(&transport)?
+        } else {
+            String::new()
+        };
+        let queue =
+            VirtQueue::new(&mut transport, QUEUE_REQUEST, false, false).map_err(as_dev_err)?;
+        transport.finish_init();
+
+        info!("virtio-9p: mount tag {:?}", mount_tag);
+        Ok(Self {
+            transport,
+            queue,
+            mount_tag,
+            msize: DEFAULT_MSIZE,
+        })
+    }
+
+    /// Reads the `virtio_9p_config` structure: `tag_len[2] tag[tag_len]`.
+    fn read_mount_tag(transport: &T) -> DevResult<String> {
+        let config = transport.config_space::<u16>().map_err(as_dev_err)?;
+        // Safe because the device reports the tag length, and the tag follows
+        // it in the config space.
+        let tag = unsafe {
+            let tag_len = config.as_ptr().read_volatile() as usize;
+            let tag_ptr = config.as_ptr().add(1) as *const u8;
+            (0..tag_len)
+                .map(|i| tag_ptr.add(i).read_volatile())
+                .collect::<alloc::vec::Vec<_>>()
+        };
+        String::from_utf8(tag).map_err(|_| DevError::InvalidParam)
+    }
+}
+
+impl<H: Hal, T: Transport> Drop for VirtIo9pDev<H, T> {
+    fn drop(&mut self) {
+        self.transport.queue_unset(QUEUE_REQUEST);
+        // Reset the device so that it no longer accesses the freed memory.
+        self.transport.set_status(DeviceStatus::empty());
+    }
+}
+
+impl<H: Hal, T: Transport> BaseDriverOps for VirtIo9pDev<H, T> {
+    fn device_name(&self) -> &str {
+        "virtio-9p"
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Char
+    }
+}
+
+impl<H: Hal, T: Transport> _9pDriverOps for VirtIo9pDev<H, T> {
+    fn mount_tag(&self) -> &str {
+        &self.mount_tag
+    }
+
+    fn msize(&self) -> u32 {
+        self.msize
+    }
+
+    fn send_with_recv(&mut self, request: &[u8], response: &mut [u8]) -> DevResult<usize> {
+        let (queue, transport) = (&mut self.queue, &mut self.transport);
+        round_trip(&mut self.msize, request, response, |req, resp| {
+            let len = queue
+                .add_notify_wait_pop(&[req], &mut [resp], transport)
+                .map_err(as_dev_err)?;
+            Ok(len as usize)
+        })
+    }
+}
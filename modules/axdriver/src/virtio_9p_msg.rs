@@ -0,0 +1,209 @@
+//! Checks of the 9P messages moved by the VirtIO 9P transport device.
+//!
+//! Kept apart from the driver so that the checks are built and tested without
+//! the `virtio-9p` feature.
+
+use axdriver_base::{DevError, DevResult};
+
+/// The maximum message size used before `Tversion` is negotiated.
+pub(crate) const DEFAULT_MSIZE: u32 = 8192;
+
+/// Length of the message header: `size[4] type[1] tag[2]`.
+const HEADER_LEN: usize = 7;
+
+const TVERSION: u8 = 100;
+const RVERSION: u8 = 101;
+
+/// Header fields of a 9P message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MsgHeader {
+    size: u32,
+    ty: u8,
+    tag: u16,
+}
+
+impl MsgHeader {
+    /// Parses the header of the message in `buf`, checking that the message
+    /// fits in the buffer.
+    fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < HEADER_LEN {
+            return None;
+        }
+        let size = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+        if (size as usize) < HEADER_LEN || size as usize > buf.len() {
+            return None;
+        }
+        Some(Self {
+            size,
+            ty: buf[4],
+            tag: u16::from_le_bytes(buf[5..7].try_into().unwrap()),
+        })
+    }
+}
+
+/// Returns the `msize` field of a `Tversion` or `Rversion` message.
+fn version_msize(msg: &[u8]) -> Option<u32> {
+    let bytes = msg.get(HEADER_LEN..HEADER_LEN + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Checks the request, exchanges it for the reply by `transfer`, which gets
+/// the reply buffer cut to the maximum message size, and checks the reply.
+///
+/// `msize` is updated if the request is a `Tversion` answered by `Rversion`.
+pub(crate) fn round_trip<F>(
+    msize: &mut u32,
+    request: &[u8],
+    response: &mut [u8],
+    transfer: F,
+) -> DevResult<usize>
+where
+    F: FnOnce(&[u8], &mut [u8]) -> DevResult<usize>,
+{
+    let req = MsgHeader::parse(request).ok_or(DevError::InvalidParam)?;
+    if req.size as usize != request.len() {
+        return Err(DevError::InvalidParam);
+    }
+    // `Tversion` may ask for a larger size, which is then negotiated.
+    let asked_msize = if req.ty == TVERSION {
+        Some(version_msize(request).ok_or(DevError::InvalidParam)?)
+    } else if req.size > *msize {
+        return Err(DevError::InvalidParam);
+    } else {
+        None
+    };
+    let resp_len = response.len().min(asked_msize.unwrap_or(*msize) as usize);
+    let len = transfer(request, &mut response[..resp_len])?.min(resp_len);
+
+    let resp = MsgHeader::parse(&response[..len]).ok_or_else(|| {
+        warn!("virtio-9p: malformed reply for message type {}", req.ty);
+        DevError::Io
+    })?;
+    if resp.tag != req.tag {
+        warn!(
+            "virtio-9p: reply tag {:#x} does not match request tag {:#x}",
+            resp.tag, req.tag
+        );
+        return Err(DevError::Io);
+    }
+    if let (Some(asked_msize), RVERSION) = (asked_msize, resp.ty) {
+        let server_msize = version_msize(&response[..len]).ok_or(DevError::Io)?;
+        // The server never increases the size asked by the client.
+        *msize = server_msize.min(asked_msize);
+        debug!("virtio-9p: negotiated msize {}", *msize);
+    }
+    Ok(resp.size as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `Tversion` message with `msize = 8192` and version "9P2000.L".
+    const TVERSION_MSG: &[u8] = &[
+        21, 0, 0, 0, TVERSION, 0xff, 0xff, 0x00, 0x20, 0, 0, 8, 0, b'9', b'P', b'2', b'0', b'0',
+        b'0', b'.', b'L',
+    ];
+
+    #[test]
+    fn test_parse_header() {
+        let hdr = MsgHeader::parse(TVERSION_MSG).unwrap();
+        assert_eq!(
+            hdr,
+            MsgHeader {
+                size: 21,
+                ty: TVERSION,
+                tag: 0xffff,
+            }
+        );
+        // Truncated messages are rejected.
+        assert!(MsgHeader::parse(&TVERSION_MSG[..HEADER_LEN - 1]).is_none());
+        assert!(MsgHeader::parse(&TVERSION_MSG[..20]).is_none());
+    }
+
+    #[test]
+    fn test_version_msize() {
+        let mut rversion = TVERSION_MSG.to_vec();
+        rversion[4] = RVERSION;
+        rversion[7..11].copy_from_slice(&4096u32.to_le_bytes());
+        assert_eq!(MsgHeader::parse(&rversion).unwrap().ty, RVERSION);
+        assert_eq!(version_msize(TVERSION_MSG), Some(8192));
+        assert_eq!(version_msize(&rversion), Some(4096));
+        assert_eq!(version_msize(&rversion[..HEADER_LEN]), None);
+    }
+
+    /// A server answering `Tversion` with `Rversion` of its own `msize`, and
+    /// the other messages with a header only.
+    fn server(msize: u32, tag_offset: u16) -> impl Fn(&[u8], &mut [u8]) -> DevResult<usize> {
+        move |req, resp| {
+            let hdr = MsgHeader::parse(req).unwrap();
+            let reply = if hdr.ty == TVERSION {
+                let mut reply = req.to_vec();
+                reply[4] = RVERSION;
+                reply[7..11].copy_from_slice(&msize.to_le_bytes());
+                reply
+            } else {
+                let mut reply = alloc::vec![HEADER_LEN as u8, 0, 0, 0, hdr.ty + 1, 0, 0];
+                reply[5..7].copy_from_slice(&hdr.tag.to_le_bytes());
+                reply
+            };
+            let tag = hdr.tag.wrapping_add(tag_offset);
+            let len = reply.len().min(resp.len());
+            resp[..len].copy_from_slice(&reply[..len]);
+            resp[5..7].copy_from_slice(&tag.to_le_bytes());
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_version_round_trip() {
+        let mut msize = DEFAULT_MSIZE;
+        let mut resp = [0; 64];
+        let len = round_trip(&mut msize, TVERSION_MSG, &mut resp, server(4096, 0)).unwrap();
+        assert_eq!(len, TVERSION_MSG.len());
+        assert_eq!(
+            MsgHeader::parse(&resp[..len]).unwrap(),
+            MsgHeader {
+                size: 21,
+                ty: RVERSION,
+                tag: 0xffff,
+            }
+        );
+        assert_eq!(&resp[11..len], &TVERSION_MSG[11..]);
+        // The smaller size of the server is taken.
+        assert_eq!(msize, 4096);
+
+        // A server never gets a larger size than asked.
+        let len = round_trip(&mut msize, TVERSION_MSG, &mut resp, server(65536, 0)).unwrap();
+        assert_eq!(len, TVERSION_MSG.len());
+        assert_eq!(msize, 8192);
+    }
+
+    #[test]
+    fn test_round_trip_checks() {
+        let mut msize = 16;
+        let mut resp = [0; 64];
+        // A `Tclunk` with tag 1 and fid 0.
+        let tclunk: &[u8] = &[11, 0, 0, 0, 120, 1, 0, 0, 0, 0, 0];
+        assert!(matches!(
+            round_trip(&mut msize, tclunk, &mut resp, server(0, 0)),
+            Ok(HEADER_LEN)
+        ));
+        // The reply must have the tag of the request.
+        assert!(matches!(
+            round_trip(&mut msize, tclunk, &mut resp, server(0, 1)),
+            Err(DevError::Io)
+        ));
+        // The requests must be whole messages within the maximum size.
+        assert!(matches!(
+            round_trip(&mut msize, &tclunk[..10], &mut resp, server(0, 0)),
+            Err(DevError::InvalidParam)
+        ));
+        msize = 8;
+        assert!(matches!(
+            round_trip(&mut msize, tclunk, &mut resp, server(0, 0)),
+            Err(DevError::InvalidParam)
+        ));
+        assert_eq!(msize, 8);
+    }
+}
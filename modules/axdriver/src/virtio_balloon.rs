@@ -0,0 +1,217 @@
+//! VirtIO memory balloon device.
+//!
+//! When the host raises the target size, pages are taken from the global
+//! allocator and reported on the inflate queue, so the host can reclaim them.
+//! When the target is lowered, the pages are reported on the deflate queue and
+//! returned to the allocator.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::ptr::{addr_of, addr_of_mut, NonNull};
+
+use axalloc::global_allocator;
+use axdriver_base::{BaseDriverOps, DevResult, DeviceType};
+use axhal::mem::virt_to_phys;
+use virtio_drivers::queue::VirtQueue;
+use virtio_drivers::transport::{DeviceStatus, Transport};
+use virtio_drivers::Hal;
+
+use crate::ops::BalloonDriverOps;
+use crate::virtio::{as_dev_err, negotiate_features};
+
+const INFLATE_QUEUE: u16 = 0;
+const DEFLATE_QUEUE: u16 = 1;
+const QUEUE_SIZE: usize = 2;
+
+const VIRTIO_BALLOON_F_MUST_TELL_HOST: u64 = 1 << 0;
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// The balloon always works on 4K pages.
+const BALLOON_PAGE_SIZE: usize = 0x1000;
+
+/// Maximum number of page frame numbers reported in one request.
+const PFNS_PER_REQ: usize = 256;
+
+/// The `virtio_balloon_config` structure in the device configuration space.
+#[repr(C)]
+struct VirtIoBalloonConfig {
+    num_pages: u32,
+    actual: u32,
+}
+
+/// The VirtIO memory balloon device driver.
+pub struct VirtIoBalloonDev<H: Hal, T: Transport> {
+    transport: T,
+    inflate_queue: VirtQueue<H, QUEUE_SIZE>,
+    deflate_queue: VirtQueue<H, QUEUE_SIZE>,
+    config: NonNull<VirtIoBalloonConfig>,
+    /// Virtual addresses of the pages held by the balloon.
+    pages: Vec<usize>,
+    pfns: Box<[u32; PFNS_PER_REQ]>,
+}
+
+unsafe impl<H: Hal, T: Transport> Send for VirtIoBalloonDev<H, T> {}
+unsafe impl<H: Hal, T: Transport> Sync for VirtIoBalloonDev<H, T> {}
+
+impl<H: Hal, T: Transport> VirtIoBalloonDev<H, T> {
+    /// Creates a new driver instance and initializes the device, or returns
+    /// an error if any step fails.
+    pub fn try_new(mut transport: T) -> DevResult<Self> {
+        let features = negotiate_features(
+            &mut transport,
+            VIRTIO_BALLOON_F_MUST_TELL_HOST | VIRTIO_F_VERSION_1,
+        );
+
+        let config = transport
+            .config_space::<VirtIoBalloonConfig>()
+            .map_err(as_dev_err)?;
+        let inflate_queue =
+            VirtQueue::new(&mut transport, INFLATE_QUEUE, false, false).map_err(as_dev_err)?;
+        let deflate_queue =
+            VirtQueue::new(&mut transport, DEFLATE_QUEUE, false, false).map_err(as_dev_err)?;
+        transport.finish_init();
+
+        Ok(Self {
+            transport,
+            inflate_queue,
+            deflate_queue,
+            config,
+            pages: Vec::new(),
+            pfns: Box::new([0; PFNS_PER_REQ]),
+        })
+    }
+
+    fn set_actual(&mut self, num_pages: u32) {
+        // Safe because the config space is mapped and has the expected layout.
+        unsafe { addr_of_mut!((*self.config.as_ptr()).actual).write_volatile(num_pages) };
+    }
+
+    /// Reports the pages in `self.pages[start..]` to the host, either on the
+    /// inflate queue or the deflate queue.
+    fn tell_host(&mut self, inflate: bool, start: usize) -> DevResult {
+        let pages = &self.pages[start..];
+        for (pfn, &vaddr) in self.pfns.iter_mut().zip(pages) {
+            *pfn = (virt_to_phys(vaddr.into()).as_usize() / BALLOON_PAGE_SIZE) as u32;
+        }
+        let (queue, queue_idx) = if inflate {
+            (&mut self.inflate_queue, INFLATE_QUEUE)
+        } else {
+            (&mut self.deflate_queue, DEFLATE_QUEUE)
+        };
+        let pfns = &self.pfns[..pages.len()];
+        // Safe because `u32` has no padding and any byte is valid for it.
+        let bytes = unsafe {
+            core::slice::from_raw_parts(pfns.as_ptr() as *const u8, core::mem::size_of_val(pfns))
+        };
+        trace!(
+            "virtio-balloon: report {} pages on queue {}",
+            pages.len(),
+            queue_idx
+        );
+        queue
+            .add_notify_wait_pop(&[bytes], &mut [], &mut self.transport)
+            .map_err(as_dev_err)?;
+        Ok(())
+    }
+
+    fn inflate(&mut self, target: usize) -> DevResult {
+        while self.pages.len() < target {
+            let start = self.pages.len();
+            let batch = (target - start).min(PFNS_PER_REQ);
+            for _ in 0..batch {
+                match global_allocator().alloc_pages(1, BALLOON_PAGE_SIZE) {
+                    Ok(vaddr) => self.pages.push(vaddr),
+                    Err(_) => break,
+                }
+            }
+            let allocated = self.pages.len() - start;
+            if allocated > 0 {
+                if let Err(e) = self.tell_host(true, start) {
+                    // The host does not know about these pages, just give them back.
+                    for vaddr in self.pages.drain(start..) {
+                        global_allocator().dealloc_pages(vaddr, 1);
+                    }
+                    return Err(e);
+                }
+            }
+            if allocated < batch {
+                warn!(
+                    "virtio-balloon: out of memory, {} of {} pages ballooned",
+                    self.pages.len(),
+                    target
+                );
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn deflate(&mut self, target: usize) -> DevResult {
+        while self.pages.len() > target {
+            let batch = (self.pages.len() - target).min(PFNS_PER_REQ);
+            let start = self.pages.len() - batch;
+            // The pages must not be used before the host is told.
+            self.tell_host(false, start)?;
+            for vaddr in self.pages.drain(start..) {
+                global_allocator().dealloc_pages(vaddr, 1);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<H: Hal, T: Transport> Drop for VirtIoBalloonDev<H, T> {
+    fn drop(&mut self) {
+        if self.deflate(0).is_err() {
+            // The host may still own the pages, so leak them.
+            warn!(
+                "virtio-balloon: failed to deflate, leaking {} pages",
+                self.pages.len()
+            );
+            self.pages.clear();
+        }
+        self.transport.queue_unset(INFLATE_QUEUE);
+        self.transport.queue_unset(DEFLATE_QUEUE);
+        // Reset the device so that it no longer accesses the freed memory.
+        self.transport.set_status(DeviceStatus::empty());
+    }
+}
+
+impl<H: Hal, T: Transport> BaseDriverOps for VirtIoBalloonDev<H, T> {
+    fn device_name(&self) -> &str {
+        "virtio-balloon"
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Char
+    }
+}
+
+impl<H: Hal, T: Transport> BalloonDriverOps for VirtIoBalloonDev<H, T> {
+    fn target_pages(&self) -> usize {
+        // Safe because the config space is mapped and has the expected layout.
+        unsafe { addr_of!((*self.config.as_ptr()).num_pages).read_volatile() as usize }
+    }
+
+    fn num_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    fn update(&mut self) -> DevResult<usize> {
+        let target = self.target_pages();
+        let res = if target > self.pages.len() {
+            self.inflate(target)
+        } else {
+            self.deflate(target)
+        };
+        // Always report what is actually ballooned, even on failure.
+        self.set_actual(self.pages.len() as u32);
+        if let Err(e) = res {
+            warn!(
+                "virtio-balloon: failed to resize to {} pages: {:?}",
+                target, e
+            );
+            return Err(e);
+        }
+        Ok(self.pages.len())
+    }
+}
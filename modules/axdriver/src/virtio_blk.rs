@@ -0,0 +1,285 @@
+//! VirtIO block device with flush and discard support.
+//!
+//! Requests are submitted one at a time, and the driver polls for their
+//! completion. If the device offers `VIRTIO_BLK_F_FLUSH`, it may cache writes,
+//! and [`flush`](BlockDriverOps::flush) sends a `VIRTIO_BLK_T_FLUSH` request
+//! to write them back. If it offers `VIRTIO_BLK_F_DISCARD`, discarded ranges
+//! are split by the limits in the config space.
+
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::ptr::{addr_of, NonNull};
+
+use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
+use axdriver_block::BlockDriverOps;
+use virtio_drivers::queue::VirtQueue;
+use virtio_drivers::transport::{DeviceStatus, Transport};
+use virtio_drivers::Hal;
+
+use crate::ops::BlockDiscardOps;
+use crate::virtio::{as_dev_err, negotiate_features};
+
+const SECTOR_SIZE: usize = 512;
+const QUEUE_SIZE: usize = 16;
+
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+const VIRTIO_BLK_F_DISCARD: u64 = 1 << 13;
+const VIRTIO_F_RING_INDIRECT_DESC: u64 = 1 << 28;
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+const SUPPORTED_FEATURES: u64 = VIRTIO_BLK_F_RO
+    | VIRTIO_BLK_F_FLUSH
+    | VIRTIO_BLK_F_DISCARD
+    | VIRTIO_F_RING_INDIRECT_DESC
+    | VIRTIO_F_VERSION_1;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_T_DISCARD: u32 = 11;
+
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+/// Size of a `virtio_blk_discard_write_zeroes` segment.
+const DISCARD_SEGMENT_SIZE: usize = 16;
+
+/// The `virtio_blk_config` structure in the device configuration space, up to
+/// the discard limits. The capacity is split as the config space may not
+/// support 64-bit accesses.
+#[repr(C)]
+#[allow(dead_code)]
+struct VirtIoBlkConfig {
+    capacity_low: u32,
+    capacity_high: u32,
+    size_max: u32,
+    seg_max: u32,
+    cylinders: u16,
+    heads: u8,
+    sectors: u8,
+    blk_size: u32,
+    physical_block_exp: u8,
+    alignment_offset: u8,
+    min_io_size: u16,
+    opt_io_size: u32,
+    writeback: u8,
+    unused0: u8,
+    num_queues: u16,
+    max_discard_sectors: u32,
+    max_discard_seg: u32,
+    discard_sector_alignment: u32,
+}
+
+/// Limits of discard requests, as read from the config space.
+#[derive(Clone, Copy)]
+struct DiscardLimits {
+    /// Maximum number of sectors in one segment.
+    max_sectors: u64,
+    /// Maximum number of segments in one request.
+    max_segments: usize,
+    /// Preferred alignment of the segments, in sectors.
+    alignment: u64,
+}
+
+/// The VirtIO block device driver.
+pub struct VirtIoBlkDev<H: Hal, T: Transport> {
+    transport: T,
+    queue: VirtQueue<H, QUEUE_SIZE>,
+    capacity: u64,
+    /// The negotiated features.
+    features: u64,
+    /// Present if `VIRTIO_BLK_F_DISCARD` is negotiated.
+    discard: Option<DiscardLimits>,
+}
+
+unsafe impl<H: Hal, T: Transport> Send for VirtIoBlkDev<H, T> {}
+unsafe impl<H: Hal, T: Transport> Sync for VirtIoBlkDev<H, T> {}
+
+impl<H: Hal, T: Transport> VirtIoBlkDev<H, T> {
+    /// Creates a new driver instance and initializes the device, or returns
+    /// an error if any step fails.
+    pub fn try_new(mut transport: T) -> DevResult<Self> {
+        let features = negotiate_features(&mut transport, SUPPORTED_FEATURES);
+        let config = transport
+            .config_space::<VirtIoBlkConfig>()
+            .map_err(as_dev_err)?;
+        let (capacity, discard) = unsafe { Self::read_config(config, features) };
+        let indirect = features & VIRTIO_F_RING_INDIRECT_DESC != 0;
+        let queue = VirtQueue::new(&mut transport, 0, indirect, false).map_err(as_dev_err)?;
+        transport.finish_init();
+
+        info!(
+            "virtio-blk: {} sectors, flush: {}, discard: {}",
+            capacity,
+            features & VIRTIO_BLK_F_FLUSH != 0,
+            discard.is_some()
+        );
+        Ok(Self {
+            transport,
+            queue,
+            capacity,
+            features,
+            discard,
+        })
+    }
+
+    /// Reads the capacity and the discard limits from the config space.
+    ///
+    /// # Safety
+    ///
+    /// `config` must point to the mapped config space of the device.
+    unsafe fn read_config(
+        config: NonNull<VirtIoBlkConfig>,
+        features: u64,
+    ) -> (u64, Option<DiscardLimits>) {
+        let cfg = config.as_ptr();
+        let capacity = core::ptr::read_volatile(addr_of!((*cfg).capacity_low)) as u64
+            | (core::ptr::read_volatile(addr_of!((*cfg).capacity_high)) as u64) << 32;
+        if features & VIRTIO_BLK_F_DISCARD == 0 {
+            return (capacity, None);
+        }
+        let max_sectors = core::ptr::read_volatile(addr_of!((*cfg).max_discard_sectors));
+        let max_segments = core::ptr::read_volatile(addr_of!((*cfg).max_discard_seg));
+        let alignment = core::ptr::read_volatile(addr_of!((*cfg).discard_sector_alignment));
+        let limits = DiscardLimits {
+            // Zero means no limit is given, fall back to one sector at a time.
+            max_sectors: (max_sectors as u64).max(1),
+            max_segments: (max_segments as usize).max(1),
+            alignment: (alignment as u64).max(1),
+        };
+        (capacity, Some(limits))
+    }
+
+    /// Submits a request and waits for its completion.
+    ///
+    /// `inputs` are read by the device after the request header, and `output`
+    /// is written by the device before the status byte.
+    fn request(
+        &mut self,
+        req_type: u32,
+        sector: u64,
+        inputs: &[&[u8]],
+        output: Option<&mut [u8]>,
+    ) -> DevResult {
+        let mut header = [0u8; 16];
+        header[..4].copy_from_slice(&req_type.to_le_bytes());
+        header[8..].copy_from_slice(&sector.to_le_bytes());
+        let mut status = [0xffu8];
+
+        let mut dev_inputs: Vec<&[u8]> = Vec::with_capacity(inputs.len() + 1);
+        dev_inputs.push(&header);
+        dev_inputs.extend_from_slice(inputs);
+        let res = match output {
+            Some(buf) => self.queue.add_notify_wait_pop(
+                &dev_inputs,
+                &mut [buf, &mut status[..]],
+                &mut self.transport,
+            ),
+            None => {
+                self.queue
+                    .add_notify_wait_pop(&dev_inputs, &mut [&mut status], &mut self.transport)
+            }
+        };
+        res.map_err(as_dev_err)?;
+        match status[0] {
+            VIRTIO_BLK_S_OK => Ok(()),
+            VIRTIO_BLK_S_UNSUPP => Err(DevError::Unsupported),
+            _ => Err(DevError::Io),
+        }
+    }
+
+    fn check_request(&self, block_id: u64, len: usize) -> DevResult {
+        if len == 0 || len % SECTOR_SIZE != 0 {
+            return Err(DevError::InvalidParam);
+        }
+        match block_id.checked_add((len / SECTOR_SIZE) as u64) {
+            Some(end) if end <= self.capacity => Ok(()),
+            _ => Err(DevError::InvalidParam),
+        }
+    }
+}
+
+impl<H: Hal, T: Transport> Drop for VirtIoBlkDev<H, T> {
+    fn drop(&mut self) {
+        // Stop the device before the queue is freed.
+        self.transport.set_status(DeviceStatus::empty());
+        self.transport.queue_unset(0);
+    }
+}
+
+impl<H: Hal, T: Transport> BaseDriverOps for VirtIoBlkDev<H, T> {
+    fn device_name(&self) -> &str {
+        "virtio-blk"
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Block
+    }
+}
+
+impl<H: Hal, T: Transport> BlockDriverOps for VirtIoBlkDev<H, T> {
+    #[inline]
+    fn num_blocks(&self) -> u64 {
+        self.capacity
+    }
+
+    #[inline]
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> DevResult {
+        self.check_request(block_id, buf.len())?;
+        self.request(VIRTIO_BLK_T_IN, block_id, &[], Some(buf))
+    }
+
+    fn write_block(&mut self, block_id: u64, buf: &[u8]) -> DevResult {
+        self.check_request(block_id, buf.len())?;
+        if self.features & VIRTIO_BLK_F_RO != 0 {
+            return Err(DevError::Unsupported);
+        }
+        self.request(VIRTIO_BLK_T_OUT, block_id, &[buf], None)
+    }
+
+    fn flush(&mut self) -> DevResult {
+        // Without a write cache, writes are stable once completed.
+        if self.features & VIRTIO_BLK_F_FLUSH == 0 {
+            return Ok(());
+        }
+        self.request(VIRTIO_BLK_T_FLUSH, 0, &[], None)
+    }
+}
+
+impl<H: Hal, T: Transport> BlockDiscardOps for VirtIoBlkDev<H, T> {
+    fn discard(&mut self, blocks: Range<u64>) -> DevResult {
+        let Some(limits) = self.discard else {
+            return Ok(());
+        };
+        if blocks.start > blocks.end || blocks.end > self.capacity {
+            return Err(DevError::InvalidParam);
+        }
+
+        let mut segments = Vec::new();
+        let mut sector = blocks.start;
+        while sector < blocks.end {
+            let mut end = (sector + limits.max_sectors).min(blocks.end);
+            // Split on the preferred alignment if the range is split anyway.
+            let aligned_end = end / limits.alignment * limits.alignment;
+            if end < blocks.end && aligned_end > sector {
+                end = aligned_end;
+            }
+            let mut segment = [0u8; DISCARD_SEGMENT_SIZE];
+            segment[..8].copy_from_slice(&sector.to_le_bytes());
+            segment[8..12].copy_from_slice(&((end - sector) as u32).to_le_bytes());
+            segments.push(segment);
+            sector = end;
+        }
+
+        for chunk in segments.chunks(limits.max_segments) {
+            let data: Vec<u8> = chunk.iter().flatten().copied().collect();
+            self.request(VIRTIO_BLK_T_DISCARD, 0, &[&data], None)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+//! VirtIO console device, exposed as a character device.
+
+use axdriver_base::{BaseDriverOps, DevResult, DeviceType};
+use virtio_drivers::device::console::VirtIOConsole;
+use virtio_drivers::transport::Transport;
+use virtio_drivers::Hal;
+
+use crate::ops::CharDriverOps;
+use crate::virtio::as_dev_err;
+
+/// The VirtIO console device driver.
+pub struct VirtIoConsoleDev<H: Hal, T: Transport> {
+    inner: VirtIOConsole<H, T>,
+}
+
+unsafe impl<H: Hal, T: Transport> Send for VirtIoConsoleDev<H, T> {}
+unsafe impl<H: Hal, T: Transport> Sync for VirtIoConsoleDev<H, T> {}
+
+impl<H: Hal, T: Transport> VirtIoConsoleDev<H, T> {
+    /// Creates a new driver instance and initializes the device, or returns
+    /// an error if any step fails.
+    pub fn try_new(transport: T) -> DevResult<Self> {
+        Ok(Self {
+            inner: VirtIOConsole::new(transport).map_err(as_dev_err)?,
+        })
+    }
+}
+
+impl<H: Hal, T: Transport> BaseDriverOps for VirtIoConsoleDev<H, T> {
+    fn device_name(&self) -> &str {
+        "virtio-console"
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Char
+    }
+}
+
+impl<H: Hal, T: Transport> CharDriverOps for VirtIoConsoleDev<H, T> {
+    fn read(&mut self, buf: &mut [u8]) -> DevResult<usize> {
+        let mut read_len = 0;
+        while read_len < buf.len() {
+            match self.inner.recv(true).map_err(as_dev_err)? {
+                Some(b) => {
+                    buf[read_len] = b;
+                    read_len += 1;
+                }
+                None => break,
+            }
+        }
+        Ok(read_len)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> DevResult<usize> {
+        for &b in buf {
+            self.inner.send(b).map_err(as_dev_err)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn poll_readable(&mut self) -> bool {
+        matches!(self.inner.recv(false), Ok(Some(_)))
+    }
+
+    fn poll_writable(&mut self) -> bool {
+        // `send` waits for the device to consume each byte.
+        true
+    }
+}
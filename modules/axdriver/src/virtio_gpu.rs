@@ -0,0 +1,306 @@
+//! VirtIO GPU device with partial flush and double buffering.
+//!
+//! Two host resources are created for the first scanout, each backed by its
+//! own framebuffer in guest memory. The front one is shown on the screen and
+//! returned by [`fb`](DisplayDriverOps::fb), the back one is drawn by the
+//! callers of [`DisplayBufferOps`] and shown by
+//! [`present`](DisplayBufferOps::present).
+//!
+//! Commands are submitted one at a time, and the driver polls for their
+//! completion, so the host has finished a flush when it returns.
+
+use alloc::vec::Vec;
+use core::ptr::NonNull;
+
+use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
+use axdriver_display::{DisplayDriverOps, DisplayInfo, FrameBuffer};
+use virtio_drivers::queue::VirtQueue;
+use virtio_drivers::transport::{DeviceStatus, Transport};
+use virtio_drivers::{BufferDirection, Hal, PhysAddr, PAGE_SIZE};
+
+use crate::ops::DisplayBufferOps;
+use crate::virtio::{as_dev_err, negotiate_features};
+
+const QUEUE_SIZE: usize = 2;
+const CONTROL_QUEUE: u16 = 0;
+
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+const CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+const CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+const CMD_SET_SCANOUT: u32 = 0x0103;
+const CMD_RESOURCE_FLUSH: u32 = 0x0104;
+const CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+const CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+
+const RESP_OK_NODATA: u32 = 0x1100;
+const RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+
+/// Size of `virtio_gpu_ctrl_hdr`.
+const CTRL_HDR_SIZE: usize = 24;
+/// Size of `virtio_gpu_resp_display_info`, with 16 scanouts.
+const DISPLAY_INFO_SIZE: usize = CTRL_HDR_SIZE + 16 * 24;
+
+const FORMAT_B8G8R8A8_UNORM: u32 = 1;
+const BYTES_PER_PIXEL: usize = 4;
+
+/// The scanout to show the framebuffers on.
+const SCANOUT_ID: u32 = 0;
+
+/// A host resource and the guest memory backing it.
+struct Resource {
+    id: u32,
+    paddr: PhysAddr,
+    vaddr: NonNull<u8>,
+    pages: usize,
+}
+
+/// The VirtIO GPU device driver with double buffering.
+pub struct VirtIoGpuDev<H: Hal, T: Transport> {
+    transport: T,
+    control_queue: VirtQueue<H, QUEUE_SIZE>,
+    width: u32,
+    height: u32,
+    /// The front resource, then the back one.
+    resources: [Resource; 2],
+}
+
+unsafe impl<H: Hal, T: Transport> Send for VirtIoGpuDev<H, T> {}
+unsafe impl<H: Hal, T: Transport> Sync for VirtIoGpuDev<H, T> {}
+
+/// Encodes a command of the given type, with the fields after the header.
+fn command(cmd_type: u32, fields: &[u32]) -> Vec<u8> {
+    let mut cmd = Vec::with_capacity(CTRL_HDR_SIZE + fields.len() * 4);
+    cmd.extend_from_slice(&cmd_type.to_le_bytes());
+    // `flags`, `fence_id`, `ctx_id` and the padding.
+    cmd.resize(CTRL_HDR_SIZE, 0);
+    for field in fields {
+        cmd.extend_from_slice(&field.to_le_bytes());
+    }
+    cmd
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+impl<H: Hal, T: Transport> VirtIoGpuDev<H, T> {
+    /// Creates a new driver instance and initializes the device, or returns
+    /// an error if any step fails.
+    pub fn try_new(mut transport: T) -> DevResult<Self> {
+        let features = negotiate_features(&mut transport, VIRTIO_F_VERSION_1);
+        let control_queue =
+            VirtQueue::new(&mut transport, CONTROL_QUEUE, false, false).map_err(as_dev_err)?;
+        transport.finish_init();
+
+        let mut dev = Self {
+            transport,
+            control_queue,
+            width: 0,
+            height: 0,
+            resources: [Resource::empty(1), Resource::empty(2)],
+        };
+        (dev.width, dev.height) = dev.display_size()?;
+        let fb_size = dev.fb_size();
+        for i in 0..dev.resources.len() {
+            dev.resources[i].alloc::<H>(fb_size)?;
+            dev.create_resource(i)?;
+        }
+        dev.show(dev.resources[0].id)?;
+        info!("virtio-gpu: {}x{}, double buffered", dev.width, dev.height);
+        Ok(dev)
+    }
+
+    fn fb_size(&self) -> usize {
+        self.width as usize * self.height as usize * BYTES_PER_PIXEL
+    }
+
+    /// Sends a command and waits for the response, which is checked to be of
+    /// type `resp_type`.
+    fn request(&mut self, cmd: &[u8], resp: &mut [u8], resp_type: u32) -> DevResult {
+        self.control_queue
+            .add_notify_wait_pop(&[cmd], &mut [resp], &mut self.transport)
+            .map_err(as_dev_err)?;
+        let ty = read_u32(resp, 0);
+        if ty == resp_type {
+            Ok(())
+        } else {
+            warn!(
+                "virtio-gpu: command {:#x} failed with {:#x}",
+                read_u32(cmd, 0),
+                ty
+            );
+            Err(DevError::Io)
+        }
+    }
+
+    fn request_nodata(&mut self, cmd_type: u32, fields: &[u32]) -> DevResult {
+        let mut resp = [0u8; CTRL_HDR_SIZE];
+        self.request(&command(cmd_type, fields), &mut resp, RESP_OK_NODATA)
+    }
+
+    /// Returns the size of the scanout.
+    fn display_size(&mut self) -> DevResult<(u32, u32)> {
+        let mut resp = [0u8; DISPLAY_INFO_SIZE];
+        self.request(
+            &command(CMD_GET_DISPLAY_INFO, &[]),
+            &mut resp,
+            RESP_OK_DISPLAY_INFO,
+        )?;
+        // The rectangle of the first scanout, after the header.
+        let pmode = CTRL_HDR_SIZE + SCANOUT_ID as usize * 24;
+        let (width, height) = (read_u32(&resp, pmode + 8), read_u32(&resp, pmode + 12));
+        if width == 0 || height == 0 {
+            return Err(DevError::BadState);
+        }
+        Ok((width, height))
+    }
+
+    /// Creates the host resource of `self.resources[index]` in the screen
+    /// size, and attaches its framebuffer as the backing.
+    fn create_resource(&mut self, index: usize) -> DevResult {
+        let (id, paddr) = (self.resources[index].id, self.resources[index].paddr as u64);
+        let (width, height) = (self.width, self.height);
+        let fb_size = self.fb_size() as u32;
+        self.request_nodata(
+            CMD_RESOURCE_CREATE_2D,
+            &[id, FORMAT_B8G8R8A8_UNORM, width, height],
+        )?;
+        // One entry: `addr`, `length` and the padding.
+        self.request_nodata(
+            CMD_RESOURCE_ATTACH_BACKING,
+            &[id, 1, paddr as u32, (paddr >> 32) as u32, fb_size, 0],
+        )
+    }
+
+    fn check_rect(&self, x: u32, y: u32, width: u32, height: u32) -> DevResult {
+        match (x.checked_add(width), y.checked_add(height)) {
+            (Some(right), Some(bottom)) if right <= self.width && bottom <= self.height => Ok(()),
+            _ => Err(DevError::InvalidParam),
+        }
+    }
+
+    /// Copies a rectangle of the framebuffer to the host resource `id`.
+    fn transfer(&mut self, id: u32, x: u32, y: u32, width: u32, height: u32) -> DevResult {
+        let offset = (y as u64 * self.width as u64 + x as u64) * BYTES_PER_PIXEL as u64;
+        let (lo, hi) = (offset as u32, (offset >> 32) as u32);
+        self.request_nodata(
+            CMD_TRANSFER_TO_HOST_2D,
+            &[x, y, width, height, lo, hi, id, 0],
+        )
+    }
+
+    /// Flushes a rectangle of the host resource `id` to the screen if it is
+    /// shown.
+    fn flush_resource(&mut self, id: u32, x: u32, y: u32, width: u32, height: u32) -> DevResult {
+        self.request_nodata(CMD_RESOURCE_FLUSH, &[x, y, width, height, id, 0])
+    }
+
+    /// Shows the resource `id` on the whole screen.
+    fn show(&mut self, id: u32) -> DevResult {
+        let (width, height) = (self.width, self.height);
+        self.request_nodata(CMD_SET_SCANOUT, &[0, 0, width, height, SCANOUT_ID, id])
+    }
+}
+
+impl Resource {
+    const fn empty(id: u32) -> Self {
+        Self {
+            id,
+            paddr: 0,
+            vaddr: NonNull::dangling(),
+            pages: 0,
+        }
+    }
+
+    /// Allocates the zeroed framebuffer.
+    fn alloc<H: Hal>(&mut self, size: usize) -> DevResult {
+        let pages = size.div_ceil(PAGE_SIZE);
+        let (paddr, vaddr) = H::dma_alloc(pages, BufferDirection::DriverToDevice);
+        if paddr == 0 {
+            return Err(DevError::NoMemory);
+        }
+        unsafe { core::ptr::write_bytes(vaddr.as_ptr(), 0, pages * PAGE_SIZE) };
+        (self.paddr, self.vaddr, self.pages) = (paddr, vaddr, pages);
+        Ok(())
+    }
+
+    fn info(&self, width: u32, height: u32) -> DisplayInfo {
+        DisplayInfo {
+            width,
+            height,
+            fb_base_vaddr: self.vaddr.as_ptr() as usize,
+            fb_size: width as usize * height as usize * BYTES_PER_PIXEL,
+        }
+    }
+}
+
+impl<H: Hal, T: Transport> Drop for VirtIoGpuDev<H, T> {
+    fn drop(&mut self) {
+        // Stop the device before the framebuffers are freed.
+        self.transport.set_status(DeviceStatus::empty());
+        self.transport.queue_unset(CONTROL_QUEUE);
+        for res in &self.resources {
+            if res.pages > 0 {
+                unsafe { H::dma_dealloc(res.paddr, res.vaddr, res.pages) };
+            }
+        }
+    }
+}
+
+impl<H: Hal, T: Transport> BaseDriverOps for VirtIoGpuDev<H, T> {
+    fn device_name(&self) -> &str {
+        "virtio-gpu"
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Display
+    }
+}
+
+impl<H: Hal, T: Transport> DisplayDriverOps for VirtIoGpuDev<H, T> {
+    fn info(&self) -> DisplayInfo {
+        self.resources[0].info(self.width, self.height)
+    }
+
+    fn fb(&self) -> FrameBuffer {
+        let info = self.info();
+        unsafe { FrameBuffer::from_raw_parts_mut(info.fb_base_vaddr as *mut u8, info.fb_size) }
+    }
+
+    fn need_flush(&self) -> bool {
+        true
+    }
+
+    fn flush(&mut self) -> DevResult {
+        self.flush_region(0, 0, self.width, self.height)
+    }
+}
+
+impl<H: Hal, T: Transport> DisplayBufferOps for VirtIoGpuDev<H, T> {
+    fn flush_region(&mut self, x: u32, y: u32, width: u32, height: u32) -> DevResult {
+        self.check_rect(x, y, width, height)?;
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+        let front = self.resources[0].id;
+        self.transfer(front, x, y, width, height)?;
+        self.flush_resource(front, x, y, width, height)
+    }
+
+    fn back_buffer_info(&self) -> DisplayInfo {
+        self.resources[1].info(self.width, self.height)
+    }
+
+    fn present(&mut self) -> DevResult {
+        let back = self.resources[1].id;
+        let (width, height) = (self.width, self.height);
+        // Upload the whole frame before it is shown, so no partial frame is
+        // ever on the screen.
+        self.transfer(back, 0, 0, width, height)?;
+        self.show(back)?;
+        self.flush_resource(back, 0, 0, width, height)?;
+        self.resources.swap(0, 1);
+        Ok(())
+    }
+}
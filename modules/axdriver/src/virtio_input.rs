@@ -0,0 +1,80 @@
+//! VirtIO input device.
+
+use alloc::collections::VecDeque;
+
+use axdriver_base::{BaseDriverOps, DevResult, DeviceType};
+use virtio_drivers::device::input::VirtIOInput;
+use virtio_drivers::transport::Transport;
+use virtio_drivers::Hal;
+
+use crate::ops::{InputDriverOps, InputEvent};
+use crate::virtio::as_dev_err;
+
+/// Maximum number of events buffered in the driver.
+const EVENT_BUF_LEN: usize = 256;
+
+/// The VirtIO input device driver.
+pub struct VirtIoInputDev<H: Hal, T: Transport> {
+    inner: VirtIOInput<H, T>,
+    events: VecDeque<InputEvent>,
+    dropped: usize,
+    irq: Option<usize>,
+}
+
+unsafe impl<H: Hal, T: Transport> Send for VirtIoInputDev<H, T> {}
+unsafe impl<H: Hal, T: Transport> Sync for VirtIoInputDev<H, T> {}
+
+impl<H: Hal, T: Transport> VirtIoInputDev<H, T> {
+    /// Creates a new driver instance and initializes the device, or returns
+    /// an error if any step fails.
+    ///
+    /// `irq` is the IRQ raised by the device, which is routed by the caller.
+    pub fn try_new(transport: T, irq: Option<usize>) -> DevResult<Self> {
+        Ok(Self {
+            inner: VirtIOInput::new(transport).map_err(as_dev_err)?,
+            events: VecDeque::with_capacity(EVENT_BUF_LEN),
+            dropped: 0,
+            irq,
+        })
+    }
+}
+
+impl<H: Hal, T: Transport> BaseDriverOps for VirtIoInputDev<H, T> {
+    fn device_name(&self) -> &str {
+        "virtio-input"
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Char
+    }
+}
+
+impl<H: Hal, T: Transport> InputDriverOps for VirtIoInputDev<H, T> {
+    fn poll_events(&mut self) {
+        self.inner.ack_interrupt();
+        while let Some(event) = self.inner.pop_pending_event() {
+            if self.events.len() >= EVENT_BUF_LEN {
+                self.dropped += 1;
+                continue;
+            }
+            self.events.push_back(InputEvent {
+                ev_type: event.event_type,
+                code: event.code,
+                value: event.value,
+            });
+        }
+    }
+
+    fn read_event(&mut self) -> Option<InputEvent> {
+        self.poll_events();
+        self.events.pop_front()
+    }
+
+    fn dropped_events(&self) -> usize {
+        self.dropped
+    }
+
+    fn irq_num(&self) -> Option<usize> {
+        self.irq
+    }
+}
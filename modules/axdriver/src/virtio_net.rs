@@ -0,0 +1,481 @@
+//! Multi-queue VirtIO network device.
+//!
+//! If the device offers `VIRTIO_NET_F_MQ`, up to one RX/TX queue pair per
+//! online CPU is enabled, and packets are transmitted on the queue pair of
+//! the current CPU. Otherwise it falls back to a single queue pair.
+//!
+//! Interrupts of all queues share one IRQ. Only the RX queues raise it, and
+//! only after [`NetIrqOps::enable_rx_interrupt`] is called. Transmitted
+//! buffers are always recycled by polling.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::ptr::{addr_of, addr_of_mut, NonNull};
+
+use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
+use axdriver_net::{EthernetAddress, NetBuf, NetBufBox, NetBufPool, NetBufPtr, NetDriverOps};
+use virtio_drivers::queue::VirtQueue;
+use virtio_drivers::transport::{DeviceStatus, Transport};
+use virtio_drivers::Hal;
+
+use crate::ops::{NetIrqOps, NetStats};
+use crate::virtio::{as_dev_err, negotiate_features};
+
+const NET_BUF_LEN: usize = 1526;
+
+/// Size of `virtio_net_hdr` when `VIRTIO_F_VERSION_1` is negotiated.
+const NET_HDR_SIZE: usize = 12;
+/// Size of `virtio_net_hdr` of legacy devices, without `num_buffers`.
+const NET_HDR_SIZE_LEGACY: usize = 10;
+
+const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+const VIRTIO_NET_F_STATUS: u64 = 1 << 16;
+const VIRTIO_NET_F_CTRL_VQ: u64 = 1 << 17;
+const VIRTIO_NET_F_MQ: u64 = 1 << 22;
+const VIRTIO_F_RING_INDIRECT_DESC: u64 = 1 << 28;
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+const VIRTIO_NET_F_SPEED_DUPLEX: u64 = 1 << 63;
+
+const SUPPORTED_FEATURES: u64 = VIRTIO_NET_F_MAC
+    | VIRTIO_NET_F_STATUS
+    | VIRTIO_NET_F_CTRL_VQ
+    | VIRTIO_NET_F_MQ
+    | VIRTIO_F_RING_INDIRECT_DESC
+    | VIRTIO_F_VERSION_1
+    | VIRTIO_NET_F_SPEED_DUPLEX;
+
+const VIRTIO_NET_S_LINK_UP: u16 = 1;
+/// The `speed` field of a device that does not know its speed.
+const VIRTIO_NET_SPEED_UNKNOWN: u32 = u32::MAX;
+
+const VIRTIO_NET_CTRL_MQ: u8 = 4;
+const VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET: u8 = 0;
+const VIRTIO_NET_OK: u8 = 0;
+
+/// The `virtio_net_config` structure in the device configuration space.
+#[repr(C)]
+#[allow(dead_code)]
+struct VirtIoNetConfig {
+    mac: [u8; 6],
+    status: u16,
+    max_virtqueue_pairs: u16,
+    mtu: u16,
+    speed: u32,
+    duplex: u8,
+}
+
+/// A pair of RX/TX virtqueues, with the buffers currently owned by the device.
+struct QueuePair<H: Hal, const QS: usize> {
+    rx_idx: u16,
+    tx_idx: u16,
+    rx_queue: VirtQueue<H, QS>,
+    tx_queue: VirtQueue<H, QS>,
+    rx_buffers: Vec<Option<NetBufBox>>,
+    tx_buffers: Vec<Option<NetBufBox>>,
+}
+
+/// The VirtIO network device driver with multi-queue support.
+///
+/// `QS` is the size of each virtqueue.
+pub struct VirtIoNetMqDev<H: Hal, T: Transport, const QS: usize> {
+    transport: T,
+    config: NonNull<VirtIoNetConfig>,
+    /// The negotiated features.
+    features: u64,
+    /// Size of the header before each packet.
+    hdr_len: usize,
+    mac: EthernetAddress,
+    queues: Vec<QueuePair<H, QS>>,
+    /// The control queue, only present if multi-queue is negotiated.
+    ctrl_queue: Option<VirtQueue<H, 2>>,
+    free_tx_bufs: Vec<NetBufBox>,
+    buf_pool: Arc<NetBufPool>,
+    /// The queue pair to be checked first on the next `receive`.
+    next_rx_queue: usize,
+    /// The IRQ shared by all queues, if any.
+    irq: Option<usize>,
+    /// Counted in software, as the device has no counters.
+    stats: NetStats,
+}
+
+unsafe impl<H: Hal, T: Transport, const QS: usize> Send for VirtIoNetMqDev<H, T, QS> {}
+unsafe impl<H: Hal, T: Transport, const QS: usize> Sync for VirtIoNetMqDev<H, T, QS> {}
+
+impl<H: Hal, const QS: usize> QueuePair<H, QS> {
+    fn new<T: Transport>(transport: &mut T, pair: u16, indirect: bool) -> DevResult<Self> {
+        let (rx_idx, tx_idx) = (pair * 2, pair * 2 + 1);
+        let mut rx_queue =
+            VirtQueue::new(transport, rx_idx, indirect, false).map_err(as_dev_err)?;
+        let mut tx_queue =
+            VirtQueue::new(transport, tx_idx, indirect, false).map_err(as_dev_err)?;
+        rx_queue.set_dev_notify(false);
+        tx_queue.set_dev_notify(false);
+        Ok(Self {
+            rx_idx,
+            tx_idx,
+            rx_queue,
+            tx_queue,
+            rx_buffers: (0..QS).map(|_| None).collect(),
+            tx_buffers: (0..QS).map(|_| None).collect(),
+        })
+    }
+
+    /// Adds an empty buffer to the RX queue.
+    fn add_rx_buffer<T: Transport>(
+        &mut self,
+        transport: &mut T,
+        mut rx_buf: NetBufBox,
+    ) -> DevResult {
+        // Safe because the buffer lives as long as it is stored in `rx_buffers`.
+        let token =
+            unsafe { self.rx_queue.add(&[], &mut [rx_buf.raw_buf_mut()]) }.map_err(as_dev_err)?;
+        let slot = &mut self.rx_buffers[token as usize];
+        if slot.is_some() {
+            return Err(DevError::BadState);
+        }
+        *slot = Some(rx_buf);
+        if self.rx_queue.should_notify() {
+            transport.notify(self.rx_idx);
+        }
+        Ok(())
+    }
+}
+
+impl<H: Hal, T: Transport, const QS: usize> VirtIoNetMqDev<H, T, QS> {
+    /// Creates a new driver instance and initializes the device, or returns
+    /// an error if any step fails.
+    ///
+    /// `irq` is the IRQ raised by the device, which is routed by the caller.
+    pub fn try_new(mut transport: T, irq: Option<usize>) -> DevResult<Self> {
+        let features = negotiate_features(&mut transport, SUPPORTED_FEATURES);
+        let config = transport
+            .config_space::<VirtIoNetConfig>()
+            .map_err(as_dev_err)?;
+        // Safe because the config space is mapped and has the expected layout.
+        let (mac, max_pairs) = unsafe {
+            let cfg = config.as_ptr();
+            (
+                core::ptr::read_volatile(addr_of!((*cfg).mac)),
+                core::ptr::read_volatile(addr_of!((*cfg).max_virtqueue_pairs)),
+            )
+        };
+
+        let mq = features & VIRTIO_NET_F_MQ != 0 && features & VIRTIO_NET_F_CTRL_VQ != 0;
+        let num_pairs = if mq {
+            (max_pairs as usize).clamp(1, axconfig::SMP) as u16
+        } else {
+            1
+        };
+        let indirect = features & VIRTIO_F_RING_INDIRECT_DESC != 0;
+
+        let mut queues = Vec::with_capacity(num_pairs as usize);
+        for pair in 0..num_pairs {
+            queues.push(QueuePair::new(&mut transport, pair, indirect)?);
+        }
+        // The control queue is always the last one, after all possible queue pairs.
+        let mut ctrl_queue = if mq {
+            Some(
+                VirtQueue::<H, 2>::new(&mut transport, max_pairs * 2, false, false)
+                    .map_err(as_dev_err)?,
+            )
+        } else {
+            None
+        };
+        transport.finish_init();
+
+        if let Some(ctrl_queue) = ctrl_queue.as_mut() {
+            Self::set_queue_pairs(&mut transport, ctrl_queue, num_pairs)?;
+        }
+        info!(
+            "virtio-net: MAC {:02x?}, {} queue pair(s) enabled (max {})",
+            mac,
+            num_pairs,
+            if mq { max_pairs } else { 1 }
+        );
+
+        let hdr_len = if features & VIRTIO_F_VERSION_1 != 0 {
+            NET_HDR_SIZE
+        } else {
+            NET_HDR_SIZE_LEGACY
+        };
+        let rx_buffers_total = QS * num_pairs as usize;
+        let buf_pool = NetBufPool::new(2 * rx_buffers_total, NET_BUF_LEN)?;
+        let mut dev = Self {
+            transport,
+            config,
+            features,
+            hdr_len,
+            mac: EthernetAddress(mac),
+            queues,
+            ctrl_queue,
+            free_tx_bufs: Vec::with_capacity(rx_buffers_total),
+            buf_pool,
+            next_rx_queue: 0,
+            irq,
+            stats: NetStats::default(),
+        };
+
+        for q in 0..dev.queues.len() {
+            for _ in 0..QS {
+                let rx_buf = dev.buf_pool.alloc_boxed().ok_or(DevError::NoMemory)?;
+                dev.queues[q].add_rx_buffer(&mut dev.transport, rx_buf)?;
+            }
+        }
+        for _ in 0..rx_buffers_total {
+            let mut tx_buf = dev.buf_pool.alloc_boxed().ok_or(DevError::NoMemory)?;
+            // Fill the header with zeros, the device ignores it for plain packets.
+            tx_buf.set_header_len(hdr_len);
+            tx_buf.raw_buf_mut()[..hdr_len].fill(0);
+            dev.free_tx_bufs.push(tx_buf);
+        }
+        Ok(dev)
+    }
+
+    /// Returns the number of enabled RX/TX queue pairs.
+    pub fn num_queues(&self) -> usize {
+        self.queues.len()
+    }
+
+    /// Transmits a packet on the given queue pair.
+    pub fn transmit_on(&mut self, queue: usize, tx_buf: NetBufPtr) -> DevResult {
+        let pair = self.queues.get_mut(queue).ok_or(DevError::InvalidParam)?;
+        let tx_buf = unsafe { NetBuf::from_buf_ptr(tx_buf) };
+        // Safe because the buffer lives as long as it is stored in `tx_buffers`.
+        let token = match unsafe { pair.tx_queue.add(&[tx_buf.packet_with_header()], &mut []) } {
+            Ok(token) => token,
+            Err(e) => {
+                if matches!(e, virtio_drivers::Error::QueueFull) {
+                    self.stats.tx_queue_full += 1;
+                }
+                return Err(as_dev_err(e));
+            }
+        };
+        self.stats.tx_packets += 1;
+        self.stats.tx_bytes += tx_buf.packet_len() as u64;
+        pair.tx_buffers[token as usize] = Some(tx_buf);
+        trace!("virtio-net: TX on queue {}", queue);
+        if pair.tx_queue.should_notify() {
+            self.transport.notify(pair.tx_idx);
+        }
+        Ok(())
+    }
+
+    /// The queue pair used for transmission by the current CPU.
+    fn current_tx_queue(&self) -> usize {
+        axhal::cpu::this_cpu_id() % self.queues.len()
+    }
+
+    /// Sends `VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET` through the control queue.
+    fn set_queue_pairs(
+        transport: &mut T,
+        ctrl_queue: &mut VirtQueue<H, 2>,
+        num_pairs: u16,
+    ) -> DevResult {
+        let hdr = [VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET];
+        let data = num_pairs.to_le_bytes();
+        let mut ack = [0xffu8];
+        ctrl_queue
+            .add_notify_wait_pop(&[&hdr, &data], &mut [&mut ack], transport)
+            .map_err(as_dev_err)?;
+        if ack[0] == VIRTIO_NET_OK {
+            Ok(())
+        } else {
+            warn!("virtio-net: failed to set {} queue pairs", num_pairs);
+            Err(DevError::Io)
+        }
+    }
+}
+
+impl<H: Hal, T: Transport, const QS: usize> Drop for VirtIoNetMqDev<H, T, QS> {
+    fn drop(&mut self) {
+        // Stop the device before the buffers it owns are freed.
+        self.transport.set_status(DeviceStatus::empty());
+        for pair in &self.queues {
+            self.transport.queue_unset(pair.rx_idx);
+            self.transport.queue_unset(pair.tx_idx);
+        }
+    }
+}
+
+impl<H: Hal, T: Transport, const QS: usize> BaseDriverOps for VirtIoNetMqDev<H, T, QS> {
+    fn device_name(&self) -> &str {
+        "virtio-net"
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Net
+    }
+}
+
+impl<H: Hal, T: Transport, const QS: usize> NetDriverOps for VirtIoNetMqDev<H, T, QS> {
+    #[inline]
+    fn mac_address(&self) -> EthernetAddress {
+        self.mac
+    }
+
+    #[inline]
+    fn can_transmit(&self) -> bool {
+        !self.free_tx_bufs.is_empty()
+            && self.queues[self.current_tx_queue()]
+                .tx_queue
+                .available_desc()
+                >= 1
+    }
+
+    #[inline]
+    fn can_receive(&self) -> bool {
+        self.queues.iter().any(|q| q.rx_queue.can_pop())
+    }
+
+    #[inline]
+    fn rx_queue_size(&self) -> usize {
+        QS * self.queues.len()
+    }
+
+    #[inline]
+    fn tx_queue_size(&self) -> usize {
+        QS * self.queues.len()
+    }
+
+    fn recycle_rx_buffer(&mut self, rx_buf: NetBufPtr) -> DevResult {
+        let mut rx_buf = unsafe { NetBuf::from_buf_ptr(rx_buf) };
+        rx_buf.set_header_len(0);
+        rx_buf.set_packet_len(0);
+        // Any RX queue can take the buffer, prefer the one with most free slots.
+        let queue = self
+            .queues
+            .iter_mut()
+            .max_by_key(|q| q.rx_queue.available_desc())
+            .ok_or(DevError::BadState)?;
+        queue.add_rx_buffer(&mut self.transport, rx_buf)
+    }
+
+    fn recycle_tx_buffers(&mut self) -> DevResult {
+        for pair in self.queues.iter_mut() {
+            while let Some(token) = pair.tx_queue.peek_used() {
+                let tx_buf = pair.tx_buffers[token as usize]
+                    .take()
+                    .ok_or(DevError::BadState)?;
+                // Safe because the buffer is the same one passed to `add`.
+                unsafe {
+                    pair.tx_queue
+                        .pop_used(token, &[tx_buf.packet_with_header()], &mut [])
+                        .map_err(as_dev_err)?;
+                }
+                self.free_tx_bufs.push(tx_buf);
+            }
+        }
+        Ok(())
+    }
+
+    fn transmit(&mut self, tx_buf: NetBufPtr) -> DevResult {
+        self.transmit_on(self.current_tx_queue(), tx_buf)
+    }
+
+    fn receive(&mut self) -> DevResult<NetBufPtr> {
+        let (num_queues, hdr_len) = (self.queues.len(), self.hdr_len);
+        for i in 0..num_queues {
+            let q = (self.next_rx_queue + i) % num_queues;
+            let pair = &mut self.queues[q];
+            if let Some(token) = pair.rx_queue.peek_used() {
+                let mut rx_buf = pair.rx_buffers[token as usize]
+                    .take()
+                    .ok_or(DevError::BadState)?;
+                // Safe because the buffer is the same one passed to `add`.
+                let len = unsafe {
+                    pair.rx_queue
+                        .pop_used(token, &[], &mut [rx_buf.raw_buf_mut()])
+                        .map_err(as_dev_err)?
+                } as usize;
+                if len < hdr_len {
+                    // Give the buffer back to the device, or the queue runs
+                    // out of buffers after enough bad packets.
+                    warn!(
+                        "virtio-net: dropped a packet of {} bytes on queue {}",
+                        len, q
+                    );
+                    pair.add_rx_buffer(&mut self.transport, rx_buf)?;
+                    return Err(DevError::BadState);
+                }
+                rx_buf.set_header_len(hdr_len);
+                rx_buf.set_packet_len(len - hdr_len);
+                self.stats.rx_packets += 1;
+                self.stats.rx_bytes += (len - hdr_len) as u64;
+                trace!("virtio-net: RX on queue {}", q);
+                // Serve the queues in a round-robin fashion.
+                self.next_rx_queue = (q + 1) % num_queues;
+                return Ok(rx_buf.into_buf_ptr());
+            }
+        }
+        Err(DevError::Again)
+    }
+
+    fn alloc_tx_buffer(&mut self, size: usize) -> DevResult<NetBufPtr> {
+        let mut net_buf = self.free_tx_bufs.pop().ok_or(DevError::NoMemory)?;
+        let pkt_len = size;
+        let hdr_len = net_buf.header_len();
+        if hdr_len + pkt_len > net_buf.capacity() {
+            return Err(DevError::InvalidParam);
+        }
+        net_buf.set_packet_len(pkt_len);
+        Ok(net_buf.into_buf_ptr())
+    }
+}
+
+impl<H: Hal, T: Transport, const QS: usize> NetIrqOps for VirtIoNetMqDev<H, T, QS> {
+    fn irq_num(&self) -> Option<usize> {
+        self.irq
+    }
+
+    fn enable_rx_interrupt(&mut self, enable: bool) -> DevResult {
+        if self.irq.is_none() {
+            return Err(DevError::Unsupported);
+        }
+        for pair in self.queues.iter_mut() {
+            pair.rx_queue.set_dev_notify(enable);
+        }
+        Ok(())
+    }
+
+    fn ack_interrupt(&mut self) -> bool {
+        self.transport.ack_interrupt()
+    }
+
+    fn link_up(&self) -> bool {
+        if self.features & VIRTIO_NET_F_STATUS == 0 {
+            return true;
+        }
+        // Safe because the config space is mapped and has the expected layout.
+        let status = unsafe { core::ptr::read_volatile(addr_of!((*self.config.as_ptr()).status)) };
+        status & VIRTIO_NET_S_LINK_UP != 0
+    }
+
+    fn link_speed(&self) -> Option<u32> {
+        if self.features & VIRTIO_NET_F_SPEED_DUPLEX == 0 || !self.link_up() {
+            return None;
+        }
+        // Safe because the config space is mapped and has the expected layout.
+        let speed = unsafe { core::ptr::read_volatile(addr_of!((*self.config.as_ptr()).speed)) };
+        Some(speed).filter(|&speed| speed != VIRTIO_NET_SPEED_UNKNOWN)
+    }
+
+    fn set_mac_address(&mut self, mac: EthernetAddress) -> DevResult {
+        // The MAC address in the config space is only writable by the driver
+        // of a legacy device.
+        if self.features & VIRTIO_NET_F_MAC == 0 || self.features & VIRTIO_F_VERSION_1 != 0 {
+            return Err(DevError::Unsupported);
+        }
+        // Safe because the config space is mapped and has the expected layout.
+        unsafe {
+            let cfg_mac = addr_of_mut!((*self.config.as_ptr()).mac) as *mut u8;
+            for (i, &b) in mac.0.iter().enumerate() {
+                core::ptr::write_volatile(cfg_mac.add(i), b);
+            }
+        }
+        self.mac = mac;
+        Ok(())
+    }
+
+    fn stats(&self) -> NetStats {
+        self.stats
+    }
+}
@@ -0,0 +1,140 @@
+//! VirtIO entropy device.
+//!
+//! The driver keeps one buffer submitted to the device, which is filled in the
+//! background. Reads are served from the filled bytes and only wait for the
+//! device if nothing has been received yet.
+
+use alloc::boxed::Box;
+
+use axdriver_base::{BaseDriverOps, DevResult, DeviceType};
+use virtio_drivers::queue::VirtQueue;
+use virtio_drivers::transport::{DeviceStatus, Transport};
+use virtio_drivers::Hal;
+
+use crate::ops::RngDriverOps;
+use crate::virtio::{as_dev_err, negotiate_features};
+
+const QUEUE_REQUEST: u16 = 0;
+const QUEUE_SIZE: usize = 2;
+
+/// Size of the buffer filled by the device for each request.
+const RNG_BUF_LEN: usize = 256;
+
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// The VirtIO entropy device driver.
+pub struct VirtIoRngDev<H: Hal, T: Transport> {
+    transport: T,
+    queue: VirtQueue<H, QUEUE_SIZE>,
+    buf: Box<[u8; RNG_BUF_LEN]>,
+    /// Token of the request that owns `buf`, or `None` if the driver owns it.
+    pending: Option<u16>,
+    /// Range of the bytes in `buf` that have not been consumed.
+    start: usize,
+    end: usize,
+}
+
+unsafe impl<H: Hal, T: Transport> Send for VirtIoRngDev<H, T> {}
+unsafe impl<H: Hal, T: Transport> Sync for VirtIoRngDev<H, T> {}
+
+impl<H: Hal, T: Transport> VirtIoRngDev<H, T> {
+    /// Creates a new driver instance and initializes the device, or returns
+    /// an error if any step fails.
+    pub fn try_new(mut transport: T) -> DevResult<Self> {
+        let features = negotiate_features(&mut transport, VIRTIO_F_VERSION_1);
+
+        let queue =
+            VirtQueue::new(&mut transport, QUEUE_REQUEST, false, false).map_err(as_dev_err)?;
+        transport.finish_init();
+
+        let mut dev = Self {
+            transport,
+            queue,
+            buf: Box::new([0; RNG_BUF_LEN]),
+            pending: None,
+            start: 0,
+            end: 0,
+        };
+        // Start collecting entropy right away.
+        dev.request_refill()?;
+        Ok(dev)
+    }
+
+    /// Hands the buffer to the device to be filled.
+    fn request_refill(&mut self) -> DevResult {
+        // Safe because the buffer is not touched until the request completes.
+        let token =
+            unsafe { self.queue.add(&[], &mut [self.buf.as_mut_slice()]) }.map_err(as_dev_err)?;
+        self.pending = Some(token);
+        if self.queue.should_notify() {
+            self.transport.notify(QUEUE_REQUEST);
+        }
+        Ok(())
+    }
+
+    /// Takes the buffer back if the pending request has completed.
+    ///
+    /// Returns `false` if the device is still working on it.
+    fn poll_refill(&mut self, token: u16) -> DevResult<bool> {
+        if !self.queue.can_pop() {
+            return Ok(false);
+        }
+        // Safe because the buffer is the same one passed to `add`.
+        let len = unsafe {
+            self.queue
+                .pop_used(token, &[], &mut [self.buf.as_mut_slice()])
+                .map_err(as_dev_err)?
+        };
+        self.pending = None;
+        self.start = 0;
+        self.end = (len as usize).min(RNG_BUF_LEN);
+        Ok(true)
+    }
+}
+
+impl<H: Hal, T: Transport> Drop for VirtIoRngDev<H, T> {
+    fn drop(&mut self) {
+        // Stop the device from writing to the buffer after it is freed.
+        self.transport.queue_unset(QUEUE_REQUEST);
+        // Reset the device so that it no longer accesses the freed memory.
+        self.transport.set_status(DeviceStatus::empty());
+    }
+}
+
+impl<H: Hal, T: Transport> BaseDriverOps for VirtIoRngDev<H, T> {
+    fn device_name(&self) -> &str {
+        "virtio-rng"
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Char
+    }
+}
+
+impl<H: Hal, T: Transport> RngDriverOps for VirtIoRngDev<H, T> {
+    fn fill_bytes(&mut self, buf: &mut [u8]) -> DevResult<usize> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            if self.start < self.end {
+                let n = (self.end - self.start).min(buf.len() - filled);
+                buf[filled..filled + n].copy_from_slice(&self.buf[self.start..self.start + n]);
+                self.start += n;
+                filled += n;
+            } else if let Some(token) = self.pending {
+                if !self.poll_refill(token)? {
+                    if filled > 0 {
+                        // Return what we have rather than waiting for more.
+                        break;
+                    }
+                    core::hint::spin_loop();
+                }
+            } else {
+                self.request_refill()?;
+            }
+        }
+        if self.start == self.end && self.pending.is_none() {
+            self.request_refill()?;
+        }
+        Ok(filled)
+    }
+}
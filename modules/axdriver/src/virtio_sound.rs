@@ -0,0 +1,352 @@
+//! VirtIO sound device, only playback is supported.
+//!
+//! Control requests are sent synchronously on the control queue. PCM periods
+//! are copied into driver-owned buffers and queued on the TX queue, and their
+//! completion is collected by polling.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::ptr::addr_of;
+
+use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
+use virtio_drivers::queue::VirtQueue;
+use virtio_drivers::transport::{DeviceStatus, Transport};
+use virtio_drivers::Hal;
+
+use crate::ops::{JackInfo, PcmDirection, PcmParams, PcmStreamInfo, SoundDriverOps};
+use crate::virtio::{as_dev_err, negotiate_features};
+
+const CONTROL_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 2;
+const CONTROL_QUEUE_SIZE: usize = 4;
+const TX_QUEUE_SIZE: usize = 32;
+
+/// Each period uses 3 descriptors: the header, the frames and the status.
+const MAX_INFLIGHT_PERIODS: usize = TX_QUEUE_SIZE / 3;
+
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+const VIRTIO_SND_R_JACK_INFO: u32 = 1;
+const VIRTIO_SND_R_PCM_INFO: u32 = 0x0100;
+const VIRTIO_SND_R_PCM_SET_PARAMS: u32 = 0x0101;
+const VIRTIO_SND_R_PCM_PREPARE: u32 = 0x0102;
+const VIRTIO_SND_R_PCM_RELEASE: u32 = 0x0103;
+const VIRTIO_SND_R_PCM_START: u32 = 0x0104;
+const VIRTIO_SND_R_PCM_STOP: u32 = 0x0105;
+
+const VIRTIO_SND_S_OK: u32 = 0x8000;
+const VIRTIO_SND_S_BAD_MSG: u32 = 0x8001;
+const VIRTIO_SND_S_NOT_SUPP: u32 = 0x8002;
+
+const VIRTIO_SND_D_OUTPUT: u8 = 0;
+
+/// Size of `virtio_snd_jack_info`.
+const JACK_INFO_SIZE: usize = 24;
+/// Size of `virtio_snd_pcm_info`.
+const PCM_INFO_SIZE: usize = 32;
+
+/// The `virtio_snd_config` structure in the device configuration space.
+#[repr(C)]
+struct VirtIoSoundConfig {
+    jacks: u32,
+    streams: u32,
+    chmaps: u32,
+}
+
+/// A period queued on the TX queue, with the buffers owned by the device.
+struct TxPeriod {
+    /// `virtio_snd_pcm_xfer`.
+    xfer: [u8; 4],
+    frames: Vec<u8>,
+    /// `virtio_snd_pcm_status`.
+    status: [u8; 8],
+}
+
+/// The VirtIO sound device driver.
+pub struct VirtIoSoundDev<H: Hal, T: Transport> {
+    transport: T,
+    control_queue: VirtQueue<H, CONTROL_QUEUE_SIZE>,
+    tx_queue: VirtQueue<H, TX_QUEUE_SIZE>,
+    jacks: u32,
+    streams: u32,
+    /// Parameters set for each stream.
+    params: Vec<Option<PcmParams>>,
+    /// Whether each stream is started.
+    running: Vec<bool>,
+    /// Periods in flight, indexed by the descriptor token.
+    tx_periods: Vec<Option<Box<TxPeriod>>>,
+    inflight: usize,
+    /// Periods completed but not yet returned by `poll_completed`.
+    completed: usize,
+    /// Set when an underrun is reported, cleared by the next submission.
+    underrun_reported: bool,
+}
+
+unsafe impl<H: Hal, T: Transport> Send for VirtIoSoundDev<H, T> {}
+unsafe impl<H: Hal, T: Transport> Sync for VirtIoSoundDev<H, T> {}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+const fn check_status(status: u32) -> DevResult {
+    match status {
+        VIRTIO_SND_S_OK => Ok(()),
+        VIRTIO_SND_S_BAD_MSG => Err(DevError::InvalidParam),
+        VIRTIO_SND_S_NOT_SUPP => Err(DevError::Unsupported),
+        _ => Err(DevError::Io),
+    }
+}
+
+impl<H: Hal, T: Transport> VirtIoSoundDev<H, T> {
+    /// Creates a new driver instance and initializes the device, or returns
+    /// an error if any step fails.
+    pub fn try_new(mut transport: T) -> DevResult<Self> {
+        let features = negotiate_features(&mut transport, VIRTIO_F_VERSION_1);
+
+        let config = transport
+            .config_space::<VirtIoSoundConfig>()
+            .map_err(as_dev_err)?;
+        // Safe because the config space is mapped and has the expected layout.
+        let (jacks, streams) = unsafe {
+            let cfg = config.as_ptr();
+            (
+                addr_of!((*cfg).jacks).read_volatile(),
+                addr_of!((*cfg).streams).read_volatile(),
+            )
+        };
+        let control_queue =
+            VirtQueue::new(&mut transport, CONTROL_QUEUE, false, false).map_err(as_dev_err)?;
+        let tx_queue =
+            VirtQueue::new(&mut transport, TX_QUEUE, false, false).map_err(as_dev_err)?;
+        transport.finish_init();
+
+        info!("virtio-sound: {} jack(s), {} stream(s)", jacks, streams);
+        Ok(Self {
+            transport,
+            control_queue,
+            tx_queue,
+            jacks,
+            streams,
+            params: (0..streams).map(|_| None).collect(),
+            running: (0..streams).map(|_| false).collect(),
+            tx_periods: (0..TX_QUEUE_SIZE).map(|_| None).collect(),
+            inflight: 0,
+            completed: 0,
+            underrun_reported: false,
+        })
+    }
+
+    /// Sends a control request and checks the status in the response.
+    fn request(&mut self, req: &[u8], resp: &mut [u8]) -> DevResult {
+        self.control_queue
+            .add_notify_wait_pop(&[req], &mut [resp], &mut self.transport)
+            .map_err(as_dev_err)?;
+        check_status(u32_at(resp, 0))
+    }
+
+    /// Sends a `VIRTIO_SND_R_*_INFO` request for one item, the item is written
+    /// to `resp` after the status.
+    fn query_info(&mut self, code: u32, id: u32, resp: &mut [u8]) -> DevResult {
+        let size = (resp.len() - 4) as u32;
+        let mut req = [0; 16];
+        req[0..4].copy_from_slice(&code.to_le_bytes());
+        req[4..8].copy_from_slice(&id.to_le_bytes());
+        req[8..12].copy_from_slice(&1u32.to_le_bytes());
+        req[12..16].copy_from_slice(&size.to_le_bytes());
+        self.request(&req, resp)
+    }
+
+    /// Sends a request that only carries a stream ID.
+    fn pcm_request(&mut self, code: u32, stream_id: u32) -> DevResult {
+        self.check_stream(stream_id)?;
+        let mut req = [0; 8];
+        req[0..4].copy_from_slice(&code.to_le_bytes());
+        req[4..8].copy_from_slice(&stream_id.to_le_bytes());
+        self.request(&req, &mut [0; 4])
+    }
+
+    fn check_stream(&self, stream_id: u32) -> DevResult {
+        if stream_id < self.streams {
+            Ok(())
+        } else {
+            Err(DevError::InvalidParam)
+        }
+    }
+}
+
+impl<H: Hal, T: Transport> Drop for VirtIoSoundDev<H, T> {
+    fn drop(&mut self) {
+        self.transport.queue_unset(CONTROL_QUEUE);
+        self.transport.queue_unset(TX_QUEUE);
+        // Reset the device so that it no longer accesses the freed memory.
+        self.transport.set_status(DeviceStatus::empty());
+    }
+}
+
+impl<H: Hal, T: Transport> BaseDriverOps for VirtIoSoundDev<H, T> {
+    fn device_name(&self) -> &str {
+        "virtio-sound"
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Char
+    }
+}
+
+impl<H: Hal, T: Transport> SoundDriverOps for VirtIoSoundDev<H, T> {
+    fn num_jacks(&self) -> u32 {
+        self.jacks
+    }
+
+    fn jack_info(&mut self, jack_id: u32) -> DevResult<JackInfo> {
+        if jack_id >= self.jacks {
+            return Err(DevError::InvalidParam);
+        }
+        let mut resp = [0; 4 + JACK_INFO_SIZE];
+        self.query_info(VIRTIO_SND_R_JACK_INFO, jack_id, &mut resp)?;
+        let info = &resp[4..];
+        Ok(JackInfo {
+            hda_reg_defconf: u32_at(info, 8),
+            hda_reg_caps: u32_at(info, 12),
+            connected: info[16] != 0,
+        })
+    }
+
+    fn num_streams(&self) -> u32 {
+        self.streams
+    }
+
+    fn stream_info(&mut self, stream_id: u32) -> DevResult<PcmStreamInfo> {
+        self.check_stream(stream_id)?;
+        let mut resp = [0; 4 + PCM_INFO_SIZE];
+        self.query_info(VIRTIO_SND_R_PCM_INFO, stream_id, &mut resp)?;
+        let info = &resp[4..];
+        Ok(PcmStreamInfo {
+            formats: u64_at(info, 8),
+            rates: u64_at(info, 16),
+            direction: if info[24] == VIRTIO_SND_D_OUTPUT {
+                PcmDirection::Output
+            } else {
+                PcmDirection::Input
+            },
+            channels_min: info[25],
+            channels_max: info[26],
+        })
+    }
+
+    fn set_params(&mut self, stream_id: u32, params: &PcmParams) -> DevResult {
+        self.check_stream(stream_id)?;
+        if params.period_bytes == 0 || params.buffer_bytes % params.period_bytes != 0 {
+            return Err(DevError::InvalidParam);
+        }
+        let mut req = [0; 24];
+        req[0..4].copy_from_slice(&VIRTIO_SND_R_PCM_SET_PARAMS.to_le_bytes());
+        req[4..8].copy_from_slice(&stream_id.to_le_bytes());
+        req[8..12].copy_from_slice(&params.buffer_bytes.to_le_bytes());
+        req[12..16].copy_from_slice(&params.period_bytes.to_le_bytes());
+        req[20] = params.channels;
+        req[21] = params.format as u8;
+        req[22] = params.rate as u8;
+        self.request(&req, &mut [0; 4])?;
+        self.params[stream_id as usize] = Some(*params);
+        Ok(())
+    }
+
+    fn prepare(&mut self, stream_id: u32) -> DevResult {
+        self.pcm_request(VIRTIO_SND_R_PCM_PREPARE, stream_id)
+    }
+
+    fn start(&mut self, stream_id: u32) -> DevResult {
+        self.pcm_request(VIRTIO_SND_R_PCM_START, stream_id)?;
+        self.running[stream_id as usize] = true;
+        Ok(())
+    }
+
+    fn stop(&mut self, stream_id: u32) -> DevResult {
+        self.pcm_request(VIRTIO_SND_R_PCM_STOP, stream_id)?;
+        self.running[stream_id as usize] = false;
+        Ok(())
+    }
+
+    fn release(&mut self, stream_id: u32) -> DevResult {
+        self.pcm_request(VIRTIO_SND_R_PCM_RELEASE, stream_id)?;
+        self.params[stream_id as usize] = None;
+        Ok(())
+    }
+
+    fn submit_period(&mut self, stream_id: u32, data: &[u8]) -> DevResult {
+        self.check_stream(stream_id)?;
+        let params = self.params[stream_id as usize].ok_or(DevError::BadState)?;
+        if data.len() != params.period_bytes as usize {
+            return Err(DevError::InvalidParam);
+        }
+        if self.inflight >= MAX_INFLIGHT_PERIODS {
+            return Err(DevError::Again);
+        }
+
+        let mut period = Box::new(TxPeriod {
+            xfer: stream_id.to_le_bytes(),
+            frames: data.to_vec(),
+            status: [0; 8],
+        });
+        // Safe because the buffers live as long as they are stored in `tx_periods`.
+        let token = unsafe {
+            let TxPeriod {
+                xfer,
+                frames,
+                status,
+            } = &mut *period;
+            self.tx_queue
+                .add(&[&xfer[..], &frames[..]], &mut [&mut status[..]])
+        }
+        .map_err(as_dev_err)?;
+        self.tx_periods[token as usize] = Some(period);
+        self.inflight += 1;
+        self.underrun_reported = false;
+        if self.tx_queue.should_notify() {
+            self.transport.notify(TX_QUEUE);
+        }
+        Ok(())
+    }
+
+    fn poll_completed(&mut self) -> DevResult<usize> {
+        let mut result = Ok(());
+        while let Some(token) = self.tx_queue.peek_used() {
+            let mut period = self.tx_periods[token as usize]
+                .take()
+                .ok_or(DevError::BadState)?;
+            // Safe because the buffers are the same ones passed to `add`.
+            unsafe {
+                let TxPeriod {
+                    xfer,
+                    frames,
+                    status,
+                } = &mut *period;
+                self.tx_queue
+                    .pop_used(token, &[&xfer[..], &frames[..]], &mut [&mut status[..]])
+            }
+            .map_err(as_dev_err)?;
+            self.inflight -= 1;
+            self.completed += 1;
+            if result.is_ok() {
+                result = check_status(u32_at(&period.status, 0));
+            }
+        }
+        if let Err(e) = result {
+            warn!("virtio-sound: failed to play a period: {:?}", e);
+            return Err(e);
+        }
+
+        let running = self.running.iter().any(|&r| r);
+        if running && self.inflight == 0 && !self.underrun_reported {
+            self.underrun_reported = true;
+            warn!("virtio-sound: underrun");
+            return Err(DevError::BadState);
+        }
+        Ok(core::mem::take(&mut self.completed))
+    }
+}
@@ -0,0 +1,901 @@
+//! Driver for xHCI (USB 3) host controllers.
+//!
+//! This first version brings up the controller in polling mode, and addresses
+//! the first device connected to a root hub port. Hubs are not supported. The
+//! device is then driven by a class driver, e.g., [`usb_storage`], through the
+//! control and bulk transfer methods.
+//!
+//! [`usb_storage`]: crate::usb_storage
+
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{fence, Ordering};
+use core::time::Duration;
+
+use axdma::{dealloc_coherent, DMAInfo};
+use axdriver_base::{DevError, DevResult};
+
+use crate::dma::alloc_coherent;
+
+/// PCI class code of USB controllers (serial bus controller).
+pub const XHCI_CLASS: u8 = 0x0c;
+/// PCI subclass code of USB controllers.
+pub const XHCI_SUBCLASS: u8 = 0x03;
+/// PCI programming interface of xHCI controllers.
+pub const XHCI_PROG_IF: u8 = 0x30;
+
+const PAGE_SIZE: usize = 4096;
+/// Number of TRBs in each ring, including the link TRB.
+const RING_SIZE: usize = 256;
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time for the root hub ports to detect the connected devices.
+const PORT_SETTLE_TIME: Duration = Duration::from_millis(100);
+
+// Capability registers.
+const CAP_CAPLENGTH: usize = 0x00;
+const CAP_HCSPARAMS1: usize = 0x04;
+const CAP_HCSPARAMS2: usize = 0x08;
+const CAP_HCCPARAMS1: usize = 0x10;
+const CAP_DBOFF: usize = 0x14;
+const CAP_RTSOFF: usize = 0x18;
+
+// Operational registers.
+const OP_USBCMD: usize = 0x00;
+const OP_USBSTS: usize = 0x04;
+const OP_CRCR: usize = 0x18;
+const OP_DCBAAP: usize = 0x30;
+const OP_CONFIG: usize = 0x38;
+const OP_PORTSC_BASE: usize = 0x400;
+
+// Registers of interrupter 0 in the runtime registers.
+const RT_ERSTSZ: usize = 0x28;
+const RT_ERSTBA: usize = 0x30;
+const RT_ERDP: usize = 0x38;
+
+const USBCMD_RS: u32 = 1 << 0;
+const USBCMD_HCRST: u32 = 1 << 1;
+const USBSTS_HCH: u32 = 1 << 0;
+const USBSTS_CNR: u32 = 1 << 11;
+/// 64-byte contexts.
+const HCCPARAMS1_CSZ: u32 = 1 << 2;
+
+const PORTSC_CCS: u32 = 1 << 0;
+const PORTSC_PED: u32 = 1 << 1;
+const PORTSC_PR: u32 = 1 << 4;
+const PORTSC_PRC: u32 = 1 << 21;
+/// The change bits, which are cleared by writing 1.
+const PORTSC_CHANGES: u32 = 0x7f << 17;
+/// The bits written back as read. Other bits are written 0 to leave them
+/// unchanged, e.g., writing 1 to `PED` disables the port.
+const PORTSC_PRESERVE: u32 = 1 << 9 | 0b11 << 14 | 0b111 << 25;
+
+/// Event handler busy, cleared by writing 1.
+const ERDP_EHB: u64 = 1 << 3;
+
+// TRB types.
+const TRB_NORMAL: u32 = 1;
+const TRB_SETUP: u32 = 2;
+const TRB_DATA: u32 = 3;
+const TRB_STATUS: u32 = 4;
+const TRB_LINK: u32 = 6;
+const TRB_ENABLE_SLOT: u32 = 9;
+const TRB_ADDRESS_DEVICE: u32 = 11;
+const TRB_CONFIGURE_ENDPOINT: u32 = 12;
+const TRB_EVALUATE_CONTEXT: u32 = 13;
+const TRB_RESET_ENDPOINT: u32 = 14;
+const TRB_SET_TR_DEQUEUE: u32 = 16;
+const TRB_TRANSFER_EVENT: u32 = 32;
+const TRB_COMMAND_COMPLETION: u32 = 33;
+
+const TRB_CYCLE: u32 = 1 << 0;
+const TRB_TOGGLE_CYCLE: u32 = 1 << 1;
+/// Interrupt on short packet.
+const TRB_ISP: u32 = 1 << 2;
+/// Interrupt on completion.
+const TRB_IOC: u32 = 1 << 5;
+/// Immediate data.
+const TRB_IDT: u32 = 1 << 6;
+const TRB_DIR_IN: u32 = 1 << 16;
+
+// Completion codes.
+const CC_SUCCESS: u32 = 1;
+const CC_STALL: u32 = 6;
+const CC_SHORT_PACKET: u32 = 13;
+
+// Endpoint types in endpoint contexts.
+const EP_TYPE_BULK_OUT: u32 = 2;
+const EP_TYPE_CONTROL: u32 = 4;
+const EP_TYPE_BULK_IN: u32 = 6;
+
+// Port speeds.
+const SPEED_FULL: u32 = 1;
+const SPEED_LOW: u32 = 2;
+const SPEED_HIGH: u32 = 3;
+
+// Standard requests and descriptors.
+const REQ_CLEAR_FEATURE: u8 = 0x01;
+const REQ_GET_DESCRIPTOR: u8 = 0x06;
+const REQ_SET_CONFIGURATION: u8 = 0x09;
+const REQ_TYPE_ENDPOINT: u8 = 0x02;
+const DESC_DEVICE: u16 = 1;
+const DESC_CONFIGURATION: u16 = 2;
+const DESC_INTERFACE: u8 = 4;
+const DESC_ENDPOINT: u8 = 5;
+const FEATURE_ENDPOINT_HALT: u16 = 0;
+
+const ENDPOINT_DIR_IN: u8 = 0x80;
+const ENDPOINT_XFER_BULK: u8 = 2;
+
+/// A coherent DMA region that is freed on drop.
+pub(crate) struct DmaRegion {
+    info: DMAInfo,
+    layout: Layout,
+}
+
+impl DmaRegion {
+    pub fn new(size: usize, align: usize) -> DevResult<Self> {
+        let layout = Layout::from_size_align(size, align).map_err(|_| DevError::InvalidParam)?;
+        let info = unsafe { alloc_coherent(layout)? };
+        unsafe { core::ptr::write_bytes(info.cpu_addr.as_ptr(), 0, size) };
+        Ok(Self { info, layout })
+    }
+
+    fn as_ptr<T>(&self) -> *mut T {
+        self.info.cpu_addr.as_ptr() as *mut T
+    }
+
+    pub fn bus_addr(&self) -> u64 {
+        self.info.bus_addr.as_u64()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.as_ptr(), self.layout.size()) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl Drop for DmaRegion {
+    fn drop(&mut self) {
+        unsafe { dealloc_coherent(self.info, self.layout) };
+    }
+}
+
+/// Transfer request block.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Trb {
+    param: u64,
+    status: u32,
+    control: u32,
+}
+
+impl Trb {
+    const fn new(trb_type: u32, param: u64, status: u32, flags: u32) -> Self {
+        Self {
+            param,
+            status,
+            control: trb_type << 10 | flags,
+        }
+    }
+
+    const fn trb_type(&self) -> u32 {
+        (self.control >> 10) & 0x3f
+    }
+
+    const fn completion_code(&self) -> u32 {
+        self.status >> 24
+    }
+
+    /// The number of bytes not transferred, of transfer events.
+    const fn residual(&self) -> usize {
+        (self.status & 0xff_ffff) as usize
+    }
+
+    const fn slot_id(&self) -> u8 {
+        (self.control >> 24) as u8
+    }
+
+    const fn endpoint_id(&self) -> u8 {
+        ((self.control >> 16) & 0x1f) as u8
+    }
+}
+
+/// A command or transfer ring of one segment.
+struct Ring {
+    trbs: DmaRegion,
+    enqueue: usize,
+    cycle: bool,
+}
+
+impl Ring {
+    fn new() -> DevResult<Self> {
+        let ring = Self {
+            trbs: DmaRegion::new(RING_SIZE * core::mem::size_of::<Trb>(), PAGE_SIZE)?,
+            enqueue: 0,
+            cycle: true,
+        };
+        // The last TRB links back to the start, the cycle bit is set when the
+        // ring wraps around.
+        let link = Trb::new(TRB_LINK, ring.trbs.bus_addr(), 0, TRB_TOGGLE_CYCLE);
+        ring.write(RING_SIZE - 1, link);
+        Ok(ring)
+    }
+
+    /// Writes a TRB, with the control word, which has the cycle bit, last.
+    fn write(&self, index: usize, trb: Trb) {
+        let ptr = unsafe { self.trbs.as_ptr::<Trb>().add(index) };
+        unsafe {
+            addr_of_mut!((*ptr).param).write_volatile(trb.param);
+            addr_of_mut!((*ptr).status).write_volatile(trb.status);
+            fence(Ordering::SeqCst);
+            addr_of_mut!((*ptr).control).write_volatile(trb.control);
+        }
+    }
+
+    fn trb_addr(&self, index: usize) -> u64 {
+        self.trbs.bus_addr() + (index * core::mem::size_of::<Trb>()) as u64
+    }
+
+    /// Enqueues a TRB with the cycle bit of the ring, and returns its bus
+    /// address.
+    fn push(&mut self, mut trb: Trb) -> u64 {
+        trb.control = (trb.control & !TRB_CYCLE) | self.cycle as u32;
+        let addr = self.trb_addr(self.enqueue);
+        self.write(self.enqueue, trb);
+        self.enqueue += 1;
+        if self.enqueue == RING_SIZE - 1 {
+            let link = Trb::new(
+                TRB_LINK,
+                self.trbs.bus_addr(),
+                0,
+                TRB_TOGGLE_CYCLE | self.cycle as u32,
+            );
+            self.write(RING_SIZE - 1, link);
+            self.enqueue = 0;
+            self.cycle = !self.cycle;
+        }
+        addr
+    }
+
+    /// The enqueue pointer with the cycle state, as given to the controller.
+    fn dequeue_ptr(&self) -> u64 {
+        self.trb_addr(self.enqueue) | self.cycle as u64
+    }
+}
+
+/// The event ring of interrupter 0, of one segment.
+struct EventRing {
+    trbs: DmaRegion,
+    /// The event ring segment table of one entry.
+    erst: DmaRegion,
+    dequeue: usize,
+    cycle: bool,
+}
+
+impl EventRing {
+    fn new() -> DevResult<Self> {
+        let trbs = DmaRegion::new(RING_SIZE * core::mem::size_of::<Trb>(), PAGE_SIZE)?;
+        let erst = DmaRegion::new(16, 64)?;
+        unsafe {
+            erst.as_ptr::<u64>().write_volatile(trbs.bus_addr());
+            erst.as_ptr::<u32>().add(2).write_volatile(RING_SIZE as u32);
+        }
+        Ok(Self {
+            trbs,
+            erst,
+            dequeue: 0,
+            cycle: true,
+        })
+    }
+
+    fn pop(&mut self) -> Option<Trb> {
+        let trb = unsafe { self.trbs.as_ptr::<Trb>().add(self.dequeue).read_volatile() };
+        if (trb.control & TRB_CYCLE != 0) != self.cycle {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        self.dequeue += 1;
+        if self.dequeue == RING_SIZE {
+            self.dequeue = 0;
+            self.cycle = !self.cycle;
+        }
+        Some(trb)
+    }
+
+    fn dequeue_ptr(&self) -> u64 {
+        self.trbs.bus_addr() + (self.dequeue * core::mem::size_of::<Trb>()) as u64
+    }
+}
+
+/// An endpoint descriptor.
+#[derive(Debug, Clone, Copy)]
+pub struct Endpoint {
+    /// The endpoint address, with the direction in bit 7.
+    pub address: u8,
+    pub attributes: u8,
+    pub max_packet_size: u16,
+}
+
+impl Endpoint {
+    pub const fn is_bulk(&self) -> bool {
+        self.attributes & 0b11 == ENDPOINT_XFER_BULK
+    }
+
+    pub const fn is_in(&self) -> bool {
+        self.address & ENDPOINT_DIR_IN != 0
+    }
+}
+
+/// An interface descriptor, with the endpoints of its default setting.
+#[derive(Debug)]
+pub struct Interface {
+    pub number: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub endpoints: Vec<Endpoint>,
+}
+
+/// A configuration descriptor, with its interfaces.
+#[derive(Debug)]
+pub struct Configuration {
+    pub value: u8,
+    pub interfaces: Vec<Interface>,
+}
+
+impl Configuration {
+    fn parse(data: &[u8]) -> Self {
+        let mut config = Self {
+            value: data.get(5).copied().unwrap_or(1),
+            interfaces: Vec::new(),
+        };
+        let mut alternate = false;
+        let mut rest = data;
+        while rest.len() >= 2 && rest[0] >= 2 && rest[0] as usize <= rest.len() {
+            let (desc, next) = rest.split_at(rest[0] as usize);
+            match desc[1] {
+                DESC_INTERFACE if desc.len() >= 9 => {
+                    alternate = desc[3] != 0;
+                    if !alternate {
+                        config.interfaces.push(Interface {
+                            number: desc[2],
+                            class: desc[5],
+                            subclass: desc[6],
+                            protocol: desc[7],
+                            endpoints: Vec::new(),
+                        });
+                    }
+                }
+                DESC_ENDPOINT if desc.len() >= 7 && !alternate => {
+                    if let Some(iface) = config.interfaces.last_mut() {
+                        iface.endpoints.push(Endpoint {
+                            address: desc[2],
+                            attributes: desc[3],
+                            max_packet_size: u16::from_le_bytes([desc[4], desc[5]]) & 0x7ff,
+                        });
+                    }
+                }
+                _ => {}
+            }
+            rest = next;
+        }
+        config
+    }
+}
+
+/// Device context index of an endpoint.
+const fn dci(address: u8) -> usize {
+    let num = (address & 0xf) as usize;
+    if num == 0 {
+        1
+    } else {
+        num * 2 + (address & ENDPOINT_DIR_IN != 0) as usize
+    }
+}
+
+/// The xHCI controller with the addressed device.
+pub struct Xhci {
+    op_base: usize,
+    rt_base: usize,
+    db_base: usize,
+    context_size: usize,
+    dcbaa: DmaRegion,
+    _scratchpad: Vec<DmaRegion>,
+    commands: Ring,
+    events: EventRing,
+    slot_id: u8,
+    input_ctx: DmaRegion,
+    output_ctx: DmaRegion,
+    /// Transfer rings of the endpoints, indexed by the device context index.
+    rings: Vec<Option<Ring>>,
+    /// Buffer of the data stages of control transfers.
+    control_buf: DmaRegion,
+}
+
+unsafe impl Send for Xhci {}
+unsafe impl Sync for Xhci {}
+
+impl Xhci {
+    /// Resets and initializes the controller whose registers are mapped at
+    /// `mmio_base`, and addresses the first connected device.
+    pub fn init(mmio_base: usize) -> DevResult<Self> {
+        let read_cap = |reg: usize| unsafe { ((mmio_base + reg) as *const u32).read_volatile() };
+        let cap_length = read_cap(CAP_CAPLENGTH) as u8 as usize;
+        let hcsparams1 = read_cap(CAP_HCSPARAMS1);
+        let hcsparams2 = read_cap(CAP_HCSPARAMS2);
+        let max_ports = (hcsparams1 >> 24) as u8;
+        let scratchpad_num = ((hcsparams2 >> 21) & 0x1f) << 5 | (hcsparams2 >> 27);
+        let context_size = if read_cap(CAP_HCCPARAMS1) & HCCPARAMS1_CSZ != 0 {
+            64
+        } else {
+            32
+        };
+
+        let mut xhci = Self {
+            op_base: mmio_base + cap_length,
+            rt_base: mmio_base + (read_cap(CAP_RTSOFF) & !0x1f) as usize,
+            db_base: mmio_base + (read_cap(CAP_DBOFF) & !0x3) as usize,
+            context_size,
+            dcbaa: DmaRegion::new(PAGE_SIZE, PAGE_SIZE)?,
+            _scratchpad: Vec::new(),
+            commands: Ring::new()?,
+            events: EventRing::new()?,
+            slot_id: 0,
+            // The input context has an extra input control context.
+            input_ctx: DmaRegion::new(33 * context_size, PAGE_SIZE)?,
+            output_ctx: DmaRegion::new(32 * context_size, PAGE_SIZE)?,
+            rings: (0..32).map(|_| None).collect(),
+            control_buf: DmaRegion::new(PAGE_SIZE, PAGE_SIZE)?,
+        };
+        xhci.reset()?;
+        xhci.alloc_scratchpad(scratchpad_num as usize)?;
+        xhci.start()?;
+        info!("xhci: {} ports, {}-byte contexts", max_ports, context_size);
+
+        axhal::time::busy_wait(PORT_SETTLE_TIME);
+        for port in 1..=max_ports {
+            if xhci.read_reg(xhci.portsc(port)) & PORTSC_CCS == 0 {
+                continue;
+            }
+            match xhci.reset_port(port) {
+                Ok(speed) => {
+                    xhci.address_device(port, speed)?;
+                    return Ok(xhci);
+                }
+                Err(e) => warn!("xhci: failed to enable port {}: {:?}", port, e),
+            }
+        }
+        warn!("xhci: no device connected");
+        Err(DevError::Unsupported)
+    }
+
+    fn read_reg(&self, addr: usize) -> u32 {
+        unsafe { (addr as *const u32).read_volatile() }
+    }
+
+    fn write_reg(&self, addr: usize, val: u32) {
+        unsafe { (addr as *mut u32).write_volatile(val) }
+    }
+
+    fn write_reg64(&self, addr: usize, val: u64) {
+        self.write_reg(addr, val as u32);
+        self.write_reg(addr + 4, (val >> 32) as u32);
+    }
+
+    fn portsc(&self, port: u8) -> usize {
+        self.op_base + OP_PORTSC_BASE + 0x10 * (port as usize - 1)
+    }
+
+    /// Polls until the bits in `mask` of the register at `addr` are `value`.
+    fn wait_reg(&self, addr: usize, mask: u32, value: u32, what: &str) -> DevResult {
+        let deadline = axhal::time::monotonic_time() + COMMAND_TIMEOUT;
+        while self.read_reg(addr) & mask != value {
+            if axhal::time::monotonic_time() > deadline {
+                error!("xhci: timed out waiting for {}", what);
+                return Err(DevError::Io);
+            }
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) -> DevResult {
+        let (usbcmd, usbsts) = (self.op_base + OP_USBCMD, self.op_base + OP_USBSTS);
+        self.write_reg(usbcmd, self.read_reg(usbcmd) & !USBCMD_RS);
+        self.wait_reg(usbsts, USBSTS_HCH, USBSTS_HCH, "halt")?;
+        self.write_reg(usbcmd, USBCMD_HCRST);
+        self.wait_reg(usbcmd, USBCMD_HCRST, 0, "reset")?;
+        self.wait_reg(usbsts, USBSTS_CNR, 0, "controller ready")
+    }
+
+    fn alloc_scratchpad(&mut self, num: usize) -> DevResult {
+        if num == 0 {
+            return Ok(());
+        }
+        let array = DmaRegion::new(num * 8, 64)?;
+        for i in 0..num {
+            let page = DmaRegion::new(PAGE_SIZE, PAGE_SIZE)?;
+            unsafe { array.as_ptr::<u64>().add(i).write_volatile(page.bus_addr()) };
+            self._scratchpad.push(page);
+        }
+        unsafe { self.dcbaa.as_ptr::<u64>().write_volatile(array.bus_addr()) };
+        self._scratchpad.push(array);
+        Ok(())
+    }
+
+    fn start(&mut self) -> DevResult {
+        // Only one device slot is used.
+        self.write_reg(self.op_base + OP_CONFIG, 1);
+        self.write_reg64(self.op_base + OP_DCBAAP, self.dcbaa.bus_addr());
+        self.write_reg64(self.op_base + OP_CRCR, self.commands.dequeue_ptr());
+        self.write_reg(self.rt_base + RT_ERSTSZ, 1);
+        self.write_reg64(self.rt_base + RT_ERDP, self.events.dequeue_ptr());
+        self.write_reg64(self.rt_base + RT_ERSTBA, self.events.erst.bus_addr());
+        // Polling mode, interrupts are left disabled.
+        self.write_reg(self.op_base + OP_USBCMD, USBCMD_RS);
+        self.wait_reg(self.op_base + OP_USBSTS, USBSTS_HCH, 0, "start")
+    }
+
+    /// Enables the port if it is not yet, and returns the speed of the
+    /// connected device.
+    ///
+    /// USB 3 ports are enabled once a device is connected, USB 2 ports are
+    /// enabled by a reset.
+    fn reset_port(&mut self, port: u8) -> DevResult<u32> {
+        let reg = self.portsc(port);
+        let portsc = self.read_reg(reg);
+        if portsc & PORTSC_PED == 0 {
+            self.write_reg(reg, portsc & PORTSC_PRESERVE | PORTSC_PR);
+            self.wait_reg(reg, PORTSC_PRC, PORTSC_PRC, "port reset")?;
+        }
+        let portsc = self.read_reg(reg);
+        self.write_reg(reg, portsc & (PORTSC_PRESERVE | PORTSC_CHANGES));
+        if portsc & PORTSC_PED == 0 {
+            return Err(DevError::Io);
+        }
+        Ok((portsc >> 10) & 0xf)
+    }
+
+    /// Pops events until one matches, or the timeout expires.
+    fn wait_event(&mut self, timeout: Duration, matches: impl Fn(&Trb) -> bool) -> DevResult<Trb> {
+        let deadline = axhal::time::monotonic_time() + timeout;
+        loop {
+            while let Some(event) = self.events.pop() {
+                let erdp = self.events.dequeue_ptr() | ERDP_EHB;
+                self.write_reg64(self.rt_base + RT_ERDP, erdp);
+                if matches(&event) {
+                    return Ok(event);
+                }
+                debug!("xhci: ignored event of type {}", event.trb_type());
+            }
+            if axhal::time::monotonic_time() > deadline {
+                return Err(DevError::Io);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Submits a command and waits for its completion event.
+    fn command(&mut self, trb: Trb) -> DevResult<Trb> {
+        let addr = self.commands.push(trb);
+        fence(Ordering::SeqCst);
+        self.write_reg(self.db_base, 0);
+        let event = self
+            .wait_event(COMMAND_TIMEOUT, |ev| {
+                ev.trb_type() == TRB_COMMAND_COMPLETION && ev.param == addr
+            })
+            .inspect_err(|_| error!("xhci: command {} timed out", trb.trb_type()))?;
+        match event.completion_code() {
+            CC_SUCCESS => Ok(event),
+            cc => {
+                warn!("xhci: command {} failed with {}", trb.trb_type(), cc);
+                Err(DevError::Io)
+            }
+        }
+    }
+
+    fn context(&self, region: &DmaRegion, index: usize) -> *mut u32 {
+        unsafe { region.as_ptr::<u8>().add(index * self.context_size) as *mut u32 }
+    }
+
+    /// Returns a context of the input context, where 0 is the input control
+    /// context, 1 is the slot context, and the others are endpoint contexts
+    /// indexed by the device context index plus one.
+    fn input(&self, index: usize) -> *mut u32 {
+        self.context(&self.input_ctx, index)
+    }
+
+    fn clear_input(&mut self) {
+        self.input_ctx.as_mut_slice().fill(0);
+    }
+
+    fn set_endpoint(&self, dci: usize, ep_type: u32, max_packet_size: u16, ring: u64) {
+        // Average TRB lengths recommended by the specification.
+        let avg_trb_len = if ep_type == EP_TYPE_CONTROL { 8 } else { 3072 };
+        let ctx = self.input(dci + 1);
+        unsafe {
+            // 3 retries on errors.
+            ctx.add(1)
+                .write_volatile(3 << 1 | ep_type << 3 | (max_packet_size as u32) << 16);
+            ctx.add(2).write_volatile(ring as u32);
+            ctx.add(3).write_volatile((ring >> 32) as u32);
+            ctx.add(4).write_volatile(avg_trb_len);
+        }
+    }
+
+    fn address_device(&mut self, port: u8, speed: u32) -> DevResult {
+        let event = self.command(Trb::new(TRB_ENABLE_SLOT, 0, 0, 0))?;
+        self.slot_id = event.slot_id();
+        let slot = (self.slot_id as u32) << 24;
+
+        let ep0 = Ring::new()?;
+        let mut max_packet_size = match speed {
+            SPEED_LOW | SPEED_FULL => 8,
+            SPEED_HIGH => 64,
+            _ => 512,
+        };
+        self.clear_input();
+        unsafe {
+            // Add the slot and endpoint 0 contexts.
+            self.input(0).add(1).write_volatile(0b11);
+            // One context entry.
+            self.input(1).write_volatile(speed << 20 | 1 << 27);
+            self.input(1).add(1).write_volatile((port as u32) << 16);
+        }
+        self.set_endpoint(1, EP_TYPE_CONTROL, max_packet_size, ep0.dequeue_ptr());
+        self.rings[1] = Some(ep0);
+        let output = self.output_ctx.bus_addr();
+        unsafe {
+            self.dcbaa
+                .as_ptr::<u64>()
+                .add(self.slot_id as usize)
+                .write_volatile(output)
+        };
+        let input = self.input_ctx.bus_addr();
+        self.command(Trb::new(TRB_ADDRESS_DEVICE, input, 0, slot))?;
+
+        // The first 8 bytes have the max packet size of endpoint 0.
+        let mut desc = [0u8; 18];
+        self.get_descriptor(DESC_DEVICE, &mut desc[..8])?;
+        let actual = if speed > SPEED_HIGH {
+            1 << desc[7]
+        } else {
+            desc[7] as u16
+        };
+        if actual != max_packet_size {
+            max_packet_size = actual;
+            self.clear_input();
+            unsafe { self.input(0).add(1).write_volatile(0b10) };
+            self.set_endpoint(1, EP_TYPE_CONTROL, max_packet_size, 0);
+            self.command(Trb::new(TRB_EVALUATE_CONTEXT, input, 0, slot))?;
+        }
+        self.get_descriptor(DESC_DEVICE, &mut desc)?;
+        info!(
+            "xhci: device {:04x}:{:04x} at port {}, speed {}, slot {}",
+            u16::from_le_bytes([desc[8], desc[9]]),
+            u16::from_le_bytes([desc[10], desc[11]]),
+            port,
+            speed,
+            self.slot_id
+        );
+        Ok(())
+    }
+
+    fn get_descriptor(&mut self, desc_type: u16, buf: &mut [u8]) -> DevResult<usize> {
+        self.control_in(0, REQ_GET_DESCRIPTOR, desc_type << 8, 0, buf)
+    }
+
+    /// Reads the first configuration descriptor of the device.
+    pub fn configuration(&mut self) -> DevResult<Configuration> {
+        let mut header = [0u8; 9];
+        self.get_descriptor(DESC_CONFIGURATION, &mut header)?;
+        let total_len = (u16::from_le_bytes([header[2], header[3]]) as usize).min(PAGE_SIZE);
+        let mut data = alloc::vec![0u8; total_len];
+        let len = self.get_descriptor(DESC_CONFIGURATION, &mut data)?;
+        Ok(Configuration::parse(&data[..len]))
+    }
+
+    /// Selects the configuration, and enables the given bulk endpoints.
+    pub fn configure(&mut self, config: &Configuration, endpoints: &[Endpoint]) -> DevResult {
+        self.clear_input();
+        let mut add_flags = 1;
+        let mut max_dci = 1;
+        for ep in endpoints {
+            if !ep.is_bulk() {
+                return Err(DevError::Unsupported);
+            }
+            let ep_type = if ep.is_in() {
+                EP_TYPE_BULK_IN
+            } else {
+                EP_TYPE_BULK_OUT
+            };
+            let dci = dci(ep.address);
+            let ring = Ring::new()?;
+            self.set_endpoint(dci, ep_type, ep.max_packet_size, ring.dequeue_ptr());
+            self.rings[dci] = Some(ring);
+            add_flags |= 1 << dci;
+            max_dci = max_dci.max(dci);
+        }
+        unsafe {
+            self.input(0).add(1).write_volatile(add_flags);
+            // Copy the slot context, with the new number of context entries.
+            let output_slot = self.context(&self.output_ctx, 0);
+            for i in 0..4 {
+                self.input(1)
+                    .add(i)
+                    .write_volatile(output_slot.add(i).read_volatile());
+            }
+            let dw0 = self.input(1).read_volatile() & !(0x1f << 27);
+            self.input(1).write_volatile(dw0 | (max_dci as u32) << 27);
+        }
+        let slot = (self.slot_id as u32) << 24;
+        let input = self.input_ctx.bus_addr();
+        self.command(Trb::new(TRB_CONFIGURE_ENDPOINT, input, 0, slot))?;
+        self.control_out(0, REQ_SET_CONFIGURATION, config.value as u16, 0, &[])
+    }
+
+    /// Enqueues the TRBs of a transfer descriptor on an endpoint, and waits
+    /// until the last one completes. Returns the number of bytes not
+    /// transferred.
+    ///
+    /// If the endpoint stalls, it is recovered and [`DevError::Again`] is
+    /// returned.
+    fn transfer(&mut self, dci: usize, trbs: &[Trb]) -> DevResult<usize> {
+        let ring = self.rings[dci].as_mut().ok_or(DevError::InvalidParam)?;
+        let mut last = 0;
+        for trb in trbs {
+            last = ring.push(*trb);
+        }
+        fence(Ordering::SeqCst);
+        self.write_reg(self.db_base + 4 * self.slot_id as usize, dci as u32);
+
+        let slot_id = self.slot_id;
+        let mut residual = 0;
+        loop {
+            let event = self
+                .wait_event(TRANSFER_TIMEOUT, |ev| {
+                    ev.trb_type() == TRB_TRANSFER_EVENT
+                        && ev.slot_id() == slot_id
+                        && ev.endpoint_id() as usize == dci
+                })
+                .inspect_err(|_| error!("xhci: transfer on endpoint {} timed out", dci))?;
+            match event.completion_code() {
+                CC_SUCCESS => {}
+                CC_SHORT_PACKET => residual += event.residual(),
+                CC_STALL => {
+                    debug!("xhci: endpoint {} stalled", dci);
+                    self.recover_halt(dci)?;
+                    return Err(DevError::Again);
+                }
+                cc => {
+                    warn!("xhci: transfer on endpoint {} failed with {}", dci, cc);
+                    return Err(DevError::Io);
+                }
+            }
+            if event.param == last {
+                return Ok(residual);
+            }
+        }
+    }
+
+    /// Resets a halted endpoint, and skips the rest of the failed transfer.
+    fn recover_halt(&mut self, dci: usize) -> DevResult {
+        let target = (self.slot_id as u32) << 24 | (dci as u32) << 16;
+        self.command(Trb::new(TRB_RESET_ENDPOINT, 0, 0, target))?;
+        let dequeue = self.rings[dci].as_ref().unwrap().dequeue_ptr();
+        self.command(Trb::new(TRB_SET_TR_DEQUEUE, dequeue, 0, target))?;
+        if dci != 1 {
+            // Also clear the halt in the device.
+            let address = (dci / 2) as u8 | if dci % 2 == 1 { ENDPOINT_DIR_IN } else { 0 };
+            self.control_out(
+                REQ_TYPE_ENDPOINT,
+                REQ_CLEAR_FEATURE,
+                FEATURE_ENDPOINT_HALT,
+                address as u16,
+                &[],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Performs a control transfer on endpoint 0, with the data stage in
+    /// `control_buf`. Returns the number of bytes transferred.
+    fn control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        len: usize,
+    ) -> DevResult<usize> {
+        if len > PAGE_SIZE {
+            return Err(DevError::InvalidParam);
+        }
+        let dir_in = request_type & ENDPOINT_DIR_IN != 0;
+        let setup = request_type as u64
+            | (request as u64) << 8
+            | (value as u64) << 16
+            | (index as u64) << 32
+            | (len as u64) << 48;
+        // Transfer type: no data stage, OUT or IN data stage.
+        let trt = match (len, dir_in) {
+            (0, _) => 0,
+            (_, false) => 2,
+            (_, true) => 3,
+        };
+        let dir = if dir_in { TRB_DIR_IN } else { 0 };
+        let mut trbs = Vec::with_capacity(3);
+        trbs.push(Trb::new(TRB_SETUP, setup, 8, TRB_IDT | trt << 16));
+        if len > 0 {
+            let buf = self.control_buf.bus_addr();
+            trbs.push(Trb::new(TRB_DATA, buf, len as u32, TRB_ISP | dir));
+        }
+        // The status stage is in the opposite direction of the data stage.
+        let status_dir = if len == 0 || !dir_in { TRB_DIR_IN } else { 0 };
+        trbs.push(Trb::new(TRB_STATUS, 0, 0, TRB_IOC | status_dir));
+        let residual = self.transfer(1, &trbs)?;
+        Ok(len - residual.min(len))
+    }
+
+    /// Performs a control transfer that reads `buf` from the device, and
+    /// returns the number of bytes read.
+    pub fn control_in(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+    ) -> DevResult<usize> {
+        let request_type = request_type | ENDPOINT_DIR_IN;
+        let len = self.control(request_type, request, value, index, buf.len())?;
+        buf[..len].copy_from_slice(&self.control_buf.as_slice()[..len]);
+        Ok(len)
+    }
+
+    /// Performs a control transfer that writes `data` to the device.
+    pub fn control_out(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &[u8],
+    ) -> DevResult {
+        if data.len() > PAGE_SIZE {
+            return Err(DevError::InvalidParam);
+        }
+        self.control_buf.as_mut_slice()[..data.len()].copy_from_slice(data);
+        let request_type = request_type & !ENDPOINT_DIR_IN;
+        self.control(request_type, request, value, index, data.len())
+            .map(|_| ())
+    }
+
+    /// Performs a bulk transfer of `len` bytes at `bus_addr` on the endpoint,
+    /// and returns the number of bytes transferred.
+    ///
+    /// The buffer must not cross a 64 KiB boundary. If the endpoint stalls,
+    /// its halt is cleared and [`DevError::Again`] is returned.
+    pub fn bulk_transfer(&mut self, endpoint: u8, bus_addr: u64, len: usize) -> DevResult<usize> {
+        if len > 0x10000 {
+            return Err(DevError::InvalidParam);
+        }
+        let trb = Trb::new(TRB_NORMAL, bus_addr, len as u32, TRB_ISP | TRB_IOC);
+        let residual = self.transfer(dci(endpoint), &[trb])?;
+        Ok(len - residual.min(len))
+    }
+}
+
+impl Drop for Xhci {
+    fn drop(&mut self) {
+        // Stop the controller so that it no longer accesses the rings.
+        let usbcmd = self.op_base + OP_USBCMD;
+        self.write_reg(usbcmd, self.read_reg(usbcmd) & !USBCMD_RS);
+        let _ = self.wait_reg(self.op_base + OP_USBSTS, USBSTS_HCH, USBSTS_HCH, "halt");
+    }
+}
@@ -1,28 +1,68 @@
 use axdriver::prelude::*;
+use axsync::Mutex;
 
 const BLOCK_SIZE: usize = 512;
 
+/// The block device under [`Disk`], taken away by [`shutdown`].
+static DISK_DEV: Mutex<Option<AxBlockDevice>> = Mutex::new(None);
+
+fn with_dev<T>(f: impl FnOnce(&mut AxBlockDevice) -> DevResult<T>) -> DevResult<T> {
+    match DISK_DEV.lock().as_mut() {
+        Some(dev) => f(dev),
+        None => Err(DevError::BadState),
+    }
+}
+
+/// Flushes and releases the block device. Accessing the disk afterwards
+/// fails with [`DevError::BadState`], and calling it again is a no-op.
+pub fn shutdown() {
+    if let Some(mut dev) = DISK_DEV.lock().take() {
+        info!("  shutdown block device {:?}", dev.device_name());
+        if let Err(e) = dev.shutdown() {
+            warn!("  failed to flush block device: {:?}", e);
+        }
+    }
+}
+
+/// Discards the whole blocks in the byte range `[offset, offset + len)` of the
+/// disk, as the file system no longer uses them.
+///
+/// Failures are only logged, since the blocks are unused either way.
+#[allow(dead_code)]
+pub fn discard(offset: u64, len: u64) {
+    let start = offset.div_ceil(BLOCK_SIZE as u64);
+    let end = (offset + len) / BLOCK_SIZE as u64;
+    if start >= end {
+        return;
+    }
+    if let Err(e) = with_dev(|dev| dev.discard(start..end)) {
+        debug!("failed to discard blocks [{}, {}): {:?}", start, end, e);
+    }
+}
+
 /// A disk device with a cursor.
 pub struct Disk {
     block_id: u64,
     offset: usize,
-    dev: AxBlockDevice,
+    num_blocks: u64,
 }
 
 impl Disk {
     /// Create a new disk.
     pub fn new(dev: AxBlockDevice) -> Self {
         assert_eq!(BLOCK_SIZE, dev.block_size());
+        let num_blocks = dev.num_blocks();
+        *DISK_DEV.lock() = Some(dev);
         Self {
             block_id: 0,
             offset: 0,
-            dev,
+            num_blocks,
         }
     }
 
     /// Get the size of the disk.
     pub fn size(&self) -> u64 {
-        self.dev.num_blocks() * BLOCK_SIZE as u64
+        self.num_blocks * BLOCK_SIZE as u64
     }
 
     /// Get the position of the cursor.
@@ -40,8 +80,7 @@ impl Disk {
     pub fn read_one(&mut self, buf: &mut [u8]) -> DevResult<usize> {
         let read_size = if self.offset == 0 && buf.len() >= BLOCK_SIZE {
             // whole block
-            self.dev
-                .read_block(self.block_id, &mut buf[0..BLOCK_SIZE])?;
+            with_dev(|dev| dev.read_block(self.block_id, &mut buf[0..BLOCK_SIZE]))?;
             self.block_id += 1;
             BLOCK_SIZE
         } else {
@@ -50,7 +89,7 @@ impl Disk {
             let start = self.offset;
             let count = buf.len().min(BLOCK_SIZE - self.offset);
 
-            self.dev.read_block(self.block_id, &mut data)?;
+            with_dev(|dev| dev.read_block(self.block_id, &mut data))?;
             buf[..count].copy_from_slice(&data[start..start + count]);
 
             self.offset += count;
@@ -67,7 +106,7 @@ impl Disk {
     pub fn write_one(&mut self, buf: &[u8]) -> DevResult<usize> {
         let write_size = if self.offset == 0 && buf.len() >= BLOCK_SIZE {
             // whole block
-            self.dev.write_block(self.block_id, &buf[0..BLOCK_SIZE])?;
+            with_dev(|dev| dev.write_block(self.block_id, &buf[0..BLOCK_SIZE]))?;
             self.block_id += 1;
             BLOCK_SIZE
         } else {
@@ -76,9 +115,11 @@ impl Disk {
             let start = self.offset;
             let count = buf.len().min(BLOCK_SIZE - self.offset);
 
-            self.dev.read_block(self.block_id, &mut data)?;
-            data[start..start + count].copy_from_slice(&buf[..count]);
-            self.dev.write_block(self.block_id, &data)?;
+            with_dev(|dev| {
+                dev.read_block(self.block_id, &mut data)?;
+                data[start..start + count].copy_from_slice(&buf[..count]);
+                dev.write_block(self.block_id, &data)
+            })?;
 
             self.offset += count;
             if self.offset >= BLOCK_SIZE {
@@ -89,4 +130,9 @@ impl Disk {
         };
         Ok(write_size)
     }
+
+    /// Flush the write cache of the device.
+    pub fn flush(&mut self) -> DevResult {
+        with_dev(|dev| dev.flush())
+    }
 }
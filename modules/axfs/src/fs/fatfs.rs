@@ -1,22 +1,23 @@
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
 use core::cell::UnsafeCell;
+use core::ops::Range;
 
 use axfs_vfs::{VfsDirEntry, VfsError, VfsNodePerm, VfsResult};
 use axfs_vfs::{VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType, VfsOps};
 use axsync::Mutex;
-use fatfs::{Dir, File, LossyOemCpConverter, NullTimeProvider, Read, Seek, SeekFrom, Write};
+use fatfs::{Dir, File, LossyOemCpConverter, Read, Seek, SeekFrom, Write};
 
 use crate::dev::Disk;
 
 const BLOCK_SIZE: usize = 512;
 
 pub struct FatFileSystem {
-    inner: fatfs::FileSystem<Disk, NullTimeProvider, LossyOemCpConverter>,
+    inner: fatfs::FileSystem<Disk, WallTimeProvider, LossyOemCpConverter>,
     root_dir: UnsafeCell<Option<VfsNodeRef>>,
 }
 
-pub struct FileWrapper<'a>(Mutex<File<'a, Disk, NullTimeProvider, LossyOemCpConverter>>);
-pub struct DirWrapper<'a>(Dir<'a, Disk, NullTimeProvider, LossyOemCpConverter>);
+pub struct FileWrapper<'a>(Mutex<File<'a, Disk, WallTimeProvider, LossyOemCpConverter>>);
+pub struct DirWrapper<'a>(Dir<'a, Disk, WallTimeProvider, LossyOemCpConverter>);
 
 unsafe impl Sync for FatFileSystem {}
 unsafe impl Send for FatFileSystem {}
@@ -28,9 +29,18 @@ unsafe impl<'a> Sync for DirWrapper<'a> {}
 impl FatFileSystem {
     #[cfg(feature = "use-ramdisk")]
     pub fn new(mut disk: Disk) -> Self {
-        let opts = fatfs::FormatVolumeOptions::new();
-        fatfs::format_volume(&mut disk, opts).expect("failed to format volume");
-        let inner = fatfs::FileSystem::new(disk, fatfs::FsOptions::new())
+        // Keep the initial image of the RAM disk, if it has a boot sector.
+        let mut signature = [0u8; 2];
+        disk.set_position(510);
+        let has_boot_sector = disk
+            .read_one(&mut signature)
+            .is_ok_and(|_| signature == [0x55, 0xaa]);
+        disk.set_position(0);
+        if !has_boot_sector {
+            let opts = fatfs::FormatVolumeOptions::new();
+            fatfs::format_volume(&mut disk, opts).expect("failed to format volume");
+        }
+        let inner = fatfs::FileSystem::new(disk, Self::fs_options())
             .expect("failed to initialize FAT filesystem");
         Self {
             inner,
@@ -40,7 +50,7 @@ impl FatFileSystem {
 
     #[cfg(not(feature = "use-ramdisk"))]
     pub fn new(disk: Disk) -> Self {
-        let inner = fatfs::FileSystem::new(disk, fatfs::FsOptions::new())
+        let inner = fatfs::FileSystem::new(disk, Self::fs_options())
             .expect("failed to initialize FAT filesystem");
         Self {
             inner,
@@ -48,16 +58,20 @@ impl FatFileSystem {
         }
     }
 
+    fn fs_options() -> fatfs::FsOptions<WallTimeProvider, LossyOemCpConverter> {
+        fatfs::FsOptions::new().time_provider(WallTimeProvider)
+    }
+
     pub fn init(&'static self) {
         // must be called before later operations
         unsafe { *self.root_dir.get() = Some(Self::new_dir(self.inner.root_dir())) }
     }
 
-    fn new_file(file: File<'_, Disk, NullTimeProvider, LossyOemCpConverter>) -> Arc<FileWrapper> {
+    fn new_file(file: File<'_, Disk, WallTimeProvider, LossyOemCpConverter>) -> Arc<FileWrapper> {
         Arc::new(FileWrapper(Mutex::new(file)))
     }
 
-    fn new_dir(dir: Dir<'_, Disk, NullTimeProvider, LossyOemCpConverter>) -> Arc<DirWrapper> {
+    fn new_dir(dir: Dir<'_, Disk, WallTimeProvider, LossyOemCpConverter>) -> Arc<DirWrapper> {
         Arc::new(DirWrapper(dir))
     }
 }
@@ -87,8 +101,11 @@ impl VfsNodeOps for FileWrapper<'static> {
 
     fn truncate(&self, size: u64) -> VfsResult {
         let mut file = self.0.lock();
+        let freed = clusters_from(&mut file, size)?;
         file.seek(SeekFrom::Start(size)).map_err(as_vfs_err)?; // TODO: more efficient
-        file.truncate().map_err(as_vfs_err)
+        file.truncate().map_err(as_vfs_err)?;
+        discard_all(&freed);
+        Ok(())
     }
 }
 
@@ -161,7 +178,14 @@ impl VfsNodeOps for DirWrapper<'static> {
         if let Some(rest) = path.strip_prefix("./") {
             return self.remove(rest);
         }
-        self.0.remove(path).map_err(as_vfs_err)
+        // The clusters of directories are not discarded, as they are few.
+        let freed = match self.0.open_file(path) {
+            Ok(mut file) => clusters_from(&mut file, 0)?,
+            Err(_) => Vec::new(),
+        };
+        self.0.remove(path).map_err(as_vfs_err)?;
+        discard_all(&freed);
+        Ok(())
     }
 
     fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
@@ -205,6 +229,102 @@ impl VfsOps for FatFileSystem {
     }
 }
 
+/// Timestamps the files with the wall time once it is known, i.e., read from
+/// the RTC or set, and with the FAT epoch (1980-01-01) otherwise.
+#[derive(Debug, Clone, Copy)]
+pub struct WallTimeProvider;
+
+impl WallTimeProvider {
+    const SECS_PER_DAY: u64 = 86400;
+
+    /// Returns the UTC date and time of the wall time, or `None` if it is
+    /// unknown or out of the range of FAT timestamps (1980 to 2107).
+    fn now() -> Option<fatfs::DateTime> {
+        if !axhal::time::wall_time_valid() {
+            return None;
+        }
+        let now = axhal::time::wall_time();
+        let (secs, millis) = (now.as_secs(), now.subsec_millis() as u16);
+        let (year, month, day) = civil_from_days(secs / Self::SECS_PER_DAY);
+        if !(1980..=2107).contains(&year) {
+            return None;
+        }
+        let secs_of_day = secs % Self::SECS_PER_DAY;
+        let date = fatfs::Date::new(year as u16, month, day);
+        let time = fatfs::Time::new(
+            (secs_of_day / 3600) as u16,
+            (secs_of_day / 60 % 60) as u16,
+            (secs_of_day % 60) as u16,
+            millis,
+        );
+        Some(fatfs::DateTime::new(date, time))
+    }
+
+    fn fat_epoch() -> fatfs::DateTime {
+        fatfs::DateTime::new(fatfs::Date::new(1980, 1, 1), fatfs::Time::new(0, 0, 0, 0))
+    }
+}
+
+impl fatfs::TimeProvider for WallTimeProvider {
+    fn get_current_date(&self) -> fatfs::Date {
+        self.get_current_date_time().date
+    }
+
+    fn get_current_date_time(&self) -> fatfs::DateTime {
+        Self::now().unwrap_or_else(Self::fat_epoch)
+    }
+}
+
+/// Converts days since the Unix epoch to the (year, month, day) of the
+/// proleptic Gregorian calendar.
+fn civil_from_days(days: u64) -> (u64, u16, u16) {
+    // Shift the epoch to 0000-03-01, so that leap days are at the end of the
+    // years, and count in eras of 400 years (146097 days).
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153; // March is 0
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u16;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u16;
+    let year = era * 400 + year_of_era + (month <= 2) as u64;
+    (year, month, day)
+}
+
+/// Returns the disk ranges of the clusters of `file` from the byte `offset`
+/// on, which are freed when the file is truncated to `offset`. Adjacent
+/// clusters are merged into one range.
+fn clusters_from(
+    file: &mut File<'_, Disk, WallTimeProvider, LossyOemCpConverter>,
+    offset: u64,
+) -> VfsResult<Vec<Range<u64>>> {
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+    let mut pos = 0;
+    // Each extent is one cluster, except that the last one ends at the file size.
+    for extent in file.extents() {
+        let extent = extent.map_err(as_vfs_err)?;
+        let (start, len) = (pos, extent.size as u64);
+        pos += len;
+        if start < offset {
+            continue;
+        }
+        let range = extent.offset..extent.offset + len;
+        match ranges.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => ranges.push(range),
+        }
+    }
+    Ok(ranges)
+}
+
+fn discard_all(ranges: &[Range<u64>]) {
+    for range in ranges {
+        crate::dev::discard(range.start, range.end - range.start);
+    }
+}
+
 impl fatfs::IoBase for Disk {
     type Error = ();
 }
@@ -243,7 +363,7 @@ impl Write for Disk {
         Ok(write_len)
     }
     fn flush(&mut self) -> Result<(), Self::Error> {
-        Ok(())
+        Disk::flush(self).map_err(|_| ())
     }
 }
 
@@ -16,6 +16,16 @@
 //!    by default, but it will override other filesystem selection features if
 //!    both are enabled.
 //!
+//! # Root Device
+//!
+//! The block device mounted on `/` is the first one found, unless the
+//! environment variable `AX_ROOT_DEV` is set to a device name (e.g.
+//! `virtio-blk1`) at build time.
+//!
+//! If the device has partitions, the first one is mounted, unless the
+//! environment variable `AX_ROOT_PART` is set to a partition number (e.g.
+//! `2` for `virtio-blk1p2`). `AX_ROOT_DEV` can also name a partition directly.
+//!
 //! [FAT]: https://en.wikipedia.org/wiki/File_Allocation_Table
 //! [`MyFileSystemIf`]: fops::MyFileSystemIf
 
@@ -34,13 +44,73 @@ mod root;
 pub mod api;
 pub mod fops;
 
+use alloc::string::String;
+
 use axdriver::{prelude::*, AxDeviceContainer};
 
+/// Name of the block device to mount on `/`, the first one is used if empty.
+const ROOT_DEV: &str = match option_env!("AX_ROOT_DEV") {
+    Some(name) => name,
+    None => "",
+};
+
+/// Number of the partition on the root device to mount on `/`, the first one
+/// is used if empty.
+const ROOT_PART: &str = match option_env!("AX_ROOT_PART") {
+    Some(number) => number,
+    None => "",
+};
+
+/// Returns the name of the block device or partition to mount on `/`.
+fn root_device_name(blk_devs: &AxDeviceContainer<AxBlockDevice>) -> String {
+    let disk = if ROOT_DEV.is_empty() {
+        let (name, _) = blk_devs.iter().next().expect("No block device found!");
+        name
+    } else {
+        ROOT_DEV
+    };
+    if !ROOT_PART.is_empty() {
+        let number = ROOT_PART
+            .parse()
+            .unwrap_or_else(|_| panic!("Invalid partition number {:?}!", ROOT_PART));
+        return axdriver::partition_name(disk, number);
+    }
+    // Partitions are listed right after their disk.
+    blk_devs
+        .iter()
+        .map(|(name, _)| name)
+        .find(|name| axdriver::partition_number(disk, name).is_some())
+        .unwrap_or(disk)
+        .into()
+}
+
 /// Initializes filesystems by block devices.
 pub fn init_filesystems(mut blk_devs: AxDeviceContainer<AxBlockDevice>) {
     info!("Initialize filesystems...");
 
-    let dev = blk_devs.take_one().expect("No block device found!");
-    info!("  use block device 0: {:?}", dev.device_name());
+    let name = root_device_name(&blk_devs);
+    let dev = blk_devs
+        .take_by_name(&name)
+        .unwrap_or_else(|| panic!("Block device {:?} not found!", name));
+    info!("  use block device {}: {:?}", name, dev.device_name());
     self::root::init_rootfs(self::dev::Disk::new(dev));
 }
+
+/// Sets the function filling the buffers read from `/dev/random` and
+/// `/dev/urandom`, e.g., the random number generator of the kernel.
+///
+/// Reading the devices fails until it is set. Only the first call takes
+/// effect.
+#[cfg(feature = "devfs")]
+pub fn set_random_source(fill: fn(&mut [u8])) {
+    self::mounts::RANDOM_SOURCE.call_once(|| fill);
+}
+
+/// Flushes and releases the block device of the root filesystem.
+///
+/// It is called on orderly shutdown, the filesystems cannot be accessed
+/// afterwards. Calling it more than once is harmless.
+pub fn shutdown_filesystems() {
+    info!("Shutdown filesystems...");
+    self::dev::shutdown();
+}
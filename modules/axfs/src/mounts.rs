@@ -3,15 +3,66 @@ use axfs_vfs::{VfsNodeType, VfsOps, VfsResult};
 
 use crate::fs;
 
+#[cfg(feature = "devfs")]
+use {
+    axerrno::ax_err,
+    axfs_vfs::{VfsNodeAttr, VfsNodeOps, VfsNodePerm},
+    lazyinit::LazyInit,
+};
+
+/// The function filling the buffers read from `/dev/random` and
+/// `/dev/urandom`, set by [`set_random_source`](crate::set_random_source).
+#[cfg(feature = "devfs")]
+pub(crate) static RANDOM_SOURCE: LazyInit<fn(&mut [u8])> = LazyInit::new();
+
+/// A random device behaves like `/dev/random` and `/dev/urandom`.
+///
+/// Reads never block, as the source is seeded at boot. Writes are discarded.
+#[cfg(feature = "devfs")]
+struct RandomDev;
+
+#[cfg(feature = "devfs")]
+impl VfsNodeOps for RandomDev {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        Ok(VfsNodeAttr::new(
+            VfsNodePerm::default_file(),
+            VfsNodeType::CharDevice,
+            0,
+            0,
+        ))
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let Some(fill) = RANDOM_SOURCE.get() else {
+            return ax_err!(Unsupported, "no random source");
+        };
+        fill(buf);
+        Ok(buf.len())
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, _size: u64) -> VfsResult {
+        Ok(())
+    }
+
+    axfs_vfs::impl_vfs_non_dir_default! {}
+}
+
 #[cfg(feature = "devfs")]
 pub(crate) fn devfs() -> Arc<fs::devfs::DeviceFileSystem> {
     let null = fs::devfs::NullDev;
     let zero = fs::devfs::ZeroDev;
     let bar = fs::devfs::ZeroDev;
+    let random = Arc::new(RandomDev);
     let devfs = fs::devfs::DeviceFileSystem::new();
     let foo_dir = devfs.mkdir("foo");
     devfs.add("null", Arc::new(null));
     devfs.add("zero", Arc::new(zero));
+    devfs.add("random", random.clone());
+    devfs.add("urandom", random);
     foo_dir.add("bar", Arc::new(bar));
     Arc::new(devfs)
 }
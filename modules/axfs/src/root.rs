@@ -5,13 +5,13 @@
 use alloc::{string::String, sync::Arc, vec::Vec};
 use axerrno::{ax_err, AxError, AxResult};
 use axfs_vfs::{VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType, VfsOps, VfsResult};
-use axsync::Mutex;
+use axsync::RwLock;
 use lazyinit::LazyInit;
 
 use crate::{api::FileType, fs, mounts};
 
-static CURRENT_DIR_PATH: Mutex<String> = Mutex::new(String::new());
-static CURRENT_DIR: LazyInit<Mutex<VfsNodeRef>> = LazyInit::new();
+static CURRENT_DIR_PATH: RwLock<String> = RwLock::new(String::new());
+static CURRENT_DIR: LazyInit<RwLock<VfsNodeRef>> = LazyInit::new();
 
 struct MountPoint {
     path: &'static str,
@@ -180,15 +180,15 @@ pub(crate) fn init_rootfs(disk: crate::dev::Disk) {
         .expect("fail to mount sysfs at /sys");
 
     ROOT_DIR.init_once(Arc::new(root_dir));
-    CURRENT_DIR.init_once(Mutex::new(ROOT_DIR.clone()));
-    *CURRENT_DIR_PATH.lock() = "/".into();
+    CURRENT_DIR.init_once(RwLock::new(ROOT_DIR.clone()));
+    *CURRENT_DIR_PATH.write() = "/".into();
 }
 
 fn parent_node_of(dir: Option<&VfsNodeRef>, path: &str) -> VfsNodeRef {
     if path.starts_with('/') {
         ROOT_DIR.clone()
     } else {
-        dir.cloned().unwrap_or_else(|| CURRENT_DIR.lock().clone())
+        dir.cloned().unwrap_or_else(|| CURRENT_DIR.read().clone())
     }
 }
 
@@ -196,7 +196,7 @@ pub(crate) fn absolute_path(path: &str) -> AxResult<String> {
     if path.starts_with('/') {
         Ok(axfs_vfs::path::canonicalize(path))
     } else {
-        let path = CURRENT_DIR_PATH.lock().clone() + path;
+        let path = CURRENT_DIR_PATH.read().clone() + path;
         Ok(axfs_vfs::path::canonicalize(&path))
     }
 }
@@ -274,7 +274,7 @@ pub(crate) fn remove_dir(dir: Option<&VfsNodeRef>, path: &str) -> AxResult {
 }
 
 pub(crate) fn current_dir() -> AxResult<String> {
-    Ok(CURRENT_DIR_PATH.lock().clone())
+    Ok(CURRENT_DIR_PATH.read().clone())
 }
 
 pub(crate) fn set_current_dir(path: &str) -> AxResult {
@@ -283,8 +283,8 @@ pub(crate) fn set_current_dir(path: &str) -> AxResult {
         abs_path += "/";
     }
     if abs_path == "/" {
-        *CURRENT_DIR.lock() = ROOT_DIR.clone();
-        *CURRENT_DIR_PATH.lock() = "/".into();
+        *CURRENT_DIR.write() = ROOT_DIR.clone();
+        *CURRENT_DIR_PATH.write() = "/".into();
         return Ok(());
     }
 
@@ -295,8 +295,8 @@ pub(crate) fn set_current_dir(path: &str) -> AxResult {
     } else if !attr.perm().owner_executable() {
         ax_err!(PermissionDenied)
     } else {
-        *CURRENT_DIR.lock() = node;
-        *CURRENT_DIR_PATH.lock() = abs_path;
+        *CURRENT_DIR.write() = node;
+        *CURRENT_DIR_PATH.write() = abs_path;
         Ok(())
     }
 }
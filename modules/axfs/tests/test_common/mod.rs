@@ -198,6 +198,13 @@ fn test_devfs_ramfs() -> Result<()> {
         .collect::<Vec<_>>();
     assert!(dirents.contains(&"null".into()));
     assert!(dirents.contains(&"zero".into()));
+    assert!(dirents.contains(&"random".into()));
+    assert!(dirents.contains(&"urandom".into()));
+
+    // read /dev/urandom, filled by the source set by `init_random_source()`
+    let mut file = File::open("/dev/urandom")?;
+    assert_eq!(file.read(&mut buf)?, N);
+    assert_eq!(buf, [RANDOM_BYTE; N]);
 
     // stat /dev
     let dname = "/dev";
@@ -252,6 +259,15 @@ fn test_devfs_ramfs() -> Result<()> {
     Ok(())
 }
 
+/// The byte the test source fills the buffers read from `/dev/urandom` with.
+const RANDOM_BYTE: u8 = 0x5a;
+
+/// Sets the random source of devfs, to be called before mounting the
+/// filesystems.
+pub fn init_random_source() {
+    axfs::set_random_source(|buf| buf.fill(RANDOM_BYTE));
+}
+
 pub fn test_all() {
     test_read_write_file().expect("test_read_write_file() failed");
     test_read_dir().expect("test_read_dir() failed");
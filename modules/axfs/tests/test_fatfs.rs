@@ -21,6 +21,7 @@ fn test_fatfs() {
 
     let disk = make_disk().expect("failed to load disk image");
     axtask::init_scheduler(); // call this to use `axsync::Mutex`.
+    test_common::init_random_source();
     axfs::init_filesystems(AxDeviceContainer::from_one(disk));
 
     test_common::test_all();
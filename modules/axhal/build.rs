@@ -3,8 +3,10 @@ use std::path::Path;
 
 const BUILTIN_PLATFORMS: &[&str] = &[
     "aarch64-bsta1000b",
+    "aarch64-phytium-pi",
     "aarch64-qemu-virt",
     "aarch64-raspi4",
+    "loongarch64-qemu-virt",
     "riscv64-qemu-virt",
     "x86_64-pc-oslab",
     "x86_64-qemu-q35",
@@ -12,8 +14,10 @@ const BUILTIN_PLATFORMS: &[&str] = &[
 
 const BUILTIN_PLATFORM_FAMILIES: &[&str] = &[
     "aarch64-bsta1000b",
+    "aarch64-phytium-pi",
     "aarch64-qemu-virt",
     "aarch64-raspi",
+    "loongarch64-qemu-virt",
     "riscv64-qemu-virt",
     "x86-pc",
 ];
@@ -33,6 +37,10 @@ fn main() {
         gen_linker_script(&arch, platform).unwrap();
     }
 
+    if std::env::var("CARGO_FEATURE_KSYMS").is_ok() {
+        gen_ksyms().unwrap();
+    }
+
     println!("cargo:rustc-cfg=platform=\"{}\"", platform);
     println!("cargo:rustc-cfg=platform_family=\"{}\"", axconfig::FAMILY);
     println!(
@@ -69,3 +77,21 @@ fn gen_linker_script(arch: &str, platform: &str) -> Result<()> {
     std::fs::write(out_path, ld_content)?;
     Ok(())
 }
+
+/// Copies the symbol table given by `AX_KSYMS` to be embedded, or creates an
+/// empty one if it is not generated yet.
+fn gen_ksyms() -> Result<()> {
+    println!("cargo:rerun-if-env-changed=AX_KSYMS");
+    let out_path = Path::new(&std::env::var("OUT_DIR").unwrap()).join("ksyms.txt");
+    let path = std::env::var("AX_KSYMS").unwrap_or_default();
+    if !path.is_empty() {
+        // Also reruns on the next build if the file does not exist yet.
+        println!("cargo:rerun-if-changed={}", path);
+    }
+    if Path::new(&path).is_file() {
+        std::fs::copy(path, out_path)?;
+    } else {
+        std::fs::write(out_path, "")?;
+    }
+    Ok(())
+}
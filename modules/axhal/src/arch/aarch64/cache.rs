@@ -0,0 +1,37 @@
+//! Data cache maintenance to the point of coherency.
+
+use core::arch::asm;
+
+/// DMA of the devices is not assumed to snoop the caches.
+pub(crate) fn is_dma_coherent() -> bool {
+    false
+}
+
+/// Returns the smallest data cache line size, from `CTR_EL0.DminLine`.
+pub(crate) fn dcache_line_size() -> usize {
+    let ctr: usize;
+    unsafe { asm!("mrs {}, ctr_el0", out(reg) ctr, options(nomem, nostack)) };
+    // Log2 of the number of 4-byte words.
+    4 << ((ctr >> 16) & 0xf)
+}
+
+/// Writes back the cache line at `addr` to memory.
+pub(crate) fn clean_line(addr: usize) {
+    unsafe { asm!("dc cvac, {}", in(reg) addr, options(nostack)) }
+}
+
+/// Discards the cache line at `addr`, without writing it back.
+pub(crate) fn invalidate_line(addr: usize) {
+    unsafe { asm!("dc ivac, {}", in(reg) addr, options(nostack)) }
+}
+
+/// Writes back and discards the cache line at `addr`.
+pub(crate) fn clean_invalidate_line(addr: usize) {
+    unsafe { asm!("dc civac, {}", in(reg) addr, options(nostack)) }
+}
+
+/// Waits for the cache operations to complete before the following memory
+/// accesses, e.g., to the doorbell register of a device.
+pub(crate) fn sync() {
+    unsafe { asm!("dsb sy", options(nostack)) }
+}
@@ -1,4 +1,6 @@
+pub(crate) mod cache;
 mod context;
+pub(crate) mod perf;
 pub(crate) mod trap;
 
 use core::arch::asm;
@@ -12,6 +14,8 @@ pub use self::context::{FpState, TaskContext, TrapFrame};
 /// Allows the current CPU to respond to interrupts.
 #[inline]
 pub fn enable_irqs() {
+    #[cfg(feature = "latency-trace")]
+    crate::latency::trace_on(crate::latency::LatencyKind::IrqOff);
     unsafe { asm!("msr daifclr, #2") };
 }
 
@@ -19,6 +23,8 @@ pub fn enable_irqs() {
 #[inline]
 pub fn disable_irqs() {
     unsafe { asm!("msr daifset, #2") };
+    #[cfg(feature = "latency-trace")]
+    crate::latency::trace_off(crate::latency::LatencyKind::IrqOff);
 }
 
 /// Returns whether the current CPU is allowed to respond to interrupts.
@@ -0,0 +1,98 @@
+//! The generic timer counter, and the PMUv3 event counters.
+
+use core::arch::asm;
+
+use aarch64_cpu::registers::CNTVCT_EL0;
+use tock_registers::interfaces::Readable;
+
+use crate::perf::{PerfError, PerfEvent};
+
+/// Enables the PMU counters.
+const PMCR_E: u64 = 1 << 0;
+/// Allows EL0 to access the PMU.
+const PMUSERENR_EN: u64 = 1 << 0;
+/// Allows EL0 to read `CNTVCT_EL0`.
+const CNTKCTL_EL0VCTEN: u64 = 1 << 1;
+
+macro_rules! read_sysreg {
+    ($reg:literal) => {{
+        let val: u64;
+        unsafe { asm!(concat!("mrs {}, ", $reg), out(reg) val) };
+        val
+    }};
+}
+
+macro_rules! write_sysreg {
+    ($reg:literal, $val:expr) => {
+        unsafe { asm!(concat!("msr ", $reg, ", {}"), "isb", in(reg) $val as u64) }
+    };
+}
+
+/// Returns the common event number of PMUv3.
+fn event_number(event: PerfEvent) -> u64 {
+    match event {
+        PerfEvent::Instructions => 0x08, // INST_RETIRED
+        PerfEvent::CacheMisses => 0x03,  // L1D_CACHE_REFILL
+    }
+}
+
+fn has_pmu() -> bool {
+    // 0 means not implemented, 0xf means an IMPLEMENTATION DEFINED PMU.
+    let version = (read_sysreg!("id_aa64dfr0_el1") >> 8) & 0xf;
+    version != 0 && version != 0xf
+}
+
+/// Allows EL0 to read the virtual counter.
+pub(crate) fn init_percpu() {
+    let ctl = read_sysreg!("cntkctl_el1");
+    write_sysreg!("cntkctl_el1", ctl | CNTKCTL_EL0VCTEN);
+}
+
+#[inline]
+pub fn read_cycles() -> u64 {
+    CNTVCT_EL0.get()
+}
+
+pub fn cycles_to_nanos(cycles: u64) -> u64 {
+    crate::time::ticks_to_nanos(cycles)
+}
+
+pub fn start_event(event: PerfEvent) -> Result<(), PerfError> {
+    if !has_pmu() || (read_sysreg!("pmcr_el0") >> 11) & 0x1f == 0 {
+        return Err(PerfError::Unsupported);
+    }
+    // Common events 0x00..0x20 are reported in PMCEID0_EL0.
+    let number = event_number(event);
+    if read_sysreg!("pmceid0_el0") & (1 << number) == 0 {
+        return Err(PerfError::Unsupported);
+    }
+    write_sysreg!("pmcntenclr_el0", 1);
+    // Leave the overflow interrupt masked.
+    write_sysreg!("pmintenclr_el1", 1);
+    // Count at EL0 and EL1, as the filter bits are all clear.
+    write_sysreg!("pmevtyper0_el0", number);
+    write_sysreg!("pmevcntr0_el0", 0);
+    write_sysreg!("pmovsclr_el0", 1);
+    write_sysreg!("pmuserenr_el0", PMUSERENR_EN);
+    let pmcr = read_sysreg!("pmcr_el0");
+    write_sysreg!("pmcr_el0", pmcr | PMCR_E);
+    write_sysreg!("pmcntenset_el0", 1);
+    Ok(())
+}
+
+fn is_started() -> bool {
+    has_pmu() && read_sysreg!("pmcntenset_el0") & 1 != 0
+}
+
+pub fn read_event() -> Result<u64, PerfError> {
+    if !is_started() {
+        return Err(PerfError::NotStarted);
+    }
+    Ok(read_sysreg!("pmevcntr0_el0"))
+}
+
+pub fn stop_event() {
+    if is_started() {
+        write_sysreg!("pmcntenclr_el0", 1);
+    }
+}
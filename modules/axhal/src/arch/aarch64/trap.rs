@@ -1,10 +1,10 @@
 use core::arch::global_asm;
 
 use aarch64_cpu::registers::{ESR_EL1, FAR_EL1};
-use page_table_entry::MappingFlags;
 use tock_registers::interfaces::Readable;
 
 use super::TrapFrame;
+use crate::trap::{PageFaultAccess, PageFaultInfo};
 
 global_asm!(include_str!("trap.S"));
 
@@ -30,6 +30,7 @@ enum TrapSource {
 
 #[no_mangle]
 fn invalid_exception(tf: &TrapFrame, kind: TrapKind, source: TrapSource) {
+    crate::backtrace::set_trap_origin(tf.elr as _, tf.r[29] as _);
     panic!(
         "Invalid exception {:?} from {:?}:\n{:#x?}",
         kind, source, tf
@@ -42,53 +43,42 @@ fn handle_irq_exception(_tf: &TrapFrame) {
 }
 
 fn handle_instruction_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
-    let mut access_flags = MappingFlags::EXECUTE;
-    if is_user {
-        access_flags |= MappingFlags::USER;
-    }
-    let vaddr = va!(FAR_EL1.get() as usize);
-
-    // Only handle Translation fault and Permission fault
-    if !matches!(iss & 0b111100, 0b0100 | 0b1100) // IFSC or DFSC bits
-        || !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user)
-    {
-        panic!(
-            "Unhandled {} Instruction Abort @ {:#x}, fault_vaddr={:#x}, ISS={:#x} ({:?}):\n{:#x?}",
-            if is_user { "EL0" } else { "EL1" },
-            tf.elr,
-            vaddr,
-            iss,
-            access_flags,
-            tf,
-        );
-    }
+    handle_page_fault(
+        tf,
+        iss,
+        PageFaultAccess::Execute,
+        is_user,
+        "Instruction Abort",
+    );
 }
 
 fn handle_data_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
     let wnr = (iss & (1 << 6)) != 0; // WnR: Write not Read
     let cm = (iss & (1 << 8)) != 0; // CM: Cache maintenance
-    let mut access_flags = if wnr & !cm {
-        MappingFlags::WRITE
+    let access = if wnr & !cm {
+        PageFaultAccess::Write
     } else {
-        MappingFlags::READ
+        PageFaultAccess::Read
+    };
+    handle_page_fault(tf, iss, access, is_user, "Data Abort");
+}
+
+fn handle_page_fault(tf: &TrapFrame, iss: u64, access: PageFaultAccess, is_user: bool, kind: &str) {
+    let info = PageFaultInfo {
+        vaddr: va!(FAR_EL1.get() as usize),
+        access,
+        user: is_user,
     };
-    if is_user {
-        access_flags |= MappingFlags::USER;
-    }
-    let vaddr = va!(FAR_EL1.get() as usize);
 
     // Only handle Translation fault and Permission fault
     if !matches!(iss & 0b111100, 0b0100 | 0b1100) // IFSC or DFSC bits
-        || !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user)
+        || !crate::trap::handle_page_fault(&info)
     {
+        crate::backtrace::set_trap_origin(tf.elr as _, tf.r[29] as _);
+        crate::trap::check_stack_overflow(&info);
         panic!(
-            "Unhandled {} Data Abort @ {:#x}, fault_vaddr={:#x}, ISS=0b{:08b} ({:?}):\n{:#x?}",
-            if is_user { "EL0" } else { "EL1" },
-            tf.elr,
-            vaddr,
-            iss,
-            access_flags,
-            tf,
+            "Unhandled {} ({}) @ {:#x}, ISS={:#x}:\n{:#x?}",
+            info, kind, tf.elr, iss, tf,
         );
     }
 }
@@ -110,6 +100,7 @@ fn handle_sync_exception(tf: &mut TrapFrame) {
             tf.elr += 4;
         }
         _ => {
+            crate::backtrace::set_trap_origin(tf.elr as _, tf.r[29] as _);
             panic!(
                 "Unhandled synchronous exception @ {:#x}: ESR={:#x} (EC {:#08b}, ISS {:#x})",
                 tf.elr,
@@ -0,0 +1,30 @@
+//! Data cache maintenance, which is not needed as DMA is coherent with the
+//! caches on LoongArch.
+
+/// The cache line size of the Loongson 3A5000 and later cores.
+const LINE_SIZE: usize = 64;
+
+/// DMA snoops the caches by hardware.
+pub(crate) fn is_dma_coherent() -> bool {
+    true
+}
+
+/// Returns the data cache line size.
+pub(crate) fn dcache_line_size() -> usize {
+    LINE_SIZE
+}
+
+/// Writes back the cache line at `addr` to memory.
+pub(crate) fn clean_line(_addr: usize) {}
+
+/// Discards the cache line at `addr`, without writing it back.
+pub(crate) fn invalidate_line(_addr: usize) {}
+
+/// Writes back and discards the cache line at `addr`.
+pub(crate) fn clean_invalidate_line(_addr: usize) {}
+
+/// Waits for the memory accesses to complete before the following ones,
+/// e.g., to the doorbell register of a device.
+pub(crate) fn sync() {
+    unsafe { core::arch::asm!("dbar 0", options(nostack)) }
+}
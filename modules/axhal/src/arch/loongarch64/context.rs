@@ -0,0 +1,288 @@
+use core::arch::naked_asm;
+use memory_addr::VirtAddr;
+
+/// General registers of LoongArch64, in the order of their numbers (`r0` to
+/// `r31`).
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Debug, Default, Clone)]
+pub struct GeneralRegisters {
+    pub zero: usize,
+    pub ra: usize,
+    pub tp: usize,
+    pub sp: usize,
+    pub a0: usize,
+    pub a1: usize,
+    pub a2: usize,
+    pub a3: usize,
+    pub a4: usize,
+    pub a5: usize,
+    pub a6: usize,
+    pub a7: usize,
+    pub t0: usize,
+    pub t1: usize,
+    pub t2: usize,
+    pub t3: usize,
+    pub t4: usize,
+    pub t5: usize,
+    pub t6: usize,
+    pub t7: usize,
+    pub t8: usize,
+    pub u0: usize, // r21, the per-CPU data base
+    pub fp: usize,
+    pub s0: usize,
+    pub s1: usize,
+    pub s2: usize,
+    pub s3: usize,
+    pub s4: usize,
+    pub s5: usize,
+    pub s6: usize,
+    pub s7: usize,
+    pub s8: usize,
+}
+
+/// Saved registers when a trap (interrupt or exception) occurs.
+#[repr(C)]
+#[derive(Debug, Default, Clone)]
+pub struct TrapFrame {
+    /// All general registers.
+    pub regs: GeneralRegisters,
+    /// Pre-exception Mode Information.
+    pub prmd: usize,
+    /// Exception Return Address.
+    pub era: usize,
+}
+
+/// Floating-point registers.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct FpState {
+    /// 64-bit floating-point registers (`f0`..`f31`).
+    pub regs: [u64; 32],
+    /// Condition flag registers (`fcc0`..`fcc7`), one in each byte.
+    pub fcc: u64,
+    /// Floating-point Control and Status Register (`fcsr0`).
+    pub fcsr: u32,
+}
+
+#[cfg(feature = "fp_simd")]
+impl FpState {
+    fn switch_to(&mut self, next_fpstate: &FpState) {
+        unsafe { fpstate_switch(self, next_fpstate) }
+    }
+}
+
+/// Saved hardware states of a task.
+///
+/// The context usually includes:
+///
+/// - Callee-saved registers
+/// - Stack pointer register
+/// - Thread pointer register (for thread-local storage, currently unsupported)
+/// - FP/SIMD registers
+///
+/// On context switch, current task saves its context from CPU to memory,
+/// and the next task restores its context from memory to CPU.
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct TaskContext {
+    pub ra: usize, // return address (r1)
+    pub sp: usize, // stack pointer (r3)
+
+    pub s0: usize, // r23-r31
+    pub s1: usize,
+    pub s2: usize,
+    pub s3: usize,
+    pub s4: usize,
+    pub s5: usize,
+    pub s6: usize,
+    pub s7: usize,
+    pub s8: usize,
+
+    pub fp: usize, // frame pointer (r22)
+
+    pub tp: usize,
+    #[cfg(feature = "fp_simd")]
+    pub fp_state: FpState,
+}
+
+impl TaskContext {
+    /// Creates a new default context for a new task.
+    pub const fn new() -> Self {
+        unsafe { core::mem::MaybeUninit::zeroed().assume_init() }
+    }
+
+    /// Initializes the context for a new task, with the given entry point and
+    /// kernel stack.
+    pub fn init(&mut self, entry: usize, kstack_top: VirtAddr, tls_area: VirtAddr) {
+        self.sp = kstack_top.as_usize();
+        self.ra = entry;
+        self.tp = tls_area.as_usize();
+    }
+
+    /// Switches to another task.
+    ///
+    /// It first saves the current task's context from CPU to this place, and then
+    /// restores the next task's context from `next_ctx` to CPU.
+    pub fn switch_to(&mut self, next_ctx: &Self) {
+        #[cfg(feature = "tls")]
+        {
+            self.tp = super::read_thread_pointer();
+            unsafe { super::write_thread_pointer(next_ctx.tp) };
+        }
+        #[cfg(feature = "fp_simd")]
+        self.fp_state.switch_to(&next_ctx.fp_state);
+        unsafe { context_switch(self, next_ctx) }
+    }
+}
+
+#[unsafe(naked)]
+unsafe extern "C" fn context_switch(_current_task: &mut TaskContext, _next_task: &TaskContext) {
+    naked_asm!(
+        "
+        // save old context (callee-saved registers)
+        st.d    $ra, $a0, 0
+        st.d    $sp, $a0, 8
+        st.d    $s0, $a0, 16
+        st.d    $s1, $a0, 24
+        st.d    $s2, $a0, 32
+        st.d    $s3, $a0, 40
+        st.d    $s4, $a0, 48
+        st.d    $s5, $a0, 56
+        st.d    $s6, $a0, 64
+        st.d    $s7, $a0, 72
+        st.d    $s8, $a0, 80
+        st.d    $fp, $a0, 88
+
+        // restore new context
+        ld.d    $fp, $a1, 88
+        ld.d    $s8, $a1, 80
+        ld.d    $s7, $a1, 72
+        ld.d    $s6, $a1, 64
+        ld.d    $s5, $a1, 56
+        ld.d    $s4, $a1, 48
+        ld.d    $s3, $a1, 40
+        ld.d    $s2, $a1, 32
+        ld.d    $s1, $a1, 24
+        ld.d    $s0, $a1, 16
+        ld.d    $sp, $a1, 8
+        ld.d    $ra, $a1, 0
+
+        ret",
+    )
+}
+
+#[unsafe(naked)]
+#[cfg(feature = "fp_simd")]
+unsafe extern "C" fn fpstate_switch(_current_fpstate: &mut FpState, _next_fpstate: &FpState) {
+    naked_asm!(
+        "
+        // save fp context
+        fst.d   $f0, $a0, 0 * 8
+        fst.d   $f1, $a0, 1 * 8
+        fst.d   $f2, $a0, 2 * 8
+        fst.d   $f3, $a0, 3 * 8
+        fst.d   $f4, $a0, 4 * 8
+        fst.d   $f5, $a0, 5 * 8
+        fst.d   $f6, $a0, 6 * 8
+        fst.d   $f7, $a0, 7 * 8
+        fst.d   $f8, $a0, 8 * 8
+        fst.d   $f9, $a0, 9 * 8
+        fst.d   $f10, $a0, 10 * 8
+        fst.d   $f11, $a0, 11 * 8
+        fst.d   $f12, $a0, 12 * 8
+        fst.d   $f13, $a0, 13 * 8
+        fst.d   $f14, $a0, 14 * 8
+        fst.d   $f15, $a0, 15 * 8
+        fst.d   $f16, $a0, 16 * 8
+        fst.d   $f17, $a0, 17 * 8
+        fst.d   $f18, $a0, 18 * 8
+        fst.d   $f19, $a0, 19 * 8
+        fst.d   $f20, $a0, 20 * 8
+        fst.d   $f21, $a0, 21 * 8
+        fst.d   $f22, $a0, 22 * 8
+        fst.d   $f23, $a0, 23 * 8
+        fst.d   $f24, $a0, 24 * 8
+        fst.d   $f25, $a0, 25 * 8
+        fst.d   $f26, $a0, 26 * 8
+        fst.d   $f27, $a0, 27 * 8
+        fst.d   $f28, $a0, 28 * 8
+        fst.d   $f29, $a0, 29 * 8
+        fst.d   $f30, $a0, 30 * 8
+        fst.d   $f31, $a0, 31 * 8
+        movcf2gr $t0, $fcc0
+        move    $t1, $t0
+        movcf2gr $t0, $fcc1
+        bstrins.d $t1, $t0, 15, 8
+        movcf2gr $t0, $fcc2
+        bstrins.d $t1, $t0, 23, 16
+        movcf2gr $t0, $fcc3
+        bstrins.d $t1, $t0, 31, 24
+        movcf2gr $t0, $fcc4
+        bstrins.d $t1, $t0, 39, 32
+        movcf2gr $t0, $fcc5
+        bstrins.d $t1, $t0, 47, 40
+        movcf2gr $t0, $fcc6
+        bstrins.d $t1, $t0, 55, 48
+        movcf2gr $t0, $fcc7
+        bstrins.d $t1, $t0, 63, 56
+        movfcsr2gr $t2, $fcsr0
+        st.d    $t1, $a0, 32 * 8
+        st.w    $t2, $a0, 33 * 8
+
+        // restore fp context
+        fld.d   $f0, $a1, 0 * 8
+        fld.d   $f1, $a1, 1 * 8
+        fld.d   $f2, $a1, 2 * 8
+        fld.d   $f3, $a1, 3 * 8
+        fld.d   $f4, $a1, 4 * 8
+        fld.d   $f5, $a1, 5 * 8
+        fld.d   $f6, $a1, 6 * 8
+        fld.d   $f7, $a1, 7 * 8
+        fld.d   $f8, $a1, 8 * 8
+        fld.d   $f9, $a1, 9 * 8
+        fld.d   $f10, $a1, 10 * 8
+        fld.d   $f11, $a1, 11 * 8
+        fld.d   $f12, $a1, 12 * 8
+        fld.d   $f13, $a1, 13 * 8
+        fld.d   $f14, $a1, 14 * 8
+        fld.d   $f15, $a1, 15 * 8
+        fld.d   $f16, $a1, 16 * 8
+        fld.d   $f17, $a1, 17 * 8
+        fld.d   $f18, $a1, 18 * 8
+        fld.d   $f19, $a1, 19 * 8
+        fld.d   $f20, $a1, 20 * 8
+        fld.d   $f21, $a1, 21 * 8
+        fld.d   $f22, $a1, 22 * 8
+        fld.d   $f23, $a1, 23 * 8
+        fld.d   $f24, $a1, 24 * 8
+        fld.d   $f25, $a1, 25 * 8
+        fld.d   $f26, $a1, 26 * 8
+        fld.d   $f27, $a1, 27 * 8
+        fld.d   $f28, $a1, 28 * 8
+        fld.d   $f29, $a1, 29 * 8
+        fld.d   $f30, $a1, 30 * 8
+        fld.d   $f31, $a1, 31 * 8
+        ld.d    $t1, $a1, 32 * 8
+        ld.w    $t2, $a1, 33 * 8
+        movgr2cf $fcc0, $t1
+        bstrpick.d $t0, $t1, 15, 8
+        movgr2cf $fcc1, $t0
+        bstrpick.d $t0, $t1, 23, 16
+        movgr2cf $fcc2, $t0
+        bstrpick.d $t0, $t1, 31, 24
+        movgr2cf $fcc3, $t0
+        bstrpick.d $t0, $t1, 39, 32
+        movgr2cf $fcc4, $t0
+        bstrpick.d $t0, $t1, 47, 40
+        movgr2cf $fcc5, $t0
+        bstrpick.d $t0, $t1, 55, 48
+        movgr2cf $fcc6, $t0
+        bstrpick.d $t0, $t1, 63, 56
+        movgr2cf $fcc7, $t0
+        movgr2fcsr $fcsr0, $t2
+
+        ret",
+    )
+}
@@ -0,0 +1,178 @@
+pub(crate) mod cache;
+mod context;
+pub(crate) mod perf;
+mod trap;
+
+use core::arch::asm;
+
+use memory_addr::{PhysAddr, VirtAddr};
+
+pub use self::context::{GeneralRegisters, TaskContext, TrapFrame};
+
+/// `IE` bit in `CRMD`, the global interrupt enable.
+const CRMD_IE: usize = 1 << 2;
+/// `VS` field in `ECFG`, the spacing of the exception entries.
+const ECFG_VS: usize = 0x7 << 16;
+
+/// Allows the current CPU to respond to interrupts.
+#[inline]
+pub fn enable_irqs() {
+    #[cfg(feature = "latency-trace")]
+    crate::latency::trace_on(crate::latency::LatencyKind::IrqOff);
+    unsafe { asm!("csrxchg {}, {}, 0x0", inout(reg) CRMD_IE => _, in(reg) CRMD_IE) }
+}
+
+/// Makes the current CPU to ignore interrupts.
+#[inline]
+pub fn disable_irqs() {
+    unsafe { asm!("csrxchg {}, {}, 0x0", inout(reg) 0usize => _, in(reg) CRMD_IE) }
+    #[cfg(feature = "latency-trace")]
+    crate::latency::trace_off(crate::latency::LatencyKind::IrqOff);
+}
+
+/// Returns whether the current CPU is allowed to respond to interrupts.
+#[inline]
+pub fn irqs_enabled() -> bool {
+    let crmd: usize;
+    unsafe { asm!("csrrd {}, 0x0", out(reg) crmd) };
+    crmd & CRMD_IE != 0
+}
+
+/// Relaxes the current CPU and waits for interrupts.
+///
+/// It must be called with interrupts enabled, otherwise it will never return.
+#[inline]
+pub fn wait_for_irqs() {
+    unsafe { asm!("idle 0") }
+}
+
+/// Halt the current CPU.
+#[inline]
+pub fn halt() {
+    disable_irqs();
+    unsafe { asm!("idle 0") } // should never return
+}
+
+/// Reads the register that stores the current page table root.
+///
+/// Returns the physical address of the page table root.
+#[inline]
+pub fn read_page_table_root() -> PhysAddr {
+    let pgdh: usize;
+    unsafe { asm!("csrrd {}, 0x1a", out(reg) pgdh) };
+    pa!(pgdh)
+}
+
+/// Reads the `PGDL` register.
+pub fn read_page_table_root0() -> PhysAddr {
+    let pgdl: usize;
+    unsafe { asm!("csrrd {}, 0x19", out(reg) pgdl) };
+    pa!(pgdl)
+}
+
+/// Writes the register to update the current page table root.
+///
+/// # Safety
+///
+/// This function is unsafe as it changes the virtual memory address space.
+pub unsafe fn write_page_table_root(root_paddr: PhysAddr) {
+    let old_root = read_page_table_root();
+    trace!("set page table root: {:#x} => {:#x}", old_root, root_paddr);
+    if old_root != root_paddr {
+        // kernel space page table use PGDH (0xffff_0000_0000_0000..0xffff_ffff_ffff_ffff)
+        asm!("csrwr {}, 0x1a", inout(reg) root_paddr.as_usize() => _);
+        flush_tlb(None);
+    }
+}
+
+/// Writes the `PGDL` register.
+///
+/// # Safety
+///
+/// This function is unsafe as it changes the virtual memory address space.
+pub unsafe fn write_page_table_root0(root_paddr: PhysAddr) {
+    asm!("csrwr {}, 0x19", inout(reg) root_paddr.as_usize() => _);
+    flush_tlb(None);
+}
+
+/// Flushes the TLB.
+///
+/// If `vaddr` is [`None`], flushes the entire TLB. Otherwise, flushes the TLB
+/// entry that maps the given virtual address.
+#[inline]
+pub fn flush_tlb(vaddr: Option<VirtAddr>) {
+    unsafe {
+        if let Some(vaddr) = vaddr {
+            // the global entries, or those of ASID 0 (`$zero`)
+            asm!("dbar 0; invtlb 0x6, $zero, {}", in(reg) vaddr.as_usize())
+        } else {
+            // flush the entire TLB
+            asm!("dbar 0; invtlb 0x0, $zero, $zero")
+        }
+    }
+}
+
+/// Writes the Exception Entry Base Address register (`EENTRY`), where all
+/// exceptions and interrupts but the TLB refill ones enter.
+#[inline]
+pub fn set_trap_vector_base(eentry: usize) {
+    unsafe {
+        // a single entry for all exceptions and interrupts
+        asm!("csrxchg {}, {}, 0x4", inout(reg) 0usize => _, in(reg) ECFG_VS);
+        asm!("csrwr {}, 0xc", inout(reg) eentry => _);
+    }
+}
+
+/// Reads the thread pointer of the current CPU.
+///
+/// It is used to implement TLS (Thread Local Storage).
+#[inline]
+pub fn read_thread_pointer() -> usize {
+    let tp;
+    unsafe { asm!("move {}, $tp", out(reg) tp) };
+    tp
+}
+
+/// Writes the thread pointer of the current CPU.
+///
+/// It is used to implement TLS (Thread Local Storage).
+///
+/// # Safety
+///
+/// This function is unsafe as it changes the CPU states.
+#[inline]
+pub unsafe fn write_thread_pointer(tp: usize) {
+    asm!("move $tp, {}", in(reg) tp)
+}
+
+/// Reads the 32-bit IOCSR (I/O control and status register) at `reg`.
+#[cfg(any(feature = "smp", feature = "irq"))]
+#[inline]
+pub(crate) fn iocsr_read_w(reg: usize) -> u32 {
+    let val: u32;
+    unsafe { asm!("iocsrrd.w {}, {}", out(reg) val, in(reg) reg) };
+    val
+}
+
+/// Writes the 32-bit IOCSR (I/O control and status register) at `reg`.
+#[cfg(any(feature = "smp", feature = "irq"))]
+#[inline]
+pub(crate) fn iocsr_write_w(reg: usize, val: u32) {
+    unsafe { asm!("iocsrwr.w {}, {}", in(reg) val, in(reg) reg) }
+}
+
+/// Reads the 64-bit IOCSR (I/O control and status register) at `reg`.
+#[cfg(any(feature = "smp", feature = "irq"))]
+#[inline]
+pub(crate) fn iocsr_read_d(reg: usize) -> u64 {
+    let val: u64;
+    unsafe { asm!("iocsrrd.d {}, {}", out(reg) val, in(reg) reg) };
+    val
+}
+
+/// Writes the 64-bit IOCSR (I/O control and status register) at `reg`.
+#[cfg(any(feature = "smp", feature = "irq"))]
+#[inline]
+pub(crate) fn iocsr_write_d(reg: usize, val: u64) {
+    unsafe { asm!("iocsrwr.d {}, {}", in(reg) val, in(reg) reg) }
+}
@@ -0,0 +1,28 @@
+//! The stable counter. The performance counters are not supported yet.
+
+use crate::perf::{PerfError, PerfEvent};
+
+/// The stable counter is always readable at all privilege levels.
+pub(crate) fn init_percpu() {}
+
+/// Reads the stable counter, which is also the timer clock.
+#[inline]
+pub fn read_cycles() -> u64 {
+    let cnt: u64;
+    unsafe { core::arch::asm!("rdtime.d {}, $zero", out(reg) cnt) };
+    cnt
+}
+
+pub fn cycles_to_nanos(cycles: u64) -> u64 {
+    crate::time::ticks_to_nanos(cycles)
+}
+
+pub fn start_event(_event: PerfEvent) -> Result<(), PerfError> {
+    Err(PerfError::Unsupported)
+}
+
+pub fn read_event() -> Result<u64, PerfError> {
+    Err(PerfError::NotStarted)
+}
+
+pub fn stop_event() {}
@@ -0,0 +1,83 @@
+use core::arch::asm;
+
+use super::TrapFrame;
+use crate::trap::{PageFaultAccess, PageFaultInfo};
+
+core::arch::global_asm!(
+    include_str!("trap.S"),
+    trapframe_size = const core::mem::size_of::<TrapFrame>(),
+);
+
+/// Interrupt flag of the numbers passed to the IRQ handler, which are the
+/// interrupt bits in `ESTAT.IS` otherwise, as `scause` on RISC-V.
+const INT_IRQ_BASE: usize = 1 << (usize::BITS - 1);
+
+/// `IS` field in `ESTAT` and `LIE` field in `ECFG`.
+const INT_MASK: usize = 0x1fff;
+
+// Exception codes (`ESTAT.Ecode`).
+const ECODE_INT: usize = 0x0;
+const ECODE_PIL: usize = 0x1;
+const ECODE_PIS: usize = 0x2;
+const ECODE_PIF: usize = 0x3;
+const ECODE_PME: usize = 0x4;
+const ECODE_PNR: usize = 0x5;
+const ECODE_PNX: usize = 0x6;
+const ECODE_BRK: usize = 0xc;
+
+fn handle_breakpoint(era: &mut usize) {
+    debug!("Exception(Breakpoint) @ {:#x} ", era);
+    *era += 4
+}
+
+fn handle_page_fault(tf: &TrapFrame, access: PageFaultAccess, is_user: bool) {
+    let badv: usize;
+    unsafe { asm!("csrrd {}, 0x7", out(reg) badv) };
+    let info = PageFaultInfo {
+        vaddr: va!(badv),
+        access,
+        user: is_user,
+    };
+    if !crate::trap::handle_page_fault(&info) {
+        crate::backtrace::set_trap_origin(tf.era, tf.regs.fp);
+        crate::trap::check_stack_overflow(&info);
+        panic!("Unhandled {} @ {:#x}:\n{:#x?}", info, tf.era, tf);
+    }
+}
+
+/// Handles the pending and enabled interrupts, from the lowest bit.
+fn handle_irqs(estat: usize) {
+    let ecfg: usize;
+    unsafe { asm!("csrrd {}, 0x4", out(reg) ecfg) };
+    let mut pending = estat & ecfg & INT_MASK;
+    while pending != 0 {
+        let bit = pending.trailing_zeros() as usize;
+        handle_trap!(IRQ, INT_IRQ_BASE + bit);
+        pending &= !(1 << bit);
+    }
+}
+
+#[no_mangle]
+fn loongarch64_trap_handler(tf: &mut TrapFrame) {
+    let estat: usize;
+    unsafe { asm!("csrrd {}, 0x5", out(reg) estat) };
+    // `PRMD.PPLV` is the privilege level before the trap.
+    let from_user = tf.prmd & 0x3 != 0;
+    match (estat >> 16) & 0x3f {
+        ECODE_INT => handle_irqs(estat),
+        ECODE_PIL | ECODE_PNR => handle_page_fault(tf, PageFaultAccess::Read, from_user),
+        ECODE_PIS | ECODE_PME => handle_page_fault(tf, PageFaultAccess::Write, from_user),
+        ECODE_PIF | ECODE_PNX => handle_page_fault(tf, PageFaultAccess::Execute, from_user),
+        ECODE_BRK => handle_breakpoint(&mut tf.era),
+        ecode => {
+            crate::backtrace::set_trap_origin(tf.era, tf.regs.fp);
+            panic!(
+                "Unhandled trap (Ecode {:#x}, EsubCode {:#x}) @ {:#x}:\n{:#x?}",
+                ecode,
+                (estat >> 22) & 0x1ff,
+                tf.era,
+                tf
+            );
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Data cache maintenance by the Zicbom extension, or the vendor instructions
+//! of the T-Head cores.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+// Instructions on the address in `a0`, which are not known by the assembler.
+/// `cbo.clean (a0)`
+const CBO_CLEAN_A0: u32 = 0x0015_200f;
+/// `cbo.inval (a0)`
+const CBO_INVAL_A0: u32 = 0x0005_200f;
+/// `cbo.flush (a0)`
+const CBO_FLUSH_A0: u32 = 0x0025_200f;
+/// `th.dcache.cva a0`
+const THEAD_CLEAN_A0: u32 = 0x0255_000b;
+/// `th.dcache.iva a0`
+const THEAD_INVAL_A0: u32 = 0x0265_000b;
+/// `th.dcache.civa a0`
+const THEAD_FLUSH_A0: u32 = 0x0275_000b;
+/// `th.sync.s`, waits for the cache operations on all cores.
+const THEAD_SYNC_S: u32 = 0x0190_000b;
+
+const OPS_UNKNOWN: u8 = 0;
+/// DMA is taken as coherent, e.g., on QEMU.
+const OPS_NONE: u8 = 1;
+const OPS_ZICBOM: u8 = 2;
+const OPS_THEAD: u8 = 3;
+
+/// Used if the device tree has no `riscv,cbom-block-size`.
+const DEFAULT_LINE_SIZE: usize = 64;
+
+static CACHE_OPS: AtomicU8 = AtomicU8::new(OPS_UNKNOWN);
+static LINE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+fn cache_ops() -> u8 {
+    let mut ops = CACHE_OPS.load(Ordering::Relaxed);
+    if ops == OPS_UNKNOWN {
+        let is_thead = super::boot_cpu_node()
+            .is_some_and(|cpu| cpu.compatible().any(|c| c.starts_with("thead,")));
+        ops = if super::has_isa_extension("zicbom") {
+            OPS_ZICBOM
+        } else if is_thead {
+            OPS_THEAD
+        } else {
+            OPS_NONE
+        };
+        CACHE_OPS.store(ops, Ordering::Relaxed);
+    }
+    ops
+}
+
+/// Whether DMA is coherent with the caches, so no maintenance is needed.
+pub(crate) fn is_dma_coherent() -> bool {
+    cache_ops() == OPS_NONE
+}
+
+/// Returns the size of the cache blocks operated by Zicbom.
+pub(crate) fn dcache_line_size() -> usize {
+    let mut size = LINE_SIZE.load(Ordering::Relaxed);
+    if size == 0 {
+        size = super::boot_cpu_node()
+            .and_then(|cpu| cpu.property_u32("riscv,cbom-block-size"))
+            .map_or(DEFAULT_LINE_SIZE, |size| size as usize);
+        LINE_SIZE.store(size, Ordering::Relaxed);
+    }
+    size
+}
+
+macro_rules! line_op {
+    ($addr:expr, $insn:expr) => {
+        unsafe { asm!(".long {insn}", insn = const $insn, in("a0") $addr, options(nostack)) }
+    };
+}
+
+/// Writes back the cache line at `addr` to memory.
+pub(crate) fn clean_line(addr: usize) {
+    match cache_ops() {
+        OPS_ZICBOM => line_op!(addr, CBO_CLEAN_A0),
+        OPS_THEAD => line_op!(addr, THEAD_CLEAN_A0),
+        _ => {}
+    }
+}
+
+/// Discards the cache line at `addr`, without writing it back.
+pub(crate) fn invalidate_line(addr: usize) {
+    match cache_ops() {
+        OPS_ZICBOM => line_op!(addr, CBO_INVAL_A0),
+        OPS_THEAD => line_op!(addr, THEAD_INVAL_A0),
+        _ => {}
+    }
+}
+
+/// Writes back and discards the cache line at `addr`.
+pub(crate) fn clean_invalidate_line(addr: usize) {
+    match cache_ops() {
+        OPS_ZICBOM => line_op!(addr, CBO_FLUSH_A0),
+        OPS_THEAD => line_op!(addr, THEAD_FLUSH_A0),
+        _ => {}
+    }
+}
+
+/// Waits for the cache operations to complete before the following memory
+/// accesses, e.g., to the doorbell register of a device.
+pub(crate) fn sync() {
+    match cache_ops() {
+        OPS_ZICBOM => unsafe { asm!("fence iorw, iorw", options(nostack)) },
+        OPS_THEAD => unsafe { asm!(".long {}", const THEAD_SYNC_S, options(nostack)) },
+        _ => {}
+    }
+}
@@ -1,7 +1,9 @@
 #[macro_use]
 mod macros;
 
+pub(crate) mod cache;
 mod context;
+pub(crate) mod perf;
 mod trap;
 
 use memory_addr::{PhysAddr, VirtAddr};
@@ -13,6 +15,8 @@ pub use self::context::{GeneralRegisters, TaskContext, TrapFrame};
 /// Allows the current CPU to respond to interrupts.
 #[inline]
 pub fn enable_irqs() {
+    #[cfg(feature = "latency-trace")]
+    crate::latency::trace_on(crate::latency::LatencyKind::IrqOff);
     unsafe { sstatus::set_sie() }
 }
 
@@ -20,6 +24,8 @@ pub fn enable_irqs() {
 #[inline]
 pub fn disable_irqs() {
     unsafe { sstatus::clear_sie() }
+    #[cfg(feature = "latency-trace")]
+    crate::latency::trace_off(crate::latency::LatencyKind::IrqOff);
 }
 
 /// Returns whether the current CPU is allowed to respond to interrupts.
@@ -107,3 +113,21 @@ pub fn read_thread_pointer() -> usize {
 pub unsafe fn write_thread_pointer(tp: usize) {
     core::arch::asm!("mv tp, {}", in(reg) tp)
 }
+
+/// Returns the device tree node of the boot CPU.
+pub(crate) fn boot_cpu_node() -> Option<crate::fdt::Node<'static>> {
+    crate::fdt::fdt().and_then(|fdt| fdt.find_node("/cpus/cpu"))
+}
+
+/// Whether the device tree lists the multi-letter ISA extension `ext` (in
+/// lower case) of the boot CPU, e.g., `zicbom`.
+pub(crate) fn has_isa_extension(ext: &str) -> bool {
+    let Some(cpu) = boot_cpu_node() else {
+        return false;
+    };
+    if let Some(exts) = cpu.property("riscv,isa-extensions") {
+        return exts.split(|&b| b == 0).any(|e| e == ext.as_bytes());
+    }
+    cpu.property_str("riscv,isa")
+        .is_some_and(|isa| isa.split('_').skip(1).any(|e| e == ext))
+}
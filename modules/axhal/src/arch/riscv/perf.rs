@@ -0,0 +1,173 @@
+//! The `cycle` CSR, and the event counters of the SBI PMU extension.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use riscv::register::{cycle, time};
+
+use crate::perf::{PerfError, PerfEvent};
+
+const SBI_EXT_PMU: usize = 0x504d55;
+const SBI_PMU_NUM_COUNTERS: usize = 0;
+const SBI_PMU_COUNTER_GET_INFO: usize = 1;
+const SBI_PMU_COUNTER_CONFIG_MATCHING: usize = 2;
+const SBI_PMU_COUNTER_STOP: usize = 4;
+
+const CFG_FLAG_CLEAR_VALUE: usize = 1 << 1;
+const CFG_FLAG_AUTO_START: usize = 1 << 2;
+/// Do not count in M-mode.
+const CFG_FLAG_SET_MINH: usize = 1 << 7;
+
+/// `scounteren` bits of the `cycle`, `time` and `instret` CSRs.
+const SCOUNTEREN_CY_TM_IR: usize = 0b111;
+
+/// Duration of the calibration of the cycle frequency.
+const CALIBRATION_NANOS: u64 = 10_000_000;
+
+const NO_COUNTER: usize = usize::MAX;
+
+/// Frequency of the `cycle` CSR, measured on the first use.
+static CYCLES_PER_SEC: AtomicU64 = AtomicU64::new(0);
+
+/// Index of the SBI PMU counter started on this CPU.
+#[percpu::def_percpu]
+static COUNTER_IDX: AtomicUsize = AtomicUsize::new(NO_COUNTER);
+
+/// Calls an SBI function, returns the value or the error code.
+fn sbi_call(fid: usize, args: [usize; 5]) -> Result<usize, isize> {
+    let (error, value): (isize, usize);
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") args[0] => error,
+            inlateout("a1") args[1] => value,
+            in("a2") args[2],
+            in("a3") args[3],
+            in("a4") args[4],
+            in("a6") fid,
+            in("a7") SBI_EXT_PMU,
+        );
+    }
+    if error == 0 {
+        Ok(value)
+    } else {
+        Err(error)
+    }
+}
+
+/// Returns the event index of the SBI hardware general events.
+fn event_idx(event: PerfEvent) -> usize {
+    match event {
+        PerfEvent::Instructions => 2, // SBI_PMU_HW_INSTRUCTIONS
+        PerfEvent::CacheMisses => 4,  // SBI_PMU_HW_CACHE_MISSES
+    }
+}
+
+/// Reads a hardware counter CSR by its number.
+fn read_counter_csr(csr: usize) -> Option<u64> {
+    macro_rules! read_csr {
+        ($($csr:literal),*) => {
+            match csr {
+                $($csr => {
+                    let val: usize;
+                    unsafe { asm!(concat!("csrr {}, ", stringify!($csr)), out(reg) val) };
+                    Some(val as u64)
+                })*
+                _ => None,
+            }
+        };
+    }
+    read_csr!(
+        0xc00, 0xc02, 0xc03, 0xc04, 0xc05, 0xc06, 0xc07, 0xc08, 0xc09, 0xc0a, 0xc0b, 0xc0c, 0xc0d,
+        0xc0e, 0xc0f, 0xc10, 0xc11, 0xc12, 0xc13, 0xc14, 0xc15, 0xc16, 0xc17, 0xc18, 0xc19, 0xc1a,
+        0xc1b, 0xc1c, 0xc1d, 0xc1e, 0xc1f
+    )
+}
+
+fn has_pmu() -> bool {
+    !sbi_rt::probe_extension(sbi_rt::Pmu).is_unavailable()
+}
+
+fn current_counter() -> &'static AtomicUsize {
+    unsafe { COUNTER_IDX.current_ref_raw() }
+}
+
+/// Allows U-mode to read the cycle, time and instret counters.
+pub(crate) fn init_percpu() {
+    unsafe { asm!("csrs scounteren, {}", in(reg) SCOUNTEREN_CY_TM_IR) };
+}
+
+#[inline]
+pub fn read_cycles() -> u64 {
+    cycle::read() as u64
+}
+
+/// Measures the frequency of the `cycle` CSR against the `time` CSR.
+fn cycles_per_sec() -> u64 {
+    let mut hz = CYCLES_PER_SEC.load(Ordering::Relaxed);
+    if hz == 0 {
+        let ticks = crate::time::nanos_to_ticks(CALIBRATION_NANOS);
+        let (start_time, start_cycles) = (time::read() as u64, read_cycles());
+        while (time::read() as u64) - start_time < ticks {
+            core::hint::spin_loop();
+        }
+        let cycles = read_cycles() - start_cycles;
+        hz = cycles * (crate::time::NANOS_PER_SEC / CALIBRATION_NANOS);
+        CYCLES_PER_SEC.store(hz, Ordering::Relaxed);
+    }
+    hz
+}
+
+/// Converts cycles to nanoseconds. The cycle frequency is calibrated on the
+/// first call, which takes 10 milliseconds.
+pub fn cycles_to_nanos(cycles: u64) -> u64 {
+    match cycles_per_sec() {
+        0 => 0,
+        hz => (cycles as u128 * crate::time::NANOS_PER_SEC as u128 / hz as u128) as u64,
+    }
+}
+
+pub fn start_event(event: PerfEvent) -> Result<(), PerfError> {
+    if !has_pmu() {
+        return Err(PerfError::Unsupported);
+    }
+    stop_event();
+    let num_counters = sbi_call(SBI_PMU_NUM_COUNTERS, [0; 5]).unwrap_or(0);
+    if num_counters == 0 {
+        return Err(PerfError::Unsupported);
+    }
+    let mask = if num_counters >= usize::BITS as usize {
+        usize::MAX
+    } else {
+        (1 << num_counters) - 1
+    };
+    // The interrupt on overflow is not enabled, as there is no `sscofpmf`
+    // overflow handler.
+    let flags = CFG_FLAG_CLEAR_VALUE | CFG_FLAG_AUTO_START | CFG_FLAG_SET_MINH;
+    let idx = sbi_call(
+        SBI_PMU_COUNTER_CONFIG_MATCHING,
+        [0, mask, flags, event_idx(event), 0],
+    )
+    .map_err(|_| PerfError::Unsupported)?;
+    current_counter().store(idx, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn read_event() -> Result<u64, PerfError> {
+    let idx = current_counter().load(Ordering::Relaxed);
+    if idx == NO_COUNTER {
+        return Err(PerfError::NotStarted);
+    }
+    let info = sbi_call(SBI_PMU_COUNTER_GET_INFO, [idx, 0, 0, 0, 0])
+        .map_err(|_| PerfError::Unsupported)?;
+    // Only hardware counters are matched for hardware events, whose CSR
+    // number is in the low 12 bits.
+    read_counter_csr(info & 0xfff).ok_or(PerfError::Unsupported)
+}
+
+pub fn stop_event() {
+    let idx = current_counter().swap(NO_COUNTER, Ordering::Relaxed);
+    if idx != NO_COUNTER {
+        let _ = sbi_call(SBI_PMU_COUNTER_STOP, [idx, 1, 0, 0, 0]);
+    }
+}
@@ -1,8 +1,8 @@
-use page_table_entry::MappingFlags;
 use riscv::register::scause::{self, Exception as E, Trap};
 use riscv::register::stval;
 
 use super::TrapFrame;
+use crate::trap::{PageFaultAccess, PageFaultInfo};
 
 include_asm_marcos!();
 
@@ -16,20 +16,16 @@ fn handle_breakpoint(sepc: &mut usize) {
     *sepc += 2
 }
 
-fn handle_page_fault(tf: &TrapFrame, mut access_flags: MappingFlags, is_user: bool) {
-    if is_user {
-        access_flags |= MappingFlags::USER;
-    }
-    let vaddr = va!(stval::read());
-    if !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user) {
-        panic!(
-            "Unhandled {} Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}",
-            if is_user { "User" } else { "Supervisor" },
-            tf.sepc,
-            vaddr,
-            access_flags,
-            tf,
-        );
+fn handle_page_fault(tf: &TrapFrame, access: PageFaultAccess, is_user: bool) {
+    let info = PageFaultInfo {
+        vaddr: va!(stval::read()),
+        access,
+        user: is_user,
+    };
+    if !crate::trap::handle_page_fault(&info) {
+        crate::backtrace::set_trap_origin(tf.sepc, tf.regs.s0);
+        crate::trap::check_stack_overflow(&info);
+        panic!("Unhandled {} @ {:#x}:\n{:#x?}", info, tf.sepc, tf);
     }
 }
 
@@ -37,16 +33,21 @@ fn handle_page_fault(tf: &TrapFrame, mut access_flags: MappingFlags, is_user: bo
 fn riscv_trap_handler(tf: &mut TrapFrame, from_user: bool) {
     let scause = scause::read();
     match scause.cause() {
-        Trap::Exception(E::LoadPageFault) => handle_page_fault(tf, MappingFlags::READ, from_user),
-        Trap::Exception(E::StorePageFault) => handle_page_fault(tf, MappingFlags::WRITE, from_user),
+        Trap::Exception(E::LoadPageFault) => {
+            handle_page_fault(tf, PageFaultAccess::Read, from_user)
+        }
+        Trap::Exception(E::StorePageFault) => {
+            handle_page_fault(tf, PageFaultAccess::Write, from_user)
+        }
         Trap::Exception(E::InstructionPageFault) => {
-            handle_page_fault(tf, MappingFlags::EXECUTE, from_user)
+            handle_page_fault(tf, PageFaultAccess::Execute, from_user)
         }
         Trap::Exception(E::Breakpoint) => handle_breakpoint(&mut tf.sepc),
         Trap::Interrupt(_) => {
             handle_trap!(IRQ, scause.bits());
         }
         _ => {
+            crate::backtrace::set_trap_origin(tf.sepc, tf.regs.s0);
             panic!(
                 "Unhandled trap {:?} @ {:#x}:\n{:#x?}",
                 scause.cause(),
@@ -0,0 +1,17 @@
+//! DMA is coherent with the caches on x86_64, so the maintenance is a no-op.
+
+pub(crate) fn is_dma_coherent() -> bool {
+    true
+}
+
+pub(crate) fn dcache_line_size() -> usize {
+    64
+}
+
+pub(crate) fn clean_line(_addr: usize) {}
+
+pub(crate) fn invalidate_line(_addr: usize) {}
+
+pub(crate) fn clean_invalidate_line(_addr: usize) {}
+
+pub(crate) fn sync() {}
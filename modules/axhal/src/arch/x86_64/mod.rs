@@ -1,6 +1,8 @@
+pub(crate) mod cache;
 mod context;
 mod gdt;
 mod idt;
+pub(crate) mod perf;
 
 #[cfg(target_os = "none")]
 mod trap;
@@ -13,12 +15,14 @@ use x86_64::instructions::interrupts;
 
 pub use self::context::{ExtendedState, FxsaveArea, TaskContext, TrapFrame};
 pub use self::gdt::GdtStruct;
-pub use self::idt::IdtStruct;
+pub use self::idt::{IdtStruct, DOUBLE_FAULT_IST_INDEX};
 pub use x86_64::structures::tss::TaskStateSegment;
 
 /// Allows the current CPU to respond to interrupts.
 #[inline]
 pub fn enable_irqs() {
+    #[cfg(feature = "latency-trace")]
+    crate::latency::trace_on(crate::latency::LatencyKind::IrqOff);
     #[cfg(not(target_os = "none"))]
     {
         warn!("enable_irqs: not implemented");
@@ -35,7 +39,9 @@ pub fn disable_irqs() {
         warn!("disable_irqs: not implemented");
     }
     #[cfg(target_os = "none")]
-    interrupts::disable()
+    interrupts::disable();
+    #[cfg(feature = "latency-trace")]
+    crate::latency::trace_off(crate::latency::LatencyKind::IrqOff);
 }
 
 /// Returns whether the current CPU is allowed to respond to interrupts.
@@ -0,0 +1,94 @@
+//! The TSC, and the architectural performance monitoring (CPUID leaf 0xA).
+
+use core::arch::x86_64::{__cpuid, _rdtsc};
+
+use x86::msr::{rdmsr, wrmsr};
+use x86_64::registers::control::{Cr4, Cr4Flags};
+
+use crate::perf::{PerfError, PerfEvent};
+
+const IA32_PMC0: u32 = 0xc1;
+const IA32_PERFEVTSEL0: u32 = 0x186;
+const IA32_PERF_GLOBAL_CTRL: u32 = 0x38f;
+
+const EVTSEL_USR: u64 = 1 << 16;
+const EVTSEL_OS: u64 = 1 << 17;
+const EVTSEL_EN: u64 = 1 << 22;
+
+/// Returns the event select and unit mask of the architectural event, and
+/// its bit in the unavailable events of CPUID leaf 0xA.
+fn event_code(event: PerfEvent) -> (u64, u32) {
+    match event {
+        PerfEvent::Instructions => (0x00c0, 1),
+        PerfEvent::CacheMisses => (0x412e, 4),
+    }
+}
+
+/// Returns the version of the architectural performance monitoring, or
+/// `None` if it is not supported or there is no general-purpose counter.
+fn pmu_version() -> Option<u32> {
+    if unsafe { __cpuid(0) }.eax < 0xa {
+        return None;
+    }
+    let eax = unsafe { __cpuid(0xa) }.eax;
+    let (version, num_counters) = (eax & 0xff, (eax >> 8) & 0xff);
+    (version != 0 && num_counters != 0).then_some(version)
+}
+
+fn is_event_available(unavailable_bit: u32) -> bool {
+    let info = unsafe { __cpuid(0xa) };
+    let num_events = (info.eax >> 24) & 0xff;
+    unavailable_bit < num_events && info.ebx & (1 << unavailable_bit) == 0
+}
+
+/// Allows user space to read the TSC.
+pub(crate) fn init_percpu() {
+    unsafe { Cr4::update(|cr4| cr4.remove(Cr4Flags::TIMESTAMP_DISABLE)) };
+}
+
+#[inline]
+pub fn read_cycles() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+pub fn cycles_to_nanos(cycles: u64) -> u64 {
+    crate::time::ticks_to_nanos(cycles)
+}
+
+pub fn start_event(event: PerfEvent) -> Result<(), PerfError> {
+    let (code, unavailable_bit) = event_code(event);
+    let version = pmu_version().ok_or(PerfError::Unsupported)?;
+    if !is_event_available(unavailable_bit) {
+        return Err(PerfError::Unsupported);
+    }
+    unsafe {
+        wrmsr(IA32_PERFEVTSEL0, 0);
+        wrmsr(IA32_PMC0, 0);
+        // The interrupt on overflow (bit 20) is left disabled.
+        wrmsr(IA32_PERFEVTSEL0, code | EVTSEL_USR | EVTSEL_OS | EVTSEL_EN);
+        if version >= 2 {
+            let ctrl = rdmsr(IA32_PERF_GLOBAL_CTRL);
+            wrmsr(IA32_PERF_GLOBAL_CTRL, ctrl | 1);
+        }
+        // Allow `rdpmc` in user space.
+        Cr4::update(|cr4| cr4.insert(Cr4Flags::PERFORMANCE_MONITOR_COUNTER));
+    }
+    Ok(())
+}
+
+fn is_started() -> bool {
+    pmu_version().is_some() && unsafe { rdmsr(IA32_PERFEVTSEL0) } & EVTSEL_EN != 0
+}
+
+pub fn read_event() -> Result<u64, PerfError> {
+    if !is_started() {
+        return Err(PerfError::NotStarted);
+    }
+    Ok(unsafe { rdmsr(IA32_PMC0) })
+}
+
+pub fn stop_event() {
+    if is_started() {
+        unsafe { wrmsr(IA32_PERFEVTSEL0, 0) };
+    }
+}
@@ -1,8 +1,8 @@
-use page_table_entry::MappingFlags;
 use x86::{controlregs::cr2, irq::*};
 use x86_64::structures::idt::PageFaultErrorCode;
 
 use super::context::TrapFrame;
+use crate::trap::{PageFaultAccess, PageFaultInfo};
 
 core::arch::global_asm!(include_str!("trap.S"));
 
@@ -10,28 +10,54 @@ const IRQ_VECTOR_START: u8 = 0x20;
 const IRQ_VECTOR_END: u8 = 0xff;
 
 fn handle_page_fault(tf: &TrapFrame) {
-    let access_flags = err_code_to_flags(tf.error_code)
-        .unwrap_or_else(|e| panic!("Invalid #PF error code: {:#x}", e));
-    let vaddr = va!(unsafe { cr2() });
-    if !handle_trap!(PAGE_FAULT, vaddr, access_flags, tf.is_user()) {
+    let code = PageFaultErrorCode::from_bits_truncate(tf.error_code);
+    let info = PageFaultInfo {
+        vaddr: va!(unsafe { cr2() }),
+        access: if code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+            PageFaultAccess::Execute
+        } else if code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+            PageFaultAccess::Write
+        } else {
+            PageFaultAccess::Read
+        },
+        user: code.contains(PageFaultErrorCode::USER_MODE),
+    };
+    // Reserved bits set in a page table entry are never resolvable.
+    let malformed = code.contains(PageFaultErrorCode::MALFORMED_TABLE);
+    if malformed || !crate::trap::handle_page_fault(&info) {
+        crate::backtrace::set_trap_origin(tf.rip as _, tf.rbp as _);
+        crate::trap::check_stack_overflow(&info);
         panic!(
-            "Unhandled {} #PF @ {:#x}, fault_vaddr={:#x}, error_code={:#x} ({:?}):\n{:#x?}",
-            if tf.is_user() { "user" } else { "kernel" },
-            tf.rip,
-            vaddr,
-            tf.error_code,
-            access_flags,
-            tf,
+            "Unhandled {} @ {:#x}, error_code={:#x} ({:?}):\n{:#x?}",
+            info, tf.rip, tf.error_code, code, tf,
         );
     }
 }
 
+/// Double faults are taken on their own stack (see [`DOUBLE_FAULT_IST_INDEX`]),
+/// since the usual one is a page fault that cannot be delivered because the
+/// kernel stack overflowed into its guard page.
+///
+/// [`DOUBLE_FAULT_IST_INDEX`]: super::DOUBLE_FAULT_IST_INDEX
+fn handle_double_fault(tf: &TrapFrame) -> ! {
+    let info = PageFaultInfo {
+        vaddr: va!(unsafe { cr2() }),
+        access: PageFaultAccess::Write,
+        user: false,
+    };
+    crate::backtrace::set_trap_origin(tf.rip as _, tf.rbp as _);
+    crate::trap::check_stack_overflow(&info);
+    panic!("#DF @ {:#x}, cr2={:#x}:\n{:#x?}", tf.rip, info.vaddr, tf);
+}
+
 #[no_mangle]
 fn x86_trap_handler(tf: &TrapFrame) {
     match tf.vector as u8 {
         PAGE_FAULT_VECTOR => handle_page_fault(tf),
         BREAKPOINT_VECTOR => debug!("#BP @ {:#x} ", tf.rip),
+        DOUBLE_FAULT_VECTOR => handle_double_fault(tf),
         GENERAL_PROTECTION_FAULT_VECTOR => {
+            crate::backtrace::set_trap_origin(tf.rip as _, tf.rbp as _);
             panic!(
                 "#GP @ {:#x}, error_code={:#x}:\n{:#x?}",
                 tf.rip, tf.error_code, tf
@@ -41,6 +67,7 @@ fn x86_trap_handler(tf: &TrapFrame) {
             handle_trap!(IRQ, tf.vector as _);
         }
         _ => {
+            crate::backtrace::set_trap_origin(tf.rip as _, tf.rbp as _);
             panic!(
                 "Unhandled exception {} ({}, error_code={:#x}) @ {:#x}:\n{:#x?}",
                 tf.vector,
@@ -60,28 +87,3 @@ fn vec_to_str(vec: u64) -> &'static str {
         "Unknown"
     }
 }
-
-fn err_code_to_flags(err_code: u64) -> Result<MappingFlags, u64> {
-    let code = PageFaultErrorCode::from_bits_truncate(err_code);
-    let reserved_bits = (PageFaultErrorCode::CAUSED_BY_WRITE
-        | PageFaultErrorCode::USER_MODE
-        | PageFaultErrorCode::INSTRUCTION_FETCH)
-        .complement();
-    if code.intersects(reserved_bits) {
-        Err(err_code)
-    } else {
-        let mut flags = MappingFlags::empty();
-        if code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
-            flags |= MappingFlags::WRITE;
-        } else {
-            flags |= MappingFlags::READ;
-        }
-        if code.contains(PageFaultErrorCode::USER_MODE) {
-            flags |= MappingFlags::USER;
-        }
-        if code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
-            flags |= MappingFlags::EXECUTE;
-        }
-        Ok(flags)
-    }
-}
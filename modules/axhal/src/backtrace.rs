@@ -0,0 +1,230 @@
+//! Stack backtraces by walking the frame pointers.
+//!
+//! The kernel must be built with frame pointers (`-C force-frame-pointers`),
+//! so that each function saves a frame record of the caller's frame pointer
+//! and the return address. The records are linked from the current frame
+//! pointer, and the walk stops when a record is out of the current stack,
+//! e.g., at the entry of a task or at the top of the boot stack.
+//!
+//! With the `ksyms` feature, the symbol table given by the `AX_KSYMS`
+//! environment variable at build time (the output of `nm -n`) is embedded in
+//! the kernel, and the addresses are printed with the function names.
+
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use lazyinit::LazyInit;
+
+/// Maximum number of frames to print.
+const MAX_DEPTH: usize = 64;
+
+static STACK_BOUNDS_FN: LazyInit<fn() -> Option<Range<usize>>> = LazyInit::new();
+
+/// The PC and the frame pointer at an unhandled exception, used as the origin
+/// of the next backtrace on this CPU.
+#[percpu::def_percpu]
+static TRAP_PC: AtomicUsize = AtomicUsize::new(0);
+#[percpu::def_percpu]
+static TRAP_FP: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the function that returns the stack range of the current task, which
+/// bounds the walk of frame records.
+///
+/// It can only be called once, usually by the task scheduler.
+pub fn set_stack_bounds_fn(f: fn() -> Option<Range<usize>>) {
+    STACK_BOUNDS_FN.init_once(f);
+}
+
+/// Records the state at an unhandled exception, so that the next backtrace
+/// starts at the faulting instruction instead of the exception handler.
+pub(crate) fn set_trap_origin(pc: usize, fp: usize) {
+    unsafe {
+        TRAP_PC.current_ref_raw().store(pc, Ordering::Relaxed);
+        TRAP_FP.current_ref_raw().store(fp, Ordering::Relaxed);
+    }
+}
+
+#[inline(always)]
+fn current_fp() -> usize {
+    let fp: usize;
+    unsafe {
+        #[cfg(target_arch = "x86_64")]
+        core::arch::asm!("mov {}, rbp", out(reg) fp);
+        #[cfg(target_arch = "aarch64")]
+        core::arch::asm!("mov {}, x29", out(reg) fp);
+        #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+        core::arch::asm!("mv {}, s0", out(reg) fp);
+        #[cfg(target_arch = "loongarch64")]
+        core::arch::asm!("move {}, $fp", out(reg) fp);
+    }
+    fp
+}
+
+/// Returns the range of the frame record at `fp`, whose first word is the
+/// caller's frame pointer and the second one is the return address.
+fn frame_record(fp: usize) -> Range<usize> {
+    const WORD: usize = core::mem::size_of::<usize>();
+    // On RISC-V and LoongArch, the frame pointer points to the top of the
+    // frame, and the record is just below it.
+    let start = if cfg!(any(
+        target_arch = "riscv32",
+        target_arch = "riscv64",
+        target_arch = "loongarch64"
+    )) {
+        fp.wrapping_sub(2 * WORD)
+    } else {
+        fp
+    };
+    start..start.wrapping_add(2 * WORD)
+}
+
+/// Returns the stack where `fp` is.
+fn stack_bounds(fp: usize) -> Range<usize> {
+    if let Some(bounds) = STACK_BOUNDS_FN.get().and_then(|f| f()) {
+        if bounds.contains(&fp) {
+            return bounds;
+        }
+    }
+    let boot_stack = crate::mem::boot_stack_range();
+    if boot_stack.contains(&fp) {
+        return boot_stack;
+    }
+    // Stacks of the init tasks on secondary CPUs, or other unknown stacks.
+    fp..fp.saturating_add(axconfig::TASK_STACK_SIZE)
+}
+
+fn is_text(addr: usize) -> bool {
+    crate::mem::kernel_text_range().contains(&addr)
+}
+
+/// Iterates the return addresses in the frame records from `fp`.
+fn frames(fp: usize) -> impl Iterator<Item = usize> {
+    const WORD: usize = core::mem::size_of::<usize>();
+    let bounds = stack_bounds(fp);
+    let mut fp = fp;
+    core::iter::from_fn(move || {
+        let record = frame_record(fp);
+        if fp % WORD != 0 || !bounds.contains(&record.start) || record.end > bounds.end {
+            return None;
+        }
+        let (next_fp, ra) = unsafe {
+            let ptr = record.start as *const usize;
+            (ptr.read(), ptr.add(1).read())
+        };
+        if ra == 0 || !is_text(ra) {
+            return None;
+        }
+        // Stacks grow downwards, so the callers' frames are at higher
+        // addresses. This also stops the walk on loops.
+        fp = if next_fp > fp { next_fp } else { 0 };
+        Some(ra)
+    })
+    .take(MAX_DEPTH)
+}
+
+/// Iterates the return addresses from the frame of the caller.
+#[cfg(feature = "latency-trace")]
+#[inline(always)]
+pub(crate) fn return_addresses() -> impl Iterator<Item = usize> {
+    frames(current_fp())
+}
+
+/// Returns the name of the function containing `addr`, and the offset of
+/// `addr` in it, if the symbol table is embedded by the `ksyms` feature.
+pub fn lookup_symbol(addr: usize) -> Option<(&'static str, usize)> {
+    ksyms::lookup(addr)
+}
+
+fn print_frame(idx: usize, addr: usize) {
+    match ksyms::lookup(addr) {
+        Some((name, offset)) => {
+            axlog::ax_println!("  #{}: {:#x} {}+{:#x}", idx, addr, name, offset)
+        }
+        None => axlog::ax_println!("  #{}: {:#x}", idx, addr),
+    }
+}
+
+/// Prints the backtrace of the caller.
+///
+/// If it is called on an unhandled exception, e.g., by the panic handler, the
+/// backtrace starts at the faulting instruction.
+#[inline(never)]
+pub fn print() {
+    let (trap_pc, trap_fp) = unsafe {
+        (
+            TRAP_PC.current_ref_raw().swap(0, Ordering::Relaxed),
+            TRAP_FP.current_ref_raw().swap(0, Ordering::Relaxed),
+        )
+    };
+    axlog::ax_println!("Backtrace:");
+    if trap_pc != 0 {
+        print_frame(0, trap_pc);
+        // Return addresses point to the instructions after the calls, look
+        // up the calls instead.
+        for (idx, ra) in frames(trap_fp).enumerate() {
+            print_frame(idx + 1, ra - 1);
+        }
+    } else {
+        for (idx, ra) in frames(current_fp()).enumerate() {
+            print_frame(idx, ra - 1);
+        }
+    }
+}
+
+#[cfg(feature = "ksyms")]
+mod ksyms {
+    macro_rules! ksyms_bytes {
+        () => {
+            include_bytes!(concat!(env!("OUT_DIR"), "/ksyms.txt"))
+        };
+    }
+
+    /// The symbol table, placed at the end of `.rodata`, so that its size
+    /// does not move the code when it is embedded.
+    ///
+    /// It is empty in the first build, so it is only accessed through the
+    /// bounds given by the linker, lest the compiler fold the lookup of an
+    /// empty table and generate different code in the two builds.
+    #[used]
+    #[link_section = ".ksyms"]
+    static KSYMS: [u8; ksyms_bytes!().len()] = *ksyms_bytes!();
+
+    extern "C" {
+        fn _sksyms();
+        fn _eksyms();
+    }
+
+    fn table() -> &'static [u8] {
+        let start = core::hint::black_box(_sksyms as usize);
+        let end = core::hint::black_box(_eksyms as usize);
+        unsafe { core::slice::from_raw_parts(start as *const u8, end - start) }
+    }
+
+    /// Parses a line `<address> <type> <name>` of the symbol table.
+    fn parse_line(line: &str) -> Option<(usize, &str)> {
+        let mut fields = line.splitn(3, ' ');
+        let addr = usize::from_str_radix(fields.next()?, 16).ok()?;
+        let _ty = fields.next()?;
+        Some((addr, fields.next()?))
+    }
+
+    /// Returns the name of the function containing `addr`, and the offset of
+    /// `addr` in it.
+    pub fn lookup(addr: usize) -> Option<(&'static str, usize)> {
+        let table = core::str::from_utf8(table()).ok()?;
+        // The symbols are sorted by address.
+        table
+            .lines()
+            .filter_map(parse_line)
+            .take_while(|&(sym_addr, _)| sym_addr <= addr)
+            .last()
+            .map(|(sym_addr, name)| (name, addr - sym_addr))
+    }
+}
+
+#[cfg(not(feature = "ksyms"))]
+mod ksyms {
+    pub fn lookup(_addr: usize) -> Option<(&'static str, usize)> {
+        None
+    }
+}
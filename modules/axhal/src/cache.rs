@@ -0,0 +1,73 @@
+//! Data cache maintenance, for DMA of devices that do not snoop the caches.
+//!
+//! The operations are `DC CVAC`, `DC IVAC` and `DC CIVAC` on AArch64, and the
+//! Zicbom extension or the T-Head vendor instructions on RISC-V. DMA is
+//! coherent on x86_64, LoongArch64 and RISC-V CPUs without them (e.g., QEMU),
+//! where the operations are no-ops.
+//!
+//! The ranges do not need to be aligned to the cache lines. All operations
+//! wait for their completion before returning.
+
+use memory_addr::{align_down, align_up, VirtAddr};
+
+use crate::arch::cache as arch;
+
+/// Returns the size of the data cache lines.
+pub fn dcache_line_size() -> usize {
+    arch::dcache_line_size()
+}
+
+/// Calls `op` on each cache line overlapping `size` bytes from `vaddr`.
+fn for_each_line(vaddr: VirtAddr, size: usize, op: fn(usize)) {
+    let line = arch::dcache_line_size();
+    let start = align_down(vaddr.as_usize(), line);
+    let end = align_up(vaddr.as_usize() + size, line);
+    (start..end).step_by(line).for_each(op);
+}
+
+/// Writes back the cached data of the range to memory, e.g., before a device
+/// reads it.
+pub fn flush_dcache_range(vaddr: VirtAddr, size: usize) {
+    if arch::is_dma_coherent() || size == 0 {
+        return;
+    }
+    for_each_line(vaddr, size, arch::clean_line);
+    arch::sync();
+}
+
+/// Discards the cached data of the range, e.g., after a device writes it, so
+/// that the CPU reads the new data from memory.
+///
+/// The lines partially in the range are written back before being discarded,
+/// so the data around the range sharing them is kept. But the data written by
+/// the device into these lines is overwritten if the CPU has dirtied them
+/// meanwhile, so DMA buffers should be aligned to the cache lines.
+pub fn invalidate_dcache_range(vaddr: VirtAddr, size: usize) {
+    if arch::is_dma_coherent() || size == 0 {
+        return;
+    }
+    let line = arch::dcache_line_size();
+    let (start, end) = (vaddr.as_usize(), vaddr.as_usize() + size);
+    let (full_start, full_end) = (align_up(start, line), align_down(end, line));
+    if start < full_start.min(end) {
+        arch::clean_invalidate_line(align_down(start, line));
+    }
+    // If the range is in a single line, it has been done above.
+    if full_end < end && full_end >= full_start {
+        arch::clean_invalidate_line(full_end);
+    }
+    (full_start..full_end)
+        .step_by(line)
+        .for_each(arch::invalidate_line);
+    arch::sync();
+}
+
+/// Writes back and discards the cached data of the range, e.g., before a
+/// device both reads and writes it.
+pub fn flush_invalidate_range(vaddr: VirtAddr, size: usize) {
+    if arch::is_dma_coherent() || size == 0 {
+        return;
+    }
+    for_each_line(vaddr, size, arch::clean_invalidate_line);
+    arch::sync();
+}
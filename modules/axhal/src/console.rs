@@ -0,0 +1,268 @@
+//! Console input and output.
+//!
+//! By default, the platform console (usually an UART) is used. It can be
+//! replaced by another device via [`set_console_device`], e.g. a VirtIO
+//! console found during device probing.
+//!
+//! With the `irq` feature, the platform console receives input on interrupts
+//! into a ring buffer, so that no input is lost while the CPUs are busy, and
+//! readers can block in [`wait_readable`] instead of polling.
+
+use lazyinit::LazyInit;
+use memory_addr::PhysAddr;
+
+// Only the platform-specific functions, the others are wrapped below.
+#[allow(unused_imports)]
+pub use super::platform::console::*;
+
+/// A device that can take over the console from the platform.
+pub trait ConsoleDevice: Send + Sync {
+    /// Writes bytes to the device.
+    fn write_bytes(&self, bytes: &[u8]);
+
+    /// Reads bytes from the device into the given mutable slice.
+    ///
+    /// Returns the number of bytes read, which may be zero.
+    fn read_bytes(&self, bytes: &mut [u8]) -> usize;
+}
+
+/// Blocks and wakes up the readers of the console, e.g., with a wait queue of
+/// the scheduler.
+pub trait ConsoleWaiter: Send + Sync {
+    /// Blocks the caller until `condition` returns `true`.
+    fn wait_until(&self, condition: &dyn Fn() -> bool);
+
+    /// Wakes up the blocked callers, called in the interrupt handler.
+    fn notify(&self);
+}
+
+static CONSOLE_DEVICE: LazyInit<&'static dyn ConsoleDevice> = LazyInit::new();
+static CONSOLE_WAITER: LazyInit<&'static dyn ConsoleWaiter> = LazyInit::new();
+
+/// Replaces the platform console with the given device.
+///
+/// It can only be called once, all later console I/O goes to the device.
+pub fn set_console_device(dev: &'static dyn ConsoleDevice) {
+    CONSOLE_DEVICE.init_once(dev);
+}
+
+/// Sets how [`wait_readable`] blocks the callers.
+///
+/// It can only be called once.
+pub fn set_console_waiter(waiter: &'static dyn ConsoleWaiter) {
+    CONSOLE_WAITER.init_once(waiter);
+}
+
+/// Writes bytes to the console from input u8 slice.
+pub fn write_bytes(bytes: &[u8]) {
+    match CONSOLE_DEVICE.get() {
+        Some(dev) => dev.write_bytes(bytes),
+        None => super::platform::console::write_bytes(bytes),
+    }
+}
+
+/// Reads a byte from the console, or returns [`None`] if no input is
+/// available.
+pub fn getchar() -> Option<u8> {
+    let mut c = 0;
+    (read_bytes(core::slice::from_mut(&mut c)) == 1).then_some(c)
+}
+
+/// Reads bytes from the console into the given mutable slice.
+///
+/// Returns the number of bytes read.
+pub fn read_bytes(bytes: &mut [u8]) -> usize {
+    if let Some(dev) = CONSOLE_DEVICE.get() {
+        return dev.read_bytes(bytes);
+    }
+    #[cfg(feature = "irq")]
+    if rx::enabled() {
+        return rx::read_bytes(bytes);
+    }
+    super::platform::console::read_bytes(bytes)
+}
+
+/// Blocks until there is input in the receive buffer of the platform console.
+///
+/// Returns `false` immediately if the input can not be waited for, i.e., the
+/// receive interrupt is not enabled, no [`ConsoleWaiter`] is set, or another
+/// console device is used, then the caller should poll instead.
+pub fn wait_readable() -> bool {
+    #[cfg(feature = "irq")]
+    if CONSOLE_DEVICE.get().is_none() && rx::enabled() {
+        if let Some(waiter) = CONSOLE_WAITER.get() {
+            waiter.wait_until(&rx::readable);
+            return true;
+        }
+    }
+    false
+}
+
+/// The layout of the pixels in a framebuffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 32-bit pixels, with the red, green and blue bytes in order from the
+    /// lowest address, then a reserved byte.
+    Rgb,
+    /// 32-bit pixels, with the blue, green and red bytes in order from the
+    /// lowest address, then a reserved byte.
+    Bgr,
+    /// 32-bit pixels, with the bits of each color given by the masks.
+    Bitmask {
+        /// The bits of red.
+        red: u32,
+        /// The bits of green.
+        green: u32,
+        /// The bits of blue.
+        blue: u32,
+    },
+}
+
+/// A linear framebuffer set up by the firmware at boot, e.g., by the UEFI
+/// Graphics Output Protocol, which a display console can draw to.
+#[derive(Debug, Clone, Copy)]
+pub struct FramebufferInfo {
+    /// The physical address of the framebuffer.
+    pub paddr: PhysAddr,
+    /// The size in bytes of the framebuffer.
+    pub size: usize,
+    /// The visible width in pixels.
+    pub width: u32,
+    /// The visible height in pixels.
+    pub height: u32,
+    /// The number of pixels of each line in memory, at least `width`.
+    pub stride: u32,
+    /// The layout of the pixels.
+    pub format: PixelFormat,
+}
+
+static BOOT_FRAMEBUFFER: LazyInit<FramebufferInfo> = LazyInit::new();
+
+/// Returns the framebuffer set up by the firmware at boot, if any.
+pub fn boot_framebuffer() -> Option<FramebufferInfo> {
+    BOOT_FRAMEBUFFER.get().copied()
+}
+
+/// Records the framebuffer set up by the firmware, on early boot.
+#[allow(dead_code)]
+pub(crate) fn set_boot_framebuffer(fb: FramebufferInfo) {
+    BOOT_FRAMEBUFFER.init_once(fb);
+}
+
+#[cfg(feature = "irq")]
+pub(crate) use self::rx::{enable_rx_irq, receive};
+
+/// The receive buffer of the platform console.
+#[cfg(feature = "irq")]
+mod rx {
+    use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+
+    /// Capacity of the receive buffer.
+    const RX_BUFFER_SIZE: usize = 256;
+
+    /// A lock-free ring buffer of bytes, written only by the interrupt
+    /// handler of the console. When it is full, the oldest byte is dropped.
+    struct RxBuffer {
+        data: [AtomicU8; RX_BUFFER_SIZE],
+        /// Number of bytes read or dropped since boot.
+        head: AtomicUsize,
+        /// Number of bytes written since boot.
+        tail: AtomicUsize,
+        /// Number of bytes dropped and not reported yet.
+        dropped: AtomicUsize,
+    }
+
+    impl RxBuffer {
+        const fn new() -> Self {
+            #[allow(clippy::declare_interior_mutable_const)]
+            const ZERO: AtomicU8 = AtomicU8::new(0);
+            Self {
+                data: [ZERO; RX_BUFFER_SIZE],
+                head: AtomicUsize::new(0),
+                tail: AtomicUsize::new(0),
+                dropped: AtomicUsize::new(0),
+            }
+        }
+
+        fn is_empty(&self) -> bool {
+            self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+        }
+
+        fn push(&self, c: u8) {
+            let tail = self.tail.load(Ordering::Relaxed);
+            let head = self.head.load(Ordering::Acquire);
+            // The slot is only overwritten after the oldest byte is removed,
+            // a reader taking it at the same time fails to advance `head`.
+            if tail - head == RX_BUFFER_SIZE
+                && self
+                    .head
+                    .compare_exchange(head, head + 1, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+            {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            self.data[tail % RX_BUFFER_SIZE].store(c, Ordering::Relaxed);
+            self.tail.store(tail + 1, Ordering::Release);
+        }
+
+        fn pop(&self) -> Option<u8> {
+            loop {
+                let head = self.head.load(Ordering::Acquire);
+                if head == self.tail.load(Ordering::Acquire) {
+                    return None;
+                }
+                let c = self.data[head % RX_BUFFER_SIZE].load(Ordering::Relaxed);
+                if self
+                    .head
+                    .compare_exchange(head, head + 1, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    return Some(c);
+                }
+            }
+        }
+    }
+
+    static RX_BUFFER: RxBuffer = RxBuffer::new();
+    static RX_IRQ_ENABLED: AtomicBool = AtomicBool::new(false);
+
+    /// Makes the reads of the platform console take the bytes from the
+    /// receive buffer. It must be called after the receive interrupt of the
+    /// platform console is enabled.
+    pub fn enable_rx_irq() {
+        RX_IRQ_ENABLED.store(true, Ordering::Release);
+    }
+
+    /// Puts bytes into the receive buffer and wakes up the waiting readers,
+    /// called by the receive interrupt handler of the platform console.
+    pub fn receive(bytes: impl Iterator<Item = u8>) {
+        bytes.for_each(|c| RX_BUFFER.push(c));
+        if let Some(waiter) = super::CONSOLE_WAITER.get() {
+            waiter.notify();
+        }
+    }
+
+    pub fn enabled() -> bool {
+        RX_IRQ_ENABLED.load(Ordering::Acquire)
+    }
+
+    pub fn readable() -> bool {
+        !RX_BUFFER.is_empty()
+    }
+
+    pub fn read_bytes(bytes: &mut [u8]) -> usize {
+        let dropped = RX_BUFFER.dropped.swap(0, Ordering::Relaxed);
+        if dropped != 0 {
+            warn!("Console input overflowed, {} bytes dropped", dropped);
+        }
+        let mut read_len = 0;
+        while read_len < bytes.len() {
+            match RX_BUFFER.pop() {
+                Some(c) => bytes[read_len] = c,
+                None => break,
+            }
+            read_len += 1;
+        }
+        read_len
+    }
+}
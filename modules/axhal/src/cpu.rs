@@ -33,9 +33,13 @@ pub fn current_task_ptr<T>() -> *const T {
         // on x86, only one instruction is needed to read the per-CPU task pointer from `gs:[off]`.
         CURRENT_TASK_PTR.read_current_raw() as _
     }
-    #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+    #[cfg(any(
+        target_arch = "riscv32",
+        target_arch = "riscv64",
+        target_arch = "loongarch64"
+    ))]
     unsafe {
-        // on RISC-V, reading `CURRENT_TASK_PTR` requires multiple instruction, so we disable local IRQs.
+        // on RISC-V and LoongArch, reading `CURRENT_TASK_PTR` requires multiple instruction, so we disable local IRQs.
         let _guard = kernel_guard::IrqSave::new();
         CURRENT_TASK_PTR.read_current_raw() as _
     }
@@ -61,7 +65,11 @@ pub unsafe fn set_current_task_ptr<T>(ptr: *const T) {
     {
         CURRENT_TASK_PTR.write_current_raw(ptr as usize)
     }
-    #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+    #[cfg(any(
+        target_arch = "riscv32",
+        target_arch = "riscv64",
+        target_arch = "loongarch64"
+    ))]
     {
         let _guard = kernel_guard::IrqSave::new();
         CURRENT_TASK_PTR.write_current_raw(ptr as usize)
@@ -73,6 +81,33 @@ pub unsafe fn set_current_task_ptr<T>(ptr: *const T) {
     }
 }
 
+/// Puts the current CPU into a low-power state until an interrupt arrives,
+/// and returns after handling it.
+///
+/// It must be called with IRQs disabled, and returns with IRQs enabled. A
+/// wakeup condition checked with IRQs disabled right before is therefore not
+/// missed: the interrupt that changes it wakes the CPU up even if it arrives
+/// before the CPU sleeps, e.g., the IPI sent after a task is queued.
+pub fn wait_for_irqs() {
+    #[cfg(target_os = "none")]
+    debug_assert!(!crate::arch::irqs_enabled());
+    #[cfg(target_arch = "x86_64")]
+    if cfg!(target_os = "none") {
+        // `sti` takes effect after the next instruction, so no interrupt is
+        // taken before `hlt`.
+        unsafe { core::arch::asm!("sti; hlt") };
+    } else {
+        crate::arch::wait_for_irqs();
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        // WFI also wakes up on interrupts masked by the CPU, which are taken
+        // once enabled.
+        crate::arch::wait_for_irqs();
+        crate::arch::enable_irqs();
+    }
+}
+
 #[allow(dead_code)]
 pub(crate) fn init_primary(cpu_id: usize) {
     percpu::init(axconfig::SMP);
@@ -81,6 +116,10 @@ pub(crate) fn init_primary(cpu_id: usize) {
         CPU_ID.write_current_raw(cpu_id);
         IS_BSP.write_current_raw(true);
     }
+    crate::mem::register_primary_stack_guard(cpu_id);
+    crate::arch::perf::init_percpu();
+    #[cfg(feature = "smp")]
+    hotplug::set_online(cpu_id, true);
 }
 
 #[allow(dead_code)]
@@ -90,4 +129,170 @@ pub(crate) fn init_secondary(cpu_id: usize) {
         CPU_ID.write_current_raw(cpu_id);
         IS_BSP.write_current_raw(false);
     }
+    crate::arch::perf::init_percpu();
+    #[cfg(feature = "smp")]
+    hotplug::set_online(cpu_id, false);
+}
+
+#[cfg(feature = "smp")]
+pub(crate) use self::hotplug::set_boot_stack;
+#[cfg(feature = "smp")]
+pub use self::hotplug::{
+    cpu_down, cpu_online, cpu_up, offline_this_cpu, online_cpu_count, HotplugError,
+};
+
+/// Taking secondary CPUs offline and bringing them back at runtime.
+///
+/// A CPU is online when tasks may be scheduled on it. [`cpu_down`] only
+/// clears the online state, then the CPU itself notices it, moves its work
+/// to other CPUs (done by `axtask` in the idle task), and calls
+/// [`offline_this_cpu`] to power itself off.
+#[cfg(feature = "smp")]
+mod hotplug {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::mem::PhysAddr;
+    use crate::time::{monotonic_time, Duration};
+
+    const _: () = assert!(axconfig::SMP <= usize::BITS as usize);
+
+    /// How long [`cpu_up`] waits for the CPU to come online.
+    const CPU_UP_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// CPUs where tasks may be scheduled.
+    static ONLINE_CPUS: AtomicUsize = AtomicUsize::new(0);
+    /// CPUs that are powered on, including the ones going offline.
+    static RUNNING_CPUS: AtomicUsize = AtomicUsize::new(0);
+    static PRIMARY_CPU_ID: AtomicUsize = AtomicUsize::new(0);
+
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NO_STACK: AtomicUsize = AtomicUsize::new(0);
+    /// Boot stacks of secondary CPUs, reused when they are started again.
+    static BOOT_STACKS: [AtomicUsize; axconfig::SMP] = [NO_STACK; axconfig::SMP];
+
+    /// Errors of CPU hotplug operations.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum HotplugError {
+        /// The CPU ID is out of range, or the CPU was never started.
+        InvalidCpu,
+        /// The primary CPU cannot be taken offline.
+        PrimaryCpu,
+        /// The last online CPU cannot be taken offline.
+        LastOnlineCpu,
+        /// A CPU cannot take itself offline, as it would wait for itself.
+        CurrentCpu,
+        /// The CPU is already offline.
+        AlreadyOffline,
+        /// The CPU is already online, or still going offline.
+        AlreadyOnline,
+        /// The CPU did not come online in time.
+        Timeout,
+    }
+
+    pub(super) fn set_online(cpu_id: usize, is_primary: bool) {
+        if is_primary {
+            PRIMARY_CPU_ID.store(cpu_id, Ordering::Relaxed);
+        }
+        RUNNING_CPUS.fetch_or(1 << cpu_id, Ordering::AcqRel);
+        ONLINE_CPUS.fetch_or(1 << cpu_id, Ordering::AcqRel);
+    }
+
+    pub(crate) fn set_boot_stack(cpu_id: usize, stack_top: PhysAddr) {
+        BOOT_STACKS[cpu_id].store(stack_top.as_usize(), Ordering::Release);
+    }
+
+    /// Returns whether tasks may be scheduled on the given CPU.
+    #[inline]
+    pub fn cpu_online(cpu_id: usize) -> bool {
+        ONLINE_CPUS.load(Ordering::Acquire) & (1 << cpu_id) != 0
+    }
+
+    /// Returns the number of online CPUs.
+    pub fn online_cpu_count() -> usize {
+        ONLINE_CPUS.load(Ordering::Acquire).count_ones() as usize
+    }
+
+    /// Takes the given secondary CPU offline, and waits until it is powered
+    /// off.
+    ///
+    /// The primary CPU, the last online CPU, and the calling CPU cannot be
+    /// taken offline.
+    pub fn cpu_down(cpu_id: usize) -> Result<(), HotplugError> {
+        if cpu_id >= axconfig::SMP {
+            return Err(HotplugError::InvalidCpu);
+        }
+        if cpu_id == PRIMARY_CPU_ID.load(Ordering::Relaxed) {
+            return Err(HotplugError::PrimaryCpu);
+        }
+        if cpu_id == super::this_cpu_id() {
+            return Err(HotplugError::CurrentCpu);
+        }
+        let bit = 1 << cpu_id;
+        let mut online = ONLINE_CPUS.load(Ordering::Acquire);
+        loop {
+            if online & bit == 0 {
+                return Err(HotplugError::AlreadyOffline);
+            }
+            if online == bit {
+                return Err(HotplugError::LastOnlineCpu);
+            }
+            match ONLINE_CPUS.compare_exchange_weak(
+                online,
+                online & !bit,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(val) => online = val,
+            }
+        }
+        info!("Taking CPU {} offline...", cpu_id);
+        // Wake it up in case it is idle.
+        #[cfg(feature = "irq")]
+        crate::irq::send_ipi(cpu_id);
+        while RUNNING_CPUS.load(Ordering::Acquire) & bit != 0 {
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    /// Starts the given secondary CPU again after [`cpu_down`], and waits
+    /// until it is online.
+    ///
+    /// The CPU goes through the secondary CPU initialization again, including
+    /// the per-CPU interrupt controller and timer.
+    pub fn cpu_up(cpu_id: usize) -> Result<(), HotplugError> {
+        if cpu_id >= axconfig::SMP {
+            return Err(HotplugError::InvalidCpu);
+        }
+        let stack_top = BOOT_STACKS[cpu_id].load(Ordering::Acquire);
+        if stack_top == 0 {
+            return Err(HotplugError::InvalidCpu);
+        }
+        if RUNNING_CPUS.load(Ordering::Acquire) & (1 << cpu_id) != 0 {
+            return Err(HotplugError::AlreadyOnline);
+        }
+        info!("Bringing CPU {} online...", cpu_id);
+        crate::platform::mp::start_secondary_cpu(cpu_id, pa!(stack_top));
+        let deadline = monotonic_time() + CPU_UP_TIMEOUT;
+        while !cpu_online(cpu_id) {
+            if monotonic_time() > deadline {
+                return Err(HotplugError::Timeout);
+            }
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    /// Powers off the current CPU after it is taken offline by [`cpu_down`],
+    /// and its tasks are moved to other CPUs.
+    ///
+    /// It restarts from the secondary CPU entry if [`cpu_up`] is called.
+    pub fn offline_this_cpu() -> ! {
+        crate::arch::disable_irqs();
+        let cpu_id = super::this_cpu_id();
+        info!("CPU {} is offline.", cpu_id);
+        RUNNING_CPUS.fetch_and(!(1 << cpu_id), Ordering::AcqRel);
+        crate::platform::mp::cpu_off()
+    }
 }
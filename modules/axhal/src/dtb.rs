@@ -0,0 +1,56 @@
+//! The flattened device tree blob (DTB) passed by the bootloader.
+//!
+//! Only the location of the blob is recorded here, see [`fdt`](crate::fdt)
+//! for the queries on its contents. The blob is kept out of the free memory
+//! and mapped read-only as a reserved region, see
+//! [`memory_regions`](crate::mem::memory_regions).
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::mem::{phys_to_virt, PhysAddr};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+
+static DTB_PADDR: AtomicUsize = AtomicUsize::new(0);
+static DTB_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Records the DTB at `paddr` if it has a valid header.
+///
+/// It must be called on early boot after the `.bss` section is cleared, while
+/// the physical memory is still mapped by the boot page table.
+#[allow(dead_code)]
+pub(crate) fn init_early(paddr: usize) {
+    let (map_start, map_end) = crate::platform::mem::BOOT_MAPPED_MEMORY;
+    if !(map_start..map_end).contains(&paddr) {
+        return;
+    }
+    let header = phys_to_virt(paddr.into()).as_ptr() as *const u32;
+    // The header fields are big-endian: `magic` and `totalsize` come first.
+    let (magic, size) = unsafe {
+        (
+            u32::from_be(header.read_volatile()),
+            u32::from_be(header.add(1).read_volatile()),
+        )
+    };
+    if magic != FDT_MAGIC || paddr + size as usize > map_end {
+        return;
+    }
+    DTB_SIZE.store(size as usize, Ordering::Relaxed);
+    DTB_PADDR.store(paddr, Ordering::Release);
+}
+
+/// Returns the physical address and the size of the DTB, or `None` if the
+/// bootloader did not pass a valid one.
+pub fn dtb_region() -> Option<(PhysAddr, usize)> {
+    match DTB_PADDR.load(Ordering::Acquire) {
+        0 => None,
+        paddr => Some((paddr.into(), DTB_SIZE.load(Ordering::Relaxed))),
+    }
+}
+
+/// Returns the DTB, or `None` if the bootloader did not pass a valid one.
+pub fn dtb() -> Option<&'static [u8]> {
+    let (paddr, size) = dtb_region()?;
+    // Safe because the blob is reserved and never written.
+    Some(unsafe { core::slice::from_raw_parts(phys_to_virt(paddr).as_ptr(), size) })
+}
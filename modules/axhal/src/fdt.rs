@@ -0,0 +1,504 @@
+//! Typed queries on the flattened device tree passed by the bootloader.
+//!
+//! The blob is parsed in place, without building a tree or allocating, so the
+//! queries can be used before the memory allocator is initialized. The whole
+//! structure block is checked once when the blob is opened, and absent or
+//! malformed trees are treated as empty, where [`memory_regions`] falls back
+//! to the physical memory in the platform config.
+
+use core::str;
+
+use crate::mem::PhysAddr;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+/// The version with `size_dt_struct` in the header, which is also the last
+/// compatible version of all blobs in use.
+const FDT_VERSION: u32 = 17;
+const FDT_HEADER_SIZE: usize = 40;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// Nesting limit of the nodes, deeper trees are taken as malformed.
+const MAX_DEPTH: usize = 16;
+
+fn be32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// Reads a number of `cells` big-endian 32-bit cells.
+fn read_cells(data: &[u8], cells: usize) -> Option<u64> {
+    (0..cells).try_fold(0u64, |val, i| Some(val << 32 | be32(data, i * 4)? as u64))
+}
+
+/// Reads a NUL-terminated string at `offset`.
+fn read_str(data: &[u8], offset: usize) -> Option<&str> {
+    let bytes = data.get(offset..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    str::from_utf8(&bytes[..len]).ok()
+}
+
+const fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+enum Token<'a> {
+    BeginNode(&'a str),
+    EndNode,
+    Prop(&'a str, &'a [u8]),
+    End,
+}
+
+/// A device tree blob whose header and structure block have been checked.
+#[derive(Clone, Copy)]
+pub struct Fdt<'a> {
+    /// The memory reservation block, up to the end of the blob.
+    rsvmap: &'a [u8],
+    structs: &'a [u8],
+    strings: &'a [u8],
+}
+
+/// A node of the device tree.
+#[derive(Clone, Copy)]
+pub struct Node<'a> {
+    fdt: Fdt<'a>,
+    name: &'a str,
+    /// Offset of the first property in the structure block.
+    props_offset: usize,
+    /// Depth of the node, 0 for the root.
+    depth: usize,
+    /// `#address-cells` and `#size-cells` of the parent, in which `reg` is.
+    parent_cells: (usize, usize),
+    /// Phandle of the interrupt parent, inherited from the ancestors.
+    interrupt_parent: Option<u32>,
+}
+
+/// What the children of a node inherit from it.
+#[derive(Clone, Copy)]
+struct Level {
+    address_cells: usize,
+    size_cells: usize,
+    interrupt_parent: Option<u32>,
+}
+
+impl Level {
+    /// Defaults of the parent of the root node.
+    const ROOT_PARENT: Self = Self {
+        address_cells: 2,
+        size_cells: 1,
+        interrupt_parent: None,
+    };
+}
+
+/// Iterator over the nodes in depth-first order, see [`Fdt::nodes`].
+pub struct Nodes<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+    stack: [Level; MAX_DEPTH],
+    depth: usize,
+}
+
+impl<'a> Fdt<'a> {
+    /// Opens the blob, checking that the header is valid, that the blocks
+    /// fit in `totalsize`, which must fit in `data`, and that the structure
+    /// block is well formed.
+    pub fn from_bytes(data: &'a [u8]) -> Option<Self> {
+        let header = |index: usize| be32(data, index * 4).map(|v| v as usize);
+        if header(0)? != FDT_MAGIC as usize || data.len() < FDT_HEADER_SIZE {
+            return None;
+        }
+        let total_size = header(1)?;
+        let (struct_offset, strings_offset) = (header(2)?, header(3)?);
+        let rsvmap_offset = header(4)?;
+        let (version, last_comp_version) = (header(5)?, header(6)?);
+        let (strings_size, struct_size) = (header(8)?, header(9)?);
+        if version < FDT_VERSION as usize || last_comp_version > FDT_VERSION as usize {
+            return None;
+        }
+        let data = data.get(..total_size)?;
+        let fdt = Self {
+            rsvmap: data.get(rsvmap_offset..)?,
+            structs: data.get(struct_offset..struct_offset.checked_add(struct_size)?)?,
+            strings: data.get(strings_offset..strings_offset.checked_add(strings_size)?)?,
+        };
+        fdt.check_structure().then_some(fdt)
+    }
+
+    /// Reads the token at `offset`, skipping NOPs, and returns it with the
+    /// offset of the next one.
+    fn token(&self, mut offset: usize) -> Option<(Token<'a>, usize)> {
+        loop {
+            let token = be32(self.structs, offset)?;
+            offset += 4;
+            return match token {
+                FDT_BEGIN_NODE => {
+                    let name = read_str(self.structs, offset)?;
+                    Some((Token::BeginNode(name), align4(offset + name.len() + 1)))
+                }
+                FDT_END_NODE => Some((Token::EndNode, offset)),
+                FDT_PROP => {
+                    let len = be32(self.structs, offset)? as usize;
+                    let name = read_str(self.strings, be32(self.structs, offset + 4)? as usize)?;
+                    let start = offset + 8;
+                    let value = self.structs.get(start..start.checked_add(len)?)?;
+                    Some((Token::Prop(name, value), align4(start + len)))
+                }
+                FDT_NOP => continue,
+                FDT_END => Some((Token::End, offset)),
+                _ => None,
+            };
+        }
+    }
+
+    /// Whether the structure block is a single root node with properly
+    /// nested nodes, followed by the end token.
+    fn check_structure(&self) -> bool {
+        let mut offset = 0;
+        let mut depth = 0;
+        let mut root_closed = false;
+        while let Some((token, next)) = self.token(offset) {
+            offset = next;
+            match token {
+                Token::BeginNode(_) if root_closed || depth == MAX_DEPTH => return false,
+                Token::BeginNode(_) => depth += 1,
+                Token::EndNode if depth == 0 => return false,
+                Token::EndNode => {
+                    depth -= 1;
+                    root_closed = depth == 0;
+                }
+                Token::Prop(..) if depth == 0 => return false,
+                Token::Prop(..) => {}
+                Token::End => return root_closed,
+            }
+        }
+        false
+    }
+
+    /// Returns all nodes in depth-first order, starting with the root.
+    pub fn nodes(&self) -> Nodes<'a> {
+        Nodes {
+            fdt: *self,
+            offset: 0,
+            stack: [Level::ROOT_PARENT; MAX_DEPTH],
+            depth: 0,
+        }
+    }
+
+    /// Returns the node at the absolute `path`, e.g., `/chosen`. The unit
+    /// address can be omitted from the path components if it is unambiguous.
+    pub fn find_node(&self, path: &str) -> Option<Node<'a>> {
+        let count = path.split('/').filter(|c| !c.is_empty()).count();
+        let mut components = path.split('/').filter(|c| !c.is_empty());
+        let mut matched = 0;
+        let mut component = components.next();
+        for node in self.nodes() {
+            if node.depth == 0 {
+                if count == 0 {
+                    return Some(node);
+                }
+                continue;
+            }
+            // Only the ancestors of the node can have been matched.
+            if node.depth - 1 < matched {
+                return None;
+            }
+            if node.depth - 1 == matched && component.is_some_and(|c| node.name_matches(c)) {
+                matched += 1;
+                if matched == count {
+                    return Some(node);
+                }
+                component = components.next();
+            }
+        }
+        None
+    }
+
+    /// Returns the node with the given `phandle`.
+    pub fn node_by_phandle(&self, phandle: u32) -> Option<Node<'a>> {
+        self.nodes().find(|node| node.phandle() == Some(phandle))
+    }
+
+    /// Returns the enabled nodes with `compat` in their `compatible` lists.
+    pub fn nodes_compatible(&self, compat: &'a str) -> impl Iterator<Item = Node<'a>> + 'a {
+        self.nodes()
+            .filter(move |node| node.is_compatible(compat) && node.is_enabled())
+    }
+
+    /// Returns the physical memory regions in the `reg` of the memory nodes.
+    pub fn memory_regions(&self) -> impl Iterator<Item = (PhysAddr, usize)> + 'a {
+        self.nodes()
+            .filter(|node| node.property_str("device_type") == Some("memory") && node.is_enabled())
+            .flat_map(|node| node.reg())
+            .filter(|&(_, size)| size != 0)
+            .map(|(base, size)| (base.into(), size))
+    }
+
+    /// Returns the physical memory regions that must not be used: the entries
+    /// of the memory reservation block, the static regions in
+    /// `/reserved-memory`, and the initrd in `/chosen`.
+    ///
+    /// Regions in `/reserved-memory` without `reg` are to be allocated by the
+    /// OS for the devices, and are not included.
+    pub fn reserved_regions(&self) -> impl Iterator<Item = (PhysAddr, usize)> + 'a {
+        let rsvmap = self
+            .rsvmap
+            .chunks_exact(16)
+            .filter_map(|entry| Some((read_cells(entry, 2)?, read_cells(&entry[8..], 2)?)))
+            .take_while(|&(base, size)| base != 0 || size != 0)
+            .map(|(base, size)| (base as usize, size as usize));
+        let reserved_memory = self
+            .nodes()
+            .skip_while(|node| !(node.depth == 1 && node.name_matches("reserved-memory")))
+            .skip(1)
+            .take_while(|node| node.depth > 1)
+            .filter(|node| node.depth == 2 && node.is_enabled())
+            .flat_map(|node| node.reg());
+        rsvmap
+            .chain(reserved_memory)
+            .chain(self.initrd_region())
+            .filter(|&(_, size)| size != 0)
+            .map(|(base, size)| (base.into(), size))
+    }
+
+    /// Returns the initrd range in `linux,initrd-start` and `linux,initrd-end`
+    /// of `/chosen`, which can be 1 or 2 cells.
+    fn initrd_region(&self) -> Option<(usize, usize)> {
+        let chosen = self.find_node("/chosen")?;
+        let read = |name| {
+            let value = chosen.property(name)?;
+            read_cells(value, value.len() / 4).map(|v| v as usize)
+        };
+        let (start, end) = (read("linux,initrd-start")?, read("linux,initrd-end")?);
+        (start < end).then_some((start, end - start))
+    }
+
+    /// Returns the first interrupt of the `interrupts` property of `node`, as
+    /// an IRQ number of the interrupt controller.
+    ///
+    /// Interrupt specifiers of the GIC are 3 cells, where shared and private
+    /// peripheral interrupts are numbered from 32 and 16. Other controllers,
+    /// e.g., the RISC-V PLIC, are taken to have the IRQ number in the first
+    /// cell. If the interrupt parent is not found, the GIC is assumed for
+    /// properties of a multiple of 3 cells.
+    pub fn interrupt_of(&self, node: &Node<'a>) -> Option<usize> {
+        const GIC_SPI: u32 = 0;
+        const GIC_PPI: u32 = 1;
+        let interrupts = node.property("interrupts")?;
+        let parent = node.interrupt_parent.and_then(|p| self.node_by_phandle(p));
+        let is_gic = match parent {
+            Some(parent) => {
+                parent.property_u32("#interrupt-cells") == Some(3)
+                    && parent.compatible().any(|c| c.contains("gic"))
+            }
+            None => interrupts.len() % 12 == 0,
+        };
+        if is_gic {
+            let num = be32(interrupts, 4)? as usize;
+            match be32(interrupts, 0)? {
+                GIC_SPI => Some(num + 32),
+                GIC_PPI => Some(num + 16),
+                _ => None,
+            }
+        } else {
+            be32(interrupts, 0).map(|irq| irq as usize)
+        }
+    }
+
+    /// Returns the path of the console in the `stdout-path` of `/chosen`,
+    /// without the options after `:`, and with aliases resolved.
+    pub fn stdout_path(&self) -> Option<&'a str> {
+        let chosen = self.find_node("/chosen")?;
+        let path = chosen
+            .property_str("stdout-path")
+            .or_else(|| chosen.property_str("linux,stdout-path"))?;
+        let path = path.split(':').next()?;
+        if path.starts_with('/') {
+            Some(path)
+        } else {
+            self.find_node("/aliases")?.property_str(path)
+        }
+    }
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Node<'a>> {
+        loop {
+            let (token, next) = self.fdt.token(self.offset)?;
+            self.offset = next;
+            match token {
+                Token::BeginNode(name) => {
+                    let parent = match self.depth {
+                        0 => Level::ROOT_PARENT,
+                        depth => self.stack[depth - 1],
+                    };
+                    let node = Node {
+                        fdt: self.fdt,
+                        name,
+                        props_offset: next,
+                        depth: self.depth,
+                        parent_cells: (parent.address_cells, parent.size_cells),
+                        interrupt_parent: parent.interrupt_parent,
+                    };
+                    // The depth is bounded by `Fdt::check_structure`.
+                    self.stack[self.depth] = Level {
+                        address_cells: node.property_u32("#address-cells").unwrap_or(2) as usize,
+                        size_cells: node.property_u32("#size-cells").unwrap_or(1) as usize,
+                        interrupt_parent: node
+                            .property_u32("interrupt-parent")
+                            .or(node.interrupt_parent),
+                    };
+                    self.depth += 1;
+                    return Some(node);
+                }
+                Token::EndNode => self.depth -= 1,
+                Token::Prop(..) => {}
+                Token::End => return None,
+            }
+        }
+    }
+}
+
+impl<'a> Node<'a> {
+    /// Returns the name of the node with the unit address, e.g.,
+    /// `virtio_mmio@a000000`. The name of the root node is empty.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Whether the node name is `name`, or has no unit address in `name` and
+    /// matches without its unit address.
+    fn name_matches(&self, name: &str) -> bool {
+        self.name == name || (!name.contains('@') && self.name.split('@').next() == Some(name))
+    }
+
+    /// Returns the properties of the node as names and raw values.
+    pub fn properties(&self) -> impl Iterator<Item = (&'a str, &'a [u8])> + 'a {
+        let fdt = self.fdt;
+        let mut offset = self.props_offset;
+        core::iter::from_fn(move || match fdt.token(offset)? {
+            (Token::Prop(name, value), next) => {
+                offset = next;
+                Some((name, value))
+            }
+            _ => None,
+        })
+    }
+
+    /// Returns the raw value of the property `name`.
+    pub fn property(&self, name: &str) -> Option<&'a [u8]> {
+        self.properties().find(|&(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    /// Returns the value of the property `name` as a single cell.
+    pub fn property_u32(&self, name: &str) -> Option<u32> {
+        self.property(name).and_then(|v| be32(v, 0))
+    }
+
+    /// Returns the value of the property `name` as one or two cells, as some
+    /// bindings allow for large numbers, e.g., frequencies.
+    pub fn property_u64(&self, name: &str) -> Option<u64> {
+        let value = self.property(name)?;
+        match value.len() {
+            4 | 8 => read_cells(value, value.len() / 4),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of the property `name` as a string.
+    pub fn property_str(&self, name: &str) -> Option<&'a str> {
+        self.property(name).and_then(|v| read_str(v, 0))
+    }
+
+    /// Returns the strings of the `compatible` property.
+    pub fn compatible(&self) -> impl Iterator<Item = &'a str> + 'a {
+        let value = self.property("compatible").unwrap_or(&[]);
+        value
+            .split(|&b| b == 0)
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| str::from_utf8(s).ok())
+    }
+
+    /// Whether `compat` is one of the `compatible` strings.
+    pub fn is_compatible(&self, compat: &str) -> bool {
+        self.compatible().any(|c| c == compat)
+    }
+
+    /// Whether the `status` is `okay` or absent.
+    pub fn is_enabled(&self) -> bool {
+        self.property_str("status")
+            .map_or(true, |status| status == "okay" || status == "ok")
+    }
+
+    /// Returns the phandle of the node, if it is referenced.
+    pub fn phandle(&self) -> Option<u32> {
+        self.property_u32("phandle")
+            .or_else(|| self.property_u32("linux,phandle"))
+    }
+
+    /// Returns the address ranges of the `reg` property as base addresses
+    /// and sizes, in the address space of the parent.
+    pub fn reg(&self) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let (address_cells, size_cells) = self.parent_cells;
+        let entry_size = (address_cells + size_cells) * 4;
+        let reg = self.property("reg").filter(|_| entry_size != 0);
+        reg.unwrap_or(&[])
+            .chunks_exact(entry_size.max(4))
+            .filter_map(move |entry| {
+                let base = read_cells(entry, address_cells)?;
+                let size = read_cells(&entry[address_cells * 4..], size_cells)?;
+                Some((base as usize, size as usize))
+            })
+    }
+}
+
+/// Returns the device tree passed by the bootloader, or `None` if it is
+/// absent or malformed.
+pub fn fdt() -> Option<Fdt<'static>> {
+    Fdt::from_bytes(crate::dtb::dtb()?)
+}
+
+/// Returns the physical memory regions in the device tree, or the physical
+/// memory in the platform config if there are none.
+pub fn memory_regions() -> impl Iterator<Item = (PhysAddr, usize)> {
+    let from_fdt = fdt().filter(|fdt| fdt.memory_regions().next().is_some());
+    let fallback = (
+        axconfig::PHYS_MEMORY_BASE.into(),
+        axconfig::PHYS_MEMORY_SIZE,
+    );
+    let fallback = from_fdt.is_none().then_some(fallback);
+    from_fdt
+        .into_iter()
+        .flat_map(|fdt| fdt.memory_regions())
+        .chain(fallback)
+}
+
+/// Returns the reserved physical memory regions in the device tree, see
+/// [`Fdt::reserved_regions`].
+pub fn reserved_regions() -> impl Iterator<Item = (PhysAddr, usize)> {
+    fdt().into_iter().flat_map(|fdt| fdt.reserved_regions())
+}
+
+/// Returns the enabled nodes with `compat` in their `compatible` lists, e.g.,
+/// `virtio,mmio`.
+pub fn nodes_compatible(compat: &'static str) -> impl Iterator<Item = Node<'static>> {
+    fdt()
+        .into_iter()
+        .flat_map(move |fdt| fdt.nodes_compatible(compat))
+}
+
+/// Returns the first interrupt of `node`, see [`Fdt::interrupt_of`].
+pub fn interrupt_of(node: &Node<'static>) -> Option<usize> {
+    node.fdt.interrupt_of(node)
+}
+
+/// Returns the path of the console node chosen by the bootloader, see
+/// [`Fdt::stdout_path`].
+pub fn stdout_path() -> Option<&'static str> {
+    fdt()?.stdout_path()
+}
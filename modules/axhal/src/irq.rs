@@ -1,17 +1,148 @@
 //! Interrupt management.
 
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use handler_table::HandlerTable;
 
 use crate::platform::irq::{dispatch_irq, MAX_IRQ_COUNT};
 use crate::trap::{register_trap_handler, IRQ};
 
-pub use crate::platform::irq::{register_handler, set_enable};
+pub use crate::platform::irq::{gsi_irq, route_gsi};
+pub use crate::platform::irq::{msi_message, register_handler, set_affinity, set_enable};
+pub use crate::platform::irq::{send_ipi, IPI_IRQ_NUM};
+pub use crate::platform::irq::{set_affinity_mask, set_priority, set_threshold};
 
 /// The type if an IRQ handler.
 pub type IrqHandler = handler_table::Handler;
 
+/// The trigger mode of an interrupt line, see [`route_gsi`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// Raised on the edges of the signal.
+    Edge,
+    /// Raised while the signal is asserted, e.g., PCI INTx.
+    Level,
+}
+
+/// The polarity of an interrupt line, see [`route_gsi`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    /// Asserted when high.
+    ActiveHigh,
+    /// Asserted when low.
+    ActiveLow,
+}
+
+/// The message a device writes to raise a message-signaled interrupt (MSI or
+/// MSI-X).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiMessage {
+    /// The doorbell address to write to.
+    pub address: u64,
+    /// The data to write.
+    pub data: u32,
+}
+
+/// A block of contiguous vectors for message-signaled interrupts, allocated
+/// by [`alloc_msi`].
+///
+/// The vectors are IRQ numbers, whose handlers are registered by
+/// [`register_handler`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiAllocation {
+    /// The IRQ number of the first vector, the others follow in order.
+    pub first_irq: usize,
+    /// The number of vectors.
+    pub count: usize,
+}
+
+impl MsiAllocation {
+    /// Returns the IRQ number of the vector `index`.
+    pub fn irq(&self, index: usize) -> usize {
+        assert!(index < self.count, "MSI vector index out of range");
+        self.first_irq + index
+    }
+
+    /// Returns the message to raise the vector `index`, e.g., to write into
+    /// an MSI-X table entry, see [`msi_message`].
+    ///
+    /// The data of the vectors are consecutive, so the message of the first
+    /// vector can also be used for multi-message MSI, as long as all vectors
+    /// target the same CPU.
+    pub fn message(&self, index: usize) -> MsiMessage {
+        msi_message(self.irq(index))
+    }
+}
+
+/// Allocates `count` contiguous vectors for message-signaled interrupts.
+///
+/// They target the current CPU until changed by [`set_affinity`]. The LAPIC
+/// vectors are used on x86_64, and the SPIs of the GICv2m frame on AArch64.
+/// Returns `None` if there is no free block of `count` vectors, or no MSI
+/// controller, e.g., on RISC-V without an IMSIC.
+pub fn alloc_msi(count: usize) -> Option<MsiAllocation> {
+    let first_irq = crate::platform::irq::alloc_msi(count)?;
+    Some(MsiAllocation { first_irq, count })
+}
+
+/// Frees the vectors allocated by [`alloc_msi`], which can then be allocated
+/// again.
+///
+/// A part of the allocation can be freed alone, e.g., a single vector by
+/// `MsiAllocation { first_irq: irq, count: 1 }`.
+pub fn free_msi(alloc: MsiAllocation) {
+    crate::platform::irq::free_msi(alloc.first_irq, alloc.count)
+}
+
+/// Allocates `count` contiguous free entries among the first `num` bits of
+/// `bitmap`, and returns the index of the first one.
+///
+/// The block is aligned to the next power of two of `count`, as multi-message
+/// MSI requires.
+#[allow(dead_code)]
+pub(crate) fn alloc_msi_block(bitmap: &mut u128, num: usize, count: usize) -> Option<usize> {
+    if count == 0 || count > num.min(u128::BITS as usize) {
+        return None;
+    }
+    let mask = u128::MAX >> (u128::BITS as usize - count);
+    let idx = (0..num - count + 1)
+        .step_by(count.next_power_of_two())
+        .find(|&idx| *bitmap & mask << idx == 0)?;
+    *bitmap |= mask << idx;
+    Some(idx)
+}
+
+/// Frees `count` entries from `idx` allocated by [`alloc_msi_block`],
+/// ignoring those out of the first `num` bits.
+#[allow(dead_code)]
+pub(crate) fn free_msi_block(bitmap: &mut u128, num: usize, idx: usize, count: usize) {
+    for i in idx..idx.saturating_add(count).min(num.min(u128::BITS as usize)) {
+        *bitmap &= !(1 << i);
+    }
+}
+
 static IRQ_HANDLER_TABLE: HandlerTable<MAX_IRQ_COUNT> = HandlerTable::new();
 
+/// The number of the IRQs handled on all CPUs since boot.
+static IRQ_COUNT: AtomicU64 = AtomicU64::new(0);
+/// The number of the timer IRQs handled on all CPUs since boot.
+static TIMER_IRQ_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the number of the IRQs handled on all CPUs since boot, including
+/// the timer and IPI ones.
+pub fn irq_count() -> u64 {
+    IRQ_COUNT.load(Ordering::Relaxed)
+}
+
+/// Returns the number of the timer IRQs handled on all CPUs since boot.
+///
+/// The difference over an interval gives the timer interrupt rate, e.g., to
+/// compare how often the idle CPUs are woken up with different tick
+/// frequencies, and with the `tickless` or `dyntick` feature of axtask.
+pub fn timer_irq_count() -> u64 {
+    TIMER_IRQ_COUNT.load(Ordering::Relaxed)
+}
+
 /// Platform-independent IRQ dispatching.
 #[allow(dead_code)]
 pub(crate) fn dispatch_irq_common(irq_num: usize) {
@@ -37,8 +168,17 @@ pub(crate) fn register_handler_common(irq_num: usize, handler: IrqHandler) -> bo
 
 #[register_trap_handler(IRQ)]
 fn handler_irq(irq_num: usize) -> bool {
+    #[cfg(feature = "latency-trace")]
+    crate::latency::irq_enter();
     let guard = kernel_guard::NoPreempt::new();
+    IRQ_COUNT.fetch_add(1, Ordering::Relaxed);
+    if irq_num == crate::platform::irq::TIMER_IRQ_NUM {
+        TIMER_IRQ_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
     dispatch_irq(irq_num);
+    crate::watchdog::check_soft_deadline();
     drop(guard); // rescheduling may occur when preemption is re-enabled.
+    #[cfg(feature = "latency-trace")]
+    crate::latency::trace_on(crate::latency::LatencyKind::IrqOff);
     true
 }
@@ -0,0 +1,175 @@
+//! Tracing of the regions with preemption or IRQs disabled.
+//!
+//! Each region is timed by the [cycle counter](crate::perf::read_cycles) of
+//! its CPU, and counted in a histogram of the durations. The longest region
+//! of each kind is kept with the return addresses at its start, to find the
+//! offender. Tracing never allocates, so it can be done anywhere.
+//!
+//! The IRQ-off regions are traced by [`arch::disable_irqs`] and
+//! [`arch::enable_irqs`], by the IRQ handler from its entry to its exit, and
+//! at the call sites that call [`trace_off`] and [`trace_on`] by themselves,
+//! e.g., the run queue locks of `axtask`. Other guards that save and restore
+//! the IRQ state by themselves, like those of `kernel_guard`, are not seen.
+//! The preemption-off regions are traced by the task scheduler.
+//!
+//! [`arch::disable_irqs`]: crate::arch::disable_irqs
+//! [`arch::enable_irqs`]: crate::arch::enable_irqs
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use crate::cpu::this_cpu_id;
+use crate::perf::{cycles_to_nanos, read_cycles};
+
+/// The number of buckets of the histograms. Bucket 0 counts the regions
+/// shorter than 1 µs, bucket `i` the ones from 2<sup>i-1</sup> µs to
+/// 2<sup>i</sup> µs, and the last one also the longer ones.
+pub const NUM_BUCKETS: usize = 16;
+
+/// The number of return addresses kept for the longest region.
+pub const NUM_CALLERS: usize = 4;
+
+/// The kinds of the traced regions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatencyKind {
+    /// Preemption is disabled.
+    PreemptOff = 0,
+    /// IRQs are disabled.
+    IrqOff = 1,
+}
+
+/// The statistics of the regions of a kind on a CPU, see [`stats`].
+#[derive(Debug, Clone)]
+pub struct LatencyStats {
+    /// The number of regions.
+    pub count: u64,
+    /// The duration of the longest region.
+    pub max: Duration,
+    /// The return addresses at the start of the longest region, innermost
+    /// first, or 0 if the walk of the stack stops earlier.
+    pub max_callers: [usize; NUM_CALLERS],
+    /// The number of regions in each bucket, see [`NUM_BUCKETS`].
+    pub histogram: [u64; NUM_BUCKETS],
+}
+
+struct Tracer {
+    /// The cycle counter at the start of the current region, or 0 if it is
+    /// not in one.
+    since: AtomicU64,
+    since_callers: [AtomicUsize; NUM_CALLERS],
+    max_cycles: AtomicU64,
+    max_callers: [AtomicUsize; NUM_CALLERS],
+    histogram: [AtomicU64; NUM_BUCKETS],
+}
+
+impl Tracer {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NULL: AtomicUsize = AtomicUsize::new(0);
+
+    const fn new() -> Self {
+        Self {
+            since: AtomicU64::new(0),
+            since_callers: [Self::NULL; NUM_CALLERS],
+            max_cycles: AtomicU64::new(0),
+            max_callers: [Self::NULL; NUM_CALLERS],
+            histogram: [Self::ZERO; NUM_BUCKETS],
+        }
+    }
+
+    /// Counts a region of `cycles`, and keeps it if it is the longest.
+    ///
+    /// Only the CPU of the tracer updates it, so plain loads and stores are
+    /// enough.
+    fn record(&self, cycles: u64) {
+        let micros = cycles_to_nanos(cycles) / 1000;
+        let bucket = ((u64::BITS - micros.leading_zeros()) as usize).min(NUM_BUCKETS - 1);
+        let count = &self.histogram[bucket];
+        count.store(count.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+        if cycles > self.max_cycles.load(Ordering::Relaxed) {
+            self.max_cycles.store(cycles, Ordering::Relaxed);
+            for (max, since) in self.max_callers.iter().zip(&self.since_callers) {
+                max.store(since.load(Ordering::Relaxed), Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn stats(&self) -> LatencyStats {
+        let histogram = core::array::from_fn(|i| self.histogram[i].load(Ordering::Relaxed));
+        LatencyStats {
+            count: histogram.iter().sum(),
+            max: Duration::from_nanos(cycles_to_nanos(self.max_cycles.load(Ordering::Relaxed))),
+            max_callers: core::array::from_fn(|i| self.max_callers[i].load(Ordering::Relaxed)),
+            histogram,
+        }
+    }
+
+    fn reset(&self) {
+        self.max_cycles.store(0, Ordering::Relaxed);
+        for count in &self.histogram {
+            count.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_TRACERS: [Tracer; 2] = [Tracer::new(), Tracer::new()];
+static TRACERS: [[Tracer; 2]; axconfig::SMP] = [EMPTY_TRACERS; axconfig::SMP];
+
+fn tracer(kind: LatencyKind) -> &'static Tracer {
+    &TRACERS[this_cpu_id()][kind as usize]
+}
+
+/// Marks the start of a region of `kind` on the current CPU, unless it is
+/// already in one.
+///
+/// It must be called in the region, i.e., with preemption or IRQs disabled,
+/// so that the region ends on the same CPU.
+#[inline(never)]
+pub fn trace_off(kind: LatencyKind) {
+    let tracer = tracer(kind);
+    if tracer.since.load(Ordering::Relaxed) != 0 {
+        return;
+    }
+    let mut callers = crate::backtrace::return_addresses();
+    for caller in &tracer.since_callers {
+        caller.store(callers.next().unwrap_or(0), Ordering::Relaxed);
+    }
+    tracer.since.store(read_cycles().max(1), Ordering::Relaxed);
+}
+
+/// Marks the end of the region of `kind` on the current CPU if it is in one,
+/// and records its duration.
+///
+/// It must be called in the region, before preemption or IRQs are enabled.
+pub fn trace_on(kind: LatencyKind) {
+    let tracer = tracer(kind);
+    let since = tracer.since.swap(0, Ordering::Relaxed);
+    if since != 0 {
+        tracer.record(read_cycles().saturating_sub(since));
+    }
+}
+
+/// Starts the IRQ-off region of an IRQ handler.
+///
+/// IRQs must have been enabled to take the IRQ, so a region still open was
+/// left by a guard not traced, and is dropped.
+pub(crate) fn irq_enter() {
+    tracer(LatencyKind::IrqOff)
+        .since
+        .store(0, Ordering::Relaxed);
+    trace_off(LatencyKind::IrqOff);
+}
+
+/// Returns the statistics of the regions of `kind` on the given CPU.
+pub fn stats(cpu_id: usize, kind: LatencyKind) -> LatencyStats {
+    TRACERS[cpu_id][kind as usize].stats()
+}
+
+/// Clears the statistics of all the CPUs.
+pub fn reset() {
+    for tracer in TRACERS.iter().flatten() {
+        tracer.reset();
+    }
+}
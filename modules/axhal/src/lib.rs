@@ -10,6 +10,7 @@
 //! - `riscv64-qemu-virt`: QEMU virt machine with RISC-V ISA.
 //! - `aarch64-qemu-virt`: QEMU virt machine with AArch64 ISA.
 //! - `aarch64-raspi`: Raspberry Pi with AArch64 ISA.
+//! - `loongarch64-qemu-virt`: QEMU virt machine with LoongArch64 ISA.
 //! - `dummy`: If none of the above platform is selected, the dummy platform
 //!    will be used. In this platform, most of the operations are no-op or
 //!    `unimplemented!()`. This platform is mainly used for [cargo test].
@@ -20,14 +21,18 @@
 //! - `fp_simd`: Enable floating-point and SIMD support.
 //! - `paging`: Enable page table manipulation.
 //! - `irq`: Enable interrupt handling support.
+//! - `rtc`: Read the wall time from the RTC at boot, and write it back in
+//!    [`time::set_wall_time`]: the CMOS RTC on x86_64, and the PL031 (AArch64)
+//!    or goldfish (RISC-V) RTC at `rtc-paddr` in the platform config.
+//! - `ksyms`: Embed the symbol table given by the `AX_KSYMS` environment
+//!    variable, to print function names in [`backtrace`]s.
+//! - `latency-trace`: Trace the regions with preemption or IRQs disabled,
+//!    see [`latency`].
 //!
 //! [ArceOS]: https://github.com/arceos-org/arceos
 //! [cargo test]: https://doc.rust-lang.org/cargo/guide/tests.html
 
 #![no_std]
-#![feature(asm_const)]
-#![feature(naked_functions)]
-#![feature(const_option)]
 #![feature(doc_auto_cfg)]
 
 #[allow(unused_imports)]
@@ -39,14 +44,24 @@ extern crate log;
 extern crate memory_addr;
 
 mod platform;
+mod random;
 
 #[macro_use]
 pub mod trap;
 
 pub mod arch;
+pub mod backtrace;
+pub mod cache;
+pub mod console;
 pub mod cpu;
+pub mod dtb;
+pub mod fdt;
 pub mod mem;
+pub mod numa;
+pub mod perf;
 pub mod time;
+pub mod tlb;
+pub mod watchdog;
 
 #[cfg(feature = "tls")]
 pub mod tls;
@@ -57,20 +72,101 @@ pub mod irq;
 #[cfg(feature = "paging")]
 pub mod paging;
 
-/// Console input and output.
-pub mod console {
-    pub use super::platform::console::*;
-}
+#[cfg(feature = "latency-trace")]
+pub mod latency;
 
-/// Miscellaneous operation, e.g. terminate the system.
+/// Miscellaneous operation, e.g. terminate or reboot the system.
 pub mod misc {
+    // Only the platform-specific operations, the others are wrapped below.
+    #[allow(unused_imports)]
     pub use super::platform::misc::*;
+    pub use super::random::{jitter_random_u64, random_u64};
+
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use handler_table::HandlerTable;
+
+    const MAX_SHUTDOWN_HOOKS: usize = 8;
+
+    static SHUTDOWN_HOOKS: HandlerTable<MAX_SHUTDOWN_HOOKS> = HandlerTable::new();
+    static NUM_SHUTDOWN_HOOKS: AtomicUsize = AtomicUsize::new(0);
+    static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+    /// Registers a function to be called by [`terminate`] before the system
+    /// is shut down, e.g., to flush and release devices.
+    ///
+    /// Hooks are called in the reverse order of registration. It returns
+    /// `false` if too many hooks have been registered.
+    pub fn register_shutdown_hook(hook: fn()) -> bool {
+        let idx = NUM_SHUTDOWN_HOOKS.fetch_add(1, Ordering::AcqRel);
+        idx < MAX_SHUTDOWN_HOOKS && SHUTDOWN_HOOKS.register_handler(idx, hook)
+    }
+
+    /// Calls the shutdown hooks, only once even if the shutdown is re-entered,
+    /// e.g., by a panic in a hook.
+    fn run_shutdown_hooks() {
+        if !SHUTTING_DOWN.swap(true, Ordering::AcqRel) {
+            let num = NUM_SHUTDOWN_HOOKS.load(Ordering::Acquire);
+            for idx in (0..num.min(MAX_SHUTDOWN_HOOKS)).rev() {
+                SHUTDOWN_HOOKS.handle(idx);
+            }
+        }
+    }
+
+    /// Shuts down the whole system.
+    ///
+    /// The watchdog is stopped, then the hooks registered by
+    /// [`register_shutdown_hook`] are called.
+    /// They are called only once even if `terminate` is re-entered, e.g., by a
+    /// panic in a hook.
+    pub fn terminate() -> ! {
+        crate::watchdog::stop();
+        run_shutdown_hooks();
+        super::platform::misc::terminate()
+    }
+
+    /// Reboots the whole system.
+    ///
+    /// The watchdog is stopped and the hooks registered by
+    /// [`register_shutdown_hook`] are called first, as in [`terminate`].
+    pub fn reboot() -> ! {
+        crate::watchdog::stop();
+        run_shutdown_hooks();
+        super::platform::misc::reboot()
+    }
 }
 
 /// Multi-core operations.
 #[cfg(feature = "smp")]
 pub mod mp {
+    // Only the platform-specific items, the others are wrapped below.
+    #[allow(unused_imports)]
     pub use super::platform::mp::*;
+
+    use crate::mem::PhysAddr;
+
+    /// Starts the given secondary CPU with its boot stack.
+    ///
+    /// The boot stack is used again when the CPU is started by
+    /// [`cpu_up`](crate::cpu::cpu_up) after it is taken offline.
+    pub fn start_secondary_cpu(cpu_id: usize, stack_top: PhysAddr) {
+        crate::cpu::set_boot_stack(cpu_id, stack_top);
+        super::platform::mp::start_secondary_cpu(cpu_id, stack_top)
+    }
+
+    /// Whether the CPU exists, so that it can be started.
+    ///
+    /// On x86_64, it must be listed in the ACPI MADT if there is one, where
+    /// the CPU ID is the APIC ID. Otherwise, all the CPUs below
+    /// [`axconfig::SMP`] are assumed to exist.
+    pub fn cpu_present(cpu_id: usize) -> bool {
+        cfg_if::cfg_if! {
+            if #[cfg(all(target_arch = "x86_64", platform_family = "x86-pc"))] {
+                cpu_id < axconfig::SMP && super::platform::mp::cpu_present(cpu_id)
+            } else {
+                cpu_id < axconfig::SMP
+            }
+        }
+    }
 }
 
 pub use self::platform::platform_init;
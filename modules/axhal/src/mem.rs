@@ -1,12 +1,17 @@
 //! Physical memory management.
 
 use core::fmt;
+use core::ptr::{addr_of, addr_of_mut};
+
+use kspin::SpinNoIrq;
+use lazyinit::LazyInit;
 
 #[doc(no_inline)]
 pub use memory_addr::{MemoryAddr, PhysAddr, VirtAddr, PAGE_SIZE_4K};
 
 bitflags::bitflags! {
     /// The flags of a physical memory region.
+    #[derive(Clone, Copy)]
     pub struct MemRegionFlags: usize {
         /// Readable.
         const READ          = 1 << 0;
@@ -72,7 +77,9 @@ pub const fn phys_to_virt(paddr: PhysAddr) -> VirtAddr {
 
 /// Returns an iterator over all physical memory regions.
 pub fn memory_regions() -> impl Iterator<Item = MemRegion> {
-    kernel_image_regions().chain(crate::platform::mem::platform_regions())
+    kernel_image_regions()
+        .chain(dtb_region())
+        .chain(crate::platform::mem::platform_regions())
 }
 
 /// Returns the memory regions of the kernel image (code and data sections).
@@ -112,6 +119,23 @@ fn kernel_image_regions() -> impl Iterator<Item = MemRegion> {
     .into_iter()
 }
 
+/// Returns the page-aligned range of the DTB passed by the bootloader, if any.
+fn dtb_range() -> Option<(PhysAddr, PhysAddr)> {
+    let (paddr, size) = crate::dtb::dtb_region()?;
+    Some((paddr.align_down_4k(), (paddr + size).align_up_4k()))
+}
+
+/// Returns the memory region of the DTB passed by the bootloader, if any.
+fn dtb_region() -> Option<MemRegion> {
+    let (start, end) = dtb_range()?;
+    Some(MemRegion {
+        paddr: start,
+        size: end.as_usize() - start.as_usize(),
+        flags: MemRegionFlags::RESERVED | MemRegionFlags::READ,
+        name: "dtb",
+    })
+}
+
 /// Returns the default MMIO memory regions (from [`axconfig::MMIO_REGIONS`]).
 #[allow(dead_code)]
 pub(crate) fn default_mmio_regions() -> impl Iterator<Item = MemRegion> {
@@ -126,19 +150,326 @@ pub(crate) fn default_mmio_regions() -> impl Iterator<Item = MemRegion> {
     })
 }
 
-/// Returns the default free memory regions (kernel image end to physical memory end).
+/// Returns the physical address of the PCI Express ECAM (enhanced
+/// configuration access mechanism) of bus 0, and the last bus in it.
+///
+/// It is found in the ACPI MCFG on x86_64 if present, or is
+/// [`axconfig::PCI_ECAM_BASE`] and [`axconfig::PCI_BUS_END`] otherwise.
+pub fn pci_ecam() -> (PhysAddr, usize) {
+    cfg_if::cfg_if! {
+        if #[cfg(all(target_arch = "x86_64", platform_family = "x86-pc"))] {
+            crate::platform::mem::pci_ecam()
+        } else {
+            (pa!(axconfig::PCI_ECAM_BASE), axconfig::PCI_BUS_END)
+        }
+    }
+}
+
+/// Returns the physical RAM regions, from the memory map passed by the
+/// bootloader, or the device tree, or the physical memory in the platform
+/// config as a fallback.
+///
+/// Unlike [`memory_regions`], they include the memory in use, e.g., the
+/// kernel image and the memory reserved by the firmware.
+pub fn ram_regions() -> impl Iterator<Item = (PhysAddr, usize)> {
+    // Safe because the boot memory map is only written on early boot.
+    let boot_ram = unsafe { *addr_of!(BOOT_RAM) };
+    let from_fdt = (boot_ram.len == 0).then(crate::fdt::memory_regions);
+    boot_ram
+        .into_iter()
+        .map(|(start, end)| (start.into(), end - start))
+        .chain(from_fdt.into_iter().flatten())
+}
+
+/// Maximum number of ranges in a [`RangeSet`].
+const MAX_RANGES: usize = 32;
+
+/// A fixed-capacity set of disjoint physical address ranges `start..end`.
+///
+/// Ranges beyond the capacity are dropped, which only loses free memory.
+#[derive(Clone, Copy)]
+struct RangeSet {
+    ranges: [(usize, usize); MAX_RANGES],
+    len: usize,
+}
+
+impl RangeSet {
+    const fn new() -> Self {
+        Self {
+            ranges: [(0, 0); MAX_RANGES],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, start: usize, end: usize) {
+        if start < end && self.len < MAX_RANGES {
+            self.ranges[self.len] = (start, end);
+            self.len += 1;
+        }
+    }
+
+    /// Adds `start..end`, merging it into the overlapped ranges.
+    fn insert(&mut self, start: usize, end: usize) {
+        self.remove(start, end);
+        self.push(start, end);
+    }
+
+    /// Removes `start..end`, splitting the ranges overlapping it.
+    fn remove(&mut self, start: usize, end: usize) {
+        let mut i = 0;
+        while i < self.len {
+            let (s, e) = self.ranges[i];
+            if e <= start || s >= end {
+                i += 1;
+            } else if s < start {
+                self.ranges[i] = (s, start);
+                self.push(end, e);
+                i += 1;
+            } else if e > end {
+                self.ranges[i] = (end, e);
+                i += 1;
+            } else {
+                self.len -= 1;
+                self.ranges[i] = self.ranges[self.len];
+            }
+        }
+    }
+}
+
+impl IntoIterator for RangeSet {
+    type Item = (usize, usize);
+    type IntoIter = core::iter::Take<core::array::IntoIter<(usize, usize), MAX_RANGES>>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.ranges[..self.len].sort_unstable();
+        self.ranges.into_iter().take(self.len)
+    }
+}
+
+/// RAM in the memory map passed by the bootloader, if it is not in the
+/// device tree (e.g., the multiboot memory map on x86_64).
+static mut BOOT_RAM: RangeSet = RangeSet::new();
+/// Memory reserved by the bootloader, e.g., the boot modules.
+static mut BOOT_RESERVED: RangeSet = RangeSet::new();
+
+/// Records a RAM region in the memory map passed by the bootloader.
+///
+/// It must be called on early boot, before other CPUs are started.
+#[allow(dead_code)]
+pub(crate) unsafe fn add_boot_ram_region(paddr: usize, size: usize) {
+    (*addr_of_mut!(BOOT_RAM)).insert(paddr, paddr.saturating_add(size));
+}
+
+/// Records a memory region that is in use by the bootloader.
+///
+/// It must be called on early boot, before other CPUs are started.
+#[allow(dead_code)]
+pub(crate) unsafe fn add_boot_reserved_region(paddr: usize, size: usize) {
+    (*addr_of_mut!(BOOT_RESERVED)).insert(paddr, paddr.saturating_add(size));
+}
+
+/// Returns the default free memory regions: the RAM in [`ram_regions`]
+/// above the kernel image and mapped by the boot page table, excluding the
+/// DTB, the regions reserved in the device tree (including the initrd), and
+/// the regions reserved by the bootloader.
+///
+/// The RAM below the kernel image is left to the firmware and the
+/// bootloader, e.g., OpenSBI on RISC-V and the real mode data on x86_64.
 #[allow(dead_code)]
 pub(crate) fn default_free_regions() -> impl Iterator<Item = MemRegion> {
-    let start = virt_to_phys((_ekernel as usize).into()).align_up_4k();
-    let end = pa!(axconfig::PHYS_MEMORY_END).align_down_4k();
-    core::iter::once(MemRegion {
-        paddr: start,
-        size: end.as_usize() - start.as_usize(),
+    let kernel_end = virt_to_phys((_ekernel as usize).into()).align_up_4k();
+    let (map_start, map_end) = crate::platform::mem::BOOT_MAPPED_MEMORY;
+    let mut free = RangeSet::new();
+    for (base, size) in ram_regions() {
+        let start = base.align_up_4k().as_usize().max(kernel_end.as_usize());
+        let end = (base + size).align_down_4k().as_usize();
+        free.insert(start.max(map_start), end.min(map_end));
+    }
+    let mut reserve = |paddr: PhysAddr, size: usize| {
+        let end = (paddr + size).align_up_4k();
+        free.remove(paddr.align_down_4k().as_usize(), end.as_usize());
+    };
+    if let Some((start, end)) = dtb_range() {
+        reserve(start, end.as_usize() - start.as_usize());
+    }
+    crate::fdt::reserved_regions().for_each(|(paddr, size)| reserve(paddr, size));
+    // Safe because the boot memory map is only written on early boot.
+    let boot_reserved = unsafe { *addr_of!(BOOT_RESERVED) };
+    for (start, end) in boot_reserved {
+        reserve(start.into(), end - start);
+    }
+    free.into_iter().map(|(start, end)| MemRegion {
+        paddr: start.into(),
+        size: end - start,
         flags: MemRegionFlags::FREE | MemRegionFlags::READ | MemRegionFlags::WRITE,
         name: "free memory",
     })
 }
 
+/// Returns the default free memory regions as [`default_free_regions`], but
+/// without the physical range `start..end`, e.g., the memory owned by other
+/// processors on the SoC.
+#[allow(dead_code)]
+pub(crate) fn default_free_regions_without(
+    start: PhysAddr,
+    end: PhysAddr,
+) -> impl Iterator<Item = MemRegion> {
+    default_free_regions().flat_map(move |region| {
+        let region_end = region.paddr + region.size;
+        let piece = |piece_start: PhysAddr, piece_end: PhysAddr| {
+            (piece_start < piece_end).then(|| MemRegion {
+                paddr: piece_start,
+                size: piece_end.as_usize() - piece_start.as_usize(),
+                flags: region.flags,
+                name: region.name,
+            })
+        };
+        [
+            piece(region.paddr, start.min(region_end)),
+            piece(end.max(region.paddr), region_end),
+        ]
+        .into_iter()
+        .flatten()
+    })
+}
+
+/// Returns the virtual address range of the kernel code.
+pub(crate) fn kernel_text_range() -> core::ops::Range<usize> {
+    _stext as usize.._etext as usize
+}
+
+/// Returns the virtual address range of the boot stack of the primary CPU.
+pub(crate) fn boot_stack_range() -> core::ops::Range<usize> {
+    boot_stack as usize..boot_stack_top as usize
+}
+
+/// Size of the guard page at the bottom of each boot or exception stack.
+pub const STACK_GUARD_SIZE: usize = PAGE_SIZE_4K;
+
+/// Maximum number of registered stack guards: the boot stacks and the
+/// exception stacks of all CPUs.
+const MAX_STACK_GUARDS: usize = 2 * axconfig::SMP;
+
+/// A stack with a guard page at its bottom, which is unmapped once paging is
+/// up, so that an overflow faults instead of silently corrupting the memory
+/// below.
+#[repr(C, align(4096))]
+pub struct GuardedStack<const N: usize> {
+    guard: [u8; STACK_GUARD_SIZE],
+    stack: [u8; N],
+}
+
+impl<const N: usize> GuardedStack<N> {
+    const SIZE_OK: () = assert!(N % PAGE_SIZE_4K == 0, "stack size must be page aligned");
+
+    /// Creates a zeroed stack.
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        let () = Self::SIZE_OK;
+        Self {
+            guard: [0; STACK_GUARD_SIZE],
+            stack: [0; N],
+        }
+    }
+
+    /// Returns the start address of the guard page.
+    pub fn guard(&self) -> VirtAddr {
+        VirtAddr::from(self.guard.as_ptr() as usize)
+    }
+
+    /// Returns the top address of the stack, i.e., its initial stack pointer.
+    pub fn top(&self) -> VirtAddr {
+        VirtAddr::from(self.stack.as_ptr_range().end as usize)
+    }
+}
+
+/// A registered stack guard page.
+#[derive(Debug, Clone, Copy)]
+pub struct StackGuard {
+    /// The start address of the guard page.
+    pub vaddr: VirtAddr,
+    /// The CPU the stack belongs to.
+    pub cpu_id: usize,
+    /// What the stack is used for, e.g., `"boot"` or `"exception"`.
+    pub context: &'static str,
+}
+
+static STACK_GUARDS: SpinNoIrq<[Option<StackGuard>; MAX_STACK_GUARDS]> =
+    SpinNoIrq::new([None; MAX_STACK_GUARDS]);
+
+/// Registers the guard page at `vaddr` of a stack of the CPU, to be unmapped
+/// by the kernel address space and recognized by the fault handlers.
+///
+/// Returns `false` if there is no room for it.
+pub fn register_stack_guard(vaddr: VirtAddr, cpu_id: usize, context: &'static str) -> bool {
+    let mut guards = STACK_GUARDS.lock();
+    match guards.iter_mut().find(|g| g.is_none()) {
+        Some(slot) => {
+            *slot = Some(StackGuard {
+                vaddr,
+                cpu_id,
+                context,
+            });
+            true
+        }
+        None => false,
+    }
+}
+
+/// Returns the registered stack guard pages.
+pub fn stack_guards() -> impl Iterator<Item = StackGuard> {
+    let guards = *STACK_GUARDS.lock();
+    guards.into_iter().flatten()
+}
+
+/// Returns the stack guard page containing `vaddr`, if any.
+pub fn stack_guard_at(vaddr: VirtAddr) -> Option<StackGuard> {
+    stack_guards().find(|g| (g.vaddr..g.vaddr + STACK_GUARD_SIZE).contains(&vaddr))
+}
+
+/// The type of a function that unmaps the 4K page at `vaddr` of the kernel
+/// address space as a guard page (`guard` is `true`), or maps it back. It
+/// returns whether it succeeds.
+pub type GuardPageFn = fn(vaddr: VirtAddr, guard: bool) -> bool;
+
+static GUARD_PAGE_FN: LazyInit<GuardPageFn> = LazyInit::new();
+
+/// Registers how guard pages are set at runtime, e.g., below the stacks of
+/// tasks, by the memory management module once the kernel page table is set
+/// up.
+///
+/// Returns `false` if a function is already registered.
+pub fn register_guard_page_fn(f: GuardPageFn) -> bool {
+    if GUARD_PAGE_FN.is_inited() {
+        return false;
+    }
+    GUARD_PAGE_FN.init_once(f);
+    true
+}
+
+/// Whether guard pages can be set at runtime by [`set_guard_page`].
+pub fn guard_pages_available() -> bool {
+    GUARD_PAGE_FN.is_inited()
+}
+
+/// Unmaps the 4K page at `vaddr` as a guard page (`guard` is `true`), or maps
+/// it back before it is freed.
+///
+/// Returns `false` if it fails or guard pages are not available, see
+/// [`register_guard_page_fn`].
+pub fn set_guard_page(vaddr: VirtAddr, guard: bool) -> bool {
+    match GUARD_PAGE_FN.get() {
+        Some(f) => f(vaddr, guard),
+        None => false,
+    }
+}
+
+/// Registers the guard page of the boot stack of the primary CPU, which is
+/// the first one in the boot stack region.
+pub(crate) fn register_primary_stack_guard(cpu_id: usize) {
+    register_stack_guard(VirtAddr::from(boot_stack as usize), cpu_id, "boot");
+}
+
 /// Fills the `.bss` section with zeros.
 #[allow(dead_code)]
 pub(crate) fn clear_bss() {
@@ -0,0 +1,205 @@
+//! NUMA (non-uniform memory access) topology.
+//!
+//! The nodes of the CPUs and of the physical memory are found on early boot,
+//! in the ACPI SRAT on x86_64, or in the `numa-node-id` properties of the CPU
+//! and memory nodes of the device tree elsewhere. The nodes are numbered from
+//! 0 in the order they are first seen, whatever their IDs in the firmware.
+//!
+//! Without such information, or with a single node, everything is on node 0
+//! and [`node_memory_regions`] gives all the memory regions unchanged.
+
+use lazyinit::LazyInit;
+
+use crate::mem::{MemRegion, PhysAddr};
+
+/// Maximum number of NUMA nodes, the nodes beyond are merged into node 0.
+pub const MAX_NUMA_NODES: usize = 8;
+/// Maximum number of memory ranges with their nodes.
+const MAX_MEMORY_RANGES: usize = 32;
+
+/// The NUMA nodes of the CPUs and the physical memory.
+#[derive(Clone, Copy)]
+pub(crate) struct Topology {
+    /// The firmware IDs of the nodes, indexed by the node numbers.
+    ids: [u32; MAX_NUMA_NODES],
+    num_nodes: usize,
+    cpu_nodes: [u8; axconfig::SMP],
+    /// The physical memory ranges `start..end` and their nodes, sorted.
+    ranges: [(usize, usize, u8); MAX_MEMORY_RANGES],
+    num_ranges: usize,
+}
+
+impl Topology {
+    pub(crate) const fn new() -> Self {
+        Self {
+            ids: [0; MAX_NUMA_NODES],
+            num_nodes: 0,
+            cpu_nodes: [0; axconfig::SMP],
+            ranges: [(0, 0, 0); MAX_MEMORY_RANGES],
+            num_ranges: 0,
+        }
+    }
+
+    /// Returns the node number of the firmware ID `id`, numbering it if it is
+    /// new. Nodes beyond [`MAX_NUMA_NODES`] are taken as node 0.
+    fn node(&mut self, id: u32) -> u8 {
+        if let Some(node) = self.ids[..self.num_nodes].iter().position(|&i| i == id) {
+            return node as u8;
+        }
+        if self.num_nodes == MAX_NUMA_NODES {
+            warn!("NUMA: too many nodes, node {} is taken as node 0", id);
+            return 0;
+        }
+        self.ids[self.num_nodes] = id;
+        self.num_nodes += 1;
+        (self.num_nodes - 1) as u8
+    }
+
+    /// Records that the CPU is on the node with the firmware ID `id`.
+    pub(crate) fn add_cpu(&mut self, cpu_id: usize, id: u32) {
+        let node = self.node(id);
+        if let Some(n) = self.cpu_nodes.get_mut(cpu_id) {
+            *n = node;
+        }
+    }
+
+    /// Records that the physical memory range is on the node with the
+    /// firmware ID `id`. Overlapping ranges are dropped.
+    pub(crate) fn add_memory(&mut self, start: usize, size: usize, id: u32) {
+        let end = start.saturating_add(size);
+        let ranges = &self.ranges[..self.num_ranges];
+        if size == 0 || ranges.iter().any(|&(s, e, _)| s < end && start < e) {
+            return;
+        }
+        if self.num_ranges == MAX_MEMORY_RANGES {
+            warn!(
+                "NUMA: too many memory ranges, [{:#x}, {:#x}) is dropped",
+                start, end
+            );
+            return;
+        }
+        let idx = ranges.partition_point(|&(s, _, _)| s < start);
+        let node = self.node(id);
+        self.ranges.copy_within(idx..self.num_ranges, idx + 1);
+        self.ranges[idx] = (start, end, node);
+        self.num_ranges += 1;
+    }
+
+    /// Returns the node of `paddr`, and the end of the range from `paddr` on
+    /// the same node. Addresses out of the ranges are on node 0.
+    fn segment_at(&self, paddr: usize) -> (usize, usize) {
+        let ranges = &self.ranges[..self.num_ranges];
+        match ranges.iter().find(|&&(_, end, _)| paddr < end) {
+            Some(&(start, end, node)) if start <= paddr => (node as usize, end),
+            Some(&(start, _, _)) => (0, start),
+            None => (0, usize::MAX),
+        }
+    }
+}
+
+static TOPOLOGY: LazyInit<Topology> = LazyInit::new();
+
+/// Returns the topology if there are multiple nodes.
+fn topology() -> Option<&'static Topology> {
+    TOPOLOGY.get().filter(|topo| topo.num_nodes > 1)
+}
+
+/// Returns the number of NUMA nodes, at least 1.
+pub fn num_nodes() -> usize {
+    topology().map_or(1, |topo| topo.num_nodes)
+}
+
+/// Returns the NUMA node of the CPU.
+pub fn node_of_cpu(cpu_id: usize) -> usize {
+    topology().map_or(0, |topo| {
+        topo.cpu_nodes.get(cpu_id).copied().unwrap_or(0) as usize
+    })
+}
+
+/// Returns the NUMA node of the physical memory at `paddr`.
+pub fn node_of_phys(paddr: PhysAddr) -> usize {
+    topology().map_or(0, |topo| topo.segment_at(paddr.as_usize()).0)
+}
+
+/// Returns the physical memory regions of [`memory_regions`] on the NUMA
+/// node, where regions spanning multiple nodes are split at the node
+/// boundaries.
+///
+/// [`memory_regions`]: crate::mem::memory_regions
+pub fn node_memory_regions(node: usize) -> impl Iterator<Item = MemRegion> {
+    let topo = topology();
+    crate::mem::memory_regions()
+        .flat_map(move |region| {
+            let end = region.paddr.as_usize() + region.size;
+            let mut start = region.paddr.as_usize();
+            core::iter::from_fn(move || {
+                if start >= end {
+                    return None;
+                }
+                let (node, seg_end) = topo.map_or((0, end), |topo| topo.segment_at(start));
+                let seg_start = start;
+                start = seg_end.min(end);
+                Some((
+                    node,
+                    MemRegion {
+                        paddr: pa!(seg_start),
+                        size: start - seg_start,
+                        flags: region.flags,
+                        name: region.name,
+                    },
+                ))
+            })
+        })
+        .filter(move |&(n, _)| n == node)
+        .map(|(_, region)| region)
+}
+
+/// Finds the NUMA nodes in the `numa-node-id` properties of the CPU and
+/// memory nodes of the device tree, where the `reg` of a CPU node is its ID.
+#[allow(dead_code)]
+fn fdt_topology() -> Option<Topology> {
+    let fdt = crate::fdt::fdt()?;
+    let mut topo = Topology::new();
+    for node in fdt.nodes().filter(|node| node.is_enabled()) {
+        let Some(id) = node.property_u32("numa-node-id") else {
+            continue;
+        };
+        match node.property_str("device_type") {
+            Some("cpu") => {
+                if let Some((cpu_id, _)) = node.reg().next() {
+                    topo.add_cpu(cpu_id, id);
+                }
+            }
+            Some("memory") => {
+                for (base, size) in node.reg() {
+                    topo.add_memory(base, size, id);
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(topo)
+}
+
+/// Finds the NUMA topology, called by the platform on early boot after the
+/// ACPI tables or the device tree can be read.
+#[allow(dead_code)]
+pub(crate) fn init_early() {
+    cfg_if::cfg_if! {
+        if #[cfg(all(target_arch = "x86_64", platform_family = "x86-pc"))] {
+            let topo = crate::platform::mem::numa_topology();
+        } else {
+            let topo = fdt_topology();
+        }
+    }
+    let Some(topo) = topo else {
+        return;
+    };
+    if topo.num_nodes > 1 {
+        debug!("NUMA: {} nodes", topo.num_nodes);
+        for &(start, end, node) in &topo.ranges[..topo.num_ranges] {
+            debug!("NUMA: node {}: [{:#x}, {:#x})", node, start, end);
+        }
+    }
+    TOPOLOGY.init_once(topo);
+}
@@ -0,0 +1,61 @@
+//! Cycle counter and hardware performance counters, for profiling.
+//!
+//! The cycle counter is always available: the TSC on x86_64, the generic
+//! timer counter (`CNTVCT_EL0`) on AArch64, the `cycle` CSR on RISC-V, and
+//! the stable counter on LoongArch64. It can also be read from user space
+//! (or EL0).
+//!
+//! Besides, one hardware event counter can be programmed on each CPU, if the
+//! CPU has a performance monitoring unit (PMU): the architectural performance
+//! monitoring on x86_64, PMUv3 on AArch64, or the SBI PMU extension on
+//! RISC-V. The counter never raises interrupts. It is not supported on
+//! LoongArch64 yet.
+
+use crate::arch::perf as arch;
+
+/// Hardware events that can be counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PerfEvent {
+    /// Instructions retired.
+    Instructions,
+    /// Cache misses. It is the last level cache on x86_64 and the L1 data
+    /// cache on AArch64, and is defined by the SBI implementation on RISC-V.
+    CacheMisses,
+}
+
+/// Errors of the event counter operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PerfError {
+    /// There is no PMU, or it can not count the event.
+    Unsupported,
+    /// The event counter is not started on this CPU.
+    NotStarted,
+}
+
+/// Reads the cycle counter of the current CPU.
+#[inline]
+pub fn read_cycles() -> u64 {
+    arch::read_cycles()
+}
+
+/// Converts cycles of [`read_cycles`] to nanoseconds.
+pub fn cycles_to_nanos(cycles: u64) -> u64 {
+    arch::cycles_to_nanos(cycles)
+}
+
+/// Starts counting `event` on the current CPU from zero.
+///
+/// If the counter is already started, it is reprogrammed for `event`.
+pub fn start_event(event: PerfEvent) -> Result<(), PerfError> {
+    arch::start_event(event)
+}
+
+/// Reads the event counter of the current CPU.
+pub fn read_event() -> Result<u64, PerfError> {
+    arch::read_event()
+}
+
+/// Stops the event counter of the current CPU.
+pub fn stop_event() {
+    arch::stop_event()
+}
@@ -53,14 +53,18 @@ pub fn init_early() {
     UART.lock().init();
 }
 
-/// Set UART IRQ Enable
+/// Enables the receive interrupt, so that the input is buffered by
+/// [`handle`].
 #[cfg(feature = "irq")]
 pub fn init_irq() {
     UART.lock().set_ier(true);
     crate::irq::register_handler(crate::platform::irq::UART_IRQ_NUM, handle);
+    crate::console::enable_rx_irq();
 }
 
 /// UART IRQ Handler
 pub fn handle() {
     trace!("Uart IRQ Handler");
+    #[cfg(feature = "irq")]
+    crate::console::receive(core::iter::from_fn(getchar));
 }
@@ -1,4 +1,4 @@
-pub use crate::platform::aarch64_common::psci::system_off as terminate;
+pub use crate::platform::aarch64_common::psci::{system_off as terminate, system_reset as reboot};
 
 use crate::mem::phys_to_virt;
 use crate::time::{busy_wait, Duration};
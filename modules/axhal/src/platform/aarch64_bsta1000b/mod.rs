@@ -19,6 +19,10 @@ pub mod time {
     pub use crate::platform::aarch64_common::generic_timer::*;
 }
 
+pub(crate) mod watchdog {
+    pub(crate) use crate::platform::aarch64_common::sbsa_wdt::*;
+}
+
 extern "C" {
     fn exception_vector_base();
     fn rust_main(cpu_id: usize, dtb: usize);
@@ -28,6 +32,8 @@ extern "C" {
 
 pub(crate) unsafe extern "C" fn rust_entry(cpu_id: usize, dtb: usize) {
     crate::mem::clear_bss();
+    crate::dtb::init_early(dtb);
+    crate::numa::init_early();
     crate::arch::set_exception_vector_base(exception_vector_base as usize);
     crate::cpu::init_primary(cpu_id);
     dw_apb_uart::init_early();
@@ -1,12 +1,14 @@
 use aarch64_cpu::{asm, asm::barrier, registers::*};
-use core::ptr::addr_of_mut;
+use core::ptr::{addr_of, addr_of_mut};
 use page_table_entry::aarch64::{MemAttr, A64PTE};
 use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
 
 use axconfig::TASK_STACK_SIZE;
 
-#[link_section = ".bss.stack"]
-static mut BOOT_STACK: [u8; TASK_STACK_SIZE] = [0; TASK_STACK_SIZE];
+use crate::mem::{GuardedStack, STACK_GUARD_SIZE};
+
+#[link_section = ".bss.stack.primary"]
+static mut BOOT_STACK: GuardedStack<TASK_STACK_SIZE> = GuardedStack::new();
 
 #[link_section = ".data.boot_page_table"]
 static mut BOOT_PT_L0: [A64PTE; 512] = [A64PTE::empty(); 512];
@@ -14,6 +16,53 @@ static mut BOOT_PT_L0: [A64PTE; 512] = [A64PTE::empty(); 512];
 #[link_section = ".data.boot_page_table"]
 static mut BOOT_PT_L1: [A64PTE; 512] = [A64PTE::empty(); 512];
 
+/// The reset value of `SCTLR_EL1` (RES1 bits only): MMU and caches off,
+/// little-endian, alignment checks off.
+const SCTLR_EL1_RESET: u64 = 0x30d0_0800;
+/// `CPTR_EL2` with only its RES1 bits set (`E2H` = 0), which traps nothing,
+/// including FP/SIMD accesses.
+const CPTR_EL2_RES1: u64 = 0x33ff;
+/// `SRE` and `Enable` bits of `ICC_SRE_EL2`.
+const ICC_SRE_EL2_SRE_ENABLE: u64 = 0b1001;
+
+/// Initializes the EL2 registers that control EL1, whose reset values are
+/// unknown, so that EL1 runs as if it were entered directly.
+///
+/// It is inlined into [`switch_to_el1`], which returns to its caller by `LR`.
+#[inline(always)]
+unsafe fn init_el2() {
+    // Disable EL1 timer traps and the timer offset.
+    CNTHCTL_EL2.modify(CNTHCTL_EL2::EL1PCEN::SET + CNTHCTL_EL2::EL1PCTEN::SET);
+    CNTVOFF_EL2.set(0);
+    // Set EL1 to 64bit, with E2H, TGE, stage 2 translation and all traps off.
+    HCR_EL2.write(HCR_EL2::RW::EL1IsAarch64);
+    core::arch::asm!(
+        "
+        msr     cptr_el2, {cptr}
+        msr     hstr_el2, xzr
+        msr     vttbr_el2, xzr
+        mrs     {tmp}, midr_el1         // EL1 reads of MIDR_EL1 and MPIDR_EL1
+        msr     vpidr_el2, {tmp}        // return these virtual values
+        mrs     {tmp}, mpidr_el1
+        msr     vmpidr_el2, {tmp}",
+        cptr = in(reg) CPTR_EL2_RES1,
+        tmp = out(reg) _,
+    );
+    // Allow EL1 to access the GICv3 CPU interface by system registers, if
+    // `ID_AA64PFR0_EL1.GIC` says there is one.
+    let pfr0: u64;
+    core::arch::asm!("mrs {}, id_aa64pfr0_el1", out(reg) pfr0);
+    if (pfr0 >> 24) & 0xf != 0 {
+        core::arch::asm!(
+            "
+            msr     s3_4_c12_c9_5, {sre}  // ICC_SRE_EL2
+            isb",
+            sre = in(reg) ICC_SRE_EL2_SRE_ENABLE,
+        );
+    }
+    SCTLR_EL1.set(SCTLR_EL1_RESET);
+}
+
 unsafe fn switch_to_el1() {
     SPSel.write(SPSel::SP::ELx);
     SP_EL0.set(0);
@@ -34,11 +83,7 @@ unsafe fn switch_to_el1() {
             );
             ELR_EL3.set(LR.get());
         }
-        // Disable EL1 timer traps and the timer offset.
-        CNTHCTL_EL2.modify(CNTHCTL_EL2::EL1PCEN::SET + CNTHCTL_EL2::EL1PCTEN::SET);
-        CNTVOFF_EL2.set(0);
-        // Set EL1 to 64bit.
-        HCR_EL2.write(HCR_EL2::RW::EL1IsAarch64);
+        init_el2();
         // Set the return address and exception level.
         SPSR_EL2.write(
             SPSR_EL2::M::EL1h
@@ -77,7 +122,7 @@ unsafe fn init_mmu() {
     barrier::isb(barrier::SY);
 
     // Set both TTBR0 and TTBR1
-    let root_paddr = pa!(BOOT_PT_L0.as_ptr() as usize).as_usize() as _;
+    let root_paddr = pa!(addr_of!(BOOT_PT_L0) as usize).as_usize() as _;
     TTBR0_EL1.set(root_paddr);
     TTBR1_EL1.set(root_paddr);
 
@@ -101,13 +146,13 @@ unsafe fn init_boot_page_table() {
 }
 
 /// The earliest entry point for the primary CPU.
-#[naked]
+#[unsafe(naked)]
 #[no_mangle]
 #[link_section = ".text.boot"]
 unsafe extern "C" fn _start() -> ! {
     // PC = 0x8_0000
     // X0 = dtb
-    core::arch::asm!("
+    core::arch::naked_asm!("
         mrs     x19, mpidr_el1
         and     x19, x19, #0xffffff     // get current CPU id
         mov     x20, x0                 // save DTB pointer
@@ -134,20 +179,19 @@ unsafe extern "C" fn _start() -> ! {
         init_mmu = sym init_mmu,
         enable_fp = sym enable_fp,
         boot_stack = sym BOOT_STACK,
-        boot_stack_size = const TASK_STACK_SIZE,
+        boot_stack_size = const STACK_GUARD_SIZE + TASK_STACK_SIZE,
         phys_virt_offset = const axconfig::PHYS_VIRT_OFFSET,
         entry = sym crate::platform::rust_entry,
-        options(noreturn),
     )
 }
 
 /// The earliest entry point for the secondary CPUs.
 #[cfg(feature = "smp")]
-#[naked]
+#[unsafe(naked)]
 #[no_mangle]
 #[link_section = ".text.boot"]
 unsafe extern "C" fn _start_secondary() -> ! {
-    core::arch::asm!("
+    core::arch::naked_asm!("
         mrs     x19, mpidr_el1
         and     x19, x19, #0xffffff     // get current CPU id
 
@@ -168,6 +212,5 @@ unsafe extern "C" fn _start_secondary() -> ! {
         enable_fp = sym enable_fp,
         phys_virt_offset = const axconfig::PHYS_VIRT_OFFSET,
         entry = sym crate::platform::rust_entry_secondary,
-        options(noreturn),
     )
 }
@@ -1,78 +1,63 @@
 #![allow(unused_imports)]
 
-use aarch64_cpu::registers::{CNTFRQ_EL0, CNTPCT_EL0, CNTP_CTL_EL0, CNTP_TVAL_EL0};
-use int_ratio::Ratio;
+use aarch64_cpu::registers::{CNTFRQ_EL0, CNTPCT_EL0, CNTP_CTL_EL0, CNTP_CVAL_EL0, CNTP_TVAL_EL0};
 use tock_registers::interfaces::{Readable, Writeable};
 
-static mut CNTPCT_TO_NANOS_RATIO: Ratio = Ratio::zero();
-static mut NANOS_TO_CNTPCT_RATIO: Ratio = Ratio::zero();
-/// RTC wall time offset in nanoseconds at monotonic time base.
-static mut RTC_EPOCHOFFSET_NANOS: u64 = 0;
-
 /// Returns the current clock time in hardware ticks.
 #[inline]
 pub fn current_ticks() -> u64 {
     CNTPCT_EL0.get()
 }
 
-/// Converts hardware ticks to nanoseconds.
-#[inline]
-pub fn ticks_to_nanos(ticks: u64) -> u64 {
-    unsafe { CNTPCT_TO_NANOS_RATIO.mul_trunc(ticks) }
-}
-
-/// Converts nanoseconds to hardware ticks.
-#[inline]
-pub fn nanos_to_ticks(nanos: u64) -> u64 {
-    unsafe { NANOS_TO_CNTPCT_RATIO.mul_trunc(nanos) }
-}
-
-/// Return epoch offset in nanoseconds (wall time offset to monotonic clock start).
-pub fn epochoffset_nanos() -> u64 {
-    unsafe { RTC_EPOCHOFFSET_NANOS }
-}
-
 /// Set a one-shot timer.
 ///
 /// A timer interrupt will be triggered at the specified monotonic time deadline (in nanoseconds).
+/// The deadline is compared with the counter, so any deadline can be programmed, and one in
+/// the past triggers the interrupt immediately.
 #[cfg(feature = "irq")]
 pub fn set_oneshot_timer(deadline_ns: u64) {
-    let cnptct = CNTPCT_EL0.get();
-    let cnptct_deadline = nanos_to_ticks(deadline_ns);
-    if cnptct < cnptct_deadline {
-        let interval = cnptct_deadline - cnptct;
-        debug_assert!(interval <= u32::MAX as u64);
-        CNTP_TVAL_EL0.set(interval);
-    } else {
-        CNTP_TVAL_EL0.set(0);
-    }
+    CNTP_CVAL_EL0.set(crate::time::nanos_to_ticks(deadline_ns));
 }
 
 /// Early stage initialization: stores the timer frequency.
+///
+/// The frequency is set in `CNTFRQ_EL0` by the firmware, the platform config
+/// is used only if the firmware leaves it zero.
 pub(crate) fn init_early() {
-    let freq = CNTFRQ_EL0.get();
-    unsafe {
-        CNTPCT_TO_NANOS_RATIO = Ratio::new(crate::time::NANOS_PER_SEC as u32, freq as u32);
-        NANOS_TO_CNTPCT_RATIO = CNTPCT_TO_NANOS_RATIO.inverse();
+    match CNTFRQ_EL0.get() {
+        0 => crate::time::init_timer_frequency(axconfig::TIMER_FREQUENCY as u64, "config"),
+        freq => crate::time::init_timer_frequency(freq, "CNTFRQ_EL0"),
     }
 
-    // Make sure `RTC_PADDR` is valid in platform config file.
-    #[cfg(feature = "rtc")]
-    if axconfig::RTC_PADDR != 0 {
-        use crate::mem::phys_to_virt;
-        use arm_pl031::Rtc;
-        use memory_addr::PhysAddr;
+    crate::time::init_wall_time();
+}
+
+/// Returns the PL031 RTC, if `RTC_PADDR` is given in the platform config.
+#[cfg(feature = "rtc")]
+fn pl031() -> Option<arm_pl031::Rtc> {
+    use crate::mem::phys_to_virt;
+    use memory_addr::PhysAddr;
 
-        const PL031_BASE: PhysAddr = pa!(axconfig::RTC_PADDR);
+    const PL031_BASE: PhysAddr = pa!(axconfig::RTC_PADDR);
+    if axconfig::RTC_PADDR == 0 {
+        return None;
+    }
+    let base_vaddr = phys_to_virt(PL031_BASE).as_usize();
+    Some(unsafe { arm_pl031::Rtc::new(base_vaddr as _) })
+}
 
-        let rtc = unsafe { Rtc::new(phys_to_virt(PL031_BASE).as_usize() as _) };
-        // Get the current time in microseconds since the epoch (1970-01-01) from the aarch64 pl031 RTC.
-        // Subtract the timer ticks to get the actual time when ArceOS was booted.
-        let epoch_time_nanos = rtc.get_unix_timestamp() as u64 * 1_000_000_000;
+/// Reads the RTC in seconds since the epoch (1970-01-01).
+#[cfg(feature = "rtc")]
+pub(crate) fn read_rtc() -> Option<u64> {
+    pl031().map(|rtc| rtc.get_unix_timestamp() as u64)
+}
 
-        unsafe {
-            RTC_EPOCHOFFSET_NANOS = epoch_time_nanos - ticks_to_nanos(current_ticks());
-        }
+/// Writes the RTC in seconds since the epoch (1970-01-01).
+#[cfg(feature = "rtc")]
+pub(crate) fn write_rtc(secs: u64) {
+    if let Some(mut rtc) = pl031() {
+        // The PL031 counter is 32-bit.
+        rtc.set_unix_timestamp(secs.min(u32::MAX as u64) as u32);
     }
 }
 
@@ -1,20 +1,66 @@
+//! The GIC (Generic Interrupt Controller), GICv2 or GICv3.
+//!
+//! The version is chosen at boot by the `compatible` of the interrupt
+//! controller in the device tree: GICv3 for `arm,gic-v3`, whose distributor
+//! and redistributors are at its `reg`, and GICv2 at `gicd-paddr` and
+//! `gicc-paddr` of the platform config otherwise.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use crate::{irq::IrqHandler, mem::phys_to_virt};
 use arm_gicv2::{translate_irq, GicCpuInterface, GicDistributor, InterruptType};
 use kspin::SpinNoIrq;
+use lazyinit::LazyInit;
 use memory_addr::PhysAddr;
 
+use super::gicv3;
+
 /// The maximum number of IRQs.
 pub const MAX_IRQ_COUNT: usize = 1024;
 
 /// The timer IRQ number.
 pub const TIMER_IRQ_NUM: usize = translate_irq(14, InterruptType::PPI).unwrap();
 
+/// The IRQ number of the inter-processor interrupts sent by [`send_ipi`],
+/// the SGI 1.
+pub const IPI_IRQ_NUM: usize = 1;
+
+/// The SGI of the TLB shootdown IPIs, see [`crate::tlb`].
+const TLB_IPI_IRQ_NUM: usize = 2;
+
 /// The UART IRQ number.
 pub const UART_IRQ_NUM: usize = translate_irq(axconfig::UART_IRQ, InterruptType::SPI).unwrap();
 
 const GICD_BASE: PhysAddr = pa!(axconfig::GICD_PADDR);
 const GICC_BASE: PhysAddr = pa!(axconfig::GICC_PADDR);
 
+/// Shared peripheral interrupts are numbered from 32.
+const SPI_START: usize = 32;
+
+/// Offsets of the GICv2 GICD registers, not covered by `arm_gicv2`.
+const GICD_ITARGETSR: usize = 0x800;
+const GICD_ICFGR: usize = 0xc00;
+const GICD_SGIR: usize = 0xf00;
+
+/// Offsets of the GICv2m MSI frame registers.
+const V2M_MSI_TYPER: usize = 0x008;
+const V2M_MSI_SETSPI_NS: usize = 0x040;
+
+/// The GICv2m MSI frame, which raises the SPI whose number is written to it.
+struct V2mFrame {
+    paddr: PhysAddr,
+    base_spi: usize,
+    num_spis: usize,
+}
+
+static V2M_FRAME: LazyInit<Option<V2mFrame>> = LazyInit::new();
+
+/// Bitmap of the allocated SPIs of the GICv2m frame, from `base_spi`.
+static V2M_SPIS: SpinNoIrq<u128> = SpinNoIrq::new(0);
+
+/// Whether the GIC is a GICv3, decided by [`init_primary`].
+static IS_GICV3: AtomicBool = AtomicBool::new(false);
+
 static GICD: SpinNoIrq<GicDistributor> =
     SpinNoIrq::new(GicDistributor::new(phys_to_virt(GICD_BASE).as_mut_ptr()));
 
@@ -22,9 +68,15 @@ static GICD: SpinNoIrq<GicDistributor> =
 static GICC: GicCpuInterface = GicCpuInterface::new(phys_to_virt(GICC_BASE).as_mut_ptr());
 
 /// Enables or disables the given IRQ.
+///
+/// SGIs and PPIs are enabled or disabled on the current CPU.
 pub fn set_enable(irq_num: usize, enabled: bool) {
     trace!("GICD set enable: {} {}", irq_num, enabled);
-    GICD.lock().set_enable(irq_num as _, enabled);
+    if is_gicv3() {
+        gicv3::set_enable(irq_num, enabled);
+    } else {
+        GICD.lock().set_enable(irq_num as _, enabled);
+    }
 }
 
 /// Registers an IRQ handler for the given IRQ.
@@ -36,24 +88,227 @@ pub fn register_handler(irq_num: usize, handler: IrqHandler) -> bool {
     crate::irq::register_handler_common(irq_num, handler)
 }
 
+/// Routes the given SPI to the CPU `cpu_id`.
+///
+/// On GICv2, the CPU interface number is taken to be the CPU ID, and only
+/// CPUs 0 to 7 can be targeted.
+pub fn set_affinity(irq_num: usize, cpu_id: usize) -> bool {
+    if is_gicv3() {
+        return gicv3::set_affinity(irq_num, cpu_id);
+    }
+    if !(SPI_START..MAX_IRQ_COUNT).contains(&irq_num) || cpu_id >= 8 {
+        return false;
+    }
+    let _gicd = GICD.lock();
+    let target = phys_to_virt(GICD_BASE + GICD_ITARGETSR + irq_num).as_mut_ptr();
+    unsafe { target.write_volatile(1 << cpu_id) };
+    true
+}
+
+/// Routes the given IRQ to the CPUs in `cpu_mask`, where bit `i` stands for
+/// the CPU `i`.
+///
+/// Only a single CPU can be targeted, see [`set_affinity`].
+pub fn set_affinity_mask(irq_num: usize, cpu_mask: usize) -> bool {
+    cpu_mask.count_ones() == 1 && set_affinity(irq_num, cpu_mask.trailing_zeros() as usize)
+}
+
+/// Sets the priority of the given IRQ.
+///
+/// It is not supported yet, so it always returns `false`.
+pub fn set_priority(_irq_num: usize, _priority: u8) -> bool {
+    false
+}
+
+/// Sets the priority threshold of the current CPU.
+///
+/// It is not supported yet, so it always returns `false`.
+pub fn set_threshold(_threshold: u8) -> bool {
+    false
+}
+
+/// Returns the IRQ number of the GSI (global system interrupt), which is
+/// the same number on this platform.
+pub fn gsi_irq(gsi: usize) -> Option<usize> {
+    Some(gsi)
+}
+
+/// Routes the GSI to the given IRQ.
+///
+/// GSIs are routed as configured by the firmware, so it always returns
+/// `false`.
+pub fn route_gsi(
+    _gsi: usize,
+    _irq_num: usize,
+    _cpu_id: usize,
+    _trigger: crate::irq::TriggerMode,
+    _polarity: crate::irq::Polarity,
+) -> bool {
+    false
+}
+
+/// Allocates `count` contiguous SPIs of the GICv2m frame for message-signaled
+/// interrupts, and returns the first one.
+///
+/// The SPIs are configured as edge-triggered, and target the current CPU.
+/// Returns `None` if there is no free block or no GICv2m frame.
+pub fn alloc_msi(count: usize) -> Option<usize> {
+    let frame = V2M_FRAME.as_ref()?;
+    let idx = crate::irq::alloc_msi_block(&mut V2M_SPIS.lock(), frame.num_spis, count)?;
+    let cpu_id = crate::cpu::this_cpu_id();
+    for irq_num in frame.base_spi + idx..frame.base_spi + idx + count {
+        set_edge_triggered(irq_num);
+        set_affinity(irq_num, cpu_id);
+    }
+    Some(frame.base_spi + idx)
+}
+
+/// Frees `count` SPIs from `irq_num` allocated by [`alloc_msi`].
+pub fn free_msi(irq_num: usize, count: usize) {
+    if let Some(frame) = V2M_FRAME.as_ref() {
+        if let Some(idx) = irq_num.checked_sub(frame.base_spi) {
+            crate::irq::free_msi_block(&mut V2M_SPIS.lock(), frame.num_spis, idx, count);
+        }
+    }
+}
+
+/// Returns the message a device writes to raise the MSI `irq_num`: the SPI
+/// number written to `MSI_SETSPI_NS` of the GICv2m frame.
+pub fn msi_message(irq_num: usize) -> crate::irq::MsiMessage {
+    let frame = V2M_FRAME.as_ref().expect("no GICv2m frame");
+    crate::irq::MsiMessage {
+        address: (frame.paddr + V2M_MSI_SETSPI_NS).as_usize() as u64,
+        data: irq_num as u32,
+    }
+}
+
+/// Sends the software-generated interrupt `sgi_num` (0 to 15) to the CPU
+/// `cpu_id`, e.g., as an inter-processor interrupt.
+///
+/// On GICv3, it is routed by the affinity of the CPU, where CPUs with `Aff0`
+/// from 0 to 15 can be targeted.
+pub fn send_sgi(sgi_num: usize, cpu_id: usize) {
+    if is_gicv3() {
+        gicv3::send_sgi(sgi_num, cpu_id);
+    } else if cpu_id < 8 {
+        let sgir = phys_to_virt(GICD_BASE + GICD_SGIR).as_mut_ptr() as *mut u32;
+        let val = 1 << (16 + cpu_id) | (sgi_num as u32 & 0xf);
+        unsafe {
+            core::arch::asm!("dsb ishst");
+            sgir.write_volatile(val);
+        }
+    }
+}
+
+/// Sends an inter-processor interrupt ([`IPI_IRQ_NUM`]) to the CPU `cpu_id`.
+pub fn send_ipi(cpu_id: usize) {
+    send_sgi(IPI_IRQ_NUM, cpu_id);
+}
+
+/// Sends a TLB shootdown IPI to the CPU `cpu_id`, see [`crate::tlb`].
+#[cfg(feature = "smp")]
+pub(crate) fn send_tlb_ipi(cpu_id: usize) {
+    send_sgi(TLB_IPI_IRQ_NUM, cpu_id);
+}
+
+/// Handles the acknowledged interrupt `irq_num`, where the TLB shootdown IPI
+/// is handled here rather than by a registered handler.
+pub(super) fn handle_irq(irq_num: usize) {
+    if irq_num == TLB_IPI_IRQ_NUM {
+        crate::tlb::handle_ipi();
+    } else {
+        crate::irq::dispatch_irq_common(irq_num);
+    }
+}
+
+fn is_gicv3() -> bool {
+    IS_GICV3.load(Ordering::Relaxed)
+}
+
+fn set_edge_triggered(irq_num: usize) {
+    if is_gicv3() {
+        return gicv3::set_edge_triggered(irq_num);
+    }
+    let _gicd = GICD.lock();
+    let cfg = phys_to_virt(GICD_BASE + GICD_ICFGR + irq_num / 16 * 4).as_mut_ptr() as *mut u32;
+    unsafe { cfg.write_volatile(cfg.read_volatile() | 0b10 << (irq_num % 16 * 2)) };
+}
+
+/// Finds the GICv2m frame in the device tree, which must be in the MMIO
+/// regions of the platform config.
+fn probe_v2m() -> Option<V2mFrame> {
+    let node = crate::fdt::nodes_compatible("arm,gic-v2m-frame").next()?;
+    let (paddr, _) = node.reg().next()?;
+    let typer = phys_to_virt(pa!(paddr + V2M_MSI_TYPER)).as_ptr() as *const u32;
+    let typer = unsafe { typer.read_volatile() };
+    let base_spi = node
+        .property_u32("arm,msi-base-spi")
+        .unwrap_or(typer >> 16 & 0x3ff);
+    let num_spis = node
+        .property_u32("arm,msi-num-spis")
+        .unwrap_or(typer & 0x3ff);
+    Some(V2mFrame {
+        paddr: pa!(paddr),
+        base_spi: base_spi as usize,
+        num_spis: num_spis as usize,
+    })
+}
+
 /// Dispatches the IRQ.
 ///
 /// This function is called by the common interrupt handler. It looks
 /// up in the IRQ handler table and calls the corresponding handler. If
 /// necessary, it also acknowledges the interrupt controller after handling.
 pub fn dispatch_irq(_unused: usize) {
-    GICC.handle_irq(|irq_num| crate::irq::dispatch_irq_common(irq_num as _));
+    if is_gicv3() {
+        gicv3::dispatch_irq();
+    } else {
+        GICC.handle_irq(|irq_num| handle_irq(irq_num as _));
+    }
+}
+
+/// Finds a GICv3 in the device tree, returns the physical addresses of its
+/// distributor and its first redistributor region.
+fn probe_gicv3() -> Option<(PhysAddr, PhysAddr)> {
+    let node = crate::fdt::nodes_compatible("arm,gic-v3").next()?;
+    let mut reg = node.reg();
+    let (gicd, _) = reg.next()?;
+    let (gicr, _) = reg.next()?;
+    Some((pa!(gicd), pa!(gicr)))
 }
 
-/// Initializes GICD, GICC on the primary CPU.
+/// Initializes the distributor and the CPU interface of the primary CPU, and
+/// the redistributor on GICv3.
 pub(crate) fn init_primary() {
-    info!("Initialize GICv2...");
-    GICD.lock().init();
-    GICC.init();
+    if let Some((gicd, gicr)) = probe_gicv3() {
+        info!("Initialize GICv3...");
+        IS_GICV3.store(true, Ordering::Relaxed);
+        gicv3::init_primary(gicd, gicr);
+    } else {
+        info!("Initialize GICv2...");
+        GICD.lock().init();
+        GICC.init();
+    }
+    set_enable(IPI_IRQ_NUM, true);
+    set_enable(TLB_IPI_IRQ_NUM, true);
+    V2M_FRAME.init_once(probe_v2m());
+    if let Some(frame) = V2M_FRAME.as_ref() {
+        info!(
+            "GICv2m: {} SPIs from {} for MSIs",
+            frame.num_spis, frame.base_spi
+        );
+    }
 }
 
-/// Initializes GICC on secondary CPUs.
+/// Initializes the CPU interface on secondary CPUs, and the redistributor on
+/// GICv3.
 #[cfg(feature = "smp")]
 pub(crate) fn init_secondary() {
-    GICC.init();
+    if is_gicv3() {
+        gicv3::init_secondary();
+    } else {
+        GICC.init();
+    }
+    set_enable(IPI_IRQ_NUM, true);
+    set_enable(TLB_IPI_IRQ_NUM, true);
 }
@@ -0,0 +1,303 @@
+//! GICv3 driver: the distributor and the redistributors accessed by MMIO,
+//! and the CPU interface by the `ICC_*` system registers.
+//!
+//! Affinity routing is enabled, so SPIs are routed by `GICD_IROUTER<n>`, and
+//! SGIs and PPIs are configured in the redistributor of each CPU. All
+//! interrupts are in the non-secure group 1.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use kspin::SpinNoIrq;
+use memory_addr::PhysAddr;
+
+use crate::mem::phys_to_virt;
+
+const GICD_CTLR: usize = 0x0000;
+const GICD_TYPER: usize = 0x0004;
+const GICD_IGROUPR: usize = 0x0080;
+const GICD_ISENABLER: usize = 0x0100;
+const GICD_ICENABLER: usize = 0x0180;
+const GICD_IPRIORITYR: usize = 0x0400;
+const GICD_ICFGR: usize = 0x0c00;
+const GICD_IROUTER: usize = 0x6000;
+
+/// `EnableGrp1NS` (or `EnableGrp1` with a single security state).
+const GICD_CTLR_ENABLE_G1: u32 = 1 << 1;
+/// `EnableGrp1` if the GIC has a single security state, ignored otherwise.
+const GICD_CTLR_ENABLE_G0: u32 = 1 << 0;
+const GICD_CTLR_ARE_NS: u32 = 1 << 4;
+const GICD_CTLR_RWP: u32 = 1 << 31;
+
+// Registers in the `RD_base` frame of a redistributor.
+const GICR_CTLR: usize = 0x0000;
+const GICR_TYPER: usize = 0x0008;
+const GICR_WAKER: usize = 0x0014;
+
+const GICR_CTLR_RWP: u32 = 1 << 3;
+const GICR_TYPER_VLPIS: u64 = 1 << 1;
+const GICR_TYPER_LAST: u64 = 1 << 4;
+const GICR_WAKER_PROCESSOR_SLEEP: u32 = 1 << 1;
+const GICR_WAKER_CHILDREN_ASLEEP: u32 = 1 << 2;
+
+/// The `SGI_base` frame follows the `RD_base` frame.
+const GICR_SGI_BASE: usize = 0x1_0000;
+/// Size of the `RD_base` and `SGI_base` frames of a redistributor, followed
+/// by two more frames if it supports virtual LPIs.
+const GICR_FRAMES_SIZE: usize = 0x2_0000;
+
+// Registers in the `SGI_base` frame.
+const GICR_IGROUPR0: usize = 0x0080;
+const GICR_ISENABLER0: usize = 0x0100;
+const GICR_ICENABLER0: usize = 0x0180;
+const GICR_IPRIORITYR: usize = 0x0400;
+
+/// `SRE` bit of `ICC_SRE_EL1`, to access the CPU interface by system
+/// registers.
+const ICC_SRE_EL1_SRE: u64 = 1 << 0;
+
+/// Priority of all interrupts, which are above the lowest priority mask.
+const DEFAULT_PRIORITY: u8 = 0xa0;
+/// INTIDs from 1020 to 1023 are special, e.g., 1023 for spurious interrupts.
+const SPECIAL_INTID_START: usize = 1020;
+
+/// Virtual address of the distributor.
+static GICD_BASE: AtomicUsize = AtomicUsize::new(0);
+/// Virtual addresses of the `RD_base` frames of the CPUs.
+static GICR_BASES: [AtomicUsize; axconfig::SMP] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+    [ZERO; axconfig::SMP]
+};
+/// Virtual address of the first redistributor.
+static GICR_REGION: AtomicUsize = AtomicUsize::new(0);
+/// Serializes the read-modify-write of the distributor registers.
+static GICD_LOCK: SpinNoIrq<()> = SpinNoIrq::new(());
+
+macro_rules! read_sysreg {
+    ($reg:literal) => {{
+        let val: u64;
+        unsafe { asm!(concat!("mrs {}, ", $reg), out(reg) val) };
+        val
+    }};
+}
+
+macro_rules! write_sysreg {
+    ($reg:literal, $val:expr) => {
+        unsafe { asm!(concat!("msr ", $reg, ", {}"), "isb", in(reg) $val as u64) }
+    };
+}
+
+fn reg32(base: usize, offset: usize) -> *mut u32 {
+    (base + offset) as *mut u32
+}
+
+fn reg64(base: usize, offset: usize) -> *mut u64 {
+    (base + offset) as *mut u64
+}
+
+fn gicd() -> usize {
+    GICD_BASE.load(Ordering::Relaxed)
+}
+
+/// Returns the `SGI_base` frame of the redistributor of the current CPU.
+fn this_sgi_base() -> usize {
+    GICR_BASES[crate::cpu::this_cpu_id()].load(Ordering::Relaxed) + GICR_SGI_BASE
+}
+
+/// Waits for the register writes of the distributor to take effect.
+fn wait_gicd_rwp() {
+    while unsafe { reg32(gicd(), GICD_CTLR).read_volatile() } & GICD_CTLR_RWP != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Waits for the register writes of the redistributor `rd_base` to take
+/// effect.
+fn wait_gicr_rwp(rd_base: usize) {
+    while unsafe { reg32(rd_base, GICR_CTLR).read_volatile() } & GICR_CTLR_RWP != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Returns the affinity of the CPU `cpu_id` in the format of `MPIDR_EL1`,
+/// whose low 24 bits are the CPU ID.
+const fn cpu_affinity(cpu_id: usize) -> u64 {
+    cpu_id as u64 & 0xff_ffff
+}
+
+/// Initializes the distributor, and the redistributor and the CPU interface
+/// of the primary CPU.
+pub(super) fn init_primary(gicd_paddr: PhysAddr, gicr_paddr: PhysAddr) {
+    let base = phys_to_virt(gicd_paddr).as_usize();
+    GICD_BASE.store(base, Ordering::Relaxed);
+    GICR_REGION.store(phys_to_virt(gicr_paddr).as_usize(), Ordering::Relaxed);
+
+    let typer = unsafe { reg32(base, GICD_TYPER).read_volatile() };
+    let num_irqs = (32 * ((typer as usize & 0x1f) + 1)).min(SPECIAL_INTID_START);
+    unsafe {
+        reg32(base, GICD_CTLR).write_volatile(0);
+        wait_gicd_rwp();
+        // SPIs are all in group 1, disabled, level-sensitive, and routed to
+        // the primary CPU.
+        for irq in (32..num_irqs).step_by(32) {
+            reg32(base, GICD_IGROUPR + irq / 8).write_volatile(u32::MAX);
+            reg32(base, GICD_ICENABLER + irq / 8).write_volatile(u32::MAX);
+        }
+        for irq in (32..num_irqs).step_by(16) {
+            reg32(base, GICD_ICFGR + irq / 4).write_volatile(0);
+        }
+        let affinity = cpu_affinity(crate::cpu::this_cpu_id());
+        for irq in 32..num_irqs {
+            (base as *mut u8)
+                .add(GICD_IPRIORITYR + irq)
+                .write_volatile(DEFAULT_PRIORITY);
+            reg64(base, GICD_IROUTER + irq * 8).write_volatile(affinity);
+        }
+        wait_gicd_rwp();
+        let ctlr = GICD_CTLR_ARE_NS | GICD_CTLR_ENABLE_G1 | GICD_CTLR_ENABLE_G0;
+        reg32(base, GICD_CTLR).write_volatile(ctlr);
+        wait_gicd_rwp();
+    }
+    init_cpu();
+}
+
+/// Initializes the redistributor and the CPU interface of a secondary CPU.
+#[cfg(feature = "smp")]
+pub(super) fn init_secondary() {
+    init_cpu();
+}
+
+/// Finds the redistributor whose affinity in `GICR_TYPER` is of the current
+/// CPU, by walking the redistributors until the last one.
+fn find_redistributor() -> Option<usize> {
+    let mpidr = read_sysreg!("mpidr_el1");
+    // Aff3.Aff2.Aff1.Aff0, as in bits [63:32] of `GICR_TYPER`.
+    let affinity = (mpidr & 0xff_ffff) | (mpidr >> 32 & 0xff) << 24;
+    let mut rd_base = GICR_REGION.load(Ordering::Relaxed);
+    loop {
+        let typer = unsafe { reg64(rd_base, GICR_TYPER).read_volatile() };
+        if typer >> 32 == affinity {
+            return Some(rd_base);
+        }
+        if typer & GICR_TYPER_LAST != 0 {
+            return None;
+        }
+        rd_base += if typer & GICR_TYPER_VLPIS != 0 {
+            GICR_FRAMES_SIZE * 2
+        } else {
+            GICR_FRAMES_SIZE
+        };
+    }
+}
+
+fn init_cpu() {
+    let cpu_id = crate::cpu::this_cpu_id();
+    let Some(rd_base) = find_redistributor() else {
+        panic!("GICv3: no redistributor for CPU {}", cpu_id);
+    };
+    GICR_BASES[cpu_id].store(rd_base, Ordering::Relaxed);
+    unsafe {
+        // Wake up the redistributor.
+        let waker = reg32(rd_base, GICR_WAKER);
+        waker.write_volatile(waker.read_volatile() & !GICR_WAKER_PROCESSOR_SLEEP);
+        while waker.read_volatile() & GICR_WAKER_CHILDREN_ASLEEP != 0 {
+            core::hint::spin_loop();
+        }
+        // SGIs are enabled, and PPIs are disabled until registered.
+        let sgi_base = rd_base + GICR_SGI_BASE;
+        reg32(sgi_base, GICR_IGROUPR0).write_volatile(u32::MAX);
+        reg32(sgi_base, GICR_ICENABLER0).write_volatile(0xffff_0000);
+        reg32(sgi_base, GICR_ISENABLER0).write_volatile(0x0000_ffff);
+        for irq in 0..32 {
+            (sgi_base as *mut u8)
+                .add(GICR_IPRIORITYR + irq)
+                .write_volatile(DEFAULT_PRIORITY);
+        }
+        wait_gicr_rwp(rd_base);
+    }
+
+    // ICC_SRE_EL1
+    let sre = read_sysreg!("s3_0_c12_c12_5");
+    write_sysreg!("s3_0_c12_c12_5", sre | ICC_SRE_EL1_SRE);
+    // ICC_PMR_EL1: accept all priorities.
+    write_sysreg!("s3_0_c4_c6_0", 0xff);
+    // ICC_BPR1_EL1: no preemption grouping.
+    write_sysreg!("s3_0_c12_c12_3", 0);
+    // ICC_CTLR_EL1: EOImode 0, EOI both drops the priority and deactivates.
+    write_sysreg!("s3_0_c12_c12_4", 0);
+    // ICC_IGRPEN1_EL1: enable group 1 interrupts.
+    write_sysreg!("s3_0_c12_c12_7", 1);
+}
+
+/// Enables or disables the given IRQ. SGIs and PPIs are of the current CPU.
+pub(super) fn set_enable(irq_num: usize, enabled: bool) {
+    let (base, offset) = if irq_num < 32 {
+        let offset = if enabled {
+            GICR_ISENABLER0
+        } else {
+            GICR_ICENABLER0
+        };
+        (this_sgi_base(), offset)
+    } else if irq_num < SPECIAL_INTID_START {
+        let offset = if enabled {
+            GICD_ISENABLER
+        } else {
+            GICD_ICENABLER
+        };
+        (gicd(), offset + irq_num / 32 * 4)
+    } else {
+        return;
+    };
+    unsafe { reg32(base, offset).write_volatile(1 << (irq_num % 32)) };
+    if !enabled {
+        if irq_num < 32 {
+            wait_gicr_rwp(base - GICR_SGI_BASE);
+        } else {
+            wait_gicd_rwp();
+        }
+    }
+}
+
+/// Routes the given SPI to the CPU `cpu_id`.
+pub(super) fn set_affinity(irq_num: usize, cpu_id: usize) -> bool {
+    if !(32..SPECIAL_INTID_START).contains(&irq_num) || cpu_id >= axconfig::SMP {
+        return false;
+    }
+    let router = reg64(gicd(), GICD_IROUTER + irq_num * 8);
+    unsafe { router.write_volatile(cpu_affinity(cpu_id)) };
+    true
+}
+
+/// Configures the given SPI as edge-triggered.
+pub(super) fn set_edge_triggered(irq_num: usize) {
+    let _lock = GICD_LOCK.lock();
+    let cfg = reg32(gicd(), GICD_ICFGR + irq_num / 16 * 4);
+    unsafe { cfg.write_volatile(cfg.read_volatile() | 0b10 << (irq_num % 16 * 2)) };
+}
+
+/// Sends the SGI `sgi_num` to the CPU `cpu_id`, by its affinity.
+pub(super) fn send_sgi(sgi_num: usize, cpu_id: usize) {
+    let affinity = cpu_affinity(cpu_id);
+    let (aff0, aff1, aff2) = (affinity & 0xff, affinity >> 8 & 0xff, affinity >> 16 & 0xff);
+    // The target list is a bitmap of Aff0 values, from 0 to 15.
+    let val = (aff2 << 32) | (aff1 << 16) | ((sgi_num as u64 & 0xf) << 24) | (1 << (aff0 & 0xf));
+    // Make the memory writes visible to the target CPU first.
+    unsafe { asm!("dsb ishst") };
+    // ICC_SGI1R_EL1
+    write_sysreg!("s3_0_c12_c11_5", val);
+}
+
+/// Acknowledges the highest priority pending interrupt, handles it, and
+/// signals its end.
+pub(super) fn dispatch_irq() {
+    // ICC_IAR1_EL1
+    let iar = read_sysreg!("s3_0_c12_c12_0");
+    let intid = iar as usize & 0xff_ffff;
+    if (SPECIAL_INTID_START..1024).contains(&intid) {
+        return;
+    }
+    super::gic::handle_irq(intid);
+    // ICC_EOIR1_EL1
+    write_sysreg!("s3_0_c12_c12_1", iar);
+}
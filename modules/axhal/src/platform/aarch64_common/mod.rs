@@ -3,9 +3,13 @@ mod boot;
 pub mod generic_timer;
 #[cfg(not(platform_family = "aarch64-raspi"))]
 pub mod psci;
+#[cfg(not(platform_family = "aarch64-raspi"))]
+pub mod sbsa_wdt;
 
 #[cfg(feature = "irq")]
 pub mod gic;
+#[cfg(feature = "irq")]
+mod gicv3;
 
 #[cfg(not(platform_family = "aarch64-bsta1000b"))]
 pub mod pl011;
@@ -8,6 +8,14 @@ use crate::mem::phys_to_virt;
 
 const UART_BASE: PhysAddr = pa!(axconfig::UART_PADDR);
 
+/// Offset of the interrupt mask set/clear register.
+#[cfg(feature = "irq")]
+const UART_IMSC: usize = 0x38;
+#[cfg(feature = "irq")]
+const IMSC_RXIM: u32 = 1 << 4;
+#[cfg(feature = "irq")]
+const IMSC_RTIM: u32 = 1 << 6;
+
 static UART: SpinNoIrq<Pl011Uart> =
     SpinNoIrq::new(Pl011Uart::new(phys_to_virt(UART_BASE).as_mut_ptr()));
 
@@ -55,19 +63,24 @@ pub fn init_early() {
     UART.lock().init();
 }
 
-/// Set UART IRQ Enable
+/// Enables the receive interrupt, so that the input is buffered by
+/// [`handle`].
 pub fn init() {
     #[cfg(feature = "irq")]
-    crate::irq::set_enable(crate::platform::irq::UART_IRQ_NUM, true);
+    {
+        // Also interrupt on receive timeouts, so that input shorter than the
+        // FIFO trigger level is not held.
+        let imsc = phys_to_virt(UART_BASE + UART_IMSC).as_mut_ptr() as *mut u32;
+        unsafe { imsc.write_volatile(IMSC_RXIM | IMSC_RTIM) };
+        crate::irq::register_handler(crate::platform::irq::UART_IRQ_NUM, handle);
+        crate::console::enable_rx_irq();
+    }
 }
 
 /// UART IRQ Handler
 pub fn handle() {
-    let is_receive_interrupt = UART.lock().is_receive_interrupt();
     UART.lock().ack_interrupts();
-    if is_receive_interrupt {
-        while let Some(c) = getchar() {
-            putchar(c);
-        }
-    }
+    // Drain the FIFO on both receive and receive timeout interrupts.
+    #[cfg(feature = "irq")]
+    crate::console::receive(core::iter::from_fn(getchar));
 }
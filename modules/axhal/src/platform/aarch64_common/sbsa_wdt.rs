@@ -0,0 +1,48 @@
+//! SBSA generic watchdog.
+//!
+//! The watchdog signal WS0 is raised when the offset has elapsed after the
+//! last refresh, and the system is reset by WS1 when it elapses once more, so
+//! the offset is half of the timeout. The counter is the system counter.
+
+use core::time::Duration;
+
+use aarch64_cpu::registers::CNTFRQ_EL0;
+use tock_registers::interfaces::Readable;
+
+use crate::mem::phys_to_virt;
+
+/// Watchdog control and status register, in the control frame.
+const WCS: usize = 0x000;
+/// Watchdog offset register, in the control frame.
+const WOR: usize = 0x008;
+/// Watchdog refresh register, in the refresh frame.
+const WRR: usize = 0x000;
+const WCS_EN: u32 = 1 << 0;
+
+fn reg(frame_paddr: usize, offset: usize) -> *mut u32 {
+    phys_to_virt(pa!(frame_paddr + offset)).as_mut_ptr() as *mut u32
+}
+
+/// Starts the watchdog. Returns `false` if there is no SBSA watchdog.
+pub(crate) fn start(timeout: Duration) -> bool {
+    if axconfig::SBSA_WDT_CTRL_PADDR == 0 || axconfig::SBSA_WDT_REFRESH_PADDR == 0 {
+        return false;
+    }
+    let offset = (timeout / 2).as_nanos() * CNTFRQ_EL0.get() as u128 / 1_000_000_000;
+    unsafe {
+        reg(axconfig::SBSA_WDT_CTRL_PADDR, WOR).write_volatile(offset.min(u32::MAX as _) as u32);
+        feed();
+        reg(axconfig::SBSA_WDT_CTRL_PADDR, WCS).write_volatile(WCS_EN);
+    }
+    true
+}
+
+/// Refreshes the watchdog, which also clears the raised WS0.
+pub(crate) fn feed() {
+    unsafe { reg(axconfig::SBSA_WDT_REFRESH_PADDR, WRR).write_volatile(0) };
+}
+
+/// Disables the watchdog.
+pub(crate) fn stop() {
+    unsafe { reg(axconfig::SBSA_WDT_CTRL_PADDR, WCS).write_volatile(0) };
+}
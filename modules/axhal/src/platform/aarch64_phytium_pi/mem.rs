@@ -1,6 +1,9 @@
 use crate::mem::*;
 use page_table_entry::{aarch64::A64PTE, GenericPTE, MappingFlags};
 
+/// The RAM mapped by the boot page table, see [`init_boot_page_table`].
+pub(crate) const BOOT_MAPPED_MEMORY: (usize, usize) = (0x8000_0000, 0xc000_0000);
+
 /// Returns platform-specific memory regions.
 pub(crate) fn platform_regions() -> impl Iterator<Item = MemRegion> {
     crate::mem::default_free_regions().chain(crate::mem::default_mmio_regions())
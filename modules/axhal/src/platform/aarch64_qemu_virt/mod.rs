@@ -16,8 +16,14 @@ pub mod time {
     pub use crate::platform::aarch64_common::generic_timer::*;
 }
 
+pub(crate) mod watchdog {
+    pub(crate) use crate::platform::aarch64_common::sbsa_wdt::*;
+}
+
 pub mod misc {
-    pub use crate::platform::aarch64_common::psci::system_off as terminate;
+    pub use crate::platform::aarch64_common::psci::{
+        system_off as terminate, system_reset as reboot,
+    };
 }
 
 extern "C" {
@@ -29,6 +35,8 @@ extern "C" {
 
 pub(crate) unsafe extern "C" fn rust_entry(cpu_id: usize, dtb: usize) {
     crate::mem::clear_bss();
+    crate::dtb::init_early(dtb);
+    crate::numa::init_early();
     crate::arch::set_exception_vector_base(exception_vector_base as usize);
     crate::arch::write_page_table_root0(0.into()); // disable low address access
     crate::cpu::init_primary(cpu_id);
@@ -8,3 +8,13 @@ pub fn start_secondary_cpu(cpu_id: usize, stack_top: PhysAddr) {
     let entry = virt_to_phys(va!(_start_secondary as usize));
     crate::platform::aarch64_common::psci::cpu_on(cpu_id, entry.as_usize(), stack_top.as_usize());
 }
+
+/// Powers off the current CPU, which can be started again by
+/// [`start_secondary_cpu`].
+pub(crate) fn cpu_off() -> ! {
+    crate::platform::aarch64_common::psci::cpu_off();
+    warn!("It should be powered off!");
+    loop {
+        crate::arch::halt();
+    }
+}
@@ -1,15 +1,44 @@
 use crate::mem::{MemRegion, MemRegionFlags};
 use page_table_entry::{aarch64::A64PTE, GenericPTE, MappingFlags};
 
+/// Memory of the VideoCore at the top of the first GiB. Its size is set by
+/// `gpu_mem` in `config.txt`, 76 MiB by default.
+const VC_MEM: (usize, usize) = (0x3b40_0000, 0x4000_0000);
+/// Start of the peripherals in the low peripheral mode of the BCM2711, the
+/// RAM below it is usable in the fourth GiB.
+const PERIPHERAL_BASE: usize = 0xfc00_0000;
+
+/// The RAM mapped by the boot page table, see [`init_boot_page_table`].
+pub(crate) const BOOT_MAPPED_MEMORY: (usize, usize) = (0, PERIPHERAL_BASE);
+
+/// Boot page table of the fourth GiB, with RAM and peripherals.
+#[repr(C, align(4096))]
+struct BootPageTable([A64PTE; 512]);
+
+#[link_section = ".data.boot_page_table"]
+static mut BOOT_PT_L2: BootPageTable = BootPageTable([A64PTE::empty(); 512]);
+
 /// Returns platform-specific memory regions.
 pub(crate) fn platform_regions() -> impl Iterator<Item = MemRegion> {
-    core::iter::once(MemRegion {
-        paddr: 0x0.into(),
-        size: 0x1000,
-        flags: MemRegionFlags::RESERVED | MemRegionFlags::READ | MemRegionFlags::WRITE,
-        name: "spintable",
-    })
-    .chain(crate::mem::default_free_regions())
+    [
+        MemRegion {
+            paddr: 0x0.into(),
+            size: 0x1000,
+            flags: MemRegionFlags::RESERVED | MemRegionFlags::READ | MemRegionFlags::WRITE,
+            name: "spintable",
+        },
+        MemRegion {
+            paddr: VC_MEM.0.into(),
+            size: VC_MEM.1 - VC_MEM.0,
+            flags: MemRegionFlags::RESERVED,
+            name: "videocore",
+        },
+    ]
+    .into_iter()
+    .chain(crate::mem::default_free_regions_without(
+        pa!(VC_MEM.0),
+        pa!(VC_MEM.1),
+    ))
     .chain(crate::mem::default_mmio_regions())
 }
 
@@ -19,9 +48,10 @@ pub(crate) unsafe fn init_boot_page_table(
 ) {
     let boot_pt_l0 = &mut *boot_pt_l0;
     let boot_pt_l1 = &mut *boot_pt_l1;
+    let boot_pt_l2 = &mut (*core::ptr::addr_of_mut!(BOOT_PT_L2)).0;
     // 0x0000_0000_0000 ~ 0x0080_0000_0000, table
     boot_pt_l0[0] = A64PTE::new_table(pa!(boot_pt_l1.as_ptr() as usize));
-    // 0x0000_0000_0000..0x0000_4000_0000, 1G block, device memory
+    // 0x0000_0000_0000..0x0000_4000_0000, 1G block, normal memory
     boot_pt_l1[0] = A64PTE::new_page(
         pa!(0),
         MappingFlags::READ | MappingFlags::WRITE | MappingFlags::EXECUTE,
@@ -39,10 +69,17 @@ pub(crate) unsafe fn init_boot_page_table(
         MappingFlags::READ | MappingFlags::WRITE | MappingFlags::EXECUTE,
         true,
     );
-    // 0x0000_C000_0000..0x0001_0000_0000, 1G block, DEVICE memory
-    boot_pt_l1[3] = A64PTE::new_page(
-        pa!(0xc000_0000),
-        MappingFlags::READ | MappingFlags::WRITE | MappingFlags::DEVICE,
-        true,
-    );
+    // 0x0000_C000_0000..0x0001_0000_0000, table
+    boot_pt_l1[3] = A64PTE::new_table(pa!(boot_pt_l2.as_ptr() as usize));
+    // 0x0000_C000_0000..0x0000_FC00_0000, 2M blocks, normal memory
+    // 0x0000_FC00_0000..0x0001_0000_0000, 2M blocks, DEVICE memory
+    for (i, pte) in boot_pt_l2.iter_mut().enumerate() {
+        let paddr = 0xc000_0000 + i * 0x20_0000;
+        let flags = if paddr < PERIPHERAL_BASE {
+            MappingFlags::READ | MappingFlags::WRITE | MappingFlags::EXECUTE
+        } else {
+            MappingFlags::READ | MappingFlags::WRITE | MappingFlags::DEVICE
+        };
+        *pte = A64PTE::new_page(pa!(paddr), flags, true);
+    }
 }
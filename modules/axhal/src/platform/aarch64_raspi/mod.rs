@@ -17,12 +17,41 @@ pub mod time {
 }
 
 pub mod misc {
+    use crate::mem::phys_to_virt;
+
+    /// Physical address of the power management block, with the watchdog.
+    const PM_PADDR: usize = 0xfe10_0000;
+    const PM_RSTC: usize = 0x1c;
+    const PM_WDOG: usize = 0x24;
+    const PM_PASSWORD: u32 = 0x5a00_0000;
+    const PM_RSTC_WRCFG_CLR: u32 = 0xffff_ffcf;
+    const PM_RSTC_WRCFG_FULL_RESET: u32 = 0x20;
+
     pub fn terminate() -> ! {
         info!("Shutting down...");
         loop {
             crate::arch::halt();
         }
     }
+
+    /// Reboot the whole system by letting the watchdog expire, as there is
+    /// no PSCI firmware.
+    pub fn reboot() -> ! {
+        info!("Rebooting...");
+        let base = phys_to_virt(pa!(PM_PADDR)).as_usize();
+        let rstc = (base + PM_RSTC) as *mut u32;
+        let wdog = (base + PM_WDOG) as *mut u32;
+        unsafe {
+            // Expire in 10 ticks of the watchdog (about 150 us).
+            wdog.write_volatile(PM_PASSWORD | 10);
+            let val = rstc.read_volatile() & PM_RSTC_WRCFG_CLR;
+            rstc.write_volatile(PM_PASSWORD | val | PM_RSTC_WRCFG_FULL_RESET);
+        }
+        warn!("It should reboot!");
+        loop {
+            crate::arch::halt();
+        }
+    }
 }
 
 extern "C" {
@@ -34,6 +63,8 @@ extern "C" {
 
 pub(crate) unsafe extern "C" fn rust_entry(cpu_id: usize, dtb: usize) {
     crate::mem::clear_bss();
+    crate::dtb::init_early(dtb);
+    crate::numa::init_early();
     crate::arch::set_exception_vector_base(exception_vector_base as usize);
     crate::arch::write_page_table_root0(0.into()); // disable low address access
     crate::cpu::init_primary(cpu_id);
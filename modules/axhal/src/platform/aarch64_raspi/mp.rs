@@ -6,10 +6,10 @@ extern "C" {
     fn _start_secondary();
 }
 
-#[naked]
+#[unsafe(naked)]
 #[link_section = ".text.boot"]
 unsafe extern "C" fn modify_stack_and_start() {
-    core::arch::asm!("
+    core::arch::naked_asm!("
         ldr     x21, ={secondary_boot_stack}    // the secondary CPU hasn't set the TTBR1
         mov     x8, {phys_virt_offset}          // minus the offset to get the phys addr of the boot stack
         sub     x21, x21, x8
@@ -18,7 +18,6 @@ unsafe extern "C" fn modify_stack_and_start() {
         b       _start_secondary",
         secondary_boot_stack = sym SECONDARY_STACK_TOP,
         phys_virt_offset = const axconfig::PHYS_VIRT_OFFSET,
-        options(noreturn)
     );
 }
 
@@ -40,3 +39,12 @@ pub fn start_secondary_cpu(cpu_id: usize, stack_top: PhysAddr) {
     }
     aarch64_cpu::asm::sev();
 }
+
+/// Parks the current CPU with IRQs disabled.
+///
+/// The spin table is not polled again, so the CPU cannot be started again.
+pub(crate) fn cpu_off() -> ! {
+    loop {
+        crate::arch::halt();
+    }
+}
@@ -19,15 +19,28 @@ pub mod misc {
     pub fn terminate() -> ! {
         unimplemented!()
     }
+
+    /// Reboot the whole system, including all CPUs.
+    pub fn reboot() -> ! {
+        unimplemented!()
+    }
 }
 
 #[cfg(feature = "smp")]
 pub mod mp {
     /// Starts the given secondary CPU with its boot stack.
     pub fn start_secondary_cpu(cpu_id: usize, stack_top: crate::mem::PhysAddr) {}
+
+    /// Powers off the current CPU.
+    pub(crate) fn cpu_off() -> ! {
+        unimplemented!()
+    }
 }
 
 pub mod mem {
+    /// The RAM mapped by the boot page table.
+    pub(crate) const BOOT_MAPPED_MEMORY: (usize, usize) = (0, usize::MAX);
+
     /// Returns platform-specific memory regions.
     pub(crate) fn platform_regions() -> impl Iterator<Item = crate::mem::MemRegion> {
         core::iter::empty()
@@ -40,25 +53,20 @@ pub mod time {
         0
     }
 
-    /// Converts hardware ticks to nanoseconds.
-    pub fn ticks_to_nanos(ticks: u64) -> u64 {
-        ticks
-    }
-
-    /// Converts nanoseconds to hardware ticks.
-    pub fn nanos_to_ticks(nanos: u64) -> u64 {
-        nanos
-    }
-
     /// Set a one-shot timer.
     ///
     /// A timer interrupt will be triggered at the specified monotonic time deadline (in nanoseconds).
     pub fn set_oneshot_timer(deadline_ns: u64) {}
 
-    /// Return epoch offset in nanoseconds (wall time offset to monotonic clock start).
-    pub fn epochoffset_nanos() -> u64 {
-        0
+    /// Reads the RTC in seconds since the epoch (1970-01-01).
+    #[cfg(feature = "rtc")]
+    pub(crate) fn read_rtc() -> Option<u64> {
+        None
     }
+
+    /// Writes the RTC in seconds since the epoch (1970-01-01).
+    #[cfg(feature = "rtc")]
+    pub(crate) fn write_rtc(secs: u64) {}
 }
 
 #[cfg(feature = "irq")]
@@ -69,6 +77,9 @@ pub mod irq {
     /// The timer IRQ number.
     pub const TIMER_IRQ_NUM: usize = 0;
 
+    /// The IRQ number of the inter-processor interrupts.
+    pub const IPI_IRQ_NUM: usize = 1;
+
     /// Enables or disables the given IRQ.
     pub fn set_enable(irq_num: usize, enabled: bool) {}
 
@@ -77,6 +88,61 @@ pub mod irq {
         false
     }
 
+    /// Sends an inter-processor interrupt to the CPU `cpu_id`.
+    pub fn send_ipi(cpu_id: usize) {}
+
+    /// Sends a TLB shootdown IPI to the CPU `cpu_id`.
+    pub(crate) fn send_tlb_ipi(cpu_id: usize) {}
+
+    /// Routes the given IRQ to the CPU `cpu_id`.
+    pub fn set_affinity(irq_num: usize, cpu_id: usize) -> bool {
+        false
+    }
+
+    /// Routes the given IRQ to the CPUs in `cpu_mask`.
+    pub fn set_affinity_mask(irq_num: usize, cpu_mask: usize) -> bool {
+        false
+    }
+
+    /// Returns the IRQ number of the GSI.
+    pub fn gsi_irq(gsi: usize) -> Option<usize> {
+        None
+    }
+
+    /// Routes the GSI to the given IRQ.
+    pub fn route_gsi(
+        gsi: usize,
+        irq_num: usize,
+        cpu_id: usize,
+        trigger: crate::irq::TriggerMode,
+        polarity: crate::irq::Polarity,
+    ) -> bool {
+        false
+    }
+
+    /// Sets the priority of the given IRQ.
+    pub fn set_priority(irq_num: usize, priority: u8) -> bool {
+        false
+    }
+
+    /// Sets the priority threshold of the current CPU.
+    pub fn set_threshold(threshold: u8) -> bool {
+        false
+    }
+
+    /// Allocates `count` contiguous vectors for message-signaled interrupts.
+    pub fn alloc_msi(count: usize) -> Option<usize> {
+        None
+    }
+
+    /// Frees `count` vectors from `irq_num` allocated by [`alloc_msi`].
+    pub fn free_msi(irq_num: usize, count: usize) {}
+
+    /// Returns the message a device writes to raise the MSI `irq_num`.
+    pub fn msi_message(irq_num: usize) -> crate::irq::MsiMessage {
+        unimplemented!()
+    }
+
     /// Dispatches the IRQ.
     ///
     /// This function is called by the common interrupt handler. It looks
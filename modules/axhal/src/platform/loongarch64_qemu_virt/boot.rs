@@ -0,0 +1,133 @@
+use axconfig::{PHYS_VIRT_OFFSET, TASK_STACK_SIZE};
+
+use crate::mem::{GuardedStack, STACK_GUARD_SIZE};
+
+#[link_section = ".bss.stack.primary"]
+static mut BOOT_STACK: GuardedStack<TASK_STACK_SIZE> = GuardedStack::new();
+
+#[link_section = ".data.boot_page_table"]
+static mut BOOT_PT_L0: [u64; 512] = [0; 512];
+
+extern "C" {
+    fn handle_tlb_refill();
+}
+
+/// `CRMD` with paging enabled, PLV 0, interrupts disabled, and the cached
+/// memory type for the direct address translation mode.
+const CRMD_PG: usize = 0xb0;
+
+/// `PWCL`: 4K pages and 9-bit indexes for the page table, the directories of
+/// level 1 (2M) and level 2 (1G), the root, as in the runtime page tables.
+const PWCL: usize = 12 | 9 << 5 | 21 << 10 | 9 << 15 | 30 << 20 | 9 << 25;
+/// `PWCH`: no directories of levels 3 and 4.
+const PWCH: usize = 0;
+/// The page size of the STLB and the TLB refill, 4K.
+const PS_4K: usize = 12;
+
+unsafe fn init_boot_page_table() {
+    // The low addresses (`PGDL`) and the high ones from 0xffff_0000_0000_0000
+    // (`PGDH`) share the root, which is indexed by bits 30 to 38.
+    // 0x0000_0000..0x4000_0000, V_D_CC_HUGE_P_W_HGLOBAL, 1G block
+    BOOT_PT_L0[0] = 0x11d3;
+}
+
+unsafe fn init_mmu() {
+    let page_table_root = core::ptr::addr_of!(BOOT_PT_L0) as usize;
+    core::arch::asm!("
+        csrwr   {tlbrentry}, 0x88       // TLBRENTRY
+        csrwr   {ps}, 0x1e              // STLBPS
+        csrwr   {ps2}, 0x8e             // TLBREHI
+        csrwr   {pwcl}, 0x1c            // PWCL
+        csrwr   {pwch}, 0x1d            // PWCH
+        csrwr   {root}, 0x19            // PGDL
+        csrwr   {root2}, 0x1a           // PGDH
+        csrwr   $zero, 0x18             // ASID
+        invtlb  0x0, $zero, $zero       // flush the entire TLB
+        csrwr   {crmd}, 0x0             // enable paging",
+        tlbrentry = inout(reg) handle_tlb_refill as usize => _,
+        ps = inout(reg) PS_4K => _,
+        ps2 = inout(reg) PS_4K => _,
+        pwcl = inout(reg) PWCL => _,
+        pwch = inout(reg) PWCH => _,
+        root = inout(reg) page_table_root => _,
+        root2 = inout(reg) page_table_root => _,
+        crmd = inout(reg) CRMD_PG => _,
+    );
+}
+
+unsafe fn enable_fp() {
+    if cfg!(feature = "fp_simd") {
+        // EUEN.FPE
+        core::arch::asm!("csrxchg {0}, {0}, 0x2", inout(reg) 1usize => _);
+    }
+}
+
+/// The earliest entry point for the primary CPU.
+#[unsafe(naked)]
+#[no_mangle]
+#[link_section = ".text.boot"]
+unsafe extern "C" fn _start() -> ! {
+    // PC = 0x20_0000, in the direct address translation mode
+    core::arch::naked_asm!("
+        pcaddi      $t0, 0              // continue at the physical address,
+        bstrpick.d  $t0, $t0, 47, 0     // in case it is entered by the
+        jirl        $zero, $t0, 12      // virtual one
+
+        csrrd   $s0, 0x20               // get current CPU id
+        la.pcrel $sp, {boot_stack}
+        li.d    $t0, {boot_stack_size}
+        add.d   $sp, $sp, $t0           // setup boot stack
+
+        bl      {enable_fp}
+        bl      {init_boot_page_table}
+        bl      {init_mmu}              // setup boot page table and enabel MMU
+
+        li.d    $s1, {phys_virt_offset} // fix up virtual high address
+        add.d   $sp, $sp, $s1
+
+        move    $a0, $s0
+        move    $a1, $zero              // no DTB is passed
+        la.pcrel $t0, {entry}
+        add.d   $t0, $t0, $s1
+        jirl    $ra, $t0, 0             // call rust_entry(cpu_id, dtb)
+        b       .",
+        phys_virt_offset = const PHYS_VIRT_OFFSET as isize,
+        boot_stack_size = const STACK_GUARD_SIZE + TASK_STACK_SIZE,
+        boot_stack = sym BOOT_STACK,
+        enable_fp = sym enable_fp,
+        init_boot_page_table = sym init_boot_page_table,
+        init_mmu = sym init_mmu,
+        entry = sym super::rust_entry,
+    )
+}
+
+/// The earliest entry point for secondary CPUs, jumped to by the boot code of
+/// QEMU at the physical address in the mailbox 0, see `mp.rs`.
+#[cfg(feature = "smp")]
+#[unsafe(naked)]
+#[no_mangle]
+#[link_section = ".text.boot"]
+unsafe extern "C" fn _start_secondary() -> ! {
+    core::arch::naked_asm!("
+        csrrd   $s0, 0x20               // get current CPU id
+        li.d    $t0, {mail_buf1}
+        iocsrrd.d $sp, $t0              // get SP from the mailbox 1
+
+        bl      {enable_fp}
+        bl      {init_mmu}              // setup boot page table and enabel MMU
+
+        li.d    $s1, {phys_virt_offset} // fix up virtual high address
+        add.d   $sp, $sp, $s1
+
+        move    $a0, $s0
+        la.pcrel $t0, {entry}
+        add.d   $t0, $t0, $s1
+        jirl    $ra, $t0, 0             // call rust_entry_secondary(cpu_id)
+        b       .",
+        phys_virt_offset = const PHYS_VIRT_OFFSET as isize,
+        mail_buf1 = const super::ipi::MAIL_BUF1,
+        enable_fp = sym enable_fp,
+        init_mmu = sym init_mmu,
+        entry = sym super::rust_entry_secondary,
+    )
+}
@@ -0,0 +1,92 @@
+//! The 16550 UART, whose registers are byte-wide and byte-spaced.
+
+use kspin::SpinNoIrq;
+
+use crate::mem::phys_to_virt;
+
+const UART_RBR_THR: usize = 0;
+#[cfg(feature = "irq")]
+const UART_IER: usize = 1;
+#[cfg(feature = "irq")]
+const UART_MCR: usize = 4;
+const UART_LSR: usize = 5;
+#[cfg(feature = "irq")]
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+#[cfg(feature = "irq")]
+const MCR_OUT2: u8 = 1 << 3;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+
+/// Serializes the output, so that lines of different CPUs are not mixed.
+static UART_LOCK: SpinNoIrq<()> = SpinNoIrq::new(());
+
+fn reg(offset: usize) -> *mut u8 {
+    phys_to_virt(pa!(axconfig::UART_PADDR + offset)).as_mut_ptr()
+}
+
+fn putchar_raw(c: u8) {
+    unsafe {
+        while reg(UART_LSR).read_volatile() & LSR_THR_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+        reg(UART_RBR_THR).write_volatile(c);
+    }
+}
+
+/// Writes a byte to the console.
+pub fn putchar(c: u8) {
+    let _lock = UART_LOCK.lock();
+    match c {
+        b'\n' => {
+            putchar_raw(b'\r');
+            putchar_raw(b'\n');
+        }
+        c => putchar_raw(c),
+    }
+}
+
+/// Reads a byte from the console, or returns [`None`] if no input is available.
+fn getchar() -> Option<u8> {
+    unsafe {
+        (reg(UART_LSR).read_volatile() & LSR_DATA_READY != 0)
+            .then(|| reg(UART_RBR_THR).read_volatile())
+    }
+}
+
+/// Writes bytes to the console from input u8 slice.
+pub fn write_bytes(bytes: &[u8]) {
+    for c in bytes {
+        putchar(*c);
+    }
+}
+
+/// Reads bytes from the console into the given mutable slice.
+/// Returns the number of bytes read.
+pub fn read_bytes(bytes: &mut [u8]) -> usize {
+    let mut read_len = 0;
+    while read_len < bytes.len() {
+        if let Some(c) = getchar() {
+            bytes[read_len] = c;
+        } else {
+            break;
+        }
+        read_len += 1;
+    }
+    read_len
+}
+
+/// Enables the receive interrupt of the UART, so that the input is buffered.
+#[cfg(feature = "irq")]
+pub(super) fn init_irq() {
+    fn handle() {
+        // Reading the received data also clears the interrupt.
+        crate::console::receive(core::iter::from_fn(getchar));
+    }
+
+    crate::irq::register_handler(axconfig::UART_IRQ, handle);
+    unsafe {
+        reg(UART_MCR).write_volatile(reg(UART_MCR).read_volatile() | MCR_OUT2);
+        reg(UART_IER).write_volatile(IER_RX_AVAILABLE);
+    }
+    crate::console::enable_rx_irq();
+}
@@ -0,0 +1,62 @@
+//! The inter-processor interrupts and the mailboxes of each core, accessed
+//! by IOCSR (I/O control and status register) instructions.
+
+#[cfg(feature = "smp")]
+use crate::arch::iocsr_write_d;
+use crate::arch::{iocsr_read_w, iocsr_write_w};
+
+const IPI_STATUS: usize = 0x1000;
+const IPI_EN: usize = 0x1004;
+const IPI_CLEAR: usize = 0x100c;
+const IPI_SEND: usize = 0x1040;
+#[cfg(feature = "smp")]
+const MAIL_SEND: usize = 0x1048;
+
+/// The mailbox 0, where the boot code of QEMU reads the entry of a secondary
+/// CPU.
+#[cfg(feature = "smp")]
+pub(super) const MAIL_BUF0: usize = 0x1020;
+/// The mailbox 1, where `_start_secondary` reads its stack.
+#[cfg(feature = "smp")]
+pub(super) const MAIL_BUF1: usize = 0x1028;
+
+/// Waits until the IPI or mailbox write is done.
+const SEND_BLOCKING: u64 = 1 << 31;
+const SEND_CPU_SHIFT: u64 = 16;
+/// The offset of the 32-bit half in the mailboxes, in bits 2 to 4.
+#[cfg(feature = "smp")]
+const MAIL_SEND_BUF_SHIFT: u64 = 2;
+
+/// The IPI vector of [`crate::irq::send_ipi`].
+pub(super) const VECTOR_IPI: u32 = 0;
+/// The IPI vector of the TLB shootdowns, see [`crate::tlb`].
+#[cfg(feature = "irq")]
+pub(super) const VECTOR_TLB: u32 = 1;
+
+/// Sends the IPI `vector` (0 to 31) to the CPU `cpu_id`.
+pub(super) fn send(cpu_id: usize, vector: u32) {
+    let val = SEND_BLOCKING | (cpu_id as u64) << SEND_CPU_SHIFT | vector as u64;
+    iocsr_write_w(IPI_SEND, val as u32);
+}
+
+/// Writes `data` to the mailbox (0 to 3) of the CPU `cpu_id`, the high half
+/// first, as the low half is checked by the boot code of QEMU.
+#[cfg(feature = "smp")]
+pub(super) fn mail_send(cpu_id: usize, mailbox: usize, data: u64) {
+    let send = SEND_BLOCKING | (cpu_id as u64) << SEND_CPU_SHIFT;
+    let buf = |half: usize| ((mailbox * 2 + half) as u64) << MAIL_SEND_BUF_SHIFT;
+    iocsr_write_d(MAIL_SEND, send | buf(1) | (data & 0xffff_ffff_0000_0000));
+    iocsr_write_d(MAIL_SEND, send | buf(0) | data << 32);
+}
+
+/// Enables all IPI vectors of the current CPU.
+pub(super) fn enable_all() {
+    iocsr_write_w(IPI_EN, u32::MAX);
+}
+
+/// Clears and returns the pending IPI vectors of the current CPU.
+pub(super) fn take_pending() -> u32 {
+    let status = iocsr_read_w(IPI_STATUS);
+    iocsr_write_w(IPI_CLEAR, status);
+    status
+}
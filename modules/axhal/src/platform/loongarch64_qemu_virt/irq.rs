@@ -0,0 +1,310 @@
+//! Interrupts of the LoongArch CPU interrupt lines, the EIOINTC (Extended I/O
+//! Interrupt Controller), and the PCH-PIC (Platform Controller Hub
+//! Programmable Interrupt Controller).
+//!
+//! The timer IRQ and the IPI are numbered by their bits in `ESTAT.IS`, with
+//! the interrupt flag as `scause` on RISC-V, and the external IRQs by their
+//! EIOINTC vectors. The PCH-PIC inputs are sent to the vectors of the same
+//! numbers.
+//!
+//! All EIOINTC vectors are delivered on the `HWI0` line of the CPUs they are
+//! routed to.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::ipi;
+use crate::arch::{iocsr_read_d, iocsr_read_w, iocsr_write_d, iocsr_write_w};
+use crate::irq::IrqHandler;
+use crate::mem::phys_to_virt;
+use kspin::SpinNoIrq;
+use lazyinit::LazyInit;
+use memory_addr::PhysAddr;
+
+/// Interrupt flag of the numbers of the CPU interrupt lines.
+const INTC_IRQ_BASE: usize = 1 << (usize::BITS - 1);
+
+/// Hardware interrupt line 0 in `ESTAT.IS`, where the EIOINTC delivers.
+const INT_HWI0: usize = INTC_IRQ_BASE + 2;
+
+/// Timer interrupt in `ESTAT.IS`.
+const INT_TIMER: usize = INTC_IRQ_BASE + 11;
+
+/// Inter-processor interrupt in `ESTAT.IS`.
+const INT_IPI: usize = INTC_IRQ_BASE + 12;
+
+/// `LIE` bits in `ECFG` of the interrupt lines above.
+const ECFG_LIE: usize = 1 << 2 | 1 << 11 | 1 << 12;
+
+static TIMER_HANDLER: LazyInit<IrqHandler> = LazyInit::new();
+static IPI_HANDLER: LazyInit<IrqHandler> = LazyInit::new();
+
+/// The maximum number of IRQs, the EIOINTC vectors.
+pub const MAX_IRQ_COUNT: usize = 256;
+
+/// The timer IRQ number (timer interrupt in `ESTAT.IS`).
+pub const TIMER_IRQ_NUM: usize = INT_TIMER;
+
+/// The IRQ number of the inter-processor interrupts sent by [`send_ipi`]
+/// (inter-processor interrupt in `ESTAT.IS`).
+pub const IPI_IRQ_NUM: usize = INT_IPI;
+
+/// Enables the extended I/O interrupts in the miscellaneous function IOCSR.
+const IOCSR_MISC_FUNC: usize = 0x420;
+const MISC_FUNC_EXT_IOI_EN: u64 = 1 << 48;
+
+/// EIOINTC registers in the IOCSR space.
+const EIOINTC_IPMAP: usize = 0x14c0;
+const EIOINTC_ENABLE: usize = 0x1600;
+const EIOINTC_COREISR: usize = 0x1800;
+const EIOINTC_COREMAP: usize = 0x1c00;
+
+/// `IPMAP` with all groups of 32 vectors delivered on `HWI0`.
+const IPMAP_ALL_HWI0: u32 = 0x0101_0101;
+/// CPUs that can be in the core bitmaps of the routes.
+const COREMAP_MAX_CPUS: usize = 4;
+
+const PCH_PIC_BASE: PhysAddr = pa!(axconfig::PCH_PIC_PADDR);
+
+/// Offsets in the PCH-PIC registers.
+const PCH_PIC_INT_MASK: usize = 0x20;
+const PCH_PIC_INT_EDGE: usize = 0x60;
+const PCH_PIC_HTMSI_VEC: usize = 0x200;
+const PCH_PIC_INT_POL: usize = 0x3e0;
+
+/// The number of PCH-PIC inputs.
+const PCH_PIC_IRQ_COUNT: usize = 64;
+
+/// Masks of the CPUs the external IRQs are routed to, 0 until an IRQ is
+/// enabled or routed for the first time.
+static AFFINITIES: [AtomicUsize; MAX_IRQ_COUNT] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+    [ZERO; MAX_IRQ_COUNT]
+};
+/// Serializes the updates of the enable bits, the routes and the masks.
+static EIOINTC_LOCK: SpinNoIrq<()> = SpinNoIrq::new(());
+
+fn pch_pic_reg(offset: usize) -> *mut u32 {
+    phys_to_virt(PCH_PIC_BASE + offset).as_mut_ptr() as *mut u32
+}
+
+fn is_external_irq(irq_num: usize) -> bool {
+    irq_num < MAX_IRQ_COUNT
+}
+
+/// Sets or clears the bit of `irq_num` in the 32-bit IOCSRs from `base`.
+/// `EIOINTC_LOCK` must be held.
+fn update_iocsr_bit(base: usize, irq_num: usize, set: bool) {
+    let reg = base + irq_num / 32 * 4;
+    let bits = iocsr_read_w(reg);
+    let bit = 1 << (irq_num % 32);
+    iocsr_write_w(reg, if set { bits | bit } else { bits & !bit });
+}
+
+/// Routes the external IRQ to the CPUs in `cpu_mask` by its core bitmap,
+/// where the first CPU takes it on QEMU. `EIOINTC_LOCK` must be held.
+fn route(irq_num: usize, cpu_mask: usize) {
+    let reg = EIOINTC_COREMAP + irq_num / 4 * 4;
+    let shift = irq_num % 4 * 8;
+    let map = iocsr_read_w(reg) & !(0xff << shift);
+    iocsr_write_w(reg, map | (cpu_mask as u32) << shift);
+    AFFINITIES[irq_num].store(cpu_mask, Ordering::Relaxed);
+}
+
+/// Enables or disables the given IRQ.
+///
+/// External IRQs are enabled toward the current CPU unless they are routed
+/// by [`set_affinity`] or [`set_affinity_mask`]. Those from the PCH-PIC are
+/// also unmasked there.
+pub fn set_enable(irq_num: usize, enabled: bool) {
+    if !is_external_irq(irq_num) {
+        return;
+    }
+    let _lock = EIOINTC_LOCK.lock();
+    if enabled && AFFINITIES[irq_num].load(Ordering::Relaxed) == 0 {
+        route(irq_num, 1 << crate::cpu::this_cpu_id());
+    }
+    update_iocsr_bit(EIOINTC_ENABLE, irq_num, enabled);
+    if irq_num < PCH_PIC_IRQ_COUNT {
+        let mask = pch_pic_reg(PCH_PIC_INT_MASK + irq_num / 32 * 4);
+        let bit = 1 << (irq_num % 32);
+        unsafe {
+            let bits = mask.read_volatile();
+            mask.write_volatile(if enabled { bits & !bit } else { bits | bit });
+        }
+    }
+}
+
+/// Sets the priority of the given external IRQ.
+///
+/// The EIOINTC has no priorities, so it always returns `false`.
+pub fn set_priority(_irq_num: usize, _priority: u8) -> bool {
+    false
+}
+
+/// Sets the priority threshold of the current CPU.
+///
+/// The EIOINTC has no priorities, so it always returns `false`.
+pub fn set_threshold(_threshold: u8) -> bool {
+    false
+}
+
+/// Registers an IRQ handler for the given IRQ.
+///
+/// It also enables the IRQ if the registration succeeds. It returns `false` if
+/// the registration failed.
+pub fn register_handler(irq_num: usize, handler: IrqHandler) -> bool {
+    let lazy_handler = match irq_num {
+        INT_TIMER => &TIMER_HANDLER,
+        INT_IPI => &IPI_HANDLER,
+        _ => return crate::irq::register_handler_common(irq_num, handler),
+    };
+    if !lazy_handler.is_inited() {
+        lazy_handler.init_once(handler);
+        return true;
+    }
+    false
+}
+
+/// Sends an inter-processor interrupt ([`IPI_IRQ_NUM`]) to the CPU `cpu_id`,
+/// by the IPI vector 0 of its IOCSR.
+pub fn send_ipi(cpu_id: usize) {
+    ipi::send(cpu_id, ipi::VECTOR_IPI);
+}
+
+/// Sends a TLB shootdown IPI to the CPU `cpu_id`, see [`crate::tlb`].
+///
+/// It shares the interrupt line with [`IPI_IRQ_NUM`], but has its own IPI
+/// vector, which is handled here rather than by the registered handler.
+#[cfg(feature = "smp")]
+pub(crate) fn send_tlb_ipi(cpu_id: usize) {
+    ipi::send(cpu_id, ipi::VECTOR_TLB);
+}
+
+/// Routes the given external IRQ to the CPU `cpu_id`.
+pub fn set_affinity(irq_num: usize, cpu_id: usize) -> bool {
+    cpu_id < axconfig::SMP && set_affinity_mask(irq_num, 1 << cpu_id)
+}
+
+/// Routes the given external IRQ to the CPUs in `cpu_mask`, where bit `i`
+/// stands for the CPU `i`. Each interrupt is handled by one of them.
+///
+/// Returns `false` if the IRQ is invalid, or the mask is empty or contains
+/// CPUs that do not exist. Only the first 4 CPUs can be in the mask, as in
+/// the core bitmaps of the EIOINTC.
+pub fn set_affinity_mask(irq_num: usize, cpu_mask: usize) -> bool {
+    let all_cpus = usize::MAX >> (usize::BITS as usize - axconfig::SMP.min(COREMAP_MAX_CPUS));
+    if !is_external_irq(irq_num) || cpu_mask == 0 || cpu_mask & !all_cpus != 0 {
+        return false;
+    }
+    let _lock = EIOINTC_LOCK.lock();
+    route(irq_num, cpu_mask);
+    true
+}
+
+/// Returns the IRQ number of the GSI (global system interrupt), which is
+/// the PCH-PIC input of the same number.
+pub fn gsi_irq(gsi: usize) -> Option<usize> {
+    (gsi < PCH_PIC_IRQ_COUNT).then_some(gsi)
+}
+
+/// Routes the GSI to the given IRQ.
+///
+/// GSIs are always sent to the vectors of the same numbers, so it always
+/// returns `false`.
+pub fn route_gsi(
+    _gsi: usize,
+    _irq_num: usize,
+    _cpu_id: usize,
+    _trigger: crate::irq::TriggerMode,
+    _polarity: crate::irq::Polarity,
+) -> bool {
+    false
+}
+
+/// Allocates `count` contiguous vectors for message-signaled interrupts.
+///
+/// MSIs through the PCH-MSI are not supported yet, so it always returns
+/// `None`.
+pub fn alloc_msi(_count: usize) -> Option<usize> {
+    None
+}
+
+/// Frees `count` vectors from `irq_num` allocated by [`alloc_msi`].
+pub fn free_msi(_irq_num: usize, _count: usize) {}
+
+/// Returns the message a device writes to raise the MSI `irq_num`.
+pub fn msi_message(_irq_num: usize) -> crate::irq::MsiMessage {
+    unreachable!("no MSI vectors are allocated without the PCH-MSI")
+}
+
+/// Dispatches the IRQ.
+///
+/// This function is called by the common interrupt handler. It looks
+/// up in the IRQ handler table and calls the corresponding handler. If
+/// necessary, it also acknowledges the interrupt controller after handling.
+pub fn dispatch_irq(irq_num: usize) {
+    match irq_num {
+        INT_TIMER => {
+            trace!("IRQ: timer");
+            super::time::clear_timer_irq();
+            TIMER_HANDLER();
+        }
+        INT_IPI => {
+            trace!("IRQ: IPI");
+            let pending = ipi::take_pending();
+            if pending & 1 << ipi::VECTOR_TLB != 0 {
+                crate::tlb::handle_ipi();
+            }
+            if pending & 1 << ipi::VECTOR_IPI != 0 {
+                if let Some(handler) = IPI_HANDLER.get() {
+                    handler();
+                }
+            }
+        }
+        INT_HWI0 => {
+            for i in 0..MAX_IRQ_COUNT / 32 {
+                let isr = EIOINTC_COREISR + i * 4;
+                let mut pending = iocsr_read_w(isr);
+                // Clear them first, so that the IRQs raised again during the
+                // handling are not lost.
+                iocsr_write_w(isr, pending);
+                while pending != 0 {
+                    let bit = pending.trailing_zeros() as usize;
+                    crate::irq::dispatch_irq_common(i * 32 + bit);
+                    pending &= !(1 << bit);
+                }
+            }
+        }
+        _ => panic!("invalid interrupt: {:#x}", irq_num),
+    }
+}
+
+/// Initializes the EIOINTC and the PCH-PIC, which are shared by all CPUs.
+pub(super) fn init_primary() {
+    let misc = iocsr_read_d(IOCSR_MISC_FUNC);
+    iocsr_write_d(IOCSR_MISC_FUNC, misc | MISC_FUNC_EXT_IOI_EN);
+    // deliver all vectors on `HWI0`
+    iocsr_write_w(EIOINTC_IPMAP, IPMAP_ALL_HWI0);
+    iocsr_write_w(EIOINTC_IPMAP + 4, IPMAP_ALL_HWI0);
+
+    // mask all inputs, which are level-triggered and active high
+    unsafe {
+        for i in 0..PCH_PIC_IRQ_COUNT / 32 {
+            pch_pic_reg(PCH_PIC_INT_MASK + i * 4).write_volatile(u32::MAX);
+            pch_pic_reg(PCH_PIC_INT_EDGE + i * 4).write_volatile(0);
+            pch_pic_reg(PCH_PIC_INT_POL + i * 4).write_volatile(0);
+        }
+        // send each input to the EIOINTC vector of the same number
+        let htmsi_vec = phys_to_virt(PCH_PIC_BASE + PCH_PIC_HTMSI_VEC).as_mut_ptr();
+        for i in 0..PCH_PIC_IRQ_COUNT {
+            htmsi_vec.add(i).write_volatile(i as u8);
+        }
+    }
+}
+
+pub(super) fn init_percpu() {
+    ipi::enable_all();
+    // enable hardware interrupts 0, timer interrupts, and IPIs
+    unsafe { core::arch::asm!("csrxchg {}, {}, 0x4", inout(reg) ECFG_LIE => _, in(reg) ECFG_LIE) };
+}
@@ -0,0 +1,36 @@
+use crate::mem::phys_to_virt;
+
+/// The sleep control register of the ACPI generic event device (GED).
+const GED_SLEEP_CTL: usize = axconfig::GED_PADDR;
+/// The reset register of the GED.
+const GED_RESET: usize = axconfig::GED_PADDR + 2;
+
+/// `SLP_EN` with `SLP_TYP` 5 (S5, soft off).
+const SLEEP_CTL_S5: u8 = (1 << 5) | (5 << 2);
+/// The value to write to the reset register.
+const RESET_VALUE: u8 = 0x42;
+
+fn write_ged(paddr: usize, val: u8) {
+    let ptr: *mut u8 = phys_to_virt(pa!(paddr)).as_mut_ptr();
+    unsafe { ptr.write_volatile(val) };
+}
+
+/// Shutdown the whole system, including all CPUs.
+pub fn terminate() -> ! {
+    info!("Shutting down...");
+    write_ged(GED_SLEEP_CTL, SLEEP_CTL_S5);
+    warn!("It should shutdown!");
+    loop {
+        crate::arch::halt();
+    }
+}
+
+/// Reboot the whole system, including all CPUs.
+pub fn reboot() -> ! {
+    info!("Rebooting...");
+    write_ged(GED_RESET, RESET_VALUE);
+    warn!("It should reboot!");
+    loop {
+        crate::arch::halt();
+    }
+}
@@ -0,0 +1,60 @@
+mod boot;
+
+pub mod console;
+pub mod mem;
+pub mod misc;
+pub mod time;
+
+#[cfg(any(feature = "smp", feature = "irq"))]
+mod ipi;
+
+#[cfg(feature = "irq")]
+pub mod irq;
+
+#[cfg(feature = "smp")]
+pub mod mp;
+
+extern "C" {
+    fn trap_vector_base();
+    fn rust_main(cpu_id: usize, dtb: usize);
+    #[cfg(feature = "smp")]
+    fn rust_main_secondary(cpu_id: usize);
+}
+
+unsafe extern "C" fn rust_entry(cpu_id: usize, dtb: usize) {
+    crate::mem::clear_bss();
+    crate::dtb::init_early(dtb);
+    crate::numa::init_early();
+    crate::cpu::init_primary(cpu_id);
+    crate::arch::set_trap_vector_base(trap_vector_base as usize);
+    self::time::init_early();
+    rust_main(cpu_id, dtb);
+}
+
+#[cfg(feature = "smp")]
+unsafe extern "C" fn rust_entry_secondary(cpu_id: usize) {
+    crate::arch::set_trap_vector_base(trap_vector_base as usize);
+    crate::cpu::init_secondary(cpu_id);
+    rust_main_secondary(cpu_id);
+}
+
+/// Initializes the platform devices for the primary CPU.
+///
+/// For example, the interrupt controller and the timer.
+pub fn platform_init() {
+    #[cfg(feature = "irq")]
+    self::irq::init_primary();
+    #[cfg(feature = "irq")]
+    self::irq::init_percpu();
+    self::time::init_percpu();
+    #[cfg(feature = "irq")]
+    self::console::init_irq();
+}
+
+/// Initializes the platform devices for secondary CPUs.
+#[cfg(feature = "smp")]
+pub fn platform_init_secondary() {
+    #[cfg(feature = "irq")]
+    self::irq::init_percpu();
+    self::time::init_percpu();
+}
@@ -0,0 +1,61 @@
+use core::arch::asm;
+
+use super::ipi;
+use crate::arch::{iocsr_read_d, iocsr_write_d};
+use crate::mem::{virt_to_phys, PhysAddr};
+
+/// `LIE` bit of the IPI in `ECFG`.
+const ECFG_LIE_IPI: usize = 1 << 12;
+/// `CRMD` in the direct address translation mode, with interrupts disabled.
+const CRMD_DA: usize = 1 << 3;
+
+/// Starts the given secondary CPU with its boot stack.
+///
+/// The CPU waits for an IPI in the boot code of QEMU, or in [`cpu_off`], and
+/// then jumps to the entry in its mailbox 0. The stack is passed in the
+/// mailbox 1.
+pub fn start_secondary_cpu(cpu_id: usize, stack_top: PhysAddr) {
+    extern "C" {
+        fn _start_secondary();
+    }
+    let entry = virt_to_phys(va!(_start_secondary as usize));
+    ipi::mail_send(cpu_id, 1, stack_top.as_usize() as u64);
+    ipi::mail_send(cpu_id, 0, entry.as_usize() as u64);
+    ipi::send(cpu_id, ipi::VECTOR_IPI);
+}
+
+/// Stops the current CPU, which can be started again by
+/// [`start_secondary_cpu`].
+///
+/// As the boot code of QEMU, it waits with only the IPI enabled until an
+/// entry is written to its mailbox 0, and jumps to the entry in the direct
+/// address translation mode.
+pub(crate) fn cpu_off() -> ! {
+    crate::arch::disable_irqs();
+    iocsr_write_d(ipi::MAIL_BUF0, 0);
+    unsafe {
+        asm!("csrwr $zero, 0x41"); // stop the timer
+        asm!("csrwr {}, 0x4", inout(reg) ECFG_LIE_IPI => _);
+    }
+    ipi::enable_all();
+    let entry = loop {
+        // `idle` is woken up by the IPI even if it is masked by `CRMD.IE`.
+        unsafe { asm!("idle 0") };
+        ipi::take_pending();
+        match iocsr_read_d(ipi::MAIL_BUF0) {
+            0 => continue,
+            entry => break entry,
+        }
+    };
+    // The PC keeps running at the physical address of the kernel image once
+    // the translation is off, as the high bits are ignored as in `_start`.
+    unsafe {
+        asm!("
+            csrwr   {crmd}, 0x0
+            jirl    $zero, {entry}, 0",
+            crmd = in(reg) CRMD_DA,
+            entry = in(reg) entry,
+            options(noreturn),
+        )
+    }
+}
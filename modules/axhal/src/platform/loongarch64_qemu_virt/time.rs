@@ -0,0 +1,77 @@
+use core::arch::asm;
+
+/// `En` bit in `TCFG`, which starts the timer.
+#[cfg(feature = "irq")]
+const TCFG_EN: usize = 1 << 0;
+/// `InitVal` in `TCFG` is a multiple of 4, in bits 2 to 47.
+#[cfg(feature = "irq")]
+const TCFG_INIT_VAL_MASK: usize = 0xffff_ffff_fffc;
+
+/// Returns the current clock time in hardware ticks.
+#[inline]
+pub fn current_ticks() -> u64 {
+    let ticks: u64;
+    unsafe { asm!("rdtime.d {}, $zero", out(reg) ticks) };
+    ticks
+}
+
+/// Starts the one-shot timer to count down `ticks`, which must be at least 4.
+#[cfg(feature = "irq")]
+fn start_timer(ticks: usize) {
+    let tcfg = (ticks & TCFG_INIT_VAL_MASK) | TCFG_EN;
+    unsafe { asm!("csrwr {}, 0x41", inout(reg) tcfg => _) };
+}
+
+/// Set a one-shot timer.
+///
+/// A timer interrupt will be triggered at the specified monotonic time deadline (in nanoseconds).
+#[cfg(feature = "irq")]
+pub fn set_oneshot_timer(deadline_ns: u64) {
+    let deadline = crate::time::nanos_to_ticks(deadline_ns);
+    let ticks = deadline.saturating_sub(current_ticks()).max(4);
+    start_timer(ticks.min(TCFG_INIT_VAL_MASK as u64) as usize);
+}
+
+/// Clears the pending timer interrupt.
+#[cfg(feature = "irq")]
+pub(super) fn clear_timer_irq() {
+    unsafe { asm!("csrwr {}, 0x44", inout(reg) 1usize => _) };
+}
+
+/// Reads the RTC in seconds since the epoch (1970-01-01).
+///
+/// The RTC of the LS7A bridge is not supported yet.
+#[cfg(feature = "rtc")]
+pub(crate) fn read_rtc() -> Option<u64> {
+    None
+}
+
+/// Writes the RTC in seconds since the epoch (1970-01-01).
+#[cfg(feature = "rtc")]
+pub(crate) fn write_rtc(_secs: u64) {}
+
+/// Returns the frequency of the stable counter, which is the base frequency
+/// in `CPUCFG` word 4 scaled by the multiplier and the divisor in word 5.
+fn stable_counter_frequency() -> Option<u64> {
+    let (base, scale): (usize, usize);
+    unsafe {
+        asm!("cpucfg {}, {}", out(reg) base, in(reg) 4usize);
+        asm!("cpucfg {}, {}", out(reg) scale, in(reg) 5usize);
+    }
+    let (mul, div) = (scale & 0xffff, (scale >> 16) & 0xffff);
+    let freq = (base & 0xffff_ffff) as u64 * mul as u64;
+    (freq > 0 && div > 0).then(|| freq / div as u64)
+}
+
+pub(super) fn init_early() {
+    match stable_counter_frequency() {
+        Some(freq) => crate::time::init_timer_frequency(freq, "CPUCFG"),
+        None => crate::time::init_timer_frequency(axconfig::TIMER_FREQUENCY as u64, "config"),
+    }
+    crate::time::init_wall_time();
+}
+
+pub(super) fn init_percpu() {
+    #[cfg(feature = "irq")]
+    start_timer(4);
+}
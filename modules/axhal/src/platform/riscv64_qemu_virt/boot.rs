@@ -2,8 +2,10 @@ use riscv::register::satp;
 
 use axconfig::{PHYS_VIRT_OFFSET, TASK_STACK_SIZE};
 
-#[link_section = ".bss.stack"]
-static mut BOOT_STACK: [u8; TASK_STACK_SIZE] = [0; TASK_STACK_SIZE];
+use crate::mem::{GuardedStack, STACK_GUARD_SIZE};
+
+#[link_section = ".bss.stack.primary"]
+static mut BOOT_STACK: GuardedStack<TASK_STACK_SIZE> = GuardedStack::new();
 
 #[link_section = ".data.boot_page_table"]
 static mut BOOT_PT_SV39: [u64; 512] = [0; 512];
@@ -20,20 +22,20 @@ unsafe fn init_boot_page_table() {
 }
 
 unsafe fn init_mmu() {
-    let page_table_root = BOOT_PT_SV39.as_ptr() as usize;
+    let page_table_root = core::ptr::addr_of!(BOOT_PT_SV39) as usize;
     satp::set(satp::Mode::Sv39, 0, page_table_root >> 12);
     riscv::asm::sfence_vma_all();
 }
 
 /// The earliest entry point for the primary CPU.
-#[naked]
+#[unsafe(naked)]
 #[no_mangle]
 #[link_section = ".text.boot"]
 unsafe extern "C" fn _start() -> ! {
     // PC = 0x8020_0000
     // a0 = hartid
     // a1 = dtb
-    core::arch::asm!("
+    core::arch::naked_asm!("
         mv      s0, a0                  // save hartid
         mv      s1, a1                  // save DTB pointer
         la      sp, {boot_stack}
@@ -53,24 +55,23 @@ unsafe extern "C" fn _start() -> ! {
         jalr    a2                      // call rust_entry(hartid, dtb)
         j       .",
         phys_virt_offset = const PHYS_VIRT_OFFSET,
-        boot_stack_size = const TASK_STACK_SIZE,
+        boot_stack_size = const STACK_GUARD_SIZE + TASK_STACK_SIZE,
         boot_stack = sym BOOT_STACK,
         init_boot_page_table = sym init_boot_page_table,
         init_mmu = sym init_mmu,
         entry = sym super::rust_entry,
-        options(noreturn),
     )
 }
 
 /// The earliest entry point for secondary CPUs.
 #[cfg(feature = "smp")]
-#[naked]
+#[unsafe(naked)]
 #[no_mangle]
 #[link_section = ".text.boot"]
 unsafe extern "C" fn _start_secondary() -> ! {
     // a0 = hartid
     // a1 = SP
-    core::arch::asm!("
+    core::arch::naked_asm!("
         mv      s0, a0                  // save hartid
         mv      sp, a1                  // set SP
 
@@ -88,6 +89,5 @@ unsafe extern "C" fn _start_secondary() -> ! {
         phys_virt_offset = const PHYS_VIRT_OFFSET,
         init_mmu = sym init_mmu,
         entry = sym super::rust_entry_secondary,
-        options(noreturn),
     )
 }
@@ -45,3 +45,39 @@ pub fn read_bytes(bytes: &mut [u8]) -> usize {
     ))
     .value
 }
+
+/// Enables the receive interrupt of the 16550 UART behind the SBI console,
+/// so that the input is buffered. The output still goes through SBI.
+#[cfg(feature = "irq")]
+pub(super) fn init_irq() {
+    use crate::mem::phys_to_virt;
+
+    const UART_IER: usize = 1;
+    const UART_MCR: usize = 4;
+    const UART_LSR: usize = 5;
+    const IER_RX_AVAILABLE: u8 = 1 << 0;
+    const MCR_OUT2: u8 = 1 << 3;
+    const LSR_DATA_READY: u8 = 1 << 0;
+
+    fn reg(offset: usize) -> *mut u8 {
+        phys_to_virt(pa!(axconfig::UART_PADDR + offset)).as_mut_ptr()
+    }
+
+    fn getchar() -> Option<u8> {
+        unsafe {
+            (reg(UART_LSR).read_volatile() & LSR_DATA_READY != 0).then(|| reg(0).read_volatile())
+        }
+    }
+
+    fn handle() {
+        // Reading the received data also clears the interrupt.
+        crate::console::receive(core::iter::from_fn(getchar));
+    }
+
+    crate::irq::register_handler(axconfig::UART_IRQ, handle);
+    unsafe {
+        reg(UART_MCR).write_volatile(reg(UART_MCR).read_volatile() | MCR_OUT2);
+        reg(UART_IER).write_volatile(IER_RX_AVAILABLE);
+    }
+    crate::console::enable_rx_irq();
+}
@@ -1,14 +1,25 @@
-//! TODO: PLIC
+//! Interrupts of the RISC-V local interrupt controller and the PLIC (Platform
+//! Level Interrupt Controller).
+//!
+//! The timer IRQ is numbered by its cause in `scause`, and the external IRQs
+//! by their PLIC interrupt sources.
+//!
+//! An external IRQ is delivered to the supervisor mode contexts of the harts
+//! it is routed to, where the first hart to claim it handles it.
+
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
 
 use crate::irq::IrqHandler;
+use crate::mem::phys_to_virt;
+use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
+use memory_addr::PhysAddr;
 use riscv::register::sie;
 
 /// `Interrupt` bit in `scause`
 pub(super) const INTC_IRQ_BASE: usize = 1 << (usize::BITS - 1);
 
 /// Supervisor software interrupt in `scause`
-#[allow(unused)]
 pub(super) const S_SOFT: usize = INTC_IRQ_BASE + 1;
 
 /// Supervisor timer interrupt in `scause`
@@ -18,6 +29,7 @@ pub(super) const S_TIMER: usize = INTC_IRQ_BASE + 5;
 pub(super) const S_EXT: usize = INTC_IRQ_BASE + 9;
 
 static TIMER_HANDLER: LazyInit<IrqHandler> = LazyInit::new();
+static IPI_HANDLER: LazyInit<IrqHandler> = LazyInit::new();
 
 /// The maximum number of IRQs.
 pub const MAX_IRQ_COUNT: usize = 1024;
@@ -25,38 +37,225 @@ pub const MAX_IRQ_COUNT: usize = 1024;
 /// The timer IRQ number (supervisor timer interrupt in `scause`).
 pub const TIMER_IRQ_NUM: usize = S_TIMER;
 
-macro_rules! with_cause {
-    ($cause: expr, @TIMER => $timer_op: expr, @EXT => $ext_op: expr $(,)?) => {
-        match $cause {
-            S_TIMER => $timer_op,
-            S_EXT => $ext_op,
-            _ => panic!("invalid trap cause: {:#x}", $cause),
-        }
-    };
+/// The IRQ number of the inter-processor interrupts sent by [`send_ipi`]
+/// (supervisor software interrupt in `scause`).
+pub const IPI_IRQ_NUM: usize = S_SOFT;
+
+const PLIC_BASE: PhysAddr = pa!(axconfig::PLIC_PADDR);
+
+/// Offsets in the PLIC registers.
+const PLIC_PRIORITY: usize = 0x0;
+const PLIC_ENABLE: usize = 0x2000;
+const PLIC_ENABLE_STRIDE: usize = 0x80;
+const PLIC_CONTEXT: usize = 0x20_0000;
+const PLIC_CONTEXT_STRIDE: usize = 0x1000;
+const PLIC_CONTEXT_THRESHOLD: usize = 0x0;
+const PLIC_CONTEXT_CLAIM: usize = 0x4;
+
+/// The highest priority of the PLIC on QEMU virt. The priority 0 never
+/// interrupts.
+const PLIC_MAX_PRIORITY: u8 = 7;
+/// The priority of external IRQs unless changed by [`set_priority`].
+const DEFAULT_PRIORITY: u8 = 1;
+
+/// Priorities of the external IRQs, written to the PLIC while they are
+/// enabled.
+static PRIORITIES: [AtomicU8; MAX_IRQ_COUNT] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const DEFAULT: AtomicU8 = AtomicU8::new(DEFAULT_PRIORITY);
+    [DEFAULT; MAX_IRQ_COUNT]
+};
+/// Masks of the harts the external IRQs are routed to, 0 until an IRQ is
+/// enabled or routed for the first time.
+static AFFINITIES: [AtomicUsize; MAX_IRQ_COUNT] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+    [ZERO; MAX_IRQ_COUNT]
+};
+/// Serializes the updates of the priorities and the enable bits.
+static PLIC_LOCK: SpinNoIrq<()> = SpinNoIrq::new(());
+
+fn plic_reg(offset: usize) -> *mut u32 {
+    phys_to_virt(PLIC_BASE + offset).as_mut_ptr() as *mut u32
+}
+
+/// The PLIC context of the supervisor mode of the hart `cpu_id`. Each hart
+/// has a machine mode context followed by a supervisor mode one on QEMU virt.
+const fn context(cpu_id: usize) -> usize {
+    cpu_id * 2 + 1
+}
+
+fn this_context() -> usize {
+    context(crate::cpu::this_cpu_id())
+}
+
+/// The enable register of the context `ctx` containing the bit of `irq_num`.
+fn enable_reg(ctx: usize, irq_num: usize) -> *mut u32 {
+    plic_reg(PLIC_ENABLE + ctx * PLIC_ENABLE_STRIDE + irq_num / 32 * 4)
+}
+
+fn is_external_irq(irq_num: usize) -> bool {
+    irq_num != 0 && irq_num < MAX_IRQ_COUNT
+}
+
+/// Sets the enable bits of the external IRQ in the contexts of the harts in
+/// `cpu_mask`, and clears them in the others. `PLIC_LOCK` must be held.
+///
+/// The pending bit of the IRQ is kept by the PLIC, so a pending IRQ is
+/// delivered to the new harts. The bits are set before the others are
+/// cleared, so it is deliverable all the time.
+fn route(irq_num: usize, cpu_mask: usize) {
+    let bit = 1 << (irq_num % 32);
+    for cpu_id in (0..axconfig::SMP).filter(|&cpu_id| cpu_mask & 1 << cpu_id != 0) {
+        let enable = enable_reg(context(cpu_id), irq_num);
+        unsafe { enable.write_volatile(enable.read_volatile() | bit) };
+    }
+    for cpu_id in (0..axconfig::SMP).filter(|&cpu_id| cpu_mask & 1 << cpu_id == 0) {
+        let enable = enable_reg(context(cpu_id), irq_num);
+        unsafe { enable.write_volatile(enable.read_volatile() & !bit) };
+    }
+    AFFINITIES[irq_num].store(cpu_mask, Ordering::Relaxed);
 }
 
 /// Enables or disables the given IRQ.
-pub fn set_enable(scause: usize, _enabled: bool) {
-    if scause == S_EXT {
-        // TODO: set enable in PLIC
+///
+/// External IRQs are enabled at their priorities, toward the current hart
+/// unless they are routed by [`set_affinity`] or [`set_affinity_mask`].
+/// They are disabled by the priority 0, as the completion of an IRQ whose
+/// enable bit is cleared in its handler would be ignored.
+pub fn set_enable(irq_num: usize, enabled: bool) {
+    if !is_external_irq(irq_num) {
+        return;
+    }
+    let _lock = PLIC_LOCK.lock();
+    let priority = if enabled {
+        PRIORITIES[irq_num].load(Ordering::Relaxed)
+    } else {
+        0
+    };
+    unsafe { plic_reg(PLIC_PRIORITY + irq_num * 4).write_volatile(priority as u32) };
+    if enabled && AFFINITIES[irq_num].load(Ordering::Relaxed) == 0 {
+        route(irq_num, 1 << crate::cpu::this_cpu_id());
     }
 }
 
+/// Sets the priority of the given external IRQ, from 1 to 7, where a higher
+/// value is more urgent. It is 1 by default.
+///
+/// Returns `false` if the IRQ or the priority is invalid.
+pub fn set_priority(irq_num: usize, priority: u8) -> bool {
+    if !is_external_irq(irq_num) || !(1..=PLIC_MAX_PRIORITY).contains(&priority) {
+        return false;
+    }
+    let _lock = PLIC_LOCK.lock();
+    PRIORITIES[irq_num].store(priority, Ordering::Relaxed);
+    let reg = plic_reg(PLIC_PRIORITY + irq_num * 4);
+    // Leave it disabled if it is.
+    if unsafe { reg.read_volatile() } != 0 {
+        unsafe { reg.write_volatile(priority as u32) };
+    }
+    true
+}
+
+/// Sets the priority threshold of the current hart, from 0 to 7, so that
+/// only the external IRQs of higher priorities interrupt it. It is 0 by
+/// default.
+///
+/// Returns `false` if the threshold is invalid.
+pub fn set_threshold(threshold: u8) -> bool {
+    if threshold > PLIC_MAX_PRIORITY {
+        return false;
+    }
+    let context = PLIC_CONTEXT + this_context() * PLIC_CONTEXT_STRIDE;
+    unsafe { plic_reg(context + PLIC_CONTEXT_THRESHOLD).write_volatile(threshold as u32) };
+    true
+}
+
 /// Registers an IRQ handler for the given IRQ.
 ///
 /// It also enables the IRQ if the registration succeeds. It returns `false` if
 /// the registration failed.
-pub fn register_handler(scause: usize, handler: IrqHandler) -> bool {
-    with_cause!(
-        scause,
-        @TIMER => if !TIMER_HANDLER.is_inited() {
-            TIMER_HANDLER.init_once(handler);
-            true
-        } else {
-            false
-        },
-        @EXT => crate::irq::register_handler_common(scause & !INTC_IRQ_BASE, handler),
-    )
+pub fn register_handler(irq_num: usize, handler: IrqHandler) -> bool {
+    let lazy_handler = match irq_num {
+        S_TIMER => &TIMER_HANDLER,
+        S_SOFT => &IPI_HANDLER,
+        _ => return crate::irq::register_handler_common(irq_num, handler),
+    };
+    if !lazy_handler.is_inited() {
+        lazy_handler.init_once(handler);
+        return true;
+    }
+    false
+}
+
+/// Sends an inter-processor interrupt ([`IPI_IRQ_NUM`]) to the hart `cpu_id`
+/// through the SBI.
+pub fn send_ipi(cpu_id: usize) {
+    sbi_rt::send_ipi(sbi_rt::HartMask::from_mask_base(1, cpu_id));
+}
+
+/// Sends a TLB shootdown IPI to the hart `cpu_id`, see [`crate::tlb`].
+///
+/// The single software interrupt is shared with [`IPI_IRQ_NUM`], and a
+/// pending shootdown is checked on each of them.
+#[cfg(feature = "smp")]
+pub(crate) fn send_tlb_ipi(cpu_id: usize) {
+    send_ipi(cpu_id);
+}
+
+/// Routes the given external IRQ to the CPU `cpu_id`.
+pub fn set_affinity(irq_num: usize, cpu_id: usize) -> bool {
+    cpu_id < axconfig::SMP && set_affinity_mask(irq_num, 1 << cpu_id)
+}
+
+/// Routes the given external IRQ to the CPUs in `cpu_mask`, where bit `i`
+/// stands for the CPU `i`. Each interrupt is handled by one of them.
+///
+/// Returns `false` if the IRQ is invalid, or the mask is empty or contains
+/// CPUs that do not exist.
+pub fn set_affinity_mask(irq_num: usize, cpu_mask: usize) -> bool {
+    let all_cpus = usize::MAX >> (usize::BITS as usize - axconfig::SMP);
+    if !is_external_irq(irq_num) || cpu_mask == 0 || cpu_mask & !all_cpus != 0 {
+        return false;
+    }
+    let _lock = PLIC_LOCK.lock();
+    route(irq_num, cpu_mask);
+    true
+}
+
+/// Returns the IRQ number of the GSI (global system interrupt), which is
+/// the same number on this platform.
+pub fn gsi_irq(gsi: usize) -> Option<usize> {
+    Some(gsi)
+}
+
+/// Routes the GSI to the given IRQ.
+///
+/// GSIs are routed as configured by the firmware, so it always returns
+/// `false`.
+pub fn route_gsi(
+    _gsi: usize,
+    _irq_num: usize,
+    _cpu_id: usize,
+    _trigger: crate::irq::TriggerMode,
+    _polarity: crate::irq::Polarity,
+) -> bool {
+    false
+}
+
+/// Allocates `count` contiguous vectors for message-signaled interrupts.
+///
+/// MSIs are not supported without an IMSIC, so it always returns `None`.
+pub fn alloc_msi(_count: usize) -> Option<usize> {
+    None
+}
+
+/// Frees `count` vectors from `irq_num` allocated by [`alloc_msi`].
+pub fn free_msi(_irq_num: usize, _count: usize) {}
+
+/// Returns the message a device writes to raise the MSI `irq_num`.
+pub fn msi_message(_irq_num: usize) -> crate::irq::MsiMessage {
+    unreachable!("no MSI vectors are allocated without an IMSIC")
 }
 
 /// Dispatches the IRQ.
@@ -65,17 +264,60 @@ pub fn register_handler(scause: usize, handler: IrqHandler) -> bool {
 /// up in the IRQ handler table and calls the corresponding handler. If
 /// necessary, it also acknowledges the interrupt controller after handling.
 pub fn dispatch_irq(scause: usize) {
-    with_cause!(
-        scause,
-        @TIMER => {
+    match scause {
+        S_TIMER => {
             trace!("IRQ: timer");
             TIMER_HANDLER();
-        },
-        @EXT => crate::irq::dispatch_irq_common(0), // TODO: get IRQ number from PLIC
-    );
+        }
+        S_SOFT => {
+            trace!("IRQ: IPI");
+            unsafe { riscv::register::sip::clear_ssoft() };
+            crate::tlb::handle_ipi();
+            if let Some(handler) = IPI_HANDLER.get() {
+                handler();
+            }
+        }
+        S_EXT => {
+            let context = PLIC_CONTEXT + this_context() * PLIC_CONTEXT_STRIDE;
+            let claim = plic_reg(context + PLIC_CONTEXT_CLAIM);
+            loop {
+                let irq_num = unsafe { claim.read_volatile() };
+                if irq_num == 0 {
+                    break;
+                }
+                crate::irq::dispatch_irq_common(irq_num as usize);
+                complete(claim, irq_num);
+            }
+        }
+        _ => panic!("invalid trap cause: {:#x}", scause),
+    }
+}
+
+/// Signals the completion of the external IRQ claimed by the current hart
+/// through its `claim` register.
+///
+/// The completion is ignored if the IRQ is not enabled for the context, so
+/// if it has been routed away meanwhile, it is enabled during the completion.
+fn complete(claim: *mut u32, irq_num: u32) {
+    let _lock = PLIC_LOCK.lock();
+    let enable = enable_reg(this_context(), irq_num as usize);
+    let bits = unsafe { enable.read_volatile() };
+    let bit = 1 << (irq_num % 32);
+    unsafe {
+        if bits & bit == 0 {
+            enable.write_volatile(bits | bit);
+            claim.write_volatile(irq_num);
+            enable.write_volatile(bits);
+        } else {
+            claim.write_volatile(irq_num);
+        }
+    }
 }
 
 pub(super) fn init_percpu() {
+    // accept external interrupts of all priorities
+    let context = PLIC_CONTEXT + this_context() * PLIC_CONTEXT_STRIDE;
+    unsafe { plic_reg(context + PLIC_CONTEXT_THRESHOLD).write_volatile(0) };
     // enable soft interrupts, timer interrupts, and external interrupts
     unsafe {
         sie::set_ssoft();
@@ -1,5 +1,8 @@
 use crate::mem::MemRegion;
 
+/// The RAM mapped by the boot page table, see `boot.rs`.
+pub(crate) const BOOT_MAPPED_MEMORY: (usize, usize) = (0x8000_0000, 0xc000_0000);
+
 /// Returns platform-specific memory regions.
 pub(crate) fn platform_regions() -> impl Iterator<Item = MemRegion> {
     crate::mem::default_free_regions().chain(crate::mem::default_mmio_regions())
@@ -20,6 +20,8 @@ extern "C" {
 
 unsafe extern "C" fn rust_entry(cpu_id: usize, dtb: usize) {
     crate::mem::clear_bss();
+    crate::dtb::init_early(dtb);
+    crate::numa::init_early();
     crate::cpu::init_primary(cpu_id);
     crate::arch::set_trap_vector_base(trap_vector_base as usize);
     self::time::init_early();
@@ -40,6 +42,8 @@ pub fn platform_init() {
     #[cfg(feature = "irq")]
     self::irq::init_percpu();
     self::time::init_percpu();
+    #[cfg(feature = "irq")]
+    self::console::init_irq();
 }
 
 /// Initializes the platform devices for secondary CPUs.
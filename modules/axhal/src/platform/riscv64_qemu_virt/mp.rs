@@ -12,3 +12,13 @@ pub fn start_secondary_cpu(hartid: usize, stack_top: PhysAddr) {
     let entry = virt_to_phys(va!(_start_secondary as usize));
     sbi_rt::hart_start(hartid, entry.as_usize(), stack_top.as_usize());
 }
+
+/// Stops the current hart, which can be started again by
+/// [`start_secondary_cpu`].
+pub(crate) fn cpu_off() -> ! {
+    sbi_rt::hart_stop();
+    warn!("It should be stopped!");
+    loop {
+        crate::arch::halt();
+    }
+}
@@ -1,57 +1,64 @@
 use riscv::register::time;
 
-const NANOS_PER_TICK: u64 = crate::time::NANOS_PER_SEC / axconfig::TIMER_FREQUENCY as u64;
-/// RTC wall time offset in nanoseconds at monotonic time base.
-static mut RTC_EPOCHOFFSET_NANOS: u64 = 0;
-
 /// Returns the current clock time in hardware ticks.
 #[inline]
 pub fn current_ticks() -> u64 {
     time::read() as u64
 }
 
-/// Converts hardware ticks to nanoseconds.
-#[inline]
-pub const fn ticks_to_nanos(ticks: u64) -> u64 {
-    ticks * NANOS_PER_TICK
+/// Set a one-shot timer.
+///
+/// A timer interrupt will be triggered at the specified monotonic time deadline (in nanoseconds).
+#[cfg(feature = "irq")]
+pub fn set_oneshot_timer(deadline_ns: u64) {
+    sbi_rt::set_timer(crate::time::nanos_to_ticks(deadline_ns));
 }
 
-/// Converts nanoseconds to hardware ticks.
-#[inline]
-pub const fn nanos_to_ticks(nanos: u64) -> u64 {
-    nanos / NANOS_PER_TICK
+/// Returns the goldfish RTC, if `RTC_PADDR` is given in the platform config.
+#[cfg(feature = "rtc")]
+fn goldfish() -> Option<riscv_goldfish::Rtc> {
+    use crate::mem::phys_to_virt;
+    use memory_addr::PhysAddr;
+
+    const GOLDFISH_BASE: PhysAddr = pa!(axconfig::RTC_PADDR);
+    if axconfig::RTC_PADDR == 0 {
+        return None;
+    }
+    let base_vaddr = phys_to_virt(GOLDFISH_BASE).as_usize();
+    Some(riscv_goldfish::Rtc::new(base_vaddr))
 }
 
-/// Return epoch offset in nanoseconds (wall time offset to monotonic clock start).
-pub fn epochoffset_nanos() -> u64 {
-    unsafe { RTC_EPOCHOFFSET_NANOS }
+/// Reads the RTC in seconds since the epoch (1970-01-01).
+#[cfg(feature = "rtc")]
+pub(crate) fn read_rtc() -> Option<u64> {
+    goldfish().map(|rtc| rtc.get_unix_timestamp())
 }
 
-/// Set a one-shot timer.
-///
-/// A timer interrupt will be triggered at the specified monotonic time deadline (in nanoseconds).
-#[cfg(feature = "irq")]
-pub fn set_oneshot_timer(deadline_ns: u64) {
-    sbi_rt::set_timer(nanos_to_ticks(deadline_ns));
+/// Writes the RTC in seconds since the epoch (1970-01-01).
+#[cfg(feature = "rtc")]
+pub(crate) fn write_rtc(secs: u64) {
+    if let Some(rtc) = goldfish() {
+        rtc.set_unix_timestamp(secs);
+    }
+}
+
+/// Returns the `timebase-frequency` in the device tree, which is in the
+/// `/cpus` node, or in the CPU nodes on some platforms.
+fn timebase_frequency() -> Option<u64> {
+    let fdt = crate::fdt::fdt()?;
+    let freq = |node: crate::fdt::Node| node.property_u64("timebase-frequency");
+    fdt.find_node("/cpus")
+        .and_then(freq)
+        .or_else(|| crate::arch::boot_cpu_node().and_then(freq))
+        .filter(|&freq| freq > 0)
 }
 
 pub(super) fn init_early() {
-    #[cfg(feature = "rtc")]
-    if axconfig::RTC_PADDR != 0 {
-        use crate::mem::phys_to_virt;
-        use memory_addr::PhysAddr;
-        use riscv_goldfish::Rtc;
-
-        const GOLDFISH_BASE: PhysAddr = pa!(axconfig::RTC_PADDR);
-        // Get the current time in microseconds since the epoch (1970-01-01) from the riscv RTC.
-        // Subtract the timer ticks to get the actual time when ArceOS was booted.
-        let epoch_time_nanos =
-            Rtc::new(phys_to_virt(GOLDFISH_BASE).as_usize()).get_unix_timestamp() * 1_000_000_000;
-
-        unsafe {
-            RTC_EPOCHOFFSET_NANOS = epoch_time_nanos - ticks_to_nanos(current_ticks());
-        }
+    match timebase_frequency() {
+        Some(freq) => crate::time::init_timer_frequency(freq, "device tree"),
+        None => crate::time::init_timer_frequency(axconfig::TIMER_FREQUENCY as u64, "config"),
     }
+    crate::time::init_wall_time();
 }
 
 pub(super) fn init_percpu() {
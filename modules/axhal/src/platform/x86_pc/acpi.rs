@@ -0,0 +1,283 @@
+//! Lookup of ACPI tables left by the firmware.
+//!
+//! The tables are read through the boot page table, which maps the whole
+//! low 4 GiB, so the lookup must happen in the early initialization. The
+//! RSDP is the one given by the UEFI firmware if booted by it, see
+//! [`set_rsdp`], or is searched in the EBDA and the BIOS ROM otherwise, as
+//! the multiboot (v1) information does not carry it. Tables with bad
+//! checksums are ignored.
+//!
+//! The CPUs in the MADT and the PCI Express ECAM in the MCFG are recorded by
+//! [`init_early`]. Without ACPI tables, all the CPUs of the platform config
+//! are assumed to exist, and the ECAM is the one of the platform config. The
+//! NUMA nodes in the SRAT are parsed by [`parse_srat`].
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use lazyinit::LazyInit;
+
+use crate::mem::phys_to_virt;
+use crate::numa::Topology;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+/// Size of the header common to all system description tables.
+pub(super) const SDT_HEADER_SIZE: usize = 36;
+
+/// Physical address of the pointer to the EBDA (Extended BIOS Data Area).
+const EBDA_PTR_PADDR: usize = 0x40e;
+/// The BIOS read-only memory area, where the RSDP may be.
+const BIOS_ROM: (usize, usize) = (0xe_0000, 0x2_0000);
+
+/// The MADT entries follow the local APIC address and the flags.
+const MADT_ENTRIES: usize = SDT_HEADER_SIZE + 8;
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_LOCAL_X2APIC: u8 = 9;
+/// Flags of the local APIC entries: the CPU is enabled, or can be enabled.
+const MADT_LAPIC_ENABLED: u32 = 1 << 0;
+const MADT_LAPIC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+/// The MCFG entries follow 8 reserved bytes, each is `base: u64,
+/// segment: u16, start_bus: u8, end_bus: u8, reserved: u32`.
+const MCFG_ENTRIES: usize = SDT_HEADER_SIZE + 8;
+const MCFG_ENTRY_SIZE: usize = 16;
+
+/// The SRAT entries follow 12 reserved bytes.
+const SRAT_ENTRIES: usize = SDT_HEADER_SIZE + 12;
+const SRAT_LOCAL_APIC: u8 = 0;
+const SRAT_MEMORY: u8 = 1;
+const SRAT_LOCAL_X2APIC: u8 = 2;
+/// Flags of all the SRAT entries: the entry is enabled.
+const SRAT_ENABLED: u32 = 1 << 0;
+
+/// The PCI Express ECAM of segment 0 found in the MCFG.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct PciEcam {
+    /// Physical address of the configuration space of bus 0.
+    pub base: usize,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+/// The system information found in the ACPI tables.
+struct AcpiInfo {
+    /// Bitmap of the usable CPUs by their APIC IDs, `None` without a MADT.
+    cpus: Option<[u64; 4]>,
+    ecam: Option<PciEcam>,
+}
+
+static ACPI_INFO: LazyInit<AcpiInfo> = LazyInit::new();
+
+/// Physical address of the RSDP given by the firmware, 0 if none.
+static RSDP_PADDR: AtomicUsize = AtomicUsize::new(0);
+
+/// Records the RSDP given by the firmware, e.g., in the UEFI configuration
+/// table, which is used instead of searching the BIOS areas.
+#[allow(dead_code)]
+pub(super) fn set_rsdp(paddr: usize) {
+    RSDP_PADDR.store(paddr, Ordering::Relaxed);
+}
+
+/// Returns the bytes of the physical memory at `paddr`.
+///
+/// # Safety
+///
+/// The memory must be mapped in the linear mapping and not be written.
+pub(super) unsafe fn phys_bytes<'a>(paddr: usize, len: usize) -> &'a [u8] {
+    core::slice::from_raw_parts(phys_to_virt(pa!(paddr)).as_ptr(), len)
+}
+
+pub(super) fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+pub(super) fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn checksum_ok(data: &[u8]) -> bool {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+/// Returns the bytes of the system description table at `paddr` with its
+/// length in the header.
+///
+/// # Safety
+///
+/// It must be a valid table, as for [`phys_bytes`].
+pub(super) unsafe fn table_bytes<'a>(paddr: usize) -> &'a [u8] {
+    let len = read_u32(phys_bytes(paddr, SDT_HEADER_SIZE), 4) as usize;
+    phys_bytes(paddr, len.max(SDT_HEADER_SIZE))
+}
+
+/// Finds the RSDP in the first KiB of the EBDA, or in the BIOS ROM, unless
+/// it is given by the firmware.
+///
+/// The ACPI 1.0 part is checked, and also the extended part of later
+/// revisions.
+fn find_rsdp() -> Option<usize> {
+    let given = RSDP_PADDR.load(Ordering::Relaxed);
+    if given != 0 {
+        return Some(given);
+    }
+    let ebda = unsafe { read_u32(phys_bytes(EBDA_PTR_PADDR, 4), 0) } as usize & 0xffff;
+    let areas = [(ebda << 4, 0x400), BIOS_ROM];
+    areas
+        .into_iter()
+        .filter(|&(base, _)| base != 0)
+        .find_map(|(base, len)| {
+            (base..base + len).step_by(16).find(|&paddr| {
+                let rsdp = unsafe { phys_bytes(paddr, 20) };
+                if !rsdp.starts_with(RSDP_SIGNATURE) || !checksum_ok(rsdp) {
+                    return false;
+                }
+                rsdp[15] < 2 || {
+                    let len = unsafe { read_u32(phys_bytes(paddr, 24), 20) } as usize;
+                    len >= 36 && checksum_ok(unsafe { phys_bytes(paddr, len) })
+                }
+            })
+        })
+}
+
+/// Returns the physical address of the first ACPI table with the given
+/// signature, from the XSDT if the RSDP is of ACPI 2.0 or later, or from the
+/// RSDT otherwise.
+pub(super) fn find_table(signature: &[u8; 4]) -> Option<usize> {
+    let rsdp = unsafe { phys_bytes(find_rsdp()?, 36) };
+    let (root, entry_size) = if rsdp[15] >= 2 {
+        (read_u64(rsdp, 24) as usize, 8)
+    } else {
+        (read_u32(rsdp, 16) as usize, 4)
+    };
+    let root_table = unsafe { table_bytes(root) };
+    if !checksum_ok(root_table) {
+        warn!("ACPI: bad checksum of the root table at {:#x}", root);
+        return None;
+    }
+    root_table[SDT_HEADER_SIZE..]
+        .chunks_exact(entry_size)
+        .map(|entry| match entry_size {
+            8 => read_u64(entry, 0) as usize,
+            _ => read_u32(entry, 0) as usize,
+        })
+        .filter(|&table| unsafe { phys_bytes(table, 4) } == signature)
+        .find(|&table| {
+            let ok = checksum_ok(unsafe { table_bytes(table) });
+            if !ok {
+                let signature = core::str::from_utf8(signature).unwrap_or("?");
+                warn!("ACPI: bad checksum of table {} at {:#x}", signature, table);
+            }
+            ok
+        })
+}
+
+/// Returns the entries of the MADT as their types and bytes.
+pub(super) fn madt_entries() -> impl Iterator<Item = (u8, &'static [u8])> {
+    let data = find_table(b"APIC").map_or(&[][..], |table| unsafe { table_bytes(table) });
+    let mut pos = MADT_ENTRIES;
+    core::iter::from_fn(move || {
+        let header = data.get(pos..pos + 2)?;
+        let (ty, len) = (header[0], header[1] as usize);
+        let entry = data.get(pos..pos + len).filter(|_| len >= 2)?;
+        pos += len;
+        Some((ty, entry))
+    })
+}
+
+/// Returns the bitmap of the APIC IDs of the enabled or online-capable CPUs
+/// in the MADT. Only 8-bit APIC IDs are recorded.
+fn parse_madt_cpus() -> Option<[u64; 4]> {
+    find_table(b"APIC")?;
+    let mut cpus = [0u64; 4];
+    for (ty, entry) in madt_entries() {
+        let (apic_id, flags) = match ty {
+            MADT_LOCAL_APIC if entry.len() >= 8 => (entry[3] as u32, read_u32(entry, 4)),
+            MADT_LOCAL_X2APIC if entry.len() >= 16 => (read_u32(entry, 4), read_u32(entry, 8)),
+            _ => continue,
+        };
+        if flags & (MADT_LAPIC_ENABLED | MADT_LAPIC_ONLINE_CAPABLE) != 0 && apic_id < 256 {
+            cpus[apic_id as usize / 64] |= 1 << (apic_id % 64);
+        }
+    }
+    Some(cpus)
+}
+
+/// Returns the ECAM of PCI segment 0 in the MCFG.
+fn parse_mcfg() -> Option<PciEcam> {
+    let data = unsafe { table_bytes(find_table(b"MCFG")?) };
+    data.get(MCFG_ENTRIES..)?
+        .chunks_exact(MCFG_ENTRY_SIZE)
+        .find(|entry| u16::from_le_bytes([entry[8], entry[9]]) == 0)
+        .map(|entry| PciEcam {
+            base: read_u64(entry, 0) as usize,
+            start_bus: entry[10],
+            end_bus: entry[11],
+        })
+}
+
+/// Returns the NUMA nodes of the CPUs and the memory in the SRAT, whose
+/// proximity domains are the node IDs, and APIC IDs are the CPU IDs.
+pub(super) fn parse_srat() -> Option<Topology> {
+    let data = unsafe { table_bytes(find_table(b"SRAT")?) };
+    let mut topo = Topology::new();
+    let mut pos = SRAT_ENTRIES;
+    while let Some(header) = data.get(pos..pos + 2) {
+        let (ty, len) = (header[0], header[1] as usize);
+        let Some(entry) = data.get(pos..pos + len).filter(|_| len >= 2) else {
+            break;
+        };
+        pos += len;
+        match ty {
+            SRAT_LOCAL_APIC if len >= 16 && read_u32(entry, 4) & SRAT_ENABLED != 0 => {
+                // The proximity domain is split into bits [7:0] and [31:8].
+                let domain = u32::from_le_bytes([entry[2], entry[9], entry[10], entry[11]]);
+                topo.add_cpu(entry[3] as usize, domain);
+            }
+            SRAT_MEMORY if len >= 40 && read_u32(entry, 28) & SRAT_ENABLED != 0 => {
+                let (base, size) = (read_u64(entry, 8), read_u64(entry, 16));
+                topo.add_memory(base as usize, size as usize, read_u32(entry, 2));
+            }
+            SRAT_LOCAL_X2APIC if len >= 24 && read_u32(entry, 12) & SRAT_ENABLED != 0 => {
+                topo.add_cpu(read_u32(entry, 8) as usize, read_u32(entry, 4));
+            }
+            _ => {}
+        }
+    }
+    Some(topo)
+}
+
+/// Records the CPUs in the MADT and the PCI Express ECAM in the MCFG.
+pub(super) fn init_early() {
+    let info = AcpiInfo {
+        cpus: parse_madt_cpus(),
+        ecam: parse_mcfg(),
+    };
+    if let Some(cpus) = info.cpus {
+        let count = cpus.iter().map(|w| w.count_ones() as usize).sum::<usize>();
+        if count > axconfig::SMP {
+            warn!(
+                "ACPI: {} CPUs found, only {} are used (`SMP` of the config)",
+                count,
+                axconfig::SMP
+            );
+        }
+    }
+    if let Some(ecam) = info.ecam {
+        debug!("ACPI: PCI ECAM {:x?}", ecam);
+    }
+    ACPI_INFO.init_once(info);
+}
+
+/// Whether the CPU with the APIC ID is usable. All CPUs are assumed to be
+/// usable without a MADT.
+#[allow(dead_code)]
+pub(super) fn cpu_present(apic_id: usize) -> bool {
+    match ACPI_INFO.cpus {
+        Some(cpus) => apic_id < 256 && cpus[apic_id / 64] & 1 << (apic_id % 64) != 0,
+        None => true,
+    }
+}
+
+/// Returns the PCI Express ECAM of segment 0 in the MCFG, if any.
+pub(super) fn pci_ecam() -> Option<PciEcam> {
+    ACPI_INFO.ecam
+}
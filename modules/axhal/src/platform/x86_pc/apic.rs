@@ -1,19 +1,24 @@
 #![allow(dead_code)]
 
+use core::sync::atomic::{AtomicU8, Ordering};
+
 use kspin::SpinNoIrq;
-use lazyinit::LazyInit;
-use memory_addr::PhysAddr;
-use x2apic::ioapic::IoApic;
 use x2apic::lapic::{xapic_base, LocalApic, LocalApicBuilder};
 use x86_64::instructions::port::Port;
 
 use self::vectors::*;
+use super::ioapic;
 use crate::mem::phys_to_virt;
 
 pub(super) mod vectors {
     pub const APIC_TIMER_VECTOR: u8 = 0xf0;
     pub const APIC_SPURIOUS_VECTOR: u8 = 0xf1;
     pub const APIC_ERROR_VECTOR: u8 = 0xf2;
+    pub const APIC_IPI_VECTOR: u8 = 0xf3;
+    pub const APIC_TLB_VECTOR: u8 = 0xf4;
+    pub const IO_APIC_VECTOR_START: u8 = 0x20;
+    pub const MSI_VECTOR_START: u8 = 0x40;
+    pub const MSI_VECTOR_COUNT: u8 = 128;
 }
 
 /// The maximum number of IRQs.
@@ -22,27 +27,42 @@ pub const MAX_IRQ_COUNT: usize = 256;
 /// The timer IRQ number.
 pub const TIMER_IRQ_NUM: usize = APIC_TIMER_VECTOR as usize;
 
-const IO_APIC_BASE: PhysAddr = pa!(0xFEC0_0000);
+/// The IRQ number of the inter-processor interrupts sent by [`send_ipi`].
+pub const IPI_IRQ_NUM: usize = APIC_IPI_VECTOR as usize;
+
+/// The base of the MSI doorbell address, the destination APIC ID is put in
+/// bits 12..20.
+const MSI_ADDRESS_BASE: u64 = 0xFEE0_0000;
 
 static mut LOCAL_APIC: Option<LocalApic> = None;
 static mut IS_X2APIC: bool = false;
-static IO_APIC: LazyInit<SpinNoIrq<IoApic>> = LazyInit::new();
+
+/// Bitmap of the allocated MSI vectors, bit `n` is for vector
+/// `MSI_VECTOR_START + n`.
+static MSI_VECTORS: SpinNoIrq<u128> = SpinNoIrq::new(0);
+
+/// Destination APIC IDs of the MSI vectors.
+static MSI_DESTINATIONS: [AtomicU8; MSI_VECTOR_COUNT as usize] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU8 = AtomicU8::new(0);
+    [ZERO; MSI_VECTOR_COUNT as usize]
+};
 
 /// Enables or disables the given IRQ.
 #[cfg(feature = "irq")]
 pub fn set_enable(vector: usize, enabled: bool) {
-    // should not affect LAPIC interrupts
-    if vector < APIC_TIMER_VECTOR as _ {
-        unsafe {
-            if enabled {
-                IO_APIC.lock().enable_irq(vector as u8);
-            } else {
-                IO_APIC.lock().disable_irq(vector as u8);
-            }
-        }
+    // should not affect LAPIC interrupts, MSIs are masked by the device
+    if ioapic::is_io_apic_vector(vector) {
+        ioapic::set_vector_masked(vector, !enabled);
     }
 }
 
+/// Returns the vector of the given ISA IRQ, by the GSI it is connected to.
+#[cfg(feature = "irq")]
+pub(super) fn isa_irq_vector(irq: u8) -> Option<usize> {
+    ioapic::gsi_vector(ioapic::isa_irq_gsi(irq))
+}
+
 /// Registers an IRQ handler for the given IRQ.
 ///
 /// It also enables the IRQ if the registration succeeds. It returns `false` if
@@ -52,20 +72,157 @@ pub fn register_handler(vector: usize, handler: crate::irq::IrqHandler) -> bool
     crate::irq::register_handler_common(vector, handler)
 }
 
+/// Returns the vector that the GSI (global system interrupt) raises, whose
+/// handler is registered by [`register_handler`].
+///
+/// GSI `n` raises the vector `0x20 + n` by default, so only GSIs below 32
+/// have a vector unless routed by [`route_gsi`].
+#[cfg(feature = "irq")]
+pub fn gsi_irq(gsi: usize) -> Option<usize> {
+    ioapic::gsi_vector(gsi.try_into().ok()?)
+}
+
+/// Routes the GSI to `vector` on the CPU `cpu_id` by the IO APIC, with the
+/// given trigger mode and polarity. The GSI stays masked until the vector is
+/// enabled.
+///
+/// The vector must be one of the IO APICs, from 0x20 to 0x3f, and not taken
+/// by another GSI. The local APIC signals the end of a level-triggered
+/// interrupt to the IO APIC after the handler returns, so the handler must
+/// make the device deassert the line, or mask the vector by [`set_enable`],
+/// otherwise it is raised again at once.
+#[cfg(feature = "irq")]
+pub fn route_gsi(
+    gsi: usize,
+    vector: usize,
+    cpu_id: usize,
+    trigger: crate::irq::TriggerMode,
+    polarity: crate::irq::Polarity,
+) -> bool {
+    use crate::irq::{Polarity, TriggerMode};
+    // Only 8-bit destination IDs fit in the entry, the CPU ID is the APIC ID.
+    let (Ok(gsi), Ok(apic_id)) = (gsi.try_into(), cpu_id.try_into()) else {
+        return false;
+    };
+    let level = trigger == TriggerMode::Level;
+    ioapic::route(gsi, vector, apic_id, level, polarity == Polarity::ActiveLow)
+}
+
+/// Sends an inter-processor interrupt ([`IPI_IRQ_NUM`]) to the CPU `cpu_id`.
+#[cfg(feature = "irq")]
+pub fn send_ipi(cpu_id: usize) {
+    unsafe { local_apic().send_ipi(APIC_IPI_VECTOR, raw_apic_id(cpu_id as u8)) };
+}
+
+/// Sends a TLB shootdown IPI to the CPU `cpu_id`, see [`crate::tlb`].
+#[cfg(all(feature = "irq", feature = "smp"))]
+pub(crate) fn send_tlb_ipi(cpu_id: usize) {
+    unsafe { local_apic().send_ipi(APIC_TLB_VECTOR, raw_apic_id(cpu_id as u8)) };
+}
+
 /// Dispatches the IRQ.
 ///
 /// This function is called by the common interrupt handler. It looks
 /// up in the IRQ handler table and calls the corresponding handler. If
 /// necessary, it also acknowledges the interrupt controller after handling.
+///
+/// The EOI is broadcast to the IO APICs for level-triggered interrupts, which
+/// lets them deliver the interrupt again.
 #[cfg(feature = "irq")]
 pub fn dispatch_irq(vector: usize) {
-    crate::irq::dispatch_irq_common(vector);
+    if vector == APIC_TLB_VECTOR as usize {
+        crate::tlb::handle_ipi();
+    } else {
+        crate::irq::dispatch_irq_common(vector);
+    }
     unsafe { local_apic().end_of_interrupt() };
 }
 
+/// Allocates `count` contiguous vectors for message-signaled interrupts, and
+/// returns the first one.
+///
+/// The vectors target the current CPU. Returns `None` if there is no free
+/// block, see [`alloc_msi_block`](crate::irq::alloc_msi_block).
+#[cfg(feature = "irq")]
+pub fn alloc_msi(count: usize) -> Option<usize> {
+    let idx = crate::irq::alloc_msi_block(&mut MSI_VECTORS.lock(), MSI_VECTOR_COUNT as _, count)?;
+    let apic_id = super::current_cpu_id() as u8;
+    for dest in &MSI_DESTINATIONS[idx..idx + count] {
+        dest.store(apic_id, Ordering::Relaxed);
+    }
+    Some(MSI_VECTOR_START as usize + idx)
+}
+
+/// Frees `count` vectors from `vector` allocated by [`alloc_msi`].
+#[cfg(feature = "irq")]
+pub fn free_msi(vector: usize, count: usize) {
+    if let Some(idx) = vector.checked_sub(MSI_VECTOR_START as usize) {
+        crate::irq::free_msi_block(&mut MSI_VECTORS.lock(), MSI_VECTOR_COUNT as _, idx, count);
+    }
+}
+
+/// Returns the message a device writes to raise the MSI `vector`, which
+/// targets the APIC ID set by [`set_affinity`].
+#[cfg(feature = "irq")]
+pub fn msi_message(vector: usize) -> crate::irq::MsiMessage {
+    let apic_id = MSI_DESTINATIONS[vector - MSI_VECTOR_START as usize].load(Ordering::Relaxed);
+    crate::irq::MsiMessage {
+        address: MSI_ADDRESS_BASE | (apic_id as u64) << 12,
+        data: vector as u32, // edge triggered, fixed delivery mode
+    }
+}
+
+/// Routes the given IRQ to the CPU `cpu_id`.
+///
+/// IO APIC and MSI vectors can be routed. The destination of an MSI vector is
+/// in the message, so the message of [`msi_message`] must be written to the
+/// device again.
+#[cfg(feature = "irq")]
+pub fn set_affinity(vector: usize, cpu_id: usize) -> bool {
+    let start = MSI_VECTOR_START as usize;
+    // Only 8-bit destination IDs fit in the message or the IO APIC entry
+    // without interrupt remapping, the CPU ID is the APIC ID.
+    if cpu_id > 0xff {
+        return false;
+    }
+    if ioapic::is_io_apic_vector(vector) {
+        return ioapic::set_vector_destination(vector, cpu_id as u8);
+    }
+    if !(start..start + MSI_VECTOR_COUNT as usize).contains(&vector) {
+        return false;
+    }
+    MSI_DESTINATIONS[vector - start].store(cpu_id as u8, Ordering::Relaxed);
+    true
+}
+
+/// Routes the given IRQ to the CPUs in `cpu_mask`, where bit `i` stands for
+/// the CPU `i`.
+///
+/// Only a single CPU can be targeted, see [`set_affinity`].
+#[cfg(feature = "irq")]
+pub fn set_affinity_mask(vector: usize, cpu_mask: usize) -> bool {
+    cpu_mask.count_ones() == 1 && set_affinity(vector, cpu_mask.trailing_zeros() as usize)
+}
+
+/// Sets the priority of the given IRQ.
+///
+/// It is not supported yet, so it always returns `false`.
+#[cfg(feature = "irq")]
+pub fn set_priority(_vector: usize, _priority: u8) -> bool {
+    false
+}
+
+/// Sets the priority threshold of the current CPU.
+///
+/// It is not supported yet, so it always returns `false`.
+#[cfg(feature = "irq")]
+pub fn set_threshold(_threshold: u8) -> bool {
+    false
+}
+
 pub(super) fn local_apic<'a>() -> &'a mut LocalApic {
     // It's safe as LAPIC is per-cpu.
-    unsafe { LOCAL_APIC.as_mut().unwrap() }
+    unsafe { (*core::ptr::addr_of_mut!(LOCAL_APIC)).as_mut().unwrap() }
 }
 
 pub(super) fn raw_apic_id(id_u8: u8) -> u32 {
@@ -114,8 +271,7 @@ pub(super) fn init_primary() {
     }
 
     info!("Initialize IO APIC...");
-    let io_apic = unsafe { IoApic::new(phys_to_virt(IO_APIC_BASE).as_usize() as u64) };
-    IO_APIC.init_once(SpinNoIrq::new(io_apic));
+    ioapic::init(super::current_cpu_id() as u8);
 }
 
 #[cfg(feature = "smp")]
@@ -3,7 +3,9 @@ use core::arch::global_asm;
 use x86_64::registers::control::{Cr0Flags, Cr4Flags};
 use x86_64::registers::model_specific::EferFlags;
 
-use axconfig::{PHYS_VIRT_OFFSET, TASK_STACK_SIZE};
+use axconfig::{KERNEL_BASE_PADDR, KERNEL_BASE_VADDR, PHYS_VIRT_OFFSET, TASK_STACK_SIZE};
+
+use crate::mem::{GuardedStack, STACK_GUARD_SIZE};
 
 /// Flags set in the ’flags’ member of the multiboot header.
 ///
@@ -30,11 +32,12 @@ const CR4: u64 = Cr4Flags::PHYSICAL_ADDRESS_EXTENSION.bits()
     };
 const EFER: u64 = EferFlags::LONG_MODE_ENABLE.bits() | EferFlags::NO_EXECUTE_ENABLE.bits();
 
-#[link_section = ".bss.stack"]
-static mut BOOT_STACK: [u8; TASK_STACK_SIZE] = [0; TASK_STACK_SIZE];
+#[link_section = ".bss.stack.primary"]
+static mut BOOT_STACK: GuardedStack<TASK_STACK_SIZE> = GuardedStack::new();
 
 global_asm!(
     include_str!("multiboot.S"),
+    include_str!("efi.S"),
     mb_magic = const MULTIBOOT_BOOTLOADER_MAGIC,
     mb_hdr_magic = const MULTIBOOT_HEADER_MAGIC,
     mb_hdr_flags = const MULTIBOOT_HEADER_FLAGS,
@@ -42,11 +45,17 @@ global_asm!(
     entry_secondary = sym super::rust_entry_secondary,
 
     offset = const PHYS_VIRT_OFFSET,
-    boot_stack_size = const TASK_STACK_SIZE,
+    boot_stack_size = const STACK_GUARD_SIZE + TASK_STACK_SIZE,
     boot_stack = sym BOOT_STACK,
 
     cr0 = const CR0,
     cr4 = const CR4,
     efer_msr = const x86::msr::IA32_EFER,
     efer = const EFER,
+
+    uefi = const cfg!(feature = "uefi") as u8,
+    kernel_base = const KERNEL_BASE_VADDR,
+    kernel_base_paddr = const KERNEL_BASE_PADDR,
+    efi_main = sym super::efi::efi_main,
+    uefi_magic = const super::efi::UEFI_BOOT_MAGIC,
 );
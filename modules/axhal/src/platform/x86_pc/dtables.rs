@@ -1,7 +1,20 @@
 //! Description tables (per-CPU GDT, per-CPU ISS, IDT)
 
-use crate::arch::{GdtStruct, IdtStruct, TaskStateSegment};
+use crate::arch::{GdtStruct, IdtStruct, TaskStateSegment, DOUBLE_FAULT_IST_INDEX};
+use crate::mem::{GuardedStack, PAGE_SIZE_4K};
 use lazyinit::LazyInit;
+use x86_64::VirtAddr;
+
+/// Size of the stack of double faults, enough to print the panic message and
+/// the backtrace.
+const EXCEPTION_STACK_SIZE: usize = 8 * PAGE_SIZE_4K;
+
+type ExceptionStack = GuardedStack<EXCEPTION_STACK_SIZE>;
+
+const EMPTY_STACK: ExceptionStack = GuardedStack::new();
+
+#[link_section = ".bss.stack"]
+static mut EXCEPTION_STACKS: [ExceptionStack; axconfig::SMP] = [EMPTY_STACK; axconfig::SMP];
 
 static IDT: LazyInit<IdtStruct> = LazyInit::new();
 
@@ -16,7 +29,11 @@ fn init_percpu() {
         IDT.load();
         let tss = TSS.current_ref_mut_raw();
         let gdt = GDT.current_ref_mut_raw();
-        tss.init_once(TaskStateSegment::new());
+        let mut new_tss = TaskStateSegment::new();
+        let stack = &*core::ptr::addr_of!(EXCEPTION_STACKS[crate::cpu::this_cpu_id()]);
+        new_tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            VirtAddr::new(stack.top().as_usize() as u64);
+        tss.init_once(new_tss);
         gdt.init_once(GdtStruct::new(tss));
         gdt.load();
         gdt.load_tss();
@@ -27,6 +44,10 @@ fn init_percpu() {
 pub(super) fn init_primary() {
     axlog::ax_println!("\nInitialize IDT & GDT...");
     IDT.init_once(IdtStruct::new());
+    let stacks = unsafe { &*core::ptr::addr_of!(EXCEPTION_STACKS) };
+    for (cpu_id, stack) in stacks.iter().enumerate() {
+        crate::mem::register_stack_guard(stack.guard(), cpu_id, "exception");
+    }
     init_percpu();
 }
 
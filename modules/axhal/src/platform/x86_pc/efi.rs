@@ -0,0 +1,416 @@
+//! Booting as a UEFI application.
+//!
+//! With the `uefi` feature, the image starts with a PE/COFF header (see
+//! `efi.S`), so that the raw binary image can be loaded by the firmware as an
+//! EFI application, while the multiboot path keeps working.
+//!
+//! [`efi_main`] runs in the identity mapping of the firmware, wherever the
+//! image is loaded. It finds the ACPI RSDP in the configuration table and the
+//! framebuffer of the Graphics Output Protocol, takes the memory map and
+//! exits the boot services, then copies the image to its link-time physical
+//! address and enters `rust_entry` from there with [`UEFI_BOOT_MAGIC`] and a
+//! [`BootInfo`], which [`init_early`] reads as the multiboot information.
+//!
+//! Until the image is copied, the code runs at another address than it is
+//! linked at, so it must not use absolute addresses: no statics, no trait
+//! objects, no formatting, no panics.
+
+use core::mem::size_of;
+
+use crate::console::{FramebufferInfo, PixelFormat};
+use crate::mem::{phys_to_virt, PAGE_SIZE_4K};
+
+/// Passed to `rust_entry` instead of the multiboot magic, in EAX.
+pub(super) const UEFI_BOOT_MAGIC: usize = 0x5546_4549; // "UEFI"
+
+/// Maximum number of RAM ranges in the [`BootInfo`].
+const MAX_RAM_RANGES: usize = 128;
+
+const EFI_SUCCESS: usize = 0;
+/// The high bit of an error status.
+const EFI_ERROR: usize = 1 << 63;
+const EFI_LOAD_ERROR: usize = EFI_ERROR | 1;
+const EFI_BUFFER_TOO_SMALL: usize = EFI_ERROR | 5;
+
+/// Allocation types of `AllocatePages`.
+const ALLOCATE_MAX_ADDRESS: u32 = 1;
+const ALLOCATE_ADDRESS: u32 = 2;
+
+/// Memory types, of the allocations and the memory map.
+const LOADER_CODE: u32 = 1;
+const LOADER_DATA: u32 = 2;
+const BOOT_SERVICES_CODE: u32 = 3;
+const BOOT_SERVICES_DATA: u32 = 4;
+const CONVENTIONAL_MEMORY: u32 = 7;
+
+/// GOP pixel formats.
+const PIXEL_RGB_RESERVED_8BIT: u32 = 0;
+const PIXEL_BGR_RESERVED_8BIT: u32 = 1;
+const PIXEL_BIT_MASK: u32 = 2;
+
+type Guid = [u8; 16];
+
+/// Returns the GUID `d1-d2-d3-d4` in its in-memory layout.
+const fn guid(d1: u32, d2: u16, d3: u16, d4: [u8; 8]) -> Guid {
+    let (a, b, c) = (d1.to_le_bytes(), d2.to_le_bytes(), d3.to_le_bytes());
+    [
+        a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], d4[0], d4[1], d4[2], d4[3], d4[4], d4[5],
+        d4[6], d4[7],
+    ]
+}
+
+const ACPI_20_TABLE_GUID: Guid = guid(
+    0x8868_e871,
+    0xe4f1,
+    0x11d3,
+    [0xbc, 0x22, 0x00, 0x80, 0xc7, 0x3c, 0x88, 0x81],
+);
+const ACPI_TABLE_GUID: Guid = guid(
+    0xeb9d_2d30,
+    0x2d88,
+    0x11d3,
+    [0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d],
+);
+const GRAPHICS_OUTPUT_PROTOCOL_GUID: Guid = guid(
+    0x9042_a9de,
+    0x23dc,
+    0x4a38,
+    [0x96, 0xfb, 0x7a, 0xde, 0xd0, 0x80, 0x51, 0x6a],
+);
+
+#[repr(C)]
+struct TableHeader {
+    signature: u64,
+    revision: u32,
+    header_size: u32,
+    crc32: u32,
+    reserved: u32,
+}
+
+#[repr(C)]
+struct SimpleTextOutput {
+    reset: usize,
+    output_string: extern "efiapi" fn(this: *mut SimpleTextOutput, string: *const u16) -> usize,
+}
+
+#[repr(C)]
+struct SystemTable {
+    hdr: TableHeader,
+    firmware_vendor: usize,
+    firmware_revision: u32,
+    console_in_handle: usize,
+    con_in: usize,
+    console_out_handle: usize,
+    con_out: *mut SimpleTextOutput,
+    standard_error_handle: usize,
+    std_err: usize,
+    runtime_services: usize,
+    boot_services: *const BootServices,
+    number_of_table_entries: usize,
+    configuration_table: *const ConfigurationTable,
+}
+
+#[repr(C)]
+struct ConfigurationTable {
+    vendor_guid: Guid,
+    vendor_table: usize,
+}
+
+/// The boot services, up to the last one used here.
+#[repr(C)]
+struct BootServices {
+    hdr: TableHeader,
+    raise_tpl: usize,
+    restore_tpl: usize,
+    allocate_pages: extern "efiapi" fn(ty: u32, mem_ty: u32, pages: usize, addr: *mut u64) -> usize,
+    free_pages: usize,
+    get_memory_map: extern "efiapi" fn(
+        size: *mut usize,
+        map: *mut u8,
+        key: *mut usize,
+        desc_size: *mut usize,
+        desc_version: *mut u32,
+    ) -> usize,
+    allocate_pool: extern "efiapi" fn(mem_ty: u32, size: usize, buf: *mut *mut u8) -> usize,
+    /// From `FreePool` to `UnloadImage`.
+    unused0: [usize; 19],
+    exit_boot_services: extern "efiapi" fn(image: usize, map_key: usize) -> usize,
+    /// From `GetNextMonotonicCount` to `LocateHandleBuffer`.
+    unused1: [usize; 10],
+    locate_protocol:
+        extern "efiapi" fn(protocol: *const Guid, registration: usize, iface: *mut usize) -> usize,
+}
+
+#[repr(C)]
+struct MemoryDescriptor {
+    ty: u32,
+    physical_start: u64,
+    virtual_start: u64,
+    number_of_pages: u64,
+    attribute: u64,
+}
+
+#[repr(C)]
+struct GraphicsOutput {
+    query_mode: usize,
+    set_mode: usize,
+    blt: usize,
+    mode: *const GraphicsOutputMode,
+}
+
+#[repr(C)]
+struct GraphicsOutputMode {
+    max_mode: u32,
+    mode: u32,
+    info: *const GraphicsOutputModeInfo,
+    size_of_info: usize,
+    frame_buffer_base: u64,
+    frame_buffer_size: usize,
+}
+
+#[repr(C)]
+struct GraphicsOutputModeInfo {
+    version: u32,
+    horizontal_resolution: u32,
+    vertical_resolution: u32,
+    pixel_format: u32,
+    /// Red, green, blue and reserved masks.
+    pixel_information: [u32; 4],
+    pixels_per_scan_line: u32,
+}
+
+/// The framebuffer of the GOP as in [`GraphicsOutputModeInfo`], the base is
+/// 0 if there is none.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct BootFramebuffer {
+    base: u64,
+    size: u64,
+    width: u32,
+    height: u32,
+    stride: u32,
+    pixel_format: u32,
+    masks: [u32; 3],
+}
+
+/// The information passed from [`efi_main`] to [`init_early`], in pages
+/// allocated as loader data below 4 GiB.
+#[repr(C)]
+pub(super) struct BootInfo {
+    /// The available RAM after the boot services exit, `(start, size)`.
+    ram: [(u64, u64); MAX_RAM_RANGES],
+    num_ram: usize,
+    /// Physical address of the ACPI RSDP, 0 if not found.
+    rsdp: u64,
+    framebuffer: BootFramebuffer,
+}
+
+/// Writes an ASCII message to the console of the firmware.
+unsafe fn print(st: &SystemTable, msg: &[u8]) {
+    let mut buf = [0u16; 128];
+    let len = msg.len().min(buf.len() - 1);
+    for (c, &b) in buf.iter_mut().zip(&msg[..len]) {
+        *c = b as u16;
+    }
+    ((*st.con_out).output_string)(st.con_out, buf.as_ptr());
+}
+
+unsafe fn find_rsdp(st: &SystemTable) -> u64 {
+    let tables = core::slice::from_raw_parts(st.configuration_table, st.number_of_table_entries);
+    let find = |guid: &Guid| {
+        tables
+            .iter()
+            .find(|t| t.vendor_guid == *guid)
+            .map_or(0, |t| t.vendor_table as u64)
+    };
+    match find(&ACPI_20_TABLE_GUID) {
+        0 => find(&ACPI_TABLE_GUID),
+        rsdp => rsdp,
+    }
+}
+
+unsafe fn find_framebuffer(bs: &BootServices) -> BootFramebuffer {
+    let mut gop = 0;
+    let status = (bs.locate_protocol)(&GRAPHICS_OUTPUT_PROTOCOL_GUID, 0, &mut gop);
+    if status != EFI_SUCCESS || gop == 0 {
+        return BootFramebuffer::default();
+    }
+    let mode = &*(*(gop as *const GraphicsOutput)).mode;
+    let info = &*mode.info;
+    let [red, green, blue, _] = info.pixel_information;
+    BootFramebuffer {
+        base: mode.frame_buffer_base,
+        size: mode.frame_buffer_size as u64,
+        width: info.horizontal_resolution,
+        height: info.vertical_resolution,
+        stride: info.pixels_per_scan_line,
+        pixel_format: info.pixel_format,
+        masks: [red, green, blue],
+    }
+}
+
+/// Records the available RAM in the memory map, merging adjacent ranges.
+unsafe fn record_ram(info: &mut BootInfo, map: *const u8, map_size: usize, desc_size: usize) {
+    info.num_ram = 0;
+    for offset in (0..map_size).step_by(desc_size) {
+        let desc = &*(map.add(offset) as *const MemoryDescriptor);
+        let available = matches!(
+            desc.ty,
+            LOADER_CODE
+                | LOADER_DATA
+                | BOOT_SERVICES_CODE
+                | BOOT_SERVICES_DATA
+                | CONVENTIONAL_MEMORY
+        );
+        if !available {
+            continue;
+        }
+        let (start, size) = (
+            desc.physical_start,
+            desc.number_of_pages * PAGE_SIZE_4K as u64,
+        );
+        match info.num_ram {
+            0 => {}
+            n if info.ram[n - 1].0 + info.ram[n - 1].1 == start => {
+                info.ram[n - 1].1 += size;
+                continue;
+            }
+            MAX_RAM_RANGES => continue,
+            _ => {}
+        }
+        info.ram[info.num_ram] = (start, size);
+        info.num_ram += 1;
+    }
+}
+
+/// The entry of the EFI application, jumped to from `efi_entry` with the
+/// load address of the image and of `efi_enter_kernel` in it.
+///
+/// It only returns on errors before the boot services exit.
+pub(super) unsafe extern "efiapi" fn efi_main(
+    image: usize,
+    system_table: usize,
+    load_addr: usize,
+    enter_kernel: usize,
+) -> usize {
+    let st = &*(system_table as *const SystemTable);
+    let bs = &*st.boot_services;
+
+    // Reserve the memory where the image is linked at.
+    let target = axconfig::KERNEL_BASE_PADDR;
+    let image_size = *((load_addr + 0x90) as *const u32) as usize; // `SizeOfImage` in the PE header
+    let mut addr = target as u64;
+    if load_addr != target
+        && (bs.allocate_pages)(
+            ALLOCATE_ADDRESS,
+            LOADER_DATA,
+            image_size / PAGE_SIZE_4K,
+            &mut addr,
+        ) != EFI_SUCCESS
+    {
+        print(st, b"ArceOS: the memory at the kernel base is not free\r\n");
+        return EFI_LOAD_ERROR;
+    }
+
+    // Below 4 GiB to be mapped by the temporary page table.
+    let mut info_addr = 0xffff_ffff;
+    let info_pages = size_of::<BootInfo>().div_ceil(PAGE_SIZE_4K);
+    if (bs.allocate_pages)(
+        ALLOCATE_MAX_ADDRESS,
+        LOADER_DATA,
+        info_pages,
+        &mut info_addr,
+    ) != EFI_SUCCESS
+    {
+        print(st, b"ArceOS: failed to allocate the boot information\r\n");
+        return EFI_LOAD_ERROR;
+    }
+    let info = &mut *(info_addr as *mut BootInfo);
+    info.rsdp = find_rsdp(st);
+    info.framebuffer = find_framebuffer(bs);
+
+    // The map grows by the allocation of its buffer.
+    let (mut map_size, mut key, mut desc_size, mut desc_version) = (0, 0, 0, 0);
+    let status = (bs.get_memory_map)(
+        &mut map_size,
+        core::ptr::null_mut(),
+        &mut key,
+        &mut desc_size,
+        &mut desc_version,
+    );
+    if status != EFI_BUFFER_TOO_SMALL || desc_size < size_of::<MemoryDescriptor>() {
+        print(st, b"ArceOS: failed to get the memory map\r\n");
+        return EFI_LOAD_ERROR;
+    }
+    let capacity = map_size + 8 * desc_size;
+    let mut map = core::ptr::null_mut();
+    if (bs.allocate_pool)(LOADER_DATA, capacity, &mut map) != EFI_SUCCESS {
+        print(st, b"ArceOS: failed to allocate the memory map\r\n");
+        return EFI_LOAD_ERROR;
+    }
+
+    // The key changes if the map changes in between, then try again.
+    let mut exited = false;
+    for _ in 0..2 {
+        map_size = capacity;
+        let status = (bs.get_memory_map)(
+            &mut map_size,
+            map,
+            &mut key,
+            &mut desc_size,
+            &mut desc_version,
+        );
+        if status == EFI_SUCCESS && (bs.exit_boot_services)(image, key) == EFI_SUCCESS {
+            exited = true;
+            break;
+        }
+    }
+    if !exited {
+        print(st, b"ArceOS: failed to exit the boot services\r\n");
+        return EFI_LOAD_ERROR;
+    }
+
+    // No boot services from here on.
+    record_ram(info, map, map_size, desc_size);
+    if load_addr != target {
+        core::ptr::copy_nonoverlapping(load_addr as *const u8, target as *mut u8, image_size);
+    }
+    let enter: extern "sysv64" fn(usize) -> ! =
+        core::mem::transmute(enter_kernel - load_addr + target);
+    enter(info_addr as usize)
+}
+
+/// Records the RAM, the ACPI RSDP and the framebuffer in the [`BootInfo`] at
+/// `info_paddr`, instead of the multiboot information.
+///
+/// It must be called on early boot, after the `.bss` section is cleared.
+pub(super) unsafe fn init_early(info_paddr: usize) {
+    let info = &*(phys_to_virt(info_paddr.into()).as_ptr() as *const BootInfo);
+    for &(start, size) in &info.ram[..info.num_ram.min(MAX_RAM_RANGES)] {
+        crate::mem::add_boot_ram_region(start as usize, size as usize);
+    }
+    if info.rsdp != 0 {
+        super::acpi::set_rsdp(info.rsdp as usize);
+    }
+    let fb = info.framebuffer;
+    let format = match fb.pixel_format {
+        PIXEL_RGB_RESERVED_8BIT => Some(PixelFormat::Rgb),
+        PIXEL_BGR_RESERVED_8BIT => Some(PixelFormat::Bgr),
+        PIXEL_BIT_MASK => Some(PixelFormat::Bitmask {
+            red: fb.masks[0],
+            green: fb.masks[1],
+            blue: fb.masks[2],
+        }),
+        _ => None, // `PixelBltOnly`, without a framebuffer
+    };
+    if let (true, Some(format)) = (fb.base != 0, format) {
+        crate::console::set_boot_framebuffer(FramebufferInfo {
+            paddr: pa!(fb.base as usize),
+            size: fb.size as usize,
+            width: fb.width,
+            height: fb.height,
+            stride: fb.stride,
+            format,
+        });
+    }
+}
@@ -0,0 +1,109 @@
+//! HPET (High Precision Event Timer), used to calibrate the TSC and the local
+//! APIC timer.
+//!
+//! The TSC stays the clock source, as reading the HPET main counter is an
+//! uncached MMIO access, which is much slower than `rdtsc`.
+
+use super::acpi;
+use crate::mem::phys_to_virt;
+
+/// General capabilities and ID register.
+const REG_GCAP_ID: usize = 0x00;
+/// General configuration register.
+const REG_GEN_CONF: usize = 0x10;
+const REG_MAIN_COUNTER: usize = 0xf0;
+
+/// The main counter is 64-bit.
+const GCAP_COUNT_SIZE_CAP: u64 = 1 << 13;
+const GEN_CONF_ENABLE: u64 = 1 << 0;
+
+const FEMTOS_PER_SEC: u128 = 1_000_000_000_000_000;
+const FEMTOS_PER_NANO: u64 = 1_000_000;
+/// Duration of each calibration.
+const CALIBRATION_NANOS: u64 = 10_000_000;
+
+pub(super) struct Hpet {
+    base_vaddr: usize,
+    /// Period of the main counter in femtoseconds.
+    period_fs: u64,
+    /// Mask of the valid bits of the main counter.
+    counter_mask: u64,
+}
+
+static mut HPET: Option<Hpet> = None;
+
+impl Hpet {
+    fn read_reg(&self, reg: usize) -> u64 {
+        unsafe { ((self.base_vaddr + reg) as *const u64).read_volatile() }
+    }
+
+    fn write_reg(&self, reg: usize, val: u64) {
+        unsafe { ((self.base_vaddr + reg) as *mut u64).write_volatile(val) }
+    }
+
+    fn counter(&self) -> u64 {
+        self.read_reg(REG_MAIN_COUNTER) & self.counter_mask
+    }
+
+    /// Counts elapsed since `start`, handling the wrap of 32-bit counters.
+    fn elapsed(&self, start: u64) -> u64 {
+        self.counter().wrapping_sub(start) & self.counter_mask
+    }
+
+    /// Returns the frequency in Hz of another counter, which is read by
+    /// `read` and counts up, measured against the main counter.
+    pub fn measure_hz(&self, read: impl Fn() -> u64) -> u64 {
+        let counts = CALIBRATION_NANOS * FEMTOS_PER_NANO / self.period_fs;
+        let start = self.counter();
+        let begin = read();
+        let mut elapsed = 0;
+        while elapsed < counts {
+            core::hint::spin_loop();
+            elapsed = self.elapsed(start);
+        }
+        let end = read();
+        let elapsed_fs = elapsed as u128 * self.period_fs as u128;
+        (end.wrapping_sub(begin) as u128 * FEMTOS_PER_SEC / elapsed_fs) as u64
+    }
+}
+
+/// Finds the HPET in the ACPI `HPET` table, and starts its main counter.
+///
+/// Returns `None` if there is no HPET.
+pub(super) fn init() -> Option<&'static Hpet> {
+    let table = acpi::find_table(b"HPET")?;
+    // The base address is a generic address structure after the event timer
+    // block ID, its address space ID is 0 for system memory.
+    let gas = unsafe { acpi::phys_bytes(table + acpi::SDT_HEADER_SIZE + 4, 12) };
+    if gas[0] != 0 {
+        return None;
+    }
+    let base_paddr = acpi::read_u64(gas, 4) as usize;
+
+    let mut hpet = Hpet {
+        base_vaddr: phys_to_virt(pa!(base_paddr)).as_usize(),
+        period_fs: 0,
+        counter_mask: u64::MAX,
+    };
+    let cap = hpet.read_reg(REG_GCAP_ID);
+    hpet.period_fs = cap >> 32;
+    if hpet.period_fs == 0 {
+        return None;
+    }
+    if cap & GCAP_COUNT_SIZE_CAP == 0 {
+        hpet.counter_mask = u32::MAX as u64;
+    }
+    let conf = hpet.read_reg(REG_GEN_CONF);
+    hpet.write_reg(REG_GEN_CONF, conf | GEN_CONF_ENABLE);
+
+    unsafe {
+        HPET = Some(hpet);
+        (*core::ptr::addr_of!(HPET)).as_ref()
+    }
+}
+
+/// Returns the HPET found by [`init`], if any.
+#[allow(dead_code)]
+pub(super) fn hpet() -> Option<&'static Hpet> {
+    unsafe { (*core::ptr::addr_of!(HPET)).as_ref() }
+}
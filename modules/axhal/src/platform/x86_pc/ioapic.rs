@@ -0,0 +1,297 @@
+//! IO APICs, which route the GSIs (global system interrupts) of devices to
+//! the vectors of the local APICs.
+//!
+//! The IO APICs and the overrides of the ISA IRQs are found in the ACPI MADT
+//! in the early initialization. GSI `n` raises the vector
+//! `IO_APIC_VECTOR_START + n` on the BSP until it is routed elsewhere, so
+//! only GSIs below 32 have a vector by default.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use kspin::SpinNoIrq;
+use lazyinit::LazyInit;
+
+use super::acpi;
+use super::apic::vectors::{IO_APIC_VECTOR_START, MSI_VECTOR_START};
+use crate::mem::phys_to_virt;
+
+const MAX_IO_APICS: usize = 4;
+const ISA_IRQ_COUNT: usize = 16;
+/// Number of the vectors reserved for the IO APICs.
+const IO_APIC_VECTOR_COUNT: usize = (MSI_VECTOR_START - IO_APIC_VECTOR_START) as usize;
+/// The IO APIC of QEMU and most PCs, used if there is no MADT.
+const DEFAULT_IO_APIC_PADDR: usize = 0xfec0_0000;
+const NO_GSI: u32 = u32::MAX;
+
+/// Offsets of the indirect register access window.
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+/// Indexes of the registers.
+const IOAPICVER: u32 = 0x01;
+const IOREDTBL: u32 = 0x10;
+
+/// Bits of the redirection table entries, whose delivery mode is fixed and
+/// destination mode is physical.
+const REDIR_POLARITY_LOW: u64 = 1 << 13;
+const REDIR_LEVEL: u64 = 1 << 15;
+const REDIR_MASKED: u64 = 1 << 16;
+const REDIR_DEST_SHIFT: u32 = 56;
+
+const MADT_IO_APIC: u8 = 1;
+const MADT_INTERRUPT_OVERRIDE: u8 = 2;
+
+/// The IO APICs and the ISA IRQs found in the MADT.
+struct Madt {
+    /// Physical addresses and the first GSIs of the IO APICs.
+    io_apics: [Option<(usize, u32)>; MAX_IO_APICS],
+    /// GSIs of the ISA IRQs with the trigger mode and polarity bits of the
+    /// redirection table entries.
+    isa_irqs: [(u32, u64); ISA_IRQ_COUNT],
+}
+
+#[derive(Clone, Copy)]
+struct IoApic {
+    base_vaddr: usize,
+    gsi_base: u32,
+    num_pins: u32,
+}
+
+static MADT: LazyInit<Madt> = LazyInit::new();
+static IO_APICS: SpinNoIrq<[Option<IoApic>; MAX_IO_APICS]> = SpinNoIrq::new([None; MAX_IO_APICS]);
+/// GSIs of the IO APIC vectors from `IO_APIC_VECTOR_START`, or `NO_GSI`.
+static VECTOR_GSIS: [AtomicU32; IO_APIC_VECTOR_COUNT] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NONE: AtomicU32 = AtomicU32::new(NO_GSI);
+    [NONE; IO_APIC_VECTOR_COUNT]
+};
+
+impl IoApic {
+    fn read(&self, reg: u32) -> u32 {
+        unsafe {
+            ((self.base_vaddr + IOREGSEL) as *mut u32).write_volatile(reg);
+            ((self.base_vaddr + IOWIN) as *const u32).read_volatile()
+        }
+    }
+
+    fn write(&self, reg: u32, val: u32) {
+        unsafe {
+            ((self.base_vaddr + IOREGSEL) as *mut u32).write_volatile(reg);
+            ((self.base_vaddr + IOWIN) as *mut u32).write_volatile(val);
+        }
+    }
+
+    fn read_entry(&self, pin: u32) -> u64 {
+        let lo = self.read(IOREDTBL + pin * 2);
+        let hi = self.read(IOREDTBL + pin * 2 + 1);
+        (hi as u64) << 32 | lo as u64
+    }
+
+    /// Writes the entry with the low half last, as it holds the mask bit.
+    fn write_entry(&self, pin: u32, entry: u64) {
+        self.write(IOREDTBL + pin * 2, (entry as u32) | REDIR_MASKED as u32);
+        self.write(IOREDTBL + pin * 2 + 1, (entry >> 32) as u32);
+        self.write(IOREDTBL + pin * 2, entry as u32);
+    }
+}
+
+/// Finds the IO APIC of the GSI, and returns it with the pin of the GSI.
+fn find_pin(io_apics: &[Option<IoApic>], gsi: u32) -> Option<(IoApic, u32)> {
+    io_apics.iter().flatten().find_map(|io_apic| {
+        let pin = gsi.checked_sub(io_apic.gsi_base)?;
+        (pin < io_apic.num_pins).then_some((*io_apic, pin))
+    })
+}
+
+/// Returns the trigger mode and polarity bits of the GSI: those of the ISA
+/// IRQ connected to it, or level-triggered and active low for a PCI GSI
+/// above the ISA IRQs.
+fn default_mode(gsi: u32) -> u64 {
+    let isa_irq = MADT.isa_irqs.iter().find(|&&(isa_gsi, _)| isa_gsi == gsi);
+    match isa_irq {
+        Some(&(_, mode)) => mode,
+        None if gsi < ISA_IRQ_COUNT as u32 => 0,
+        None => REDIR_LEVEL | REDIR_POLARITY_LOW,
+    }
+}
+
+/// Parses the trigger mode and polarity in the flags of an interrupt source
+/// override, where 0 means conforming to the ISA bus (edge-triggered and
+/// active high).
+fn override_mode(flags: u16) -> u64 {
+    let mut mode = 0;
+    if flags & 0b11 == 0b11 {
+        mode |= REDIR_POLARITY_LOW;
+    }
+    if (flags >> 2) & 0b11 == 0b11 {
+        mode |= REDIR_LEVEL;
+    }
+    mode
+}
+
+fn parse_madt() -> Option<Madt> {
+    let mut madt = Madt {
+        io_apics: [None; MAX_IO_APICS],
+        isa_irqs: core::array::from_fn(|irq| (irq as u32, 0)),
+    };
+    let mut num_io_apics = 0;
+    for (ty, entry) in acpi::madt_entries() {
+        match ty {
+            MADT_IO_APIC if entry.len() >= 12 && num_io_apics < MAX_IO_APICS => {
+                let paddr = acpi::read_u32(entry, 4) as usize;
+                madt.io_apics[num_io_apics] = Some((paddr, acpi::read_u32(entry, 8)));
+                num_io_apics += 1;
+            }
+            // Only the ISA bus (0) has overrides.
+            MADT_INTERRUPT_OVERRIDE if entry.len() >= 10 && entry[2] == 0 => {
+                let irq = entry[3] as usize;
+                let flags = u16::from_le_bytes([entry[8], entry[9]]);
+                if irq < ISA_IRQ_COUNT {
+                    madt.isa_irqs[irq] = (acpi::read_u32(entry, 4), override_mode(flags));
+                }
+            }
+            _ => {}
+        }
+    }
+    (num_io_apics > 0).then_some(madt)
+}
+
+/// Finds the IO APICs and the ISA IRQ overrides in the MADT, or assumes a
+/// single IO APIC at the usual address with the ISA IRQs on the same pins.
+pub(super) fn init_early() {
+    MADT.init_once(parse_madt().unwrap_or_else(|| Madt {
+        io_apics: [Some((DEFAULT_IO_APIC_PADDR, 0)), None, None, None],
+        isa_irqs: core::array::from_fn(|irq| (irq as u32, 0)),
+    }));
+}
+
+/// Initializes the IO APICs, with all pins masked and routed to the BSP.
+pub(super) fn init(bsp_apic_id: u8) {
+    let mut io_apics = IO_APICS.lock();
+    for (slot, &(paddr, gsi_base)) in io_apics.iter_mut().zip(MADT.io_apics.iter().flatten()) {
+        let mut io_apic = IoApic {
+            base_vaddr: phys_to_virt(pa!(paddr)).as_usize(),
+            gsi_base,
+            num_pins: 0,
+        };
+        io_apic.num_pins = (io_apic.read(IOAPICVER) >> 16 & 0xff) + 1;
+        info!(
+            "IO APIC at {:#x}: GSI {}..{}",
+            paddr,
+            gsi_base,
+            gsi_base + io_apic.num_pins
+        );
+        for pin in 0..io_apic.num_pins {
+            let gsi = gsi_base + pin;
+            let mut entry = REDIR_MASKED | (bsp_apic_id as u64) << REDIR_DEST_SHIFT;
+            if (gsi as usize) < IO_APIC_VECTOR_COUNT {
+                entry |= (IO_APIC_VECTOR_START as u64 + gsi as u64) | default_mode(gsi);
+                VECTOR_GSIS[gsi as usize].store(gsi, Ordering::Relaxed);
+            }
+            io_apic.write_entry(pin, entry);
+        }
+        *slot = Some(io_apic);
+    }
+}
+
+/// Returns the GSI that the ISA IRQ is connected to.
+#[cfg(feature = "irq")]
+pub(super) fn isa_irq_gsi(irq: u8) -> u32 {
+    MADT.isa_irqs
+        .get(irq as usize)
+        .map_or(irq as u32, |&(gsi, _)| gsi)
+}
+
+/// Returns the vector raised by the GSI, if it is routed to one.
+#[cfg(feature = "irq")]
+pub(super) fn gsi_vector(gsi: u32) -> Option<usize> {
+    let idx = VECTOR_GSIS
+        .iter()
+        .position(|vector_gsi| vector_gsi.load(Ordering::Relaxed) == gsi)?;
+    Some(IO_APIC_VECTOR_START as usize + idx)
+}
+
+/// Returns the GSI routed to the IO APIC vector.
+#[cfg(feature = "irq")]
+fn vector_gsi(vector: usize) -> Option<u32> {
+    let idx = vector.checked_sub(IO_APIC_VECTOR_START as usize)?;
+    let gsi = VECTOR_GSIS.get(idx)?.load(Ordering::Relaxed);
+    (gsi != NO_GSI).then_some(gsi)
+}
+
+/// Whether the vector is one of the IO APICs.
+#[cfg(feature = "irq")]
+pub(super) fn is_io_apic_vector(vector: usize) -> bool {
+    (IO_APIC_VECTOR_START as usize..MSI_VECTOR_START as usize).contains(&vector)
+}
+
+/// Updates the redirection table entry of the GSI routed to the vector.
+#[cfg(feature = "irq")]
+fn update_vector_entry(vector: usize, f: impl FnOnce(u64) -> u64) -> bool {
+    let Some(gsi) = vector_gsi(vector) else {
+        return false;
+    };
+    let io_apics = IO_APICS.lock();
+    let Some((io_apic, pin)) = find_pin(&io_apics[..], gsi) else {
+        return false;
+    };
+    io_apic.write_entry(pin, f(io_apic.read_entry(pin)));
+    true
+}
+
+/// Masks or unmasks the GSI routed to the vector.
+#[cfg(feature = "irq")]
+pub(super) fn set_vector_masked(vector: usize, masked: bool) {
+    update_vector_entry(vector, |entry| {
+        if masked {
+            entry | REDIR_MASKED
+        } else {
+            entry & !REDIR_MASKED
+        }
+    });
+}
+
+/// Changes the destination of the GSI routed to the vector.
+#[cfg(feature = "irq")]
+pub(super) fn set_vector_destination(vector: usize, apic_id: u8) -> bool {
+    update_vector_entry(vector, |entry| {
+        entry & !(0xff << REDIR_DEST_SHIFT) | (apic_id as u64) << REDIR_DEST_SHIFT
+    })
+}
+
+/// Routes the GSI to the vector on the CPU `apic_id`, keeping it masked or
+/// unmasked.
+///
+/// Returns `false` if no IO APIC has the GSI, or the vector is not one of the
+/// IO APICs or is taken by another GSI.
+#[cfg(feature = "irq")]
+pub(super) fn route(gsi: u32, vector: usize, apic_id: u8, level: bool, active_low: bool) -> bool {
+    if !is_io_apic_vector(vector) {
+        return false;
+    }
+    let idx = vector - IO_APIC_VECTOR_START as usize;
+    let io_apics = IO_APICS.lock();
+    let Some((io_apic, pin)) = find_pin(&io_apics[..], gsi) else {
+        return false;
+    };
+    if VECTOR_GSIS[idx]
+        .compare_exchange(NO_GSI, gsi, Ordering::Relaxed, Ordering::Relaxed)
+        .is_err_and(|old| old != gsi)
+    {
+        return false;
+    }
+    for (i, vector_gsi) in VECTOR_GSIS.iter().enumerate() {
+        if i != idx && vector_gsi.load(Ordering::Relaxed) == gsi {
+            vector_gsi.store(NO_GSI, Ordering::Relaxed);
+        }
+    }
+    let mut entry = io_apic.read_entry(pin) & REDIR_MASKED;
+    entry |= (apic_id as u64) << REDIR_DEST_SHIFT | vector as u64;
+    if level {
+        entry |= REDIR_LEVEL;
+    }
+    if active_low {
+        entry |= REDIR_POLARITY_LOW;
+    }
+    io_apic.write_entry(pin, entry);
+    true
+}
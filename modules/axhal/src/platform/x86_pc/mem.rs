@@ -1,6 +1,92 @@
-// TODO: get memory regions from multiboot info.
+use crate::mem::{phys_to_virt, MemRegion, MemRegionFlags, PhysAddr};
 
-use crate::mem::{MemRegion, MemRegionFlags};
+/// The RAM mapped by the boot page table, see `multiboot.S`.
+pub(crate) const BOOT_MAPPED_MEMORY: (usize, usize) = (0, 0x1_0000_0000);
+
+/// `mem_lower` and `mem_upper` are valid.
+const MULTIBOOT_INFO_MEMORY: u32 = 1 << 0;
+/// `mods_count` and `mods_addr` are valid.
+const MULTIBOOT_INFO_MODS: u32 = 1 << 3;
+/// `mmap_length` and `mmap_addr` are valid.
+const MULTIBOOT_INFO_MEM_MAP: u32 = 1 << 6;
+/// Type of the available RAM in the memory map, as in the E820 map.
+const MULTIBOOT_MEMORY_AVAILABLE: u32 = 1;
+
+/// Records the RAM in the memory map of the multiboot information at `mbi`,
+/// and the boot modules (e.g., the initrd) as reserved memory.
+///
+/// If there is no memory map, the sizes of the lower and upper memory are
+/// used. It must be called on early boot, after the `.bss` section is
+/// cleared.
+pub(super) unsafe fn init_early(mbi: usize) {
+    let info = phys_to_virt(mbi.into()).as_ptr();
+    let read_u32 = |ptr: *const u8, offset: usize| (ptr.add(offset) as *const u32).read_unaligned();
+    let read_u64 = |ptr: *const u8, offset: usize| (ptr.add(offset) as *const u64).read_unaligned();
+    let flags = read_u32(info, 0);
+    if flags & MULTIBOOT_INFO_MEM_MAP != 0 {
+        let (len, addr) = (read_u32(info, 44) as usize, read_u32(info, 48) as usize);
+        let mut entry = phys_to_virt(addr.into()).as_ptr();
+        let end = entry.add(len);
+        // Each entry is `size: u32, base_addr: u64, length: u64, type: u32`,
+        // where `size` does not count itself.
+        while entry < end {
+            let (base, size) = (read_u64(entry, 4) as usize, read_u64(entry, 12) as usize);
+            if read_u32(entry, 20) == MULTIBOOT_MEMORY_AVAILABLE {
+                crate::mem::add_boot_ram_region(base, size);
+            }
+            entry = entry.add(read_u32(entry, 0) as usize + 4);
+        }
+    } else if flags & MULTIBOOT_INFO_MEMORY != 0 {
+        // In KiB, the upper memory starts at 1 MiB.
+        let (lower, upper) = (read_u32(info, 4) as usize, read_u32(info, 8) as usize);
+        crate::mem::add_boot_ram_region(0, lower << 10);
+        crate::mem::add_boot_ram_region(0x10_0000, upper << 10);
+    }
+    if flags & MULTIBOOT_INFO_MODS != 0 {
+        let (count, addr) = (read_u32(info, 20) as usize, read_u32(info, 24) as usize);
+        let mods = phys_to_virt(addr.into()).as_ptr();
+        // Each module is `mod_start: u32, mod_end: u32, string: u32, reserved: u32`.
+        for i in 0..count {
+            let (start, end) = (read_u32(mods, i * 16), read_u32(mods, i * 16 + 4));
+            let size = end.saturating_sub(start) as usize;
+            crate::mem::add_boot_reserved_region(start as usize, size);
+        }
+    }
+}
+
+/// Returns the physical address of the PCI Express ECAM of bus 0 and the
+/// last bus in it, from the ACPI MCFG if present, or the platform config.
+pub(crate) fn pci_ecam() -> (PhysAddr, usize) {
+    match super::acpi::pci_ecam() {
+        Some(ecam) => (pa!(ecam.base), ecam.end_bus as usize),
+        None => (pa!(axconfig::PCI_ECAM_BASE), axconfig::PCI_BUS_END),
+    }
+}
+
+/// Returns the NUMA topology in the ACPI SRAT, if any.
+pub(crate) fn numa_topology() -> Option<crate::numa::Topology> {
+    super::acpi::parse_srat()
+}
+
+/// Returns the MMIO region of the ECAM in the MCFG, unless it is in the MMIO
+/// regions of the platform config.
+fn mcfg_ecam_region() -> Option<MemRegion> {
+    let ecam = super::acpi::pci_ecam()?;
+    let start = ecam.base + ((ecam.start_bus as usize) << 20);
+    let size = (ecam.end_bus as usize + 1).saturating_sub(ecam.start_bus as usize) << 20;
+    let configured = axconfig::MMIO_REGIONS
+        .iter()
+        .any(|&(base, len)| base <= start && start + size <= base + len);
+    (!configured).then_some(MemRegion {
+        paddr: pa!(start),
+        size,
+        flags: MemRegionFlags::RESERVED
+            | MemRegionFlags::DEVICE
+            | MemRegionFlags::READ
+            | MemRegionFlags::WRITE,
+        name: "PCI ECAM",
+    })
+}
 
 /// Returns platform-specific memory regions.
 pub(crate) fn platform_regions() -> impl Iterator<Item = MemRegion> {
@@ -12,4 +98,5 @@ pub(crate) fn platform_regions() -> impl Iterator<Item = MemRegion> {
     })
     .chain(crate::mem::default_free_regions())
     .chain(crate::mem::default_mmio_regions())
+    .chain(mcfg_ecam_region())
 }
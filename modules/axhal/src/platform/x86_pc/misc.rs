@@ -1,5 +1,7 @@
 use x86_64::instructions::port::PortWriteOnly;
 
+use crate::time::{busy_wait, Duration};
+
 /// Shutdown the whole system (in QEMU), including all CPUs.
 ///
 /// See <https://wiki.osdev.org/Shutdown> for more information.
@@ -26,3 +28,25 @@ pub fn terminate() -> ! {
         crate::arch::halt();
     }
 }
+
+/// Reboot the whole system, including all CPUs.
+///
+/// The reset control register at port `0xcf9` is tried first, then the
+/// keyboard controller. See <https://wiki.osdev.org/Reboot>.
+pub fn reboot() -> ! {
+    info!("Rebooting...");
+    // Select the hard reset, then start the reset.
+    let mut rst_cnt = PortWriteOnly::new(0xcf9);
+    unsafe {
+        rst_cnt.write(0x02u8);
+        rst_cnt.write(0x06u8);
+    }
+    busy_wait(Duration::from_millis(10));
+    // Pulse the CPU reset line through the keyboard controller.
+    unsafe { PortWriteOnly::new(0x64).write(0xfeu8) };
+    busy_wait(Duration::from_millis(10));
+    warn!("It should reboot!");
+    loop {
+        crate::arch::halt();
+    }
+}
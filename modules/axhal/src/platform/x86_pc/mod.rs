@@ -1,11 +1,16 @@
+mod acpi;
 mod apic;
 mod boot;
 mod dtables;
+mod efi;
+mod hpet;
+mod ioapic;
 mod uart16550;
 
 pub mod mem;
 pub mod misc;
 pub mod time;
+pub(crate) mod watchdog;
 
 #[cfg(feature = "smp")]
 pub mod mp;
@@ -32,16 +37,26 @@ fn current_cpu_id() -> usize {
     }
 }
 
-unsafe extern "C" fn rust_entry(magic: usize, _mbi: usize) {
-    // TODO: handle multiboot info
-    if magic == self::boot::MULTIBOOT_BOOTLOADER_MAGIC {
-        crate::mem::clear_bss();
-        crate::cpu::init_primary(current_cpu_id());
-        self::uart16550::init();
-        self::dtables::init_primary();
-        self::time::init_early();
-        rust_main(current_cpu_id(), 0);
+unsafe extern "C" fn rust_entry(magic: usize, mbi: usize) {
+    match magic {
+        self::boot::MULTIBOOT_BOOTLOADER_MAGIC => {
+            crate::mem::clear_bss();
+            self::mem::init_early(mbi);
+        }
+        self::efi::UEFI_BOOT_MAGIC => {
+            crate::mem::clear_bss();
+            self::efi::init_early(mbi);
+        }
+        _ => return,
     }
+    crate::cpu::init_primary(current_cpu_id());
+    self::uart16550::init();
+    self::dtables::init_primary();
+    self::acpi::init_early();
+    crate::numa::init_early();
+    self::ioapic::init_early();
+    self::time::init_early();
+    rust_main(current_cpu_id(), 0);
 }
 
 #[allow(unused_variables)]
@@ -58,6 +73,8 @@ unsafe extern "C" fn rust_entry_secondary(magic: usize) {
 pub fn platform_init() {
     self::apic::init_primary();
     self::time::init_primary();
+    #[cfg(feature = "irq")]
+    self::uart16550::init_irq();
 }
 
 /// Initializes the platform devices for secondary CPUs.
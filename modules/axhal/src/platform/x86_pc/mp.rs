@@ -28,6 +28,11 @@ unsafe fn setup_startup_page(stack_top: PhysAddr) {
     start_page[U64_PER_PAGE - 1] = ap_entry32 as usize as _; // entry
 }
 
+/// Whether the CPU with the APIC ID exists, as listed in the ACPI MADT.
+pub(crate) fn cpu_present(apic_id: usize) -> bool {
+    super::acpi::cpu_present(apic_id)
+}
+
 /// Starts the given secondary CPU with its boot stack.
 pub fn start_secondary_cpu(apic_id: usize, stack_top: PhysAddr) {
     unsafe { setup_startup_page(stack_top) };
@@ -43,3 +48,11 @@ pub fn start_secondary_cpu(apic_id: usize, stack_top: PhysAddr) {
     busy_wait(Duration::from_micros(200)); // 200us
     unsafe { lapic.send_sipi(START_PAGE_IDX, apic_id) };
 }
+
+/// Halts the current CPU with IRQs disabled, until the INIT-SIPI-SIPI
+/// sequence of [`start_secondary_cpu`] starts it again.
+pub(crate) fn cpu_off() -> ! {
+    loop {
+        crate::arch::halt();
+    }
+}
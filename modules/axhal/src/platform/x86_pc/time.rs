@@ -1,84 +1,162 @@
-use raw_cpuid::CpuId;
-
 #[cfg(feature = "irq")]
 use int_ratio::Ratio;
+use raw_cpuid::CpuId;
 
 #[cfg(feature = "irq")]
-const LAPIC_TICKS_PER_SEC: u64 = 1_000_000_000; // TODO: need to calibrate
+const LAPIC_TICKS_PER_SEC: u64 = 1_000_000_000; // used if there is no HPET
 
 #[cfg(feature = "irq")]
 static mut NANOS_TO_LAPIC_TICKS_RATIO: Ratio = Ratio::zero();
 
-static mut INIT_TICK: u64 = 0;
-static mut CPU_FREQ_MHZ: u64 = axconfig::TIMER_FREQUENCY as u64 / 1_000_000;
+/// The MSR of the deadline in the TSC-deadline mode of the local APIC timer.
+#[cfg(feature = "irq")]
+const IA32_TSC_DEADLINE: u32 = 0x6e0;
 
-/// RTC wall time offset in nanoseconds at monotonic time base.
-static mut RTC_EPOCHOFFSET_NANOS: u64 = 0;
+/// Whether the local APIC timer is in the TSC-deadline mode, otherwise it is
+/// in the one-shot mode, counting down at the calibrated frequency.
+#[cfg(feature = "irq")]
+static mut TSC_DEADLINE_MODE: bool = false;
+
+/// Frequency of the PIT input clock in Hz.
+const PIT_FREQUENCY: u64 = 1_193_182;
+/// Duration of the TSC calibration against the PIT.
+const PIT_CALIBRATION_MILLIS: u64 = 10;
+/// Polls of the PIT before giving up, far longer than the calibration, in
+/// case the PIT is absent or gated off.
+const PIT_MAX_POLLS: usize = 1 << 24;
+
+static mut INIT_TICK: u64 = 0;
 
 /// Returns the current clock time in hardware ticks.
 pub fn current_ticks() -> u64 {
     unsafe { core::arch::x86_64::_rdtsc() - INIT_TICK }
 }
 
-/// Converts hardware ticks to nanoseconds.
-pub fn ticks_to_nanos(ticks: u64) -> u64 {
-    ticks * 1_000 / unsafe { CPU_FREQ_MHZ }
-}
-
-/// Converts nanoseconds to hardware ticks.
-pub fn nanos_to_ticks(nanos: u64) -> u64 {
-    nanos * unsafe { CPU_FREQ_MHZ } / 1_000
-}
-
-/// Return epoch offset in nanoseconds (wall time offset to monotonic clock start).
-pub fn epochoffset_nanos() -> u64 {
-    unsafe { RTC_EPOCHOFFSET_NANOS }
-}
-
 /// Set a one-shot timer.
 ///
 /// A timer interrupt will be triggered at the specified monotonic time deadline (in nanoseconds).
+///
+/// In the TSC-deadline mode, the deadline is compared with the TSC, so it is
+/// as precise as the clock, and a deadline in the past triggers the interrupt
+/// immediately.
 #[cfg(feature = "irq")]
 pub fn set_oneshot_timer(deadline_ns: u64) {
+    if unsafe { TSC_DEADLINE_MODE } {
+        let tsc = unsafe { INIT_TICK }.saturating_add(crate::time::nanos_to_ticks(deadline_ns));
+        unsafe {
+            // Orders the write of the MSR after the earlier writes of the
+            // local APIC, e.g., the timer mode. Writing 0 disarms the timer.
+            core::arch::x86_64::_mm_mfence();
+            x86_64::registers::model_specific::Msr::new(IA32_TSC_DEADLINE).write(tsc.max(1));
+        }
+        return;
+    }
     let lapic = super::apic::local_apic();
     let now_ns = crate::time::monotonic_time_nanos();
     unsafe {
         if now_ns < deadline_ns {
-            let apic_ticks = NANOS_TO_LAPIC_TICKS_RATIO.mul_trunc(deadline_ns - now_ns);
-            assert!(apic_ticks <= u32::MAX as u64);
-            lapic.set_timer_initial(apic_ticks.max(1) as u32);
+            // The count is 32-bit, the timer fires early for deadlines too far
+            // away, and the handler programs it again.
+            let apic_ticks =
+                (*core::ptr::addr_of!(NANOS_TO_LAPIC_TICKS_RATIO)).mul_trunc(deadline_ns - now_ns);
+            lapic.set_timer_initial(apic_ticks.clamp(1, u32::MAX as u64) as u32);
         } else {
             lapic.set_timer_initial(1);
         }
     }
 }
 
-pub(super) fn init_early() {
-    if let Some(freq) = CpuId::new()
+/// Returns the TSC frequency in Hz enumerated by CPUID leaf 0x15, as the
+/// ratio to the core crystal clock, which is exact if reported.
+fn cpuid_tsc_hz() -> Option<u64> {
+    CpuId::new()
+        .get_tsc_info()
+        .and_then(|info| info.tsc_frequency())
+        .filter(|&hz| hz > 0)
+}
+
+/// Returns the processor base frequency in Hz of CPUID leaf 0x16, which is
+/// only nominal, but equals the TSC frequency on most processors.
+fn cpuid_base_hz() -> Option<u64> {
+    CpuId::new()
         .get_processor_frequency_info()
-        .map(|info| info.processor_base_frequency())
-    {
-        if freq > 0 {
-            axlog::ax_println!("Got TSC frequency by CPUID: {} MHz", freq);
-            unsafe { CPU_FREQ_MHZ = freq as u64 }
-        }
+        .map(|info| info.processor_base_frequency() as u64 * 1_000_000)
+        .filter(|&hz| hz > 0)
+}
+
+/// Measures the TSC frequency in Hz against channel 2 of the PIT, whose gate
+/// is controlled by the software, so it does not raise interrupts.
+///
+/// Returns `None` if the PIT does not count.
+fn pit_measure_tsc_hz() -> Option<u64> {
+    use x86_64::instructions::port::Port;
+
+    let mut gate = Port::<u8>::new(0x61);
+    let mut command = Port::<u8>::new(0x43);
+    let mut channel2 = Port::<u8>::new(0x42);
+    let latch = PIT_FREQUENCY * PIT_CALIBRATION_MILLIS / 1000;
+    unsafe {
+        // Gate high and the speaker off, then the one-shot mode 0, whose
+        // output, readable in port 0x61, goes high when the count ends.
+        let saved = gate.read();
+        gate.write((saved & !0x02) | 0x01);
+        command.write(0b1011_0000);
+        channel2.write(latch as u8);
+        channel2.write((latch >> 8) as u8);
+
+        let begin = core::arch::x86_64::_rdtsc();
+        let done = (0..PIT_MAX_POLLS).any(|_| gate.read() & 0x20 != 0);
+        let end = core::arch::x86_64::_rdtsc();
+        gate.write(saved);
+        done.then(|| (end - begin) * 1000 / PIT_CALIBRATION_MILLIS)
     }
+}
 
+pub(super) fn init_early() {
+    // CPUID leaf 0x15 gives the exact TSC frequency, otherwise the TSC is
+    // measured against the HPET, or the PIT if there is no HPET. The nominal
+    // frequency of CPUID leaf 0x16 and the platform config are the last
+    // resorts.
+    let hpet = super::hpet::init();
+    let (freq_hz, source) = if let Some(hz) = cpuid_tsc_hz() {
+        (hz, "CPUID leaf 0x15")
+    } else if let Some(hpet) = hpet {
+        let hz = hpet.measure_hz(|| unsafe { core::arch::x86_64::_rdtsc() });
+        (hz, "calibrated by HPET")
+    } else if let Some(hz) = pit_measure_tsc_hz() {
+        (hz, "calibrated by PIT")
+    } else if let Some(hz) = cpuid_base_hz() {
+        (hz, "CPUID leaf 0x16")
+    } else {
+        (axconfig::TIMER_FREQUENCY as u64, "config")
+    };
+
+    crate::time::init_timer_frequency(freq_hz, source);
     unsafe {
         INIT_TICK = core::arch::x86_64::_rdtsc();
     }
 
-    #[cfg(feature = "rtc")]
-    {
-        use x86_rtc::Rtc;
+    crate::time::init_wall_time();
+}
 
-        // Get the current time in microseconds since the epoch (1970-01-01) from the x86 RTC.
-        // Subtract the timer ticks to get the actual time when ArceOS was booted.
-        let eopch_time_nanos = Rtc::new().get_unix_timestamp() * 1_000_000_000;
-        unsafe {
-            RTC_EPOCHOFFSET_NANOS = eopch_time_nanos - ticks_to_nanos(INIT_TICK);
-        }
-    }
+/// Reads the CMOS RTC in seconds since the epoch (1970-01-01).
+#[cfg(feature = "rtc")]
+pub(crate) fn read_rtc() -> Option<u64> {
+    Some(x86_rtc::Rtc::new().get_unix_timestamp())
+}
+
+/// Writes the CMOS RTC in seconds since the epoch (1970-01-01).
+#[cfg(feature = "rtc")]
+pub(crate) fn write_rtc(secs: u64) {
+    x86_rtc::Rtc::new().set_unix_timestamp(secs);
+}
+
+/// Whether the local APIC timer supports the TSC-deadline mode.
+#[cfg(feature = "irq")]
+fn cpu_has_tsc_deadline() -> bool {
+    CpuId::new()
+        .get_feature_info()
+        .is_some_and(|finfo| finfo.has_tsc_deadline())
 }
 
 pub(super) fn init_primary() {
@@ -86,22 +164,44 @@ pub(super) fn init_primary() {
     unsafe {
         use x2apic::lapic::{TimerDivide, TimerMode};
         let lapic = super::apic::local_apic();
+        if cpu_has_tsc_deadline() {
+            info!("Using TSC-deadline timer.");
+            lapic.set_timer_mode(TimerMode::TscDeadline);
+            lapic.enable_timer();
+            TSC_DEADLINE_MODE = true;
+            return;
+        }
         lapic.set_timer_mode(TimerMode::OneShot);
         lapic.set_timer_divide(TimerDivide::Div256); // indeed it is Div1, the name is confusing.
         lapic.enable_timer();
 
-        // TODO: calibrate with HPET
-        NANOS_TO_LAPIC_TICKS_RATIO = Ratio::new(
-            LAPIC_TICKS_PER_SEC as u32,
-            crate::time::NANOS_PER_SEC as u32,
-        );
+        // Count down from the maximum, which does not expire during the
+        // calibration, then stop the timer.
+        let lapic_hz = super::hpet::hpet().map_or(LAPIC_TICKS_PER_SEC, |hpet| {
+            lapic.set_timer_initial(u32::MAX);
+            let hz = hpet.measure_hz(|| (u32::MAX - lapic.timer_current()) as u64);
+            lapic.set_timer_initial(0);
+            hz
+        });
+        debug!("LAPIC timer frequency: {} Hz", lapic_hz);
+        NANOS_TO_LAPIC_TICKS_RATIO = Ratio::new(lapic_hz as u32, crate::time::NANOS_PER_SEC as u32);
     }
 }
 
 #[cfg(feature = "smp")]
 pub(super) fn init_secondary() {
+    // Each local APIC timer is reset, so set it up in the mode chosen by the
+    // primary CPU, with the frequency calibrated there.
     #[cfg(feature = "irq")]
     unsafe {
-        super::apic::local_apic().enable_timer();
+        use x2apic::lapic::{TimerDivide, TimerMode};
+        let lapic = super::apic::local_apic();
+        if TSC_DEADLINE_MODE {
+            lapic.set_timer_mode(TimerMode::TscDeadline);
+        } else {
+            lapic.set_timer_mode(TimerMode::OneShot);
+            lapic.set_timer_divide(TimerDivide::Div256);
+        }
+        lapic.enable_timer();
     }
 }
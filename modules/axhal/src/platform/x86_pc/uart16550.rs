@@ -8,6 +8,10 @@ const OSC_FREQ: usize = 1_843_200;
 
 static COM1: SpinNoIrq<Uart16550> = SpinNoIrq::new(Uart16550::new(0x3f8));
 
+/// The ISA IRQ of COM1.
+#[cfg(feature = "irq")]
+const COM1_IRQ: u8 = 4;
+
 bitflags::bitflags! {
     /// Line status flags
     struct LineStsFlags: u8 {
@@ -65,6 +69,12 @@ impl Uart16550 {
         }
     }
 
+    /// Interrupts when data is received.
+    #[cfg(feature = "irq")]
+    fn enable_rx_interrupt(&mut self) {
+        unsafe { self.int_en.write(0x01) };
+    }
+
     fn line_sts(&mut self) -> LineStsFlags {
         unsafe { LineStsFlags::from_bits_truncate(self.line_sts.read()) }
     }
@@ -125,3 +135,22 @@ pub fn read_bytes(bytes: &mut [u8]) -> usize {
 pub(super) fn init() {
     COM1.lock().init(115200);
 }
+
+/// Enables the receive interrupt, so that the input is buffered by
+/// [`handle`].
+#[cfg(feature = "irq")]
+pub(super) fn init_irq() {
+    let Some(vector) = super::apic::isa_irq_vector(COM1_IRQ) else {
+        return;
+    };
+    crate::irq::register_handler(vector, handle);
+    COM1.lock().enable_rx_interrupt();
+    crate::console::enable_rx_irq();
+}
+
+/// UART IRQ Handler
+#[cfg(feature = "irq")]
+fn handle() {
+    // Reading the received data also clears the interrupt.
+    crate::console::receive(core::iter::from_fn(getchar));
+}
@@ -0,0 +1,135 @@
+//! The TCO watchdog of the Intel ICH9 LPC bridge, as emulated by QEMU q35.
+//!
+//! The TCO timer counts down in 0.6 s units. The first timeout only sets a
+//! status bit, and the system is reset on the second one, so the timer is
+//! loaded with half of the timeout. Resets are also blocked by the
+//! `NO_REBOOT` bit of the chipset configuration registers, which is set by
+//! default and cleared when the watchdog is started.
+
+use core::time::Duration;
+
+use x86_64::instructions::port::Port;
+
+use crate::mem::phys_to_virt;
+
+const PCI_CONFIG_ADDRESS: u16 = 0xcf8;
+const PCI_CONFIG_DATA: u16 = 0xcfc;
+
+/// The LPC bridge is the function 0 of the device 31 on bus 0.
+const LPC_DEVICE: u32 = 31;
+const ICH9_LPC_ID: u32 = 0x2918_8086;
+const LPC_PMBASE: u32 = 0x40;
+const LPC_ACPI_CNTL: u32 = 0x44;
+const LPC_RCBA: u32 = 0xf0;
+const ACPI_CNTL_ACPI_EN: u32 = 1 << 7;
+const RCBA_EN: u32 = 1 << 0;
+
+/// Offset of the general control and status register in the RCBA.
+const RCBA_GCS: usize = 0x3410;
+const GCS_NO_REBOOT: u32 = 1 << 5;
+
+/// Offsets in the ACPI power management I/O space.
+const PM_SMI_EN: u16 = 0x30;
+const PM_TCO_BASE: u16 = 0x60;
+const SMI_EN_TCO_EN: u32 = 1 << 13;
+
+/// Offsets in the TCO I/O space.
+const TCO_RLD: u16 = 0x00;
+const TCO1_STS: u16 = 0x04;
+const TCO2_STS: u16 = 0x06;
+const TCO1_CNT: u16 = 0x08;
+const TCO_TMR: u16 = 0x12;
+const TCO1_STS_TIMEOUT: u16 = 1 << 3;
+const TCO2_STS_SECOND_TO: u16 = 1 << 1;
+const TCO1_CNT_TMR_HLT: u16 = 1 << 11;
+
+const TCO_TICK_MILLIS: u128 = 600;
+/// Valid values of the TCO timer, smaller values are ignored by hardware.
+const TCO_TMR_RANGE: (u128, u128) = (4, 0x3ff);
+
+static mut TCO_BASE: u16 = 0;
+
+fn lpc_config_read(reg: u32) -> u32 {
+    let addr = 0x8000_0000 | (LPC_DEVICE << 11) | (reg & 0xfc);
+    unsafe {
+        Port::<u32>::new(PCI_CONFIG_ADDRESS).write(addr);
+        Port::<u32>::new(PCI_CONFIG_DATA).read()
+    }
+}
+
+fn tco_port(reg: u16) -> Port<u16> {
+    Port::new(unsafe { TCO_BASE } + reg)
+}
+
+/// Returns whether the physical range is mapped as MMIO.
+fn is_mmio(paddr: usize, size: usize) -> bool {
+    axconfig::MMIO_REGIONS
+        .iter()
+        .any(|&(base, len)| paddr >= base && paddr + size <= base + len)
+}
+
+/// Finds the TCO I/O space, and allows the watchdog to reset the system.
+fn probe() -> Option<u16> {
+    if lpc_config_read(0) != ICH9_LPC_ID {
+        return None;
+    }
+    if lpc_config_read(LPC_ACPI_CNTL) & ACPI_CNTL_ACPI_EN == 0 {
+        warn!("TCO watchdog: the ACPI I/O space is disabled");
+        return None;
+    }
+    let rcba = lpc_config_read(LPC_RCBA);
+    let gcs_paddr = (rcba & !0x3fff) as usize + RCBA_GCS;
+    if rcba & RCBA_EN == 0 || !is_mmio(gcs_paddr, 4) {
+        warn!("TCO watchdog: the chipset configuration registers are not mapped");
+        return None;
+    }
+    let gcs = phys_to_virt(pa!(gcs_paddr)).as_mut_ptr() as *mut u32;
+    unsafe { gcs.write_volatile(gcs.read_volatile() & !GCS_NO_REBOOT) };
+
+    let pm_base = (lpc_config_read(LPC_PMBASE) & 0xff80) as u16;
+    // Only reset on the second timeout, without an SMI on the first one.
+    let mut smi_en = Port::<u32>::new(pm_base + PM_SMI_EN);
+    unsafe {
+        let val = smi_en.read();
+        smi_en.write(val & !SMI_EN_TCO_EN);
+    }
+    Some(pm_base + PM_TCO_BASE)
+}
+
+/// Starts the TCO watchdog. Returns `false` if there is no TCO watchdog.
+pub(crate) fn start(timeout: Duration) -> bool {
+    if unsafe { TCO_BASE } == 0 {
+        match probe() {
+            Some(base) => unsafe { TCO_BASE = base },
+            None => return false,
+        }
+    }
+    let ticks = timeout.as_millis() / 2 / TCO_TICK_MILLIS;
+    let ticks = ticks.clamp(TCO_TMR_RANGE.0, TCO_TMR_RANGE.1);
+    unsafe {
+        tco_port(TCO_TMR).write(ticks as u16);
+        feed();
+        let mut cnt = tco_port(TCO1_CNT);
+        let val = cnt.read();
+        cnt.write(val & !TCO1_CNT_TMR_HLT);
+    }
+    true
+}
+
+/// Reloads the TCO timer, and clears the timeout status.
+pub(crate) fn feed() {
+    unsafe {
+        tco_port(TCO_RLD).write(1);
+        tco_port(TCO1_STS).write(TCO1_STS_TIMEOUT);
+        tco_port(TCO2_STS).write(TCO2_STS_SECOND_TO);
+    }
+}
+
+/// Halts the TCO timer.
+pub(crate) fn stop() {
+    unsafe {
+        let mut cnt = tco_port(TCO1_CNT);
+        let val = cnt.read();
+        cnt.write(val | TCO1_CNT_TMR_HLT);
+    }
+}
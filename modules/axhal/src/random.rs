@@ -0,0 +1,153 @@
+//! Hardware random number sources.
+//!
+//! RDSEED or RDRAND is used on x86_64, `RNDR` on AArch64 (ARMv8.5-RNG), and
+//! the `seed` CSR (Zkr) on RISC-V. If the CPU has none of them, the jitter of
+//! the cycle counter is the only source, which is much weaker.
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        use core::arch::x86_64::{__cpuid, __cpuid_count, _rdrand64_step, _rdseed64_step};
+
+        /// Times to retry RDSEED or RDRAND if there is no random number ready.
+        const MAX_RETRIES: usize = 10;
+
+        #[target_feature(enable = "rdseed")]
+        unsafe fn rdseed() -> Option<u64> {
+            let mut val = 0;
+            for _ in 0..MAX_RETRIES {
+                if _rdseed64_step(&mut val) == 1 {
+                    return Some(val);
+                }
+                core::hint::spin_loop();
+            }
+            None
+        }
+
+        #[target_feature(enable = "rdrand")]
+        unsafe fn rdrand() -> Option<u64> {
+            let mut val = 0;
+            for _ in 0..MAX_RETRIES {
+                if _rdrand64_step(&mut val) == 1 {
+                    return Some(val);
+                }
+            }
+            None
+        }
+
+        fn hw_random_u64() -> Option<u64> {
+            unsafe {
+                // RDSEED is the entropy source, and RDRAND is a generator
+                // reseeded from it.
+                if __cpuid(0).eax >= 7 && __cpuid_count(7, 0).ebx & (1 << 18) != 0 {
+                    if let Some(val) = rdseed() {
+                        return Some(val);
+                    }
+                }
+                if __cpuid(1).ecx & (1 << 30) != 0 {
+                    return rdrand();
+                }
+            }
+            None
+        }
+    } else if #[cfg(target_arch = "aarch64")] {
+        use aarch64_cpu::registers::ID_AA64ISAR0_EL1;
+        use tock_registers::interfaces::Readable;
+
+        const MAX_RETRIES: usize = 10;
+
+        fn hw_random_u64() -> Option<u64> {
+            if (ID_AA64ISAR0_EL1.get() >> 60) & 0xf == 0 {
+                return None;
+            }
+            for _ in 0..MAX_RETRIES {
+                let (val, ok): (u64, u64);
+                // `RNDR` sets NZCV to 0b0100 on failure.
+                unsafe {
+                    core::arch::asm!(
+                        "mrs {val}, s3_3_c2_c4_0",
+                        "cset {ok}, ne",
+                        val = out(reg) val,
+                        ok = out(reg) ok,
+                        options(nomem, nostack),
+                    )
+                };
+                if ok != 0 {
+                    return Some(val);
+                }
+            }
+            None
+        }
+    } else if #[cfg(target_arch = "riscv64")] {
+        use core::sync::atomic::{AtomicU8, Ordering};
+
+        const OPST_ES16: usize = 0b10;
+        const OPST_DEAD: usize = 0b11;
+        /// Times to poll the `seed` CSR for 16 bits of entropy.
+        const MAX_POLLS: usize = 1000;
+
+        const ZKR_UNKNOWN: u8 = 0;
+        const ZKR_ABSENT: u8 = 1;
+        const ZKR_PRESENT: u8 = 2;
+
+        static ZKR_STATE: AtomicU8 = AtomicU8::new(ZKR_UNKNOWN);
+
+        fn read_seed16() -> Option<u64> {
+            for _ in 0..MAX_POLLS {
+                let seed: usize;
+                // The `seed` CSR must be accessed by a write.
+                unsafe { core::arch::asm!("csrrw {}, 0x015, x0", out(reg) seed) };
+                match seed >> 30 {
+                    OPST_ES16 => return Some((seed & 0xffff) as u64),
+                    OPST_DEAD => return None,
+                    _ => core::hint::spin_loop(),
+                }
+            }
+            None
+        }
+
+        fn hw_random_u64() -> Option<u64> {
+            // The `seed` CSR traps if the firmware does not allow S-mode to
+            // access it, it is assumed to be allowed if Zkr is present.
+            let mut state = ZKR_STATE.load(Ordering::Relaxed);
+            if state == ZKR_UNKNOWN {
+                let zkr = crate::arch::has_isa_extension("zkr");
+                state = if zkr { ZKR_PRESENT } else { ZKR_ABSENT };
+                ZKR_STATE.store(state, Ordering::Relaxed);
+            }
+            if state != ZKR_PRESENT {
+                return None;
+            }
+            (0..4).try_fold(0, |val, _| Some((val << 16) | read_seed16()?))
+        }
+    } else {
+        fn hw_random_u64() -> Option<u64> {
+            None
+        }
+    }
+}
+
+/// Returns a random number from the hardware random number generator, or
+/// [`None`] if the CPU has none or it fails.
+pub fn random_u64() -> Option<u64> {
+    hw_random_u64()
+}
+
+/// Returns a random number from the jitter of the cycle counter.
+///
+/// It is a last resort if [`random_u64`] fails, as the jitter has little
+/// entropy and may be predictable, especially on emulators. It should only be
+/// mixed into a seed, never used directly as a random number.
+pub fn jitter_random_u64() -> u64 {
+    let mut val = 0u64;
+    for i in 0..64 {
+        let start = crate::perf::read_cycles();
+        // Spin for a varying time, whose length depends on the caches and
+        // the pipeline.
+        for _ in 0..(val & 0xf) + 1 {
+            core::hint::spin_loop();
+        }
+        let delta = crate::perf::read_cycles().wrapping_sub(start);
+        val = (val ^ delta).rotate_left(7).wrapping_add(i);
+    }
+    val
+}
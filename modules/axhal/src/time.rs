@@ -1,5 +1,9 @@
 //! Time-related operations.
 
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use int_ratio::Ratio;
+
 pub use core::time::Duration;
 
 /// A measurement of the system clock.
@@ -10,9 +14,9 @@ pub type TimeValue = Duration;
 
 #[cfg(feature = "irq")]
 pub use crate::platform::irq::TIMER_IRQ_NUM;
+pub use crate::platform::time::current_ticks;
 #[cfg(feature = "irq")]
 pub use crate::platform::time::set_oneshot_timer;
-pub use crate::platform::time::{current_ticks, epochoffset_nanos, nanos_to_ticks, ticks_to_nanos};
 
 /// Number of milliseconds in a second.
 pub const MILLIS_PER_SEC: u64 = 1_000;
@@ -25,6 +29,175 @@ pub const NANOS_PER_MILLIS: u64 = 1_000_000;
 /// Number of nanoseconds in a microsecond.
 pub const NANOS_PER_MICROS: u64 = 1_000;
 
+/// Frequency of the hardware clock in Hz, detected by the platform at boot.
+static TIMER_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+static mut TICKS_TO_NANOS_RATIO: Ratio = Ratio::zero();
+static mut NANOS_TO_TICKS_RATIO: Ratio = Ratio::zero();
+
+/// The highest frequency of the scheduler ticks in Hz.
+pub const MAX_TICK_FREQUENCY: u64 = 10_000;
+
+/// Frequency of the scheduler ticks in Hz, the `ticks-per-sec` item of the
+/// platform config unless it is set at boot.
+static TICK_FREQUENCY: AtomicU64 = AtomicU64::new(axconfig::TICKS_PER_SEC as u64);
+
+/// Returns the frequency of the scheduler ticks in Hz.
+pub fn tick_frequency() -> u64 {
+    TICK_FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Returns the interval of the scheduler ticks in nanoseconds.
+pub fn tick_nanos() -> u64 {
+    NANOS_PER_SEC / tick_frequency()
+}
+
+/// Sets the frequency of the scheduler ticks, called once at boot before the
+/// timer interrupts are enabled.
+///
+/// # Panics
+///
+/// Panics if it is 0 or above [`MAX_TICK_FREQUENCY`].
+pub fn init_tick_frequency(freq_hz: u64) {
+    assert!(
+        (1..=MAX_TICK_FREQUENCY).contains(&freq_hz),
+        "tick frequency {} Hz is out of range",
+        freq_hz
+    );
+    TICK_FREQUENCY.store(freq_hz, Ordering::Relaxed);
+}
+
+/// Returns the frequency of the hardware clock in Hz, which is detected or
+/// calibrated at boot, or 0 if the platform has no clock.
+pub fn timer_frequency() -> u64 {
+    TIMER_FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Converts hardware ticks to nanoseconds.
+#[inline]
+pub fn ticks_to_nanos(ticks: u64) -> u64 {
+    unsafe { (*core::ptr::addr_of!(TICKS_TO_NANOS_RATIO)).mul_trunc(ticks) }
+}
+
+/// Converts nanoseconds to hardware ticks.
+#[inline]
+pub fn nanos_to_ticks(nanos: u64) -> u64 {
+    unsafe { (*core::ptr::addr_of!(NANOS_TO_TICKS_RATIO)).mul_trunc(nanos) }
+}
+
+/// Returns `numerator / denominator` as a ratio of 32-bit integers.
+///
+/// Both are divided by their GCD first, and if they are still too large, low
+/// bits of both are dropped, which changes the ratio by less than 2^-31. The
+/// multiplication in [`Ratio::mul_trunc`] is 128-bit, so the conversions do
+/// not overflow for any uptime representable in 64 bits.
+fn ratio(numerator: u64, denominator: u64) -> Ratio {
+    let (mut a, mut b) = (numerator, denominator);
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    let (mut numerator, mut denominator) = (numerator / a, denominator / a);
+    while numerator > u32::MAX as u64 || denominator > u32::MAX as u64 {
+        numerator >>= 1;
+        denominator >>= 1;
+    }
+    Ratio::new(numerator as u32, denominator.max(1) as u32)
+}
+
+/// Sets the frequency of the hardware clock, called by the platform once at
+/// boot before the monotonic clock is used. `source` tells how the frequency
+/// was obtained, which is logged.
+#[allow(dead_code)]
+pub(crate) fn init_timer_frequency(freq_hz: u64, source: &str) {
+    assert!(freq_hz > 0, "unknown timer frequency");
+    TIMER_FREQUENCY.store(freq_hz, Ordering::Relaxed);
+    unsafe {
+        TICKS_TO_NANOS_RATIO = ratio(NANOS_PER_SEC, freq_hz);
+        NANOS_TO_TICKS_RATIO = ratio(freq_hz, NANOS_PER_SEC);
+    }
+    axlog::ax_println!(
+        "Timer frequency: {}.{:06} MHz ({})",
+        freq_hz / 1_000_000,
+        freq_hz % 1_000_000,
+        source
+    );
+}
+
+/// The relation between the monotonic clock and the wall clock: the wall
+/// time at the monotonic time 0 (the boot epoch).
+///
+/// The boot epoch is kept in one atomic word, so readers never see a torn
+/// value while it is set or adjusted. Changing it moves the wall clock only,
+/// the monotonic clock never jumps.
+pub struct TimeBase {
+    /// The boot epoch in nanoseconds since the Unix epoch.
+    epoch_nanos: AtomicU64,
+    /// Whether the boot epoch is known, from the RTC or [`set_wall_time`].
+    valid: AtomicBool,
+}
+
+impl TimeBase {
+    /// Creates a time base whose boot epoch is unknown, i.e., the wall clock
+    /// starts from the Unix epoch at boot.
+    pub const fn new() -> Self {
+        Self {
+            epoch_nanos: AtomicU64::new(0),
+            valid: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the boot epoch in nanoseconds since the Unix epoch.
+    pub fn epoch_nanos(&self) -> u64 {
+        self.epoch_nanos.load(Ordering::Acquire)
+    }
+
+    /// Whether the boot epoch is known, so that the wall clock tells the
+    /// calendar time.
+    pub fn is_valid(&self) -> bool {
+        self.valid.load(Ordering::Acquire)
+    }
+
+    /// Converts the monotonic time in nanoseconds to the wall time in
+    /// nanoseconds since the Unix epoch.
+    pub fn wall_time_nanos(&self, monotonic_nanos: u64) -> u64 {
+        monotonic_nanos.saturating_add(self.epoch_nanos())
+    }
+
+    /// Sets the boot epoch so that the monotonic time `monotonic_nanos`
+    /// corresponds to the wall time `wall_nanos`.
+    ///
+    /// Wall times before the system boot are clamped to the boot time, as the
+    /// boot epoch is unsigned.
+    pub fn set(&self, wall_nanos: u64, monotonic_nanos: u64) {
+        let epoch = wall_nanos.saturating_sub(monotonic_nanos);
+        self.epoch_nanos.store(epoch, Ordering::Release);
+        self.valid.store(true, Ordering::Release);
+    }
+
+    /// Moves the boot epoch by `delta_nanos`, forward if positive and backward
+    /// if negative, clamped at the Unix epoch.
+    pub fn adjust(&self, delta_nanos: i64) {
+        let _ = self
+            .epoch_nanos
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |epoch| {
+                Some(epoch.saturating_add_signed(delta_nanos))
+            });
+    }
+}
+
+impl Default for TimeBase {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static TIME_BASE: TimeBase = TimeBase::new();
+
+/// Returns the system [`TimeBase`], which relates the monotonic clock to the
+/// wall clock.
+pub fn time_base() -> &'static TimeBase {
+    &TIME_BASE
+}
+
 /// Returns nanoseconds elapsed since system boot.
 pub fn monotonic_time_nanos() -> u64 {
     ticks_to_nanos(current_ticks())
@@ -36,23 +209,76 @@ pub fn monotonic_time() -> TimeValue {
 }
 
 /// Returns nanoseconds elapsed since epoch (also known as realtime).
+///
+/// Unlike the monotonic time, it jumps when the wall time is set or adjusted,
+/// possibly backward.
 pub fn wall_time_nanos() -> u64 {
-    monotonic_time_nanos() + epochoffset_nanos()
+    TIME_BASE.wall_time_nanos(monotonic_time_nanos())
 }
 
 /// Returns the time elapsed since epoch (also known as realtime) in [`TimeValue`].
 pub fn wall_time() -> TimeValue {
-    TimeValue::from_nanos(monotonic_time_nanos() + epochoffset_nanos())
+    TimeValue::from_nanos(wall_time_nanos())
+}
+
+/// Return epoch offset in nanoseconds (wall time offset to monotonic clock start).
+pub fn epochoffset_nanos() -> u64 {
+    TIME_BASE.epoch_nanos()
+}
+
+/// Whether the wall time is known, i.e., read from the RTC or set by
+/// [`set_wall_time`]. Otherwise the wall clock starts from the epoch at boot.
+pub fn wall_time_valid() -> bool {
+    TIME_BASE.is_valid()
+}
+
+/// Sets the wall time, which is also written to the RTC if the platform has
+/// a writable one, so that it persists across reboots.
+///
+/// Wall times before the system boot are clamped to the boot time, as the
+/// epoch offset is unsigned.
+pub fn set_wall_time(time: TimeValue) {
+    store_wall_time_nanos(time.as_nanos().min(u64::MAX as u128) as u64);
+    #[cfg(feature = "rtc")]
+    crate::platform::time::write_rtc(time.as_secs());
+}
+
+/// Corrects the wall time by `delta_nanos`, forward if positive and backward
+/// if negative, e.g., by an SNTP client. The monotonic time is not affected.
+///
+/// The RTC is not written, call [`set_wall_time`] to persist the wall time.
+pub fn adjust_wall_time(delta_nanos: i64) {
+    TIME_BASE.adjust(delta_nanos);
+}
+
+/// Initializes the wall time from the RTC, called by the platform once the
+/// monotonic clock works. The RTC is not read again, the wall time advances
+/// with the monotonic clock.
+#[allow(dead_code)]
+pub(crate) fn init_wall_time() {
+    #[cfg(feature = "rtc")]
+    if let Some(secs) = crate::platform::time::read_rtc() {
+        store_wall_time_nanos(secs.saturating_mul(NANOS_PER_SEC));
+    }
+}
+
+fn store_wall_time_nanos(nanos: u64) {
+    TIME_BASE.set(nanos, monotonic_time_nanos());
 }
 
 /// Busy waiting for the given duration.
+///
+/// It uses the monotonic clock, so it is not affected by [`set_wall_time`].
 pub fn busy_wait(dur: Duration) {
-    busy_wait_until(wall_time() + dur);
+    let deadline = monotonic_time() + dur;
+    while monotonic_time() < deadline {
+        core::hint::spin_loop();
+    }
 }
 
-/// Busy waiting until reaching the given deadline.
+/// Busy waiting until reaching the given deadline of the monotonic clock.
 pub fn busy_wait_until(deadline: TimeValue) {
-    while wall_time() < deadline {
+    while monotonic_time() < deadline {
         core::hint::spin_loop();
     }
 }
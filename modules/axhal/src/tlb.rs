@@ -0,0 +1,166 @@
+//! TLB maintenance across CPUs.
+//!
+//! When the mappings of an address space change, other CPUs where it is
+//! active may still cache the old ones in their TLBs. [`flush_remote`] sends
+//! them a dedicated TLB shootdown IPI, and waits until each of them has
+//! flushed the range.
+//!
+//! Address spaces are identified by the physical addresses of their page
+//! table roots, and each CPU records the one it is running on by
+//! [`set_active_aspace`] when switching to it.
+//!
+//! Without the `smp` and `irq` features, there are no other CPUs to notify,
+//! and [`flush_remote`] only flushes the current CPU.
+
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use memory_addr::{MemoryAddr, VirtAddr, PAGE_SIZE_4K};
+
+/// Ranges of more pages are flushed by flushing the entire TLB.
+const FLUSH_ALL_THRESHOLD: usize = 32;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_ASPACE: AtomicUsize = AtomicUsize::new(0);
+/// The address space active on each CPU, 0 for none yet.
+static ACTIVE_ASPACES: [AtomicUsize; axconfig::SMP] = [NO_ASPACE; axconfig::SMP];
+
+/// Records that the address space `aspace_id`, i.e., the physical address of
+/// its page table root, is active on the current CPU.
+///
+/// It must be called right before the page table root is written, so that
+/// the CPU gets all the shootdowns of the address space from when it may
+/// cache its mappings.
+pub fn set_active_aspace(aspace_id: usize) {
+    ACTIVE_ASPACES[crate::cpu::this_cpu_id()].store(aspace_id, Ordering::SeqCst);
+}
+
+/// Returns the address space active on the current CPU, recorded by
+/// [`set_active_aspace`].
+pub fn active_aspace() -> usize {
+    ACTIVE_ASPACES[crate::cpu::this_cpu_id()].load(Ordering::SeqCst)
+}
+
+/// Flushes the TLB entries of the range on the current CPU, or the entire
+/// TLB if the range is large.
+pub fn flush_local(range: &Range<VirtAddr>) {
+    let start = range.start.align_down_4k();
+    let num_pages = (range.end.align_up_4k().as_usize() - start.as_usize()) / PAGE_SIZE_4K;
+    if num_pages > FLUSH_ALL_THRESHOLD {
+        crate::arch::flush_tlb(None);
+    } else {
+        for i in 0..num_pages {
+            crate::arch::flush_tlb(Some(start + i * PAGE_SIZE_4K));
+        }
+    }
+}
+
+/// Flushes the TLB entries of `vaddr_range` in the address space `aspace_id`
+/// on all CPUs where it is active, see [`set_active_aspace`].
+///
+/// The current CPU is flushed directly if the address space is active on it.
+/// The other online CPUs are sent a TLB shootdown IPI, and it blocks until
+/// all of them acknowledge that they have flushed the range. If some do not
+/// within a timeout, e.g., because they run with IRQs disabled for too long,
+/// it gives up with a warning.
+///
+/// # Panics
+///
+/// Panics if other CPUs must be notified while IRQs are disabled on the
+/// current CPU, since two CPUs flushing each other with IRQs disabled would
+/// both wait until the timeout. It must not be called with a spinlock that
+/// disables IRQs held, e.g., that of the kernel address space.
+pub fn flush_remote(vaddr_range: Range<VirtAddr>, aspace_id: usize) {
+    if active_aspace() == aspace_id {
+        flush_local(&vaddr_range);
+    }
+    #[cfg(all(feature = "smp", feature = "irq"))]
+    shootdown::flush_others(vaddr_range, aspace_id);
+}
+
+/// Handles the TLB shootdown IPI, flushing the range requested by
+/// [`flush_remote`] and acknowledging it. Called by the platform IRQ
+/// dispatching.
+#[cfg(feature = "irq")]
+pub(crate) fn handle_ipi() {
+    #[cfg(feature = "smp")]
+    shootdown::handle_request();
+}
+
+#[cfg(all(feature = "smp", feature = "irq"))]
+mod shootdown {
+    use core::ops::Range;
+    use core::sync::atomic::{fence, AtomicUsize, Ordering};
+
+    use kspin::SpinNoPreempt;
+    use memory_addr::VirtAddr;
+
+    use super::ACTIVE_ASPACES;
+    use crate::cpu::{cpu_online, this_cpu_id};
+    use crate::time::{monotonic_time, Duration};
+
+    const _: () = assert!(axconfig::SMP <= usize::BITS as usize);
+
+    /// How long [`flush_others`] waits for the acknowledgements.
+    const SHOOTDOWN_TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// Serializes the shootdowns, the waiting initiator keeps IRQs enabled so
+    /// that it can still be flushed by another one.
+    static SHOOTDOWN_LOCK: SpinNoPreempt<()> = SpinNoPreempt::new(());
+    /// The range of the ongoing shootdown.
+    static FLUSH_START: AtomicUsize = AtomicUsize::new(0);
+    static FLUSH_END: AtomicUsize = AtomicUsize::new(0);
+    /// The CPUs that have not acknowledged the ongoing shootdown yet.
+    static PENDING_CPUS: AtomicUsize = AtomicUsize::new(0);
+
+    pub fn flush_others(vaddr_range: Range<VirtAddr>, aspace_id: usize) {
+        // Orders the page table updates before reading the active CPUs, which
+        // pairs with `set_active_aspace()` before switching page tables.
+        fence(Ordering::SeqCst);
+        let this = this_cpu_id();
+        let targets = (0..axconfig::SMP)
+            .filter(|&i| i != this && cpu_online(i))
+            .filter(|&i| ACTIVE_ASPACES[i].load(Ordering::SeqCst) == aspace_id)
+            .fold(0, |mask, i| mask | 1 << i);
+        if targets == 0 {
+            return;
+        }
+        assert!(
+            crate::arch::irqs_enabled(),
+            "TLB shootdown with IRQs disabled"
+        );
+
+        let _guard = SHOOTDOWN_LOCK.lock();
+        FLUSH_START.store(vaddr_range.start.as_usize(), Ordering::Relaxed);
+        FLUSH_END.store(vaddr_range.end.as_usize(), Ordering::Relaxed);
+        PENDING_CPUS.store(targets, Ordering::Release);
+        for i in (0..axconfig::SMP).filter(|&i| targets & 1 << i != 0) {
+            crate::platform::irq::send_tlb_ipi(i);
+        }
+
+        let deadline = monotonic_time() + SHOOTDOWN_TIMEOUT;
+        while PENDING_CPUS.load(Ordering::Acquire) != 0 {
+            if monotonic_time() > deadline {
+                warn!(
+                    "TLB shootdown of [{:#x}, {:#x}) timed out, CPUs {:#x} did not respond",
+                    vaddr_range.start,
+                    vaddr_range.end,
+                    PENDING_CPUS.swap(0, Ordering::AcqRel)
+                );
+                break;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    pub fn handle_request() {
+        let bit = 1 << this_cpu_id();
+        if PENDING_CPUS.load(Ordering::Acquire) & bit == 0 {
+            return;
+        }
+        let start = FLUSH_START.load(Ordering::Relaxed);
+        let end = FLUSH_END.load(Ordering::Relaxed);
+        super::flush_local(&(VirtAddr::from(start)..VirtAddr::from(end)));
+        PENDING_CPUS.fetch_and(!bit, Ordering::Release);
+    }
+}
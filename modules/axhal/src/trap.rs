@@ -1,8 +1,10 @@
 //! Trap handling.
 
+use core::fmt;
+
+use lazyinit::LazyInit;
 use linkme::distributed_slice as def_trap_handler;
 use memory_addr::VirtAddr;
-use page_table_entry::MappingFlags;
 
 pub use linkme::distributed_slice as register_trap_handler;
 
@@ -10,9 +12,127 @@ pub use linkme::distributed_slice as register_trap_handler;
 #[def_trap_handler]
 pub static IRQ: [fn(usize) -> bool];
 
-/// A slice of page fault handler functions.
-#[def_trap_handler]
-pub static PAGE_FAULT: [fn(VirtAddr, MappingFlags, bool) -> bool];
+/// The type of a page fault handler, which returns whether the fault is
+/// resolved, so that the faulting instruction can be retried.
+pub type PageFaultHandler = fn(&PageFaultInfo) -> bool;
+
+static PAGE_FAULT_HANDLER: LazyInit<PageFaultHandler> = LazyInit::new();
+
+/// The type of a handler of the overflows of other kernel stacks than the
+/// boot or exception stacks, e.g., those of tasks. It is given the faulting
+/// address of a fatal kernel page fault, and does not return if the fault is
+/// on a stack guard page it knows.
+pub type StackOverflowHandler = fn(VirtAddr);
+
+static STACK_OVERFLOW_HANDLER: LazyInit<StackOverflowHandler> = LazyInit::new();
+
+/// The kind of the access that caused a page fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFaultAccess {
+    /// A data read.
+    Read,
+    /// A data write.
+    Write,
+    /// An instruction fetch.
+    Execute,
+}
+
+/// The information of a page fault, decoded in the same way on all
+/// architectures.
+#[derive(Debug, Clone, Copy)]
+pub struct PageFaultInfo {
+    /// The faulting virtual address.
+    pub vaddr: VirtAddr,
+    /// The kind of the faulting access.
+    pub access: PageFaultAccess,
+    /// Whether the fault is taken from user mode.
+    pub user: bool,
+}
+
+impl PageFaultInfo {
+    /// Whether the faulting address is in the user address space, i.e., below
+    /// the kernel address space.
+    pub fn is_user_addr(&self) -> bool {
+        self.vaddr.as_usize() < axconfig::KERNEL_ASPACE_BASE
+    }
+
+    /// Whether the kernel faults on a user address, e.g., while copying from
+    /// or to user memory, which can be resolved as for a user fault. Other
+    /// faults taken from kernel mode are kernel bugs.
+    pub fn is_kernel_access_to_user(&self) -> bool {
+        !self.user && self.is_user_addr()
+    }
+}
+
+impl fmt::Display for PageFaultInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mode = if self.user { "user" } else { "kernel" };
+        write!(
+            f,
+            "{} {:?} page fault at {:#x}",
+            mode, self.access, self.vaddr
+        )?;
+        if self.is_kernel_access_to_user() {
+            write!(f, " (on a user address)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Registers the handler of all page faults.
+///
+/// Returns `false` if a handler is already registered. Unresolved faults, or
+/// all faults without a handler, are fatal.
+pub fn register_page_fault_handler(handler: PageFaultHandler) -> bool {
+    if PAGE_FAULT_HANDLER.is_inited() {
+        return false;
+    }
+    PAGE_FAULT_HANDLER.init_once(handler);
+    true
+}
+
+/// Registers the handler of the overflows of other kernel stacks, see
+/// [`StackOverflowHandler`].
+///
+/// Returns `false` if a handler is already registered.
+pub fn register_stack_overflow_handler(handler: StackOverflowHandler) -> bool {
+    if STACK_OVERFLOW_HANDLER.is_inited() {
+        return false;
+    }
+    STACK_OVERFLOW_HANDLER.init_once(handler);
+    true
+}
+
+/// Calls the registered page fault handler, returns whether the fault is
+/// resolved. The architecture trap handlers panic with `info` otherwise.
+#[allow(dead_code)]
+pub(crate) fn handle_page_fault(info: &PageFaultInfo) -> bool {
+    match PAGE_FAULT_HANDLER.get() {
+        Some(handler) => handler(info),
+        None => false,
+    }
+}
+
+/// Panics with a stack overflow report if the kernel faults on the guard page
+/// of a boot or exception stack, or lets the registered
+/// [`StackOverflowHandler`] handle a fault on the guard page of another
+/// stack. Called by the architecture trap handlers before they panic with a
+/// generic fatal trap.
+#[allow(dead_code)]
+pub(crate) fn check_stack_overflow(info: &PageFaultInfo) {
+    if info.user {
+        return;
+    }
+    if let Some(guard) = crate::mem::stack_guard_at(info.vaddr) {
+        panic!(
+            "stack overflow on CPU {} in {} ({})",
+            guard.cpu_id, guard.context, info
+        );
+    }
+    if let Some(handler) = STACK_OVERFLOW_HANDLER.get() {
+        handler(info.vaddr);
+    }
+}
 
 #[allow(unused_macros)]
 macro_rules! handle_trap {
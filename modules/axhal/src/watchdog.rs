@@ -0,0 +1,90 @@
+//! Watchdog that resets the system if it is not fed in time.
+//!
+//! The hardware watchdog of the platform is used if there is one: the TCO
+//! watchdog on x86_64 (QEMU q35), or the SBSA generic watchdog on aarch64.
+//! Otherwise, a software watchdog checks the deadline on each interrupt,
+//! including the periodic timer interrupts, and reboots the system when it
+//! expires. It does not catch hangs with IRQs disabled.
+
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use crate::time::{monotonic_time_nanos, Duration};
+
+cfg_if::cfg_if! {
+    if #[cfg(any(
+        all(target_arch = "x86_64", platform_family = "x86-pc"),
+        all(target_arch = "aarch64", not(platform_family = "aarch64-raspi")),
+    ))] {
+        use crate::platform::watchdog as hw;
+    } else {
+        mod hw {
+            pub fn start(_timeout: super::Duration) -> bool {
+                false
+            }
+            pub fn feed() {}
+            pub fn stop() {}
+        }
+    }
+}
+
+const STOPPED: u8 = 0;
+const HARDWARE: u8 = 1;
+const SOFTWARE: u8 = 2;
+
+static STATE: AtomicU8 = AtomicU8::new(STOPPED);
+static TIMEOUT_NANOS: AtomicU64 = AtomicU64::new(0);
+/// Deadline of the software watchdog in monotonic nanoseconds.
+static SOFT_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Starts the watchdog, which resets the system if it is not fed within
+/// `timeout`.
+///
+/// If it is already started, the timeout is changed.
+pub fn start(timeout: Duration) {
+    if STATE.load(Ordering::Acquire) == HARDWARE {
+        hw::stop();
+    }
+    if hw::start(timeout) {
+        info!("Watchdog started with timeout {:?}", timeout);
+        STATE.store(HARDWARE, Ordering::Release);
+    } else {
+        info!("Software watchdog started with timeout {:?}", timeout);
+        TIMEOUT_NANOS.store(timeout.as_nanos() as u64, Ordering::Release);
+        SOFT_DEADLINE.store(deadline_from_now(), Ordering::Release);
+        STATE.store(SOFTWARE, Ordering::Release);
+    }
+}
+
+/// Feeds the watchdog, so that it restarts counting down the timeout.
+pub fn feed() {
+    match STATE.load(Ordering::Acquire) {
+        HARDWARE => hw::feed(),
+        SOFTWARE => SOFT_DEADLINE.store(deadline_from_now(), Ordering::Release),
+        _ => {}
+    }
+}
+
+/// Stops the watchdog.
+pub fn stop() {
+    match STATE.swap(STOPPED, Ordering::AcqRel) {
+        HARDWARE => hw::stop(),
+        SOFTWARE => SOFT_DEADLINE.store(u64::MAX, Ordering::Release),
+        _ => return,
+    }
+    info!("Watchdog stopped");
+}
+
+fn deadline_from_now() -> u64 {
+    monotonic_time_nanos().saturating_add(TIMEOUT_NANOS.load(Ordering::Acquire))
+}
+
+/// Reboots the system if the software watchdog has expired.
+#[cfg(feature = "irq")]
+pub(crate) fn check_soft_deadline() {
+    let deadline = SOFT_DEADLINE.load(Ordering::Acquire);
+    if deadline != u64::MAX && monotonic_time_nanos() > deadline {
+        error!("Watchdog expired, rebooting...");
+        // Do not call the shutdown hooks, as the system is not responding.
+        crate::platform::misc::reboot();
+    }
+}
@@ -11,11 +11,11 @@ mod aspace;
 pub use self::aspace::AddrSpace;
 
 use axerrno::{AxError, AxResult};
-use axhal::mem::phys_to_virt;
-use axhal::paging::PagingError;
+use axhal::mem::{phys_to_virt, virt_to_phys};
+use axhal::paging::{MappingFlags, PagingError};
 use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
-use memory_addr::{va, PhysAddr};
+use memory_addr::{va, PhysAddr, VirtAddr, PAGE_SIZE_4K};
 
 static KERNEL_ASPACE: LazyInit<SpinNoIrq<AddrSpace>> = LazyInit::new();
 
@@ -39,6 +39,9 @@ pub fn new_kernel_aspace() -> AxResult<AddrSpace> {
     for r in axhal::mem::memory_regions() {
         aspace.map_linear(phys_to_virt(r.paddr), r.paddr, r.size, r.flags.into())?;
     }
+    for guard in axhal::mem::stack_guards() {
+        aspace.unmap(guard.vaddr, axhal::mem::STACK_GUARD_SIZE)?;
+    }
     Ok(aspace)
 }
 
@@ -62,10 +65,41 @@ pub fn init_memory_management() {
     let kernel_aspace = new_kernel_aspace().expect("failed to initialize kernel address space");
     debug!("kernel address space init OK: {:#x?}", kernel_aspace);
     KERNEL_ASPACE.init_once(SpinNoIrq::new(kernel_aspace));
-    unsafe { axhal::arch::write_page_table_root(kernel_page_table_root()) };
+    activate_kernel_aspace();
+    axhal::mem::register_guard_page_fn(set_guard_page);
 }
 
 /// Initializes kernel paging for secondary CPUs.
 pub fn init_memory_management_secondary() {
-    unsafe { axhal::arch::write_page_table_root(kernel_page_table_root()) };
+    activate_kernel_aspace();
+}
+
+/// Unmaps a page of the kernel address space as a guard page, or maps it back
+/// linearly, see [`axhal::mem::set_guard_page`].
+fn set_guard_page(vaddr: VirtAddr, guard: bool) -> bool {
+    let mut aspace = KERNEL_ASPACE.lock();
+    let res = if guard {
+        aspace.unmap(vaddr, PAGE_SIZE_4K)
+    } else {
+        let flags = MappingFlags::READ | MappingFlags::WRITE;
+        aspace.map_linear(vaddr, virt_to_phys(vaddr), PAGE_SIZE_4K, flags)
+    };
+    let root = aspace.page_table_root();
+    drop(aspace);
+    if res.is_err() {
+        return false;
+    }
+    // Other CPUs can not be notified with IRQs disabled, e.g., when a CPU
+    // starts, then they may miss a new guard page until the stale TLB entry
+    // is evicted.
+    if axhal::arch::irqs_enabled() {
+        axhal::tlb::flush_remote(vaddr..vaddr + PAGE_SIZE_4K, root.as_usize());
+    }
+    true
+}
+
+fn activate_kernel_aspace() {
+    let root = kernel_page_table_root();
+    axhal::tlb::set_active_aspace(root.as_usize());
+    unsafe { axhal::arch::write_page_table_root(root) };
 }
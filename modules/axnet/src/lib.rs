@@ -8,17 +8,23 @@
 //!
 //! - [`TcpSocket`]: A TCP socket that provides POSIX-like APIs.
 //! - [`UdpSocket`]: A UDP socket that provides POSIX-like APIs.
-//! - [`dns_query`]: Function for DNS query.
+//! - [`dns_query`]: Function for DNS query, with [`set_dns_servers`] to
+//!   replace the nameservers.
+//! - [`interface_stats`]: Packet and error counters of the interfaces.
+//! - [`net_events`], [`wait_interfaces`]: Sleeping until the sockets may be
+//!   ready, for `poll`-like APIs.
 //!
 //! # Cargo Features
 //!
 //! - `smoltcp`: Use [smoltcp] as the underlying network stack. This is enabled
 //!   by default.
+//! - `irq`, `multitask`: If both are enabled, blocking socket operations sleep
+//!   until the NIC interrupts on received packets, instead of polling the NIC
+//!   in a loop. NICs without interrupt support are still polled.
 //!
 //! [smoltcp]: https://github.com/smoltcp-rs/smoltcp
 
 #![no_std]
-#![feature(new_uninit)]
 
 #[macro_use]
 extern crate log;
@@ -31,18 +37,37 @@ cfg_if::cfg_if! {
     }
 }
 
-pub use self::net_impl::TcpSocket;
 pub use self::net_impl::UdpSocket;
 pub use self::net_impl::{bench_receive, bench_transmit};
-pub use self::net_impl::{dns_query, poll_interfaces};
+pub use self::net_impl::{
+    dns_query, dns_servers, interface_stats, net_events, poll_interfaces, set_dns_servers,
+    wait_interfaces,
+};
+pub use self::net_impl::{ConnectError, TcpSocket};
+pub use axdriver::prelude::NetStats;
 
 use axdriver::{prelude::*, AxDeviceContainer};
 
 /// Initializes the network subsystem by NIC devices.
-pub fn init_network(mut net_devs: AxDeviceContainer<AxNetDevice>) {
+///
+/// An interface is created for each NIC, the first one (`eth0`) is used to
+/// route all sockets except those connected to 127.0.0.1, which go through the
+/// loopback interface `lo`.
+pub fn init_network(net_devs: AxDeviceContainer<AxNetDevice>) {
     info!("Initialize network subsystem...");
 
-    let dev = net_devs.take_one().expect("No NIC device found!");
-    info!("  use NIC 0: {:?}", dev.device_name());
-    net_impl::init(dev);
+    assert!(!net_devs.is_empty(), "No NIC device found!");
+    for (name, dev) in net_devs.iter() {
+        info!("  use NIC {}: {:?}", name, dev.device_name());
+    }
+    net_impl::init(net_devs);
+}
+
+/// Releases the NIC devices of all interfaces.
+///
+/// It is called on orderly shutdown, sockets can no longer send or receive
+/// packets afterwards. Calling it more than once is harmless.
+pub fn shutdown_network() {
+    info!("Shutdown network subsystem...");
+    net_impl::shutdown_interfaces();
 }
@@ -0,0 +1,130 @@
+//! The DHCP client configuring `eth0`, if its IP address is `dhcp`.
+//!
+//! The smoltcp DHCP socket runs the DISCOVER/OFFER/REQUEST/ACK exchange from
+//! the unspecified address, renews the lease at T1 and rebinds it at T2, and
+//! starts the discovery again if the lease expires or the server NAKs. Its
+//! events are handled whenever the interfaces are polled, adding the leased
+//! address, the gateway and the DNS servers, or removing them.
+//!
+//! With `irq` and `multitask`, a background task polls the interfaces when
+//! the socket needs to send, so the lease is renewed without socket
+//! operations.
+
+use alloc::vec::Vec;
+use core::net::{IpAddr, Ipv4Addr};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use axsync::LazyInit;
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::dhcpv4::{self, Event};
+use smoltcp::wire::{IpCidr, Ipv4Address, Ipv4Cidr};
+
+use super::{ETH0, SOCKET_SET};
+
+/// The longest time the boot waits for a lease.
+const INIT_TIMEOUT: Duration = Duration::from_secs(5);
+/// The longest time the background task sleeps, so that the replies are
+/// received in time on the NICs without interrupts.
+#[cfg(all(feature = "irq", feature = "multitask"))]
+const MAX_SLEEP: Duration = Duration::from_secs(1);
+
+static HANDLE: LazyInit<SocketHandle> = LazyInit::new();
+static CONFIGURED: AtomicBool = AtomicBool::new(false);
+
+/// The configuration of a lease.
+struct Lease {
+    address: Ipv4Cidr,
+    router: Option<Ipv4Address>,
+    dns_servers: Vec<IpAddr>,
+}
+
+/// Starts the DHCP client on `eth0`, after the socket set is initialized.
+pub(super) fn init() {
+    HANDLE.init_once(SOCKET_SET.add(dhcpv4::Socket::new()));
+    #[cfg(all(feature = "irq", feature = "multitask"))]
+    axtask::Builder::new().name("dhcp").spawn(|| loop {
+        SOCKET_SET.poll_interfaces();
+        let delay = ETH0.poll_delay(&SOCKET_SET.0);
+        axtask::sleep(delay.map_or(MAX_SLEEP, |delay| delay.min(MAX_SLEEP)));
+    });
+}
+
+/// Polls the interfaces until `eth0` gets a lease, or the timeout elapses.
+///
+/// It busy-waits, as the interrupts are not enabled yet at boot.
+pub(super) fn wait_configured() {
+    let deadline = axhal::time::monotonic_time() + INIT_TIMEOUT;
+    while !CONFIGURED.load(Ordering::Acquire) {
+        if axhal::time::monotonic_time() >= deadline {
+            warn!(
+                "DHCP: no lease in {:?}, continue in background",
+                INIT_TIMEOUT
+            );
+            return;
+        }
+        SOCKET_SET.poll_interfaces();
+        axhal::time::busy_wait(Duration::from_millis(1));
+    }
+}
+
+/// Handles the event of the DHCP socket, after the interfaces are polled.
+pub(super) fn poll() {
+    let Some(&handle) = HANDLE.get() else {
+        return;
+    };
+    // The interface is locked after the socket set is released, in the order
+    // they are locked by polling.
+    let event = SOCKET_SET.with_socket_mut::<dhcpv4::Socket, _, _>(handle, |socket| {
+        match socket.poll()? {
+            Event::Configured(config) => Some(Some(Lease {
+                address: config.address,
+                router: config.router,
+                dns_servers: config
+                    .dns_servers
+                    .iter()
+                    .map(|ip| IpAddr::V4(Ipv4Addr::from(ip.0)))
+                    .collect(),
+            })),
+            Event::Deconfigured => Some(None),
+        }
+    });
+    match event {
+        Some(Some(lease)) => configure(lease),
+        Some(None) => deconfigure(),
+        None => {}
+    }
+}
+
+fn configure(lease: Lease) {
+    info!("DHCP: leased {} on {:?}", lease.address, ETH0.name());
+    let mut iface = ETH0.iface.lock();
+    iface.update_ip_addrs(|addrs| {
+        // Keep the IPv6 addresses.
+        addrs.retain(|addr| !matches!(addr, IpCidr::Ipv4(_)));
+        addrs.push(IpCidr::Ipv4(lease.address)).unwrap();
+    });
+    match lease.router {
+        Some(router) => {
+            info!("  gateway:  {}", router);
+            iface.routes_mut().add_default_ipv4_route(router).unwrap();
+        }
+        None => {
+            iface.routes_mut().remove_default_ipv4_route();
+        }
+    }
+    drop(iface);
+    if !lease.dns_servers.is_empty() {
+        super::dns::set_dns_servers(&lease.dns_servers);
+        info!("  dns:      {:?}", lease.dns_servers);
+    }
+    CONFIGURED.store(true, Ordering::Release);
+}
+
+fn deconfigure() {
+    warn!("DHCP: lease lost on {:?}, discover again", ETH0.name());
+    CONFIGURED.store(false, Ordering::Release);
+    let mut iface = ETH0.iface.lock();
+    iface.update_ip_addrs(|addrs| addrs.retain(|addr| !matches!(addr, IpCidr::Ipv4(_))));
+    iface.routes_mut().remove_default_ipv4_route();
+}
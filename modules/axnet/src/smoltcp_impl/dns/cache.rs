@@ -0,0 +1,73 @@
+//! The cache of the DNS answers, each kept for its TTL.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::net::IpAddr;
+use core::time::Duration;
+
+use axerrno::AxResult;
+use axhal::time::TimeValue;
+
+/// The most answers cached, the ones expiring first are evicted beyond it.
+const CAPACITY: usize = 64;
+
+struct Entry {
+    /// The addresses, or the error if the name does not exist.
+    result: AxResult<Vec<IpAddr>>,
+    expires: TimeValue,
+}
+
+/// The answers keyed by the lowercase name and the query type.
+pub struct Cache {
+    entries: BTreeMap<(String, u16), Entry>,
+}
+
+impl Cache {
+    pub const fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the cached answer if it has not expired.
+    pub fn get(&mut self, name: &str, qtype: u16, now: TimeValue) -> Option<AxResult<Vec<IpAddr>>> {
+        let key = (String::from(name), qtype);
+        let entry = self.entries.get(&key)?;
+        if entry.expires <= now {
+            self.entries.remove(&key);
+            return None;
+        }
+        Some(entry.result.clone())
+    }
+
+    /// Caches the answer for the TTL, unless it is zero.
+    pub fn insert(
+        &mut self,
+        name: String,
+        qtype: u16,
+        result: AxResult<Vec<IpAddr>>,
+        ttl: Duration,
+        now: TimeValue,
+    ) {
+        if ttl.is_zero() {
+            return;
+        }
+        let key = (name, qtype);
+        if self.entries.len() >= CAPACITY && !self.entries.contains_key(&key) {
+            self.entries.retain(|_, entry| entry.expires > now);
+            if self.entries.len() >= CAPACITY {
+                let first = self
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.expires)
+                    .map(|(key, _)| key.clone());
+                if let Some(first) = first {
+                    self.entries.remove(&first);
+                }
+            }
+        }
+        let expires = now + ttl;
+        self.entries.insert(key, Entry { result, expires });
+    }
+}
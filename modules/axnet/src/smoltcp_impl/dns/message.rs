@@ -0,0 +1,258 @@
+//! Encoding of the DNS queries and parsing of the responses, see RFC 1035 and
+//! RFC 3596 for the AAAA records.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use axerrno::{ax_err, AxResult};
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_AAAA: u16 = 28;
+const TYPE_CNAME: u16 = 5;
+const TYPE_SOA: u16 = 6;
+const CLASS_IN: u16 = 1;
+
+pub const RCODE_NOERROR: u8 = 0;
+pub const RCODE_NXDOMAIN: u8 = 3;
+
+const FLAG_QR: u16 = 0x8000;
+const FLAG_TC: u16 = 0x0200;
+const FLAG_RD: u16 = 0x0100;
+
+const HEADER_LEN: usize = 12;
+const MAX_NAME_LEN: usize = 253;
+const MAX_LABEL_LEN: usize = 63;
+/// The most compression pointers followed in a name, against loops.
+const MAX_POINTERS: usize = 16;
+/// The most CNAME records followed from the queried name.
+const MAX_CNAMES: usize = 8;
+
+/// A response to a query, see [`parse_response`].
+pub struct Response {
+    /// The response is truncated, and should be queried again over TCP. The
+    /// other fields are not parsed then.
+    pub truncated: bool,
+    pub rcode: u8,
+    /// The addresses of the queried type of the name, after following the
+    /// CNAME records.
+    pub addrs: Vec<IpAddr>,
+    /// The smallest TTL of the records giving the addresses, or if there is
+    /// none, how long the absence may be cached as told by the SOA record
+    /// (RFC 2308), in seconds.
+    pub ttl: Option<u32>,
+}
+
+enum RecordData {
+    Addr(IpAddr),
+    Cname(String),
+    /// The `MINIMUM` field of a SOA record.
+    Soa(u32),
+    Other,
+}
+
+struct Record {
+    name: String,
+    rtype: u16,
+    ttl: u32,
+    data: RecordData,
+}
+
+/// Encodes a recursive query of the given type for the name, with the ID.
+pub fn encode_query(id: u16, name: &str, qtype: u16) -> AxResult<Vec<u8>> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return ax_err!(InvalidInput, "dns_query() failed: invalid name length");
+    }
+    let mut buf = Vec::with_capacity(HEADER_LEN + name.len() + 6);
+    buf.extend_from_slice(&id.to_be_bytes());
+    buf.extend_from_slice(&FLAG_RD.to_be_bytes());
+    // One question, no records.
+    buf.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > MAX_LABEL_LEN {
+            return ax_err!(InvalidInput, "dns_query() failed: invalid label length");
+        }
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf.push(0);
+    buf.extend_from_slice(&qtype.to_be_bytes());
+    buf.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(buf)
+}
+
+/// Parses the response to the query of the given ID, name and type.
+///
+/// Returns `None` if it is malformed, or not a response to the query.
+pub fn parse_response(buf: &[u8], id: u16, name: &str, qtype: u16) -> Option<Response> {
+    if buf.len() < HEADER_LEN || read_u16(buf, 0)? != id {
+        return None;
+    }
+    let flags = read_u16(buf, 2)?;
+    if flags & FLAG_QR == 0 {
+        return None;
+    }
+    let rcode = (flags & 0xf) as u8;
+    if flags & FLAG_TC != 0 {
+        return Some(Response {
+            truncated: true,
+            rcode,
+            addrs: Vec::new(),
+            ttl: None,
+        });
+    }
+    let name = name.strip_suffix('.').unwrap_or(name);
+    let qdcount = read_u16(buf, 4)?;
+    let ancount = read_u16(buf, 6)?;
+    let nscount = read_u16(buf, 8)?;
+
+    let mut pos = HEADER_LEN;
+    for _ in 0..qdcount {
+        let (qname, next) = read_name(buf, pos)?;
+        if !qname.eq_ignore_ascii_case(name) || read_u16(buf, next)? != qtype {
+            return None;
+        }
+        pos = next + 4;
+    }
+    let mut answers = Vec::with_capacity(ancount as usize);
+    for _ in 0..ancount {
+        let (record, next) = read_record(buf, pos)?;
+        answers.push(record);
+        pos = next;
+    }
+
+    // Follow the CNAME chain to the canonical name.
+    let mut target = String::from(name);
+    let mut ttl = u32::MAX;
+    for _ in 0..MAX_CNAMES {
+        let cname = answers.iter().find_map(|r| match &r.data {
+            RecordData::Cname(cname) if r.name.eq_ignore_ascii_case(&target) => {
+                Some((cname, r.ttl))
+            }
+            _ => None,
+        });
+        match cname {
+            Some((cname, cname_ttl)) => {
+                target.clone_from(cname);
+                ttl = ttl.min(cname_ttl);
+            }
+            None => break,
+        }
+    }
+    let mut addrs = Vec::new();
+    for record in &answers {
+        if let RecordData::Addr(addr) = record.data {
+            if record.rtype == qtype && record.name.eq_ignore_ascii_case(&target) {
+                addrs.push(addr);
+                ttl = ttl.min(record.ttl);
+            }
+        }
+    }
+    if !addrs.is_empty() {
+        return Some(Response {
+            truncated: false,
+            rcode,
+            addrs,
+            ttl: Some(ttl),
+        });
+    }
+
+    // No address, the SOA record in the authority section tells how long it
+    // may be cached.
+    let mut negative_ttl = None;
+    for _ in 0..nscount {
+        let (record, next) = read_record(buf, pos)?;
+        if let RecordData::Soa(minimum) = record.data {
+            negative_ttl = Some(record.ttl.min(minimum));
+        }
+        pos = next;
+    }
+    Some(Response {
+        truncated: false,
+        rcode,
+        addrs,
+        ttl: negative_ttl,
+    })
+}
+
+fn read_u16(buf: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(buf.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(buf.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+/// Reads a possibly compressed name, returns it and the position after it.
+fn read_name(buf: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len = *buf.get(pos)? as usize;
+        match len & 0xc0 {
+            0x00 if len == 0 => return Some((name, end.unwrap_or(pos + 1))),
+            0x00 => {
+                let label = buf.get(pos + 1..pos + 1 + len)?;
+                if !name.is_empty() {
+                    name.push('.');
+                }
+                name.push_str(core::str::from_utf8(label).ok()?);
+                if name.len() > MAX_NAME_LEN {
+                    return None;
+                }
+                pos += 1 + len;
+            }
+            0xc0 => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None;
+                }
+                end.get_or_insert(pos + 2);
+                pos = ((len & 0x3f) << 8) | *buf.get(pos + 1)? as usize;
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Reads a resource record, returns it and the position after it.
+fn read_record(buf: &[u8], pos: usize) -> Option<(Record, usize)> {
+    let (name, pos) = read_name(buf, pos)?;
+    let rtype = read_u16(buf, pos)?;
+    let class = read_u16(buf, pos + 2)?;
+    // RFC 2181: a TTL with the most significant bit set is taken as zero.
+    let ttl = match read_u32(buf, pos + 4)? {
+        ttl if ttl > i32::MAX as u32 => 0,
+        ttl => ttl,
+    };
+    let rdlen = read_u16(buf, pos + 8)? as usize;
+    let rdpos = pos + 10;
+    let rdata = buf.get(rdpos..rdpos + rdlen)?;
+    let data = match (rtype, class) {
+        (TYPE_A, CLASS_IN) => {
+            RecordData::Addr(Ipv4Addr::from(<[u8; 4]>::try_from(rdata).ok()?).into())
+        }
+        (TYPE_AAAA, CLASS_IN) => {
+            RecordData::Addr(Ipv6Addr::from(<[u8; 16]>::try_from(rdata).ok()?).into())
+        }
+        (TYPE_CNAME, CLASS_IN) => RecordData::Cname(read_name(buf, rdpos)?.0),
+        (TYPE_SOA, CLASS_IN) => {
+            let (_mname, next) = read_name(buf, rdpos)?;
+            let (_rname, next) = read_name(buf, next)?;
+            // Skip SERIAL, REFRESH, RETRY and EXPIRE.
+            RecordData::Soa(read_u32(buf, next + 16)?)
+        }
+        _ => RecordData::Other,
+    };
+    Some((
+        Record {
+            name,
+            rtype,
+            ttl,
+            data,
+        },
+        rdpos + rdlen,
+    ))
+}
@@ -0,0 +1,286 @@
+//! A stub DNS resolver.
+//!
+//! It sends recursive queries to the nameservers in turn over UDP, and over
+//! TCP again if a response is truncated. A query is retried on all servers
+//! with the timeout doubled each round. The answers, including the ones that
+//! the name does not exist, are cached for their TTLs.
+
+mod cache;
+mod message;
+
+use alloc::vec::Vec;
+use core::net::{IpAddr, SocketAddr};
+use core::sync::atomic::{AtomicU16, Ordering};
+use core::time::Duration;
+
+use axerrno::{ax_err, ax_err_type, AxError, AxResult};
+use axhal::time::{monotonic_time, monotonic_time_nanos, TimeValue};
+use axsync::Mutex;
+
+use self::cache::Cache;
+use self::message::{Response, RCODE_NOERROR, RCODE_NXDOMAIN, TYPE_A, TYPE_AAAA};
+use super::{net_events, wait_interfaces, TcpSocket, UdpSocket, SOCKET_SET};
+
+const DNS_PORT: u16 = 53;
+/// The largest response over UDP without EDNS, see RFC 1035.
+const MAX_UDP_LEN: usize = 512;
+
+/// The rounds of queries to all the servers.
+const ATTEMPTS: usize = 3;
+/// The timeout of the first round, doubled each round.
+const INITIAL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The most time an answer is cached, whatever its TTL.
+const MAX_TTL: Duration = Duration::from_secs(3600);
+/// The time an absent name is cached if the server does not tell it.
+const DEFAULT_NEGATIVE_TTL: Duration = Duration::from_secs(5);
+/// The most time an absent name is cached, short as it may be created soon.
+const MAX_NEGATIVE_TTL: Duration = Duration::from_secs(30);
+
+static SERVERS: Mutex<Vec<IpAddr>> = Mutex::new(Vec::new());
+static CACHE: Mutex<Cache> = Mutex::new(Cache::new());
+
+/// Replaces the nameservers, queried in the given order.
+///
+/// They are configured by `AX_DNS` at build time, and may be replaced, e.g.,
+/// by the ones got from DHCP.
+pub fn set_dns_servers(servers: &[IpAddr]) {
+    *SERVERS.lock() = servers.to_vec();
+}
+
+/// Returns the nameservers, in the order they are queried.
+pub fn dns_servers() -> Vec<IpAddr> {
+    SERVERS.lock().clone()
+}
+
+/// Resolves the name to all its IPv4 and IPv6 addresses, the IPv4 ones
+/// first.
+///
+/// Returns [`Err(NotFound)`](AxError::NotFound) if the name does not exist or
+/// has no address.
+pub fn dns_query(name: &str) -> AxResult<Vec<IpAddr>> {
+    let v4 = resolve(name, TYPE_A);
+    if let Err(AxError::NotFound) = v4 {
+        // The name does not exist, nor its AAAA records.
+        return v4;
+    }
+    let v6 = resolve(name, TYPE_AAAA);
+    let addrs: Vec<_> = v4.iter().chain(v6.iter()).flatten().copied().collect();
+    if !addrs.is_empty() {
+        return Ok(addrs);
+    }
+    v4?;
+    v6?;
+    ax_err!(NotFound, "dns_query() failed: no address")
+}
+
+/// Resolves the name to the addresses of the type, from the cache if it is
+/// there.
+fn resolve(name: &str, qtype: u16) -> AxResult<Vec<IpAddr>> {
+    let name = name.strip_suffix('.').unwrap_or(name).to_ascii_lowercase();
+    if let Some(result) = CACHE.lock().get(&name, qtype, monotonic_time()) {
+        return result;
+    }
+    let (result, ttl) = lookup(&name, qtype)?;
+    CACHE
+        .lock()
+        .insert(name, qtype, result.clone(), ttl, monotonic_time());
+    result
+}
+
+/// Queries the servers for the name, until one of them answers.
+///
+/// Returns the answer and how long it may be cached, or the error of the last
+/// query if none answers.
+fn lookup(name: &str, qtype: u16) -> AxResult<(AxResult<Vec<IpAddr>>, Duration)> {
+    let servers = dns_servers();
+    if servers.is_empty() {
+        return ax_err!(BadState, "dns_query() failed: no DNS server");
+    }
+    let id = query_id();
+    let query = message::encode_query(id, name, qtype)?;
+    let mut timeout = INITIAL_TIMEOUT;
+    let mut last_err = AxError::ConnectionRefused;
+    for _ in 0..ATTEMPTS {
+        for &server in &servers {
+            let server = SocketAddr::new(server, DNS_PORT);
+            match exchange(server, &query, id, name, qtype, timeout) {
+                Ok(response) if response.rcode == RCODE_NOERROR => {
+                    let ttl = cache_ttl(&response);
+                    return Ok((Ok(response.addrs), ttl));
+                }
+                Ok(response) if response.rcode == RCODE_NXDOMAIN => {
+                    debug!("DNS query {} {}: no such name", name, qtype);
+                    let ttl = cache_ttl(&response);
+                    return Ok((ax_err!(NotFound, "dns_query() failed: no such name"), ttl));
+                }
+                Ok(response) => {
+                    warn!(
+                        "DNS server {}: query {} {} failed with rcode {}",
+                        server, name, qtype, response.rcode
+                    );
+                    last_err = AxError::ConnectionRefused;
+                }
+                Err(e) => {
+                    debug!(
+                        "DNS server {}: query {} {} failed: {:?}",
+                        server, name, qtype, e
+                    );
+                    last_err = e;
+                }
+            }
+        }
+        timeout *= 2;
+    }
+    Err(last_err)
+}
+
+/// Returns how long the answer in the response may be cached.
+fn cache_ttl(response: &Response) -> Duration {
+    if response.addrs.is_empty() {
+        response
+            .ttl
+            .map_or(DEFAULT_NEGATIVE_TTL, |ttl| Duration::from_secs(ttl as u64))
+            .min(MAX_NEGATIVE_TTL)
+    } else {
+        Duration::from_secs(response.ttl.unwrap_or(0) as u64).min(MAX_TTL)
+    }
+}
+
+/// Sends the query to the server and receives the response, over TCP if it
+/// is truncated over UDP.
+fn exchange(
+    server: SocketAddr,
+    query: &[u8],
+    id: u16,
+    name: &str,
+    qtype: u16,
+    timeout: Duration,
+) -> AxResult<Response> {
+    let response = exchange_udp(server, query, id, name, qtype, monotonic_time() + timeout)?;
+    if !response.truncated {
+        return Ok(response);
+    }
+    debug!("DNS server {}: truncated response, retry over TCP", server);
+    exchange_tcp(server, query, id, name, qtype, monotonic_time() + timeout)
+}
+
+fn exchange_udp(
+    server: SocketAddr,
+    query: &[u8],
+    id: u16,
+    name: &str,
+    qtype: u16,
+    deadline: TimeValue,
+) -> AxResult<Response> {
+    let socket = UdpSocket::new();
+    socket.set_nonblocking(true);
+    socket.connect(server)?;
+    block_until(deadline, || socket.send(query))?;
+    let mut buf = [0; MAX_UDP_LEN];
+    loop {
+        let len = block_until(deadline, || socket.recv(&mut buf))?;
+        // Ignore the stray datagrams, e.g., the late responses to a previous
+        // query from the same port.
+        if let Some(response) = message::parse_response(&buf[..len], id, name, qtype) {
+            return Ok(response);
+        }
+    }
+}
+
+fn exchange_tcp(
+    server: SocketAddr,
+    query: &[u8],
+    id: u16,
+    name: &str,
+    qtype: u16,
+    deadline: TimeValue,
+) -> AxResult<Response> {
+    let socket = TcpSocket::new();
+    socket.set_nonblocking(true);
+    match socket.connect(server) {
+        Ok(()) | Err(AxError::WouldBlock) => {}
+        Err(e) => return Err(e),
+    }
+    block_until(deadline, || {
+        if socket.poll()?.writable {
+            Ok(())
+        } else {
+            Err(AxError::WouldBlock)
+        }
+    })?;
+
+    // Each message is prefixed by its length over TCP.
+    let mut msg = Vec::with_capacity(2 + query.len());
+    msg.extend_from_slice(&(query.len() as u16).to_be_bytes());
+    msg.extend_from_slice(query);
+    let mut sent = 0;
+    while sent < msg.len() {
+        let n = block_until(deadline, || socket.send(&msg[sent..]))?;
+        sent += n;
+    }
+    let mut len = [0; 2];
+    recv_exact(&socket, &mut len, deadline)?;
+    let mut buf = alloc::vec![0; u16::from_be_bytes(len) as usize];
+    recv_exact(&socket, &mut buf, deadline)?;
+    message::parse_response(&buf, id, name, qtype)
+        .ok_or_else(|| ax_err_type!(InvalidData, "dns_query() failed: invalid response"))
+}
+
+fn recv_exact(socket: &TcpSocket, buf: &mut [u8], deadline: TimeValue) -> AxResult {
+    let mut pos = 0;
+    while pos < buf.len() {
+        match block_until(deadline, || socket.recv(&mut buf[pos..]))? {
+            0 => return ax_err!(UnexpectedEof, "dns_query() failed: connection closed"),
+            n => pos += n,
+        }
+    }
+    Ok(())
+}
+
+/// Polls the interfaces and runs `f` on a nonblocking socket until it does
+/// not return `WouldBlock`, or until the deadline passes, then it returns
+/// `WouldBlock` too.
+fn block_until<F, T>(deadline: TimeValue, mut f: F) -> AxResult<T>
+where
+    F: FnMut() -> AxResult<T>,
+{
+    loop {
+        let events = net_events();
+        SOCKET_SET.poll_interfaces();
+        match f() {
+            Err(AxError::WouldBlock) => {
+                let now = monotonic_time();
+                if now >= deadline {
+                    return Err(AxError::WouldBlock);
+                }
+                // Polls once more before giving up on timeout.
+                match wait_interfaces(events, Some(deadline - now)) {
+                    Ok(()) | Err(AxError::TimedOut) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Returns an ID for a new query, which varies with the time as there is no
+/// random number generator.
+fn query_id() -> u16 {
+    static SEQ: AtomicU16 = AtomicU16::new(0);
+    let nanos = monotonic_time_nanos();
+    let seq = SEQ.fetch_add(0x9e37, Ordering::Relaxed);
+    (nanos ^ (nanos >> 16) ^ (nanos >> 32)) as u16 ^ seq
+}
+
+/// Parses the comma-separated nameservers configured at build time.
+pub(super) fn init_servers(servers: &str) {
+    let servers: Vec<IpAddr> = servers
+        .split(',')
+        .filter(|server| !server.is_empty())
+        .map(|server| server.parse().expect("invalid DNS server address"))
+        .collect();
+    info!("DNS servers: {:?}", servers);
+    set_dns_servers(&servers);
+}
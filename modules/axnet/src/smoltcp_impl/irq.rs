@@ -0,0 +1,102 @@
+//! Waiting for received packets on NIC interrupts.
+//!
+//! The IRQ handler only masks the IRQ lines and schedules the RX work, which
+//! acknowledges the devices, unmasks the lines and receives the packets by
+//! polling the interfaces in task context, then wakes up the waiting tasks.
+//! They are also woken up whenever polling changes the states of the sockets,
+//! e.g., a connection is established by another task.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use axtask::workqueue::{self, WorkItem};
+use axtask::{WaitQueue, WaitResult};
+use lazyinit::LazyInit;
+
+/// IRQs of the NICs that interrupt on received packets.
+static NET_IRQS: LazyInit<Vec<usize>> = LazyInit::new();
+/// Number of NIC interrupts and socket state changes since boot.
+static EVENTS: AtomicUsize = AtomicUsize::new(0);
+/// Whether the devices need to be acknowledged.
+static NEED_ACK: AtomicBool = AtomicBool::new(false);
+static WAIT_QUEUE: WaitQueue = WaitQueue::new();
+static RX_WORK: WorkItem = WorkItem::new(rx_work);
+
+/// The longest time to sleep, as packets queued by other tasks are only sent
+/// on the next poll.
+const MAX_WAIT: Duration = Duration::from_millis(10);
+
+fn handle_net_irq() {
+    // The lines may be level-triggered, keep them masked until the devices
+    // are acknowledged.
+    if NET_IRQS.is_inited() {
+        for &irq in NET_IRQS.iter() {
+            axhal::irq::set_enable(irq, false);
+        }
+    }
+    NEED_ACK.store(true, Ordering::Release);
+    workqueue::schedule(&RX_WORK);
+}
+
+/// Acknowledges the devices and receives the packets in task context, then
+/// wakes up the waiting tasks.
+fn rx_work() {
+    super::poll_interfaces();
+    notify();
+}
+
+/// Registers the handler of the given IRQ, returns whether it succeeds.
+pub(super) fn register(irq: usize) -> bool {
+    axhal::irq::register_handler(irq, handle_net_irq)
+}
+
+/// Records the IRQs with registered handlers. The devices must not raise
+/// them before.
+pub(super) fn init(irqs: Vec<usize>) {
+    NET_IRQS.init_once(irqs);
+}
+
+/// Whether any NIC interrupts on received packets.
+pub(super) fn enabled() -> bool {
+    NET_IRQS.is_inited() && !NET_IRQS.is_empty()
+}
+
+/// Whether the IRQ is handled here.
+pub(super) fn is_net_irq(irq: usize) -> bool {
+    NET_IRQS.is_inited() && NET_IRQS.contains(&irq)
+}
+
+/// Returns whether the devices need to be acknowledged, and clears it.
+pub(super) fn take_need_ack() -> bool {
+    NEED_ACK.swap(false, Ordering::AcqRel)
+}
+
+/// Number of events since boot.
+pub(super) fn events() -> usize {
+    EVENTS.load(Ordering::Acquire)
+}
+
+/// Counts an event and wakes up the waiting tasks, on interrupts or when
+/// polling changes the states of the sockets.
+pub(super) fn notify() {
+    EVENTS.fetch_add(1, Ordering::AcqRel);
+    WAIT_QUEUE.notify_all(false);
+}
+
+/// Blocks the current task until an event after `events`, or the timeout
+/// if it is given and shorter than [`MAX_WAIT`].
+///
+/// Returns [`WaitResult::TimedOut`] only if the given timeout elapsed, and
+/// [`WaitResult::Notified`] after [`MAX_WAIT`], as the interfaces are due to
+/// be polled either way.
+pub(super) fn wait(events: usize, timeout: Option<Duration>) -> WaitResult {
+    let condition = || self::events() != events;
+    match timeout {
+        Some(timeout) if timeout <= MAX_WAIT => WAIT_QUEUE.wait_timeout_until(timeout, condition),
+        _ => match WAIT_QUEUE.wait_timeout_until(MAX_WAIT, condition) {
+            WaitResult::TimedOut => WaitResult::Notified,
+            result => result,
+        },
+    }
+}
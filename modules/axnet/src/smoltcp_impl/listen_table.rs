@@ -7,19 +7,27 @@ use smoltcp::iface::{SocketHandle, SocketSet};
 use smoltcp::socket::tcp::{self, State};
 use smoltcp::wire::{IpAddress, IpEndpoint, IpListenEndpoint};
 
-use super::{SocketSetWrapper, LISTEN_QUEUE_SIZE, SOCKET_SET};
+use super::tcp::TcpOptions;
+use super::{LISTEN_QUEUE_SIZE, SOCKET_SET};
 
 const PORT_NUM: usize = 65536;
 
 struct ListenTableEntry {
     listen_endpoint: IpListenEndpoint,
+    /// Whether it accepts IPv6 connections if it listens on the unspecified
+    /// address, i.e., on `::` rather than `0.0.0.0`.
+    ipv6: bool,
+    /// The options of the listener, inherited by the connections.
+    options: TcpOptions,
     syn_queue: VecDeque<SocketHandle>,
 }
 
 impl ListenTableEntry {
-    pub fn new(listen_endpoint: IpListenEndpoint) -> Self {
+    pub fn new(listen_endpoint: IpListenEndpoint, ipv6: bool, options: TcpOptions) -> Self {
         Self {
             listen_endpoint,
+            ipv6,
+            options,
             syn_queue: VecDeque::with_capacity(LISTEN_QUEUE_SIZE),
         }
     }
@@ -28,7 +36,7 @@ impl ListenTableEntry {
     fn can_accept(&self, dst: IpAddress) -> bool {
         match self.listen_endpoint.addr {
             Some(addr) => addr == dst,
-            None => true,
+            None => self.ipv6 || matches!(dst, IpAddress::Ipv4(_)),
         }
     }
 }
@@ -57,22 +65,33 @@ impl ListenTable {
         Self { tcp }
     }
 
-    pub fn can_listen(&self, port: u16) -> bool {
-        self.tcp[port as usize].lock().is_none()
-    }
-
-    pub fn listen(&self, listen_endpoint: IpListenEndpoint) -> AxResult {
+    pub fn listen(
+        &self,
+        listen_endpoint: IpListenEndpoint,
+        ipv6: bool,
+        options: TcpOptions,
+    ) -> AxResult {
         let port = listen_endpoint.port;
         assert_ne!(port, 0);
         let mut entry = self.tcp[port as usize].lock();
         if entry.is_none() {
-            *entry = Some(Box::new(ListenTableEntry::new(listen_endpoint)));
+            *entry = Some(Box::new(ListenTableEntry::new(
+                listen_endpoint,
+                ipv6,
+                options,
+            )));
             Ok(())
         } else {
             ax_err!(AddrInUse, "socket listen() failed")
         }
     }
 
+    pub fn set_options(&self, port: u16, options: TcpOptions) {
+        if let Some(entry) = self.tcp[port as usize].lock().deref_mut() {
+            entry.options = options;
+        }
+    }
+
     pub fn unlisten(&self, port: u16) {
         debug!("TCP socket unlisten on {}", port);
         *self.tcp[port as usize].lock() = None;
@@ -126,7 +145,7 @@ impl ListenTable {
                 warn!("SYN queue overflow!");
                 return;
             }
-            let mut socket = SocketSetWrapper::new_tcp_socket();
+            let mut socket = entry.options.new_socket();
             if socket.listen(entry.listen_endpoint).is_ok() {
                 let handle = sockets.add(socket);
                 debug!(
@@ -1,29 +1,36 @@
 mod addr;
 mod bench;
+mod dhcp;
 mod dns;
+#[cfg(all(feature = "irq", feature = "multitask"))]
+mod irq;
 mod listen_table;
+mod port_table;
+mod slaac;
 mod tcp;
 mod udp;
 
-use alloc::vec;
+use alloc::{format, string::String, vec, vec::Vec};
 use core::cell::RefCell;
 use core::ops::DerefMut;
+use core::sync::atomic::{AtomicBool, Ordering};
 
-use axdriver::prelude::*;
+use axdriver::{prelude::*, AxDeviceContainer, LoopbackDev};
 use axdriver_net::{DevError, NetBufPtr};
-use axhal::time::{wall_time_nanos, NANOS_PER_MICROS};
-use axsync::Mutex;
-use lazyinit::LazyInit;
+use axerrno::AxResult;
+use axhal::time::{monotonic_time_nanos, NANOS_PER_MICROS};
+use axsync::{LazyInit, Mutex};
 use smoltcp::iface::{Config, Interface, SocketHandle, SocketSet};
 use smoltcp::phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken};
 use smoltcp::socket::{self, AnySocket};
 use smoltcp::time::Instant;
-use smoltcp::wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr};
+use smoltcp::wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr, IpEndpoint, Ipv6Address};
 
 use self::listen_table::ListenTable;
+use self::port_table::PortTable;
 
-pub use self::dns::dns_query;
-pub use self::tcp::TcpSocket;
+pub use self::dns::{dns_query, dns_servers, set_dns_servers};
+pub use self::tcp::{ConnectError, TcpSocket};
 pub use self::udp::UdpSocket;
 
 macro_rules! env_or_default {
@@ -35,10 +42,26 @@ macro_rules! env_or_default {
     };
 }
 
+/// Comma-separated IP addresses of the interfaces, in the order of NICs.
+/// The address of `eth0` may be `dhcp` to get it by DHCP.
 const IP: &str = env_or_default!("AX_IP");
+/// Comma-separated gateways of the interfaces, in the order of NICs.
 const GATEWAY: &str = env_or_default!("AX_GW");
-const DNS_SEVER: &str = "8.8.8.8";
+/// Comma-separated nameservers, see [`dns::set_dns_servers`].
+const DNS_SERVERS: &str = env_or_default!("AX_DNS");
+/// The nameserver used if `AX_DNS` is empty.
+const DEFAULT_DNS_SERVER: &str = "8.8.8.8";
+/// Comma-separated global IPv6 addresses of the interfaces, in the order of
+/// NICs. If the one of `eth0` is empty, it is got by SLAAC.
+const IP6: &str = env_or_default!("AX_IP6");
+/// Comma-separated IPv6 gateways of the interfaces, in the order of NICs.
+const GATEWAY6: &str = env_or_default!("AX_GW6");
 const IP_PREFIX: u8 = 24;
+const IP6_PREFIX: u8 = 64;
+const LOOPBACK_IP: IpAddress = IpAddress::v4(127, 0, 0, 1);
+const LOOPBACK_PREFIX: u8 = 8;
+const LOOPBACK_IP6: IpAddress = IpAddress::Ipv6(Ipv6Address::LOOPBACK);
+const LOOPBACK_PREFIX6: u8 = 128;
 
 const STANDARD_MTU: usize = 1500;
 
@@ -46,23 +69,48 @@ const RANDOM_SEED: u64 = 0xA2CE_05A2_CE05_A2CE;
 
 const TCP_RX_BUF_LEN: usize = 64 * 1024;
 const TCP_TX_BUF_LEN: usize = 64 * 1024;
+/// The time a TCP handshake may take, see [`TcpSocket::connect`].
+const TCP_CONNECT_TIMEOUT: core::time::Duration = core::time::Duration::from_secs(75);
+/// The largest buffer a TCP socket may set, see [`TcpSocket::set_recv_buffer_size`].
+const TCP_MAX_BUF_LEN: usize = 1024 * 1024;
 const UDP_RX_BUF_LEN: usize = 64 * 1024;
 const UDP_TX_BUF_LEN: usize = 64 * 1024;
 const LISTEN_QUEUE_SIZE: usize = 512;
+/// The time-to-live of smoltcp if it is not set.
+const DEFAULT_TTL: u8 = 64;
 
 static LISTEN_TABLE: LazyInit<ListenTable> = LazyInit::new();
+static PORT_TABLE: LazyInit<PortTable> = LazyInit::new();
 static SOCKET_SET: LazyInit<SocketSetWrapper> = LazyInit::new();
 static ETH0: LazyInit<InterfaceWrapper> = LazyInit::new();
+/// The loopback interface `lo`, which shares the socket set of `eth0`.
+static LO: LazyInit<InterfaceWrapper> = LazyInit::new();
+/// Interfaces other than `eth0`. Each has its own empty socket set, so they
+/// answer ARP and ICMP requests, but sockets are always routed via `eth0`.
+static OTHER_IFACES: LazyInit<Vec<(InterfaceWrapper, Mutex<SocketSet>)>> = LazyInit::new();
 
 struct SocketSetWrapper<'a>(Mutex<SocketSet<'a>>);
 
+/// The device of an interface, either a NIC or the loopback device.
+///
+/// The loopback device is a separate variant, as it has a different type from
+/// [`AxNetDevice`] unless the `dyn` feature of `axdriver` is enabled.
+enum NetDevice {
+    Nic(AxNetDevice),
+    Loopback(LoopbackDev),
+}
+
 struct DeviceWrapper {
-    inner: RefCell<AxNetDevice>, // use `RefCell` is enough since it's wrapped in `Mutex` in `InterfaceWrapper`.
+    inner: RefCell<Option<NetDevice>>, // use `RefCell` is enough since it's wrapped in `Mutex` in `InterfaceWrapper`.
+    /// The statistics taken when the device is shut down.
+    final_stats: NetStats,
 }
 
 struct InterfaceWrapper {
-    name: &'static str,
+    name: String,
     ether_addr: EthernetAddress,
+    /// The link state seen on the last poll.
+    link_up: AtomicBool,
     dev: Mutex<DeviceWrapper>,
     iface: Mutex<Interface>,
 }
@@ -72,9 +120,9 @@ impl<'a> SocketSetWrapper<'a> {
         Self(Mutex::new(SocketSet::new(vec![])))
     }
 
-    pub fn new_tcp_socket() -> socket::tcp::Socket<'a> {
-        let tcp_rx_buffer = socket::tcp::SocketBuffer::new(vec![0; TCP_RX_BUF_LEN]);
-        let tcp_tx_buffer = socket::tcp::SocketBuffer::new(vec![0; TCP_TX_BUF_LEN]);
+    pub fn new_tcp_socket(rx_buf_len: usize, tx_buf_len: usize) -> socket::tcp::Socket<'a> {
+        let tcp_rx_buffer = socket::tcp::SocketBuffer::new(vec![0; rx_buf_len]);
+        let tcp_tx_buffer = socket::tcp::SocketBuffer::new(vec![0; tx_buf_len]);
         socket::tcp::Socket::new(tcp_rx_buffer, tcp_tx_buffer)
     }
 
@@ -90,11 +138,6 @@ impl<'a> SocketSetWrapper<'a> {
         socket::udp::Socket::new(udp_rx_buffer, udp_tx_buffer)
     }
 
-    pub fn new_dns_socket() -> socket::dns::Socket<'a> {
-        let server_addr = DNS_SEVER.parse().expect("invalid DNS server address");
-        socket::dns::Socket::new(&[server_addr], vec![])
-    }
-
     pub fn add<T: AnySocket<'a>>(&self, socket: T) -> SocketHandle {
         let handle = self.0.lock().add(socket);
         debug!("socket {}: created", handle);
@@ -120,7 +163,29 @@ impl<'a> SocketSetWrapper<'a> {
     }
 
     pub fn poll_interfaces(&self) {
-        ETH0.poll(&self.0);
+        #[cfg(all(feature = "irq", feature = "multitask"))]
+        if irq::take_need_ack() {
+            ETH0.ack_irq();
+            for (iface, _) in OTHER_IFACES.iter() {
+                iface.ack_irq();
+            }
+        }
+        // `lo` first, so that the packets to 127.0.0.1 are not routed via `eth0`.
+        let mut changed = LO.poll(&self.0);
+        changed |= ETH0.poll(&self.0);
+        dhcp::poll();
+        slaac::poll();
+        for (iface, sockets) in OTHER_IFACES.iter() {
+            iface.poll(sockets);
+        }
+        // Wake up the tasks waiting for the sockets, e.g., a connection is
+        // established or refused.
+        #[cfg(all(feature = "irq", feature = "multitask"))]
+        if changed {
+            irq::notify();
+        }
+        #[cfg(not(all(feature = "irq", feature = "multitask")))]
+        let _ = changed;
     }
 
     pub fn remove(&self, handle: SocketHandle) {
@@ -130,7 +195,7 @@ impl<'a> SocketSetWrapper<'a> {
 }
 
 impl InterfaceWrapper {
-    fn new(name: &'static str, dev: AxNetDevice, ether_addr: EthernetAddress) -> Self {
+    fn new(name: String, dev: NetDevice, ether_addr: EthernetAddress) -> Self {
         let mut config = Config::new(HardwareAddress::Ethernet(ether_addr));
         config.random_seed = RANDOM_SEED;
 
@@ -139,17 +204,18 @@ impl InterfaceWrapper {
         Self {
             name,
             ether_addr,
+            link_up: AtomicBool::new(false),
             dev: Mutex::new(dev),
             iface,
         }
     }
 
     fn current_time() -> Instant {
-        Instant::from_micros_const((wall_time_nanos() / NANOS_PER_MICROS) as i64)
+        Instant::from_micros_const((monotonic_time_nanos() / NANOS_PER_MICROS) as i64)
     }
 
     pub fn name(&self) -> &str {
-        self.name
+        &self.name
     }
 
     pub fn ethernet_address(&self) -> EthernetAddress {
@@ -167,22 +233,239 @@ impl InterfaceWrapper {
         let mut iface = self.iface.lock();
         match gateway {
             IpAddress::Ipv4(v4) => iface.routes_mut().add_default_ipv4_route(v4).unwrap(),
+            IpAddress::Ipv6(v6) => iface.routes_mut().add_default_ipv6_route(v6).unwrap(),
+        };
+    }
+
+    /// Checks the link state of the NIC, and logs it if it has changed.
+    ///
+    /// Returns whether the link is up. The interface is not polled while the
+    /// link is down, so it neither receives nor transmits packets.
+    pub fn poll_link(&self) -> bool {
+        let mut dev = self.dev.lock();
+        let Some(dev) = dev.device() else {
+            return false;
         };
+        let up = dev.link_up();
+        if self.link_up.swap(up, Ordering::Relaxed) != up {
+            if !up {
+                warn!("net interface {:?}: link down", self.name);
+            } else if let Some(speed) = dev.link_speed() {
+                info!("net interface {:?}: link up, {} Mbps", self.name, speed);
+            } else {
+                info!("net interface {:?}: link up", self.name);
+            }
+        }
+        up
     }
 
-    pub fn poll(&self, sockets: &Mutex<SocketSet>) {
+    /// Polls the interface, returns whether the states of the sockets may
+    /// have changed.
+    pub fn poll(&self, sockets: &Mutex<SocketSet>) -> bool {
+        if !self.poll_link() {
+            return false;
+        }
         let mut dev = self.dev.lock();
         let mut iface = self.iface.lock();
         let mut sockets = sockets.lock();
         let timestamp = Self::current_time();
-        iface.poll(timestamp, dev.deref_mut(), &mut sockets);
+        iface.poll(timestamp, dev.deref_mut(), &mut sockets)
+    }
+
+    fn shutdown(&self) {
+        self.dev.lock().shutdown(&self.name);
+    }
+
+    fn stats(&self) -> NetStats {
+        self.dev.lock().stats()
+    }
+
+    /// Returns the time until the next poll is needed by the sockets.
+    #[cfg(all(feature = "irq", feature = "multitask"))]
+    fn poll_delay(&self, sockets: &Mutex<SocketSet>) -> Option<core::time::Duration> {
+        let mut iface = self.iface.lock();
+        let sockets = sockets.lock();
+        iface
+            .poll_delay(Self::current_time(), &sockets)
+            .map(|delay| core::time::Duration::from_micros(delay.total_micros()))
+    }
+
+    /// Registers the handler of the RX interrupt of the NIC, returns the IRQ.
+    ///
+    /// The IRQ is routed to the CPU given by [`axdriver::net_irq_cpu`].
+    #[cfg(all(feature = "irq", feature = "multitask"))]
+    fn register_irq(&self) -> Option<usize> {
+        let irq = self.dev.lock().device()?.irq_num()?;
+        if irq::register(irq) {
+            let cpu_id = axdriver::net_irq_cpu();
+            if cpu_id != 0 && !axhal::irq::set_affinity(irq, cpu_id) {
+                warn!("  failed to route IRQ {} to CPU {}", irq, cpu_id);
+            }
+            Some(irq)
+        } else {
+            warn!("  failed to register IRQ {}, fall back to polling", irq);
+            None
+        }
+    }
+
+    /// Enables the RX interrupt of the NIC if its IRQ is registered.
+    #[cfg(all(feature = "irq", feature = "multitask"))]
+    fn enable_irq(&self) {
+        let mut dev = self.dev.lock();
+        if let Some(dev) = dev.device() {
+            if dev.irq_num().is_some_and(irq::is_net_irq) {
+                if let Err(e) = dev.enable_rx_interrupt(true) {
+                    warn!("failed to enable RX interrupt of {:?}: {:?}", self.name, e);
+                }
+            }
+        }
+    }
+
+    /// Acknowledges the interrupt of the NIC and unmasks its IRQ.
+    #[cfg(all(feature = "irq", feature = "multitask"))]
+    fn ack_irq(&self) {
+        let mut dev = self.dev.lock();
+        if let Some(dev) = dev.device() {
+            if let Some(irq) = dev.irq_num().filter(|&irq| irq::is_net_irq(irq)) {
+                dev.ack_interrupt();
+                axhal::irq::set_enable(irq, true);
+            }
+        }
+    }
+}
+
+/// Forwards a method call to the device of either variant.
+macro_rules! forward {
+    ($self:ident, $dev:ident => $call:expr) => {
+        match $self {
+            NetDevice::Nic($dev) => $call,
+            NetDevice::Loopback($dev) => $call,
+        }
+    };
+}
+
+impl BaseDriverOps for NetDevice {
+    fn device_name(&self) -> &str {
+        forward!(self, dev => dev.device_name())
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Net
+    }
+}
+
+impl NetDriverOps for NetDevice {
+    fn mac_address(&self) -> axdriver_net::EthernetAddress {
+        forward!(self, dev => dev.mac_address())
+    }
+
+    fn can_transmit(&self) -> bool {
+        forward!(self, dev => dev.can_transmit())
+    }
+
+    fn can_receive(&self) -> bool {
+        forward!(self, dev => dev.can_receive())
+    }
+
+    fn rx_queue_size(&self) -> usize {
+        forward!(self, dev => dev.rx_queue_size())
+    }
+
+    fn tx_queue_size(&self) -> usize {
+        forward!(self, dev => dev.tx_queue_size())
+    }
+
+    fn recycle_rx_buffer(&mut self, rx_buf: NetBufPtr) -> DevResult {
+        forward!(self, dev => dev.recycle_rx_buffer(rx_buf))
+    }
+
+    fn recycle_tx_buffers(&mut self) -> DevResult {
+        forward!(self, dev => dev.recycle_tx_buffers())
+    }
+
+    fn transmit(&mut self, tx_buf: NetBufPtr) -> DevResult {
+        forward!(self, dev => dev.transmit(tx_buf))
+    }
+
+    fn receive(&mut self) -> DevResult<NetBufPtr> {
+        forward!(self, dev => dev.receive())
+    }
+
+    fn alloc_tx_buffer(&mut self, size: usize) -> DevResult<NetBufPtr> {
+        forward!(self, dev => dev.alloc_tx_buffer(size))
+    }
+}
+
+impl NetIrqOps for NetDevice {
+    fn irq_num(&self) -> Option<usize> {
+        forward!(self, dev => dev.irq_num())
+    }
+
+    fn enable_rx_interrupt(&mut self, enable: bool) -> DevResult {
+        forward!(self, dev => dev.enable_rx_interrupt(enable))
+    }
+
+    fn ack_interrupt(&mut self) -> bool {
+        forward!(self, dev => dev.ack_interrupt())
+    }
+
+    fn link_up(&self) -> bool {
+        forward!(self, dev => dev.link_up())
+    }
+
+    fn link_speed(&self) -> Option<u32> {
+        forward!(self, dev => dev.link_speed())
+    }
+
+    fn set_mac_address(&mut self, mac: axdriver_net::EthernetAddress) -> DevResult {
+        forward!(self, dev => dev.set_mac_address(mac))
+    }
+
+    fn stats(&self) -> NetStats {
+        forward!(self, dev => dev.stats())
+    }
+}
+
+impl ShutdownOps for NetDevice {
+    fn shutdown(&mut self) -> DevResult {
+        match self {
+            Self::Nic(dev) => dev.shutdown(),
+            Self::Loopback(_) => Ok(()),
+        }
     }
 }
 
 impl DeviceWrapper {
-    fn new(inner: AxNetDevice) -> Self {
+    fn new(inner: NetDevice) -> Self {
         Self {
-            inner: RefCell::new(inner),
+            inner: RefCell::new(Some(inner)),
+            final_stats: NetStats::default(),
+        }
+    }
+
+    /// Returns the device, or `None` if it has been shut down.
+    fn device(&mut self) -> Option<&mut NetDevice> {
+        self.inner.get_mut().as_mut()
+    }
+
+    /// Returns the statistics of the device, or the final ones if it has been
+    /// shut down.
+    fn stats(&mut self) -> NetStats {
+        match self.device() {
+            Some(dev) => dev.stats(),
+            None => self.final_stats,
+        }
+    }
+
+    /// Releases the device, the interface neither receives nor transmits
+    /// afterwards.
+    fn shutdown(&mut self, iface_name: &str) {
+        if let Some(mut dev) = self.inner.get_mut().take() {
+            info!("  shutdown net interface {:?}", iface_name);
+            self.final_stats = dev.stats();
+            if let Err(e) = dev.shutdown() {
+                warn!("  failed to shutdown {:?}: {:?}", iface_name, e);
+            }
         }
     }
 }
@@ -199,6 +482,7 @@ impl Device for DeviceWrapper {
 
     fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
         let mut dev = self.inner.borrow_mut();
+        let dev = dev.as_mut()?;
         if let Err(e) = dev.recycle_tx_buffers() {
             warn!("recycle_tx_buffers failed: {:?}", e);
             return None;
@@ -221,6 +505,7 @@ impl Device for DeviceWrapper {
 
     fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
         let mut dev = self.inner.borrow_mut();
+        let dev = dev.as_mut()?;
         if let Err(e) = dev.recycle_tx_buffers() {
             warn!("recycle_tx_buffers failed: {:?}", e);
             return None;
@@ -241,8 +526,10 @@ impl Device for DeviceWrapper {
     }
 }
 
-struct AxNetRxToken<'a>(&'a RefCell<AxNetDevice>, NetBufPtr);
-struct AxNetTxToken<'a>(&'a RefCell<AxNetDevice>);
+// The device is always present while a token lives, as the tokens borrow the
+// `DeviceWrapper` that is only shut down under its lock.
+struct AxNetRxToken<'a>(&'a RefCell<Option<NetDevice>>, NetBufPtr);
+struct AxNetTxToken<'a>(&'a RefCell<Option<NetDevice>>);
 
 impl<'a> RxToken for AxNetRxToken<'a> {
     fn preprocess(&self, sockets: &mut SocketSet<'_>) {
@@ -260,7 +547,8 @@ impl<'a> RxToken for AxNetRxToken<'a> {
             rx_buf.packet()
         );
         let result = f(rx_buf.packet_mut());
-        self.0.borrow_mut().recycle_rx_buffer(rx_buf).unwrap();
+        let mut dev = self.0.borrow_mut();
+        dev.as_mut().unwrap().recycle_rx_buffer(rx_buf).unwrap();
         result
     }
 }
@@ -271,6 +559,7 @@ impl<'a> TxToken for AxNetTxToken<'a> {
         F: FnOnce(&mut [u8]) -> R,
     {
         let mut dev = self.0.borrow_mut();
+        let dev = dev.as_mut().unwrap();
         let mut tx_buf = dev.alloc_tx_buffer(len).unwrap();
         let ret = f(tx_buf.packet_mut());
         trace!("SEND {} bytes: {:02X?}", len, tx_buf.packet());
@@ -280,15 +569,32 @@ impl<'a> TxToken for AxNetTxToken<'a> {
 }
 
 fn snoop_tcp_packet(buf: &[u8], sockets: &mut SocketSet<'_>) -> Result<(), smoltcp::wire::Error> {
-    use smoltcp::wire::{EthernetFrame, IpProtocol, Ipv4Packet, TcpPacket};
+    use smoltcp::wire::{
+        EthernetFrame, EthernetProtocol, IpProtocol, Ipv4Packet, Ipv6Packet, TcpPacket,
+    };
 
     let ether_frame = EthernetFrame::new_checked(buf)?;
-    let ipv4_packet = Ipv4Packet::new_checked(ether_frame.payload())?;
+    let (src_ip, dst_ip, protocol, payload) = match ether_frame.ethertype() {
+        EthernetProtocol::Ipv4 => {
+            let packet = Ipv4Packet::new_checked(ether_frame.payload())?;
+            let src = IpAddress::Ipv4(packet.src_addr());
+            let dst = IpAddress::Ipv4(packet.dst_addr());
+            (src, dst, packet.next_header(), packet.payload())
+        }
+        // The extension headers are not supported.
+        EthernetProtocol::Ipv6 => {
+            let packet = Ipv6Packet::new_checked(ether_frame.payload())?;
+            let src = IpAddress::Ipv6(packet.src_addr());
+            let dst = IpAddress::Ipv6(packet.dst_addr());
+            (src, dst, packet.next_header(), packet.payload())
+        }
+        _ => return Ok(()),
+    };
 
-    if ipv4_packet.next_header() == IpProtocol::Tcp {
-        let tcp_packet = TcpPacket::new_checked(ipv4_packet.payload())?;
-        let src_addr = (ipv4_packet.src_addr(), tcp_packet.src_port()).into();
-        let dst_addr = (ipv4_packet.dst_addr(), tcp_packet.dst_port()).into();
+    if protocol == IpProtocol::Tcp {
+        let tcp_packet = TcpPacket::new_checked(payload)?;
+        let src_addr = IpEndpoint::new(src_ip, tcp_packet.src_port());
+        let dst_addr = IpEndpoint::new(dst_ip, tcp_packet.dst_port());
         let is_first = tcp_packet.syn() && !tcp_packet.ack();
         if is_first {
             // create a socket for the first incoming TCP packet, as the later accept() returns.
@@ -306,6 +612,65 @@ pub fn poll_interfaces() {
     SOCKET_SET.poll_interfaces();
 }
 
+/// Returns the number of network events so far, i.e., NIC interrupts and
+/// socket state changes, to be passed to [`wait_interfaces`] after polling.
+pub fn net_events() -> usize {
+    #[cfg(all(feature = "irq", feature = "multitask"))]
+    {
+        irq::events()
+    }
+    #[cfg(not(all(feature = "irq", feature = "multitask")))]
+    {
+        0
+    }
+}
+
+/// Blocks the current task when polling the interfaces does not make a
+/// blocking socket operation progress, or no socket is ready for `poll`.
+///
+/// If the NICs interrupt on received packets, it sleeps until an event after
+/// `events` (got by [`net_events`] before polling) or the next timer of the
+/// sockets. Otherwise it just yields the CPU.
+///
+/// It sleeps no longer than `timeout` if given, which is the time left for an
+/// operation with a deadline.
+///
+/// Returns an error if the current task is canceled, then the operation
+/// should give up, or [`Err(TimedOut)`](axerrno::AxError::TimedOut) if it
+/// slept until the `timeout` elapsed.
+#[cfg_attr(
+    not(all(feature = "irq", feature = "multitask")),
+    allow(unused_variables)
+)]
+pub fn wait_interfaces(events: usize, timeout: Option<core::time::Duration>) -> AxResult {
+    #[cfg(all(feature = "irq", feature = "multitask"))]
+    if irq::enabled() {
+        match (ETH0.poll_delay(&SOCKET_SET.0), timeout) {
+            (Some(delay), Some(timeout)) if delay < timeout => {
+                irq::wait(events, Some(delay));
+            }
+            (delay, None) => {
+                irq::wait(events, delay);
+            }
+            (_, Some(timeout)) => {
+                if irq::wait(events, Some(timeout)).timed_out() {
+                    return axerrno::ax_err!(TimedOut, "socket operation timed out");
+                }
+            }
+        }
+    } else {
+        axtask::yield_now();
+    }
+    #[cfg(not(all(feature = "irq", feature = "multitask")))]
+    axtask::yield_now();
+
+    #[cfg(feature = "multitask")]
+    if axtask::is_cancel_requested() {
+        return axerrno::ax_err!(BadState, "blocking socket operation canceled");
+    }
+    Ok(())
+}
+
 /// Benchmark raw socket transmit bandwidth.
 pub fn bench_transmit() {
     ETH0.dev.lock().bench_transmit_bandwidth();
@@ -316,21 +681,157 @@ pub fn bench_receive() {
     ETH0.dev.lock().bench_receive_bandwidth();
 }
 
-pub(crate) fn init(net_dev: AxNetDevice) {
-    let ether_addr = EthernetAddress(net_dev.mac_address().0);
-    let eth0 = InterfaceWrapper::new("eth0", net_dev, ether_addr);
+/// Returns the names and the NIC statistics of all interfaces, `eth0` first
+/// and `lo` last.
+pub fn interface_stats() -> Vec<(String, NetStats)> {
+    if !ETH0.is_inited() {
+        return Vec::new();
+    }
+    let mut stats = vec![(ETH0.name().into(), ETH0.stats())];
+    if OTHER_IFACES.is_inited() {
+        for (iface, _) in OTHER_IFACES.iter() {
+            stats.push((iface.name().into(), iface.stats()));
+        }
+    }
+    if LO.is_inited() {
+        stats.push((LO.name().into(), LO.stats()));
+    }
+    stats
+}
+
+pub(crate) fn shutdown_interfaces() {
+    if ETH0.is_inited() {
+        ETH0.shutdown();
+    }
+    if OTHER_IFACES.is_inited() {
+        for (iface, _) in OTHER_IFACES.iter() {
+            iface.shutdown();
+        }
+    }
+    if LO.is_inited() {
+        LO.shutdown();
+    }
+}
 
-    let ip = IP.parse().expect("invalid IP address");
-    let gateway = GATEWAY.parse().expect("invalid gateway IP address");
-    eth0.setup_ip_addr(ip, IP_PREFIX);
-    eth0.setup_gateway(gateway);
+pub(crate) fn init(net_devs: AxDeviceContainer<AxNetDevice>) {
+    let mut ips = IP.split(',');
+    let mut gateways = GATEWAY.split(',');
+    let mut ip6s = IP6.split(',');
+    let mut gateways6 = GATEWAY6.split(',');
+    let mut use_dhcp = false;
+    let mut use_slaac = false;
+    let mut others = Vec::new();
+    #[cfg(all(feature = "irq", feature = "multitask"))]
+    let mut irqs = Vec::new();
+
+    // The loopback device probed by `axdriver` is used for `lo` if any, it is
+    // not a NIC.
+    let (loopbacks, nics): (Vec<_>, Vec<_>) = net_devs
+        .into_iter()
+        .partition(|(_, dev)| dev.device_name() == "loopback");
+    assert!(!nics.is_empty(), "No NIC device found!");
+    let lo_dev = match loopbacks.into_iter().next() {
+        Some((_, dev)) => NetDevice::Nic(dev),
+        None => NetDevice::Loopback(LoopbackDev::new()),
+    };
 
-    ETH0.init_once(eth0);
+    for (i, (dev_name, net_dev)) in nics.into_iter().enumerate() {
+        // The configured address is used even if the NIC cannot be programmed
+        // with it, see `axdriver::mac_override`.
+        let mac = match axdriver::mac_override() {
+            Some(mac) if i == 0 => mac,
+            _ => net_dev.mac_address(),
+        };
+        let ether_addr = EthernetAddress(mac.0);
+        let iface = InterfaceWrapper::new(format!("eth{}", i), NetDevice::Nic(net_dev), ether_addr);
+        info!("created net interface {:?} on {}:", iface.name(), dev_name);
+        info!("  ether:    {}", iface.ethernet_address());
+        if !iface.poll_link() {
+            info!("  link:     down, waiting for it to come up");
+        }
+
+        // `eth0` must be configured, others are optional.
+        let ip = ips.next().filter(|ip| i == 0 || !ip.is_empty());
+        let gateway = gateways.next().filter(|gw| i == 0 || !gw.is_empty());
+        if ip == Some("dhcp") {
+            assert!(i == 0, "DHCP is only supported on eth0");
+            use_dhcp = true;
+            info!("  ip:       dhcp");
+        } else if let Some(ip) = ip {
+            let ip = ip.parse().expect("invalid IP address");
+            iface.setup_ip_addr(ip, IP_PREFIX);
+            info!("  ip:       {}/{}", ip, IP_PREFIX);
+        }
+        // The gateway is leased by DHCP.
+        if let Some(gateway) = gateway.filter(|_| !use_dhcp) {
+            let gateway = gateway.parse().expect("invalid gateway IP address");
+            iface.setup_gateway(gateway);
+            info!("  gateway:  {}", gateway);
+        }
+        let link_local = slaac::link_local_addr(ether_addr);
+        iface.setup_ip_addr(IpAddress::Ipv6(link_local), IP6_PREFIX);
+        info!("  ip6:      {}/{}", link_local, IP6_PREFIX);
+        if let Some(ip6) = ip6s.next().filter(|ip| !ip.is_empty()) {
+            let ip6: Ipv6Address = ip6.parse().expect("invalid IPv6 address");
+            iface.setup_ip_addr(IpAddress::Ipv6(ip6), IP6_PREFIX);
+            info!("  ip6:      {}/{}", ip6, IP6_PREFIX);
+        } else if i == 0 {
+            use_slaac = true;
+            info!("  ip6:      slaac");
+        }
+        if let Some(gateway6) = gateways6.next().filter(|gw| !gw.is_empty()) {
+            let gateway6: Ipv6Address = gateway6.parse().expect("invalid IPv6 gateway address");
+            iface.setup_gateway(IpAddress::Ipv6(gateway6));
+            info!("  gateway6: {}", gateway6);
+        }
+        #[cfg(all(feature = "irq", feature = "multitask"))]
+        if let Some(irq) = iface.register_irq() {
+            info!("  irq:      {}", irq);
+            irqs.push(irq);
+        }
+
+        if i == 0 {
+            ETH0.init_once(iface);
+        } else {
+            others.push((iface, Mutex::new(SocketSet::new(vec![]))));
+        }
+    }
+
+    let lo = InterfaceWrapper::new("lo".into(), lo_dev, EthernetAddress([0; 6]));
+    lo.setup_ip_addr(LOOPBACK_IP, LOOPBACK_PREFIX);
+    lo.setup_ip_addr(LOOPBACK_IP6, LOOPBACK_PREFIX6);
+    info!("created net interface {:?}:", lo.name());
+    info!("  ip:       {}/{}", LOOPBACK_IP, LOOPBACK_PREFIX);
+    info!("  ip6:      {}/{}", LOOPBACK_IP6, LOOPBACK_PREFIX6);
+
+    LO.init_once(lo);
+    OTHER_IFACES.init_once(others);
     SOCKET_SET.init_once(SocketSetWrapper::new());
     LISTEN_TABLE.init_once(ListenTable::new());
+    PORT_TABLE.init_once(PortTable::new());
+    dns::init_servers(if DNS_SERVERS.is_empty() {
+        DEFAULT_DNS_SERVER
+    } else {
+        DNS_SERVERS
+    });
+    if use_dhcp {
+        dhcp::init();
+    }
+    if use_slaac {
+        slaac::init();
+    }
 
-    info!("created net interface {:?}:", ETH0.name());
-    info!("  ether:    {}", ETH0.ethernet_address());
-    info!("  ip:       {}/{}", ip, IP_PREFIX);
-    info!("  gateway:  {}", gateway);
+    // The handlers use the IRQ list, enable the interrupts after it is ready.
+    #[cfg(all(feature = "irq", feature = "multitask"))]
+    {
+        irq::init(irqs);
+        ETH0.enable_irq();
+        for (iface, _) in OTHER_IFACES.iter() {
+            iface.enable_irq();
+        }
+    }
+
+    if use_dhcp {
+        dhcp::wait_configured();
+    }
 }
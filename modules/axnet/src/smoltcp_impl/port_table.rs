@@ -0,0 +1,245 @@
+//! The registry of the local ports bound by the sockets, for TCP and UDP
+//! separately.
+//!
+//! A bind conflicts with the bindings of the same port whose addresses
+//! overlap: `0.0.0.0` overlaps with all IPv4 addresses, and `::` with all
+//! addresses as it accepts IPv4 too. With `SO_REUSEADDR`, a TCP socket may
+//! bind a port kept by the closed connections in TIME-WAIT, or shared with the
+//! sockets also having it if none of them is listening, and UDP sockets may
+//! share a port if all of them have it.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use axerrno::{ax_err, AxResult};
+use axhal::time::{monotonic_time, TimeValue};
+use axsync::Mutex;
+use smoltcp::wire::{IpAddress, IpEndpoint};
+
+use super::PORT_TABLE;
+
+/// The time the port of a closed TCP connection is kept, as long as the
+/// TIME-WAIT state of Linux.
+const TIME_WAIT: Duration = Duration::from_secs(60);
+
+const EPHEMERAL_PORT_START: u16 = 0xc000;
+const EPHEMERAL_PORT_END: u16 = 0xffff;
+
+#[derive(Clone, Copy)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+struct Binding {
+    id: u64,
+    addr: IpAddress,
+    reuse_addr: bool,
+    listening: bool,
+    /// When the port is released, if it is kept after the connection is
+    /// closed.
+    expires: Option<TimeValue>,
+}
+
+/// The bindings of each port.
+struct Ports {
+    ports: BTreeMap<u16, Vec<Binding>>,
+    next_ephemeral: u16,
+}
+
+pub struct PortTable {
+    tcp: Mutex<Ports>,
+    udp: Mutex<Ports>,
+    next_id: AtomicU64,
+}
+
+/// A port bound by a socket, released when it is dropped.
+pub struct BoundPort {
+    protocol: Protocol,
+    port: u16,
+    id: u64,
+}
+
+/// Whether the sockets bound to the addresses may receive the same packets.
+fn overlaps(a: IpAddress, b: IpAddress) -> bool {
+    match (a, b) {
+        (IpAddress::Ipv6(a), _) if a.is_unspecified() => true,
+        (_, IpAddress::Ipv6(b)) if b.is_unspecified() => true,
+        (IpAddress::Ipv4(a), IpAddress::Ipv4(b)) => {
+            a.is_unspecified() || b.is_unspecified() || a == b
+        }
+        (a, b) => a == b,
+    }
+}
+
+impl Binding {
+    /// Whether a new binding of the address conflicts with this one.
+    fn conflicts(&self, protocol: Protocol, addr: IpAddress, reuse_addr: bool) -> bool {
+        if !overlaps(self.addr, addr) {
+            return false;
+        }
+        if !reuse_addr {
+            return true;
+        }
+        match protocol {
+            Protocol::Tcp => self.expires.is_none() && (self.listening || !self.reuse_addr),
+            Protocol::Udp => !self.reuse_addr,
+        }
+    }
+}
+
+impl Ports {
+    const fn new() -> Self {
+        Self {
+            ports: BTreeMap::new(),
+            next_ephemeral: EPHEMERAL_PORT_START,
+        }
+    }
+
+    /// Returns the bindings of the port, after removing the expired ones.
+    fn bindings(&mut self, port: u16) -> &[Binding] {
+        let now = monotonic_time();
+        if let Some(bindings) = self.ports.get_mut(&port) {
+            bindings.retain(|b| !matches!(b.expires, Some(expires) if expires <= now));
+            if bindings.is_empty() {
+                self.ports.remove(&port);
+            }
+        }
+        self.ports.get(&port).map_or(&[], Vec::as_slice)
+    }
+
+    fn ephemeral_port(&mut self) -> AxResult<u16> {
+        for _ in EPHEMERAL_PORT_START..=EPHEMERAL_PORT_END {
+            let port = self.next_ephemeral;
+            self.next_ephemeral = if port == EPHEMERAL_PORT_END {
+                EPHEMERAL_PORT_START
+            } else {
+                port + 1
+            };
+            if self.bindings(port).is_empty() {
+                return Ok(port);
+            }
+        }
+        ax_err!(AddrInUse, "no avaliable ports!")
+    }
+
+    fn remove(&mut self, port: u16, id: u64) {
+        if let Some(bindings) = self.ports.get_mut(&port) {
+            bindings.retain(|b| b.id != id);
+        }
+        self.bindings(port);
+    }
+
+    fn find(&mut self, port: u16, id: u64) -> Option<&mut Binding> {
+        self.ports.get_mut(&port)?.iter_mut().find(|b| b.id == id)
+    }
+}
+
+impl PortTable {
+    pub fn new() -> Self {
+        Self {
+            tcp: Mutex::new(Ports::new()),
+            udp: Mutex::new(Ports::new()),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    fn ports(&self, protocol: Protocol) -> &Mutex<Ports> {
+        match protocol {
+            Protocol::Tcp => &self.tcp,
+            Protocol::Udp => &self.udp,
+        }
+    }
+
+    /// Binds the local address, or a free ephemeral port of it if its port
+    /// is 0.
+    ///
+    /// Returns [`Err(AddrInUse)`](axerrno::AxError::AddrInUse) if it conflicts
+    /// with another binding.
+    pub fn bind(
+        &self,
+        protocol: Protocol,
+        addr: IpEndpoint,
+        reuse_addr: bool,
+    ) -> AxResult<BoundPort> {
+        let mut ports = self.ports(protocol).lock();
+        let port = if addr.port == 0 {
+            ports.ephemeral_port()?
+        } else {
+            addr.port
+        };
+        if ports
+            .bindings(port)
+            .iter()
+            .any(|b| b.conflicts(protocol, addr.addr, reuse_addr))
+        {
+            return ax_err!(AddrInUse, "socket bind() failed");
+        }
+        Ok(self.insert(&mut ports, protocol, port, addr.addr, reuse_addr))
+    }
+
+    /// Registers the port of a TCP connection accepted by a listener, which
+    /// shares the port of the listener without conflicts.
+    pub fn bind_accepted(&self, addr: IpEndpoint, reuse_addr: bool) -> BoundPort {
+        let mut ports = self.tcp.lock();
+        self.insert(&mut ports, Protocol::Tcp, addr.port, addr.addr, reuse_addr)
+    }
+
+    fn insert(
+        &self,
+        ports: &mut Ports,
+        protocol: Protocol,
+        port: u16,
+        addr: IpAddress,
+        reuse_addr: bool,
+    ) -> BoundPort {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        ports.ports.entry(port).or_default().push(Binding {
+            id,
+            addr,
+            reuse_addr,
+            listening: false,
+            expires: None,
+        });
+        BoundPort { protocol, port, id }
+    }
+}
+
+impl BoundPort {
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    fn update<F: FnOnce(&mut Binding)>(&self, f: F) {
+        let mut ports = PORT_TABLE.ports(self.protocol).lock();
+        if let Some(binding) = ports.find(self.port, self.id) {
+            f(binding);
+        }
+    }
+
+    /// Marks the port as listened on, then no other socket may bind it.
+    pub fn set_listening(&self) {
+        self.update(|b| b.listening = true);
+    }
+
+    pub fn set_reuse_addr(&self, reuse_addr: bool) {
+        self.update(|b| b.reuse_addr = reuse_addr);
+    }
+
+    /// Keeps the port for the TIME-WAIT period after the connection is
+    /// closed, then releases it.
+    pub fn linger(self) {
+        self.update(|b| b.expires = Some(monotonic_time() + TIME_WAIT));
+        core::mem::forget(self);
+    }
+}
+
+impl Drop for BoundPort {
+    fn drop(&mut self) {
+        PORT_TABLE
+            .ports(self.protocol)
+            .lock()
+            .remove(self.port, self.id);
+    }
+}
@@ -0,0 +1,221 @@
+//! IPv6 stateless address autoconfiguration, see RFC 4862.
+//!
+//! Each interface has the link-local address formed from its MAC address by
+//! the modified EUI-64 format, without the duplicate address detection. If no
+//! global address of `eth0` is configured, it solicits the router
+//! advertisements, adds the address in each advertised prefix with the
+//! autonomous flag, and routes via the advertising router by default.
+//!
+//! The lifetimes are not tracked: an address is removed by an advertisement
+//! with a zero valid lifetime, and the route by a zero router lifetime. The
+//! neighbor discovery itself is done by smoltcp.
+
+use alloc::{vec, vec::Vec};
+use core::time::Duration;
+
+use axhal::time::{monotonic_time, TimeValue};
+use axsync::{LazyInit, Mutex};
+use smoltcp::iface::SocketHandle;
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::socket::raw;
+use smoltcp::wire::{
+    EthernetAddress, Icmpv6Packet, Icmpv6Repr, IpAddress, IpCidr, IpProtocol, IpVersion,
+    Ipv6Address, Ipv6Cidr, Ipv6Packet, Ipv6Repr, NdiscPrefixInfoFlags, NdiscRepr,
+    RawHardwareAddress,
+};
+
+use super::{ETH0, IP6_PREFIX, SOCKET_SET, STANDARD_MTU};
+
+/// The most router solicitations sent before an advertisement, see RFC 4861.
+const MAX_RTR_SOLICITATIONS: usize = 3;
+const RTR_SOLICITATION_INTERVAL: Duration = Duration::from_secs(4);
+/// The hop limit of the neighbor discovery messages, which are dropped if it
+/// has been decremented by a router.
+const NDISC_HOP_LIMIT: u8 = 255;
+
+static HANDLE: LazyInit<SocketHandle> = LazyInit::new();
+static STATE: Mutex<State> = Mutex::new(State {
+    solicitations: 0,
+    next_solicitation: Duration::ZERO,
+    advertised: false,
+});
+
+struct State {
+    solicitations: usize,
+    next_solicitation: TimeValue,
+    /// Whether a router advertisement has been received, then no more
+    /// solicitations are sent.
+    advertised: bool,
+}
+
+/// A router advertisement.
+struct Advert {
+    router: Ipv6Address,
+    router_lifetime: smoltcp::time::Duration,
+    /// The prefix for autoconfiguration and its valid lifetime.
+    prefix: Option<(Ipv6Address, smoltcp::time::Duration)>,
+}
+
+/// Returns the link-local address of the interface with the MAC address.
+pub(super) fn link_local_addr(mac: EthernetAddress) -> Ipv6Address {
+    eui64_addr(Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), mac)
+}
+
+/// Returns the address in the 64-bit prefix, with the interface identifier
+/// in the modified EUI-64 format (RFC 4291).
+fn eui64_addr(prefix: Ipv6Address, mac: EthernetAddress) -> Ipv6Address {
+    let mut addr = prefix.0;
+    addr[8..11].copy_from_slice(&mac.0[..3]);
+    addr[11..13].copy_from_slice(&[0xff, 0xfe]);
+    addr[13..].copy_from_slice(&mac.0[3..]);
+    // Flip the universal/local bit.
+    addr[8] ^= 0x02;
+    Ipv6Address(addr)
+}
+
+/// Starts the autoconfiguration of `eth0`, after the socket set is
+/// initialized. The first router solicitation is sent on the next poll.
+pub(super) fn init() {
+    let rx_buffer = raw::PacketBuffer::new(
+        vec![raw::PacketMetadata::EMPTY; 4],
+        vec![0; 4 * STANDARD_MTU],
+    );
+    let tx_buffer =
+        raw::PacketBuffer::new(vec![raw::PacketMetadata::EMPTY; 1], vec![0; STANDARD_MTU]);
+    let socket = raw::Socket::new(IpVersion::Ipv6, IpProtocol::Icmpv6, rx_buffer, tx_buffer);
+    HANDLE.init_once(SOCKET_SET.add(socket));
+}
+
+/// Handles the received router advertisements and sends the solicitations,
+/// after the interfaces are polled.
+pub(super) fn poll() {
+    let Some(&handle) = HANDLE.get() else {
+        return;
+    };
+    let now = monotonic_time();
+    // The interface is locked after the socket set is released, in the order
+    // they are locked by polling.
+    let adverts = SOCKET_SET.with_socket_mut::<raw::Socket, _, _>(handle, |socket| {
+        let mut adverts = Vec::new();
+        while let Ok(packet) = socket.recv() {
+            adverts.extend(parse_advert(packet));
+        }
+        let mut state = STATE.lock();
+        if adverts.is_empty()
+            && !state.advertised
+            && state.solicitations < MAX_RTR_SOLICITATIONS
+            && now >= state.next_solicitation
+            && socket.send_slice(&router_solicitation()).is_ok()
+        {
+            debug!("SLAAC: router solicitation sent");
+            state.solicitations += 1;
+            state.next_solicitation = now + RTR_SOLICITATION_INTERVAL;
+        }
+        adverts
+    });
+    for advert in adverts {
+        configure(advert);
+    }
+}
+
+/// Parses an IPv6 packet received by the raw socket, returns the router
+/// advertisement if it is.
+fn parse_advert(packet: &[u8]) -> Option<Advert> {
+    let ip_packet = Ipv6Packet::new_checked(packet).ok()?;
+    let ip_repr = Ipv6Repr::parse(&ip_packet).ok()?;
+    // It must be sent by a router on the link, see RFC 4861 section 6.1.2.
+    if ip_repr.hop_limit != NDISC_HOP_LIMIT || !ip_repr.src_addr.is_link_local() {
+        return None;
+    }
+    let icmp_packet = Icmpv6Packet::new_checked(ip_packet.payload()).ok()?;
+    let icmp_repr = Icmpv6Repr::parse(
+        &IpAddress::Ipv6(ip_repr.src_addr),
+        &IpAddress::Ipv6(ip_repr.dst_addr),
+        &icmp_packet,
+        &ChecksumCapabilities::default(),
+    )
+    .ok()?;
+    match icmp_repr {
+        Icmpv6Repr::Ndisc(NdiscRepr::RouterAdvert {
+            router_lifetime,
+            prefix_info,
+            ..
+        }) => Some(Advert {
+            router: ip_repr.src_addr,
+            router_lifetime,
+            prefix: prefix_info
+                .filter(|info| {
+                    info.flags.contains(NdiscPrefixInfoFlags::ADDRCONF)
+                        && info.prefix_len == IP6_PREFIX
+                        && !info.prefix.is_link_local()
+                })
+                .map(|info| (info.prefix, info.valid_lifetime)),
+        }),
+        _ => None,
+    }
+}
+
+/// Builds a router solicitation to all routers, from the link-local address
+/// of `eth0`.
+fn router_solicitation() -> Vec<u8> {
+    let ether_addr = ETH0.ethernet_address();
+    let src_addr = link_local_addr(ether_addr);
+    let dst_addr = Ipv6Address::LINK_LOCAL_ALL_ROUTERS;
+    let icmp_repr = Icmpv6Repr::Ndisc(NdiscRepr::RouterSolicit {
+        lladdr: Some(RawHardwareAddress::from_bytes(ether_addr.as_bytes())),
+    });
+    let ip_repr = Ipv6Repr {
+        src_addr,
+        dst_addr,
+        next_header: IpProtocol::Icmpv6,
+        payload_len: icmp_repr.buffer_len(),
+        hop_limit: NDISC_HOP_LIMIT,
+    };
+    let mut buf = vec![0; ip_repr.buffer_len() + icmp_repr.buffer_len()];
+    ip_repr.emit(&mut Ipv6Packet::new_unchecked(&mut buf[..]));
+    icmp_repr.emit(
+        &IpAddress::Ipv6(src_addr),
+        &IpAddress::Ipv6(dst_addr),
+        &mut Icmpv6Packet::new_unchecked(&mut buf[ip_repr.buffer_len()..]),
+        &ChecksumCapabilities::default(),
+    );
+    buf
+}
+
+fn configure(advert: Advert) {
+    STATE.lock().advertised = true;
+    let mut iface = ETH0.iface.lock();
+    if advert.router_lifetime == smoltcp::time::Duration::ZERO {
+        if iface.routes_mut().remove_default_ipv6_route().is_some() {
+            info!("SLAAC: router {} is no longer default", advert.router);
+        }
+    } else {
+        let old = iface
+            .routes_mut()
+            .add_default_ipv6_route(advert.router)
+            .unwrap();
+        if !matches!(old, Some(old) if old.via_router == IpAddress::Ipv6(advert.router)) {
+            info!("SLAAC: gateway {} on {:?}", advert.router, ETH0.name());
+        }
+    }
+    if let Some((prefix, valid_lifetime)) = advert.prefix {
+        let cidr = IpCidr::Ipv6(Ipv6Cidr::new(
+            eui64_addr(prefix, ETH0.ethernet_address()),
+            IP6_PREFIX,
+        ));
+        iface.update_ip_addrs(|addrs| {
+            let exists = addrs.contains(&cidr);
+            if valid_lifetime == smoltcp::time::Duration::ZERO {
+                if exists {
+                    info!("SLAAC: {} expired on {:?}", cidr, ETH0.name());
+                    addrs.retain(|addr| *addr != cidr);
+                }
+            } else if !exists {
+                match addrs.push(cidr) {
+                    Ok(()) => info!("SLAAC: {} on {:?}", cidr, ETH0.name()),
+                    Err(_) => warn!("SLAAC: no room for {} on {:?}", cidr, ETH0.name()),
+                }
+            }
+        });
+    }
+}
@@ -1,17 +1,22 @@
 use core::cell::UnsafeCell;
 use core::net::SocketAddr;
 use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use core::time::Duration;
 
 use axerrno::{ax_err, ax_err_type, AxError, AxResult};
+use axhal::time::{monotonic_time, TimeValue};
 use axio::PollState;
 use axsync::Mutex;
 
 use smoltcp::iface::SocketHandle;
-use smoltcp::socket::tcp::{self, ConnectError, State};
-use smoltcp::wire::{IpEndpoint, IpListenEndpoint};
+use smoltcp::socket::tcp::{self, State};
+use smoltcp::wire::{IpAddress, IpEndpoint, IpListenEndpoint};
 
 use super::addr::{from_core_sockaddr, into_core_sockaddr, is_unspecified, UNSPECIFIED_ENDPOINT};
-use super::{SocketSetWrapper, ETH0, LISTEN_TABLE, SOCKET_SET};
+use super::port_table::{BoundPort, Protocol};
+use super::{net_events, wait_interfaces, SocketSetWrapper, ETH0, LISTEN_TABLE, LO, SOCKET_SET};
+use super::{DEFAULT_TTL, PORT_TABLE, TCP_CONNECT_TIMEOUT, TCP_MAX_BUF_LEN};
+use super::{TCP_RX_BUF_LEN, TCP_TX_BUF_LEN};
 
 // State transitions:
 // CLOSED -(connect)-> BUSY -> CONNECTING -> CONNECTED -(shutdown)-> BUSY -> CLOSED
@@ -25,6 +30,52 @@ const STATE_CONNECTING: u8 = 2;
 const STATE_CONNECTED: u8 = 3;
 const STATE_LISTENING: u8 = 4;
 
+/// The reason a connection attempt failed, see [`TcpSocket::take_error`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectError {
+    /// The remote refused the connection by a reset.
+    Refused,
+    /// The handshake did not complete within the connect timeout.
+    TimedOut,
+    /// No local address can reach the remote.
+    Unreachable,
+}
+
+/// The options of a TCP socket, applied to the smoltcp socket when it is
+/// created. The connections accepted by a listener inherit its options.
+#[derive(Clone, Copy)]
+pub(super) struct TcpOptions {
+    nodelay: bool,
+    ttl: Option<u8>,
+    recv_buffer_size: usize,
+    send_buffer_size: usize,
+}
+
+impl TcpOptions {
+    const fn new() -> Self {
+        Self {
+            nodelay: false,
+            ttl: None,
+            recv_buffer_size: TCP_RX_BUF_LEN,
+            send_buffer_size: TCP_TX_BUF_LEN,
+        }
+    }
+
+    /// Creates a smoltcp socket with the options.
+    pub(super) fn new_socket<'a>(&self) -> tcp::Socket<'a> {
+        let mut socket =
+            SocketSetWrapper::new_tcp_socket(self.recv_buffer_size, self.send_buffer_size);
+        self.apply(&mut socket);
+        socket
+    }
+
+    /// Applies the options other than the buffer sizes to the smoltcp socket.
+    fn apply(&self, socket: &mut tcp::Socket) {
+        socket.set_nagle_enabled(!self.nodelay);
+        socket.set_hop_limit(self.ttl);
+    }
+}
+
 /// A TCP socket that provides POSIX-like APIs.
 ///
 /// - [`connect`] is for TCP clients.
@@ -40,7 +91,15 @@ pub struct TcpSocket {
     handle: UnsafeCell<Option<SocketHandle>>,
     local_addr: UnsafeCell<IpEndpoint>,
     peer_addr: UnsafeCell<IpEndpoint>,
+    /// The local port in the port table, if bound.
+    bound: UnsafeCell<Option<BoundPort>>,
+    /// When the connection attempt in progress times out.
+    connect_deadline: UnsafeCell<TimeValue>,
+    /// The reason the last connection attempt failed, until it is taken.
+    error: Mutex<Option<ConnectError>>,
     nonblock: AtomicBool,
+    reuse_addr: AtomicBool,
+    options: Mutex<TcpOptions>,
 }
 
 unsafe impl Sync for TcpSocket {}
@@ -53,7 +112,12 @@ impl TcpSocket {
             handle: UnsafeCell::new(None),
             local_addr: UnsafeCell::new(UNSPECIFIED_ENDPOINT),
             peer_addr: UnsafeCell::new(UNSPECIFIED_ENDPOINT),
+            bound: UnsafeCell::new(None),
+            connect_deadline: UnsafeCell::new(Duration::ZERO),
+            error: Mutex::new(None),
             nonblock: AtomicBool::new(false),
+            reuse_addr: AtomicBool::new(false),
+            options: Mutex::new(TcpOptions::new()),
         }
     }
 
@@ -62,24 +126,36 @@ impl TcpSocket {
         handle: SocketHandle,
         local_addr: IpEndpoint,
         peer_addr: IpEndpoint,
+        bound: BoundPort,
+        reuse_addr: bool,
+        options: TcpOptions,
     ) -> Self {
         Self {
             state: AtomicU8::new(STATE_CONNECTED),
             handle: UnsafeCell::new(Some(handle)),
             local_addr: UnsafeCell::new(local_addr),
             peer_addr: UnsafeCell::new(peer_addr),
+            bound: UnsafeCell::new(Some(bound)),
+            connect_deadline: UnsafeCell::new(Duration::ZERO),
+            error: Mutex::new(None),
             nonblock: AtomicBool::new(false),
+            reuse_addr: AtomicBool::new(reuse_addr),
+            options: Mutex::new(options),
         }
     }
 
     /// Returns the local address and port, or
-    /// [`Err(NotConnected)`](AxError::NotConnected) if not connected.
+    /// [`Err(NotConnected)`](AxError::NotConnected) if neither bound nor
+    /// connected.
     #[inline]
     pub fn local_addr(&self) -> AxResult<SocketAddr> {
         match self.get_state() {
             STATE_CONNECTED | STATE_LISTENING => {
                 Ok(into_core_sockaddr(unsafe { self.local_addr.get().read() }))
             }
+            STATE_CLOSED if unsafe { (*self.bound.get()).is_some() } => {
+                Ok(into_core_sockaddr(unsafe { self.local_addr.get().read() }))
+            }
             _ => Err(AxError::NotConnected),
         }
     }
@@ -115,28 +191,125 @@ impl TcpSocket {
         self.nonblock.store(nonblocking, Ordering::Release);
     }
 
+    /// Returns whether this socket may bind an address in use, see
+    /// [`set_reuse_addr`](Self::set_reuse_addr).
+    #[inline]
+    pub fn reuse_addr(&self) -> bool {
+        self.reuse_addr.load(Ordering::Acquire)
+    }
+
+    /// Allows or forbids this socket to bind an address in use, i.e.,
+    /// `SO_REUSEADDR`.
+    ///
+    /// If allowed, it may bind the port kept by a closed connection in
+    /// TIME-WAIT, or shared with other sockets also allowing it unless one of
+    /// them is listening. It is forbidden by default.
+    pub fn set_reuse_addr(&self, reuse_addr: bool) {
+        self.reuse_addr.store(reuse_addr, Ordering::Release);
+        // SAFETY: `self.bound` is only changed on binding, connecting or closing.
+        if let Some(bound) = unsafe { &*self.bound.get() } {
+            bound.set_reuse_addr(reuse_addr);
+        }
+    }
+
+    /// Returns whether the Nagle algorithm is disabled on this socket.
+    pub fn nodelay(&self) -> bool {
+        self.options.lock().nodelay
+    }
+
+    /// Disables or enables the Nagle algorithm, which is enabled by default.
+    ///
+    /// If it is disabled, small segments are sent at once, rather than held
+    /// until the data in flight is acknowledged.
+    pub fn set_nodelay(&self, nodelay: bool) {
+        self.update_options(|options| options.nodelay = nodelay);
+    }
+
+    /// Returns the time-to-live of the IP packets sent from this socket.
+    pub fn ttl(&self) -> u8 {
+        self.options.lock().ttl.unwrap_or(DEFAULT_TTL)
+    }
+
+    /// Sets the time-to-live of the IP packets sent from this socket.
+    ///
+    /// Returns [`Err(InvalidInput)`](AxError::InvalidInput) if it is zero.
+    pub fn set_ttl(&self, ttl: u8) -> AxResult {
+        if ttl == 0 {
+            return ax_err!(InvalidInput, "socket set_ttl() failed: zero TTL");
+        }
+        self.update_options(|options| options.ttl = Some(ttl));
+        Ok(())
+    }
+
+    /// Returns the size of the receive buffer in bytes.
+    pub fn recv_buffer_size(&self) -> usize {
+        self.options.lock().recv_buffer_size
+    }
+
+    /// Sets the size of the receive buffer, which bounds the receive window.
+    ///
+    /// It is at most 1 MiB, a larger size is reduced to it. It must be set
+    /// before [`connect`](Self::connect) or [`listen`](Self::listen), otherwise
+    /// [`Err(BadState)`](AxError::BadState) is returned.
+    ///
+    /// Returns [`Err(InvalidInput)`](AxError::InvalidInput) if it is zero.
+    pub fn set_recv_buffer_size(&self, size: usize) -> AxResult {
+        self.set_buffer_size(size, |options| &mut options.recv_buffer_size)
+    }
+
+    /// Returns the size of the send buffer in bytes.
+    pub fn send_buffer_size(&self) -> usize {
+        self.options.lock().send_buffer_size
+    }
+
+    /// Sets the size of the send buffer, which bounds the data in flight.
+    ///
+    /// The same limits apply as [`set_recv_buffer_size`](Self::set_recv_buffer_size).
+    pub fn set_send_buffer_size(&self, size: usize) -> AxResult {
+        self.set_buffer_size(size, |options| &mut options.send_buffer_size)
+    }
+
     /// Connects to the given address and port.
     ///
-    /// The local port is generated automatically.
+    /// The local port is generated automatically if the socket is not bound.
+    ///
+    /// In nonblocking mode, it returns [`Err(WouldBlock)`](AxError::WouldBlock)
+    /// after the SYN is sent, then the handshake goes on in the background.
+    /// Its progress is checked by [`poll_connect`](Self::poll_connect), and
+    /// the socket becomes writable when it completes or fails. Calling it
+    /// again meanwhile also checks the progress.
+    ///
+    /// If the connection fails, it returns
+    /// [`Err(ConnectionRefused)`](AxError::ConnectionRefused), and the reason
+    /// is kept for [`take_error`](Self::take_error) until the next attempt.
     pub fn connect(&self, remote_addr: SocketAddr) -> AxResult {
-        self.update_state(STATE_CLOSED, STATE_CONNECTING, || {
+        let res = self.update_state(STATE_CLOSED, STATE_CONNECTING, || {
+            *self.error.lock() = None;
             // SAFETY: no other threads can read or write these fields.
+            let options = *self.options.lock();
             let handle = unsafe { self.handle.get().read() }
-                .unwrap_or_else(|| SOCKET_SET.add(SocketSetWrapper::new_tcp_socket()));
+                .unwrap_or_else(|| SOCKET_SET.add(options.new_socket()));
 
             // TODO: check remote addr unreachable
             let remote_endpoint = from_core_sockaddr(remote_addr);
             let bound_endpoint = self.bound_endpoint()?;
-            let iface = &ETH0.iface;
+            // The local address is chosen by the interface the remote is on.
+            let iface = if remote_addr.ip().is_loopback() {
+                &LO.iface
+            } else {
+                &ETH0.iface
+            };
             let (local_endpoint, remote_endpoint) = SOCKET_SET
                 .with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
+                    options.apply(socket);
                     socket
                         .connect(iface.lock().context(), remote_endpoint, bound_endpoint)
                         .or_else(|e| match e {
-                            ConnectError::InvalidState => {
+                            tcp::ConnectError::InvalidState => {
                                 ax_err!(BadState, "socket connect() failed")
                             }
-                            ConnectError::Unaddressable => {
+                            tcp::ConnectError::Unaddressable => {
+                                *self.error.lock() = Some(ConnectError::Unreachable);
                                 ax_err!(ConnectionRefused, "socket connect() failed")
                             }
                         })?;
@@ -151,48 +324,83 @@ impl TcpSocket {
                 self.local_addr.get().write(local_endpoint);
                 self.peer_addr.get().write(remote_endpoint);
                 self.handle.get().write(Some(handle));
+                self.connect_deadline
+                    .get()
+                    .write(monotonic_time() + TCP_CONNECT_TIMEOUT);
             }
             Ok(())
+        });
+        match res {
+            Ok(res) => res?,
+            Err(STATE_CONNECTING) => {} // an earlier attempt is in progress
+            // EISCONN
+            Err(_) => return ax_err!(AlreadyExists, "socket connect() failed: already connected"),
+        }
+
+        // Here our state must be `CONNECTING`. Send the SYN at once, then wait
+        // for the handshake if blocking.
+        SOCKET_SET.poll_interfaces();
+        self.block_on(|| {
+            let PollState { writable, .. } = self.poll_connecting()?;
+            if !writable {
+                Err(AxError::WouldBlock)
+            } else if self.get_state() == STATE_CONNECTED {
+                Ok(())
+            } else {
+                ax_err!(ConnectionRefused, "socket connect() failed")
+            }
         })
-        .unwrap_or_else(|_| ax_err!(AlreadyExists, "socket connect() failed: already connected"))?; // EISCONN
+    }
 
-        // Here our state must be `CONNECTING`, and only one thread can run here.
-        if self.is_nonblocking() {
-            Err(AxError::WouldBlock)
+    /// Checks the progress of a nonblocking [`connect`](Self::connect).
+    ///
+    /// Returns `Ok(true)` once the connection is established, `Ok(false)`
+    /// while the handshake is in progress, or
+    /// [`Err(ConnectionRefused)`](AxError::ConnectionRefused) if it failed,
+    /// whose reason is given by [`take_error`](Self::take_error). Returns
+    /// [`Err(NotConnected)`](AxError::NotConnected) if not connecting.
+    pub fn poll_connect(&self) -> AxResult<bool> {
+        if self.is_connecting() && !self.poll_connecting()?.writable {
+            return Ok(false);
+        }
+        if self.is_connected() {
+            Ok(true)
+        } else if self.error.lock().is_some() {
+            ax_err!(ConnectionRefused, "socket connect() failed")
         } else {
-            self.block_on(|| {
-                let PollState { writable, .. } = self.poll_connect()?;
-                if !writable {
-                    Err(AxError::WouldBlock)
-                } else if self.get_state() == STATE_CONNECTED {
-                    Ok(())
-                } else {
-                    ax_err!(ConnectionRefused, "socket connect() failed")
-                }
-            })
+            ax_err!(NotConnected, "socket poll_connect() failed")
         }
     }
 
+    /// Returns the reason the last connection attempt failed, and clears it,
+    /// i.e., `SO_ERROR`. So it is returned only once.
+    pub fn take_error(&self) -> Option<ConnectError> {
+        self.error.lock().take()
+    }
+
     /// Binds an unbound socket to the given address and port.
     ///
-    /// If the given port is 0, it generates one automatically.
+    /// If the given port is 0, it generates one automatically, which is
+    /// returned by [`local_addr`](Self::local_addr).
+    ///
+    /// Returns [`Err(AddrInUse)`](AxError::AddrInUse) if the address is
+    /// bound by another socket, see [`set_reuse_addr`](Self::set_reuse_addr).
     ///
     /// It's must be called before [`listen`](Self::listen) and
     /// [`accept`](Self::accept).
-    pub fn bind(&self, mut local_addr: SocketAddr) -> AxResult {
+    pub fn bind(&self, local_addr: SocketAddr) -> AxResult {
         self.update_state(STATE_CLOSED, STATE_CLOSED, || {
-            // TODO: check addr is available
-            if local_addr.port() == 0 {
-                local_addr.set_port(get_ephemeral_port()?);
-            }
-            // SAFETY: no other threads can read or write `self.local_addr` as we
-            // have changed the state to `BUSY`.
+            // SAFETY: no other threads can read or write `self.local_addr` and
+            // `self.bound` as we have changed the state to `BUSY`.
             unsafe {
-                let old = self.local_addr.get().read();
-                if old != UNSPECIFIED_ENDPOINT {
+                if (*self.bound.get()).is_some() {
                     return ax_err!(InvalidInput, "socket bind() failed: already bound");
                 }
-                self.local_addr.get().write(from_core_sockaddr(local_addr));
+                let mut local_endpoint = from_core_sockaddr(local_addr);
+                let bound = PORT_TABLE.bind(Protocol::Tcp, local_endpoint, self.reuse_addr())?;
+                local_endpoint.port = bound.port();
+                self.local_addr.get().write(local_endpoint);
+                *self.bound.get() = Some(bound);
             }
             Ok(())
         })
@@ -206,10 +414,12 @@ impl TcpSocket {
     pub fn listen(&self) -> AxResult {
         self.update_state(STATE_CLOSED, STATE_LISTENING, || {
             let bound_endpoint = self.bound_endpoint()?;
-            unsafe {
-                (*self.local_addr.get()).port = bound_endpoint.port;
+            let ipv6 = unsafe { matches!((*self.local_addr.get()).addr, IpAddress::Ipv6(_)) };
+            LISTEN_TABLE.listen(bound_endpoint, ipv6, *self.options.lock())?;
+            // SAFETY: `self.bound` is initialized by `bound_endpoint()` above.
+            if let Some(bound) = unsafe { &*self.bound.get() } {
+                bound.set_listening();
             }
-            LISTEN_TABLE.listen(bound_endpoint)?;
             debug!("TCP socket listening on {}", bound_endpoint);
             Ok(())
         })
@@ -232,7 +442,12 @@ impl TcpSocket {
         self.block_on(|| {
             let (handle, (local_addr, peer_addr)) = LISTEN_TABLE.accept(local_port)?;
             debug!("TCP socket accepted a new connection {}", peer_addr);
-            Ok(TcpSocket::new_connected(handle, local_addr, peer_addr))
+            let reuse_addr = self.reuse_addr();
+            let bound = PORT_TABLE.bind_accepted(local_addr, reuse_addr);
+            let options = *self.options.lock();
+            Ok(TcpSocket::new_connected(
+                handle, local_addr, peer_addr, bound, reuse_addr, options,
+            ))
         })
     }
 
@@ -247,6 +462,10 @@ impl TcpSocket {
                 debug!("TCP socket {}: shutting down", handle);
                 socket.close();
             });
+            // The port is kept while the connection is closing.
+            if let Some(bound) = unsafe { (*self.bound.get()).take() } {
+                bound.linger();
+            }
             unsafe { self.local_addr.get().write(UNSPECIFIED_ENDPOINT) }; // clear bound address
             SOCKET_SET.poll_interfaces();
             Ok(())
@@ -258,7 +477,10 @@ impl TcpSocket {
             // SAFETY: `self.local_addr` should be initialized in a listening socket,
             // and no other threads can read or write it.
             let local_port = unsafe { self.local_addr.get().read().port };
-            unsafe { self.local_addr.get().write(UNSPECIFIED_ENDPOINT) }; // clear bound address
+            unsafe {
+                self.local_addr.get().write(UNSPECIFIED_ENDPOINT); // clear bound address
+                *self.bound.get() = None; // release the port
+            }
             LISTEN_TABLE.unlisten(local_port);
             SOCKET_SET.poll_interfaces();
             Ok(())
@@ -335,12 +557,13 @@ impl TcpSocket {
     /// Whether the socket is readable or writable.
     pub fn poll(&self) -> AxResult<PollState> {
         match self.get_state() {
-            STATE_CONNECTING => self.poll_connect(),
+            STATE_CONNECTING => self.poll_connecting(),
             STATE_CONNECTED => self.poll_stream(),
             STATE_LISTENING => self.poll_listener(),
+            // A failed connection stays writable until the error is taken.
             _ => Ok(PollState {
                 readable: false,
-                writable: false,
+                writable: self.error.lock().is_some(),
             }),
         }
     }
@@ -402,14 +625,65 @@ impl TcpSocket {
         self.get_state() == STATE_LISTENING
     }
 
-    fn bound_endpoint(&self) -> AxResult<IpListenEndpoint> {
-        // SAFETY: no other threads can read or write `self.local_addr`.
-        let local_addr = unsafe { self.local_addr.get().read() };
-        let port = if local_addr.port != 0 {
-            local_addr.port
-        } else {
-            get_ephemeral_port()?
+    /// Updates the options, and applies them to the smoltcp socket if it is
+    /// connecting or connected, or to the connections to accept if listening.
+    fn update_options<F: FnOnce(&mut TcpOptions)>(&self, f: F) {
+        let options = {
+            let mut options = self.options.lock();
+            f(&mut options);
+            *options
         };
+        match self.get_state() {
+            STATE_CONNECTING | STATE_CONNECTED => {
+                // SAFETY: `self.handle` should be initialized in these states.
+                let handle = unsafe { self.handle.get().read().unwrap() };
+                SOCKET_SET
+                    .with_socket_mut::<tcp::Socket, _, _>(handle, |socket| options.apply(socket));
+            }
+            STATE_LISTENING => {
+                // SAFETY: `self.local_addr` should be initialized in a listening
+                // socket.
+                let local_port = unsafe { self.local_addr.get().read().port };
+                LISTEN_TABLE.set_options(local_port, options);
+            }
+            _ => {}
+        }
+    }
+
+    /// Sets the size of a buffer, before the smoltcp socket is created.
+    fn set_buffer_size<F>(&self, size: usize, field: F) -> AxResult
+    where
+        F: FnOnce(&mut TcpOptions) -> &mut usize,
+    {
+        if size == 0 {
+            return ax_err!(InvalidInput, "socket set buffer size failed: zero size");
+        }
+        self.update_state(STATE_CLOSED, STATE_CLOSED, || {
+            // SAFETY: no other threads can read or write `self.handle` as we
+            // have changed the state to `BUSY`.
+            if unsafe { self.handle.get().read() }.is_some() {
+                return ax_err!(BadState, "socket set buffer size failed: already used");
+            }
+            *field(&mut self.options.lock()) = size.min(TCP_MAX_BUF_LEN);
+            Ok(())
+        })
+        .unwrap_or_else(|_| ax_err!(BadState, "socket set buffer size failed: already used"))
+    }
+
+    /// Returns the bound address, binds an ephemeral port first if unbound.
+    fn bound_endpoint(&self) -> AxResult<IpListenEndpoint> {
+        // SAFETY: no other threads can read or write `self.local_addr` and
+        // `self.bound`.
+        let mut local_addr = unsafe { self.local_addr.get().read() };
+        if unsafe { (*self.bound.get()).is_none() } {
+            let bound = PORT_TABLE.bind(Protocol::Tcp, local_addr, self.reuse_addr())?;
+            local_addr.port = bound.port();
+            unsafe {
+                self.local_addr.get().write(local_addr);
+                *self.bound.get() = Some(bound);
+            }
+        }
+        let port = local_addr.port;
         assert_ne!(port, 0);
         let addr = if !is_unspecified(local_addr.addr) {
             Some(local_addr.addr)
@@ -419,13 +693,31 @@ impl TcpSocket {
         Ok(IpListenEndpoint { addr, port })
     }
 
-    fn poll_connect(&self) -> AxResult<PollState> {
-        // SAFETY: `self.handle` should be initialized above.
+    /// Checks whether the handshake has completed, and changes the state if
+    /// so. It becomes writable when the connection is established or failed.
+    fn poll_connecting(&self) -> AxResult<PollState> {
+        // SAFETY: `self.handle` and `self.connect_deadline` should be
+        // initialized in a connecting socket.
         let handle = unsafe { self.handle.get().read().unwrap() };
-        let writable =
-            SOCKET_SET.with_socket::<tcp::Socket, _, _>(handle, |socket| match socket.state() {
-                State::SynSent => false, // wait for connection
-                State::Established => {
+        let deadline = unsafe { self.connect_deadline.get().read() };
+        let timed_out = monotonic_time() >= deadline;
+        let writable = SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
+            // Only one thread changes the state while the socket set is locked.
+            if !self.is_connecting() {
+                return true;
+            }
+            match socket.state() {
+                State::SynSent | State::SynReceived if !timed_out => false, // wait for connection
+                State::SynSent | State::SynReceived => {
+                    socket.abort();
+                    self.connect_failed(handle, ConnectError::TimedOut);
+                    true
+                }
+                State::Closed => {
+                    self.connect_failed(handle, ConnectError::Refused);
+                    true
+                }
+                _ => {
                     self.set_state(STATE_CONNECTED); // connected
                     debug!(
                         "TCP socket {}: connected to {}",
@@ -434,21 +726,26 @@ impl TcpSocket {
                     );
                     true
                 }
-                _ => {
-                    unsafe {
-                        self.local_addr.get().write(UNSPECIFIED_ENDPOINT);
-                        self.peer_addr.get().write(UNSPECIFIED_ENDPOINT);
-                    }
-                    self.set_state(STATE_CLOSED); // connection failed
-                    true
-                }
-            });
+            }
+        });
         Ok(PollState {
             readable: false,
             writable,
         })
     }
 
+    /// Records the reason of a failed connection, and closes the socket.
+    fn connect_failed(&self, handle: SocketHandle, err: ConnectError) {
+        debug!("TCP socket {}: connection failed: {:?}", handle, err);
+        *self.error.lock() = Some(err);
+        unsafe {
+            self.local_addr.get().write(UNSPECIFIED_ENDPOINT);
+            self.peer_addr.get().write(UNSPECIFIED_ENDPOINT);
+            *self.bound.get() = None;
+        }
+        self.set_state(STATE_CLOSED); // connection failed
+    }
+
     fn poll_stream(&self) -> AxResult<PollState> {
         // SAFETY: `self.handle` should be initialized in a connected socket.
         let handle = unsafe { self.handle.get().read().unwrap() };
@@ -482,10 +779,11 @@ impl TcpSocket {
             f()
         } else {
             loop {
+                let events = net_events();
                 SOCKET_SET.poll_interfaces();
                 match f() {
                     Ok(t) => return Ok(t),
-                    Err(AxError::WouldBlock) => axtask::yield_now(),
+                    Err(AxError::WouldBlock) => wait_interfaces(events, None)?,
                     Err(e) => return Err(e),
                 }
             }
@@ -502,26 +800,3 @@ impl Drop for TcpSocket {
         }
     }
 }
-
-fn get_ephemeral_port() -> AxResult<u16> {
-    const PORT_START: u16 = 0xc000;
-    const PORT_END: u16 = 0xffff;
-    static CURR: Mutex<u16> = Mutex::new(PORT_START);
-
-    let mut curr = CURR.lock();
-    let mut tries = 0;
-    // TODO: more robust
-    while tries <= PORT_END - PORT_START {
-        let port = *curr;
-        if *curr == PORT_END {
-            *curr = PORT_START;
-        } else {
-            *curr += 1;
-        }
-        if LISTEN_TABLE.can_listen(port) {
-            return Ok(port);
-        }
-        tries += 1;
-    }
-    ax_err!(AddrInUse, "no avaliable ports!")
-}
@@ -1,5 +1,6 @@
-use core::net::SocketAddr;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
 
 use axerrno::{ax_err, ax_err_type, AxError, AxResult};
 use axio::PollState;
@@ -8,17 +9,26 @@ use spin::RwLock;
 
 use smoltcp::iface::SocketHandle;
 use smoltcp::socket::udp::{self, BindError, SendError};
-use smoltcp::wire::{IpEndpoint, IpListenEndpoint};
+use smoltcp::wire::{IpAddress, IpEndpoint, IpListenEndpoint};
 
-use super::addr::{from_core_sockaddr, into_core_sockaddr, is_unspecified, UNSPECIFIED_ENDPOINT};
-use super::{SocketSetWrapper, SOCKET_SET};
+use super::addr::{from_core_sockaddr, into_core_sockaddr, is_unspecified};
+use super::port_table::{BoundPort, Protocol};
+use super::{net_events, wait_interfaces, SocketSetWrapper, DEFAULT_TTL, PORT_TABLE, SOCKET_SET};
 
 /// A UDP socket that provides POSIX-like APIs.
 pub struct UdpSocket {
     handle: SocketHandle,
     local_addr: RwLock<Option<IpEndpoint>>,
     peer_addr: RwLock<Option<IpEndpoint>>,
+    /// The local port in the port table, if bound.
+    bound: Mutex<Option<BoundPort>>,
     nonblock: AtomicBool,
+    reuse_addr: AtomicBool,
+    broadcast: AtomicBool,
+    /// The read timeout in nanoseconds, 0 if none.
+    read_timeout: AtomicU64,
+    /// The write timeout in nanoseconds, 0 if none.
+    write_timeout: AtomicU64,
 }
 
 impl UdpSocket {
@@ -31,7 +41,12 @@ impl UdpSocket {
             handle,
             local_addr: RwLock::new(None),
             peer_addr: RwLock::new(None),
+            bound: Mutex::new(None),
             nonblock: AtomicBool::new(false),
+            reuse_addr: AtomicBool::new(false),
+            broadcast: AtomicBool::new(false),
+            read_timeout: AtomicU64::new(0),
+            write_timeout: AtomicU64::new(0),
         }
     }
 
@@ -69,21 +84,115 @@ impl UdpSocket {
         self.nonblock.store(nonblocking, Ordering::Release);
     }
 
+    /// Returns the read timeout of this socket, `None` if the reads block
+    /// indefinitely.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        load_timeout(&self.read_timeout)
+    }
+
+    /// Sets the read timeout of this socket.
+    ///
+    /// A blocking [`recv`](Self::recv), [`recv_from`](Self::recv_from) or
+    /// [`peek_from`](Self::peek_from) returns
+    /// [`Err(WouldBlock)`](AxError::WouldBlock) if no datagram is received
+    /// within the timeout. `None` makes them block indefinitely.
+    ///
+    /// Returns [`Err(InvalidInput)`](AxError::InvalidInput) if the timeout
+    /// is zero.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> AxResult {
+        store_timeout(&self.read_timeout, timeout)
+    }
+
+    /// Returns the write timeout of this socket, `None` if the writes block
+    /// indefinitely.
+    pub fn write_timeout(&self) -> Option<Duration> {
+        load_timeout(&self.write_timeout)
+    }
+
+    /// Sets the write timeout of this socket.
+    ///
+    /// A blocking [`send`](Self::send) or [`send_to`](Self::send_to) returns
+    /// [`Err(WouldBlock)`](AxError::WouldBlock) if the transmit buffer stays
+    /// full within the timeout. `None` makes them block indefinitely.
+    ///
+    /// Returns [`Err(InvalidInput)`](AxError::InvalidInput) if the timeout
+    /// is zero.
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> AxResult {
+        store_timeout(&self.write_timeout, timeout)
+    }
+
+    /// Returns whether this socket may bind an address in use, see
+    /// [`set_reuse_addr`](Self::set_reuse_addr).
+    #[inline]
+    pub fn reuse_addr(&self) -> bool {
+        self.reuse_addr.load(Ordering::Acquire)
+    }
+
+    /// Allows or forbids this socket to bind an address in use, i.e.,
+    /// `SO_REUSEADDR`.
+    ///
+    /// If allowed, it may share the port with other sockets also allowing
+    /// it. It is forbidden by default.
+    pub fn set_reuse_addr(&self, reuse_addr: bool) {
+        self.reuse_addr.store(reuse_addr, Ordering::Release);
+        if let Some(bound) = self.bound.lock().as_ref() {
+            bound.set_reuse_addr(reuse_addr);
+        }
+    }
+
+    /// Returns whether this socket may send to the broadcast address.
+    #[inline]
+    pub fn broadcast(&self) -> bool {
+        self.broadcast.load(Ordering::Acquire)
+    }
+
+    /// Allows or forbids this socket to send to the broadcast address
+    /// `255.255.255.255`, which is forbidden by default.
+    #[inline]
+    pub fn set_broadcast(&self, broadcast: bool) {
+        self.broadcast.store(broadcast, Ordering::Release);
+    }
+
+    /// Returns the time-to-live of the IP packets sent from this socket.
+    pub fn ttl(&self) -> u8 {
+        SOCKET_SET.with_socket::<udp::Socket, _, _>(self.handle, |socket| {
+            socket.hop_limit().unwrap_or(DEFAULT_TTL)
+        })
+    }
+
+    /// Sets the time-to-live of the IP packets sent from this socket.
+    ///
+    /// Returns [`Err(InvalidInput)`](AxError::InvalidInput) if it is zero.
+    pub fn set_ttl(&self, ttl: u8) -> AxResult {
+        if ttl == 0 {
+            return ax_err!(InvalidInput, "socket set_ttl() failed: zero TTL");
+        }
+        SOCKET_SET.with_socket_mut::<udp::Socket, _, _>(self.handle, |socket| {
+            socket.set_hop_limit(Some(ttl))
+        });
+        Ok(())
+    }
+
     /// Binds an unbound socket to the given address and port.
     ///
+    /// If the given port is 0, it generates one automatically, which is
+    /// returned by [`local_addr`](Self::local_addr).
+    ///
+    /// Returns [`Err(AddrInUse)`](AxError::AddrInUse) if the address is
+    /// bound by another socket, see [`set_reuse_addr`](Self::set_reuse_addr).
+    ///
     /// It's must be called before [`send_to`](Self::send_to) and
     /// [`recv_from`](Self::recv_from).
-    pub fn bind(&self, mut local_addr: SocketAddr) -> AxResult {
+    pub fn bind(&self, local_addr: SocketAddr) -> AxResult {
         let mut self_local_addr = self.local_addr.write();
 
-        if local_addr.port() == 0 {
-            local_addr.set_port(get_ephemeral_port()?);
-        }
         if self_local_addr.is_some() {
             return ax_err!(InvalidInput, "socket bind() failed: already bound");
         }
 
-        let local_endpoint = from_core_sockaddr(local_addr);
+        let mut local_endpoint = from_core_sockaddr(local_addr);
+        let bound = PORT_TABLE.bind(Protocol::Udp, local_endpoint, self.reuse_addr())?;
+        local_endpoint.port = bound.port();
         let endpoint = IpListenEndpoint {
             addr: (!is_unspecified(local_endpoint.addr)).then_some(local_endpoint.addr),
             port: local_endpoint.port,
@@ -96,6 +205,7 @@ impl UdpSocket {
         })?;
 
         *self_local_addr = Some(local_endpoint);
+        *self.bound.lock() = Some(bound);
         debug!("UDP socket {}: bound on {}", self.handle, endpoint);
         Ok(())
     }
@@ -106,6 +216,12 @@ impl UdpSocket {
         if remote_addr.port() == 0 || remote_addr.ip().is_unspecified() {
             return ax_err!(InvalidInput, "socket send_to() failed: invalid address");
         }
+        if matches!(remote_addr.ip(), IpAddr::V4(ip) if ip.is_broadcast()) && !self.broadcast() {
+            return ax_err!(
+                PermissionDenied,
+                "socket send_to() failed: broadcast not allowed"
+            );
+        }
         self.send_impl(buf, from_core_sockaddr(remote_addr))
     }
 
@@ -138,7 +254,13 @@ impl UdpSocket {
         let mut self_peer_addr = self.peer_addr.write();
 
         if self.local_addr.read().is_none() {
-            self.bind(into_core_sockaddr(UNSPECIFIED_ENDPOINT))?;
+            // Bound to the unspecified address of the same family, to receive
+            // from the peer.
+            let unspecified: IpAddr = match addr {
+                SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+                SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+            };
+            self.bind(SocketAddr::new(unspecified, 0))?;
         }
 
         *self_peer_addr = Some(from_core_sockaddr(addr));
@@ -176,6 +298,8 @@ impl UdpSocket {
             debug!("UDP socket {}: shutting down", self.handle);
             socket.close();
         });
+        // The port is released, as the socket no longer receives on it.
+        *self.bound.lock() = None;
         SOCKET_SET.poll_interfaces();
         Ok(())
     }
@@ -211,7 +335,7 @@ impl UdpSocket {
             return ax_err!(NotConnected, "socket send() failed");
         }
 
-        self.block_on(|| {
+        self.block_on(self.write_timeout(), || {
             SOCKET_SET.with_socket_mut::<udp::Socket, _, _>(self.handle, |socket| {
                 if socket.can_send() {
                     socket
@@ -235,12 +359,22 @@ impl UdpSocket {
     where
         F: FnMut(&mut udp::Socket) -> AxResult<T>,
     {
-        if self.local_addr.read().is_none() {
-            return ax_err!(NotConnected, "socket send() failed");
-        }
+        let ipv4_only = match *self.local_addr.read() {
+            Some(local_addr) => matches!(local_addr.addr, IpAddress::Ipv4(_)),
+            None => return ax_err!(NotConnected, "socket send() failed"),
+        };
 
-        self.block_on(|| {
+        self.block_on(self.read_timeout(), || {
             SOCKET_SET.with_socket_mut::<udp::Socket, _, _>(self.handle, |socket| {
+                // A socket bound to an IPv4 address, including `0.0.0.0`, does
+                // not receive IPv6 datagrams.
+                while ipv4_only {
+                    match socket.peek() {
+                        Ok((_, meta)) if matches!(meta.endpoint.addr, IpAddress::Ipv6(_)) => {}
+                        _ => break,
+                    }
+                    socket.recv().ok();
+                }
                 if socket.can_recv() {
                     // data available
                     op(socket)
@@ -252,18 +386,37 @@ impl UdpSocket {
         })
     }
 
-    fn block_on<F, T>(&self, mut f: F) -> AxResult<T>
+    /// Runs `f` until it does not return `WouldBlock`, or the timeout elapses.
+    fn block_on<F, T>(&self, timeout: Option<Duration>, mut f: F) -> AxResult<T>
     where
         F: FnMut() -> AxResult<T>,
     {
         if self.is_nonblocking() {
             f()
         } else {
+            let deadline = timeout.map(|t| axhal::time::monotonic_time() + t);
             loop {
+                let events = net_events();
                 SOCKET_SET.poll_interfaces();
                 match f() {
                     Ok(t) => return Ok(t),
-                    Err(AxError::WouldBlock) => axtask::yield_now(),
+                    Err(AxError::WouldBlock) => {
+                        let left = match deadline {
+                            Some(deadline) => {
+                                let now = axhal::time::monotonic_time();
+                                if now >= deadline {
+                                    return Err(AxError::WouldBlock);
+                                }
+                                Some(deadline - now)
+                            }
+                            None => None,
+                        };
+                        // Polls once more before giving up on timeout.
+                        match wait_interfaces(events, left) {
+                            Ok(()) | Err(AxError::TimedOut) => {}
+                            Err(e) => return Err(e),
+                        }
+                    }
                     Err(e) => return Err(e),
                 }
             }
@@ -278,17 +431,21 @@ impl Drop for UdpSocket {
     }
 }
 
-fn get_ephemeral_port() -> AxResult<u16> {
-    const PORT_START: u16 = 0xc000;
-    const PORT_END: u16 = 0xffff;
-    static CURR: Mutex<u16> = Mutex::new(PORT_START);
-    let mut curr = CURR.lock();
-
-    let port = *curr;
-    if *curr == PORT_END {
-        *curr = PORT_START;
-    } else {
-        *curr += 1;
+fn load_timeout(timeout: &AtomicU64) -> Option<Duration> {
+    match timeout.load(Ordering::Acquire) {
+        0 => None,
+        nanos => Some(Duration::from_nanos(nanos)),
     }
-    Ok(port)
+}
+
+fn store_timeout(timeout: &AtomicU64, value: Option<Duration>) -> AxResult {
+    let nanos = match value {
+        Some(dur) if dur.is_zero() => {
+            return ax_err!(InvalidInput, "socket set timeout failed: zero duration")
+        }
+        Some(dur) => dur.as_nanos().min(u64::MAX as u128) as u64,
+        None => 0,
+    };
+    timeout.store(nanos, Ordering::Release);
+    Ok(())
 }
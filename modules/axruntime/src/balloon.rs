@@ -0,0 +1,68 @@
+//! Memory balloon support.
+//!
+//! The first memory balloon device is resized to the target set by the host.
+//! With `multitask` and `irq` enabled, a background task polls the target
+//! periodically, otherwise it is only applied at boot and when [`update`] is
+//! called.
+
+use axdriver::{prelude::*, AxDeviceContainer};
+use kspin::SpinNoIrq;
+
+static BALLOON: SpinNoIrq<Option<AxBalloonDevice>> = SpinNoIrq::new(None);
+
+/// Interval between two checks of the balloon target.
+#[cfg(all(feature = "multitask", feature = "irq"))]
+const POLL_INTERVAL: core::time::Duration = core::time::Duration::from_secs(1);
+
+/// Statistics of the memory balloon, in 4K pages.
+#[derive(Debug, Clone, Copy)]
+pub struct BalloonStats {
+    /// The number of pages the host asks the balloon to hold.
+    pub target_pages: usize,
+    /// The number of pages actually held by the balloon.
+    pub actual_pages: usize,
+}
+
+/// Returns the balloon statistics, or `None` if there is no balloon device.
+pub fn stats() -> Option<BalloonStats> {
+    BALLOON.lock().as_ref().map(|dev| BalloonStats {
+        target_pages: dev.target_pages(),
+        actual_pages: dev.num_pages(),
+    })
+}
+
+/// Resizes the balloon to the target set by the host.
+///
+/// Returns the number of pages held afterwards, or `None` if there is no
+/// balloon device.
+pub fn update() -> Option<usize> {
+    let mut balloon = BALLOON.lock();
+    let dev = balloon.as_mut()?;
+    // On failure, the device still reports what it actually holds.
+    Some(dev.update().unwrap_or_else(|_| dev.num_pages()))
+}
+
+pub(crate) fn init_balloon(mut balloon_devs: AxDeviceContainer<AxBalloonDevice>) {
+    let Some(dev) = balloon_devs.take_one() else {
+        return;
+    };
+    info!("Use {:?} as the memory balloon.", dev.device_name());
+    *BALLOON.lock() = Some(dev);
+    update();
+
+    #[cfg(all(feature = "multitask", feature = "irq"))]
+    axtask::spawn_raw(
+        || loop {
+            axtask::sleep(POLL_INTERVAL);
+            update();
+        },
+        "balloon".into(),
+        axconfig::TASK_STACK_SIZE,
+    );
+}
+
+pub(crate) fn shutdown_balloon() {
+    if let Some(mut dev) = BALLOON.lock().take() {
+        dev.shutdown().ok();
+    }
+}
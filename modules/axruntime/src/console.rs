@@ -0,0 +1,34 @@
+use alloc::boxed::Box;
+use axdriver::{prelude::*, AxDeviceContainer};
+use axhal::console::ConsoleDevice;
+use kspin::SpinNoIrq;
+
+/// Wraps a character device to be used as the system console.
+struct CharConsole(SpinNoIrq<AxCharDevice>);
+
+impl ConsoleDevice for CharConsole {
+    fn write_bytes(&self, bytes: &[u8]) {
+        let mut dev = self.0.lock();
+        let mut written = 0;
+        while written < bytes.len() {
+            match dev.write(&bytes[written..]) {
+                Ok(n) => written += n,
+                Err(_) => break,
+            }
+        }
+    }
+
+    fn read_bytes(&self, bytes: &mut [u8]) -> usize {
+        self.0.lock().read(bytes).unwrap_or(0)
+    }
+}
+
+/// Uses the first character device (if any) as the system console instead of
+/// the platform UART.
+pub(crate) fn init_console(mut char_devs: AxDeviceContainer<AxCharDevice>) {
+    if let Some(dev) = char_devs.take_one() {
+        info!("Use {:?} as the system console.", dev.device_name());
+        let console = Box::leak(Box::new(CharConsole(SpinNoIrq::new(dev))));
+        axhal::console::set_console_device(console);
+    }
+}
@@ -0,0 +1,60 @@
+//! Input device support.
+
+use alloc::vec::Vec;
+
+use axdriver::{prelude::*, AxDeviceContainer};
+use kspin::SpinNoIrq;
+
+pub use axdriver::prelude::InputEvent;
+
+static INPUT_DEVICES: SpinNoIrq<Vec<AxInputDevice>> = SpinNoIrq::new(Vec::new());
+
+/// Pops the oldest pending event from any of the input devices.
+///
+/// Returns `None` if there is no pending event.
+pub fn read_event() -> Option<InputEvent> {
+    INPUT_DEVICES
+        .lock()
+        .iter_mut()
+        .find_map(|dev| dev.read_event())
+}
+
+/// Returns the number of events dropped by all input devices since boot.
+pub fn dropped_events() -> usize {
+    INPUT_DEVICES
+        .lock()
+        .iter()
+        .map(|dev| dev.dropped_events())
+        .sum()
+}
+
+/// Moves the received events out of the device queues, so that no event is
+/// lost while nobody reads them.
+#[cfg(feature = "irq")]
+fn handle_irq() {
+    for dev in INPUT_DEVICES.lock().iter_mut() {
+        dev.poll_events();
+    }
+}
+
+pub(crate) fn init_input(mut input_devs: AxDeviceContainer<AxInputDevice>) {
+    let mut devices = INPUT_DEVICES.lock();
+    while let Some(dev) = input_devs.take_one() {
+        info!("  use input device: {:?}", dev.device_name());
+        // The handler polls all devices, so a shared IRQ is registered once.
+        #[cfg(feature = "irq")]
+        if let Some(irq) = dev.irq_num() {
+            let registered = devices.iter().any(|d| d.irq_num() == Some(irq));
+            if !registered && !axhal::irq::register_handler(irq, handle_irq) {
+                warn!("  failed to register IRQ {}, fall back to polling", irq);
+            }
+        }
+        devices.push(dev);
+    }
+}
+
+pub(crate) fn shutdown_input() {
+    for mut dev in INPUT_DEVICES.lock().drain(..) {
+        dev.shutdown().ok();
+    }
+}
@@ -2,6 +2,14 @@ use core::panic::PanicInfo;
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    #[cfg(feature = "multitask")]
+    if let Some(curr) = axtask::current_may_uninit() {
+        error!("{} panicked:", curr.id_name());
+    }
     error!("{}", info);
-    axhal::misc::terminate()
+    axhal::backtrace::print();
+    match axconfig::PANIC {
+        "reboot" => axhal::misc::reboot(),
+        _ => axhal::misc::terminate(),
+    }
 }
@@ -9,10 +9,18 @@
 //! - `paging`: Enable page table manipulation support.
 //! - `irq`: Enable interrupt handling support.
 //! - `multitask`: Enable multi-threading support.
+//! - `tickless`: Let the task manager program the timer for the next timed
+//!   event, instead of a periodic timer interrupt.
+//! - `dyntick`: Also stop the scheduler ticks while a CPU runs a single task.
 //! - `smp`: Enable SMP (symmetric multiprocessing) support.
 //! - `fs`: Enable filesystem support.
 //! - `net`: Enable networking support.
 //! - `display`: Enable graphics support.
+//! - `char-console`: Use the first character device as the system console.
+//! - `rng`: Seed the global random number generator from an RNG device.
+//! - `balloon`: Let the host reclaim memory through a memory balloon device.
+//! - `input`: Enable keyboard and mouse input support.
+//! - `sound`: Enable sound playback support.
 //!
 //! All the features are optional and disabled by default.
 
@@ -22,12 +30,29 @@
 #[macro_use]
 extern crate axlog;
 
+#[cfg(any(feature = "char-console", feature = "input"))]
+extern crate alloc;
+
 #[cfg(all(target_os = "none", not(test)))]
 mod lang_items;
 
 #[cfg(feature = "smp")]
 mod mp;
 
+#[cfg(feature = "char-console")]
+mod console;
+
+pub mod random;
+
+#[cfg(feature = "balloon")]
+pub mod balloon;
+
+#[cfg(feature = "input")]
+pub mod input;
+
+#[cfg(feature = "sound")]
+pub mod sound;
+
 #[cfg(feature = "smp")]
 pub use self::mp::rust_main_secondary;
 
@@ -144,6 +169,9 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
     #[cfg(feature = "alloc")]
     init_allocator();
 
+    #[cfg(feature = "smp")]
+    self::mp::register_stack_guards(cpu_id);
+
     #[cfg(feature = "paging")]
     axmm::init_memory_management();
 
@@ -153,19 +181,50 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
     #[cfg(feature = "multitask")]
     axtask::init_scheduler();
 
-    #[cfg(any(feature = "fs", feature = "net", feature = "display"))]
+    self::random::init_random();
+
+    #[cfg(any(
+        feature = "fs",
+        feature = "net",
+        feature = "display",
+        feature = "char-console",
+        feature = "rng",
+        feature = "balloon",
+        feature = "input",
+        feature = "sound"
+    ))]
     {
         #[allow(unused_variables)]
         let all_devices = axdriver::init_drivers();
 
         #[cfg(feature = "fs")]
-        axfs::init_filesystems(all_devices.block);
+        {
+            axfs::set_random_source(self::random::fill_random);
+            axfs::init_filesystems(all_devices.block);
+        }
 
         #[cfg(feature = "net")]
         axnet::init_network(all_devices.net);
 
         #[cfg(feature = "display")]
         axdisplay::init_display(all_devices.display);
+
+        #[cfg(feature = "char-console")]
+        self::console::init_console(all_devices.char);
+
+        #[cfg(feature = "rng")]
+        self::random::init_rng_device(all_devices.rng);
+
+        #[cfg(feature = "balloon")]
+        self::balloon::init_balloon(all_devices.balloon);
+
+        #[cfg(feature = "input")]
+        self::input::init_input(all_devices.input);
+
+        #[cfg(feature = "sound")]
+        self::sound::init_sound(all_devices.sound);
+
+        axhal::misc::register_shutdown_hook(shutdown_devices);
     }
 
     #[cfg(feature = "smp")]
@@ -190,6 +249,11 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
         core::hint::spin_loop();
     }
 
+    #[cfg(all(feature = "multitask", feature = "irq"))]
+    if axconfig::WATCHDOG != 0 {
+        init_watchdog();
+    }
+
     unsafe { main() };
 
     #[cfg(feature = "multitask")]
@@ -201,31 +265,106 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
     }
 }
 
+/// Starts the watchdog, and a task of the lowest priority that feeds it. The
+/// system is reset if the task is not scheduled in time, e.g., when the
+/// scheduler livelocks.
+#[cfg(all(feature = "multitask", feature = "irq"))]
+fn init_watchdog() {
+    // The lowest priority of CFS, other schedulers ignore it.
+    const FEEDER_PRIORITY: isize = 19;
+    let timeout = axhal::time::Duration::from_millis(axconfig::WATCHDOG_TIMEOUT_MS as u64);
+    axhal::watchdog::start(timeout);
+    axtask::spawn_raw(
+        move || {
+            axtask::set_current_priority(FEEDER_PRIORITY);
+            loop {
+                axhal::watchdog::feed();
+                axtask::sleep(timeout / 4);
+            }
+        },
+        "watchdog".into(),
+        axconfig::TASK_STACK_SIZE,
+    );
+}
+
+/// Flushes and releases the devices before the system is shut down. The
+/// console device is kept for the last messages.
+#[cfg(any(
+    feature = "fs",
+    feature = "net",
+    feature = "display",
+    feature = "char-console",
+    feature = "rng",
+    feature = "balloon",
+    feature = "input",
+    feature = "sound"
+))]
+fn shutdown_devices() {
+    info!("Shutdown devices...");
+
+    #[cfg(feature = "sound")]
+    self::sound::shutdown_sound();
+
+    #[cfg(feature = "input")]
+    self::input::shutdown_input();
+
+    #[cfg(feature = "balloon")]
+    self::balloon::shutdown_balloon();
+
+    #[cfg(feature = "rng")]
+    self::random::shutdown_rng_device();
+
+    #[cfg(feature = "net")]
+    axnet::shutdown_network();
+
+    #[cfg(feature = "fs")]
+    axfs::shutdown_filesystems();
+}
+
 #[cfg(feature = "alloc")]
 fn init_allocator() {
-    use axhal::mem::{memory_regions, phys_to_virt, MemRegionFlags};
+    use axhal::mem::{phys_to_virt, MemRegionFlags};
 
     info!("Initialize global memory allocator...");
     info!("  use {} allocator.", axalloc::global_allocator().name());
 
+    // With the `numa` feature, the regions are split at the node boundaries,
+    // and the memory of other nodes than the one of the largest region is
+    // given to their own page allocators.
+    #[cfg(feature = "numa")]
+    let regions = || {
+        (0..axhal::numa::num_nodes())
+            .flat_map(|node| axhal::numa::node_memory_regions(node).map(move |r| (node, r)))
+    };
+    #[cfg(not(feature = "numa"))]
+    let regions = || axhal::mem::memory_regions().map(|r| (0, r));
+
     let mut max_region_size = 0;
     let mut max_region_paddr = 0.into();
-    for r in memory_regions() {
+    for (_, r) in regions() {
         if r.flags.contains(MemRegionFlags::FREE) && r.size > max_region_size {
             max_region_size = r.size;
             max_region_paddr = r.paddr;
         }
     }
-    for r in memory_regions() {
+    for (_node, r) in regions() {
         if r.flags.contains(MemRegionFlags::FREE) && r.paddr == max_region_paddr {
-            axalloc::global_init(phys_to_virt(r.paddr).as_usize(), r.size);
+            let vaddr = phys_to_virt(r.paddr).as_usize();
+            #[cfg(feature = "numa")]
+            axalloc::global_init_on_node(_node, vaddr, r.size);
+            #[cfg(not(feature = "numa"))]
+            axalloc::global_init(vaddr, r.size);
             break;
         }
     }
-    for r in memory_regions() {
+    for (_node, r) in regions() {
         if r.flags.contains(MemRegionFlags::FREE) && r.paddr != max_region_paddr {
-            axalloc::global_add_memory(phys_to_virt(r.paddr).as_usize(), r.size)
-                .expect("add heap memory region failed");
+            let vaddr = phys_to_virt(r.paddr).as_usize();
+            #[cfg(feature = "numa")]
+            let res = axalloc::global_add_node_memory(_node, vaddr, r.size);
+            #[cfg(not(feature = "numa"))]
+            let res = axalloc::global_add_memory(vaddr, r.size);
+            res.expect("add heap memory region failed");
         }
     }
 }
@@ -235,29 +374,52 @@ fn init_interrupt() {
     use axhal::time::TIMER_IRQ_NUM;
 
     // Setup timer interrupt handler
-    const PERIODIC_INTERVAL_NANOS: u64 =
-        axhal::time::NANOS_PER_SEC / axconfig::TICKS_PER_SEC as u64;
+    axhal::time::init_tick_frequency(axconfig::TICKS_PER_SEC as u64);
+    info!("  tick frequency: {} Hz", axhal::time::tick_frequency());
 
     #[percpu::def_percpu]
     static NEXT_DEADLINE: u64 = 0;
 
+    // With `tickless`, the task manager programs the timer instead.
+    #[cfg_attr(feature = "tickless", allow(dead_code))]
     fn update_timer() {
         let now_ns = axhal::time::monotonic_time_nanos();
         // Safety: we have disabled preemption in IRQ handler.
         let mut deadline = unsafe { NEXT_DEADLINE.read_current_raw() };
+        let interval = axhal::time::tick_nanos();
         if now_ns >= deadline {
-            deadline = now_ns + PERIODIC_INTERVAL_NANOS;
+            deadline = now_ns + interval;
         }
-        unsafe { NEXT_DEADLINE.write_current_raw(deadline + PERIODIC_INTERVAL_NANOS) };
+        unsafe { NEXT_DEADLINE.write_current_raw(deadline + interval) };
         axhal::time::set_oneshot_timer(deadline);
     }
 
     axhal::irq::register_handler(TIMER_IRQ_NUM, || {
+        #[cfg(not(feature = "tickless"))]
         update_timer();
         #[cfg(feature = "multitask")]
         axtask::on_timer_tick();
     });
 
+    // Block the readers of the console until the receive interrupt.
+    #[cfg(feature = "multitask")]
+    {
+        struct ConsoleWaitQueue(axtask::WaitQueue);
+
+        impl axhal::console::ConsoleWaiter for ConsoleWaitQueue {
+            fn wait_until(&self, condition: &dyn Fn() -> bool) {
+                self.0.wait_until_noncancelable(condition)
+            }
+
+            fn notify(&self) {
+                self.0.notify_all(false)
+            }
+        }
+
+        static CONSOLE_WAITER: ConsoleWaitQueue = ConsoleWaitQueue(axtask::WaitQueue::new());
+        axhal::console::set_console_waiter(&CONSOLE_WAITER);
+    }
+
     // Enable IRQs before starting app
     axhal::arch::enable_irqs();
 }
@@ -1,36 +1,55 @@
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 use axconfig::{SMP, TASK_STACK_SIZE};
-use axhal::mem::{virt_to_phys, VirtAddr};
+use axhal::mem::{virt_to_phys, GuardedStack};
+
+type SecondaryBootStack = GuardedStack<TASK_STACK_SIZE>;
+
+const EMPTY_STACK: SecondaryBootStack = GuardedStack::new();
 
 #[link_section = ".bss.stack"]
-static mut SECONDARY_BOOT_STACK: [[u8; TASK_STACK_SIZE]; SMP - 1] = [[0; TASK_STACK_SIZE]; SMP - 1];
+static mut SECONDARY_BOOT_STACK: [SecondaryBootStack; SMP - 1] = [EMPTY_STACK; SMP - 1];
 
 static ENTERED_CPUS: AtomicUsize = AtomicUsize::new(1);
 
-#[allow(clippy::absurd_extreme_comparisons)]
+/// Returns the present secondary CPUs with their boot stacks.
+fn secondary_cpus(
+    primary_cpu_id: usize,
+) -> impl Iterator<Item = (usize, &'static SecondaryBootStack)> {
+    let stacks = unsafe { &*core::ptr::addr_of!(SECONDARY_BOOT_STACK) };
+    (0..SMP)
+        .filter(move |&i| i != primary_cpu_id && axhal::mp::cpu_present(i))
+        .zip(stacks.iter())
+}
+
+/// Registers the guard pages of the boot stacks of the secondary CPUs, before
+/// the kernel address space is set up.
+pub fn register_stack_guards(primary_cpu_id: usize) {
+    for (cpu_id, stack) in secondary_cpus(primary_cpu_id) {
+        axhal::mem::register_stack_guard(stack.guard(), cpu_id, "boot");
+    }
+}
+
 pub fn start_secondary_cpus(primary_cpu_id: usize) {
-    let mut logic_cpu_id = 0;
-    for i in 0..SMP {
-        if i != primary_cpu_id && logic_cpu_id < SMP - 1 {
-            let stack_top = virt_to_phys(VirtAddr::from(unsafe {
-                SECONDARY_BOOT_STACK[logic_cpu_id].as_ptr_range().end as usize
-            }));
-
-            debug!("starting CPU {}...", i);
-            axhal::mp::start_secondary_cpu(i, stack_top);
-            logic_cpu_id += 1;
-
-            while ENTERED_CPUS.load(Ordering::Acquire) <= logic_cpu_id {
-                core::hint::spin_loop();
-            }
+    for i in (0..SMP).filter(|&i| i != primary_cpu_id && !axhal::mp::cpu_present(i)) {
+        warn!("CPU {} is not present", i);
+    }
+    for (logic_cpu_id, (i, stack)) in secondary_cpus(primary_cpu_id).enumerate() {
+        let stack_top = virt_to_phys(stack.top());
+
+        debug!("starting CPU {}...", i);
+        axhal::mp::start_secondary_cpu(i, stack_top);
+
+        while ENTERED_CPUS.load(Ordering::Acquire) <= logic_cpu_id + 1 {
+            core::hint::spin_loop();
         }
     }
 }
 
 /// The main entry point of the ArceOS runtime for secondary CPUs.
 ///
-/// It is called from the bootstrapping code in [axhal].
+/// It is called from the bootstrapping code in [axhal], also when the CPU is
+/// started again by [`axhal::cpu::cpu_up`].
 #[no_mangle]
 pub extern "C" fn rust_main_secondary(cpu_id: usize) -> ! {
     ENTERED_CPUS.fetch_add(1, Ordering::Relaxed);
@@ -62,5 +81,8 @@ pub extern "C" fn rust_main_secondary(cpu_id: usize) -> ! {
     #[cfg(not(feature = "multitask"))]
     loop {
         axhal::arch::wait_for_irqs();
+        if !axhal::cpu::cpu_online(cpu_id) {
+            axhal::cpu::offline_this_cpu();
+        }
     }
 }
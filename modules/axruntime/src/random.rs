@@ -0,0 +1,196 @@
+//! The global cryptographically secure pseudo-random number generator.
+//!
+//! It generates the ChaCha20 keystream and erases the key after each request.
+//! Fresh entropy is mixed in before each request from the random number
+//! generator of the CPU, and from the random number generator device if one is
+//! present. Without any of them, it is seeded from the jitter of the cycle
+//! counter at boot, and the output may be predictable.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use kspin::SpinNoIrq;
+
+#[cfg(feature = "rng")]
+use axdriver::{prelude::*, AxDeviceContainer};
+
+const CHACHA_CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+/// Size in bytes of the entropy read from the device on each request.
+#[cfg(feature = "rng")]
+const RESEED_LEN: usize = 32;
+
+/// Number of words read from the random number generator of the CPU on each
+/// request.
+const CPU_RESEED_WORDS: usize = 4;
+
+static RNG: SpinNoIrq<ChaChaRng> = SpinNoIrq::new(ChaChaRng::new());
+static HAS_CPU_RNG: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "rng")]
+static RNG_DEVICE: SpinNoIrq<Option<AxRngDevice>> = SpinNoIrq::new(None);
+
+struct ChaChaRng {
+    key: [u32; 8],
+    counter: u64,
+}
+
+impl ChaChaRng {
+    const fn new() -> Self {
+        Self {
+            key: [0; 8],
+            counter: 0,
+        }
+    }
+
+    fn next_block(&mut self) -> [u32; 16] {
+        let block = chacha20_block(&self.key, self.counter);
+        self.counter = self.counter.wrapping_add(1);
+        block
+    }
+
+    /// Replaces the key with fresh keystream, so the previous output cannot be
+    /// reconstructed from the current state.
+    fn rekey(&mut self) {
+        let block = self.next_block();
+        self.key.copy_from_slice(&block[..8]);
+    }
+
+    /// Mixes `seed` into the key.
+    fn reseed(&mut self, seed: &[u8]) {
+        for (i, chunk) in seed.chunks(4).enumerate() {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.key[i % 8] ^= u32::from_le_bytes(word);
+        }
+        self.rekey();
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(64) {
+            let block = self.next_block();
+            for (dst, word) in chunk.chunks_mut(4).zip(block) {
+                dst.copy_from_slice(&word.to_le_bytes()[..dst.len()]);
+            }
+        }
+        self.rekey();
+    }
+}
+
+#[inline(always)]
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(16);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(12);
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(8);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(7);
+}
+
+/// Computes a ChaCha20 block with a zero nonce.
+fn chacha20_block(key: &[u32; 8], counter: u64) -> [u32; 16] {
+    let mut state = [0; 16];
+    state[..4].copy_from_slice(&CHACHA_CONSTANTS);
+    state[4..12].copy_from_slice(key);
+    state[12] = counter as u32;
+    state[13] = (counter >> 32) as u32;
+
+    let mut x = state;
+    for _ in 0..10 {
+        quarter_round(&mut x, 0, 4, 8, 12);
+        quarter_round(&mut x, 1, 5, 9, 13);
+        quarter_round(&mut x, 2, 6, 10, 14);
+        quarter_round(&mut x, 3, 7, 11, 15);
+        quarter_round(&mut x, 0, 5, 10, 15);
+        quarter_round(&mut x, 1, 6, 11, 12);
+        quarter_round(&mut x, 2, 7, 8, 13);
+        quarter_round(&mut x, 3, 4, 9, 14);
+    }
+    for (x, s) in x.iter_mut().zip(state) {
+        *x = x.wrapping_add(s);
+    }
+    x
+}
+
+/// Mixes entropy from the random number generator of the CPU into `rng`,
+/// returns `false` if the CPU has none or it fails.
+fn reseed_from_cpu(rng: &mut ChaChaRng) -> bool {
+    let mut seed = [0; CPU_RESEED_WORDS * 8];
+    for chunk in seed.chunks_mut(8) {
+        match axhal::misc::random_u64() {
+            Some(val) => chunk.copy_from_slice(&val.to_le_bytes()),
+            None => return false,
+        }
+    }
+    rng.reseed(&seed);
+    true
+}
+
+/// Mixes entropy from the random number generator device into `rng`.
+#[cfg(feature = "rng")]
+fn reseed_from_device(rng: &mut ChaChaRng) {
+    if let Some(dev) = RNG_DEVICE.lock().as_mut() {
+        let mut seed = [0; RESEED_LEN];
+        match dev.fill_bytes(&mut seed) {
+            Ok(len) => rng.reseed(&seed[..len]),
+            Err(e) => warn!("failed to read from {}: {:?}", dev.device_name(), e),
+        }
+    }
+}
+
+/// Fills `buf` with random bytes.
+pub fn fill_random(buf: &mut [u8]) {
+    let mut rng = RNG.lock();
+    if HAS_CPU_RNG.load(Ordering::Relaxed) {
+        reseed_from_cpu(&mut rng);
+    }
+    #[cfg(feature = "rng")]
+    reseed_from_device(&mut rng);
+    rng.fill(buf);
+}
+
+/// Mixes additional entropy into the global generator.
+pub fn add_entropy(seed: &[u8]) {
+    RNG.lock().reseed(seed);
+}
+
+/// Seeds the global generator with the boot time, and the random number
+/// generator of the CPU, or the jitter of the cycle counter if there is none.
+pub(crate) fn init_random() {
+    let mut rng = RNG.lock();
+    rng.reseed(&axhal::time::monotonic_time_nanos().to_le_bytes());
+    if reseed_from_cpu(&mut rng) {
+        info!("Use the random number generator of the CPU as an entropy source.");
+        HAS_CPU_RNG.store(true, Ordering::Relaxed);
+    } else {
+        warn!("No random number generator in the CPU, seeding from the timer jitter.");
+        let mut seed = [0; CPU_RESEED_WORDS * 8];
+        for chunk in seed.chunks_mut(8) {
+            chunk.copy_from_slice(&axhal::misc::jitter_random_u64().to_le_bytes());
+        }
+        rng.reseed(&seed);
+    }
+}
+
+/// Uses the first random number generator device (if any) as the entropy
+/// source of the global generator.
+#[cfg(feature = "rng")]
+pub(crate) fn init_rng_device(mut rng_devs: AxDeviceContainer<AxRngDevice>) {
+    if let Some(dev) = rng_devs.take_one() {
+        info!("Use {:?} as the entropy source.", dev.device_name());
+        *RNG_DEVICE.lock() = Some(dev);
+        reseed_from_device(&mut RNG.lock());
+    } else if !HAS_CPU_RNG.load(Ordering::Relaxed) {
+        warn!("No entropy source found, random numbers may be predictable.");
+    }
+}
+
+/// Releases the entropy source, the global generator keeps working without
+/// reseeding.
+#[cfg(feature = "rng")]
+pub(crate) fn shutdown_rng_device() {
+    if let Some(mut dev) = RNG_DEVICE.lock().take() {
+        dev.shutdown().ok();
+    }
+}
@@ -0,0 +1,113 @@
+//! Sound playback support.
+//!
+//! Only one output stream of the first sound device is used at a time.
+
+use axdriver::{prelude::*, AxDeviceContainer};
+use kspin::SpinNoIrq;
+
+pub use axdriver::prelude::{PcmFormat, PcmParams, PcmRate};
+
+struct Playback {
+    dev: AxSoundDevice,
+    /// The stream being played and whether it has been started.
+    stream: Option<(u32, bool)>,
+}
+
+static PLAYBACK: SpinNoIrq<Option<Playback>> = SpinNoIrq::new(None);
+
+fn supports(info: &PcmStreamInfo, params: &PcmParams) -> bool {
+    info.direction == PcmDirection::Output
+        && info.formats & (1 << params.format as u8) != 0
+        && info.rates & (1 << params.rate as u8) != 0
+        && (info.channels_min..=info.channels_max).contains(&params.channels)
+}
+
+/// Prepares the first output stream that supports `params` for playback.
+pub fn start_playback(params: &PcmParams) -> DevResult {
+    let mut playback = PLAYBACK.lock();
+    let playback = playback.as_mut().ok_or(DevError::Unsupported)?;
+    if playback.stream.is_some() {
+        return Err(DevError::ResourceBusy);
+    }
+    let dev = &mut playback.dev;
+    let stream_id = (0..dev.num_streams())
+        .find(|&id| matches!(dev.stream_info(id), Ok(info) if supports(&info, params)))
+        .ok_or(DevError::Unsupported)?;
+    dev.set_params(stream_id, params)?;
+    dev.prepare(stream_id)?;
+    debug!("sound: playing on stream {} with {:?}", stream_id, params);
+    playback.stream = Some((stream_id, false));
+    Ok(())
+}
+
+/// Queues one period of frames for playback, waiting while the device queue
+/// is full. The stream is started after the first period is queued.
+///
+/// If the device ran out of frames before this period was queued, the period
+/// is still queued but [`DevError::BadState`] is returned to report the
+/// underrun.
+pub fn write_period(data: &[u8]) -> DevResult {
+    let mut underrun = false;
+    loop {
+        let mut playback = PLAYBACK.lock();
+        let Playback { dev, stream } = playback.as_mut().ok_or(DevError::Unsupported)?;
+        let (stream_id, started) = stream.as_mut().ok_or(DevError::BadState)?;
+        match dev.poll_completed() {
+            Ok(_) => {}
+            Err(DevError::BadState) => underrun = true,
+            Err(e) => return Err(e),
+        }
+        match dev.submit_period(*stream_id, data) {
+            Ok(()) => {
+                if !*started {
+                    dev.start(*stream_id)?;
+                    *started = true;
+                }
+                break;
+            }
+            Err(DevError::Again) => {}
+            Err(e) => return Err(e),
+        }
+        drop(playback);
+        #[cfg(feature = "multitask")]
+        axtask::yield_now();
+        #[cfg(not(feature = "multitask"))]
+        core::hint::spin_loop();
+    }
+    if underrun {
+        Err(DevError::BadState)
+    } else {
+        Ok(())
+    }
+}
+
+/// Stops the playback and releases the stream.
+pub fn stop_playback() -> DevResult {
+    let mut playback = PLAYBACK.lock();
+    let Playback { dev, stream } = playback.as_mut().ok_or(DevError::Unsupported)?;
+    let (stream_id, started) = stream.take().ok_or(DevError::BadState)?;
+    if started {
+        dev.stop(stream_id)?;
+    }
+    dev.release(stream_id)
+}
+
+pub(crate) fn init_sound(mut sound_devs: AxDeviceContainer<AxSoundDevice>) {
+    if let Some(mut dev) = sound_devs.take_one() {
+        info!("Use sound device: {:?}", dev.device_name());
+        for jack_id in 0..dev.num_jacks() {
+            if let Ok(jack) = dev.jack_info(jack_id) {
+                debug!("  jack {}: {:?}", jack_id, jack);
+            }
+        }
+        *PLAYBACK.lock() = Some(Playback { dev, stream: None });
+    }
+}
+
+pub(crate) fn shutdown_sound() {
+    // Stop the running stream (if any) before the device is reset.
+    stop_playback().ok();
+    if let Some(Playback { mut dev, .. }) = PLAYBACK.lock().take() {
+        dev.shutdown().ok();
+    }
+}
@@ -0,0 +1,195 @@
+//! A condition variable working with [`Mutex`].
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use axtask::WaitQueue;
+
+use crate::MutexGuard;
+
+/// A condition variable, similar to
+/// [`std::sync::Condvar`](https://doc.rust-lang.org/std/sync/struct.Condvar.html).
+///
+/// A task waiting on it releases the lock of a [`Mutex`](crate::Mutex) and
+/// blocks at once, and locks the mutex again before returning, so that it is
+/// not missing a notification sent by a task that has locked the mutex after
+/// it. The waits may also return without being notified, so the condition
+/// waited for has to be checked again in a loop.
+///
+/// [`wait`](Self::wait) keeps waiting even if the current task is canceled
+/// by [`axtask::cancel`], like [`Mutex::lock`](crate::Mutex::lock).
+pub struct Condvar {
+    /// The number of notifications sent, to tell whether one is sent after a
+    /// task starts to wait.
+    seq: AtomicU32,
+    wq: WaitQueue,
+}
+
+/// Whether a timed wait on a [`Condvar`] returned because the timeout
+/// elapsed, see [`Condvar::wait_timeout`].
+#[cfg(feature = "irq")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+#[cfg(feature = "irq")]
+impl WaitTimeoutResult {
+    /// Returns `true` if the wait returned because the timeout elapsed.
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+impl Condvar {
+    /// Creates a new condition variable with no waiters.
+    pub const fn new() -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+            wq: WaitQueue::new(),
+        }
+    }
+
+    /// Releases the lock of the given guard and blocks the current task until
+    /// this condition variable is notified, then locks the mutex again and
+    /// returns the new guard.
+    #[cfg_attr(feature = "deadlock-detect", track_caller)]
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        // Read before unlocking, so a notification sent after is seen.
+        let seq = self.seq.load(Ordering::Relaxed);
+        drop(guard);
+        self.wq
+            .wait_until_noncancelable(|| self.seq.load(Ordering::Relaxed) != seq);
+        mutex.lock()
+    }
+
+    /// Same as [`wait`](Self::wait), but also returns if the given duration
+    /// has elapsed, in which case the [`WaitTimeoutResult`] tells it timed
+    /// out. The mutex is locked again either way.
+    ///
+    /// Unlike [`wait`](Self::wait), it returns early if the current task is
+    /// canceled by [`axtask::cancel`], as a spurious wakeup.
+    #[cfg(feature = "irq")]
+    #[doc(cfg(feature = "irq"))]
+    #[cfg_attr(feature = "deadlock-detect", track_caller)]
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        dur: core::time::Duration,
+    ) -> (MutexGuard<'a, T>, WaitTimeoutResult) {
+        let mutex = guard.mutex();
+        let seq = self.seq.load(Ordering::Relaxed);
+        drop(guard);
+        let result = self
+            .wq
+            .wait_timeout_until(dur, || self.seq.load(Ordering::Relaxed) != seq);
+        (mutex.lock(), WaitTimeoutResult(result.timed_out()))
+    }
+
+    /// Wakes up one of the tasks waiting on this condition variable, if any.
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        self.wq.notify_one(true);
+    }
+
+    /// Wakes up all the tasks waiting on this condition variable.
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        self.wq.notify_all(true);
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl core::fmt::Debug for Condvar {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.pad("Condvar { .. }")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mutex::tests::{INIT, SERIAL};
+    use crate::{Condvar, Mutex};
+    use axtask as thread;
+    use core::time::Duration;
+    use std::collections::VecDeque;
+
+    /// Producers and consumers pass items through a bounded buffer, waiting
+    /// while it is full or empty.
+    #[test]
+    fn bounded_buffer() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        const CAPACITY: usize = 4;
+        const NUM_PRODUCERS: usize = 4;
+        const NUM_CONSUMERS: usize = 4;
+        const NUM_ITEMS: usize = 1000;
+        static BUF: Mutex<VecDeque<usize>> = Mutex::new(VecDeque::new());
+        static NOT_FULL: Condvar = Condvar::new();
+        static NOT_EMPTY: Condvar = Condvar::new();
+        static SUM: Mutex<usize> = Mutex::new(0);
+
+        let producers: Vec<_> = (0..NUM_PRODUCERS)
+            .map(|_| {
+                thread::spawn(|| {
+                    for i in 1..=NUM_ITEMS {
+                        let mut buf = BUF.lock();
+                        while buf.len() == CAPACITY {
+                            buf = NOT_FULL.wait(buf);
+                        }
+                        buf.push_back(i);
+                        drop(buf);
+                        NOT_EMPTY.notify_one();
+                    }
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..NUM_CONSUMERS)
+            .map(|_| {
+                thread::spawn(|| {
+                    let mut sum = 0;
+                    for _ in 0..NUM_ITEMS * NUM_PRODUCERS / NUM_CONSUMERS {
+                        let mut buf = BUF.lock();
+                        let item = loop {
+                            if let Some(item) = buf.pop_front() {
+                                break item;
+                            }
+                            // The clock stands still under the unit tests,
+                            // so it only returns when notified.
+                            buf = NOT_EMPTY.wait_timeout(buf, Duration::from_secs(1)).0;
+                        };
+                        drop(buf);
+                        NOT_FULL.notify_one();
+                        sum += item;
+                    }
+                    *SUM.lock() += sum;
+                })
+            })
+            .collect();
+
+        for task in producers.into_iter().chain(consumers) {
+            task.join();
+        }
+        assert!(BUF.lock().is_empty());
+        assert_eq!(*SUM.lock(), NUM_PRODUCERS * NUM_ITEMS * (NUM_ITEMS + 1) / 2);
+        println!("Condvar bounded buffer test OK");
+    }
+
+    #[test]
+    fn wait_timeout_elapsed() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        let m = Mutex::new(0);
+        let cv = Condvar::new();
+        let (guard, result) = cv.wait_timeout(m.lock(), Duration::ZERO);
+        assert!(result.timed_out());
+        // The mutex is locked again.
+        assert!(m.is_locked());
+        assert_eq!(*guard, 0);
+    }
+}
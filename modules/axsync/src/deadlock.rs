@@ -0,0 +1,267 @@
+//! Detection of the deadlocks between the [`Mutex`](crate::Mutex) and
+//! [`RwLock`](crate::RwLock) locks, with the `deadlock-detect` feature.
+//!
+//! It records the locks held by each task, and the order in which the locks
+//! have been acquired by any task: a lock requested while another is held
+//! adds an edge from the held lock to the requested one to a global graph.
+//! A task requesting a lock that, by the edges, is acquired before one it
+//! already holds could deadlock with the task that acquired them in that
+//! order, even if they never actually race, and it panics at once, with the
+//! call sites of the locks and the tasks involved. So does a task requesting
+//! a lock it already holds.
+//!
+//! Only the blocking acquisitions are checked. The ones that cannot block
+//! forever, i.e., with `try_` and the timed ones, are recorded as held, but
+//! they may be requested in any order.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::panic::Location;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use kspin::SpinNoIrq;
+
+/// The identity of a lock in the lock-order graph, embedded in the lock.
+///
+/// The ID is assigned when the lock is used first, and its edges are removed
+/// when it is dropped, so that a new lock at the same address is not mistaken
+/// for it.
+pub(crate) struct LockClass {
+    id: AtomicUsize,
+}
+
+/// A lock held by a task.
+struct Held {
+    id: usize,
+    exclusive: bool,
+    site: &'static Location<'static>,
+}
+
+/// How an edge of the lock-order graph was added first.
+struct Edge {
+    /// The task that acquired the locks in the order.
+    task: String,
+    /// Where the task had acquired the lock held.
+    held_site: &'static Location<'static>,
+    /// Where the task requested the other lock.
+    site: &'static Location<'static>,
+}
+
+struct Graph {
+    /// The locks held by each task, keyed by the task ID, in the order of
+    /// acquisition.
+    held: BTreeMap<u64, Vec<Held>>,
+    /// The edges from each lock to the locks requested while it was held.
+    edges: BTreeMap<usize, BTreeMap<usize, Edge>>,
+}
+
+static GRAPH: SpinNoIrq<Graph> = SpinNoIrq::new(Graph {
+    held: BTreeMap::new(),
+    edges: BTreeMap::new(),
+});
+
+impl LockClass {
+    pub(crate) const fn new() -> Self {
+        Self {
+            id: AtomicUsize::new(0),
+        }
+    }
+
+    fn id(&self) -> usize {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+        let id = self.id.load(Ordering::Acquire);
+        if id != 0 {
+            return id;
+        }
+        let new_id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        match self
+            .id
+            .compare_exchange(0, new_id, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => new_id,
+            Err(id) => id,
+        }
+    }
+}
+
+impl Drop for LockClass {
+    fn drop(&mut self) {
+        let id = *self.id.get_mut();
+        if id != 0 {
+            let mut graph = GRAPH.lock();
+            graph.edges.remove(&id);
+            for edges in graph.edges.values_mut() {
+                edges.remove(&id);
+            }
+        }
+    }
+}
+
+impl Graph {
+    /// Returns the first edge of a path from `from` to `to`, if any.
+    fn find_path(&self, from: usize, to: usize) -> Option<&Edge> {
+        let mut visited = Vec::new();
+        let mut stack: Vec<(usize, &Edge)> = self
+            .edges
+            .get(&from)?
+            .iter()
+            .map(|(&next, edge)| (next, edge))
+            .collect();
+        while let Some((id, first)) = stack.pop() {
+            if id == to {
+                return Some(first);
+            }
+            if visited.contains(&id) {
+                continue;
+            }
+            visited.push(id);
+            if let Some(edges) = self.edges.get(&id) {
+                stack.extend(edges.keys().map(|&next| (next, first)));
+            }
+        }
+        None
+    }
+}
+
+/// Checks that the current task may block on the lock, before it does, and
+/// adds the edges from the locks it holds to the lock.
+///
+/// # Panics
+///
+/// Panics if the task holds the lock, or the edges make a cycle.
+#[track_caller]
+pub(crate) fn before_lock(class: &LockClass, exclusive: bool) {
+    let site = Location::caller();
+    let id = class.id();
+    let curr = axtask::current();
+    let mut graph = GRAPH.lock();
+    let Some(held) = graph.held.get(&curr.id().as_u64()) else {
+        return;
+    };
+    if let Some(h) = held.iter().find(|h| h.id == id) {
+        let (held_site, kind) = (h.site, lock_kind(h.exclusive));
+        drop(graph);
+        panic!(
+            "deadlock: {} locks {} at {} while holding it {} since {}",
+            curr.id_name(),
+            lock_kind(exclusive),
+            site,
+            kind,
+            held_site
+        );
+    }
+    for h in held {
+        if let Some(edge) = graph.find_path(id, h.id) {
+            let message = alloc::format!(
+                "deadlock: lock order inversion: {} locks at {} while holding the lock taken at {}, \
+                 but {} has locked the latter at {} while holding the former taken at {}",
+                curr.id_name(),
+                site,
+                h.site,
+                edge.task,
+                edge.site,
+                edge.held_site,
+            );
+            drop(graph);
+            panic!("{}", message);
+        }
+    }
+    let new_edges: Vec<_> = held.iter().map(|h| (h.id, h.site)).collect();
+    for (held_id, held_site) in new_edges {
+        graph
+            .edges
+            .entry(held_id)
+            .or_default()
+            .entry(id)
+            .or_insert_with(|| Edge {
+                task: curr.id_name(),
+                held_site,
+                site,
+            });
+    }
+}
+
+/// Records that the current task holds the lock.
+#[track_caller]
+pub(crate) fn acquired(class: &LockClass, exclusive: bool) {
+    let held = Held {
+        id: class.id(),
+        exclusive,
+        site: Location::caller(),
+    };
+    let task_id = axtask::current().id().as_u64();
+    GRAPH.lock().held.entry(task_id).or_default().push(held);
+}
+
+/// Records that the current task has released the lock.
+pub(crate) fn released(class: &LockClass) {
+    let id = class.id();
+    let task_id = axtask::current().id().as_u64();
+    let mut graph = GRAPH.lock();
+    if let Some(held) = graph.held.get_mut(&task_id) {
+        if let Some(index) = held.iter().rposition(|h| h.id == id) {
+            held.remove(index);
+        }
+        if held.is_empty() {
+            graph.held.remove(&task_id);
+        }
+    }
+}
+
+fn lock_kind(exclusive: bool) -> &'static str {
+    if exclusive {
+        "exclusively"
+    } else {
+        "shared"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mutex::tests::{INIT, SERIAL};
+    use crate::{Mutex, RwLock};
+    use axtask as thread;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    /// Locking in the order A, B and then B, A fires the detector, though the
+    /// two never race.
+    #[test]
+    fn abba() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        static A: Mutex<()> = Mutex::new(());
+        static B: RwLock<()> = RwLock::new(());
+
+        thread::spawn(|| {
+            let _a = A.lock();
+            let _b = B.write();
+        })
+        .join();
+        let result = catch_unwind(|| {
+            let _b = B.read();
+            let _a = A.lock();
+        });
+        assert!(result.is_err());
+        // The guards are released by the unwinding.
+        assert!(!A.is_locked());
+        assert_eq!(B.reader_count(), 0);
+        println!("Deadlock detection ABBA test OK");
+    }
+
+    #[test]
+    fn self_deadlock() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        let m = Mutex::new(0);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let _first = m.lock();
+            let _second = m.lock();
+        }));
+        assert!(result.is_err());
+        assert!(!m.is_locked());
+        println!("Deadlock detection self-deadlock test OK");
+    }
+}
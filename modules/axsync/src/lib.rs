@@ -3,25 +3,61 @@
 //! Currently supported primitives:
 //!
 //! - [`Mutex`]: A mutual exclusion primitive.
+//! - [`Condvar`]: A condition variable working with [`Mutex`].
+//! - [`RwLock`]: A reader-writer lock preferring writers.
+//! - [`Once`] and [`LazyInit`]: One-time initialization.
 //! - mod [`spin`]: spinlocks imported from the [`kspin`] crate.
 //!
 //! # Cargo Features
 //!
 //! - `multitask`: For use in the multi-threaded environments. If the feature is
-//!   not enabled, [`Mutex`] will be an alias of [`spin::SpinNoIrq`]. This
-//!   feature is enabled by default.
+//!   not enabled, [`Mutex`] will be an alias of [`spin::SpinNoIrq`], and
+//!   [`RwLock`] and [`Once`] will spin instead of blocking. This feature is
+//!   enabled by default. [`Condvar`] is only available with it.
+//! - `smp`: Lets the adaptive [`Mutex`] spin while its owner runs on another
+//!   CPU.
+//! - `deadlock-detect`: Panics when a task locks a [`Mutex`] or [`RwLock`]
+//!   it already holds, or in the reverse order of another acquisition, which
+//!   could deadlock. It is for debugging, as it adds bookkeeping to each
+//!   lock and unlock.
+//! - `irq`: Enables the timed waits, e.g., [`Condvar::wait_timeout`] and
+//!   [`Mutex::try_lock_for`].
+//! - `sched_prio`: Uses the priority scheduler of [`axtask`], under which the
+//!   priority inheritance of [`Mutex`] takes effect.
 
 #![cfg_attr(not(test), no_std)]
 #![feature(doc_cfg)]
 
+#[cfg(feature = "deadlock-detect")]
+extern crate alloc;
+
 pub use kspin as spin;
 
+#[cfg(feature = "deadlock-detect")]
+mod deadlock;
+
+mod once;
+mod rwlock;
+
+pub use self::once::{LazyInit, Once, OncePoisoned};
+pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+#[cfg(feature = "multitask")]
+mod condvar;
 #[cfg(feature = "multitask")]
 mod mutex;
 
 #[cfg(feature = "multitask")]
 #[doc(cfg(feature = "multitask"))]
-pub use self::mutex::{Mutex, MutexGuard};
+pub use self::mutex::{Mutex, MutexGuard, MAX_ADAPTIVE_SPINS};
+
+#[cfg(feature = "multitask")]
+#[doc(cfg(feature = "multitask"))]
+pub use self::condvar::Condvar;
+
+#[cfg(all(feature = "multitask", feature = "irq"))]
+#[doc(cfg(all(feature = "multitask", feature = "irq")))]
+pub use self::condvar::WaitTimeoutResult;
 
 #[cfg(not(feature = "multitask"))]
 #[doc(cfg(not(feature = "multitask")))]
@@ -5,7 +5,11 @@ use core::fmt;
 use core::ops::{Deref, DerefMut};
 use core::sync::atomic::{AtomicU64, Ordering};
 
-use axtask::{current, WaitQueue};
+use axtask::{current, WaitQueue, WaitResult};
+
+/// The most times a task spins on an adaptive [`Mutex`] before it blocks,
+/// see [`Mutex::new_adaptive`].
+pub const MAX_ADAPTIVE_SPINS: usize = 1000;
 
 /// A mutual exclusion primitive useful for protecting shared data, similar to
 /// [`std::sync::Mutex`](https://doc.rust-lang.org/std/sync/struct.Mutex.html).
@@ -13,9 +17,20 @@ use axtask::{current, WaitQueue};
 /// When the mutex is locked, the current task will block and be put into the
 /// wait queue. When the mutex is unlocked, all tasks waiting on the queue
 /// will be woken up.
+///
+/// A task blocking on the mutex boosts the owner to its own priority until the
+/// owner unlocks it, with [`axtask::boost_priority`], so that a more urgent
+/// task is not held up by less urgent ones running instead of the owner.
+///
+/// An adaptive mutex created by [`new_adaptive`](Mutex::new_adaptive) spins
+/// for a while before blocking, see there.
 pub struct Mutex<T: ?Sized> {
     wq: WaitQueue,
     owner_id: AtomicU64,
+    #[cfg_attr(not(feature = "smp"), allow(dead_code))]
+    adaptive: bool,
+    #[cfg(feature = "deadlock-detect")]
+    class: crate::deadlock::LockClass,
     data: UnsafeCell<T>,
 }
 
@@ -38,6 +53,31 @@ impl<T> Mutex<T> {
         Self {
             wq: WaitQueue::new(),
             owner_id: AtomicU64::new(0),
+            adaptive: false,
+            #[cfg(feature = "deadlock-detect")]
+            class: crate::deadlock::LockClass::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Creates a new adaptive [`Mutex`] wrapping the supplied data.
+    ///
+    /// A task finding it locked spins, up to [`MAX_ADAPTIVE_SPINS`] times,
+    /// while the owner is running on another CPU, as the owner may unlock it
+    /// sooner than the task could block and be woken up. It blocks once the
+    /// owner stops running, e.g., blocks itself. It suits the short critical
+    /// sections that are contended.
+    ///
+    /// The spin runs with preemption disabled, and it is skipped without the
+    /// `smp` feature, where the owner never runs while the task does.
+    #[inline(always)]
+    pub const fn new_adaptive(data: T) -> Self {
+        Self {
+            wq: WaitQueue::new(),
+            owner_id: AtomicU64::new(0),
+            adaptive: true,
+            #[cfg(feature = "deadlock-detect")]
+            class: crate::deadlock::LockClass::new(),
             data: UnsafeCell::new(data),
         }
     }
@@ -68,8 +108,58 @@ impl<T: ?Sized> Mutex<T> {
     ///
     /// The returned value may be dereferenced for data access
     /// and the lock will be dropped when the guard falls out of scope.
+    ///
+    /// It keeps waiting for the lock even if the current task is canceled by
+    /// [`axtask::cancel`], see [`lock_cancelable`](Self::lock_cancelable).
+    #[cfg_attr(feature = "deadlock-detect", track_caller)]
     pub fn lock(&self) -> MutexGuard<T> {
+        self.lock_inner(false, None).unwrap()
+    }
+
+    /// Same as [`lock`](Self::lock), but returns [`None`] if the current task
+    /// is canceled by [`axtask::cancel`] before the lock is acquired.
+    #[cfg_attr(feature = "deadlock-detect", track_caller)]
+    pub fn lock_cancelable(&self) -> Option<MutexGuard<T>> {
+        self.lock_inner(true, None)
+    }
+
+    /// Same as [`lock`](Self::lock), but gives up and returns [`None`] if the
+    /// lock is not acquired within the given duration, or the current task
+    /// is canceled by [`axtask::cancel`] meanwhile.
+    ///
+    /// The current task sleeps while waiting, and the wakeup of an unlock
+    /// racing with the timeout is passed on to the other waiters.
+    #[cfg(feature = "irq")]
+    #[doc(cfg(feature = "irq"))]
+    #[cfg_attr(feature = "deadlock-detect", track_caller)]
+    pub fn try_lock_for(&self, dur: core::time::Duration) -> Option<MutexGuard<T>> {
+        self.try_lock_until(axhal::time::monotonic_time() + dur)
+    }
+
+    /// Same as [`try_lock_for`](Self::try_lock_for), but gives up at the
+    /// given deadline of [`axhal::time::monotonic_time`].
+    #[cfg(feature = "irq")]
+    #[doc(cfg(feature = "irq"))]
+    #[cfg_attr(feature = "deadlock-detect", track_caller)]
+    pub fn try_lock_until(&self, deadline: axhal::time::TimeValue) -> Option<MutexGuard<T>> {
+        self.lock_inner(true, Some(deadline))
+    }
+
+    /// Locks it, giving up on cancellation if `cancelable`, and at the
+    /// `deadline` if any, which is only set with the `irq` feature.
+    #[cfg_attr(feature = "deadlock-detect", track_caller)]
+    fn lock_inner(
+        &self,
+        cancelable: bool,
+        deadline: Option<axhal::time::TimeValue>,
+    ) -> Option<MutexGuard<T>> {
         let current_id = current().id().as_u64();
+        #[cfg(feature = "deadlock-detect")]
+        if deadline.is_none() {
+            crate::deadlock::before_lock(&self.class, true);
+        }
+        // The owner found last, looked up again only if the lock changes hands.
+        let mut owner: Option<(u64, Option<axtask::AxTaskRef>)> = None;
         loop {
             // Can fail to lock even if the spinlock is not locked. May be more efficient than `try_lock`
             // when called in a loop.
@@ -87,19 +177,93 @@ impl<T: ?Sized> Mutex<T> {
                         "{} tried to acquire mutex it already owns.",
                         current().id_name()
                     );
+                    let owner = match &mut owner {
+                        Some((id, task)) if *id == owner_id => task.as_ref(),
+                        cached => {
+                            let (_, task) = cached.insert((owner_id, axtask::find_task(owner_id)));
+                            task.as_ref()
+                        }
+                    };
+                    #[cfg(feature = "smp")]
+                    if self.adaptive && self.spin_on_owner(owner_id, owner) {
+                        continue;
+                    }
+                    self.inherit_priority(owner_id, owner);
                     // Wait until the lock looks unlocked before retrying
-                    self.wq.wait_until(|| !self.is_locked());
+                    let result = match deadline {
+                        #[cfg(feature = "irq")]
+                        Some(deadline) => {
+                            let dur = deadline.saturating_sub(axhal::time::monotonic_time());
+                            self.wq.wait_timeout_until(dur, || !self.is_locked())
+                        }
+                        _ if cancelable => self.wq.wait_until(|| !self.is_locked()),
+                        _ => {
+                            self.wq.wait_until_noncancelable(|| !self.is_locked());
+                            WaitResult::Notified
+                        }
+                    };
+                    axtask::set_current_waiting_for(0, 0);
+                    if result != WaitResult::Notified {
+                        // The wakeup of an unlock may have been taken by this
+                        // task as it gave up, and no one else would get it.
+                        if !self.is_locked() {
+                            self.wq.notify_one(true);
+                        }
+                        return None;
+                    }
                 }
             }
         }
-        MutexGuard {
+        #[cfg(feature = "deadlock-detect")]
+        crate::deadlock::acquired(&self.class, true);
+        Some(MutexGuard {
             lock: self,
             data: unsafe { &mut *self.data.get() },
+        })
+    }
+
+    /// Spins while the lock is held by the given owner running on another
+    /// CPU, and returns whether it looks unlocked then, see
+    /// [`new_adaptive`](Self::new_adaptive).
+    #[cfg(feature = "smp")]
+    fn spin_on_owner(&self, owner_id: u64, owner: Option<&axtask::AxTaskRef>) -> bool {
+        let Some(owner) = owner else {
+            return !self.is_locked();
+        };
+        let _guard = kernel_guard::NoPreempt::new();
+        for _ in 0..MAX_ADAPTIVE_SPINS {
+            match self.owner_id.load(Ordering::Relaxed) {
+                0 => return true,
+                id if id == owner_id && owner.is_running() => core::hint::spin_loop(),
+                // Blocked or preempted, or it is someone else's now.
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    /// The key of the lock in the priority boosts of its owner.
+    fn key(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+
+    /// Boosts the owner of the lock to the priority of the current task, which
+    /// is about to wait for it.
+    fn inherit_priority(&self, owner_id: u64, owner: Option<&axtask::AxTaskRef>) {
+        let Some(owner) = owner else {
+            return;
+        };
+        axtask::set_current_waiting_for(owner_id, self.key());
+        axtask::boost_priority(owner, self.key(), current().effective_priority());
+        // The owner may have unlocked it and removed its boost before this.
+        if self.owner_id.load(Ordering::Acquire) != owner_id {
+            axtask::restore_priority(owner, self.key());
         }
     }
 
     /// Try to lock this [`Mutex`], returning a lock guard if successful.
     #[inline(always)]
+    #[cfg_attr(feature = "deadlock-detect", track_caller)]
     pub fn try_lock(&self) -> Option<MutexGuard<T>> {
         let current_id = current().id().as_u64();
         // The reason for using a strong compare_exchange is explained here:
@@ -109,6 +273,8 @@ impl<T: ?Sized> Mutex<T> {
             .compare_exchange(0, current_id, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
+            #[cfg(feature = "deadlock-detect")]
+            crate::deadlock::acquired(&self.class, true);
             Some(MutexGuard {
                 lock: self,
                 data: unsafe { &mut *self.data.get() },
@@ -118,6 +284,21 @@ impl<T: ?Sized> Mutex<T> {
         }
     }
 
+    /// Creates a guard for the [`Mutex`] without locking it, e.g., to wait on
+    /// a [`Condvar`](crate::Condvar) with a mutex locked by FFI code.
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held by the current task, and the guard takes over
+    /// releasing it, see [`force_unlock`](Self::force_unlock).
+    #[inline(always)]
+    pub unsafe fn make_guard_unchecked(&self) -> MutexGuard<T> {
+        MutexGuard {
+            lock: self,
+            data: unsafe { &mut *self.data.get() },
+        }
+    }
+
     /// Force unlock the [`Mutex`].
     ///
     /// # Safety
@@ -133,6 +314,14 @@ impl<T: ?Sized> Mutex<T> {
             "{} tried to release mutex it doesn't own",
             current().id_name()
         );
+        #[cfg(feature = "deadlock-detect")]
+        crate::deadlock::released(&self.class);
+        // Remove the boost before waking up the waiter, so that the woken
+        // waiter preempts the task that no longer holds the lock.
+        let curr = current();
+        if curr.is_priority_boosted() {
+            axtask::restore_priority(curr.as_task_ref(), self.key());
+        }
         self.wq.notify_one(true);
     }
 
@@ -167,6 +356,13 @@ impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
     }
 }
 
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    /// Returns the [`Mutex`] locked by the guard.
+    pub(crate) fn mutex(&self) -> &'a Mutex<T> {
+        self.lock
+    }
+}
+
 impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
     type Target = T;
     #[inline(always)]
@@ -198,12 +394,18 @@ impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use crate::Mutex;
     use axtask as thread;
+    #[cfg(feature = "sched_prio")]
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use core::time::Duration;
     use std::sync::Once;
 
-    static INIT: Once = Once::new();
+    /// Shared by the tests of all the primitives, as the scheduler can only be
+    /// initialized once, and the tests must run one at a time.
+    pub(crate) static INIT: Once = Once::new();
+    pub(crate) static SERIAL: std::sync::Mutex<()> = std::sync::Mutex::new(());
 
     fn may_interrupt() {
         // simulate interrupts
@@ -214,6 +416,7 @@ mod tests {
 
     #[test]
     fn lots_and_lots() {
+        let _lock = SERIAL.lock();
         INIT.call_once(thread::init_scheduler);
 
         const NUM_TASKS: u32 = 10;
@@ -249,4 +452,185 @@ mod tests {
         assert_eq!(*M.lock(), NUM_ITERS * NUM_TASKS * 3);
         println!("Mutex test OK");
     }
+
+    /// A task of high priority blocking on a mutex held by one of low priority
+    /// is not held up by another of medium priority that never blocks.
+    #[cfg(feature = "sched_prio")]
+    #[test]
+    fn priority_inheritance() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        const LOW: isize = 10;
+        const MEDIUM: isize = 0;
+        const HIGH: isize = -10;
+        /// The number of times the task of medium priority yields, before
+        /// it gives up waiting for the task of high priority.
+        const MAX_SPINS: usize = 1000;
+        static M: Mutex<()> = Mutex::new(());
+        static WQ: thread::WaitQueue = thread::WaitQueue::new();
+        static LOCKED: AtomicBool = AtomicBool::new(false);
+        static MEDIUM_STARTED: AtomicBool = AtomicBool::new(false);
+        static DONE: AtomicBool = AtomicBool::new(false);
+        static SPINS: AtomicUsize = AtomicUsize::new(0);
+        static SPINS_WHEN_LOCKED: AtomicUsize = AtomicUsize::new(0);
+
+        assert!(thread::set_current_priority(MEDIUM));
+
+        let low = thread::spawn(|| {
+            let guard = M.lock();
+            LOCKED.store(true, Ordering::Release);
+            assert!(thread::set_current_priority(LOW));
+            for _ in 0..100 {
+                thread::yield_now();
+            }
+            drop(guard);
+            assert!(!thread::current().is_priority_boosted());
+        });
+        while !LOCKED.load(Ordering::Acquire) {
+            thread::yield_now();
+        }
+
+        let high = thread::spawn(|| {
+            assert!(thread::set_current_priority(HIGH));
+            WQ.wait_until(|| MEDIUM_STARTED.load(Ordering::Acquire));
+            let _guard = M.lock();
+            SPINS_WHEN_LOCKED.store(SPINS.load(Ordering::Relaxed), Ordering::Relaxed);
+            DONE.store(true, Ordering::Release);
+        });
+        let medium = thread::spawn(|| {
+            MEDIUM_STARTED.store(true, Ordering::Release);
+            WQ.notify_one(true);
+            while !DONE.load(Ordering::Acquire) && SPINS.load(Ordering::Relaxed) < MAX_SPINS {
+                SPINS.fetch_add(1, Ordering::Relaxed);
+                thread::yield_now();
+            }
+        });
+
+        high.join();
+        medium.join();
+        low.join();
+        // Without the boost, the task of low priority would not run to unlock
+        // the mutex until the one of medium priority gives up.
+        assert!(SPINS_WHEN_LOCKED.load(Ordering::Relaxed) <= 1);
+        println!("Priority inheritance test OK");
+    }
+
+    /// Unlocking one of the mutexes keeps the boost by the waiters of the
+    /// others still held.
+    #[cfg(feature = "sched_prio")]
+    #[test]
+    fn priority_restore() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        const LOW: isize = 10;
+        const MEDIUM: isize = -5;
+        const HIGH: isize = -10;
+        static A: Mutex<()> = Mutex::new(());
+        static B: Mutex<()> = Mutex::new(());
+        static WQ: thread::WaitQueue = thread::WaitQueue::new();
+        static LOCKED: AtomicBool = AtomicBool::new(false);
+        static MEDIUM_WAITING: AtomicBool = AtomicBool::new(false);
+        static HIGH_WAITING: AtomicBool = AtomicBool::new(false);
+
+        assert!(thread::set_current_priority(0));
+
+        let low = thread::spawn(|| {
+            let a = A.lock();
+            let b = B.lock();
+            LOCKED.store(true, Ordering::Release);
+            assert!(thread::set_current_priority(LOW));
+            WQ.wait_until(|| HIGH_WAITING.load(Ordering::Acquire));
+            assert_eq!(thread::current().effective_priority(), HIGH);
+            drop(a);
+            assert_eq!(thread::current().effective_priority(), MEDIUM);
+            drop(b);
+            assert!(!thread::current().is_priority_boosted());
+        });
+        while !LOCKED.load(Ordering::Acquire) {
+            thread::yield_now();
+        }
+
+        let medium = thread::spawn(|| {
+            assert!(thread::set_current_priority(MEDIUM));
+            MEDIUM_WAITING.store(true, Ordering::Release);
+            drop(B.lock());
+        });
+        while !MEDIUM_WAITING.load(Ordering::Acquire) {
+            thread::yield_now();
+        }
+
+        let high = thread::spawn(|| {
+            assert!(thread::set_current_priority(HIGH));
+            HIGH_WAITING.store(true, Ordering::Release);
+            WQ.notify_one(false);
+            drop(A.lock());
+        });
+
+        high.join();
+        medium.join();
+        low.join();
+        println!("Priority restore test OK");
+    }
+
+    #[test]
+    fn lock_timeout() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        static M: Mutex<()> = Mutex::new(());
+        let guard = M.lock();
+        // The clock stands still under the unit tests, so only a zero
+        // timeout elapses.
+        let quitter = thread::spawn(|| assert!(M.try_lock_for(Duration::ZERO).is_none()));
+        quitter.join();
+        let waiter = thread::spawn(|| assert!(M.try_lock_for(Duration::from_secs(1)).is_some()));
+        thread::yield_now();
+        drop(guard);
+        waiter.join();
+        assert!(!M.is_locked());
+        println!("Mutex timeout test OK");
+    }
+
+    /// Waiters giving up as the lock is released do not keep the others
+    /// waiting forever.
+    #[test]
+    fn timeout_racing_unlock() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        const NUM_ITERS: usize = 100;
+        static M: Mutex<usize> = Mutex::new(0);
+
+        for i in 0..NUM_ITERS {
+            let guard = M.lock();
+            let quitter = thread::spawn(|| {
+                if let Some(mut val) = M.try_lock_for(Duration::from_secs(1)) {
+                    *val += 1;
+                }
+            });
+            let impatient = thread::spawn(|| {
+                may_interrupt();
+                if let Some(mut val) = M.try_lock_for(Duration::ZERO) {
+                    *val += 1;
+                }
+            });
+            let waiter = thread::spawn(|| *M.lock() += 1);
+            may_interrupt();
+            // Give up waiting at about the same time as the lock is released.
+            if i % 2 == 0 {
+                drop(guard);
+                thread::cancel(&quitter);
+            } else {
+                thread::cancel(&quitter);
+                drop(guard);
+            }
+            waiter.join();
+            quitter.join();
+            impatient.join();
+        }
+        assert!(*M.lock() >= NUM_ITERS);
+        println!("Mutex timeout racing unlock test OK");
+    }
 }
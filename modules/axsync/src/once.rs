@@ -0,0 +1,322 @@
+//! One-time initialization, blocking the tasks racing with the initializer.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+#[cfg(feature = "multitask")]
+use axtask::WaitQueue;
+
+const INCOMPLETE: u8 = 0;
+const RUNNING: u8 = 1;
+const COMPLETE: u8 = 2;
+const POISONED: u8 = 3;
+
+/// The error of a [`Once`] whose initializer panicked, so it never completes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OncePoisoned;
+
+impl fmt::Display for OncePoisoned {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the initializer of a `Once` panicked")
+    }
+}
+
+/// A synchronization primitive to run a one-time initialization, similar to
+/// [`std::sync::Once`](https://doc.rust-lang.org/std/sync/struct.Once.html).
+///
+/// With the `multitask` feature, the tasks racing with the one running the
+/// initializer block in a wait queue until it completes, so the initializer
+/// may block too. Otherwise, they spin.
+///
+/// The initializer must not call [`call_once`](Self::call_once) on the same
+/// [`Once`], which deadlocks.
+pub struct Once {
+    state: AtomicU8,
+    #[cfg(feature = "multitask")]
+    wq: WaitQueue,
+}
+
+/// Marks the [`Once`] poisoned if the initializer unwinds.
+struct CompletionGuard<'a> {
+    once: &'a Once,
+    state_on_drop: u8,
+}
+
+impl Once {
+    /// Creates a new [`Once`] not completed yet.
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(INCOMPLETE),
+            #[cfg(feature = "multitask")]
+            wq: WaitQueue::new(),
+        }
+    }
+
+    /// Runs `f` if it is the first call on this [`Once`], otherwise waits for
+    /// the first one to complete, without running `f`.
+    ///
+    /// It returns [`OncePoisoned`] if the initializer of the first call
+    /// panicked, also to the calls waiting for it.
+    pub fn call_once<F: FnOnce()>(&self, f: F) -> Result<(), OncePoisoned> {
+        loop {
+            match self.state.compare_exchange(
+                INCOMPLETE,
+                RUNNING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    let mut guard = CompletionGuard {
+                        once: self,
+                        state_on_drop: POISONED,
+                    };
+                    f();
+                    guard.state_on_drop = COMPLETE;
+                    return Ok(());
+                }
+                Err(COMPLETE) => return Ok(()),
+                Err(POISONED) => return Err(OncePoisoned),
+                Err(_) => self.wait_running(),
+            }
+        }
+    }
+
+    /// Returns `true` if an initializer has run to completion.
+    #[inline]
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+
+    /// Returns `true` if the initializer panicked.
+    #[inline]
+    pub fn is_poisoned(&self) -> bool {
+        self.state.load(Ordering::Acquire) == POISONED
+    }
+
+    fn wait_running(&self) {
+        #[cfg(feature = "multitask")]
+        self.wq
+            .wait_until_noncancelable(|| self.state.load(Ordering::Acquire) != RUNNING);
+        #[cfg(not(feature = "multitask"))]
+        core::hint::spin_loop();
+    }
+}
+
+impl Drop for CompletionGuard<'_> {
+    fn drop(&mut self) {
+        self.once.state.store(self.state_on_drop, Ordering::Release);
+        #[cfg(feature = "multitask")]
+        self.once.wq.notify_all(true);
+    }
+}
+
+impl Default for Once {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Once {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Once")
+            .field("completed", &self.is_completed())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A value initialized once, by [`init_once`](Self::init_once) or the first
+/// [`call_once`](Self::call_once), which the racing tasks wait for like
+/// [`Once`].
+///
+/// It dereferences to the value, which panics if it is not initialized yet.
+pub struct LazyInit<T> {
+    once: Once,
+    data: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for LazyInit<T> {}
+unsafe impl<T: Send> Send for LazyInit<T> {}
+
+impl<T> LazyInit<T> {
+    /// Creates a new uninitialized value.
+    pub const fn new() -> Self {
+        Self {
+            once: Once::new(),
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Initializes the value with the result of `f` if it is the first call,
+    /// otherwise waits for the first one to complete, and returns the value.
+    ///
+    /// It returns [`OncePoisoned`] if the initializer of the first call
+    /// panicked.
+    pub fn call_once<F: FnOnce() -> T>(&self, f: F) -> Result<&T, OncePoisoned> {
+        self.once.call_once(|| {
+            // Safety: only the first call writes it, and no one reads it
+            // before it completes.
+            unsafe { (*self.data.get()).write(f()) };
+        })?;
+        // Safety: initialized as the `Once` has completed.
+        Ok(unsafe { self.get_unchecked() })
+    }
+
+    /// Initializes the value, and returns it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if it has been initialized, or is being initialized.
+    pub fn init_once(&self, data: T) -> &T {
+        let mut data = Some(data);
+        let value = self.call_once(|| data.take().unwrap());
+        assert!(data.is_none(), "Already initialized");
+        value.unwrap()
+    }
+
+    /// Returns `true` if the value has been initialized.
+    #[inline]
+    pub fn is_inited(&self) -> bool {
+        self.once.is_completed()
+    }
+
+    /// Returns the value if it has been initialized, without waiting.
+    pub fn get(&self) -> Option<&T> {
+        if self.is_inited() {
+            // Safety: initialized as the `Once` has completed.
+            Some(unsafe { self.get_unchecked() })
+        } else {
+            None
+        }
+    }
+
+    /// Returns a mutable reference to the value if it has been initialized.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        if self.is_inited() {
+            // Safety: initialized as the `Once` has completed.
+            Some(unsafe { (*self.data.get()).assume_init_mut() })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the value without checking if it has been initialized.
+    ///
+    /// # Safety
+    ///
+    /// It must have been initialized.
+    #[inline]
+    pub unsafe fn get_unchecked(&self) -> &T {
+        (*self.data.get()).assume_init_ref()
+    }
+}
+
+impl<T> Deref for LazyInit<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.get().expect("Use uninitialized value")
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for LazyInit<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.get() {
+            Some(value) => write!(f, "LazyInit {{ data: {:?} }}", value),
+            None => write!(f, "LazyInit {{ <uninitialized> }}"),
+        }
+    }
+}
+
+impl<T> Default for LazyInit<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for LazyInit<T> {
+    fn drop(&mut self) {
+        if self.is_inited() {
+            // Safety: initialized as the `Once` has completed.
+            unsafe { (*self.data.get()).assume_init_drop() };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mutex::tests::{INIT, SERIAL};
+    use crate::{LazyInit, Once, OncePoisoned};
+    use axtask as thread;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    /// Only one of the racing tasks runs the initializer, which blocks, and
+    /// the others wait for it.
+    #[test]
+    fn racing_call_once() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        const NUM_TASKS: usize = 10;
+        static VALUE: LazyInit<usize> = LazyInit::new();
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+        let tasks: Vec<_> = (0..NUM_TASKS)
+            .map(|i| {
+                thread::spawn(move || {
+                    let value = VALUE.call_once(|| {
+                        RUNS.fetch_add(1, Ordering::Relaxed);
+                        // Let the others race with it.
+                        for _ in 0..10 {
+                            thread::yield_now();
+                        }
+                        i
+                    });
+                    assert_eq!(value, Ok(VALUE.get().unwrap()));
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.join();
+        }
+        assert_eq!(RUNS.load(Ordering::Relaxed), 1);
+        assert!(VALUE.is_inited());
+        assert!(*VALUE < NUM_TASKS);
+        println!("Once racing test OK");
+    }
+
+    /// A panicking initializer poisons the `Once`, and the tasks waiting for
+    /// it get an error.
+    #[test]
+    fn poisoned() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        static ONCE: Once = Once::new();
+        static WAITER_RESULT: AtomicUsize = AtomicUsize::new(0);
+
+        let mut waiter = None;
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            ONCE.call_once(|| {
+                waiter = Some(thread::spawn(|| {
+                    let result = ONCE.call_once(|| unreachable!());
+                    assert_eq!(result, Err(OncePoisoned));
+                    WAITER_RESULT.store(1, Ordering::Release);
+                }));
+                // Let the waiter block on it.
+                thread::yield_now();
+                panic!("initializer failed");
+            })
+        }));
+        assert!(result.is_err());
+        assert!(ONCE.is_poisoned());
+        assert!(!ONCE.is_completed());
+        waiter.unwrap().join();
+        assert_eq!(WAITER_RESULT.load(Ordering::Acquire), 1);
+        assert_eq!(ONCE.call_once(|| unreachable!()), Err(OncePoisoned));
+        println!("Once poisoning test OK");
+    }
+}
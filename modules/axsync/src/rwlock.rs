@@ -0,0 +1,574 @@
+//! A reader-writer lock preferring writers.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axhal::time::TimeValue;
+#[cfg(feature = "multitask")]
+use axtask::WaitQueue;
+#[cfg(not(feature = "multitask"))]
+use kernel_guard::{BaseGuard, NoPreemptIrqSave};
+
+/// The lock state when it is locked for writing, otherwise the state is the
+/// number of readers.
+const WRITER: usize = usize::MAX;
+
+/// A reader-writer lock, similar to
+/// [`std::sync::RwLock`](https://doc.rust-lang.org/std/sync/struct.RwLock.html).
+///
+/// It allows a number of readers or at most one writer at any point in time.
+/// Writers are preferred: once a writer waits for the lock, new readers wait
+/// until it has got the lock and released it, so that writers are not starved
+/// by a stream of readers. Hence a task already holding a read lock must not
+/// lock it for reading again, which may deadlock.
+///
+/// With the `multitask` feature, the tasks waiting for the lock block in wait
+/// queues like [`Mutex`](crate::Mutex), but they do not boost the priority of
+/// the holders. Otherwise, they spin with IRQs and preemption disabled while
+/// the lock is held, like [`SpinNoIrq`](crate::spin::SpinNoIrq).
+pub struct RwLock<T: ?Sized> {
+    state: AtomicUsize,
+    /// The number of writers waiting for the lock, which keep new readers out.
+    waiting_writers: AtomicUsize,
+    #[cfg(feature = "deadlock-detect")]
+    class: crate::deadlock::LockClass,
+    #[cfg(feature = "multitask")]
+    read_wq: WaitQueue,
+    #[cfg(feature = "multitask")]
+    write_wq: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+/// A guard that provides shared data access.
+///
+/// When the guard falls out of scope it will release the read lock.
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+    #[cfg(not(feature = "multitask"))]
+    irq_state: <NoPreemptIrqSave as BaseGuard>::State,
+}
+
+/// A guard that provides mutable data access.
+///
+/// When the guard falls out of scope it will release the write lock.
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+    #[cfg(not(feature = "multitask"))]
+    irq_state: <NoPreemptIrqSave as BaseGuard>::State,
+}
+
+// Same unsafe impls as `std::sync::RwLock`
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    /// Creates a new [`RwLock`] wrapping the supplied data.
+    #[inline(always)]
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            waiting_writers: AtomicUsize::new(0),
+            #[cfg(feature = "deadlock-detect")]
+            class: crate::deadlock::LockClass::new(),
+            #[cfg(feature = "multitask")]
+            read_wq: WaitQueue::new(),
+            #[cfg(feature = "multitask")]
+            write_wq: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Consumes this [`RwLock`] and unwraps the underlying data.
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        // We know statically that there are no outstanding references to
+        // `self` so there's no need to lock.
+        let RwLock { data, .. } = self;
+        data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Returns `true` if the lock is currently held by a writer.
+    ///
+    /// It provides no synchronization guarantees, and should only be used as
+    /// a heuristic, like [`Mutex::is_locked`](crate::Mutex::is_locked).
+    #[inline(always)]
+    pub fn is_write_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) == WRITER
+    }
+
+    /// Returns the number of readers holding the lock, with the same caveat
+    /// as [`is_write_locked`](Self::is_write_locked).
+    #[inline(always)]
+    pub fn reader_count(&self) -> usize {
+        match self.state.load(Ordering::Relaxed) {
+            WRITER => 0,
+            readers => readers,
+        }
+    }
+
+    /// Whether a new reader can get the lock now, i.e., no writer holds it
+    /// or waits for it.
+    fn can_read(&self) -> bool {
+        self.state.load(Ordering::Relaxed) != WRITER
+            && self.waiting_writers.load(Ordering::Relaxed) == 0
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if state >= WRITER - 1 {
+                // Locked for writing, or too many readers.
+                return false;
+            }
+            match self.state.compare_exchange_weak(
+                state,
+                state + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => state = current,
+            }
+        }
+    }
+
+    fn try_lock_exclusive(&self) -> bool {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Waits on `wq` until `condition` is true, and returns `false` if it
+    /// gives up at the `deadline`, or on cancellation if there is a deadline.
+    #[cfg(feature = "multitask")]
+    fn wait<F>(wq: &WaitQueue, _deadline: Option<TimeValue>, condition: F) -> bool
+    where
+        F: Fn() -> bool,
+    {
+        #[cfg(feature = "irq")]
+        if let Some(deadline) = _deadline {
+            let dur = deadline.saturating_sub(axhal::time::monotonic_time());
+            return wq.wait_timeout_until(dur, condition) == axtask::WaitResult::Notified;
+        }
+        wq.wait_until_noncancelable(condition);
+        true
+    }
+
+    /// Waits for the shared lock, and returns `false` if it gives up at the
+    /// `deadline`, which is only set with the `multitask` and `irq` features.
+    fn lock_shared(&self, _deadline: Option<TimeValue>) -> bool {
+        while !(self.can_read() && self.try_lock_shared()) {
+            // The readers are woken up all at once, so one giving up takes no
+            // wakeup from the others.
+            #[cfg(feature = "multitask")]
+            if !Self::wait(&self.read_wq, _deadline, || self.can_read()) {
+                return false;
+            }
+            #[cfg(not(feature = "multitask"))]
+            core::hint::spin_loop();
+        }
+        true
+    }
+
+    /// Waits for the exclusive lock, like [`lock_shared`](Self::lock_shared).
+    fn lock_exclusive(&self, _deadline: Option<TimeValue>) -> bool {
+        if self.try_lock_exclusive() {
+            return true;
+        }
+        self.waiting_writers.fetch_add(1, Ordering::Relaxed);
+        while !self.try_lock_exclusive() {
+            #[cfg(feature = "multitask")]
+            if !Self::wait(&self.write_wq, _deadline, || {
+                self.state.load(Ordering::Relaxed) == 0
+            }) {
+                self.waiting_writers.fetch_sub(1, Ordering::Relaxed);
+                // Let in the readers kept out by this writer, or pass on the
+                // wakeup of an unlock that it may have taken as it gave up.
+                self.wake_waiters();
+                return false;
+            }
+            #[cfg(not(feature = "multitask"))]
+            core::hint::spin_loop();
+        }
+        self.waiting_writers.fetch_sub(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Wakes up the waiters that may get the lock now: a writer if the lock
+    /// is free, or the readers if no writer holds it or waits for it.
+    #[cfg(feature = "multitask")]
+    fn wake_waiters(&self) {
+        let state = self.state.load(Ordering::Relaxed);
+        if self.waiting_writers.load(Ordering::Relaxed) != 0 {
+            if state == 0 {
+                self.write_wq.notify_one(true);
+            }
+        } else if state != WRITER {
+            self.read_wq.notify_all(true);
+        }
+    }
+
+    /// Locks this [`RwLock`] with shared read access, blocking the current
+    /// task until it can be acquired.
+    ///
+    /// It waits while a writer holds the lock or waits for it. Other readers
+    /// may hold the lock at the same time.
+    #[cfg_attr(feature = "deadlock-detect", track_caller)]
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        #[cfg(not(feature = "multitask"))]
+        let irq_state = NoPreemptIrqSave::acquire();
+        #[cfg(feature = "deadlock-detect")]
+        crate::deadlock::before_lock(&self.class, false);
+        self.lock_shared(None);
+        #[cfg(feature = "deadlock-detect")]
+        crate::deadlock::acquired(&self.class, false);
+        RwLockReadGuard {
+            lock: self,
+            #[cfg(not(feature = "multitask"))]
+            irq_state,
+        }
+    }
+
+    /// Locks this [`RwLock`] with exclusive write access, blocking the
+    /// current task until it can be acquired.
+    ///
+    /// Readers arriving after it starts waiting wait for it to release the
+    /// lock.
+    #[cfg_attr(feature = "deadlock-detect", track_caller)]
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        #[cfg(not(feature = "multitask"))]
+        let irq_state = NoPreemptIrqSave::acquire();
+        #[cfg(feature = "deadlock-detect")]
+        crate::deadlock::before_lock(&self.class, true);
+        self.lock_exclusive(None);
+        #[cfg(feature = "deadlock-detect")]
+        crate::deadlock::acquired(&self.class, true);
+        RwLockWriteGuard {
+            lock: self,
+            #[cfg(not(feature = "multitask"))]
+            irq_state,
+        }
+    }
+
+    /// Same as [`read`](Self::read), but gives up and returns [`None`] if the
+    /// lock is not acquired within the given duration, or the current task
+    /// is canceled by [`axtask::cancel`] meanwhile.
+    #[cfg(all(feature = "multitask", feature = "irq"))]
+    #[doc(cfg(all(feature = "multitask", feature = "irq")))]
+    #[cfg_attr(feature = "deadlock-detect", track_caller)]
+    pub fn try_read_for(&self, dur: core::time::Duration) -> Option<RwLockReadGuard<T>> {
+        self.try_read_until(axhal::time::monotonic_time() + dur)
+    }
+
+    /// Same as [`try_read_for`](Self::try_read_for), but gives up at the
+    /// given deadline of [`axhal::time::monotonic_time`].
+    #[cfg(all(feature = "multitask", feature = "irq"))]
+    #[doc(cfg(all(feature = "multitask", feature = "irq")))]
+    #[cfg_attr(feature = "deadlock-detect", track_caller)]
+    pub fn try_read_until(&self, deadline: TimeValue) -> Option<RwLockReadGuard<T>> {
+        if self.lock_shared(Some(deadline)) {
+            #[cfg(feature = "deadlock-detect")]
+            crate::deadlock::acquired(&self.class, false);
+            Some(RwLockReadGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    /// Same as [`write`](Self::write), but gives up and returns [`None`] if
+    /// the lock is not acquired within the given duration, or the current
+    /// task is canceled by [`axtask::cancel`] meanwhile.
+    ///
+    /// A writer giving up lets in the readers waiting for it, and passes on
+    /// the wakeup of an unlock racing with the timeout to the other writers.
+    #[cfg(all(feature = "multitask", feature = "irq"))]
+    #[doc(cfg(all(feature = "multitask", feature = "irq")))]
+    #[cfg_attr(feature = "deadlock-detect", track_caller)]
+    pub fn try_write_for(&self, dur: core::time::Duration) -> Option<RwLockWriteGuard<T>> {
+        self.try_write_until(axhal::time::monotonic_time() + dur)
+    }
+
+    /// Same as [`try_write_for`](Self::try_write_for), but gives up at the
+    /// given deadline of [`axhal::time::monotonic_time`].
+    #[cfg(all(feature = "multitask", feature = "irq"))]
+    #[doc(cfg(all(feature = "multitask", feature = "irq")))]
+    #[cfg_attr(feature = "deadlock-detect", track_caller)]
+    pub fn try_write_until(&self, deadline: TimeValue) -> Option<RwLockWriteGuard<T>> {
+        if self.lock_exclusive(Some(deadline)) {
+            #[cfg(feature = "deadlock-detect")]
+            crate::deadlock::acquired(&self.class, true);
+            Some(RwLockWriteGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    /// Attempts to lock this [`RwLock`] with shared read access, returning
+    /// [`None`] if a writer holds it.
+    ///
+    /// Unlike [`read`](Self::read), it does not give way to the waiting
+    /// writers.
+    #[inline(always)]
+    #[cfg_attr(feature = "deadlock-detect", track_caller)]
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        #[cfg(not(feature = "multitask"))]
+        let irq_state = NoPreemptIrqSave::acquire();
+        if self.try_lock_shared() {
+            #[cfg(feature = "deadlock-detect")]
+            crate::deadlock::acquired(&self.class, false);
+            Some(RwLockReadGuard {
+                lock: self,
+                #[cfg(not(feature = "multitask"))]
+                irq_state,
+            })
+        } else {
+            #[cfg(not(feature = "multitask"))]
+            NoPreemptIrqSave::release(irq_state);
+            None
+        }
+    }
+
+    /// Attempts to lock this [`RwLock`] with exclusive write access,
+    /// returning [`None`] if it is held by any reader or writer.
+    #[inline(always)]
+    #[cfg_attr(feature = "deadlock-detect", track_caller)]
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        #[cfg(not(feature = "multitask"))]
+        let irq_state = NoPreemptIrqSave::acquire();
+        if self.try_lock_exclusive() {
+            #[cfg(feature = "deadlock-detect")]
+            crate::deadlock::acquired(&self.class, true);
+            Some(RwLockWriteGuard {
+                lock: self,
+                #[cfg(not(feature = "multitask"))]
+                irq_state,
+            })
+        } else {
+            #[cfg(not(feature = "multitask"))]
+            NoPreemptIrqSave::release(irq_state);
+            None
+        }
+    }
+
+    fn unlock_shared(&self) {
+        #[cfg(feature = "deadlock-detect")]
+        crate::deadlock::released(&self.class);
+        // The last reader lets a waiting writer in.
+        if self.state.fetch_sub(1, Ordering::Release) == 1 {
+            #[cfg(feature = "multitask")]
+            self.write_wq.notify_one(true);
+        }
+    }
+
+    fn unlock_exclusive(&self) {
+        #[cfg(feature = "deadlock-detect")]
+        crate::deadlock::released(&self.class);
+        self.state.store(0, Ordering::Release);
+        // The waiting writers go first, the readers wait for them anyway.
+        #[cfg(feature = "multitask")]
+        self.wake_waiters();
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    ///
+    /// Since this call borrows the [`RwLock`] mutably, no actual locking
+    /// needs to take place.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        // We know statically that there are no other references to `self`, so
+        // there's no need to lock it.
+        unsafe { &mut *self.data.get() }
+    }
+}
+
+impl<T: ?Sized + Default> Default for RwLock<T> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_read() {
+            Some(guard) => write!(f, "RwLock {{ data: ")
+                .and_then(|()| (*guard).fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "RwLock {{ <locked> }}"),
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        // We know statically that no writer is referencing data
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        // We know statically that only we are referencing data
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RwLockWriteGuard<'a, T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        // We know statically that only we are referencing data
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for RwLockReadGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for RwLockWriteGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockReadGuard<'a, T> {
+    /// The dropping of the [`RwLockReadGuard`] will release the read lock it
+    /// was created from.
+    fn drop(&mut self) {
+        self.lock.unlock_shared();
+        #[cfg(not(feature = "multitask"))]
+        NoPreemptIrqSave::release(self.irq_state);
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockWriteGuard<'a, T> {
+    /// The dropping of the [`RwLockWriteGuard`] will release the write lock it
+    /// was created from.
+    fn drop(&mut self) {
+        self.lock.unlock_exclusive();
+        #[cfg(not(feature = "multitask"))]
+        NoPreemptIrqSave::release(self.irq_state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mutex::tests::{INIT, SERIAL};
+    use crate::RwLock;
+    use axtask as thread;
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use core::time::Duration;
+
+    #[test]
+    fn readers_and_writers() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        let lock = RwLock::new(0);
+        let r1 = lock.read();
+        let r2 = lock.try_read().unwrap();
+        assert_eq!(lock.reader_count(), 2);
+        assert!(lock.try_write().is_none());
+        drop((r1, r2));
+        let mut w = lock.try_write().unwrap();
+        *w += 1;
+        assert!(lock.is_write_locked());
+        assert!(lock.try_read().is_none());
+        drop(w);
+        assert_eq!(*lock.read(), 1);
+        assert_eq!(lock.into_inner(), 1);
+    }
+
+    /// A writer gets the lock among readers that keep it held by turns.
+    #[test]
+    fn writer_not_starved() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        const NUM_READERS: usize = 10;
+        static LOCK: RwLock<usize> = RwLock::new(0);
+        static WRITTEN: AtomicBool = AtomicBool::new(false);
+        static READS: AtomicUsize = AtomicUsize::new(0);
+
+        let readers: Vec<_> = (0..NUM_READERS)
+            .map(|_| {
+                thread::spawn(|| {
+                    while !WRITTEN.load(Ordering::Acquire) {
+                        let guard = LOCK.read();
+                        READS.fetch_add(1, Ordering::Relaxed);
+                        // Let the others in before releasing it, so that
+                        // the lock is always held by some readers.
+                        thread::yield_now();
+                        assert!(*guard == 0 || WRITTEN.load(Ordering::Acquire));
+                    }
+                })
+            })
+            .collect();
+        while READS.load(Ordering::Relaxed) < NUM_READERS {
+            thread::yield_now();
+        }
+
+        let writer = thread::spawn(|| {
+            let mut guard = LOCK.write();
+            assert_eq!(LOCK.reader_count(), 0);
+            *guard = 1;
+            WRITTEN.store(true, Ordering::Release);
+        });
+        writer.join();
+        for reader in readers {
+            reader.join();
+        }
+        assert_eq!(*LOCK.read(), 1);
+        println!("RwLock writer preference test OK");
+    }
+
+    /// A writer giving up lets in the readers waiting behind it.
+    #[test]
+    fn writer_timeout() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        static LOCK: RwLock<()> = RwLock::new(());
+        static READ: AtomicBool = AtomicBool::new(false);
+
+        let guard = LOCK.write();
+        // The clock stands still under the unit tests, so only a zero
+        // timeout elapses.
+        let quitter = thread::spawn(|| {
+            assert!(LOCK.try_read_for(Duration::ZERO).is_none());
+            assert!(LOCK.try_write_for(Duration::ZERO).is_none());
+        });
+        quitter.join();
+        drop(guard);
+
+        let guard = LOCK.read();
+        let writer =
+            thread::spawn(|| assert!(LOCK.try_write_for(Duration::from_secs(1)).is_none()));
+        thread::yield_now();
+        let reader = thread::spawn(|| {
+            drop(LOCK.read());
+            READ.store(true, Ordering::Release);
+        });
+        thread::yield_now();
+        // Kept out by the waiting writer.
+        assert!(!READ.load(Ordering::Acquire));
+        // Give up waiting as if the timeout has elapsed.
+        thread::cancel(&writer);
+        writer.join();
+        reader.join();
+        assert!(READ.load(Ordering::Acquire));
+        drop(guard);
+        assert!(LOCK.try_write_for(Duration::from_secs(1)).is_some());
+        println!("RwLock timeout test OK");
+    }
+}
@@ -7,11 +7,28 @@ use kernel_guard::NoPreemptIrqSave;
 pub(crate) use crate::run_queue::{current_run_queue, select_run_queue};
 
 #[doc(cfg(feature = "multitask"))]
-pub use crate::task::{CurrentTask, TaskId, TaskInner};
+pub use crate::rt::{Policy, MAX_RT_PRIORITY, MIN_RT_PRIORITY};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::scope::{scope, Scope, ScopedJoinHandle};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::stats::{all_stats, find_task, for_each_task, stats, TaskInfo, TaskStats};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::task::{CurrentTask, TaskId, TaskInner, TaskState};
 #[doc(cfg(feature = "multitask"))]
 pub use crate::task_ext::{TaskExtMut, TaskExtRef};
 #[doc(cfg(feature = "multitask"))]
-pub use crate::wait_queue::WaitQueue;
+pub use crate::task_local::TaskLocalKey;
+#[doc(cfg(feature = "multitask"))]
+pub use crate::wait_queue::{wait_any, wait_any_with, WaitAnyResult, WaitQueue, WaitResult};
+
+#[cfg(feature = "latency-trace")]
+pub use crate::latency::{latency_report, reset_latency_report, CpuLatency, LatencyReport};
+#[cfg(feature = "sched_prio")]
+pub use crate::priority::{DEFAULT_PRIORITY, MAX_PRIORITY, MIN_PRIORITY};
+#[cfg(feature = "sched_rt")]
+pub use crate::rt::set_rt_throttle;
+#[cfg(feature = "latency-trace")]
+pub use axhal::latency::LatencyStats;
 
 /// The reference type of a task.
 pub type AxTaskRef = Arc<AxTask>;
@@ -23,14 +40,26 @@ cfg_if::cfg_if! {
     if #[cfg(feature = "sched_rr")] {
         const MAX_TIME_SLICE: usize = 5;
         pub(crate) type AxTask = scheduler::RRTask<TaskInner, MAX_TIME_SLICE>;
-        pub(crate) type Scheduler = scheduler::RRScheduler<TaskInner, MAX_TIME_SLICE>;
+        pub(crate) type NormalScheduler = scheduler::RRScheduler<TaskInner, MAX_TIME_SLICE>;
     } else if #[cfg(feature = "sched_cfs")] {
-        pub(crate) type AxTask = scheduler::CFSTask<TaskInner>;
-        pub(crate) type Scheduler = scheduler::CFScheduler<TaskInner>;
+        pub(crate) type AxTask = crate::cfs::CFSTask<TaskInner>;
+        pub(crate) type NormalScheduler = crate::cfs::CFScheduler<TaskInner>;
+    } else if #[cfg(feature = "sched_prio")] {
+        const MAX_TIME_SLICE: usize = 5;
+        pub(crate) type AxTask = crate::priority::PrioTask<TaskInner, MAX_TIME_SLICE>;
+        pub(crate) type NormalScheduler = crate::priority::PrioScheduler<TaskInner, MAX_TIME_SLICE>;
     } else {
         // If no scheduler features are set, use FIFO as the default.
         pub(crate) type AxTask = scheduler::FifoTask<TaskInner>;
-        pub(crate) type Scheduler = scheduler::FifoScheduler<TaskInner>;
+        pub(crate) type NormalScheduler = scheduler::FifoScheduler<TaskInner>;
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "sched_rt")] {
+        pub(crate) type Scheduler = crate::rt::RtScheduler;
+    } else {
+        pub(crate) type Scheduler = NormalScheduler;
     }
 }
 
@@ -73,30 +102,80 @@ pub fn init_scheduler() {
     info!("Initialize scheduling...");
 
     crate::run_queue::init();
-    #[cfg(feature = "irq")]
-    crate::timers::init();
+    axhal::backtrace::set_stack_bounds_fn(current_stack_range);
+    axhal::trap::register_stack_overflow_handler(handle_stack_overflow);
 
     info!("  use {} scheduler.", Scheduler::scheduler_name());
+    #[cfg(feature = "sched_rt")]
+    info!("  with the real-time FIFO class.");
+}
+
+/// Returns the kernel stack of the current task, used to bound backtraces.
+fn current_stack_range() -> Option<core::ops::Range<usize>> {
+    let range = current_may_uninit()?.kernel_stack_range()?;
+    Some(range.start.as_usize()..range.end.as_usize())
+}
+
+/// Terminates the current task if `vaddr`, where the kernel faults, is in the
+/// guard page below its stack.
+///
+/// The task-local values and the other resources it owns are leaked, as they
+/// may be in a broken state.
+fn handle_stack_overflow(vaddr: axhal::mem::VirtAddr) {
+    let Some(curr) = current_may_uninit() else {
+        return;
+    };
+    if !curr.in_stack_guard(vaddr) {
+        return;
+    }
+    let used = curr.kernel_stack_top().unwrap() - vaddr;
+    error!(
+        "stack overflow in task '{}' ({} bytes used)",
+        curr.name(),
+        used
+    );
+    current_run_queue::<NoPreemptIrqSave>().exit_current(EXIT_STACK_OVERFLOW)
 }
 
 /// Initializes the task scheduler for secondary CPUs.
 pub fn init_scheduler_secondary() {
     crate::run_queue::init_secondary();
-    #[cfg(feature = "irq")]
-    crate::timers::init();
 }
 
+/// The number of calls of [`on_timer_tick`] on all CPUs.
+#[cfg(feature = "irq")]
+static TIMER_TICKS: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
+
 /// Handles periodic timer ticks for the task manager.
 ///
-/// For example, advance scheduler states, checks timed events, etc.
+/// For example, advance scheduler states, checks timed events, etc. With the
+/// `tickless` feature, it also programs the one-shot timer for the next tick
+/// or timed event.
 #[cfg(feature = "irq")]
 #[doc(cfg(feature = "irq"))]
 pub fn on_timer_tick() {
     use kernel_guard::NoOp;
+    TIMER_TICKS.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
     crate::timers::check_events();
     // Since irq and preemption are both disabled here,
     // we can get current run queue with the default `kernel_guard::NoOp`.
-    current_run_queue::<NoOp>().scheduler_timer_tick();
+    let mut rq = current_run_queue::<NoOp>();
+    rq.scheduler_timer_tick();
+    // The idle task needs no ticks, only the timer events wake it up, nor
+    // does a task running alone with the `dyntick` feature.
+    #[cfg(feature = "tickless")]
+    crate::timers::program_next_timer(rq.needs_tick());
+}
+
+/// Returns the number of timer interrupts handled by [`on_timer_tick`] on
+/// all CPUs since boot.
+///
+/// It shows how often the CPUs are woken up, e.g., fewer interrupts per
+/// second while idle with the `tickless` feature.
+#[cfg(feature = "irq")]
+#[doc(cfg(feature = "irq"))]
+pub fn timer_tick_count() -> u64 {
+    TIMER_TICKS.load(core::sync::atomic::Ordering::Relaxed)
 }
 
 /// Adds the given task to the run queue, returns the task reference.
@@ -116,7 +195,101 @@ where
     spawn_task(TaskInner::new(f, name, stack_size))
 }
 
-/// Spawns a new task with the default parameters.
+/// The minimum stack size of the tasks spawned by [`Builder`].
+pub const MIN_STACK_SIZE: usize = 0x4000;
+
+/// A task factory, to spawn tasks with other parameters than the defaults of
+/// [`spawn`].
+///
+/// The name is kept in the task, and shows up in logs, panic messages and
+/// [`stats`].
+///
+/// # Examples
+///
+/// ```no_run
+/// let task = axtask::Builder::new()
+///     .name("net-poll")
+///     .stack_size(256 * 1024)
+///     .nice(10)
+///     .spawn(|| axtask::yield_now());
+/// ```
+#[derive(Debug, Default)]
+pub struct Builder {
+    name: String,
+    stack_size: Option<usize>,
+    nice: Option<isize>,
+    policy: Option<Policy>,
+}
+
+impl Builder {
+    /// Creates a builder with the default parameters of [`spawn`].
+    pub const fn new() -> Self {
+        Self {
+            name: String::new(),
+            stack_size: None,
+            nice: None,
+            policy: None,
+        }
+    }
+
+    /// Sets the name of the task, empty by default.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Sets the stack size of the task, [`axconfig::TASK_STACK_SIZE`] by
+    /// default. It is rounded up to a multiple of the page size.
+    ///
+    /// # Panics
+    ///
+    /// Panics if it is less than [`MIN_STACK_SIZE`].
+    pub fn stack_size(mut self, stack_size: usize) -> Self {
+        assert!(
+            stack_size >= MIN_STACK_SIZE,
+            "stack size {:#x} is less than the minimum {:#x}",
+            stack_size,
+            MIN_STACK_SIZE
+        );
+        self.stack_size = Some(stack_size);
+        self
+    }
+
+    /// Sets the nice value of the task, see [`set_nice`]. It is ignored if
+    /// the scheduler does not take it.
+    pub fn nice(mut self, nice: isize) -> Self {
+        self.nice = Some(nice);
+        self
+    }
+
+    /// Sets the scheduling policy of the task, see [`set_scheduler`]. It is
+    /// ignored if the scheduler does not support it.
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Spawns the task, and returns its reference.
+    pub fn spawn<F>(self, f: F) -> AxTaskRef
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let stack_size = self.stack_size.unwrap_or(axconfig::TASK_STACK_SIZE);
+        let task_ref = TaskInner::new(f, self.name, stack_size).into_arc();
+        if let Some(nice) = self.nice {
+            // Before it is put into any run queue.
+            set_nice(&task_ref, nice);
+        }
+        if let Some(policy) = self.policy {
+            set_scheduler(&task_ref, policy);
+        }
+        select_run_queue::<NoPreemptIrqSave>(&task_ref).add_task(task_ref.clone());
+        task_ref
+    }
+}
+
+/// Spawns a new task with the default parameters, the same as
+/// `Builder::new().spawn(f)`, see [`Builder`].
 ///
 /// The default task name is an empty string. The default task stack size is
 /// [`axconfig::TASK_STACK_SIZE`].
@@ -129,51 +302,158 @@ where
     spawn_raw(f, "".into(), axconfig::TASK_STACK_SIZE)
 }
 
-/// Set the priority for current task.
+/// Set the priority for the given task.
 ///
 /// The range of the priority is dependent on the underlying scheduler. For
 /// example, in the [CFS] scheduler, the priority is the nice value, ranging from
-/// -20 to 19.
+/// -20 to 19. In the priority scheduler (`sched_prio`), lower values are more
+/// urgent, ranging from `MIN_PRIORITY` (-16) to `MAX_PRIORITY` (15).
+///
+/// With the priority scheduler, a ready task is rescheduled at once under the
+/// new priority, preempting the running task if it is now more urgent, and
+/// the priority of a blocked task takes effect when it is woken up.
 ///
 /// Returns `true` if the priority is set successfully.
 ///
 /// [CFS]: https://en.wikipedia.org/wiki/Completely_Fair_Scheduler
-pub fn set_priority(prio: isize) -> bool {
-    current_run_queue::<NoPreemptIrqSave>().set_current_priority(prio)
+pub fn set_priority(task: &AxTaskRef, prio: isize) -> bool {
+    crate::run_queue::set_task_priority(task, prio)
 }
 
-/// Set the affinity for the current task.
-/// [`AxCpuMask`] is used to specify the CPU affinity.
-/// Returns `true` if the affinity is set successfully.
+/// Set the priority for current task, see [`set_priority`].
+pub fn set_current_priority(prio: isize) -> bool {
+    set_priority(current().as_task_ref(), prio)
+}
+
+/// Sets the nice value of the given task, from -20 (the most favorable) to 19
+/// (the least favorable).
 ///
-/// TODO: support set the affinity for other tasks.
-pub fn set_current_affinity(cpumask: AxCpuMask) -> bool {
-    if cpumask.is_empty() {
-        false
-    } else {
-        let curr = current().clone();
+/// With the [CFS] scheduler, the CPU share of the task is weighted by the
+/// nice value, where each step changes it by about 10%. The time the task
+/// has run so far is not reweighted. With other schedulers, it is set as the
+/// priority by [`set_priority`].
+///
+/// Returns `true` if the nice value is set successfully.
+///
+/// [CFS]: https://en.wikipedia.org/wiki/Completely_Fair_Scheduler
+pub fn set_nice(task: &AxTaskRef, nice: isize) -> bool {
+    (-20..=19).contains(&nice) && set_priority(task, nice)
+}
+
+/// Sets the scheduling policy of the given task.
+///
+/// With the `sched_rt` feature, a task of [`Policy::Fifo`] is in the
+/// real-time class, which always runs before the normal class: a ready
+/// real-time task preempts any normal task, or any less urgent real-time
+/// task, at once, on whatever CPU it is put. Real-time tasks of the same
+/// priority run first in first out, without time slices. See
+/// `set_rt_throttle` to keep them from starving the normal tasks.
+///
+/// A ready task is moved to the ready queue of its new class at once, and
+/// the priority set by [`set_priority`] applies again when the task goes
+/// back to [`Policy::Normal`].
+///
+/// Returns `true` if the policy is set successfully, or `false` if the
+/// real-time priority is out of range, or the `sched_rt` feature is not
+/// enabled for a real-time policy.
+pub fn set_scheduler(task: &AxTaskRef, policy: Policy) -> bool {
+    crate::run_queue::set_task_policy(task, policy)
+}
+
+/// Gets the priority of the current task, the one last set by
+/// [`set_priority`], or 0 by default.
+pub fn current_priority() -> isize {
+    current().priority()
+}
+
+/// The maximum number of lock owners a priority boost is passed on to by
+/// [`boost_priority`], which ends the chain of a deadlock cycle.
+const MAX_BOOST_CHAIN: usize = 16;
 
+/// Boosts the given task, which owns the given lock, to the priority until
+/// [`restore_priority`] of the lock, if it is more urgent than the one the
+/// task runs with. The lock is any unique key, e.g., its address.
+///
+/// It is used for priority inheritance by locks: a task blocking on a lock
+/// boosts the owner to its own priority, so that the owner is not held up by
+/// less urgent tasks while holding the lock. If the owner is also waiting for
+/// a lock, recorded by [`set_current_waiting_for`], the boost is passed on to
+/// the owner of that lock, and so on along the chain.
+///
+/// [`set_priority`] on a boosted task takes effect when the boost is removed.
+/// Tasks that have exited are not boosted.
+///
+/// Returns `true` if the task now runs with the priority or a more urgent
+/// one.
+pub fn boost_priority(task: &AxTaskRef, lock: usize, prio: isize) -> bool {
+    if !crate::run_queue::boost_task_priority(task, lock, prio) {
+        return task.effective_priority() <= prio;
+    }
+    let mut waiter = task.clone();
+    for _ in 0..MAX_BOOST_CHAIN {
+        let (owner_id, lock) = waiter.waiting_for();
+        let Some(owner) = crate::stats::find_task(owner_id) else {
+            break;
+        };
+        // The rest of the chain is boosted as well if the owner is.
+        if !crate::run_queue::boost_task_priority(&owner, lock, prio) {
+            break;
+        }
+        waiter = owner;
+    }
+    true
+}
+
+/// Removes the priority boosts of the given task by [`boost_priority`] for
+/// the given lock.
+///
+/// It is called by the owner of a lock when it unlocks. The task keeps the
+/// most urgent boost by the waiters of the other locks it still holds, or
+/// runs with its own priority again if there is none.
+pub fn restore_priority(task: &AxTaskRef, lock: usize) {
+    crate::run_queue::restore_task_priority(task, lock)
+}
+
+/// Records that the current task is waiting for the given lock owned by the
+/// task of `owner_id`, or 0 if it stops waiting, to pass on the priority
+/// boosts by [`boost_priority`] to the owner.
+pub fn set_current_waiting_for(owner_id: u64, lock: usize) {
+    current().set_waiting_for(owner_id, lock);
+}
+
+/// Sets the CPU affinity of the given task.
+/// [`AxCpuMask`] is used to specify the CPU affinity.
+///
+/// The task is only put into the run queues of the allowed CPUs from now on.
+/// If it is the current task and the current CPU is excluded, it is migrated
+/// to an allowed CPU before returning. Otherwise, it leaves an excluded CPU
+/// the next time it is scheduled there, a running one being preempted for it.
+///
+/// Returns `false` if the mask has no online CPUs, in which case the affinity
+/// is unchanged.
+pub fn set_affinity(task: &AxTaskRef, cpumask: AxCpuMask) -> bool {
+    if !crate::run_queue::has_online_cpu(cpumask) {
+        return false;
+    }
+    let curr = current();
+    if curr.ptr_eq(task) {
         curr.set_cpumask(cpumask);
         // After setting the affinity, we need to check if current cpu matches
         // the affinity. If not, we need to migrate the task to the correct CPU.
         #[cfg(feature = "smp")]
-        if !cpumask.get(axhal::cpu::this_cpu_id()) {
-            const MIGRATION_TASK_STACK_SIZE: usize = 4096;
-            // Spawn a new migration task for migrating.
-            let migration_task = TaskInner::new(
-                move || crate::run_queue::migrate_entry(curr),
-                "migration-task".into(),
-                MIGRATION_TASK_STACK_SIZE,
-            )
-            .into_arc();
-
-            // Migrate the current task to the correct CPU using the migration task.
-            current_run_queue::<NoPreemptIrqSave>().migrate_current(migration_task);
-
+        {
+            current_run_queue::<NoPreemptIrqSave>().migrate_current(false);
             assert!(cpumask.get(axhal::cpu::this_cpu_id()), "Migration failed");
         }
-        true
+    } else {
+        crate::run_queue::set_task_affinity(task, cpumask);
     }
+    true
+}
+
+/// Set the affinity for the current task, see [`set_affinity`].
+pub fn set_current_affinity(cpumask: AxCpuMask) -> bool {
+    set_affinity(current().as_task_ref(), cpumask)
 }
 
 /// Current task gives up the CPU time voluntarily, and switches to another
@@ -184,13 +464,19 @@ pub fn yield_now() {
 
 /// Current task is going to sleep for the given duration.
 ///
+/// It returns early if the current task is canceled, see [`cancel`].
+///
 /// If the feature `irq` is not enabled, it uses busy-wait instead.
 pub fn sleep(dur: core::time::Duration) {
-    sleep_until(axhal::time::wall_time() + dur);
+    sleep_until(axhal::time::monotonic_time() + dur);
 }
 
 /// Current task is going to sleep, it will be woken up at the given deadline.
 ///
+/// The deadline is a time of the monotonic clock, so the sleep is not affected
+/// when the wall time is set or adjusted. It returns early if the current task
+/// is canceled, see [`cancel`].
+///
 /// If the feature `irq` is not enabled, it uses busy-wait instead.
 pub fn sleep_until(deadline: axhal::time::TimeValue) {
     #[cfg(feature = "irq")]
@@ -199,19 +485,85 @@ pub fn sleep_until(deadline: axhal::time::TimeValue) {
     axhal::time::busy_wait_until(deadline);
 }
 
+/// Waits for the given task to exit, and returns its exit code, i.e., the
+/// argument of [`exit`], or 0 if it returned from its entry function.
+///
+/// It returns immediately if the task has already exited, the exit code is
+/// kept as long as a reference to the task exists. All tasks joining the same
+/// task get the same exit code. It is [`EXIT_CANCELED`] if the task was
+/// canceled before it exited.
+///
+/// Returns [`Err(JoinError::Deadlock)`](JoinError::Deadlock) at once if the
+/// task is the current task, or [`Err(JoinError::Canceled)`](JoinError::Canceled)
+/// if the current task is canceled while waiting.
+pub fn join(task: &AxTaskRef) -> Result<i32, JoinError> {
+    if current().ptr_eq(task) {
+        return Err(JoinError::Deadlock);
+    }
+    task.join().ok_or(JoinError::Canceled)
+}
+
+/// Why [`join`] failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// The task is the current task, which would wait for itself forever.
+    Deadlock,
+    /// The current task was requested to cancel while waiting, see [`cancel`].
+    Canceled,
+}
+
+/// The exit code of a task that exits after it is canceled, reported by
+/// [`join`] whatever code it exits with.
+pub const EXIT_CANCELED: i32 = i32::MIN;
+
+/// The exit code of a task terminated because of a stack overflow, which is
+/// detected by the guard page below its stack.
+pub const EXIT_STACK_OVERFLOW: i32 = i32::MIN + 1;
+
+/// Requests the given task to cancel.
+///
+/// The cancellation is cooperative: the task is not stopped, but its waits
+/// on [`WaitQueue`]s, [`join`] and [`sleep`] return early from now on, with
+/// [`WaitResult::Canceled`] where they return a result, so that it can back
+/// out and exit. A task blocked in one of them is woken up. It can also poll
+/// [`is_cancel_requested`] in long computations.
+///
+/// Canceling a task that has exited does nothing.
+pub fn cancel(task: &AxTaskRef) {
+    if task.request_cancel() {
+        // Nothing is done if it is not blocked.
+        select_run_queue::<NoPreemptIrqSave>(task).unblock_task(task.clone(), false);
+    }
+}
+
+/// Whether the current task has been requested to cancel by [`cancel`].
+pub fn is_cancel_requested() -> bool {
+    current().is_cancel_requested()
+}
+
 /// Exits the current task.
+///
+/// The task-local values of the task are dropped first.
 pub fn exit(exit_code: i32) -> ! {
+    // Safety: it is the current task, which is exiting.
+    unsafe { crate::task_local::TaskLocals::clear(current().task_locals()) };
     current_run_queue::<NoPreemptIrqSave>().exit_current(exit_code)
 }
 
 /// The idle task routine.
 ///
-/// It runs an infinite loop that keeps calling [`yield_now()`].
+/// It runs an infinite loop that keeps calling [`yield_now()`]. With the `irq`
+/// feature, the CPU sleeps in between until a task is put into its run queue
+/// (woken up by a reschedule IPI from other CPUs) or a timer event is due.
 pub fn run_idle() -> ! {
     loop {
         yield_now();
+        #[cfg(feature = "smp")]
+        if !axhal::cpu::cpu_online(axhal::cpu::this_cpu_id()) {
+            crate::run_queue::offline_current_cpu();
+        }
         debug!("idle task: waiting for IRQs...");
         #[cfg(feature = "irq")]
-        axhal::arch::wait_for_irqs();
+        crate::run_queue::wait_for_tasks();
     }
 }
@@ -0,0 +1,196 @@
+//! The Completely Fair Scheduler (CFS), with nice values.
+//!
+//! Each task accumulates a virtual runtime while running, at a rate inversely
+//! proportional to the weight of its nice value, in the same table as Linux,
+//! and the ready task with the smallest virtual runtime runs next.
+//!
+//! The virtual runtime accumulated so far is kept when the nice value
+//! changes, only the following ticks are weighted by the new one, so a task
+//! gets neither a windfall nor a penalty for its past running time.
+//!
+//! When a task migrates to the scheduler of another CPU, its virtual runtime
+//! is normalized by the smallest ones of the two schedulers, so it keeps its
+//! lead or lag relative to the other tasks.
+
+use alloc::{collections::BTreeMap, sync::Arc};
+use core::ops::Deref;
+use core::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+
+use scheduler::BaseScheduler;
+
+/// The most favorable nice value.
+pub const MIN_NICE: isize = -20;
+/// The least favorable nice value.
+pub const MAX_NICE: isize = 19;
+
+/// The weights of the nice values from -20 to 19, where each step changes the
+/// CPU share by about 10%.
+const NICE_TO_WEIGHT: [isize; 40] = [
+    /* -20 */ 88761, 71755, 56483, 46273, 36291, //
+    /* -15 */ 29154, 23254, 18705, 14949, 11916, //
+    /* -10 */ 9548, 7620, 6100, 4904, 3906, //
+    /*  -5 */ 3121, 2501, 1991, 1586, 1277, //
+    /*   0 */ 1024, 820, 655, 526, 423, //
+    /*   5 */ 335, 272, 215, 172, 137, //
+    /*  10 */ 110, 87, 70, 56, 45, //
+    /*  15 */ 36, 29, 23, 18, 15, //
+];
+
+/// The virtual runtime of a tick at nice 0.
+const TICK_VRUNTIME: isize = 1024;
+
+/// A task wrapper for the [`CFScheduler`].
+pub struct CFSTask<T> {
+    inner: T,
+    vruntime: AtomicIsize,
+    /// The virtual runtime added by each tick, from the nice value.
+    tick_vruntime: AtomicIsize,
+    /// Breaks the ties of the virtual runtime, in the order the tasks are
+    /// put into the scheduler.
+    id: AtomicIsize,
+    /// The ID of the scheduler the task was last picked from, and its
+    /// smallest virtual runtime at that time.
+    sched_id: AtomicUsize,
+    base_vruntime: AtomicIsize,
+}
+
+impl<T> CFSTask<T> {
+    /// Creates a new [`CFSTask`] from the inner task struct, with nice 0.
+    pub const fn new(inner: T) -> Self {
+        Self {
+            inner,
+            vruntime: AtomicIsize::new(0),
+            tick_vruntime: AtomicIsize::new(TICK_VRUNTIME),
+            id: AtomicIsize::new(0),
+            sched_id: AtomicUsize::new(0),
+            base_vruntime: AtomicIsize::new(0),
+        }
+    }
+
+    /// Returns a reference to the inner task struct.
+    pub const fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    fn vruntime(&self) -> isize {
+        self.vruntime.load(Ordering::Acquire)
+    }
+
+    fn key(&self) -> (isize, isize) {
+        (self.vruntime(), self.id.load(Ordering::Acquire))
+    }
+
+    fn set_nice(&self, nice: isize) {
+        let weight = NICE_TO_WEIGHT[(nice - MIN_NICE) as usize];
+        self.tick_vruntime
+            .store(TICK_VRUNTIME * 1024 / weight, Ordering::Release);
+    }
+}
+
+impl<T> Deref for CFSTask<T> {
+    type Target = T;
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+/// The Completely Fair Scheduler.
+///
+/// The priority of a task is its nice value, from [`MIN_NICE`] to
+/// [`MAX_NICE`].
+pub struct CFScheduler<T> {
+    ready_queue: BTreeMap<(isize, isize), Arc<CFSTask<T>>>,
+    /// The smallest virtual runtime of the tasks, which only increases. New
+    /// tasks start from it, and woken tasks catch up with it.
+    min_vruntime: isize,
+    id_pool: isize,
+    /// A unique ID, non-zero, to tell the tasks migrated from other
+    /// schedulers.
+    sched_id: usize,
+}
+
+impl<T> CFScheduler<T> {
+    /// Creates a new empty [`CFScheduler`].
+    pub fn new() -> Self {
+        static SCHED_ID: AtomicUsize = AtomicUsize::new(1);
+        Self {
+            ready_queue: BTreeMap::new(),
+            min_vruntime: 0,
+            id_pool: 0,
+            sched_id: SCHED_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the name of the scheduler.
+    pub fn scheduler_name() -> &'static str {
+        "Completely Fair"
+    }
+
+    /// Brings the virtual runtime of the task to this scheduler, and up to
+    /// the smallest one of it. It keeps its debt if it is ahead, but it does
+    /// not get back the time it did not run while sleeping or waiting on
+    /// another scheduler behind this one.
+    fn catch_up(&self, task: &CFSTask<T>) {
+        let sched_id = task.sched_id.load(Ordering::Acquire);
+        if sched_id != 0 && sched_id != self.sched_id {
+            let base = task.base_vruntime.load(Ordering::Acquire);
+            task.vruntime
+                .fetch_add(self.min_vruntime - base, Ordering::AcqRel);
+        }
+        task.vruntime.fetch_max(self.min_vruntime, Ordering::AcqRel);
+    }
+
+    fn insert(&mut self, task: Arc<CFSTask<T>>) {
+        self.id_pool += 1;
+        task.id.store(self.id_pool, Ordering::Release);
+        self.ready_queue.insert(task.key(), task);
+    }
+}
+
+impl<T> BaseScheduler for CFScheduler<T> {
+    type SchedItem = Arc<CFSTask<T>>;
+
+    fn init(&mut self) {}
+
+    fn add_task(&mut self, task: Self::SchedItem) {
+        self.catch_up(&task);
+        self.insert(task);
+    }
+
+    fn remove_task(&mut self, task: &Self::SchedItem) -> Option<Self::SchedItem> {
+        self.ready_queue.remove(&task.key())
+    }
+
+    fn pick_next_task(&mut self) -> Option<Self::SchedItem> {
+        let (_, task) = self.ready_queue.pop_first()?;
+        self.min_vruntime = self.min_vruntime.max(task.vruntime());
+        task.sched_id.store(self.sched_id, Ordering::Release);
+        task.base_vruntime
+            .store(self.min_vruntime, Ordering::Release);
+        Some(task)
+    }
+
+    fn put_prev_task(&mut self, prev: Self::SchedItem, _preempt: bool) {
+        self.catch_up(&prev);
+        self.insert(prev);
+    }
+
+    fn task_tick(&mut self, current: &Self::SchedItem) -> bool {
+        let delta = current.tick_vruntime.load(Ordering::Acquire);
+        let vruntime = current.vruntime.fetch_add(delta, Ordering::AcqRel) + delta;
+        self.ready_queue
+            .first_key_value()
+            .is_some_and(|(&(min, _), _)| vruntime > min)
+    }
+
+    fn set_priority(&mut self, task: &Self::SchedItem, prio: isize) -> bool {
+        if (MIN_NICE..=MAX_NICE).contains(&prio) {
+            // The key in the ready queue is unchanged.
+            task.set_nice(prio);
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! The report of the regions with preemption or IRQs disabled, traced by
+//! [`axhal::latency`].
+//!
+//! The preemption-off regions are traced where the preemption disable count
+//! of the current task changes between 0 and 1, and the IRQ-off regions also
+//! at the run queue locks, which disable IRQs by [`kernel_guard`].
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use axhal::latency::{self, LatencyKind, LatencyStats, NUM_BUCKETS};
+
+/// The latency statistics of a CPU, see [`latency_report`].
+#[derive(Debug, Clone)]
+pub struct CpuLatency {
+    /// The CPU ID.
+    pub cpu_id: usize,
+    /// The regions with preemption disabled, traced with the `preempt`
+    /// feature only.
+    pub preempt_off: LatencyStats,
+    /// The regions with IRQs disabled.
+    pub irq_off: LatencyStats,
+}
+
+/// The latency statistics of all the CPUs, returned by [`latency_report`].
+///
+/// It is printed as a table by [`Display`](fmt::Display).
+#[derive(Debug, Clone)]
+pub struct LatencyReport {
+    /// The statistics of each CPU, in ascending order of the CPU ID.
+    pub cpus: Vec<CpuLatency>,
+}
+
+/// Returns the statistics of the regions with preemption or IRQs disabled
+/// on each CPU, since the boot or the last [`reset_latency_report`].
+pub fn latency_report() -> LatencyReport {
+    let cpus = (0..axconfig::SMP)
+        .map(|cpu_id| CpuLatency {
+            cpu_id,
+            preempt_off: latency::stats(cpu_id, LatencyKind::PreemptOff),
+            irq_off: latency::stats(cpu_id, LatencyKind::IrqOff),
+        })
+        .collect();
+    LatencyReport { cpus }
+}
+
+/// Clears the statistics of [`latency_report`].
+pub fn reset_latency_report() {
+    latency::reset();
+}
+
+/// Starts an IRQ-off region if a run queue guard disabled IRQs, given whether
+/// they were enabled before, and returns whether it did.
+pub(crate) fn trace_lock_acquired(irqs_were_enabled: bool) -> bool {
+    let traced = irqs_were_enabled && !axhal::arch::irqs_enabled();
+    if traced {
+        latency::trace_off(LatencyKind::IrqOff);
+    }
+    traced
+}
+
+/// Ends the IRQ-off region of a run queue guard before it is released, if it
+/// is started by [`trace_lock_acquired`], as IRQs are enabled again then.
+///
+/// The guard may be released by another task after a context switch, which
+/// ends the region started by the guard of the previous task on this CPU.
+pub(crate) fn trace_lock_released(traced: bool) {
+    if traced {
+        latency::trace_on(LatencyKind::IrqOff);
+    }
+}
+
+fn fmt_stats(
+    f: &mut fmt::Formatter,
+    cpu_id: usize,
+    kind: &str,
+    stats: &LatencyStats,
+) -> fmt::Result {
+    writeln!(
+        f,
+        "{:>3} {:<11} {:>10} {:>10}",
+        cpu_id,
+        kind,
+        stats.count,
+        stats.max.as_micros()
+    )?;
+    for &addr in stats.max_callers.iter().take_while(|&&addr| addr != 0) {
+        match axhal::backtrace::lookup_symbol(addr) {
+            Some((name, offset)) => writeln!(f, "      at {:#x} {}+{:#x}", addr, name, offset)?,
+            None => writeln!(f, "      at {:#x}", addr)?,
+        }
+    }
+    write!(f, "      us:")?;
+    for (i, &count) in stats.histogram.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        match i {
+            0 => write!(f, " <1:{}", count)?,
+            _ if i == NUM_BUCKETS - 1 => write!(f, " >={}:{}", 1u64 << (i - 1), count)?,
+            _ => write!(f, " {}:{}", 1u64 << (i - 1), count)?,
+        }
+    }
+    writeln!(f)
+}
+
+impl fmt::Display for LatencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>3} {:<11} {:>10} {:>10}",
+            "CPU", "KIND", "COUNT", "MAX(us)"
+        )?;
+        for cpu in &self.cpus {
+            fmt_stats(f, cpu.cpu_id, "preempt-off", &cpu.preempt_off)?;
+            fmt_stats(f, cpu.cpu_id, "irq-off", &cpu.irq_off)?;
+        }
+        Ok(())
+    }
+}
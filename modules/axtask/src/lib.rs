@@ -10,28 +10,41 @@
 //!   management and scheduling is used, as well as more task-related APIs.
 //!   Otherwise, only a few APIs with naive implementation is available.
 //! - `irq`: Interrupts are enabled. If this feature is enabled, timer-based
-//!    APIs can be used, such as [`sleep`], [`sleep_until`], and
-//!    [`WaitQueue::wait_timeout`].
+//!    APIs can be used, such as [`sleep`], [`sleep_until`],
+//!    [`WaitQueue::wait_timeout`], and the callbacks of [`timers`].
+//! - `tickless`: Program the one-shot timer for the next timed event instead
+//!    of ticking periodically while the CPU is idle. Scheduler ticks still
+//!    come periodically while tasks run. It also enables the `irq` feature.
+//! - `dyntick`: Also stop the scheduler ticks while a CPU runs a single task,
+//!    until another task is ready to run on it. It also enables the
+//!    `tickless` feature.
 //! - `preempt`: Enable preemptive scheduling.
+//! - `latency-trace`: Trace the regions with preemption (if the `preempt`
+//!    feature is enabled) or IRQs disabled, see `latency_report`. It also
+//!    enables the `multitask` feature.
 //! - `sched_fifo`: Use the [FIFO cooperative scheduler][1]. It also enables the
 //!   `multitask` feature if it is enabled. This feature is enabled by default,
 //!   and it can be overriden by other scheduler features.
 //! - `sched_rr`: Use the [Round-robin preemptive scheduler][2]. It also enables
 //!   the `multitask` and `preempt` features if it is enabled.
-//! - `sched_cfs`: Use the [Completely Fair Scheduler][3]. It also enables the
-//!   the `multitask` and `preempt` features if it is enabled.
+//! - `sched_cfs`: Use the [Completely Fair Scheduler][3], with nice values
+//!   set by [`set_nice`]. It also enables the `multitask` and `preempt`
+//!   features if it is enabled.
+//! - `sched_prio`: Use the strict priority preemptive scheduler, with
+//!   round-robin within each priority. It also enables the `multitask` and
+//!   `preempt` features if it is enabled.
+//! - `sched_rt`: Add the real-time FIFO scheduling class above the scheduler
+//!   chosen by the other features, see [`set_scheduler`]. It also enables the
+//!   `multitask` and `preempt` features if it is enabled.
 //!
 //! [1]: scheduler::FifoScheduler
 //! [2]: scheduler::RRScheduler
-//! [3]: scheduler::CFScheduler
+//! [3]: https://en.wikipedia.org/wiki/Completely_Fair_Scheduler
 
 #![cfg_attr(not(test), no_std)]
 #![feature(doc_cfg)]
 #![feature(doc_auto_cfg)]
 #![feature(linkage)]
-#![feature(const_mut_refs)]
-#![feature(const_ptr_is_null)]
-#![feature(const_unsafecell_get_mut)]
 
 #[cfg(test)]
 mod tests;
@@ -46,11 +59,23 @@ cfg_if::cfg_if! {
         mod run_queue;
         mod task;
         mod task_ext;
+        mod task_local;
+        mod stats;
         mod api;
         mod wait_queue;
+        mod rt;
+        mod scope;
+
+        pub mod workqueue;
 
         #[cfg(feature = "irq")]
-        mod timers;
+        pub mod timers;
+        #[cfg(feature = "latency-trace")]
+        mod latency;
+        #[cfg(feature = "sched_cfs")]
+        mod cfs;
+        #[cfg(feature = "sched_prio")]
+        mod priority;
 
         #[doc(cfg(feature = "multitask"))]
         pub use self::api::*;
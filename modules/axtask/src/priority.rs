@@ -0,0 +1,177 @@
+//! A strict priority preemptive scheduler.
+//!
+//! The ready tasks of the most urgent priority always run first, and the
+//! tasks of the same priority are scheduled in round-robin, each running for
+//! at most `S` ticks in a row. A task preempted before its time slice is used
+//! up goes back to the front of its queue.
+
+use alloc::{collections::VecDeque, sync::Arc};
+use core::ops::Deref;
+use core::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+
+use scheduler::BaseScheduler;
+
+/// The most urgent priority.
+pub const MIN_PRIORITY: isize = -16;
+/// The least urgent priority.
+pub const MAX_PRIORITY: isize = 15;
+/// The priority of new tasks.
+pub const DEFAULT_PRIORITY: isize = 0;
+
+const NUM_LEVELS: usize = (MAX_PRIORITY - MIN_PRIORITY + 1) as usize;
+
+/// Returns the level of the ready queue of the priority, 0 for the most
+/// urgent one.
+const fn level_of(prio: isize) -> usize {
+    (prio - MIN_PRIORITY) as usize
+}
+
+/// A task wrapper for the [`PrioScheduler`].
+///
+/// `S` is the maximum number of ticks the task runs before the other tasks of
+/// the same priority run.
+pub struct PrioTask<T, const S: usize> {
+    inner: T,
+    prio: AtomicIsize,
+    /// The level of the ready queue the task is in, written by the scheduler
+    /// holding it.
+    level: AtomicUsize,
+    time_slice: AtomicIsize,
+}
+
+impl<T, const S: usize> PrioTask<T, S> {
+    /// Creates a new [`PrioTask`] from the inner task struct, with the
+    /// [`DEFAULT_PRIORITY`].
+    pub const fn new(inner: T) -> Self {
+        Self {
+            inner,
+            prio: AtomicIsize::new(DEFAULT_PRIORITY),
+            level: AtomicUsize::new(level_of(DEFAULT_PRIORITY)),
+            time_slice: AtomicIsize::new(S as isize),
+        }
+    }
+
+    /// Returns a reference to the inner task struct.
+    pub const fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    fn level(&self) -> usize {
+        level_of(self.prio.load(Ordering::Acquire))
+    }
+}
+
+impl<T, const S: usize> Deref for PrioTask<T, S> {
+    type Target = T;
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+/// A strict priority scheduler, with round-robin between the tasks of the
+/// same priority.
+///
+/// Priorities range from [`MIN_PRIORITY`] (the most urgent) to
+/// [`MAX_PRIORITY`]. A new priority of a ready task moves it to the back of
+/// the queue of the new priority, and that of another task takes effect when
+/// it is put back into the scheduler.
+pub struct PrioScheduler<T, const S: usize> {
+    ready_queues: [VecDeque<Arc<PrioTask<T, S>>>; NUM_LEVELS],
+    /// Bit `i` is set if the ready queue of level `i` is not empty.
+    ready_levels: u32,
+}
+
+impl<T, const S: usize> PrioScheduler<T, S> {
+    /// Creates a new empty [`PrioScheduler`].
+    pub fn new() -> Self {
+        Self {
+            ready_queues: core::array::from_fn(|_| VecDeque::new()),
+            ready_levels: 0,
+        }
+    }
+
+    /// Returns the name of the scheduler.
+    pub fn scheduler_name() -> &'static str {
+        "Priority"
+    }
+
+    /// Whether a ready task is more urgent than `current`.
+    pub fn outranks(&self, current: &Arc<PrioTask<T, S>>) -> bool {
+        self.ready_levels != 0 && (self.ready_levels.trailing_zeros() as usize) < current.level()
+    }
+
+    fn push(&mut self, task: Arc<PrioTask<T, S>>, front: bool) {
+        let level = task.level();
+        task.level.store(level, Ordering::Relaxed);
+        if front {
+            self.ready_queues[level].push_front(task);
+        } else {
+            self.ready_queues[level].push_back(task);
+        }
+        self.ready_levels |= 1 << level;
+    }
+
+    fn update_ready_levels(&mut self, level: usize) {
+        if self.ready_queues[level].is_empty() {
+            self.ready_levels &= !(1 << level);
+        }
+    }
+}
+
+impl<T, const S: usize> BaseScheduler for PrioScheduler<T, S> {
+    type SchedItem = Arc<PrioTask<T, S>>;
+
+    fn init(&mut self) {}
+
+    fn add_task(&mut self, task: Self::SchedItem) {
+        self.push(task, false);
+    }
+
+    fn remove_task(&mut self, task: &Self::SchedItem) -> Option<Self::SchedItem> {
+        // The level is only meaningful if the task is in this scheduler, in
+        // which case it is not found otherwise.
+        let level = task.level.load(Ordering::Relaxed);
+        let queue = &mut self.ready_queues[level];
+        let idx = queue.iter().position(|t| Arc::ptr_eq(t, task))?;
+        let task = queue.remove(idx);
+        self.update_ready_levels(level);
+        task
+    }
+
+    fn pick_next_task(&mut self) -> Option<Self::SchedItem> {
+        if self.ready_levels == 0 {
+            return None;
+        }
+        let level = self.ready_levels.trailing_zeros() as usize;
+        let task = self.ready_queues[level].pop_front();
+        self.update_ready_levels(level);
+        task
+    }
+
+    fn put_prev_task(&mut self, prev: Self::SchedItem, preempt: bool) {
+        if prev.time_slice.load(Ordering::Acquire) > 0 && preempt {
+            self.push(prev, true);
+        } else {
+            prev.time_slice.store(S as isize, Ordering::Release);
+            self.push(prev, false);
+        }
+    }
+
+    fn task_tick(&mut self, current: &Self::SchedItem) -> bool {
+        let old_slice = current.time_slice.fetch_sub(1, Ordering::Release);
+        old_slice <= 1
+    }
+
+    fn set_priority(&mut self, task: &Self::SchedItem, prio: isize) -> bool {
+        if !(MIN_PRIORITY..=MAX_PRIORITY).contains(&prio) {
+            return false;
+        }
+        let queued = self.remove_task(task);
+        task.prio.store(prio, Ordering::Release);
+        if let Some(task) = queued {
+            self.push(task, false);
+        }
+        true
+    }
+}
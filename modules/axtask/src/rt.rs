@@ -0,0 +1,262 @@
+//! The real-time scheduling class, above the normal one.
+//!
+//! A task of the real-time class, set by [`set_scheduler`] with
+//! [`Policy::Fifo`], always runs before the tasks of the normal class, which
+//! are scheduled by the scheduler chosen by the other features. Real-time
+//! tasks are scheduled by their real-time priorities, first in first out
+//! within each priority, like `SCHED_FIFO` of POSIX: a task runs until it
+//! blocks, yields, or a more urgent real-time task becomes ready, which
+//! preempts it at once, even from another CPU. A preempted task stays at the
+//! front of its queue.
+//!
+//! As a real-time task spinning forever would lock the normal tasks out of
+//! its CPU, the share of the CPU time of the real-time class can be limited
+//! by [`set_rt_throttle`].
+//!
+//! [`set_scheduler`]: crate::set_scheduler
+
+#[cfg(feature = "sched_rt")]
+use alloc::{collections::VecDeque, sync::Arc};
+#[cfg(feature = "sched_rt")]
+use core::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "sched_rt")]
+use core::time::Duration;
+
+#[cfg(feature = "sched_rt")]
+use scheduler::BaseScheduler;
+
+#[cfg(feature = "sched_rt")]
+use crate::{AxTaskRef, NormalScheduler};
+
+/// The least urgent real-time priority.
+pub const MIN_RT_PRIORITY: u8 = 1;
+/// The most urgent real-time priority.
+pub const MAX_RT_PRIORITY: u8 = 99;
+
+/// The scheduling policy of a task, see [`set_scheduler`](crate::set_scheduler).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// The normal class, scheduled by the scheduler chosen by the features,
+    /// with the priority set by [`set_priority`](crate::set_priority).
+    Normal,
+    /// The real-time class, first in first out within the real-time priority,
+    /// from [`MIN_RT_PRIORITY`] to [`MAX_RT_PRIORITY`] (the most urgent).
+    ///
+    /// It needs the `sched_rt` feature.
+    Fifo(u8),
+}
+
+impl Policy {
+    /// Returns the policy of the real-time priority, 0 for the normal class.
+    pub(crate) const fn from_rt_priority(prio: u8) -> Self {
+        if prio == 0 {
+            Self::Normal
+        } else {
+            Self::Fifo(prio)
+        }
+    }
+
+    /// Returns the real-time priority, 0 for the normal class.
+    pub(crate) const fn rt_priority(self) -> u8 {
+        match self {
+            Self::Normal => 0,
+            Self::Fifo(prio) => prio,
+        }
+    }
+
+    /// Whether the policy is supported by the scheduler.
+    pub(crate) const fn is_supported(self) -> bool {
+        match self {
+            Self::Normal => true,
+            Self::Fifo(prio) => {
+                cfg!(feature = "sched_rt") && MIN_RT_PRIORITY <= prio && prio <= MAX_RT_PRIORITY
+            }
+        }
+    }
+}
+
+/// The runtime of the real-time class in each period, both in ticks, as
+/// `runtime << 32 | period`, or 0 if it is not throttled.
+#[cfg(feature = "sched_rt")]
+static RT_THROTTLE: AtomicU64 = AtomicU64::new(0);
+
+/// Limits the real-time tasks to run for at most `runtime` in each `period`
+/// on each CPU, leaving the rest of the period to the normal tasks if any
+/// are ready, or lifts the limit if `runtime` is not shorter than `period`,
+/// which is the default.
+///
+/// The time is counted in timer ticks while the CPU is not idle, so both are
+/// rounded down to ticks. Returns `false` if `period` is shorter than a tick,
+/// or longer than [`u32::MAX`] ticks.
+#[cfg(feature = "sched_rt")]
+pub fn set_rt_throttle(runtime: Duration, period: Duration) -> bool {
+    let to_ticks = |dur: Duration| dur.as_nanos() / axhal::time::tick_nanos() as u128;
+    let (runtime, period) = (to_ticks(runtime), to_ticks(period));
+    if period == 0 || period > u32::MAX as u128 {
+        return false;
+    }
+    let throttle = if runtime < period {
+        (runtime as u64) << 32 | period as u64
+    } else {
+        0
+    };
+    RT_THROTTLE.store(throttle, Ordering::Relaxed);
+    true
+}
+
+/// Returns the runtime of the real-time class in each period, in ticks.
+#[cfg(feature = "sched_rt")]
+fn rt_throttle() -> Option<(usize, usize)> {
+    let throttle = RT_THROTTLE.load(Ordering::Relaxed);
+    (throttle != 0).then_some(((throttle >> 32) as usize, throttle as u32 as usize))
+}
+
+/// A scheduler of the real-time class, with the scheduler of the normal class
+/// below it.
+#[cfg(feature = "sched_rt")]
+pub(crate) struct RtScheduler {
+    /// The ready queues of the real-time priorities, indexed by the priority.
+    ready_queues: [VecDeque<AxTaskRef>; MAX_RT_PRIORITY as usize + 1],
+    /// Bit `i` is set if the ready queue of priority `i` is not empty.
+    ready_levels: u128,
+    normal: NormalScheduler,
+    /// The ticks in the current throttling period, and the ones run by the
+    /// real-time tasks among them.
+    period_ticks: usize,
+    rt_ticks: usize,
+}
+
+#[cfg(feature = "sched_rt")]
+impl RtScheduler {
+    pub fn new() -> Self {
+        Self {
+            ready_queues: core::array::from_fn(|_| VecDeque::new()),
+            ready_levels: 0,
+            normal: NormalScheduler::new(),
+            period_ticks: 0,
+            rt_ticks: 0,
+        }
+    }
+
+    /// Returns the name of the scheduler of the normal class.
+    pub fn scheduler_name() -> &'static str {
+        NormalScheduler::scheduler_name()
+    }
+
+    /// Returns the most urgent real-time priority of the ready tasks.
+    fn top_priority(&self) -> Option<u8> {
+        (self.ready_levels != 0).then(|| (127 - self.ready_levels.leading_zeros()) as u8)
+    }
+
+    /// Whether the real-time class has used up its runtime in this period.
+    fn throttled(&self) -> bool {
+        rt_throttle().is_some_and(|(runtime, _)| self.rt_ticks >= runtime)
+    }
+
+    /// Whether a ready task is to preempt `current` at once, i.e., a
+    /// real-time task more urgent than it, unless throttled.
+    pub fn outranks(&self, current: &AxTaskRef) -> bool {
+        let curr_prio = current.rt_priority();
+        match self.top_priority() {
+            Some(prio) if !self.throttled() => prio > curr_prio,
+            #[cfg(feature = "sched_prio")]
+            _ if curr_prio == 0 => self.normal.outranks(current),
+            _ => false,
+        }
+    }
+
+    /// Puts the task into the ready queue of its real-time priority, or gives
+    /// it back if it is in the normal class.
+    fn push_rt(&mut self, task: AxTaskRef, front: bool) -> Result<(), AxTaskRef> {
+        let prio = task.rt_priority();
+        task.set_rt_level(prio);
+        if prio == 0 {
+            return Err(task);
+        }
+        let queue = &mut self.ready_queues[prio as usize];
+        if front {
+            queue.push_front(task);
+        } else {
+            queue.push_back(task);
+        }
+        self.ready_levels |= 1 << prio;
+        Ok(())
+    }
+
+    fn pop_rt(&mut self) -> Option<AxTaskRef> {
+        let level = self.top_priority()? as usize;
+        let task = self.ready_queues[level].pop_front();
+        self.update_ready_levels(level);
+        task
+    }
+
+    fn update_ready_levels(&mut self, level: usize) {
+        if self.ready_queues[level].is_empty() {
+            self.ready_levels &= !(1 << level);
+        }
+    }
+}
+
+#[cfg(feature = "sched_rt")]
+impl BaseScheduler for RtScheduler {
+    type SchedItem = AxTaskRef;
+
+    fn init(&mut self) {
+        self.normal.init();
+    }
+
+    fn add_task(&mut self, task: Self::SchedItem) {
+        if let Err(task) = self.push_rt(task, false) {
+            self.normal.add_task(task);
+        }
+    }
+
+    fn remove_task(&mut self, task: &Self::SchedItem) -> Option<Self::SchedItem> {
+        // The level is only meaningful if the task is in this scheduler, in
+        // which case it is not found otherwise.
+        let level = task.rt_level() as usize;
+        if level == 0 {
+            return self.normal.remove_task(task);
+        }
+        let queue = &mut self.ready_queues[level];
+        let idx = queue.iter().position(|t| Arc::ptr_eq(t, task))?;
+        let task = queue.remove(idx);
+        self.update_ready_levels(level);
+        task
+    }
+
+    fn pick_next_task(&mut self) -> Option<Self::SchedItem> {
+        if self.ready_levels != 0 && !self.throttled() {
+            return self.pop_rt();
+        }
+        // A throttled real-time task still runs rather than the idle task.
+        self.normal.pick_next_task().or_else(|| self.pop_rt())
+    }
+
+    fn put_prev_task(&mut self, prev: Self::SchedItem, preempt: bool) {
+        if let Err(prev) = self.push_rt(prev, preempt) {
+            self.normal.put_prev_task(prev, preempt);
+        }
+    }
+
+    fn task_tick(&mut self, current: &Self::SchedItem) -> bool {
+        let is_rt = current.rt_priority() != 0;
+        if let Some((_, period)) = rt_throttle() {
+            self.rt_ticks += is_rt as usize;
+            self.period_ticks += 1;
+            if self.period_ticks >= period {
+                self.period_ticks = 0;
+                self.rt_ticks = 0;
+            }
+        }
+        if is_rt {
+            // Let the normal tasks run if any, the time slices do not apply.
+            return self.throttled();
+        }
+        self.normal.task_tick(current) || self.outranks(current)
+    }
+
+    fn set_priority(&mut self, task: &Self::SchedItem, prio: isize) -> bool {
+        self.normal.set_priority(task, prio)
+    }
+}
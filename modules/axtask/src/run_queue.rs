@@ -1,12 +1,17 @@
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::mem::MaybeUninit;
+#[cfg(any(feature = "smp", feature = "dyntick"))]
+use core::sync::atomic::AtomicUsize;
+#[cfg(any(feature = "irq", feature = "smp"))]
+use core::sync::atomic::{AtomicBool, Ordering};
 
 #[cfg(feature = "smp")]
 use alloc::sync::Weak;
 
 use kernel_guard::BaseGuard;
-use kspin::SpinRaw;
+use kspin::{SpinNoIrq, SpinRaw};
 use lazyinit::LazyInit;
 use scheduler::BaseScheduler;
 
@@ -14,7 +19,7 @@ use axhal::cpu::this_cpu_id;
 
 use crate::task::{CurrentTask, TaskState};
 use crate::wait_queue::WaitQueueGuard;
-use crate::{AxCpuMask, AxTaskRef, Scheduler, TaskInner, WaitQueue};
+use crate::{AxCpuMask, AxTaskRef, Policy, Scheduler, TaskInner, WaitQueue};
 
 macro_rules! percpu_static {
     ($(
@@ -66,11 +71,15 @@ const ARRAY_REPEAT_VALUE: MaybeUninit<&'static mut AxRunQueue> = MaybeUninit::un
 /// * [`CurrentRunQueueRef`] - a static reference to the current [`AxRunQueue`].
 #[inline(always)]
 pub(crate) fn current_run_queue<G: BaseGuard>() -> CurrentRunQueueRef<'static, G> {
+    #[cfg(feature = "latency-trace")]
+    let irqs_enabled = axhal::arch::irqs_enabled();
     let irq_state = G::acquire();
     CurrentRunQueueRef {
         inner: unsafe { RUN_QUEUE.current_ref_mut_raw() },
         current_task: crate::current(),
         state: irq_state,
+        #[cfg(feature = "latency-trace")]
+        irq_off_traced: crate::latency::trace_lock_acquired(irqs_enabled),
         _phantom: core::marker::PhantomData,
     }
 }
@@ -78,7 +87,8 @@ pub(crate) fn current_run_queue<G: BaseGuard>() -> CurrentRunQueueRef<'static, G
 /// Selects the run queue index based on a CPU set bitmap and load balancing.
 ///
 /// This function filters the available run queues based on the provided `cpumask` and
-/// selects the run queue index for the next task. The selection is based on a round-robin algorithm.
+/// selects the least loaded one, see [`AxRunQueue::load`]. The ties are broken in a
+/// round-robin way, so that a burst of spawns spreads over the idle CPUs.
 ///
 /// ## Arguments
 ///
@@ -97,18 +107,22 @@ pub(crate) fn current_run_queue<G: BaseGuard>() -> CurrentRunQueueRef<'static, G
 #[allow(clippy::modulo_one)]
 #[inline]
 fn select_run_queue_index(cpumask: AxCpuMask) -> usize {
-    use core::sync::atomic::{AtomicUsize, Ordering};
     static RUN_QUEUE_INDEX: AtomicUsize = AtomicUsize::new(0);
 
     assert!(!cpumask.is_empty(), "No available CPU for task execution");
 
-    // Round-robin selection of the run queue index.
-    loop {
-        let index = RUN_QUEUE_INDEX.fetch_add(1, Ordering::SeqCst) % axconfig::SMP;
-        if cpumask.get(index) {
-            return index;
-        }
+    // Tasks that can only run on offline CPUs wait on the run queue of their
+    // first CPU, until it is online again.
+    if !has_online_cpu(cpumask) {
+        return cpumask.first_index().unwrap();
     }
+
+    let start = RUN_QUEUE_INDEX.fetch_add(1, Ordering::Relaxed);
+    (0..axconfig::SMP)
+        .map(|i| (start + i) % axconfig::SMP)
+        .filter(|&index| cpumask.get(index) && axhal::cpu::cpu_online(index))
+        .min_by_key(|&index| get_run_queue(index).load())
+        .unwrap()
 }
 
 /// Retrieves a `'static` reference to the run queue corresponding to the given index.
@@ -128,12 +142,29 @@ fn select_run_queue_index(cpumask: AxCpuMask) -> usize {
 ///
 /// This function will panic if the index is out of bounds.
 ///
-#[cfg(feature = "smp")]
 #[inline]
 fn get_run_queue(index: usize) -> &'static mut AxRunQueue {
     unsafe { RUN_QUEUES[index].assume_init_mut() }
 }
 
+/// Returns the run queues of the online CPUs.
+fn online_run_queues() -> impl Iterator<Item = &'static mut AxRunQueue> {
+    #[cfg(feature = "smp")]
+    let cpus = (0..axconfig::SMP).filter(|&i| axhal::cpu::cpu_online(i));
+    #[cfg(not(feature = "smp"))]
+    let cpus = core::iter::once(this_cpu_id());
+    cpus.map(get_run_queue)
+}
+
+/// Whether the CPU affinity allows an online CPU.
+pub(crate) fn has_online_cpu(cpumask: AxCpuMask) -> bool {
+    #[cfg(feature = "smp")]
+    let online = |i| axhal::cpu::cpu_online(i);
+    #[cfg(not(feature = "smp"))]
+    let online = |i| i == this_cpu_id();
+    (0..axconfig::SMP).any(|i| cpumask.get(i) && online(i))
+}
+
 /// Selects the appropriate run queue for the provided task.
 ///
 /// * In a single-core system, this function always returns a reference to the global run queue.
@@ -147,14 +178,13 @@ fn get_run_queue(index: usize) -> &'static mut AxRunQueue {
 ///
 /// * [`AxRunQueueRef`] - a static reference to the selected [`AxRunQueue`] (current or remote).
 ///
-/// ## TODO
-///
-/// 1. Implement better load balancing across CPUs for more efficient task distribution.
-/// 2. Use a more generic load balancing algorithm that can be customized or replaced.
-///
 #[inline]
 pub(crate) fn select_run_queue<G: BaseGuard>(task: &AxTaskRef) -> AxRunQueueRef<'static, G> {
+    #[cfg(feature = "latency-trace")]
+    let irqs_enabled = axhal::arch::irqs_enabled();
     let irq_state = G::acquire();
+    #[cfg(feature = "latency-trace")]
+    let irq_off_traced = crate::latency::trace_lock_acquired(irqs_enabled);
     #[cfg(not(feature = "smp"))]
     {
         let _ = task;
@@ -162,6 +192,8 @@ pub(crate) fn select_run_queue<G: BaseGuard>(task: &AxTaskRef) -> AxRunQueueRef<
         AxRunQueueRef {
             inner: unsafe { RUN_QUEUE.current_ref_mut_raw() },
             state: irq_state,
+            #[cfg(feature = "latency-trace")]
+            irq_off_traced,
             _phantom: core::marker::PhantomData,
         }
     }
@@ -172,6 +204,8 @@ pub(crate) fn select_run_queue<G: BaseGuard>(task: &AxTaskRef) -> AxRunQueueRef<
         AxRunQueueRef {
             inner: get_run_queue(index),
             state: irq_state,
+            #[cfg(feature = "latency-trace")]
+            irq_off_traced,
             _phantom: core::marker::PhantomData,
         }
     }
@@ -185,8 +219,39 @@ pub(crate) struct AxRunQueue {
     /// Since irq and preempt are preserved by the kernel guard hold by `AxRunQueueRef`,
     /// we just use a simple raw spin lock here.
     scheduler: SpinRaw<Scheduler>,
+    /// Whether tasks have been put into the scheduler since the idle task
+    /// last checked it, see [`wait_for_tasks`].
+    #[cfg(feature = "irq")]
+    wakeup_pending: AtomicBool,
+    /// Whether the idle task is about to sleep or sleeping, so that a
+    /// reschedule IPI is needed to wake it up.
+    #[cfg(feature = "irq")]
+    idle: AtomicBool,
+    /// The number of ready tasks in the scheduler, read by other CPUs to
+    /// balance the load, and to decide whether the CPU needs ticks.
+    #[cfg(any(feature = "smp", feature = "dyntick"))]
+    nr_ready: AtomicUsize,
+    /// Whether the scheduler ticks are stopped while the CPU runs a single
+    /// task, see [`CurrentRunQueueRef::needs_tick`].
+    #[cfg(feature = "dyntick")]
+    tick_stopped: AtomicBool,
+    /// Whether the current task of the CPU is the idle task.
+    #[cfg(feature = "smp")]
+    curr_idle: AtomicBool,
+    /// The timer ticks since the CPU is online, to balance the load every
+    /// [`BALANCE_INTERVAL`] ticks.
+    #[cfg(all(feature = "smp", feature = "irq"))]
+    ticks: AtomicUsize,
 }
 
+/// The interval in timer ticks to check the imbalance of the run queues.
+#[cfg(all(feature = "smp", feature = "irq"))]
+const BALANCE_INTERVAL: usize = 4;
+/// The difference of the loads of two run queues above which ready tasks are
+/// moved from the busier one on the periodic check.
+#[cfg(all(feature = "smp", feature = "irq"))]
+const BALANCE_THRESHOLD: usize = 2;
+
 /// A reference to the run queue with specific guard.
 ///
 /// Note:
@@ -197,11 +262,17 @@ pub(crate) struct AxRunQueue {
 pub(crate) struct AxRunQueueRef<'a, G: BaseGuard> {
     inner: &'a mut AxRunQueue,
     state: G::State,
+    /// Whether the guard disabled IRQs, which are enabled again when it is
+    /// released, as a traced IRQ-off region.
+    #[cfg(feature = "latency-trace")]
+    irq_off_traced: bool,
     _phantom: core::marker::PhantomData<G>,
 }
 
 impl<'a, G: BaseGuard> Drop for AxRunQueueRef<'a, G> {
     fn drop(&mut self) {
+        #[cfg(feature = "latency-trace")]
+        crate::latency::trace_lock_released(self.irq_off_traced);
         G::release(self.state);
     }
 }
@@ -215,11 +286,16 @@ pub(crate) struct CurrentRunQueueRef<'a, G: BaseGuard> {
     inner: &'a mut AxRunQueue,
     current_task: CurrentTask,
     state: G::State,
+    /// See [`AxRunQueueRef`].
+    #[cfg(feature = "latency-trace")]
+    irq_off_traced: bool,
     _phantom: core::marker::PhantomData<G>,
 }
 
 impl<'a, G: BaseGuard> Drop for CurrentRunQueueRef<'a, G> {
     fn drop(&mut self) {
+        #[cfg(feature = "latency-trace")]
+        crate::latency::trace_lock_released(self.irq_off_traced);
         G::release(self.state);
     }
 }
@@ -236,7 +312,8 @@ impl<'a, G: BaseGuard> AxRunQueueRef<'a, G> {
             self.inner.cpu_id
         );
         assert!(task.is_ready());
-        self.inner.scheduler.lock().add_task(task);
+        self.inner.sched_add(task);
+        self.inner.kick();
     }
 
     /// Unblock one task by inserting it into the run queue.
@@ -276,12 +353,49 @@ impl<'a, G: BaseGuard> CurrentRunQueueRef<'a, G> {
             #[cfg(feature = "preempt")]
             curr.set_preempt_pending(true);
         }
+        // Let the idle task run to take the CPU offline.
+        #[cfg(all(feature = "smp", feature = "preempt"))]
+        if !curr.is_idle() && !axhal::cpu::cpu_online(self.inner.cpu_id) {
+            curr.set_preempt_pending(true);
+        }
+        #[cfg(feature = "smp")]
+        if self.inner.ticks.fetch_add(1, Ordering::Relaxed) % BALANCE_INTERVAL == 0 {
+            self.inner.balance();
+        }
+    }
+
+    /// Whether the CPU needs the next scheduler tick, i.e., it is not idle,
+    /// and other tasks are ready to run on it with the `dyntick` feature.
+    ///
+    /// With `dyntick`, the ticks are marked as stopped if not needed, and
+    /// resumed by the next task put into this run queue.
+    #[cfg(feature = "tickless")]
+    pub fn needs_tick(&self) -> bool {
+        if self.current_task.is_idle() {
+            return false;
+        }
+        #[cfg(feature = "dyntick")]
+        {
+            // Pairs with `kick()`: either we see the ready task, or it sees
+            // the ticks stopped and resumes them.
+            self.inner.tick_stopped.store(true, Ordering::SeqCst);
+            if self.inner.nr_ready.load(Ordering::SeqCst) == 0 {
+                return false;
+            }
+            self.inner.tick_stopped.store(false, Ordering::SeqCst);
+        }
+        true
     }
 
     /// Yield the current task and reschedule.
     /// This function will put the current task into this run queue with `Ready` state,
     /// and reschedule to the next task on this run queue.
     pub fn yield_current(&mut self) {
+        // Leave this CPU instead if it is no longer allowed.
+        #[cfg(feature = "smp")]
+        if self.migrate_current(false) {
+            return;
+        }
         let curr = &self.current_task;
         trace!("task yield: {}", curr.id_name());
         assert!(curr.is_running());
@@ -289,28 +403,46 @@ impl<'a, G: BaseGuard> CurrentRunQueueRef<'a, G> {
         self.inner
             .put_task_with_state(curr.clone(), TaskState::Running, false);
 
-        self.inner.resched();
+        self.inner.resched(false);
     }
 
-    /// Migrate the current task to a new run queue matching its CPU affinity and reschedule.
+    /// Migrate the current task to a new run queue matching its CPU affinity and reschedule,
+    /// if its CPU affinity excludes this CPU.
     /// This function will spawn a new `migration_task` to perform the migration, which will set
     /// current task to `Ready` state and select a proper run queue for it according to its CPU affinity,
     /// switch to the migration task immediately after migration task is prepared.
     ///
     /// Note: the ownership if migrating task (which is current task) is handed over to the migration task,
     /// before the migration task inserted it into the target run queue.
+    ///
+    /// Returns `true` if the current task has been migrated, and is running on
+    /// another CPU now. `preempt` tells if it is preempted to migrate.
     #[cfg(feature = "smp")]
-    pub fn migrate_current(&mut self, migration_task: AxTaskRef) {
+    pub fn migrate_current(&mut self, preempt: bool) -> bool {
         let curr = &self.current_task;
+        if curr.cpumask().get(self.inner.cpu_id) {
+            return false;
+        }
         trace!("task migrate: {}", curr.id_name());
         assert!(curr.is_running());
 
+        const MIGRATION_TASK_STACK_SIZE: usize = 4096;
+        let migrated_task = curr.clone();
+        let migration_task = TaskInner::new(
+            move || migrate_entry(migrated_task),
+            "migration-task".into(),
+            MIGRATION_TASK_STACK_SIZE,
+        )
+        .into_arc();
+
         // Mark current task's state as `Ready`,
         // but, do not put current task to the scheduler of this run queue.
         curr.set_state(TaskState::Ready);
 
         // Call `switch_to` to reschedule to the migration task that performs the migration directly.
-        self.inner.switch_to(crate::current(), migration_task);
+        self.inner
+            .switch_to(crate::current(), migration_task, preempt);
+        true
     }
 
     /// Preempts the current task and reschedules.
@@ -341,9 +473,14 @@ impl<'a, G: BaseGuard> CurrentRunQueueRef<'a, G> {
             can_preempt
         );
         if can_preempt {
+            // Preempted by `set_affinity()` to leave this CPU.
+            #[cfg(feature = "smp")]
+            if self.migrate_current(true) {
+                return;
+            }
             self.inner
-                .put_task_with_state(curr.clone(), TaskState::Running, true);
-            self.inner.resched();
+                .put_task_with_state(self.current_task.clone(), TaskState::Running, true);
+            self.inner.resched(true);
         } else {
             curr.set_preempt_pending(true);
         }
@@ -365,9 +502,16 @@ impl<'a, G: BaseGuard> CurrentRunQueueRef<'a, G> {
             axhal::misc::terminate();
         } else {
             curr.set_state(TaskState::Exited);
+            // A priority boost is of no use any longer, and the boosts after
+            // this are refused by `boost_task_priority()`.
+            curr.take_priority_boost();
 
             // Notify the joiner task.
-            curr.notify_exit(exit_code);
+            if curr.is_cancel_requested() {
+                curr.notify_exit(crate::EXIT_CANCELED);
+            } else {
+                curr.notify_exit(exit_code);
+            }
 
             // Safety: it is called from `current_run_queue::<NoPreemptIrqSave>().exit_current(exit_code)`,
             // which disabled IRQs and preemption.
@@ -379,7 +523,7 @@ impl<'a, G: BaseGuard> CurrentRunQueueRef<'a, G> {
             }
 
             // Schedule to next task.
-            self.inner.resched();
+            self.inner.resched(false);
         }
         unreachable!("task exited!");
     }
@@ -391,31 +535,72 @@ impl<'a, G: BaseGuard> CurrentRunQueueRef<'a, G> {
     ///     2. The caller must ensure that the current task is in the running state.
     ///     3. The caller must ensure that the current task is not the idle task.
     ///     4. The lock of the wait queue will be released explicitly after current task is pushed into it.
-    pub fn blocked_resched(&mut self, mut wq_guard: WaitQueueGuard) {
+    ///
+    /// If `cancelable`, it returns at once when a cancellation request is
+    /// pending, leaving the task in the wait queue for `cancel_events()`.
+    pub fn blocked_resched(&mut self, mut wq_guard: WaitQueueGuard, cancelable: bool) {
+        self.mark_blocked(1);
+        let curr = &self.current_task;
+        // A task woken up by `cancel()` in a noncancelable wait is still in
+        // the wait queue, and blocks again.
+        if !curr.in_wait_queue() {
+            curr.set_in_wait_queue(true);
+            crate::wait_queue::push_waiter(&mut wq_guard, curr.clone());
+        }
+        // Drop the lock of wait queue explictly.
+        drop(wq_guard);
+        self.block_resched(cancelable);
+    }
+
+    /// Same as [`blocked_resched`](Self::blocked_resched), but puts the
+    /// current task into all the wait queues locked by `wq_guards`, for
+    /// [`crate::wait_any`].
+    ///
+    /// The task is woken up by the first of them that notifies it. The locks
+    /// are released after it is in all the queues.
+    pub fn blocked_resched_any(&mut self, mut wq_guards: Vec<WaitQueueGuard>) {
+        self.mark_blocked(wq_guards.len());
+        let curr = &self.current_task;
+        curr.set_in_wait_queue(true);
+        for wq in wq_guards.iter_mut() {
+            crate::wait_queue::push_waiter(wq, curr.clone());
+        }
+        drop(wq_guards);
+        self.block_resched(true);
+    }
+
+    /// Marks the current task as blocked, with the locks of `num_wq_locks`
+    /// wait queues held.
+    fn mark_blocked(&self, _num_wq_locks: usize) {
         let curr = &self.current_task;
         assert!(curr.is_running());
         assert!(!curr.is_idle());
         // we must not block current task with preemption disabled.
-        // Current expected preempt count is 2.
-        // 1 for `NoPreemptIrqSave`, 1 for wait queue's `SpinNoIrq`.
+        // Expected preempt count: 1 for `NoPreemptIrqSave`, 1 for each wait
+        // queue's `SpinNoIrq`.
         #[cfg(feature = "preempt")]
-        assert!(curr.can_preempt(2));
+        assert!(curr.can_preempt(1 + _num_wq_locks));
 
         // Mark the task as blocked, this has to be done before adding it to the wait queue
         // while holding the lock of the wait queue.
+        curr.stats_counters().block();
         curr.set_state(TaskState::Blocked);
-        curr.set_in_wait_queue(true);
+    }
 
-        wq_guard.push_back(curr.clone());
-        // Drop the lock of wait queue explictly.
-        drop(wq_guard);
+    /// Reschedules after the current task is marked as blocked and put into
+    /// the wait queues.
+    fn block_resched(&mut self, cancelable: bool) {
+        let curr = &self.current_task;
+        if cancelable && curr.abort_block_on_cancel() {
+            return;
+        }
 
         // Current task's state has been changed to `Blocked` and added to the wait queue.
         // Note that the state may have been set as `Ready` in `unblock_task()`,
         // see `unblock_task()` for details.
 
         debug!("task block: {}", curr.id_name());
-        self.inner.resched();
+        self.inner.resched(false);
     }
 
     #[cfg(feature = "irq")]
@@ -425,20 +610,20 @@ impl<'a, G: BaseGuard> CurrentRunQueueRef<'a, G> {
         assert!(curr.is_running());
         assert!(!curr.is_idle());
 
-        let now = axhal::time::wall_time();
-        if now < deadline {
+        let now = axhal::time::monotonic_time();
+        if now < deadline && !curr.is_cancel_requested() {
             crate::timers::set_alarm_wakeup(deadline, curr.clone());
+            curr.stats_counters().block();
             curr.set_state(TaskState::Blocked);
-            self.inner.resched();
+            if !curr.abort_block_on_cancel() {
+                self.inner.resched(false);
+            }
+            // Woken up by `cancel()` before the deadline.
+            if curr.is_cancel_requested() {
+                crate::timers::cancel_alarm_wakeup(curr.as_task_ref());
+            }
         }
     }
-
-    pub fn set_current_priority(&mut self, prio: isize) -> bool {
-        self.inner
-            .scheduler
-            .lock()
-            .set_priority(self.current_task.as_task_ref(), prio)
-    }
 }
 
 impl AxRunQueue {
@@ -448,12 +633,183 @@ impl AxRunQueue {
         let gc_task = TaskInner::new(gc_entry, "gc".into(), axconfig::TASK_STACK_SIZE).into_arc();
         // gc task should be pinned to the current CPU.
         gc_task.set_cpumask(AxCpuMask::one_shot(cpu_id));
-
-        let mut scheduler = Scheduler::new();
-        scheduler.add_task(gc_task);
-        Self {
+        // So is the worker task running the work queue of the CPU.
+        let worker_task = TaskInner::new(
+            move || crate::workqueue::worker_entry(cpu_id),
+            "worker".into(),
+            axconfig::TASK_STACK_SIZE,
+        )
+        .into_arc();
+        worker_task.set_cpumask(AxCpuMask::one_shot(cpu_id));
+
+        let rq = Self {
             cpu_id,
-            scheduler: SpinRaw::new(scheduler),
+            scheduler: SpinRaw::new(Scheduler::new()),
+            #[cfg(feature = "irq")]
+            wakeup_pending: AtomicBool::new(false),
+            #[cfg(feature = "irq")]
+            idle: AtomicBool::new(false),
+            #[cfg(any(feature = "smp", feature = "dyntick"))]
+            nr_ready: AtomicUsize::new(0),
+            #[cfg(feature = "dyntick")]
+            tick_stopped: AtomicBool::new(false),
+            #[cfg(feature = "smp")]
+            curr_idle: AtomicBool::new(crate::current().is_idle()),
+            #[cfg(all(feature = "smp", feature = "irq"))]
+            ticks: AtomicUsize::new(0),
+        };
+        rq.sched_add(gc_task);
+        rq.sched_add(worker_task);
+        rq
+    }
+
+    /// Adds a new task into the scheduler.
+    fn sched_add(&self, task: AxTaskRef) {
+        self.scheduler.lock().add_task(task);
+        #[cfg(any(feature = "smp", feature = "dyntick"))]
+        self.nr_ready.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Puts a task back into the scheduler.
+    fn sched_put(&self, task: AxTaskRef, preempt: bool) {
+        self.scheduler.lock().put_prev_task(task, preempt);
+        #[cfg(any(feature = "smp", feature = "dyntick"))]
+        self.nr_ready.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Picks the next task to run from the scheduler.
+    fn sched_pick(&self) -> Option<AxTaskRef> {
+        let task = self.scheduler.lock().pick_next_task();
+        #[cfg(any(feature = "smp", feature = "dyntick"))]
+        if task.is_some() {
+            self.nr_ready.fetch_sub(1, Ordering::Relaxed);
+        }
+        task
+    }
+
+    /// Returns the load of the run queue, i.e., the number of ready tasks,
+    /// plus one if the CPU is running a task other than the idle task.
+    #[cfg(feature = "smp")]
+    fn load(&self) -> usize {
+        self.nr_ready.load(Ordering::Relaxed) + !self.curr_idle.load(Ordering::Relaxed) as usize
+    }
+
+    /// Takes a ready task that can run on this CPU from the busiest other
+    /// run queue, called when this one has no ready tasks.
+    ///
+    /// Tasks that are still switching out on their CPUs are left alone.
+    #[cfg(feature = "smp")]
+    fn steal_task(&self) -> Option<AxTaskRef> {
+        let busiest = online_run_queues()
+            .filter(|rq| rq.cpu_id != self.cpu_id)
+            .max_by_key(|rq| rq.nr_ready.load(Ordering::Relaxed))?;
+        if busiest.nr_ready.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        let task = busiest.sched_pick()?;
+        if task.cpumask().get(self.cpu_id) && !task.on_cpu() {
+            debug!(
+                "task steal: {} from run_queue {} to {}",
+                task.id_name(),
+                busiest.cpu_id,
+                self.cpu_id
+            );
+            Some(task)
+        } else {
+            busiest.sched_put(task, true);
+            None
+        }
+    }
+
+    /// Moves ready tasks of this run queue, which must be the current one, to
+    /// the least loaded one if the difference of their loads exceeds
+    /// [`BALANCE_THRESHOLD`], to even them out.
+    #[cfg(all(feature = "smp", feature = "irq"))]
+    fn balance(&self) {
+        let Some(idlest) = online_run_queues()
+            .filter(|rq| rq.cpu_id != self.cpu_id)
+            .min_by_key(|rq| rq.load())
+        else {
+            return;
+        };
+        let (load, target_load) = (self.load(), idlest.load());
+        if load <= target_load + BALANCE_THRESHOLD {
+            return;
+        }
+        let mut moved = false;
+        for _ in 0..(load - target_load) / 2 {
+            let Some(task) = self.sched_pick() else {
+                break;
+            };
+            // Tasks still switching out are left alone, as in `steal_task()`.
+            if !task.cpumask().get(idlest.cpu_id) || task.on_cpu() {
+                self.sched_put(task, true);
+                break;
+            }
+            debug!(
+                "task balance: {} from run_queue {} to {}",
+                task.id_name(),
+                self.cpu_id,
+                idlest.cpu_id
+            );
+            idlest.sched_put(task, false);
+            moved = true;
+        }
+        if moved {
+            idlest.kick();
+        }
+    }
+
+    /// Notifies the CPU of this run queue that tasks have been put into it,
+    /// and sends it a reschedule IPI if it is idle.
+    ///
+    /// With the `sched_prio` or `sched_rt` feature, the current task of the
+    /// CPU is also preempted if the tasks are more urgent, where other CPUs
+    /// always get the IPI to check it. With the `dyntick` feature, the
+    /// scheduler ticks of the CPU are resumed if they are stopped.
+    fn kick(&self) {
+        // Pairs with `wait_for_tasks()`: either the idle task sees the pending
+        // wakeup, or we see it idle and wake it up.
+        #[cfg(feature = "irq")]
+        {
+            self.wakeup_pending.store(true, Ordering::SeqCst);
+            let remote = self.cpu_id != this_cpu_id();
+            let preemptive = cfg!(any(feature = "sched_prio", feature = "sched_rt"));
+            // Pairs with `needs_tick()` in the same way.
+            #[cfg(feature = "dyntick")]
+            let tick_stopped = self.tick_stopped.load(Ordering::SeqCst);
+            #[cfg(not(feature = "dyntick"))]
+            let tick_stopped = false;
+            if remote && (preemptive || tick_stopped || self.idle.load(Ordering::SeqCst)) {
+                axhal::irq::send_ipi(self.cpu_id);
+            }
+            #[cfg(feature = "dyntick")]
+            if !remote {
+                self.restart_tick();
+            }
+            #[cfg(any(feature = "sched_prio", feature = "sched_rt"))]
+            if !remote {
+                self.check_preempt();
+            }
+        }
+    }
+
+    /// Resumes the scheduler ticks of this CPU, which must be the current
+    /// one, if they are stopped.
+    #[cfg(feature = "dyntick")]
+    fn restart_tick(&self) {
+        if self.tick_stopped.swap(false, Ordering::SeqCst) {
+            crate::timers::resume_tick();
+        }
+    }
+
+    /// Marks the current task to be preempted if a ready task in this run
+    /// queue, which must be the current one, is more urgent.
+    #[cfg(any(feature = "sched_prio", feature = "sched_rt"))]
+    fn check_preempt(&self) {
+        let curr = crate::current();
+        if !curr.is_idle() && self.scheduler.lock().outranks(curr.as_task_ref()) {
+            curr.set_preempt_pending(true);
         }
     }
 
@@ -476,6 +832,7 @@ impl AxRunQueue {
             // If the task is blocked, wait for the task to finish its scheduling process.
             // See `unblock_task()` for details.
             if current_state == TaskState::Blocked {
+                task.stats_counters().unblock();
                 // Wait for next task's scheduling process to complete.
                 // If the owning (remote) CPU is still in the middle of schedule() with
                 // this task (next task) as prev, wait until it's done referencing the task.
@@ -492,8 +849,8 @@ impl AxRunQueue {
                     core::hint::spin_loop();
                 }
             }
-            // TODO: priority
-            self.scheduler.lock().put_prev_task(task, preempt);
+            self.sched_put(task, preempt);
+            self.kick();
             true
         } else {
             false
@@ -502,25 +859,41 @@ impl AxRunQueue {
 
     /// Core reschedule subroutine.
     /// Pick the next task to run and switch to it.
-    fn resched(&mut self) {
-        let next = self
-            .scheduler
-            .lock()
-            .pick_next_task()
-            .unwrap_or_else(|| unsafe {
-                // Safety: IRQs must be disabled at this time.
-                IDLE_TASK.current_ref_raw().get_unchecked().clone()
-            });
+    ///
+    /// `preempt` tells if the current task is preempted, rather than giving
+    /// up the CPU by itself.
+    fn resched(&mut self, preempt: bool) {
+        // A CPU going offline only runs the idle task, which takes it offline.
+        #[cfg(feature = "smp")]
+        let next = loop {
+            if !axhal::cpu::cpu_online(self.cpu_id) {
+                break None;
+            }
+            let next = self.sched_pick();
+            match next {
+                // Its CPU affinity has changed since it was put into this run queue.
+                Some(task) if !task.cpumask().get(self.cpu_id) => migrate_entry(task),
+                // Take a task from other CPUs rather than being idle.
+                None => break self.steal_task(),
+                next => break next,
+            }
+        };
+        #[cfg(not(feature = "smp"))]
+        let next = self.sched_pick();
+        let next = next.unwrap_or_else(|| unsafe {
+            // Safety: IRQs must be disabled at this time.
+            IDLE_TASK.current_ref_raw().get_unchecked().clone()
+        });
         assert!(
             next.is_ready(),
             "next {} is not ready: {:?}",
             next.id_name(),
             next.state()
         );
-        self.switch_to(crate::current(), next);
+        self.switch_to(crate::current(), next, preempt);
     }
 
-    fn switch_to(&mut self, prev_task: CurrentTask, next_task: AxTaskRef) {
+    fn switch_to(&mut self, prev_task: CurrentTask, next_task: AxTaskRef, preempt: bool) {
         // Make sure that IRQs are disabled by kernel guard or other means.
         #[cfg(all(not(test), feature = "irq"))] // Note: irq is faked under unit tests.
         assert!(
@@ -538,6 +911,17 @@ impl AxRunQueue {
         if prev_task.ptr_eq(&next_task) {
             return;
         }
+        next_task.set_cpu_id(this_cpu_id());
+        prev_task.check_stack_canary();
+        let now = axhal::time::monotonic_time_nanos();
+        prev_task.stats_counters().switch_out(now, preempt);
+        next_task.stats_counters().switch_in(now);
+        #[cfg(feature = "tickless")]
+        if prev_task.is_idle() {
+            crate::timers::resume_tick();
+        }
+        #[cfg(feature = "smp")]
+        self.curr_idle.store(next_task.is_idle(), Ordering::Relaxed);
 
         // Claim the task as running, we do this before switching to it
         // such that any running task will have this set.
@@ -602,11 +986,192 @@ fn gc_entry() {
 /// then puts the task to the scheduler of target run queue.
 #[cfg(feature = "smp")]
 pub(crate) fn migrate_entry(migrated_task: AxTaskRef) {
-    select_run_queue::<kernel_guard::NoPreemptIrqSave>(&migrated_task)
-        .inner
-        .scheduler
-        .lock()
-        .put_prev_task(migrated_task, false)
+    let rq = select_run_queue::<kernel_guard::NoPreemptIrqSave>(&migrated_task);
+    rq.inner.sched_put(migrated_task, false);
+    rq.inner.kick();
+}
+
+/// Puts the current CPU into a low-power state until tasks are put into its
+/// run queue, or an interrupt arrives. Called by the idle task.
+///
+/// The pending wakeup is checked again with IRQs disabled, so a task queued
+/// between the idle task's last look at the run queue and the sleep is not
+/// missed: either it is seen here, or its reschedule IPI (or the IRQ that
+/// queued it on this CPU) wakes the CPU up.
+#[cfg(feature = "irq")]
+pub(crate) fn wait_for_tasks() {
+    axhal::arch::disable_irqs();
+    // Safety: IRQs are disabled, and the idle task is pinned to this CPU.
+    let rq = unsafe { RUN_QUEUE.current_ref_raw() };
+    rq.idle.store(true, Ordering::SeqCst);
+    if rq.wakeup_pending.swap(false, Ordering::SeqCst) {
+        axhal::arch::enable_irqs();
+    } else {
+        axhal::cpu::wait_for_irqs();
+    }
+    rq.idle.store(false, Ordering::SeqCst);
+}
+
+/// The handler of reschedule IPIs, which wake idle CPUs up, preempt the
+/// current task for more urgent ones with the `sched_prio` or `sched_rt`
+/// feature, or resume the ticks stopped with the `dyntick` feature.
+#[cfg(all(feature = "smp", feature = "irq"))]
+fn resched_ipi_handler() {
+    trace!("reschedule IPI on CPU {}", this_cpu_id());
+    // Safety: IRQs are disabled in IRQ handlers.
+    #[cfg(any(feature = "sched_prio", feature = "sched_rt", feature = "dyntick"))]
+    let rq = unsafe { RUN_QUEUE.current_ref_raw() };
+    #[cfg(feature = "dyntick")]
+    rq.restart_tick();
+    #[cfg(any(feature = "sched_prio", feature = "sched_rt"))]
+    rq.check_preempt();
+}
+
+/// Serializes the changes of the priorities and the priority boosts of tasks,
+/// which are set in the schedulers and recorded in the tasks separately.
+static PRIORITY_LOCK: SpinNoIrq<()> = SpinNoIrq::new(());
+
+/// Sets the priority of the task in the scheduler of each run queue.
+///
+/// The task may be ready in any run queue, or be put into one meanwhile, so
+/// the scheduler of each run queue is updated, and checks whether its current
+/// task is to be preempted.
+fn sched_set_priority(task: &AxTaskRef, prio: isize) -> bool {
+    let mut ok = true;
+    for rq in online_run_queues() {
+        ok &= rq.scheduler.lock().set_priority(task, prio);
+        #[cfg(any(feature = "sched_prio", feature = "sched_rt"))]
+        rq.kick();
+    }
+    ok
+}
+
+/// Sets the priority of the task, see [`crate::set_priority`].
+///
+/// If the task is boosted to a more urgent priority, the new one takes
+/// effect when the boost is removed.
+pub(crate) fn set_task_priority(task: &AxTaskRef, prio: isize) -> bool {
+    let _lock = PRIORITY_LOCK.lock();
+    // Let the schedulers validate it even if the boost stays in effect.
+    if !sched_set_priority(task, prio) {
+        return false;
+    }
+    task.set_priority(prio);
+    let effective = task.effective_priority();
+    if effective != prio {
+        sched_set_priority(task, effective);
+    }
+    true
+}
+
+/// Records the boost of the task to the priority by a waiter of the lock, and
+/// applies it if it is more urgent than the effective priority, see
+/// [`crate::boost_priority`].
+///
+/// Returns `true` if the boost of the lock is raised, or `false` if it is
+/// already as urgent, the task has exited, or the scheduler does not support
+/// the priority.
+pub(crate) fn boost_task_priority(task: &AxTaskRef, lock: usize, prio: isize) -> bool {
+    let _lock = PRIORITY_LOCK.lock();
+    if task.state() == TaskState::Exited || prio >= task.priority() {
+        return false;
+    }
+    if prio < task.effective_priority() && !sched_set_priority(task, prio) {
+        return false;
+    }
+    task.add_priority_boost(lock, prio)
+}
+
+/// Removes the priority boosts of the task by the waiters of the lock, see
+/// [`crate::restore_priority`].
+pub(crate) fn restore_task_priority(task: &AxTaskRef, lock: usize) {
+    let _lock = PRIORITY_LOCK.lock();
+    if task.remove_priority_boost(lock) {
+        sched_set_priority(task, task.effective_priority());
+    }
+}
+
+/// Sets the scheduling policy of the task, see [`crate::set_scheduler`].
+///
+/// A ready task is moved to the ready queue of the new class at once, which
+/// may preempt the current task of its CPU.
+pub(crate) fn set_task_policy(task: &AxTaskRef, policy: Policy) -> bool {
+    if !policy.is_supported() {
+        return false;
+    }
+    let _lock = PRIORITY_LOCK.lock();
+    if task.policy() == policy {
+        return true;
+    }
+    task.set_policy(policy);
+    #[cfg(feature = "sched_rt")]
+    for rq in online_run_queues() {
+        let mut scheduler = rq.scheduler.lock();
+        if let Some(task) = scheduler.remove_task(task) {
+            scheduler.add_task(task);
+        }
+        drop(scheduler);
+        rq.kick();
+    }
+    true
+}
+
+/// Sets the CPU affinity of the task other than the current one, see
+/// [`crate::set_affinity`].
+///
+/// The task leaves the CPUs it is no longer allowed on the next time it is
+/// scheduled there: a ready task when it is picked, and a running task when
+/// it is preempted, which is requested by a reschedule IPI to those CPUs.
+pub(crate) fn set_task_affinity(task: &AxTaskRef, cpumask: AxCpuMask) {
+    task.set_cpumask(cpumask);
+    #[cfg(all(feature = "smp", feature = "irq", feature = "preempt"))]
+    if task.is_running() {
+        task.set_preempt_pending(true);
+        let this = this_cpu_id();
+        (0..axconfig::SMP)
+            .filter(|&i| i != this && !cpumask.get(i) && axhal::cpu::cpu_online(i))
+            .for_each(axhal::irq::send_ipi);
+    }
+}
+
+/// Takes the current CPU offline after [`axhal::cpu::cpu_down`], called by
+/// the idle task.
+///
+/// The runnable tasks are migrated to other online CPUs, except the ones that
+/// can only run on this CPU (e.g., the gc task), which wait here until the
+/// CPU is online again. The pending timer events and work items are handed
+/// over to other CPUs as well.
+#[cfg(feature = "smp")]
+pub(crate) fn offline_current_cpu() -> ! {
+    let _guard = kernel_guard::NoPreemptIrqSave::new();
+    let cpu_id = this_cpu_id();
+    // Safety: IRQs and preemption are disabled by the guard.
+    let rq = unsafe { RUN_QUEUE.current_ref_mut_raw() };
+
+    let mut pinned = alloc::vec::Vec::new();
+    loop {
+        let Some(task) = rq.sched_pick() else {
+            break;
+        };
+        if has_online_cpu(task.cpumask()) {
+            debug!(
+                "task migrate: {} from offline CPU {}",
+                task.id_name(),
+                cpu_id
+            );
+            migrate_entry(task);
+        } else {
+            pinned.push(task);
+        }
+    }
+    for task in pinned {
+        rq.sched_put(task, false);
+    }
+
+    #[cfg(feature = "irq")]
+    crate::timers::hand_over_events();
+    crate::workqueue::hand_over_work();
+    axhal::cpu::offline_this_cpu()
 }
 
 /// Clear the `on_cpu` field of previous task running on this CPU.
@@ -627,6 +1192,7 @@ pub(crate) fn init() {
     let idle_task = TaskInner::new(|| crate::run_idle(), "idle".into(), IDLE_TASK_STACK_SIZE);
     // idle task should be pinned to the current CPU.
     idle_task.set_cpumask(AxCpuMask::one_shot(cpu_id));
+    idle_task.set_cpu_id(cpu_id);
     IDLE_TASK.with_current(|i| {
         i.init_once(idle_task.into_arc());
     });
@@ -634,6 +1200,7 @@ pub(crate) fn init() {
     // Put the subsequent execution into the `main` task.
     let main_task = TaskInner::new_init("main".into()).into_arc();
     main_task.set_state(TaskState::Running);
+    main_task.set_cpu_id(cpu_id);
     unsafe { CurrentTask::init_current(main_task) }
 
     RUN_QUEUE.with_current(|rq| {
@@ -642,14 +1209,25 @@ pub(crate) fn init() {
     unsafe {
         RUN_QUEUES[cpu_id].write(RUN_QUEUE.current_ref_mut_raw());
     }
+
+    #[cfg(all(feature = "smp", feature = "irq"))]
+    axhal::irq::register_handler(axhal::irq::IPI_IRQ_NUM, resched_ipi_handler);
 }
 
 pub(crate) fn init_secondary() {
     let cpu_id = this_cpu_id();
 
+    // The CPU is online again, the idle task is resumed on the boot stack.
+    #[cfg(feature = "smp")]
+    if IDLE_TASK.with_current(|i| i.is_inited()) {
+        unsafe { CurrentTask::restore_current(IDLE_TASK.current_ref_raw().get_unchecked()) };
+        return;
+    }
+
     // Put the subsequent execution into the `idle` task.
     let idle_task = TaskInner::new_init("idle".into()).into_arc();
     idle_task.set_state(TaskState::Running);
+    idle_task.set_cpu_id(cpu_id);
     IDLE_TASK.with_current(|i| {
         i.init_once(idle_task.clone());
     });
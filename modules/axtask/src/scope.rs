@@ -0,0 +1,185 @@
+//! Scoped tasks, which may borrow the data of the task spawning them.
+//!
+//! Like [`std::thread::scope`], [`scope`] runs a closure with a [`Scope`],
+//! where tasks can be spawned with closures that borrow non-`'static` data
+//! from outside the scope, as all of them are joined before [`scope`]
+//! returns.
+//!
+//! A panic stops the whole system, so a scoped task can only fail by
+//! terminating without returning, e.g., by [`exit`](crate::exit) or a stack
+//! overflow. Then its borrows are leaked rather than released, and [`scope`]
+//! still joins all the other tasks, before it panics if the failure is not
+//! handled by [`ScopedJoinHandle::join`].
+//!
+//! # Examples
+//!
+//! ```no_run
+//! let mut buf = [0u8; 64];
+//! axtask::scope(|s| {
+//!     for (i, chunk) in buf.chunks_mut(16).enumerate() {
+//!         s.spawn(move || chunk.fill(i as u8));
+//!     }
+//! });
+//! assert_eq!(buf[63], 3);
+//! ```
+//!
+//! [`std::thread::scope`]: https://doc.rust-lang.org/std/thread/fn.scope.html
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use kspin::SpinNoIrq;
+
+use crate::{AxTaskRef, Builder};
+
+/// A scope to spawn scoped tasks in, see [`scope`].
+///
+/// `'scope` is the lifetime of the scope, during which the tasks run, and
+/// `'env` is the lifetime of the data they may borrow from outside.
+pub struct Scope<'scope, 'env: 'scope> {
+    /// The tasks spawned in the scope.
+    tasks: SpinNoIrq<Vec<AxTaskRef>>,
+    /// The number of tasks that have neither returned nor been joined by
+    /// their handles.
+    num_unfinished: AtomicUsize,
+    // Invariant over the lifetimes, as `std::thread::Scope`.
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+/// The result of a scoped task, written by it when it returns.
+struct Packet<T> {
+    result: UnsafeCell<Option<T>>,
+}
+
+// Safety: the result is only written by the task, and read after it exits.
+unsafe impl<T: Send> Sync for Packet<T> {}
+
+/// An owned permission to join a scoped task, see [`Scope::spawn`].
+pub struct ScopedJoinHandle<'scope, T> {
+    task: AxTaskRef,
+    packet: Arc<Packet<T>>,
+    /// The counter of the unfinished tasks in the scope.
+    num_unfinished: &'scope AtomicUsize,
+}
+
+/// Creates a scope to spawn scoped tasks in, see the [module-level
+/// documentation](self).
+///
+/// All the tasks spawned in the scope are joined before it returns, even
+/// the ones not joined by their handles, and even if the current task is
+/// canceled meanwhile.
+///
+/// # Panics
+///
+/// Panics if a task spawned in the scope terminated without returning, and
+/// it was not joined by its handle.
+pub fn scope<'env, F, T>(f: F) -> T
+where
+    F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+{
+    let scope = Scope {
+        tasks: SpinNoIrq::new(Vec::new()),
+        num_unfinished: AtomicUsize::new(0),
+        scope: PhantomData,
+        env: PhantomData,
+    };
+    let result = f(&scope);
+    // The tasks being joined may spawn more.
+    while let Some(task) = scope.pop_task() {
+        task.join_noncancelable();
+    }
+    let failed = scope.num_unfinished.load(Ordering::Acquire);
+    if failed != 0 {
+        panic!("{} scoped task(s) terminated without returning", failed);
+    }
+    result
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// Spawns a scoped task with the default parameters, the same as
+    /// `Builder::new().spawn_scoped(self, f)`, see [`Builder`].
+    ///
+    /// Unlike [`spawn`](crate::spawn), `f` may borrow anything that outlives
+    /// the scope.
+    pub fn spawn<F, T>(&'scope self, f: F) -> ScopedJoinHandle<'scope, T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        Builder::new().spawn_scoped(self, f)
+    }
+
+    /// Takes out the most recently spawned task not joined by the scope yet.
+    fn pop_task(&self) -> Option<AxTaskRef> {
+        self.tasks.lock().pop()
+    }
+}
+
+impl Builder {
+    /// Spawns a scoped task in the given scope, see [`Scope::spawn`].
+    pub fn spawn_scoped<'scope, 'env, F, T>(
+        self,
+        scope: &'scope Scope<'scope, 'env>,
+        f: F,
+    ) -> ScopedJoinHandle<'scope, T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let my_packet = Arc::new(Packet {
+            result: UnsafeCell::new(None),
+        });
+        let their_packet = my_packet.clone();
+        let main = move || {
+            let ret = f();
+            // Safety: only read after the task exits.
+            unsafe { *their_packet.result.get() = Some(ret) };
+            scope.num_unfinished.fetch_sub(1, Ordering::Release);
+        };
+        let main: Box<dyn FnOnce() + Send + 'scope> = Box::new(main);
+        // Safety: the scope joins the task before `'scope` ends, and the
+        // borrows of a task terminated without returning are never used
+        // again.
+        let main: Box<dyn FnOnce() + Send + 'static> = unsafe { core::mem::transmute(main) };
+
+        scope.num_unfinished.fetch_add(1, Ordering::Relaxed);
+        let task = self.spawn(main);
+        scope.tasks.lock().push(task.clone());
+        ScopedJoinHandle {
+            task,
+            packet: my_packet,
+            num_unfinished: &scope.num_unfinished,
+        }
+    }
+}
+
+impl<T> ScopedJoinHandle<'_, T> {
+    /// Returns the reference to the task.
+    pub fn task(&self) -> &AxTaskRef {
+        &self.task
+    }
+
+    /// Whether the task has exited.
+    pub fn is_finished(&self) -> bool {
+        self.task.state() == crate::TaskState::Exited
+    }
+
+    /// Waits for the task to exit, and returns what it returned, or [`None`]
+    /// if it terminated without returning, which does not fail the scope
+    /// then.
+    ///
+    /// Unlike [`join`](crate::join), it keeps waiting even if the current
+    /// task is canceled.
+    pub fn join(self) -> Option<T> {
+        self.task.join_noncancelable();
+        // Safety: the task has exited, so no one else touches the result.
+        let result = unsafe { (*self.packet.result.get()).take() };
+        if result.is_none() {
+            self.num_unfinished.fetch_sub(1, Ordering::Relaxed);
+        }
+        result
+    }
+}
@@ -0,0 +1,214 @@
+//! The registry of all the tasks alive, and their runtime statistics.
+//!
+//! A task is in the registry from its creation until it is dropped, after it
+//! exits and the last reference to it is gone, so exited tasks not yet
+//! joined or cleaned up are still listed.
+//!
+//! The running time is accumulated at each context switch, and the blocked
+//! time when a blocked task is woken up, both by the monotonic clock. As the
+//! counters live in the task itself, they stay correct when the task migrates
+//! between CPUs.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::{string::String, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use kspin::SpinNoIrq;
+
+use crate::task::TaskState;
+use crate::{AxTask, AxTaskRef};
+
+/// All the tasks alive, indexed by the task ID.
+static TASKS: SpinNoIrq<BTreeMap<u64, Weak<AxTask>>> = SpinNoIrq::new(BTreeMap::new());
+
+/// The runtime statistics of a task, returned by [`stats`](crate::stats).
+#[derive(Debug, Clone)]
+pub struct TaskStats {
+    /// The task ID.
+    pub id: u64,
+    /// The task name.
+    pub name: String,
+    /// The total time the task has been running, including the current run.
+    pub run_time: Duration,
+    /// The total time the task has been blocked, e.g., waiting in a wait
+    /// queue or sleeping, not including the time waiting in a run queue.
+    pub blocked_time: Duration,
+    /// The number of times the task gave up the CPU, by yielding, blocking
+    /// or exiting.
+    pub voluntary_switches: u64,
+    /// The number of times the task was preempted.
+    pub involuntary_switches: u64,
+}
+
+/// The information of a task, given by [`for_each_task`](crate::for_each_task).
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    /// The task ID.
+    pub id: u64,
+    /// The task name.
+    pub name: String,
+    /// The state of the task.
+    pub state: TaskState,
+    /// The CPU the task is running on, or ran on the last time.
+    pub cpu_id: usize,
+    /// The priority the task is scheduled with, i.e., the nice value with
+    /// the CFS scheduler, including the boost by
+    /// [`boost_priority`](crate::boost_priority).
+    pub priority: isize,
+    /// The most bytes of the kernel stack that have been in use, 0 for the
+    /// init tasks running on the boot stacks.
+    pub stack_used: usize,
+    /// The size of the kernel stack, 0 for the init tasks.
+    pub stack_size: usize,
+    /// The runtime statistics.
+    pub stats: TaskStats,
+}
+
+/// The counters of the runtime statistics in a task.
+pub(crate) struct TaskStatsCounters {
+    run_nanos: AtomicU64,
+    /// The monotonic time in nanoseconds the task last started running.
+    run_since: AtomicU64,
+    blocked_nanos: AtomicU64,
+    /// The monotonic time in nanoseconds the task last got blocked.
+    blocked_since: AtomicU64,
+    voluntary_switches: AtomicU64,
+    involuntary_switches: AtomicU64,
+}
+
+impl TaskStatsCounters {
+    pub const fn new() -> Self {
+        Self {
+            run_nanos: AtomicU64::new(0),
+            run_since: AtomicU64::new(0),
+            blocked_nanos: AtomicU64::new(0),
+            blocked_since: AtomicU64::new(0),
+            voluntary_switches: AtomicU64::new(0),
+            involuntary_switches: AtomicU64::new(0),
+        }
+    }
+
+    /// Called when the task is switched out at `now`.
+    ///
+    /// Only the CPU running the task updates the counters, so plain loads and
+    /// stores are enough.
+    pub fn switch_out(&self, now: u64, preempt: bool) {
+        let run = now.saturating_sub(self.run_since.load(Ordering::Relaxed));
+        let total = self.run_nanos.load(Ordering::Relaxed) + run;
+        self.run_nanos.store(total, Ordering::Relaxed);
+        let switches = if preempt {
+            &self.involuntary_switches
+        } else {
+            &self.voluntary_switches
+        };
+        switches.store(switches.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+    }
+
+    /// Called when the task is switched in at `now`.
+    pub fn switch_in(&self, now: u64) {
+        self.run_since.store(now, Ordering::Relaxed);
+    }
+
+    /// Called by the task itself before it gets blocked.
+    pub fn block(&self) {
+        let now = axhal::time::monotonic_time_nanos();
+        self.blocked_since.store(now, Ordering::Release);
+    }
+
+    /// Called when the task is woken up, after its state changes from
+    /// blocked, which happens once for each [`block`](Self::block).
+    pub fn unblock(&self) {
+        let now = axhal::time::monotonic_time_nanos();
+        let blocked = now.saturating_sub(self.blocked_since.load(Ordering::Acquire));
+        self.blocked_nanos.fetch_add(blocked, Ordering::Relaxed);
+    }
+}
+
+impl TaskStats {
+    fn new(task: &AxTaskRef) -> Self {
+        let counters = task.stats_counters();
+        let mut run_nanos = counters.run_nanos.load(Ordering::Relaxed);
+        if task.is_running() {
+            let now = axhal::time::monotonic_time_nanos();
+            run_nanos += now.saturating_sub(counters.run_since.load(Ordering::Relaxed));
+        }
+        Self {
+            id: task.id().as_u64(),
+            name: String::from(task.name()),
+            run_time: Duration::from_nanos(run_nanos),
+            blocked_time: Duration::from_nanos(counters.blocked_nanos.load(Ordering::Relaxed)),
+            voluntary_switches: counters.voluntary_switches.load(Ordering::Relaxed),
+            involuntary_switches: counters.involuntary_switches.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl TaskInfo {
+    fn new(task: &AxTaskRef) -> Self {
+        let (stack_used, stack_size) = task.kernel_stack_usage().unwrap_or((0, 0));
+        Self {
+            id: task.id().as_u64(),
+            name: String::from(task.name()),
+            state: task.state(),
+            cpu_id: task.cpu_id(),
+            priority: task.effective_priority(),
+            stack_used,
+            stack_size,
+            stats: TaskStats::new(task),
+        }
+    }
+}
+
+/// Adds a new task to the list of all tasks.
+pub(crate) fn register_task(task: &AxTaskRef) {
+    TASKS
+        .lock()
+        .insert(task.id().as_u64(), Arc::downgrade(task));
+}
+
+/// Removes a task being dropped from the list of all tasks.
+pub(crate) fn unregister_task(id: u64) {
+    TASKS.lock().remove(&id);
+}
+
+/// Returns the task of the ID if it is alive.
+pub fn find_task(id: u64) -> Option<AxTaskRef> {
+    TASKS.lock().get(&id).and_then(Weak::upgrade)
+}
+
+/// Returns the runtime statistics of the task.
+pub fn stats(task: &AxTaskRef) -> TaskStats {
+    TaskStats::new(task)
+}
+
+/// Returns the tasks alive, in ascending order of the task ID.
+fn all_tasks() -> Vec<AxTaskRef> {
+    // The tasks are not dropped with the list locked, which removes them
+    // from it.
+    TASKS.lock().values().filter_map(Weak::upgrade).collect()
+}
+
+/// Calls `f` with the information of each task alive, in ascending order of
+/// the task ID.
+///
+/// The tasks are taken from the registry before `f` is called, and are kept
+/// alive until the iteration ends, so `f` may block, spawn tasks, or run
+/// while the tasks exit. The tasks spawned meanwhile are not included.
+pub fn for_each_task<F>(mut f: F)
+where
+    F: FnMut(&TaskInfo),
+{
+    for task in all_tasks() {
+        f(&TaskInfo::new(&task));
+    }
+}
+
+/// Returns the runtime statistics of all the tasks alive, in descending order
+/// of the running time.
+pub fn all_stats() -> Vec<TaskStats> {
+    let mut stats: Vec<TaskStats> = all_tasks().iter().map(TaskStats::new).collect();
+    stats.sort_unstable_by(|a, b| b.run_time.cmp(&a.run_time));
+    stats
+}
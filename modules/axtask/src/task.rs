@@ -1,20 +1,26 @@
-use alloc::{boxed::Box, string::String, sync::Arc};
-use core::ops::Deref;
-use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicU8, Ordering};
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use core::ops::{Deref, Range};
+use core::sync::atomic::{
+    fence, AtomicBool, AtomicI32, AtomicIsize, AtomicU64, AtomicU8, Ordering,
+};
 use core::{alloc::Layout, cell::UnsafeCell, fmt, ptr::NonNull};
 
-#[cfg(feature = "preempt")]
 use core::sync::atomic::AtomicUsize;
 
 use kspin::SpinNoIrq;
-use memory_addr::{align_up_4k, VirtAddr};
+use memory_addr::{align_up_4k, VirtAddr, PAGE_SIZE_4K};
 
 use axhal::arch::TaskContext;
 #[cfg(feature = "tls")]
 use axhal::tls::TlsArea;
 
+use crate::stats::TaskStatsCounters;
 use crate::task_ext::AxTaskExt;
-use crate::{AxCpuMask, AxTask, AxTaskRef, WaitQueue};
+use crate::task_local::TaskLocals;
+use crate::{AxCpuMask, AxTask, AxTaskRef, Policy, WaitQueue};
+
+/// The value of `TaskInner::priority_boost` when the task is not boosted.
+const NO_BOOST: isize = isize::MAX;
 
 /// A unique identifier for a thread.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -23,7 +29,7 @@ pub struct TaskId(u64);
 /// The possible states of a task.
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
-pub(crate) enum TaskState {
+pub enum TaskState {
     /// Task is running on some CPU.
     Running = 1,
     /// Task is ready to run on some scheduler's ready queue.
@@ -48,8 +54,41 @@ pub struct TaskInner {
     /// CPU affinity mask.
     cpumask: SpinNoIrq<AxCpuMask>,
 
-    /// Mark whether the task is in the wait queue.
+    /// The priority last set by [`crate::set_priority`].
+    priority: AtomicIsize,
+    /// The priority inherited by [`crate::boost_priority`], or [`NO_BOOST`],
+    /// the most urgent one in `boosts`.
+    priority_boost: AtomicIsize,
+    /// The priorities inherited from the waiters of each lock the task holds,
+    /// keyed by the lock, only changed with the priority lock of the run
+    /// queues held.
+    boosts: SpinNoIrq<Vec<(usize, isize)>>,
+    /// The ID of the task owning the lock the task waits for, or 0, set by
+    /// [`crate::set_current_waiting_for`].
+    waiting_for: AtomicU64,
+    /// The lock the task waits for, valid if `waiting_for` is not 0.
+    waiting_lock: AtomicUsize,
+    /// The real-time priority set by [`crate::set_scheduler`], or 0 in the
+    /// normal class.
+    rt_priority: AtomicU8,
+    /// The real-time priority of the ready queue the task is put into last,
+    /// or 0 for the normal class, only meaningful in that scheduler.
+    #[cfg(feature = "sched_rt")]
+    rt_level: AtomicU8,
+
+    /// The CPU the task is running on, or ran on the last time.
+    cpu_id: AtomicUsize,
+
+    /// Mark whether the task is in the wait queue, and not notified yet. A
+    /// task in several wait queues by [`crate::wait_any`] is woken up by the
+    /// notifier that clears it.
     in_wait_queue: AtomicBool,
+    /// The order of arrival of the task in its current wait, which keeps its
+    /// place in the wait queue when it blocks again in the same wait.
+    wait_ticket: AtomicU64,
+
+    /// Set by [`crate::cancel`], never cleared.
+    cancel_requested: AtomicBool,
 
     /// Used to indicate whether the task is running on a CPU.
     #[cfg(feature = "smp")]
@@ -60,6 +99,12 @@ pub struct TaskInner {
     /// expired by setting it as zero in `timer_ticket_expired()`, which is called by `cancel_events()`.
     #[cfg(feature = "irq")]
     timer_ticket_id: AtomicU64,
+    /// The CPU whose timer queue holds the timer event, and its deadline in
+    /// monotonic nanoseconds, to remove it when the timer is cancelled.
+    #[cfg(feature = "irq")]
+    timer_cpu: AtomicUsize,
+    #[cfg(feature = "irq")]
+    timer_deadline: AtomicU64,
 
     #[cfg(feature = "preempt")]
     need_resched: AtomicBool,
@@ -69,9 +114,14 @@ pub struct TaskInner {
     exit_code: AtomicI32,
     wait_for_exit: WaitQueue,
 
+    /// Runtime statistics, updated by the scheduler.
+    stats: TaskStatsCounters,
+
     kstack: Option<TaskStack>,
     ctx: UnsafeCell<TaskContext>,
     task_ext: AxTaskExt,
+    /// Values of the task-local storage keys, only accessed by the task.
+    task_locals: UnsafeCell<TaskLocals>,
 
     #[cfg(feature = "tls")]
     tls: TlsArea,
@@ -147,12 +197,37 @@ impl TaskInner {
     /// Wait for the task to exit, and return the exit code.
     ///
     /// It will return immediately if the task has already exited (but not dropped).
+    /// Returns [`None`] if the task is the current one, which would never exit
+    /// while waiting for itself, or if the current task is canceled while
+    /// waiting.
     pub fn join(&self) -> Option<i32> {
-        self.wait_for_exit
-            .wait_until(|| self.state() == TaskState::Exited);
+        if crate::current_may_uninit().is_some_and(|curr| core::ptr::eq(&*curr, self)) {
+            return None;
+        }
+        if self
+            .wait_for_exit
+            .wait_until(|| self.state() == TaskState::Exited)
+            .canceled()
+        {
+            return None;
+        }
         Some(self.exit_code.load(Ordering::Acquire))
     }
 
+    /// Waits for the task to exit like [`join`](Self::join), but keeps
+    /// waiting even if the current task is canceled.
+    pub(crate) fn join_noncancelable(&self) -> i32 {
+        self.wait_for_exit
+            .wait_until_noncancelable(|| self.state() == TaskState::Exited);
+        self.exit_code.load(Ordering::Acquire)
+    }
+
+    /// Whether the task has been requested to cancel by [`crate::cancel`].
+    #[inline]
+    pub fn is_cancel_requested(&self) -> bool {
+        self.cancel_requested.load(Ordering::SeqCst)
+    }
+
     /// Returns the pointer to the user-defined task extended data.
     ///
     /// # Safety
@@ -193,6 +268,29 @@ impl TaskInner {
         }
     }
 
+    /// Returns the address range of the kernel stack.
+    ///
+    /// The init tasks (`main` and `idle`) run on the boot stacks, and have no
+    /// kernel stack allocated.
+    #[inline]
+    pub fn kernel_stack_range(&self) -> Option<Range<VirtAddr>> {
+        self.kstack.as_ref().map(|s| s.bottom()..s.top())
+    }
+
+    /// Returns the most bytes of the kernel stack that have been in use, and
+    /// the size of the kernel stack, if it is allocated.
+    pub fn kernel_stack_usage(&self) -> Option<(usize, usize)> {
+        self.kstack
+            .as_ref()
+            .map(|s| (s.high_water_mark(), s.size()))
+    }
+
+    /// Returns the CPU the task is running on, or ran on the last time.
+    #[inline]
+    pub fn cpu_id(&self) -> usize {
+        self.cpu_id.load(Ordering::Relaxed)
+    }
+
     /// Gets the cpu affinity mask of the task.
     ///
     /// Returns the cpu affinity mask of the task in type [`AxCpuMask`].
@@ -209,6 +307,35 @@ impl TaskInner {
     pub fn set_cpumask(&self, cpumask: AxCpuMask) {
         *self.cpumask.lock() = cpumask
     }
+
+    /// Gets the priority of the task, the one last set by
+    /// [`crate::set_priority`], or 0 by default.
+    #[inline]
+    pub fn priority(&self) -> isize {
+        self.priority.load(Ordering::Relaxed)
+    }
+
+    /// Gets the priority the task is scheduled with, the more urgent one of
+    /// its [`priority`](Self::priority) and the priority it is boosted to by
+    /// [`crate::boost_priority`].
+    #[inline]
+    pub fn effective_priority(&self) -> isize {
+        self.priority()
+            .min(self.priority_boost.load(Ordering::Relaxed))
+    }
+
+    /// Whether the task is boosted by [`crate::boost_priority`].
+    #[inline]
+    pub fn is_priority_boosted(&self) -> bool {
+        self.priority_boost.load(Ordering::Relaxed) != NO_BOOST
+    }
+
+    /// Gets the scheduling policy of the task, set by
+    /// [`crate::set_scheduler`], or [`Policy::Normal`] by default.
+    #[inline]
+    pub fn policy(&self) -> Policy {
+        Policy::from_rt_priority(self.rt_priority())
+    }
 }
 
 // private methods
@@ -223,9 +350,24 @@ impl TaskInner {
             state: AtomicU8::new(TaskState::Ready as u8),
             // By default, the task is allowed to run on all CPUs.
             cpumask: SpinNoIrq::new(AxCpuMask::full()),
+            priority: AtomicIsize::new(0),
+            priority_boost: AtomicIsize::new(NO_BOOST),
+            boosts: SpinNoIrq::new(Vec::new()),
+            waiting_for: AtomicU64::new(0),
+            waiting_lock: AtomicUsize::new(0),
+            rt_priority: AtomicU8::new(0),
+            #[cfg(feature = "sched_rt")]
+            rt_level: AtomicU8::new(0),
+            cpu_id: AtomicUsize::new(0),
             in_wait_queue: AtomicBool::new(false),
+            wait_ticket: AtomicU64::new(0),
+            cancel_requested: AtomicBool::new(false),
             #[cfg(feature = "irq")]
             timer_ticket_id: AtomicU64::new(0),
+            #[cfg(feature = "irq")]
+            timer_cpu: AtomicUsize::new(0),
+            #[cfg(feature = "irq")]
+            timer_deadline: AtomicU64::new(0),
             #[cfg(feature = "smp")]
             on_cpu: AtomicBool::new(false),
             #[cfg(feature = "preempt")]
@@ -234,9 +376,11 @@ impl TaskInner {
             preempt_disable_count: AtomicUsize::new(0),
             exit_code: AtomicI32::new(0),
             wait_for_exit: WaitQueue::new(),
+            stats: TaskStatsCounters::new(),
             kstack: None,
             ctx: UnsafeCell::new(TaskContext::new()),
             task_ext: AxTaskExt::empty(),
+            task_locals: UnsafeCell::new(TaskLocals::new()),
             #[cfg(feature = "tls")]
             tls: TlsArea::alloc(),
         }
@@ -262,7 +406,9 @@ impl TaskInner {
     }
 
     pub(crate) fn into_arc(self) -> AxTaskRef {
-        Arc::new(AxTask::new(self))
+        let task = Arc::new(AxTask::new(self));
+        crate::stats::register_task(&task);
+        task
     }
 
     #[inline]
@@ -290,8 +436,13 @@ impl TaskInner {
             .is_ok()
     }
 
+    /// Whether the task is running on a CPU.
+    ///
+    /// Unless it is the current task, it is only a hint that may be out of
+    /// date at once, e.g., for the waiters of a lock to spin rather than block
+    /// while its owner runs on another CPU.
     #[inline]
-    pub(crate) fn is_running(&self) -> bool {
+    pub fn is_running(&self) -> bool {
         matches!(self.state(), TaskState::Running)
     }
 
@@ -310,6 +461,91 @@ impl TaskInner {
         self.is_idle
     }
 
+    #[inline]
+    pub(crate) fn set_priority(&self, prio: isize) {
+        self.priority.store(prio, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn update_priority_boost(&self, boosts: &[(usize, isize)]) {
+        let prio = boosts.iter().map(|&(_, prio)| prio).min();
+        self.priority_boost
+            .store(prio.unwrap_or(NO_BOOST), Ordering::Relaxed);
+    }
+
+    /// Records the boost to the priority from a waiter of the lock, and
+    /// returns `true` if it is more urgent than the boosts of the lock so far.
+    pub(crate) fn add_priority_boost(&self, lock: usize, prio: isize) -> bool {
+        let mut boosts = self.boosts.lock();
+        match boosts.iter_mut().find(|(l, _)| *l == lock) {
+            Some((_, p)) if *p <= prio => return false,
+            Some((_, p)) => *p = prio,
+            None => boosts.push((lock, prio)),
+        }
+        self.update_priority_boost(&boosts);
+        true
+    }
+
+    /// Removes the boosts from the waiters of the lock, and returns `true` if
+    /// there were any.
+    pub(crate) fn remove_priority_boost(&self, lock: usize) -> bool {
+        let mut boosts = self.boosts.lock();
+        let len = boosts.len();
+        boosts.retain(|&(l, _)| l != lock);
+        if boosts.len() == len {
+            return false;
+        }
+        self.update_priority_boost(&boosts);
+        true
+    }
+
+    /// Removes all the priority boosts, and returns `true` if there were any.
+    #[inline]
+    pub(crate) fn take_priority_boost(&self) -> bool {
+        self.boosts.lock().clear();
+        self.priority_boost.swap(NO_BOOST, Ordering::Relaxed) != NO_BOOST
+    }
+
+    #[inline]
+    pub(crate) fn rt_priority(&self) -> u8 {
+        self.rt_priority.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub(crate) fn set_policy(&self, policy: Policy) {
+        self.rt_priority
+            .store(policy.rt_priority(), Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "sched_rt")]
+    #[inline]
+    pub(crate) fn rt_level(&self) -> u8 {
+        self.rt_level.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "sched_rt")]
+    #[inline]
+    pub(crate) fn set_rt_level(&self, level: u8) {
+        self.rt_level.store(level, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn waiting_for(&self) -> (u64, usize) {
+        let owner_id = self.waiting_for.load(Ordering::Acquire);
+        (owner_id, self.waiting_lock.load(Ordering::Relaxed))
+    }
+
+    #[inline]
+    pub(crate) fn set_waiting_for(&self, owner_id: u64, lock: usize) {
+        self.waiting_lock.store(lock, Ordering::Relaxed);
+        self.waiting_for.store(owner_id, Ordering::Release);
+    }
+
+    #[inline]
+    pub(crate) fn set_cpu_id(&self, cpu_id: usize) {
+        self.cpu_id.store(cpu_id, Ordering::Relaxed);
+    }
+
     #[inline]
     pub(crate) fn in_wait_queue(&self) -> bool {
         self.in_wait_queue.load(Ordering::Acquire)
@@ -320,6 +556,54 @@ impl TaskInner {
         self.in_wait_queue.store(in_wait_queue, Ordering::Release);
     }
 
+    /// Clears the `in_wait_queue` flag, and returns `true` if it was set,
+    /// i.e., the caller is the one to wake the task up from its wait queues.
+    #[inline]
+    pub(crate) fn take_in_wait_queue(&self) -> bool {
+        self.in_wait_queue.swap(false, Ordering::AcqRel)
+    }
+
+    #[inline]
+    pub(crate) fn wait_ticket(&self) -> u64 {
+        self.wait_ticket.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub(crate) fn set_wait_ticket(&self, ticket: u64) {
+        self.wait_ticket.store(ticket, Ordering::Relaxed);
+    }
+
+    /// Marks the task as requested to cancel, and returns `false` if it has
+    /// exited, in which case it is left as is.
+    pub(crate) fn request_cancel(&self) -> bool {
+        if self.state() == TaskState::Exited {
+            return false;
+        }
+        self.cancel_requested.store(true, Ordering::SeqCst);
+        // Pairs with `abort_block_on_cancel()`: either the task sees the
+        // request, or the caller sees the task blocked and wakes it up.
+        fence(Ordering::SeqCst);
+        true
+    }
+
+    /// Called by the task itself after it is marked as blocked in a
+    /// cancelable wait, in case a cancellation request has come before, and
+    /// missed the blocked state to wake it up.
+    ///
+    /// Returns `true` if the task is running again, in which case it must not
+    /// reschedule. Otherwise, it may have been woken up by others meanwhile.
+    pub(crate) fn abort_block_on_cancel(&self) -> bool {
+        fence(Ordering::SeqCst);
+        if self.is_cancel_requested()
+            && self.transition_state(TaskState::Blocked, TaskState::Running)
+        {
+            self.stats.unblock();
+            true
+        } else {
+            false
+        }
+    }
+
     /// Returns task's current timer ticket ID.
     #[inline]
     #[cfg(feature = "irq")]
@@ -338,6 +622,25 @@ impl TaskInner {
             .store(timer_ticket_id, Ordering::Release);
     }
 
+    /// Returns the CPU whose timer queue holds the timer event, and its
+    /// deadline.
+    #[inline]
+    #[cfg(feature = "irq")]
+    pub(crate) fn timer_location(&self) -> (usize, u64) {
+        (
+            self.timer_cpu.load(Ordering::Acquire),
+            self.timer_deadline.load(Ordering::Acquire),
+        )
+    }
+
+    /// Sets the CPU whose timer queue holds the timer event, and its deadline.
+    #[inline]
+    #[cfg(feature = "irq")]
+    pub(crate) fn set_timer_location(&self, cpu_id: usize, deadline: u64) {
+        self.timer_cpu.store(cpu_id, Ordering::Release);
+        self.timer_deadline.store(deadline, Ordering::Release);
+    }
+
     /// Expire timer ticket ID by setting it to 0,
     /// it can be used to identify one timer event is triggered or expired.
     #[inline]
@@ -362,11 +665,20 @@ impl TaskInner {
     #[cfg(feature = "preempt")]
     pub(crate) fn disable_preempt(&self) {
         self.preempt_disable_count.fetch_add(1, Ordering::Relaxed);
+        // Only the task itself changes the count.
+        #[cfg(feature = "latency-trace")]
+        if self.preempt_disable_count.load(Ordering::Relaxed) == 1 {
+            axhal::latency::trace_off(axhal::latency::LatencyKind::PreemptOff);
+        }
     }
 
     #[inline]
     #[cfg(feature = "preempt")]
     pub(crate) fn enable_preempt(&self, resched: bool) {
+        #[cfg(feature = "latency-trace")]
+        if self.preempt_disable_count.load(Ordering::Relaxed) == 1 {
+            axhal::latency::trace_on(axhal::latency::LatencyKind::PreemptOff);
+        }
         if self.preempt_disable_count.fetch_sub(1, Ordering::Relaxed) == 1 && resched {
             // If current task is pending to be preempted, do rescheduling.
             Self::current_check_preempt_pending();
@@ -387,6 +699,38 @@ impl TaskInner {
         }
     }
 
+    /// Whether `vaddr` is in the guard page below the kernel stack, i.e., an
+    /// access to it is a stack overflow.
+    pub(crate) fn in_stack_guard(&self, vaddr: VirtAddr) -> bool {
+        self.kstack
+            .as_ref()
+            .is_some_and(|s| s.guard_contains(vaddr))
+    }
+
+    /// Panics if the canary word at the bottom of the kernel stack is
+    /// overwritten, for the stacks without a guard page.
+    pub(crate) fn check_stack_canary(&self) {
+        if self.kstack.as_ref().is_some_and(|s| !s.canary_intact()) {
+            panic!(
+                "stack overflow in task '{}' (canary overwritten)",
+                self.name
+            );
+        }
+    }
+
+    /// Returns the task-local storage, which must only be accessed by the task
+    /// itself.
+    #[inline]
+    pub(crate) fn task_locals(&self) -> *mut TaskLocals {
+        self.task_locals.get()
+    }
+
+    /// Returns the counters of the runtime statistics.
+    #[inline]
+    pub(crate) fn stats_counters(&self) -> &TaskStatsCounters {
+        &self.stats
+    }
+
     /// Notify all tasks that join on this task.
     pub(crate) fn notify_exit(&self, exit_code: i32) {
         self.exit_code.store(exit_code, Ordering::Release);
@@ -431,30 +775,129 @@ impl fmt::Debug for TaskInner {
 impl Drop for TaskInner {
     fn drop(&mut self) {
         debug!("task drop: {}", self.id_name());
+        crate::stats::unregister_task(self.id.as_u64());
     }
 }
 
+/// The word at the bottom of a task stack without a guard page, checked at
+/// each context switch.
+const STACK_CANARY: u64 = 0x5741_434b_4341_4e59;
+
+/// The byte a task stack is filled with when allocated, to find how much of
+/// it has been in use.
+const STACK_PAINT: u8 = 0xa5;
+
+/// The area below the guard page of a task stack. A fault on the guard page
+/// pushes its trap frame and runs the fault handler there, except on x86_64,
+/// where it turns into a double fault taken on a stack of its own.
+const OVERFLOW_AREA_SIZE: usize = if cfg!(target_arch = "x86_64") {
+    0
+} else {
+    PAGE_SIZE_4K
+};
+
+/// A kernel stack of a task.
+///
+/// When paging is available, it is page aligned, with an unmapped guard page
+/// below it, so that an overflow faults. Otherwise, there is a canary word at
+/// its bottom instead.
 struct TaskStack {
     ptr: NonNull<u8>,
     layout: Layout,
+    /// The offset of the bottom of the stack from `ptr`.
+    offset: usize,
+    /// Whether the guard page is unmapped.
+    guarded: bool,
 }
 
 impl TaskStack {
     pub fn alloc(size: usize) -> Self {
-        let layout = Layout::from_size_align(size, 16).unwrap();
-        Self {
+        let guarded = axhal::mem::guard_pages_available();
+        let (layout, offset) = if guarded {
+            let offset = OVERFLOW_AREA_SIZE + PAGE_SIZE_4K;
+            (Layout::from_size_align(offset + size, PAGE_SIZE_4K), offset)
+        } else {
+            (Layout::from_size_align(size, 16), 0)
+        };
+        let layout = layout.unwrap();
+        let mut stack = Self {
             ptr: NonNull::new(unsafe { alloc::alloc::alloc(layout) }).unwrap(),
             layout,
+            offset,
+            guarded,
+        };
+        if guarded {
+            stack.guarded = axhal::mem::set_guard_page(stack.guard(), true);
+        }
+        unsafe {
+            core::ptr::write_bytes(stack.bottom().as_mut_ptr(), STACK_PAINT, size);
         }
+        if !stack.guarded {
+            unsafe { stack.canary_ptr().write(STACK_CANARY) };
+        }
+        stack
+    }
+
+    pub fn size(&self) -> usize {
+        self.layout.size() - self.offset
+    }
+
+    /// Returns the most bytes of the stack that have been in use, i.e., not
+    /// having the paint from the bottom of the stack up to them.
+    ///
+    /// It is an estimate, as a word may be written with the paint.
+    pub fn high_water_mark(&self) -> usize {
+        const PAINT: u64 = u64::from_ne_bytes([STACK_PAINT; 8]);
+        // Skip the canary word.
+        let start = if self.guarded { 0 } else { 8 };
+        let bottom = self.bottom().as_ptr() as *const u64;
+        let words = self.size() / 8;
+        let unused = (start / 8..words)
+            .take_while(|&i| unsafe { bottom.add(i).read_volatile() } == PAINT)
+            .count();
+        self.size() - start - unused * 8
     }
 
     pub const fn top(&self) -> VirtAddr {
         unsafe { core::mem::transmute(self.ptr.as_ptr().add(self.layout.size())) }
     }
+
+    pub fn bottom(&self) -> VirtAddr {
+        VirtAddr::from(self.ptr.as_ptr() as usize + self.offset)
+    }
+
+    /// Returns the start address of the guard page, which is only unmapped
+    /// if `guarded`.
+    fn guard(&self) -> VirtAddr {
+        self.bottom() - PAGE_SIZE_4K
+    }
+
+    /// Whether `vaddr` is in the unmapped guard page.
+    pub fn guard_contains(&self, vaddr: VirtAddr) -> bool {
+        self.guarded && (self.guard()..self.bottom()).contains(&vaddr)
+    }
+
+    fn canary_ptr(&self) -> *mut u64 {
+        self.bottom().as_mut_ptr() as *mut u64
+    }
+
+    /// Whether the canary word is intact, always `true` if there is a guard
+    /// page instead.
+    pub fn canary_intact(&self) -> bool {
+        self.guarded || unsafe { self.canary_ptr().read_volatile() } == STACK_CANARY
+    }
 }
 
 impl Drop for TaskStack {
     fn drop(&mut self) {
+        if self.guarded && !axhal::mem::set_guard_page(self.guard(), false) {
+            // It can not be given back to the allocator.
+            warn!(
+                "failed to map the stack guard page {:#x} back",
+                self.guard()
+            );
+            return;
+        }
         unsafe { alloc::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
     }
 }
@@ -501,6 +944,16 @@ impl CurrentTask {
         axhal::cpu::set_current_task_ptr(ptr);
     }
 
+    /// Makes `task` current again on a CPU that is online again, where it was
+    /// the current task when the CPU went offline. The reference counted as
+    /// the current task was not released, so it is reused.
+    #[cfg(feature = "smp")]
+    pub(crate) unsafe fn restore_current(task: &AxTaskRef) {
+        #[cfg(feature = "tls")]
+        axhal::arch::write_thread_pointer(task.tls.tls_ptr() as usize);
+        axhal::cpu::set_current_task_ptr(Arc::as_ptr(task));
+    }
+
     pub(crate) unsafe fn set_current(prev: Self, next: AxTaskRef) {
         let Self(arc) = prev;
         ManuallyDrop::into_inner(arc); // `call Arc::drop()` to decrease prev task reference count.
@@ -522,6 +975,10 @@ extern "C" fn task_entry() -> ! {
         // Clear the prev task on CPU before running the task entry function.
         crate::run_queue::clear_prev_task_on_cpu();
     }
+    // A new task does not release the guard of the scheduler taken by the
+    // previous task, which ends the preemption-off region of the switch.
+    #[cfg(all(feature = "latency-trace", feature = "preempt"))]
+    axhal::latency::trace_on(axhal::latency::LatencyKind::PreemptOff);
     // Enable irq (if feature "irq" is enabled) before running the task entry function.
     #[cfg(feature = "irq")]
     axhal::arch::enable_irqs();
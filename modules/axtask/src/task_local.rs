@@ -0,0 +1,157 @@
+//! Task-local storage.
+
+use alloc::{boxed::Box, collections::BTreeMap};
+use core::any::Any;
+use core::cell::Cell;
+
+/// Declares task-local storage keys of type [`TaskLocalKey`], in the same
+/// syntax as `thread_local!` of the standard library.
+///
+/// Each task gets its own value of a key, initialized by the expression the
+/// first time the task accesses it, and dropped when the task exits.
+///
+/// # Examples
+///
+/// ```
+/// use core::cell::Cell;
+///
+/// # axtask::init_scheduler();
+/// axtask::task_local! {
+///     static COUNTER: Cell<usize> = Cell::new(0);
+/// }
+///
+/// COUNTER.set(COUNTER.get() + 1);
+/// assert_eq!(COUNTER.get(), 1);
+/// ```
+#[macro_export]
+macro_rules! task_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr; $($rest:tt)*) => {
+        $crate::task_local!($(#[$attr])* $vis static $name: $t = $init);
+        $crate::task_local!($($rest)*);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr) => {
+        $(#[$attr])*
+        $vis static $name: $crate::TaskLocalKey<$t> = {
+            fn __init() -> $t {
+                $init
+            }
+            $crate::TaskLocalKey::new(__init)
+        };
+    };
+}
+
+/// A key of the task-local storage, declared by [`task_local!`].
+///
+/// Values can only be accessed by the task that owns them, through shared
+/// references, so they need interior mutability such as [`Cell`] or
+/// [`RefCell`](core::cell::RefCell) to be modified.
+pub struct TaskLocalKey<T: 'static> {
+    init: fn() -> T,
+}
+
+impl<T: 'static> TaskLocalKey<T> {
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> Self {
+        Self { init }
+    }
+
+    /// Calls `f` with a reference to the value of the current task,
+    /// initializing it if it is the first access.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the scheduler is not initialized, i.e., there is no current
+    /// task.
+    pub fn with<F, R>(&'static self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        let key = self as *const Self as usize;
+        // Safety: only the current task accesses its task-local storage, and
+        // the values are not dropped until it exits.
+        let value =
+            unsafe { TaskLocals::get_or_init(crate::current().task_locals(), key, self.init) };
+        f(unsafe { &*value })
+    }
+}
+
+impl<T: Copy + 'static> TaskLocalKey<Cell<T>> {
+    /// Returns a copy of the value of the current task.
+    pub fn get(&'static self) -> T {
+        self.with(Cell::get)
+    }
+
+    /// Sets the value of the current task.
+    pub fn set(&'static self, value: T) {
+        self.with(|cell| cell.set(value))
+    }
+}
+
+impl<T: 'static> TaskLocalKey<Cell<T>> {
+    /// Replaces the value of the current task, and returns the old one.
+    pub fn replace(&'static self, value: T) -> T {
+        self.with(|cell| cell.replace(value))
+    }
+
+    /// Takes the value of the current task, leaving [`Default::default()`].
+    pub fn take(&'static self) -> T
+    where
+        T: Default,
+    {
+        self.with(Cell::take)
+    }
+}
+
+/// The task-local values of a task, indexed by the addresses of the keys.
+pub(crate) struct TaskLocals {
+    values: BTreeMap<usize, Box<dyn Any>>,
+}
+
+impl TaskLocals {
+    pub const fn new() -> Self {
+        Self {
+            values: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the value of the key, initialized by `init` if it is absent.
+    ///
+    /// The values are boxed, so the pointer stays valid until the value is
+    /// dropped by [`clear`](Self::clear), even if other values are added by
+    /// `init` meanwhile.
+    ///
+    /// # Safety
+    ///
+    /// It must only be called by the task owning the storage. No references
+    /// to the storage are kept while `init` runs, as it may access other keys.
+    pub unsafe fn get_or_init<T: 'static>(
+        this: *mut Self,
+        key: usize,
+        init: fn() -> T,
+    ) -> *const T {
+        if let Some(value) = unsafe { (*this).values.get(&key) } {
+            return (**value).downcast_ref::<T>().unwrap();
+        }
+        let value: Box<dyn Any> = Box::new(init());
+        let value = unsafe { (*this).values.entry(key).or_insert(value) };
+        (**value).downcast_ref::<T>().unwrap()
+    }
+
+    /// Drops all the values, including the ones initialized by the
+    /// destructors of others.
+    ///
+    /// # Safety
+    ///
+    /// It must only be called by the task owning the storage, when it exits.
+    pub unsafe fn clear(this: *mut Self) {
+        loop {
+            let values = core::mem::take(unsafe { &mut (*this).values });
+            if values.is_empty() {
+                break;
+            }
+            // The destructors may access the storage.
+            drop(values);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Mutex, Once};
 
 use crate::{api as axtask, current, WaitQueue};
@@ -104,6 +104,116 @@ fn test_wait_queue() {
     assert!(!current().in_wait_queue());
 }
 
+#[test]
+fn test_wait_queue_fifo() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    const NUM_TASKS: usize = 10;
+
+    static WQ: WaitQueue = WaitQueue::new();
+    static WAITING: AtomicUsize = AtomicUsize::new(0);
+    static GO: AtomicBool = AtomicBool::new(false);
+    static ORDER: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+    let tasks: Vec<_> = (0..NUM_TASKS)
+        .map(|i| {
+            axtask::spawn(move || {
+                WAITING.fetch_add(1, Ordering::Relaxed);
+                WQ.wait_until(|| GO.load(Ordering::Relaxed));
+                ORDER.lock().unwrap().push(i);
+            })
+        })
+        .collect();
+    while WAITING.load(Ordering::Relaxed) < NUM_TASKS {
+        axtask::yield_now();
+    }
+
+    // The first task blocks again in front of the others.
+    assert!(WQ.notify_one(false));
+    axtask::yield_now();
+    // So do all of them, in the order they started to wait.
+    WQ.notify_all(false);
+    axtask::yield_now();
+
+    GO.store(true, Ordering::Relaxed);
+    for _ in 0..NUM_TASKS {
+        assert!(WQ.notify_one(false));
+        axtask::yield_now();
+    }
+    assert!(!WQ.notify_one(false));
+    for task in tasks {
+        task.join();
+    }
+    assert_eq!(*ORDER.lock().unwrap(), (0..NUM_TASKS).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_wait_queue_stress() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    const NUM_PRODUCERS: usize = 4;
+    const NUM_CONSUMERS: usize = 8;
+    const ITEMS_PER_PRODUCER: usize = 1000;
+    const ITEMS_PER_CONSUMER: usize = NUM_PRODUCERS * ITEMS_PER_PRODUCER / NUM_CONSUMERS;
+    /// The most items the others may take while a consumer waits for one.
+    const MAX_WAIT: usize = 4 * NUM_PRODUCERS * NUM_CONSUMERS;
+
+    static WQ: WaitQueue = WaitQueue::new();
+    static ITEMS: AtomicUsize = AtomicUsize::new(0);
+    static TAKEN: AtomicUsize = AtomicUsize::new(0);
+
+    let producers: Vec<_> = (0..NUM_PRODUCERS)
+        .map(|_| {
+            axtask::spawn(|| {
+                for _ in 0..ITEMS_PER_PRODUCER {
+                    ITEMS.fetch_add(1, Ordering::Relaxed);
+                    WQ.notify_one(false);
+                    axtask::yield_now();
+                }
+            })
+        })
+        .collect();
+    let consumers: Vec<_> = (0..NUM_CONSUMERS)
+        .map(|_| {
+            axtask::spawn(|| {
+                let mut max_wait = 0;
+                for _ in 0..ITEMS_PER_CONSUMER {
+                    let start = TAKEN.load(Ordering::Relaxed);
+                    WQ.wait_until(|| {
+                        ITEMS
+                            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                                n.checked_sub(1)
+                            })
+                            .is_ok()
+                    });
+                    let waited = TAKEN.fetch_add(1, Ordering::Relaxed) - start;
+                    max_wait = max_wait.max(waited);
+                    axtask::yield_now();
+                }
+                axtask::exit(max_wait as _);
+            })
+        })
+        .collect();
+
+    for producer in producers {
+        producer.join();
+    }
+    for consumer in consumers {
+        let max_wait = consumer.join().unwrap() as usize;
+        assert!(
+            max_wait <= MAX_WAIT,
+            "a consumer waited for {max_wait} items"
+        );
+    }
+    assert_eq!(
+        TAKEN.load(Ordering::Relaxed),
+        NUM_PRODUCERS * ITEMS_PER_PRODUCER
+    );
+    assert_eq!(ITEMS.load(Ordering::Relaxed), 0);
+}
+
 #[test]
 fn test_task_join() {
     let _lock = SERIAL.lock();
@@ -127,4 +237,232 @@ fn test_task_join() {
     for i in 0..NUM_TASKS {
         assert_eq!(tasks[i].join(), Some(i as _));
     }
+    assert_eq!(axtask::join(&tasks[0]), Ok(0));
+    assert_eq!(
+        axtask::join(current().as_task_ref()),
+        Err(axtask::JoinError::Deadlock)
+    );
+}
+
+#[test]
+fn test_task_local() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    use core::cell::Cell;
+
+    crate::task_local! {
+        static COUNTER: Cell<usize> = Cell::new(0);
+    }
+
+    const NUM_INCREMENTS: usize = 100;
+    let tasks: Vec<_> = (0..2)
+        .map(|i| {
+            axtask::spawn(move || {
+                for _ in 0..NUM_INCREMENTS * (i + 1) {
+                    COUNTER.set(COUNTER.get() + 1);
+                    axtask::yield_now();
+                }
+                axtask::exit(COUNTER.get() as _);
+            })
+        })
+        .collect();
+
+    assert_eq!(tasks[0].join(), Some(NUM_INCREMENTS as _));
+    assert_eq!(tasks[1].join(), Some(2 * NUM_INCREMENTS as _));
+    assert_eq!(COUNTER.get(), 0);
+}
+
+#[test]
+fn test_for_each_task() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static WQ: WaitQueue = WaitQueue::new();
+    static GO: AtomicBool = AtomicBool::new(false);
+
+    let task = axtask::spawn_raw(
+        || {
+            WQ.wait_until(|| GO.load(Ordering::Relaxed));
+        },
+        "listed".into(),
+        0x4000,
+    );
+    axtask::yield_now();
+    let id = task.id().as_u64();
+
+    let mut found = None;
+    axtask::for_each_task(|info| {
+        if info.id == id {
+            found = Some(info.clone());
+        }
+    });
+    let info = found.unwrap();
+    assert_eq!(info.name, "listed");
+    assert_eq!(info.state, axtask::TaskState::Blocked);
+    assert_eq!(info.stack_size, 0x4000);
+    assert!(info.stack_used > 0 && info.stack_used < info.stack_size);
+
+    GO.store(true, Ordering::Relaxed);
+    WQ.notify_one(false);
+    task.join();
+    drop(task);
+    // Another exit wakes up the GC task to drop it.
+    axtask::spawn(|| {}).join();
+    axtask::yield_now();
+    let mut listed = false;
+    axtask::for_each_task(|info| listed |= info.id == id);
+    assert!(!listed);
+}
+
+#[test]
+fn test_set_scheduler() {
+    use axtask::Policy;
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    let task = axtask::spawn(|| {});
+    assert_eq!(task.policy(), Policy::Normal);
+    assert!(!axtask::set_scheduler(&task, Policy::Fifo(0)));
+    assert!(!axtask::set_scheduler(&task, Policy::Fifo(100)));
+    let rt = axtask::set_scheduler(&task, Policy::Fifo(axtask::MAX_RT_PRIORITY));
+    assert_eq!(rt, cfg!(feature = "sched_rt"));
+    if rt {
+        assert_eq!(task.policy(), Policy::Fifo(axtask::MAX_RT_PRIORITY));
+    }
+    assert!(axtask::set_scheduler(&task, Policy::Normal));
+    assert_eq!(task.policy(), Policy::Normal);
+    task.join();
+}
+
+#[test]
+fn test_wait_any() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static WQ1: WaitQueue = WaitQueue::new();
+    static WQ2: WaitQueue = WaitQueue::new();
+    static OTHER_WOKEN: AtomicBool = AtomicBool::new(false);
+
+    // Ready before sleeping.
+    let res = axtask::wait_any_with(&[&WQ1, &WQ2], None, || true);
+    assert_eq!(res.result, axtask::WaitResult::Notified);
+    assert!(res.fired.is_empty());
+
+    // Another waiter on `WQ1` gets its notification after `WQ2` wakes up
+    // the main task, which is in front of it.
+    axtask::spawn(|| {
+        WQ1.wait();
+        OTHER_WOKEN.store(true, Ordering::Release);
+    });
+    let notifier = axtask::spawn(|| {
+        assert!(WQ2.notify_one(false));
+        assert!(WQ1.notify_one(false));
+    });
+    let res = axtask::wait_any(&[&WQ1, &WQ2], None);
+    assert_eq!(res.result, axtask::WaitResult::Notified);
+    assert_eq!(res.fired, [1]);
+    notifier.join();
+    while !OTHER_WOKEN.load(Ordering::Acquire) {
+        axtask::yield_now();
+    }
+    // No waiter is left behind.
+    assert!(!WQ1.notify_one(false));
+    assert!(!WQ2.notify_one(false));
+    assert!(!current().in_wait_queue());
+}
+
+#[test]
+fn test_scope() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    const NUM_TASKS: usize = 4;
+    const CHUNK_SIZE: usize = 100;
+
+    // Each task fills its own slice of the buffer of the main task.
+    let mut buf = [0usize; NUM_TASKS * CHUNK_SIZE];
+    let base = 1000;
+    let sum = axtask::scope(|s| {
+        let handles: Vec<_> = buf
+            .chunks_mut(CHUNK_SIZE)
+            .enumerate()
+            .map(|(i, chunk)| {
+                s.spawn(move || {
+                    for (j, x) in chunk.iter_mut().enumerate() {
+                        *x = base + i * CHUNK_SIZE + j;
+                        axtask::yield_now();
+                    }
+                    chunk.iter().sum::<usize>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .sum::<usize>()
+    });
+    assert!(buf.iter().enumerate().all(|(i, &x)| x == base + i));
+    assert_eq!(sum, buf.iter().sum());
+
+    // Tasks not joined by their handles, including the ones spawned by the
+    // scoped tasks, are joined by the scope.
+    let count = AtomicUsize::new(0);
+    axtask::scope(|s| {
+        s.spawn(|| {
+            axtask::yield_now();
+            s.spawn(|| {
+                axtask::yield_now();
+                count.fetch_add(1, Ordering::Relaxed);
+            });
+            count.fetch_add(1, Ordering::Relaxed);
+        });
+        // A task terminated without returning does not fail the scope if
+        // it is joined by its handle.
+        assert!(s.spawn(|| axtask::exit(1)).join().is_none());
+    });
+    assert_eq!(count.load(Ordering::Relaxed), 2);
+}
+
+/// Counts the timer interrupts of an idle CPU over a minute, with the
+/// scheduler ticks and with them stopped by the `tickless` feature.
+#[cfg(feature = "tickless")]
+#[test]
+fn test_tickless_idle_interrupts() {
+    use crate::timers::next_timer_deadline;
+    use axhal::time::{tick_frequency, NANOS_PER_SEC};
+
+    const SPAN: u64 = 60 * NANOS_PER_SEC;
+
+    /// Follows the timer from one interrupt to the next, as programmed by
+    /// the handler, with the sleepers waking up at `wakeups`.
+    fn interrupts(tick: bool, wakeups: &[u64]) -> u64 {
+        let (mut now, mut count) = (0, 0);
+        loop {
+            let next = wakeups.iter().copied().find(|&t| t > now);
+            now = next_timer_deadline(now, next, tick);
+            if now > SPAN {
+                return count;
+            }
+            count += 1;
+        }
+    }
+
+    let ticking = interrupts(true, &[]);
+    let tickless = interrupts(false, &[]);
+    println!(
+        "tickless: idle timer interrupts per second: {} with ticks, {:.3} without",
+        ticking / 60,
+        tickless as f64 / 60.0
+    );
+    assert!(ticking >= 60 * tick_frequency());
+    // The timer only fires when it has to be programmed again.
+    assert_eq!(tickless, 1);
+    // And when a sleeper wakes up, after which it is programmed a minute
+    // ahead again.
+    assert_eq!(
+        interrupts(false, &[NANOS_PER_SEC / 2, 10 * NANOS_PER_SEC]),
+        2
+    );
 }
@@ -1,66 +1,502 @@
-use core::sync::atomic::{AtomicU64, Ordering};
+//! Timers, and one-shot and periodic timer callbacks.
+//!
+//! Each CPU keeps the timers set on it ordered by their deadlines, so setting
+//! and cancelling a timer take O(log n), and a timer tick only visits the
+//! timers that are due. A task woken up before its deadline, e.g., by
+//! [`WaitQueue::notify_one`](crate::WaitQueue::notify_one), removes its timer
+//! from the queue of the CPU it was set on.
+//!
+//! The timers also queue the delayed work items of
+//! [`workqueue::schedule_delayed`](crate::workqueue::schedule_delayed), and
+//! the callbacks set by [`set_oneshot`] and [`set_periodic`], which run in a
+//! dedicated timer task rather than in interrupt context.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use core::time::Duration;
+//!
+//! let blink = axtask::timers::set_periodic(Duration::from_millis(500), || {
+//!     toggle_led();
+//! });
+//! // ...
+//! axtask::timers::cancel(&blink);
+//! ```
 
-use kernel_guard::NoOp;
-use lazyinit::LazyInit;
-use timer_list::{TimeValue, TimerEvent, TimerList};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::{boxed::Box, sync::Arc};
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
 
-use axhal::time::wall_time;
+#[cfg(feature = "smp")]
+use alloc::vec::Vec;
 
-use crate::{select_run_queue, AxTaskRef};
+use kernel_guard::{NoOp, NoPreemptIrqSave};
+use kspin::SpinNoIrq;
+
+use axhal::cpu::this_cpu_id;
+use axhal::time::TimeValue;
+
+use crate::workqueue::WorkItem;
+use crate::{select_run_queue, AxTaskRef, WaitQueue};
 
 static TIMER_TICKET_ID: AtomicU64 = AtomicU64::new(1);
 
-percpu_static! {
-    TIMER_LIST: LazyInit<TimerList<TaskWakeupEvent>> = LazyInit::new(),
+/// The event when a timer expires.
+enum TimerEvent {
+    /// Wakes up a sleeping task, or a task waiting with a timeout.
+    Wakeup(AxTaskRef),
+    /// Schedules a delayed work item.
+    Work(&'static WorkItem),
+    /// Runs a timer callback in the timer task.
+    Callback(Arc<TimerCallback>),
 }
 
-struct TaskWakeupEvent {
+/// Where a timer is in the timer queues.
+#[derive(Clone, Copy)]
+struct TimerLocation {
+    cpu_id: usize,
+    deadline: u64,
     ticket_id: u64,
-    task: AxTaskRef,
-}
-
-impl TimerEvent for TaskWakeupEvent {
-    fn callback(self, _now: TimeValue) {
-        // Ignore the timer event if timeout was set but not triggered
-        // (wake up by `WaitQueue::notify()`).
-        // Judge if this timer event is still valid by checking the ticket ID.
-        if self.task.timer_ticket() != self.ticket_id {
-            // Timer ticket ID is not matched.
-            // Just ignore this timer event and return.
-            return;
+}
+
+/// The timer of a work item or a timer callback, which has at most one timer
+/// at a time.
+pub(crate) struct TimerSlot(SpinNoIrq<Option<TimerLocation>>);
+
+impl TimerSlot {
+    pub const fn new() -> Self {
+        Self(SpinNoIrq::new(None))
+    }
+
+    /// Whether a timer is set and has not expired.
+    pub fn is_armed(&self) -> bool {
+        self.0.lock().is_some()
+    }
+
+    /// Records the timer, unless there is one.
+    fn arm(&self, timer: TimerLocation) -> bool {
+        let mut slot = self.0.lock();
+        if slot.is_some() {
+            return false;
         }
+        *slot = Some(timer);
+        true
+    }
 
-        // Timer ticket match.
-        select_run_queue::<NoOp>(&self.task).unblock_task(self.task, true)
+    /// Clears the timer, and returns it if any.
+    fn disarm(&self) -> Option<TimerLocation> {
+        self.0.lock().take()
+    }
+
+    /// Clears the timer if it is the expired one, and returns whether it is,
+    /// i.e., it has not been cancelled.
+    fn expire(&self, ticket_id: u64) -> bool {
+        let mut slot = self.0.lock();
+        if slot.is_some_and(|t| t.ticket_id == ticket_id) {
+            *slot = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Records that the timer has been moved to the timer queue of another
+    /// CPU.
+    #[cfg(feature = "smp")]
+    fn relocate(&self, ticket_id: u64, cpu_id: usize) {
+        if let Some(timer) = self.0.lock().as_mut() {
+            if timer.ticket_id == ticket_id {
+                timer.cpu_id = cpu_id;
+            }
+        }
     }
 }
 
-pub fn set_alarm_wakeup(deadline: TimeValue, task: AxTaskRef) {
-    TIMER_LIST.with_current(|timer_list| {
-        let ticket_id = TIMER_TICKET_ID.fetch_add(1, Ordering::AcqRel);
-        task.set_timer_ticket(ticket_id);
-        timer_list.set(deadline, TaskWakeupEvent { ticket_id, task });
-    })
+/// Timers ordered by the deadline, in monotonic nanoseconds, and the ticket
+/// ID, which tells apart the timers of the same deadline.
+type TimerQueue = BTreeMap<(u64, u64), TimerEvent>;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_QUEUE: SpinNoIrq<TimerQueue> = SpinNoIrq::new(BTreeMap::new());
+/// The timer queue of each CPU, locked as timers may be cancelled on other
+/// CPUs.
+static TIMER_QUEUES: [SpinNoIrq<TimerQueue>; axconfig::SMP] = [EMPTY_QUEUE; axconfig::SMP];
+
+/// Timers left by offline CPUs, adopted by the next CPU that checks its
+/// timers.
+#[cfg(feature = "smp")]
+static ORPHAN_TIMERS: SpinNoIrq<Vec<((u64, u64), TimerEvent)>> = SpinNoIrq::new(Vec::new());
+#[cfg(feature = "smp")]
+static HAS_ORPHAN_TIMERS: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "tickless")]
+percpu_static! {
+    /// The monotonic time in nanoseconds the timer is programmed to fire at.
+    TIMER_DEADLINE: u64 = u64::MAX,
+}
+
+/// The longest time the timer is programmed ahead, so that the deadline fits
+/// in the timer hardware after the conversion to its ticks.
+#[cfg(feature = "tickless")]
+const MAX_TIMER_NANOS: u64 = 60 * axhal::time::NANOS_PER_SEC;
+
+/// Converts a deadline in the monotonic time to nanoseconds, saturated.
+fn to_monotonic_nanos(deadline: TimeValue) -> u64 {
+    deadline.as_nanos().min(u64::MAX as u128) as u64
+}
+
+/// Wakes up the task of an expired timer, unless the timer has been
+/// cancelled meanwhile.
+fn wakeup(ticket_id: u64, task: AxTaskRef) {
+    // Judge if this timer is still valid by checking the ticket ID, it may
+    // have been cancelled by `WaitQueue::notify()` after it was taken out of
+    // the timer queue.
+    if task.timer_ticket() == ticket_id {
+        select_run_queue::<NoOp>(&task).unblock_task(task, true)
+    }
+}
+
+/// Sets a timer on the current CPU to wake up the task at `deadline`.
+pub(crate) fn set_alarm_wakeup(deadline: TimeValue, task: AxTaskRef) {
+    let _guard = NoPreemptIrqSave::new();
+    let cpu_id = this_cpu_id();
+    let deadline = to_monotonic_nanos(deadline);
+    let ticket_id = TIMER_TICKET_ID.fetch_add(1, Ordering::AcqRel);
+    task.set_timer_ticket(ticket_id);
+    task.set_timer_location(cpu_id, deadline);
+    TIMER_QUEUES[cpu_id]
+        .lock()
+        .insert((deadline, ticket_id), TimerEvent::Wakeup(task));
+    #[cfg(feature = "tickless")]
+    program_timer_before(deadline);
+}
+
+/// Sets a timer on the current CPU to handle the event at `deadline`, and
+/// records it in `slot`.
+///
+/// Returns `false` if the slot has a timer already.
+fn set_slot_timer(deadline: TimeValue, slot: &TimerSlot, event: TimerEvent) -> bool {
+    let _guard = NoPreemptIrqSave::new();
+    let cpu_id = this_cpu_id();
+    let deadline = to_monotonic_nanos(deadline);
+    let ticket_id = TIMER_TICKET_ID.fetch_add(1, Ordering::AcqRel);
+    let timer = TimerLocation {
+        cpu_id,
+        deadline,
+        ticket_id,
+    };
+    if !slot.arm(timer) {
+        return false;
+    }
+    // If it is cancelled meanwhile, the timer is invalidated by the ticket ID.
+    TIMER_QUEUES[cpu_id]
+        .lock()
+        .insert((deadline, ticket_id), event);
+    #[cfg(feature = "tickless")]
+    program_timer_before(deadline);
+    true
+}
+
+/// Cancels the timer recorded in `slot`, if any, and returns whether there
+/// is one.
+fn cancel_slot_timer(slot: &TimerSlot) -> bool {
+    match slot.disarm() {
+        Some(timer) => {
+            TIMER_QUEUES[timer.cpu_id]
+                .lock()
+                .remove(&(timer.deadline, timer.ticket_id));
+            true
+        }
+        None => false,
+    }
+}
+
+/// Sets a timer on the current CPU to schedule the work item at `deadline`.
+///
+/// Returns `false` if the work item has a timer already.
+pub(crate) fn set_work_timer(deadline: TimeValue, work: &'static WorkItem) -> bool {
+    set_slot_timer(deadline, work.timer(), TimerEvent::Work(work))
+}
+
+/// Cancels the timer of the work item set by [`set_work_timer`], if any, and
+/// returns whether there is one.
+pub(crate) fn cancel_work_timer(work: &'static WorkItem) -> bool {
+    cancel_slot_timer(work.timer())
+}
+
+/// Cancels the timer of the task set by [`set_alarm_wakeup`], if any, and
+/// removes it from the timer queue.
+pub(crate) fn cancel_alarm_wakeup(task: &AxTaskRef) {
+    let ticket_id = task.timer_ticket();
+    if ticket_id == 0 {
+        return;
+    }
+    task.timer_ticket_expired();
+    // The timer may have expired, or be moved by `hand_over_events()`, in
+    // which case the expired ticket ID invalidates it.
+    let (cpu_id, deadline) = task.timer_location();
+    TIMER_QUEUES[cpu_id].lock().remove(&(deadline, ticket_id));
 }
 
-pub fn check_events() {
+/// Handles the expired timers of the current CPU.
+pub(crate) fn check_events() {
+    #[cfg(feature = "smp")]
+    adopt_orphan_timers();
+    let queue = &TIMER_QUEUES[this_cpu_id()];
+    let now = axhal::time::monotonic_time_nanos();
     loop {
-        let now = wall_time();
-        let event = unsafe {
-            // Safety: IRQs are disabled at this time.
-            TIMER_LIST.current_ref_mut_raw()
+        // Do not handle the event with the timer queue locked.
+        let expired = match queue.lock().first_entry() {
+            Some(entry) if entry.key().0 <= now => entry.remove_entry(),
+            _ => break,
+        };
+        match expired {
+            ((_, ticket_id), TimerEvent::Wakeup(task)) => wakeup(ticket_id, task),
+            ((_, ticket_id), TimerEvent::Work(work)) => {
+                if work.timer().expire(ticket_id) {
+                    crate::workqueue::schedule(work);
+                }
+            }
+            ((deadline, ticket_id), TimerEvent::Callback(callback)) => {
+                if callback.timer.expire(ticket_id) {
+                    READY_CALLBACKS.lock().push_back((callback, deadline));
+                    TIMER_TASK_WQ.notify_one(false);
+                }
+            }
         }
-        .expire_one(now);
-        if let Some((_deadline, event)) = event {
-            event.callback(now);
-        } else {
-            break;
+    }
+}
+
+/// Programs the timer of the current CPU to fire at `deadline`, unless it is
+/// programmed to fire earlier.
+#[cfg(feature = "tickless")]
+fn program_timer_before(deadline: u64) {
+    // Safety: IRQs are disabled or the task is pinned by the kernel guard
+    // of the callers.
+    let programmed = unsafe { TIMER_DEADLINE.read_current_raw() };
+    if deadline < programmed {
+        unsafe { TIMER_DEADLINE.write_current_raw(deadline) };
+        axhal::time::set_oneshot_timer(deadline);
+    }
+}
+
+/// Programs the timer of the current CPU for the earliest pending timer, or
+/// the next scheduler tick if `tick` is set, i.e., the CPU is not idle, nor
+/// running a single task with the `dyntick` feature. Called on each timer
+/// interrupt, as the timer is one-shot.
+#[cfg(feature = "tickless")]
+pub(crate) fn program_next_timer(tick: bool) {
+    let now = axhal::time::monotonic_time_nanos();
+    let next = TIMER_QUEUES[this_cpu_id()]
+        .lock()
+        .first_key_value()
+        .map(|(&(next, _), _)| next);
+    let deadline = next_timer_deadline(now, next, tick);
+    // Safety: IRQs are disabled at this time.
+    unsafe { TIMER_DEADLINE.write_current_raw(deadline) };
+    axhal::time::set_oneshot_timer(deadline);
+}
+
+/// Returns the deadline the timer is programmed for at `now`, given the
+/// earliest pending timer and whether the scheduler ticks are needed.
+#[cfg(feature = "tickless")]
+pub(crate) fn next_timer_deadline(now: u64, next: Option<u64>, tick: bool) -> u64 {
+    let mut deadline = now + MAX_TIMER_NANOS;
+    if let Some(next) = next {
+        deadline = deadline.min(next);
+    }
+    if tick {
+        deadline = deadline.min(now + axhal::time::tick_nanos());
+    }
+    deadline
+}
+
+/// Resumes the scheduler ticks when the CPU leaves the idle task, or another
+/// task is ready to run on it with the `dyntick` feature.
+#[cfg(feature = "tickless")]
+pub(crate) fn resume_tick() {
+    program_timer_before(axhal::time::monotonic_time_nanos() + axhal::time::tick_nanos());
+}
+
+/// Moves the pending timers of the current CPU, which is going offline, to
+/// the orphan list for other CPUs to adopt.
+#[cfg(feature = "smp")]
+pub(crate) fn hand_over_events() {
+    let timers = core::mem::take(&mut *TIMER_QUEUES[this_cpu_id()].lock());
+    let mut orphans = ORPHAN_TIMERS.lock();
+    orphans.extend(timers);
+    HAS_ORPHAN_TIMERS.store(!orphans.is_empty(), Ordering::Release);
+    #[cfg(feature = "tickless")]
+    unsafe {
+        TIMER_DEADLINE.write_current_raw(u64::MAX)
+    };
+}
+
+/// Moves the timers left by offline CPUs to the current CPU.
+#[cfg(feature = "smp")]
+fn adopt_orphan_timers() {
+    if !HAS_ORPHAN_TIMERS.load(Ordering::Acquire) {
+        return;
+    }
+    let mut orphans = ORPHAN_TIMERS.lock();
+    HAS_ORPHAN_TIMERS.store(false, Ordering::Release);
+    let cpu_id = this_cpu_id();
+    let mut queue = TIMER_QUEUES[cpu_id].lock();
+    for ((deadline, ticket_id), event) in orphans.drain(..) {
+        match &event {
+            TimerEvent::Wakeup(task) => task.set_timer_location(cpu_id, deadline),
+            TimerEvent::Work(work) => work.timer().relocate(ticket_id, cpu_id),
+            TimerEvent::Callback(callback) => callback.timer.relocate(ticket_id, cpu_id),
         }
+        queue.insert((deadline, ticket_id), event);
     }
 }
 
-pub fn init() {
-    TIMER_LIST.with_current(|timer_list| {
-        timer_list.init_once(TimerList::new());
+/// A callback set by [`set_oneshot`] or [`set_periodic`].
+struct TimerCallback {
+    /// Only called by the timer task.
+    func: UnsafeCell<Box<dyn FnMut() + Send>>,
+    /// The period in nanoseconds, or 0 for a one-shot callback.
+    period: u64,
+    timer: TimerSlot,
+    cancelled: AtomicBool,
+    /// Whether the timer task is calling it.
+    running: AtomicBool,
+}
+
+// Safety: `func` is only accessed by the timer task.
+unsafe impl Sync for TimerCallback {}
+
+/// A handle to a timer callback, to [`cancel`] it.
+///
+/// Dropping the handle does not cancel the callback.
+pub struct TimerHandle(Arc<TimerCallback>);
+
+/// Callbacks whose timers have expired, with their deadlines.
+static READY_CALLBACKS: SpinNoIrq<VecDeque<(Arc<TimerCallback>, u64)>> =
+    SpinNoIrq::new(VecDeque::new());
+/// The timer task waits here for the callbacks.
+static TIMER_TASK_WQ: WaitQueue = WaitQueue::new();
+/// [`cancel`] waits here for the running callback to complete.
+static CALLBACK_DONE_WQ: WaitQueue = WaitQueue::new();
+
+static TIMER_TASK_STARTED: AtomicBool = AtomicBool::new(false);
+/// The ID of the timer task, 0 before it starts.
+static TIMER_TASK_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Calls `f` once in the timer task after the given duration.
+pub fn set_oneshot<F>(after: Duration, f: F) -> TimerHandle
+where
+    F: FnOnce() + Send + 'static,
+{
+    let mut f = Some(f);
+    set_callback(after, 0, move || {
+        if let Some(f) = f.take() {
+            f()
+        }
+    })
+}
+
+/// Calls `f` in the timer task every `period`, starting after one period.
+///
+/// The deadlines are multiples of the period since it is set, so they do not
+/// drift over time. If the callback is late for more than one period, the
+/// missed calls are coalesced into one.
+///
+/// # Panics
+///
+/// Panics if the period is zero.
+pub fn set_periodic<F>(period: Duration, f: F) -> TimerHandle
+where
+    F: FnMut() + Send + 'static,
+{
+    let period = period.as_nanos().min(u64::MAX as u128) as u64;
+    assert!(period > 0, "the period of a timer must not be zero");
+    set_callback(Duration::from_nanos(period), period, f)
+}
+
+/// Cancels the timer callback.
+///
+/// When it returns, the callback is not running and will not be called
+/// again, unless it is called by the callback itself, which keeps running
+/// until it returns.
+pub fn cancel(handle: &TimerHandle) {
+    let callback = &handle.0;
+    // Pairs with the timer task, which checks it after setting `running`.
+    callback.cancelled.store(true, Ordering::SeqCst);
+    cancel_slot_timer(&callback.timer);
+    if crate::current().id().as_u64() != TIMER_TASK_ID.load(Ordering::Acquire) {
+        CALLBACK_DONE_WQ.wait_until_noncancelable(|| !callback.running.load(Ordering::SeqCst));
+    }
+    // It may have been rearmed by the last call.
+    cancel_slot_timer(&callback.timer);
+}
+
+fn set_callback<F>(after: Duration, period: u64, f: F) -> TimerHandle
+where
+    F: FnMut() + Send + 'static,
+{
+    start_timer_task();
+    let callback = Arc::new(TimerCallback {
+        func: UnsafeCell::new(Box::new(f)),
+        period,
+        timer: TimerSlot::new(),
+        cancelled: AtomicBool::new(false),
+        running: AtomicBool::new(false),
     });
+    let deadline = axhal::time::monotonic_time() + after;
+    set_slot_timer(
+        deadline,
+        &callback.timer,
+        TimerEvent::Callback(callback.clone()),
+    );
+    TimerHandle(callback)
+}
+
+/// Spawns the timer task on the first use of the timer callbacks.
+fn start_timer_task() {
+    if !TIMER_TASK_STARTED.swap(true, Ordering::AcqRel) {
+        crate::spawn_raw(timer_task_entry, "timer".into(), axconfig::TASK_STACK_SIZE);
+    }
+}
+
+/// The routine of the timer task, which calls the callbacks whose timers
+/// have expired.
+fn timer_task_entry() {
+    TIMER_TASK_ID.store(crate::current().id().as_u64(), Ordering::Release);
+    loop {
+        TIMER_TASK_WQ.wait_until_noncancelable(|| !READY_CALLBACKS.lock().is_empty());
+        loop {
+            let ready = READY_CALLBACKS.lock().pop_front();
+            let Some((callback, deadline)) = ready else {
+                break;
+            };
+            run_callback(&callback, deadline);
+        }
+    }
+}
+
+fn run_callback(callback: &Arc<TimerCallback>, deadline: u64) {
+    // Pairs with `cancel()`, which checks `running` after setting
+    // `cancelled`.
+    callback.running.store(true, Ordering::SeqCst);
+    if !callback.cancelled.load(Ordering::SeqCst) {
+        // Safety: only the timer task calls it.
+        unsafe { (*callback.func.get())() };
+        if callback.period > 0 && !callback.cancelled.load(Ordering::SeqCst) {
+            // Skip the periods that have passed.
+            let now = axhal::time::monotonic_time_nanos();
+            let missed = now.saturating_sub(deadline) / callback.period;
+            let next = deadline + (missed + 1) * callback.period;
+            set_slot_timer(
+                TimeValue::from_nanos(next),
+                &callback.timer,
+                TimerEvent::Callback(callback.clone()),
+            );
+        }
+    }
+    callback.running.store(false, Ordering::SeqCst);
+    CALLBACK_DONE_WQ.notify_all(false);
 }
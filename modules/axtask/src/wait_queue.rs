@@ -1,5 +1,8 @@
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
 
 use kernel_guard::{NoOp, NoPreemptIrqSave};
 use kspin::{SpinNoIrq, SpinNoIrqGuard};
@@ -28,12 +31,97 @@ use crate::{current_run_queue, select_run_queue, AxTaskRef, CurrentTask};
 /// WQ.wait(); // block until `notify()` is called
 /// assert_eq!(VALUE.load(Ordering::Relaxed), 1);
 /// ```
+///
+/// # Wakeups
+///
+/// The waiters are woken up in the order they started to wait, i.e.,
+/// [`notify_one`](WaitQueue::notify_one) wakes up the one that has waited the
+/// longest. A task woken up by it in [`wait_until`](WaitQueue::wait_until)
+/// that finds the condition still false blocks again in the place it had,
+/// instead of behind the tasks that started to wait later.
+///
+/// [`notify_all`](WaitQueue::notify_all) wakes up the tasks in the queue when
+/// it is called, not the ones that start to wait meanwhile, e.g., the woken
+/// ones that block again.
+///
+/// A task can also wait on several queues at once by [`wait_any`]. The first
+/// queue that notifies it wakes it up, and a `notify_one` on another queue
+/// meanwhile goes to the next waiter there.
+///
+/// The condition of [`wait_until`](WaitQueue::wait_until) is checked with the
+/// queue locked, and checked again each time the task is woken up, so a
+/// notification after the condition becomes true is never missed, even if
+/// it comes between the check and the task blocking. A plain
+/// [`wait`](WaitQueue::wait) misses the notifications before it, so the
+/// condition it waits for must be checked with `wait_until` instead of
+/// before it.
+///
+/// # Cancellation
+///
+/// The waits are cancellation points: if the current task is requested to
+/// cancel by [`cancel`](crate::cancel), they return [`WaitResult::Canceled`]
+/// at once, before or while blocking. Notifications are not lost meanwhile,
+/// a task both notified and canceled gets [`WaitResult::Notified`]. Only
+/// [`wait_until_noncancelable`](WaitQueue::wait_until_noncancelable) keeps
+/// waiting, for the code that cannot back out.
 pub struct WaitQueue {
     queue: SpinNoIrq<VecDeque<AxTaskRef>>,
 }
 
+/// Why a wait on a [`WaitQueue`] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitResult {
+    /// The task was notified, or the condition became true.
+    Notified,
+    /// The timeout elapsed first.
+    TimedOut,
+    /// The task was requested to cancel first, see [`cancel`](crate::cancel).
+    Canceled,
+}
+
+impl WaitResult {
+    /// Whether the timeout elapsed first.
+    pub const fn timed_out(self) -> bool {
+        matches!(self, Self::TimedOut)
+    }
+
+    /// Whether the task was requested to cancel first.
+    pub const fn canceled(self) -> bool {
+        matches!(self, Self::Canceled)
+    }
+}
+
+/// Why a wait on several wait queues by [`wait_any`] returned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WaitAnyResult {
+    /// Why the wait returned, [`WaitResult::Notified`] if any of the queues
+    /// notified the task, or the readiness condition was true at first.
+    pub result: WaitResult,
+    /// The indices of the queues that notified the task, in ascending order,
+    /// empty unless the task slept and was notified.
+    pub fired: Vec<usize>,
+}
+
 pub(crate) type WaitQueueGuard<'a> = SpinNoIrqGuard<'a, VecDeque<AxTaskRef>>;
 
+/// Gives the current task a new place behind all the waiters, at the start
+/// of a wait.
+fn start_wait(curr: &CurrentTask) {
+    static NEXT_WAIT_TICKET: AtomicU64 = AtomicU64::new(1);
+    curr.set_wait_ticket(NEXT_WAIT_TICKET.fetch_add(1, Ordering::Relaxed));
+}
+
+/// Puts the task into the wait queue in the place of its wait ticket, which
+/// is the back of the queue unless it blocks again in the same wait.
+pub(crate) fn push_waiter(wq: &mut VecDeque<AxTaskRef>, task: AxTaskRef) {
+    let ticket = task.wait_ticket();
+    let index = wq
+        .iter()
+        .rposition(|t| t.wait_ticket() < ticket)
+        .map_or(0, |i| i + 1);
+    wq.insert(index, task);
+}
+
 impl WaitQueue {
     /// Creates an empty wait queue.
     pub const fn new() -> Self {
@@ -51,64 +139,141 @@ impl WaitQueue {
 
     /// Cancel events by removing the task from the wait queue.
     /// If `from_timer_list` is true, try to remove the task from the timer list.
-    fn cancel_events(&self, curr: CurrentTask, _from_timer_list: bool) {
+    ///
+    /// Returns `true` if the task was still in the wait queue, i.e., it was
+    /// not notified. It is decided with the wait queue locked, so a racing
+    /// `notify()` either has removed the task or will not find it.
+    fn cancel_events(&self, curr: CurrentTask, _from_timer_list: bool) -> bool {
         // A task can be wake up only one events (timer or `notify()`), remove
-        // the event from another queue.
-        if curr.in_wait_queue() {
-            // wake up by timer (timeout).
-            self.queue.lock().retain(|t| !curr.ptr_eq(t));
-            curr.set_in_wait_queue(false);
+        // the event from another queue. Only notifiers clear the flag once it
+        // is set, with the wait queue locked.
+        let mut in_wait_queue = curr.in_wait_queue();
+        if in_wait_queue {
+            let mut wq = self.queue.lock();
+            in_wait_queue = curr.in_wait_queue();
+            if in_wait_queue {
+                // wake up by timer (timeout).
+                wq.retain(|t| !curr.ptr_eq(t));
+                curr.set_in_wait_queue(false);
+            }
         }
 
         // Try to cancel a timer event from timer lists.
-        // Just mark task's current timer ticket ID as expired.
         #[cfg(feature = "irq")]
         if _from_timer_list {
-            curr.timer_ticket_expired();
-            // Note:
-            //  this task is still not removed from timer list of target CPU,
-            //  which may cause some redundant timer events because it still needs to
-            //  go through the process of expiring an event from the timer list and invoking the callback.
-            //  (it can be considered a lazy-removal strategy, it will be ignored when it is about to take effect.)
+            crate::timers::cancel_alarm_wakeup(curr.as_task_ref());
         }
+        in_wait_queue
     }
 
     /// Blocks the current task and put it into the wait queue, until other task
-    /// notifies it.
-    pub fn wait(&self) {
-        current_run_queue::<NoPreemptIrqSave>().blocked_resched(self.queue.lock());
-        self.cancel_events(crate::current(), false);
+    /// notifies it, or it is canceled.
+    pub fn wait(&self) -> WaitResult {
+        let curr = crate::current();
+        if curr.is_cancel_requested() {
+            return WaitResult::Canceled;
+        }
+        start_wait(&curr);
+        current_run_queue::<NoPreemptIrqSave>().blocked_resched(self.queue.lock(), true);
+        let canceled = curr.is_cancel_requested();
+        // Still in the wait queue, it must have been woken up by `cancel()`.
+        if self.cancel_events(curr, false) && canceled {
+            WaitResult::Canceled
+        } else {
+            WaitResult::Notified
+        }
     }
 
     /// Blocks the current task and put it into the wait queue, until the given
-    /// `condition` becomes true.
+    /// `condition` becomes true, or it is canceled.
     ///
     /// Note that even other tasks notify this task, it will not wake up until
     /// the condition becomes true.
-    pub fn wait_until<F>(&self, condition: F)
+    pub fn wait_until<F>(&self, condition: F) -> WaitResult
+    where
+        F: Fn() -> bool,
+    {
+        self.wait_until_inner(condition, true)
+    }
+
+    /// Same as [`wait_until`](Self::wait_until), but it is not a
+    /// cancellation point, i.e., it keeps waiting for the condition even if
+    /// the current task is canceled.
+    pub fn wait_until_noncancelable<F>(&self, condition: F)
+    where
+        F: Fn() -> bool,
+    {
+        self.wait_until_inner(condition, false);
+    }
+
+    fn wait_until_inner<F>(&self, condition: F, cancelable: bool) -> WaitResult
     where
         F: Fn() -> bool,
     {
         let curr = crate::current();
+        start_wait(&curr);
+        let mut result = WaitResult::Notified;
         loop {
             let mut rq = current_run_queue::<NoPreemptIrqSave>();
             let wq = self.queue.lock();
             if condition() {
                 break;
             }
-            rq.blocked_resched(wq);
+            if cancelable && curr.is_cancel_requested() {
+                result = WaitResult::Canceled;
+                break;
+            }
+            rq.blocked_resched(wq, cancelable);
             // Preemption may occur here.
         }
         self.cancel_events(curr, false);
+        result
     }
 
     /// Blocks the current task and put it into the wait queue, until other tasks
-    /// notify it, or the given duration has elapsed.
+    /// notify it, the given duration has elapsed, or it is canceled.
+    ///
+    /// A notification racing with the timeout is reported as
+    /// [`WaitResult::Notified`] if it has woken up this task, so it is never
+    /// lost nor counted twice.
     #[cfg(feature = "irq")]
-    pub fn wait_timeout(&self, dur: core::time::Duration) -> bool {
+    pub fn wait_timeout(&self, dur: core::time::Duration) -> WaitResult {
+        self.wait_timeout_remaining(dur).0
+    }
+
+    /// Same as [`wait_timeout`](Self::wait_timeout), but also returns the
+    /// part of the duration that has not elapsed, zero if timed out.
+    ///
+    /// It helps to wait again for the rest of a time budget, e.g., after a
+    /// wakeup where the awaited condition is still false (it needs the `irq`
+    /// feature):
+    ///
+    /// ```ignore
+    /// # use core::{sync::atomic::{AtomicBool, Ordering}, time::Duration};
+    /// # use axtask::{WaitQueue, WaitResult};
+    /// static READY: AtomicBool = AtomicBool::new(false);
+    /// static WQ: WaitQueue = WaitQueue::new();
+    ///
+    /// let mut budget = Duration::from_millis(100);
+    /// while !READY.load(Ordering::Acquire) {
+    ///     let (result, remaining) = WQ.wait_timeout_remaining(budget);
+    ///     if result == WaitResult::TimedOut {
+    ///         break;
+    ///     }
+    ///     budget = remaining;
+    /// }
+    /// ```
+    #[cfg(feature = "irq")]
+    pub fn wait_timeout_remaining(
+        &self,
+        dur: core::time::Duration,
+    ) -> (WaitResult, core::time::Duration) {
         let mut rq = current_run_queue::<NoPreemptIrqSave>();
         let curr = crate::current();
-        let deadline = axhal::time::wall_time() + dur;
+        if curr.is_cancel_requested() {
+            return (WaitResult::Canceled, dur);
+        }
+        let deadline = axhal::time::monotonic_time() + dur;
         debug!(
             "task wait_timeout: {} deadline={:?}",
             curr.id_name(),
@@ -116,27 +281,36 @@ impl WaitQueue {
         );
         crate::timers::set_alarm_wakeup(deadline, curr.clone());
 
-        rq.blocked_resched(self.queue.lock());
+        start_wait(&curr);
+        rq.blocked_resched(self.queue.lock(), true);
 
-        let timeout = curr.in_wait_queue(); // still in the wait queue, must have timed out
-
-        // Always try to remove the task from the timer list.
-        self.cancel_events(curr, true);
-        timeout
+        // Always try to remove the task from the timer list. Still in the
+        // wait queue, it must have timed out or been canceled.
+        let canceled = curr.is_cancel_requested();
+        let notified = !self.cancel_events(curr, true);
+        let remaining = deadline.saturating_sub(axhal::time::monotonic_time());
+        if notified {
+            (WaitResult::Notified, remaining)
+        } else if canceled {
+            (WaitResult::Canceled, remaining)
+        } else {
+            (WaitResult::TimedOut, core::time::Duration::ZERO)
+        }
     }
 
     /// Blocks the current task and put it into the wait queue, until the given
-    /// `condition` becomes true, or the given duration has elapsed.
+    /// `condition` becomes true, the given duration has elapsed, or it is
+    /// canceled.
     ///
     /// Note that even other tasks notify this task, it will not wake up until
     /// the above conditions are met.
     #[cfg(feature = "irq")]
-    pub fn wait_timeout_until<F>(&self, dur: core::time::Duration, condition: F) -> bool
+    pub fn wait_timeout_until<F>(&self, dur: core::time::Duration, condition: F) -> WaitResult
     where
         F: Fn() -> bool,
     {
         let curr = crate::current();
-        let deadline = axhal::time::wall_time() + dur;
+        let deadline = axhal::time::monotonic_time() + dur;
         debug!(
             "task wait_timeout: {}, deadline={:?}",
             curr.id_name(),
@@ -144,47 +318,58 @@ impl WaitQueue {
         );
         crate::timers::set_alarm_wakeup(deadline, curr.clone());
 
-        let mut timeout = true;
+        start_wait(&curr);
+        let mut result = WaitResult::TimedOut;
         loop {
             let mut rq = current_run_queue::<NoPreemptIrqSave>();
-            if axhal::time::wall_time() >= deadline {
+            if axhal::time::monotonic_time() >= deadline {
                 break;
             }
             let wq = self.queue.lock();
             if condition() {
-                timeout = false;
+                result = WaitResult::Notified;
+                break;
+            }
+            if curr.is_cancel_requested() {
+                result = WaitResult::Canceled;
                 break;
             }
 
-            rq.blocked_resched(wq);
+            rq.blocked_resched(wq, true);
             // Preemption may occur here.
         }
         // Always try to remove the task from the timer list.
         self.cancel_events(curr, true);
-        timeout
+        result
     }
 
-    /// Wakes up one task in the wait queue, usually the first one.
+    /// Wakes up the task that has waited the longest in the wait queue.
     ///
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
+    ///
+    /// Returns `true` if a task is woken up, or `false` if the queue is empty.
     pub fn notify_one(&self, resched: bool) -> bool {
         let mut wq = self.queue.lock();
-        if let Some(task) = wq.pop_front() {
-            unblock_one_task(task, resched);
+        // Skip the tasks woken up by other queues in `wait_any()`, which
+        // leave this one by themselves.
+        if let Some(index) = wq.iter().position(|t| t.take_in_wait_queue()) {
+            unblock_one_task(wq.remove(index).unwrap(), resched);
             true
         } else {
             false
         }
     }
 
-    /// Wakes all tasks in the wait queue.
+    /// Wakes all tasks in the wait queue, but not the ones that start to wait
+    /// after it is called.
     ///
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
     pub fn notify_all(&self, resched: bool) {
-        while self.notify_one(resched) {
-            // loop until the wait queue is empty
+        let mut wq = self.queue.lock();
+        for task in wq.drain(..) {
+            unblock_one_task(task, resched);
         }
     }
 
@@ -194,13 +379,121 @@ impl WaitQueue {
     /// preemption is enabled.
     pub fn notify_task(&mut self, resched: bool, task: &AxTaskRef) -> bool {
         let mut wq = self.queue.lock();
-        if let Some(index) = wq.iter().position(|t| Arc::ptr_eq(t, task)) {
-            unblock_one_task(wq.remove(index).unwrap(), resched);
-            true
+        match wq.iter().position(|t| Arc::ptr_eq(t, task)) {
+            Some(index) if task.take_in_wait_queue() => {
+                unblock_one_task(wq.remove(index).unwrap(), resched);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Blocks the current task on all the wait queues at once, until any of them
+/// notifies it, the timeout elapses, or it is canceled.
+///
+/// It is the same as [`wait_any_with`] with a readiness condition that is
+/// never true.
+pub fn wait_any(queues: &[&WaitQueue], timeout: Option<Duration>) -> WaitAnyResult {
+    wait_any_with(queues, timeout, || false)
+}
+
+/// Blocks the current task on all the wait queues at once, unless `ready`
+/// returns `true`, until any of them notifies it, the timeout elapses, or it
+/// is canceled.
+///
+/// `ready` is checked with all the queues locked, after which the task is in
+/// all of them, so an event that makes it true is not missed if it is
+/// notified on any of the queues. Unlike [`WaitQueue::wait_until`], the task
+/// sleeps at most once: it returns at the first notification, and `ready` is
+/// not checked again, e.g., for `poll()` to find out which files are ready.
+///
+/// The task is removed from all the queues before it returns. A
+/// [`notify_one`](WaitQueue::notify_one) on a queue after the task is woken
+/// up by another one goes to the next waiter of that queue, so it is not
+/// lost, and that queue is not reported as fired.
+///
+/// # Panics
+///
+/// Panics if a timeout is given without the `irq` feature.
+pub fn wait_any_with<F>(queues: &[&WaitQueue], timeout: Option<Duration>, ready: F) -> WaitAnyResult
+where
+    F: Fn() -> bool,
+{
+    let curr = crate::current();
+    #[cfg(feature = "irq")]
+    let deadline = timeout.map(|dur| axhal::time::monotonic_time() + dur);
+    #[cfg(feature = "irq")]
+    if let Some(deadline) = deadline {
+        crate::timers::set_alarm_wakeup(deadline, curr.clone());
+    }
+    #[cfg(feature = "irq")]
+    let expired = || deadline.is_some_and(|deadline| axhal::time::monotonic_time() >= deadline);
+    #[cfg(not(feature = "irq"))]
+    assert!(timeout.is_none(), "timeouts need the `irq` feature");
+    #[cfg(not(feature = "irq"))]
+    let expired = || false;
+
+    // Lock each queue once, in the order of the addresses, so that waits on
+    // overlapping queues do not deadlock.
+    let addr = |wq: &&WaitQueue| *wq as *const WaitQueue;
+    let mut locked = queues.to_vec();
+    locked.sort_unstable_by_key(addr);
+    locked.dedup_by_key(|wq| addr(wq));
+
+    start_wait(&curr);
+    let early_result = {
+        let mut rq = current_run_queue::<NoPreemptIrqSave>();
+        let wq_guards: Vec<_> = locked.iter().map(|wq| wq.queue.lock()).collect();
+        if ready() {
+            Some(WaitResult::Notified)
+        } else if curr.is_cancel_requested() {
+            Some(WaitResult::Canceled)
+        } else if expired() {
+            Some(WaitResult::TimedOut)
         } else {
-            false
+            rq.blocked_resched_any(wq_guards);
+            None
         }
+    };
+
+    let mut fired = Vec::new();
+    if early_result.is_none() {
+        // No queue wakes the task up from now on. The ones that have are
+        // those it is no longer in.
+        curr.set_in_wait_queue(false);
+        let fired_locked: Vec<bool> = locked
+            .iter()
+            .map(|wq| {
+                let mut wq = wq.queue.lock();
+                match wq.iter().position(|t| curr.ptr_eq(t)) {
+                    Some(index) => {
+                        wq.remove(index);
+                        false
+                    }
+                    None => true,
+                }
+            })
+            .collect();
+        fired = (0..queues.len())
+            .filter(|&i| {
+                let index = locked.binary_search_by_key(&addr(&queues[i]), addr);
+                fired_locked[index.unwrap()]
+            })
+            .collect();
     }
+    #[cfg(feature = "irq")]
+    if deadline.is_some() {
+        crate::timers::cancel_alarm_wakeup(curr.as_task_ref());
+    }
+
+    let result = match early_result {
+        Some(result) => result,
+        None if !fired.is_empty() => WaitResult::Notified,
+        None if curr.is_cancel_requested() => WaitResult::Canceled,
+        None => WaitResult::TimedOut,
+    };
+    WaitAnyResult { result, fired }
 }
 
 fn unblock_one_task(task: AxTaskRef, resched: bool) {
@@ -0,0 +1,246 @@
+//! Work queues for deferred work, e.g., from interrupt handlers.
+//!
+//! A [`WorkItem`] is a function to run later in task context, by the worker
+//! task of the CPU it is scheduled on. [`schedule`] never blocks nor
+//! allocates, as the work items are linked into a lock-free list, so it can
+//! be called in interrupt handlers.
+//!
+//! A work item is queued at most once: scheduling it again before it runs
+//! does nothing, while scheduling it when it is running makes it run again.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use axtask::workqueue::{self, WorkItem};
+//!
+//! static REFILL: WorkItem = WorkItem::new(refill_rx_ring);
+//!
+//! fn refill_rx_ring() { /* may block */ }
+//!
+//! fn irq_handler() {
+//!     workqueue::schedule(&REFILL);
+//! }
+//! ```
+
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicPtr, AtomicU8, Ordering};
+
+use axhal::cpu::this_cpu_id;
+use kernel_guard::NoPreemptIrqSave;
+
+use crate::WaitQueue;
+
+#[cfg(feature = "irq")]
+use crate::timers::TimerSlot;
+
+/// The work item is in the list of a work queue, so its `next` is in use.
+const QUEUED: u8 = 1 << 0;
+/// The work item is to be run, cleared when it is cancelled or starts to run.
+const PENDING: u8 = 1 << 1;
+
+/// A function to be run by the worker tasks, see the [module-level
+/// documentation](self).
+pub struct WorkItem {
+    func: fn(),
+    state: AtomicU8,
+    /// The next work item in the list of the work queue.
+    next: AtomicPtr<WorkItem>,
+    /// The timer set by [`schedule_delayed`], if any.
+    #[cfg(feature = "irq")]
+    timer: TimerSlot,
+}
+
+impl WorkItem {
+    /// Creates a new work item that runs `func`.
+    pub const fn new(func: fn()) -> Self {
+        Self {
+            func,
+            state: AtomicU8::new(0),
+            next: AtomicPtr::new(null_mut()),
+            #[cfg(feature = "irq")]
+            timer: TimerSlot::new(),
+        }
+    }
+
+    /// Whether the work item is scheduled and has not started to run, or its
+    /// delay has not expired.
+    pub fn is_pending(&self) -> bool {
+        #[cfg(feature = "irq")]
+        if self.timer.is_armed() {
+            return true;
+        }
+        self.state.load(Ordering::Acquire) & PENDING != 0
+    }
+
+    /// Returns the timer set by [`schedule_delayed`].
+    #[cfg(feature = "irq")]
+    pub(crate) fn timer(&self) -> &TimerSlot {
+        &self.timer
+    }
+}
+
+/// The work queue of a CPU, run by its worker task.
+struct WorkQueue {
+    /// The most recently queued work item, linked to the earlier ones.
+    head: AtomicPtr<WorkItem>,
+    worker_wq: WaitQueue,
+}
+
+impl WorkQueue {
+    const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(null_mut()),
+            worker_wq: WaitQueue::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire).is_null()
+    }
+
+    /// Links a work item with the `QUEUED` flag set by the caller into the
+    /// list, and wakes up the worker.
+    fn push(&self, work: &'static WorkItem) {
+        let work_ptr = work as *const WorkItem as *mut WorkItem;
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            work.next.store(head, Ordering::Relaxed);
+            match self.head.compare_exchange_weak(
+                head,
+                work_ptr,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => head = current,
+            }
+        }
+        self.worker_wq.notify_one(false);
+    }
+
+    /// Takes all the queued work items, in the order they are queued.
+    fn take_all(&self) -> Drain {
+        let mut head = self.head.swap(null_mut(), Ordering::Acquire);
+        // Reverse the list, no one else touches the links until the `QUEUED`
+        // flags are cleared.
+        let mut reversed = null_mut();
+        while !head.is_null() {
+            let work = unsafe { &*head };
+            head = work.next.load(Ordering::Relaxed);
+            work.next.store(reversed, Ordering::Relaxed);
+            reversed = work as *const WorkItem as *mut WorkItem;
+        }
+        Drain { next: reversed }
+    }
+}
+
+/// The work items taken out of a work queue.
+struct Drain {
+    next: *mut WorkItem,
+}
+
+impl Iterator for Drain {
+    type Item = &'static WorkItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next.is_null() {
+            return None;
+        }
+        // Safety: work items are `'static`, and the link is read before the
+        // item is given out, after which it may be queued again.
+        let work = unsafe { &*self.next };
+        self.next = work.next.load(Ordering::Relaxed);
+        Some(work)
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_QUEUE: WorkQueue = WorkQueue::new();
+static WORK_QUEUES: [WorkQueue; axconfig::SMP] = [EMPTY_QUEUE; axconfig::SMP];
+
+/// Schedules the work item to run on the current CPU.
+///
+/// It can be called in interrupt handlers. Returns `false` if the work item
+/// is already pending, in which case it runs only once.
+pub fn schedule(work: &'static WorkItem) -> bool {
+    let mut state = work.state.load(Ordering::Acquire);
+    loop {
+        if state & PENDING != 0 {
+            return false;
+        }
+        match work.state.compare_exchange_weak(
+            state,
+            state | PENDING | QUEUED,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => break,
+            Err(current) => state = current,
+        }
+    }
+    // A cancelled work item may be still in the list.
+    if state & QUEUED == 0 {
+        let _guard = NoPreemptIrqSave::new();
+        WORK_QUEUES[this_cpu_id()].push(work);
+    }
+    true
+}
+
+/// Schedules the work item to run on the current CPU after the given
+/// duration, or on another CPU if this one goes offline meanwhile.
+///
+/// Returns `false` if the work item is already pending or delayed.
+#[cfg(feature = "irq")]
+pub fn schedule_delayed(work: &'static WorkItem, after: core::time::Duration) -> bool {
+    if work.state.load(Ordering::Acquire) & PENDING != 0 {
+        return false;
+    }
+    let deadline = axhal::time::monotonic_time() + after;
+    crate::timers::set_work_timer(deadline, work)
+}
+
+/// Cancels the work item if it is pending or delayed.
+///
+/// Returns `true` if the work item was pending or delayed, and it will not
+/// run unless it is scheduled again. It does not wait for a running work
+/// item to finish.
+pub fn cancel(work: &'static WorkItem) -> bool {
+    #[cfg(feature = "irq")]
+    let delayed = crate::timers::cancel_work_timer(work);
+    #[cfg(not(feature = "irq"))]
+    let delayed = false;
+    // It is skipped by the worker if it is still queued.
+    let state = work.state.fetch_and(!PENDING, Ordering::AcqRel);
+    delayed || state & PENDING != 0
+}
+
+/// The routine of the worker task of the given CPU.
+pub(crate) fn worker_entry(cpu_id: usize) {
+    let queue = &WORK_QUEUES[cpu_id];
+    loop {
+        queue
+            .worker_wq
+            .wait_until_noncancelable(|| !queue.is_empty());
+        for work in queue.take_all() {
+            let state = work.state.fetch_and(!(QUEUED | PENDING), Ordering::AcqRel);
+            if state & PENDING != 0 {
+                (work.func)();
+            }
+        }
+    }
+}
+
+/// Moves the queued work items of the current CPU, which is going offline,
+/// to another online CPU.
+#[cfg(feature = "smp")]
+pub(crate) fn hand_over_work() {
+    let cpu_id = this_cpu_id();
+    let Some(target) = (0..axconfig::SMP).find(|&i| i != cpu_id && axhal::cpu::cpu_online(i))
+    else {
+        return;
+    };
+    for work in WORK_QUEUES[cpu_id].take_all() {
+        // The `QUEUED` flag is kept.
+        WORK_QUEUES[target].push(work);
+    }
+}
@@ -29,11 +29,32 @@ else ifeq ($(filter $(MAKECMDGOALS),clippy unittest unittest_no_fail_fast),) # n
   endif
 endif
 
+# Extracts the function symbols from the ELF, and only updates the symbol
+# table if it is changed, so that the kernel is not rebuilt again.
+define gen_ksyms
+  @$(NM) -n -C --defined-only $(OUT_ELF) | awk '$$2 ~ /^[TtWw]$$/' > $(OUT_KSYMS).tmp
+  @if cmp -s $(OUT_KSYMS).tmp $(OUT_KSYMS); then rm $(OUT_KSYMS).tmp; else mv $(OUT_KSYMS).tmp $(OUT_KSYMS); fi
+endef
+
+# Checks that the functions are at the same addresses as in the symbol table
+# embedded, i.e., the code is not moved by embedding it.
+define check_ksyms
+  @$(NM) -n -C --defined-only $(OUT_ELF) | awk '$$2 ~ /^[TtWw]$$/' | cmp -s - $(OUT_KSYMS) || \
+    { echo "error: the code is moved by the embedded symbol table, see $(OUT_KSYMS)"; exit 1; }
+endef
+
 _cargo_build:
 	@printf "    $(GREEN_C)Building$(END_C) App: $(APP_NAME), Arch: $(ARCH), Platform: $(PLATFORM_NAME), App type: $(APP_TYPE)\n"
 ifeq ($(APP_TYPE), rust)
 	$(call cargo_build,$(APP),$(AX_FEAT) $(LIB_FEAT) $(APP_FEAT))
 	@cp $(rust_elf) $(OUT_ELF)
+ifneq ($(filter ksyms,$(FEATURES)),)
+	@# build again with the symbol table of the first build embedded, the code is not moved
+	$(call gen_ksyms)
+	$(call cargo_build,$(APP),$(AX_FEAT) $(LIB_FEAT) $(APP_FEAT))
+	@cp $(rust_elf) $(OUT_ELF)
+	$(call check_ksyms)
+endif
 else ifeq ($(APP_TYPE), c)
 	$(call cargo_build,ulib/axlibc,$(AX_FEAT) $(LIB_FEAT))
 endif
@@ -44,4 +65,15 @@ $(OUT_DIR):
 $(OUT_BIN): _cargo_build $(OUT_ELF)
 	$(call run_cmd,$(OBJCOPY),$(OUT_ELF) --strip-all -O binary $@)
 
+# The raw binary starts with a PE header with the `uefi` feature, padded to
+# the file alignment in the header.
+$(OUT_EFI): $(OUT_BIN)
+	$(call run_cmd,mkdir,-p $(dir $@))
+	$(call run_cmd,cp,$< $@)
+	$(call run_cmd,truncate,-s %4096 $@)
+
+ifeq ($(UEFI), y)
+  build: $(OUT_EFI)
+endif
+
 .PHONY: _cargo_build
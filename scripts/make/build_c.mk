@@ -17,7 +17,7 @@ ulib_obj := $(patsubst $(src_dir)/%.c,$(obj_dir)/%.o,$(ulib_src))
 CFLAGS += $(addprefix -DAX_CONFIG_,$(shell echo $(lib_feat) | tr 'a-z' 'A-Z' | tr '-' '_'))
 CFLAGS += -DAX_LOG_$(shell echo $(LOG) | tr 'a-z' 'A-Z')
 
-CFLAGS += -nostdinc -fno-builtin -ffreestanding -Wall
+CFLAGS += -nostdinc -fno-builtin -ffreestanding -fno-omit-frame-pointer -Wall
 CFLAGS += -I$(CURDIR)/$(inc_dir)
 LDFLAGS += -nostdlib -static -no-pie --gc-sections -znostart-stop-gc -T$(LD_SCRIPT)
 
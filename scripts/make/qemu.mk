@@ -10,9 +10,16 @@ else
   $(error "BUS" must be one of "mmio" or "pci")
 endif
 
-qemu_args-x86_64 := \
-  -machine q35 \
-  -kernel $(OUT_ELF)
+ifeq ($(UEFI), y)
+  qemu_args-x86_64 := \
+    -machine q35 \
+    -bios $(OVMF) \
+    -drive format=raw,file=fat:rw:$(OUT_DIR)/efi
+else
+  qemu_args-x86_64 := \
+    -machine q35 \
+    -kernel $(OUT_ELF)
+endif
 
 qemu_args-riscv64 := \
   -machine virt \
@@ -21,23 +28,42 @@ qemu_args-riscv64 := \
 
 qemu_args-aarch64 := \
   -cpu cortex-a72 \
-  -machine virt \
+  -machine virt,gic-version=$(GIC_VERSION),virtualization=$(if $(filter y,$(EL2)),on,off) \
   -kernel $(OUT_BIN)
 
-qemu_args-y := -m 128M -smp $(SMP) $(qemu_args-$(ARCH))
+qemu_args-loongarch64 := \
+  -machine virt \
+  -kernel $(OUT_ELF)
 
-qemu_args-$(BLK) += \
-  -device virtio-blk-$(vdev-suffix),drive=disk0 \
-  -drive id=disk0,if=none,format=raw,file=$(DISK_IMG)
+qemu_args-y := -m 128M -smp $(SMP) $(qemu_args-$(ARCH))
 
-qemu_args-$(NET) += \
-  -device igb,netdev=net0
-  # -device virtio-net-$(vdev-suffix),netdev=net0
+ifeq ($(DISK_DEV), nvme)
+  qemu_args-$(BLK) += -device nvme,serial=deadbeef,drive=disk0
+else ifeq ($(DISK_DEV), sdhci)
+  qemu_args-$(BLK) += -device sdhci-pci -device sd-card,drive=disk0
+else ifeq ($(DISK_DEV), usb-storage)
+  qemu_args-$(BLK) += -device qemu-xhci -device usb-storage,drive=disk0
+else
+  qemu_args-$(BLK) += -device virtio-blk-$(vdev-suffix),drive=disk0
+endif
+qemu_args-$(BLK) += -drive id=disk0,if=none,format=raw,file=$(DISK_IMG)
+
+ifeq ($(NIC), virtio-net)
+  ifeq ($(shell test $(NET_QUEUES) -gt 1; echo $$?),0)
+    qemu_args-$(NET) += \
+      -device virtio-net-$(vdev-suffix),netdev=net0,mq=on,vectors=$(shell echo $$(($(NET_QUEUES) * 2 + 2)))
+    netdev-queues := ,queues=$(NET_QUEUES)
+  else
+    qemu_args-$(NET) += -device virtio-net-$(vdev-suffix),netdev=net0
+  endif
+else
+  qemu_args-$(NET) += -device $(NIC),netdev=net0
+endif
 
 ifeq ($(NET_DEV), user)
   qemu_args-$(NET) += -netdev user,id=net0,hostfwd=tcp::5555-:5555,hostfwd=udp::5555-:5555
 else ifeq ($(NET_DEV), tap)
-  qemu_args-$(NET) += -netdev tap,id=net0,script=scripts/net/qemu-ifup.sh,downscript=no,vhost=$(VHOST),vhostforce=$(VHOST)
+  qemu_args-$(NET) += -netdev tap,id=net0,script=scripts/net/qemu-ifup.sh,downscript=no,vhost=$(VHOST),vhostforce=$(VHOST)$(netdev-queues)
   QEMU := sudo $(QEMU)
 else ifeq ($(NET_DEV), bridge)
   qemu_args-$(NET) += -netdev bridge,id=net0,br=virbr0
@@ -63,6 +89,28 @@ ifeq ($(GRAPHIC), n)
   qemu_args-y += -nographic
 endif
 
+ifeq ($(VCONSOLE), y)
+  qemu_args-y += \
+    -chardev pty,id=vcon0 \
+    -device virtio-serial-$(vdev-suffix) \
+    -device virtconsole,chardev=vcon0
+endif
+
+qemu_args-$(RNG) += -device virtio-rng-$(vdev-suffix)
+qemu_args-$(BALLOON) += -device virtio-balloon-$(vdev-suffix)
+qemu_args-$(SOUND) += \
+  -audiodev wav,id=snd0,path=sound.wav \
+  -device virtio-sound-$(vdev-suffix),audiodev=snd0
+qemu_args-$(INPUT) += \
+  -device virtio-keyboard-$(vdev-suffix) \
+  -device virtio-tablet-$(vdev-suffix)
+
+ifneq ($(VIRTFS),)
+  qemu_args-y += \
+    -fsdev local,id=fsdev0,path=$(VIRTFS),security_model=none \
+    -device virtio-9p-$(vdev-suffix),fsdev=fsdev0,mount_tag=arceos
+endif
+
 ifeq ($(QEMU_LOG), y)
   qemu_args-y += -D qemu.log -d in_asm,int,mmu,pcall,cpu_reset,guest_errors
 endif
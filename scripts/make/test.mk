@@ -3,4 +3,7 @@
 define unit_test
   $(call run_cmd,cargo test,-p axfs $(1) --features "myfs" -- --nocapture)
   $(call run_cmd,cargo test,--workspace $(1) -- --nocapture)
+  $(call run_cmd,cargo test,-p axsync $(1) --features "deadlock-detect" -- --nocapture)
+  $(call run_cmd,cargo test,-p axsync $(1) --features "sched_prio" -- --nocapture)
+  $(call run_cmd,cargo test,-p axtask $(1) --features "tickless" -- --nocapture)
 endef
@@ -107,9 +107,7 @@
 //! 2. Once finished with architectural setup, the arch code calls `kernel_init()`.
 
 #![allow(clippy::upper_case_acronyms)]
-#![feature(asm_const)]
 #![feature(format_args_nl)]
-#![feature(panic_info_message)]
 #![feature(trait_alias)]
 #![no_main]
 #![no_std]
@@ -30,13 +30,6 @@ int getsockopt(int fd, int level, int optname, void *restrict optval, socklen_t
     return -1;
 }
 
-int setsockopt(int fd, int level, int optname, const void *optval, socklen_t optlen)
-{
-    unimplemented("fd: %d, level: %d, optname: %d, optval: %d, optlen: %d", fd, level, optname,
-                  *(int *)optval, optlen);
-    return 0;
-}
-
 // TODO
 ssize_t sendmsg(int fd, const struct msghdr *msg, int flags)
 {
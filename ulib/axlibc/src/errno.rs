@@ -1,22 +1,37 @@
 use axerrno::LinuxError;
 use core::ffi::{c_char, c_int};
 
-/// The global errno variable.
-#[cfg_attr(feature = "tls", thread_local)]
-#[no_mangle]
+#[cfg(feature = "multitask")]
+arceos_posix_api::task_local! {
+    /// The errno of each task.
+    static ERRNO: core::cell::Cell<c_int> = core::cell::Cell::new(0);
+}
+
+/// The global errno variable, without the `multitask` feature.
+#[cfg(not(feature = "multitask"))]
 #[allow(non_upper_case_globals)]
-pub static mut errno: c_int = 0;
+static mut errno: c_int = 0;
 
 pub fn set_errno(code: i32) {
+    #[cfg(feature = "multitask")]
+    ERRNO.set(code);
+    #[cfg(not(feature = "multitask"))]
     unsafe {
         errno = code;
     }
 }
 
-/// Returns a pointer to the global errno variable.
+/// Returns a pointer to the errno variable of the current task.
 #[no_mangle]
 pub unsafe extern "C" fn __errno_location() -> *mut c_int {
-    core::ptr::addr_of_mut!(errno)
+    #[cfg(feature = "multitask")]
+    {
+        ERRNO.with(|errno| errno.as_ptr())
+    }
+    #[cfg(not(feature = "multitask"))]
+    {
+        core::ptr::addr_of_mut!(errno)
+    }
 }
 
 /// Returns a pointer to the string representation of the given error code.
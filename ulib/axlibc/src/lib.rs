@@ -28,7 +28,6 @@
 #![cfg_attr(all(not(test), not(doc)), no_std)]
 #![feature(doc_cfg)]
 #![feature(doc_auto_cfg)]
-#![feature(naked_functions)]
 #![feature(thread_local)]
 #![allow(clippy::missing_safety_doc)]
 
@@ -73,6 +72,7 @@ mod io;
 mod mktime;
 mod rand;
 mod resource;
+mod sched;
 mod setjmp;
 mod sys;
 mod time;
@@ -85,7 +85,10 @@ pub use self::io::{read, writev};
 pub use self::errno::strerror;
 pub use self::mktime::mktime;
 pub use self::rand::{rand, random, srand};
-pub use self::resource::{getrlimit, setrlimit};
+pub use self::resource::{getpriority, getrlimit, setpriority, setrlimit};
+pub use self::sched::{
+    sched_getaffinity, sched_getscheduler, sched_setaffinity, sched_setscheduler,
+};
 pub use self::setjmp::{longjmp, setjmp};
 pub use self::sys::sysconf;
 pub use self::time::{clock_gettime, nanosleep};
@@ -1,6 +1,6 @@
-use core::ffi::c_int;
+use core::ffi::{c_int, c_uint};
 
-use arceos_posix_api::{sys_getrlimit, sys_setrlimit};
+use arceos_posix_api::{sys_getpriority, sys_getrlimit, sys_setpriority, sys_setrlimit};
 
 use crate::utils::e;
 
@@ -15,3 +15,23 @@ pub unsafe extern "C" fn getrlimit(resource: c_int, rlimits: *mut crate::ctypes:
 pub unsafe extern "C" fn setrlimit(resource: c_int, rlimits: *mut crate::ctypes::rlimit) -> c_int {
     e(sys_setrlimit(resource, rlimits))
 }
+
+/// Get the nice value of the current task
+///
+/// A return value of -1 is also a valid nice value, `errno` must be cleared
+/// before the call to tell it from an error.
+#[no_mangle]
+pub unsafe extern "C" fn getpriority(which: c_int, who: c_uint) -> c_int {
+    let ret = e(sys_getpriority(which, who));
+    if ret < 0 {
+        ret
+    } else {
+        20 - ret
+    }
+}
+
+/// Set the nice value of the current task
+#[no_mangle]
+pub unsafe extern "C" fn setpriority(which: c_int, who: c_uint, prio: c_int) -> c_int {
+    e(sys_setpriority(which, who, prio))
+}
@@ -0,0 +1,43 @@
+use core::ffi::c_int;
+
+use arceos_posix_api::{
+    sys_sched_getaffinity, sys_sched_getscheduler, sys_sched_setaffinity, sys_sched_setscheduler,
+};
+
+use crate::{ctypes, utils::e};
+
+/// Set the CPU affinity of the current thread
+#[no_mangle]
+pub unsafe extern "C" fn sched_setaffinity(
+    pid: c_int,
+    cpusetsize: usize,
+    mask: *const ctypes::cpu_set_t,
+) -> c_int {
+    e(sys_sched_setaffinity(pid, cpusetsize, mask))
+}
+
+/// Get the CPU affinity of the current thread
+#[no_mangle]
+pub unsafe extern "C" fn sched_getaffinity(
+    pid: c_int,
+    cpusetsize: usize,
+    mask: *mut ctypes::cpu_set_t,
+) -> c_int {
+    e(sys_sched_getaffinity(pid, cpusetsize, mask))
+}
+
+/// Set the scheduling policy and the real-time priority of a thread
+#[no_mangle]
+pub unsafe extern "C" fn sched_setscheduler(
+    pid: c_int,
+    policy: c_int,
+    param: *const ctypes::sched_param,
+) -> c_int {
+    e(sys_sched_setscheduler(pid, policy, param))
+}
+
+/// Get the scheduling policy of a thread
+#[no_mangle]
+pub unsafe extern "C" fn sched_getscheduler(pid: c_int) -> c_int {
+    e(sys_sched_getscheduler(pid))
+}
@@ -76,7 +76,9 @@ impl Read for Stdin {
             if read_len > 0 {
                 return Ok(read_len);
             }
-            crate::thread::yield_now();
+            if !arceos_api::stdio::ax_console_wait_readable() {
+                crate::thread::yield_now();
+            }
         }
     }
 }
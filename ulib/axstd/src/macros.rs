@@ -21,3 +21,14 @@ macro_rules! println {
         $crate::io::__print_impl(format_args!("{}\n", format_args!($($arg)*)));
     }
 }
+
+/// Declares new thread local storage keys of type [`std::thread::LocalKey`].
+///
+/// [`std::thread::LocalKey`]: crate::thread::LocalKey
+#[cfg(feature = "multitask")]
+#[macro_export]
+macro_rules! thread_local {
+    ($($tt:tt)*) => {
+        $crate::os::arceos::modules::axtask::task_local!($($tt)*);
+    }
+}
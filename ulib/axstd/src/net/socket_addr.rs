@@ -14,8 +14,8 @@ pub use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, Socket
 ///
 ///  * [`SocketAddr`]: [`to_socket_addrs`] is the identity function.
 ///
-///  * [`SocketAddrV4`], <code>([IpAddr], [u16])</code>,
-///    <code>([Ipv4Addr], [u16])</code>:
+///  * [`SocketAddrV4`], [`SocketAddrV6`], <code>([IpAddr], [u16])</code>,
+///    <code>([Ipv4Addr], [u16])</code>, <code>([Ipv6Addr], [u16])</code>:
 ///    [`to_socket_addrs`] constructs a [`SocketAddr`] trivially.
 ///
 ///  * <code>(&[str], [u16])</code>: <code>&[str]</code> should be either a string representation
@@ -50,6 +50,13 @@ impl ToSocketAddrs for SocketAddrV4 {
     }
 }
 
+impl ToSocketAddrs for SocketAddrV6 {
+    type Iter = option::IntoIter<SocketAddr>;
+    fn to_socket_addrs(&self) -> io::Result<option::IntoIter<SocketAddr>> {
+        SocketAddr::V6(*self).to_socket_addrs()
+    }
+}
+
 impl ToSocketAddrs for (IpAddr, u16) {
     type Iter = option::IntoIter<SocketAddr>;
     fn to_socket_addrs(&self) -> io::Result<option::IntoIter<SocketAddr>> {
@@ -66,6 +73,14 @@ impl ToSocketAddrs for (Ipv4Addr, u16) {
     }
 }
 
+impl ToSocketAddrs for (Ipv6Addr, u16) {
+    type Iter = option::IntoIter<SocketAddr>;
+    fn to_socket_addrs(&self) -> io::Result<option::IntoIter<SocketAddr>> {
+        let (ip, port) = *self;
+        SocketAddrV6::new(ip, port, 0, 0).to_socket_addrs()
+    }
+}
+
 impl<'a> ToSocketAddrs for &'a [SocketAddr] {
     type Iter = iter::Cloned<slice::Iter<'a, SocketAddr>>;
 
@@ -91,12 +106,9 @@ mod no_dns {
         fn to_socket_addrs(&self) -> io::Result<option::IntoIter<SocketAddr>> {
             let (host, port) = *self;
             Ok(host
-                .parse::<Ipv4Addr>()
+                .parse::<IpAddr>()
                 .ok()
-                .map(|addr| {
-                    let addr = SocketAddrV4::new(addr, port);
-                    SocketAddr::V4(addr)
-                })
+                .map(|addr| SocketAddr::new(addr, port))
                 .into_iter())
         }
     }
@@ -136,9 +148,8 @@ mod dns {
             let (host, port) = *self;
 
             // try to parse the host as a regular IP address first
-            if let Ok(addr) = host.parse::<Ipv4Addr>() {
-                let addr = SocketAddrV4::new(addr, port);
-                return Ok(vec![SocketAddr::V4(addr)].into_iter());
+            if let Ok(addr) = host.parse::<IpAddr>() {
+                return Ok(vec![SocketAddr::new(addr, port)].into_iter());
             }
 
             Ok(arceos_api::net::ax_dns_query(host)?
@@ -43,6 +43,35 @@ impl TcpStream {
     pub fn shutdown(&self) -> io::Result<()> {
         api::ax_tcp_shutdown(&self.0)
     }
+
+    /// Sets the value of the `TCP_NODELAY` option on this socket.
+    ///
+    /// If set, this option disables the Nagle algorithm. This means that
+    /// segments are always sent as soon as possible, even if there is only a
+    /// small amount of data. When not set, data is buffered until there is a
+    /// sufficient amount to send out, thereby avoiding the frequent sending of
+    /// small packets.
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        api::ax_tcp_set_nodelay(&self.0, nodelay)
+    }
+
+    /// Gets the value of the `TCP_NODELAY` option on this socket.
+    pub fn nodelay(&self) -> io::Result<bool> {
+        api::ax_tcp_nodelay(&self.0)
+    }
+
+    /// Sets the value for the `IP_TTL` option on this socket.
+    ///
+    /// This value sets the time-to-live field that is used in every packet
+    /// sent from this socket.
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        set_ttl(&self.0, ttl)
+    }
+
+    /// Gets the value of the `IP_TTL` option for this socket.
+    pub fn ttl(&self) -> io::Result<u32> {
+        api::ax_tcp_ttl(&self.0).map(u32::from)
+    }
 }
 
 impl Read for TcpStream {
@@ -74,6 +103,9 @@ impl TcpListener {
     /// The address type can be any implementor of [`ToSocketAddrs`] trait. See
     /// its documentation for concrete examples.
     ///
+    /// As on Unix, the `SO_REUSEADDR` option is set on the listener, so a
+    /// server can be restarted while its closed connections are in TIME-WAIT.
+    ///
     /// If `addr` yields multiple addresses, `bind` will be attempted with
     /// each of the addresses until one succeeds and returns the listener. If
     /// none of the addresses succeed in creating a listener, the error returned
@@ -83,6 +115,7 @@ impl TcpListener {
             let addr = addr?;
             let backlog = 128;
             let socket = api::ax_tcp_socket();
+            api::ax_tcp_set_reuse_addr(&socket, true)?;
             api::ax_tcp_bind(&socket, *addr)?;
             api::ax_tcp_listen(&socket, backlog)?;
             Ok(TcpListener(socket))
@@ -102,4 +135,24 @@ impl TcpListener {
     pub fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
         api::ax_tcp_accept(&self.0).map(|(a, b)| (TcpStream(a), b))
     }
+
+    /// Sets the value for the `IP_TTL` option on this socket.
+    ///
+    /// This value sets the time-to-live field that is used in every packet
+    /// sent from this socket, and is inherited by the accepted streams.
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        set_ttl(&self.0, ttl)
+    }
+
+    /// Gets the value of the `IP_TTL` option for this socket.
+    pub fn ttl(&self) -> io::Result<u32> {
+        api::ax_tcp_ttl(&self.0).map(u32::from)
+    }
+}
+
+fn set_ttl(socket: &AxTcpSocketHandle, ttl: u32) -> io::Result<()> {
+    match u8::try_from(ttl) {
+        Ok(ttl) => api::ax_tcp_set_ttl(socket, ttl),
+        Err(_) => axerrno::ax_err!(InvalidInput, "TTL out of range"),
+    }
 }
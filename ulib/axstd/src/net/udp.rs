@@ -1,5 +1,6 @@
 use super::{SocketAddr, ToSocketAddrs};
 use crate::io;
+use core::time::Duration;
 
 use arceos_api::net::{self as api, AxUdpSocketHandle};
 
@@ -93,4 +94,83 @@ impl UdpSocket {
     pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
         api::ax_udp_recv(&self.0, buf)
     }
+
+    /// Sets the read timeout to the timeout specified.
+    ///
+    /// If the value specified is [`None`], then [`recv`](Self::recv) and
+    /// [`recv_from`](Self::recv_from) calls will block indefinitely. Otherwise
+    /// they return an error of kind [`WouldBlock`](io::Error::WouldBlock)
+    /// when the timeout elapses. An [`Err`] is returned if the zero
+    /// [`Duration`] is passed to this method.
+    pub fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        api::ax_udp_set_read_timeout(&self.0, dur)
+    }
+
+    /// Returns the read timeout of this socket.
+    ///
+    /// If the timeout is [`None`], then [`recv`](Self::recv) and
+    /// [`recv_from`](Self::recv_from) calls will block indefinitely.
+    pub fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        api::ax_udp_read_timeout(&self.0)
+    }
+
+    /// Sets the write timeout to the timeout specified.
+    ///
+    /// If the value specified is [`None`], then [`send`](Self::send) and
+    /// [`send_to`](Self::send_to) calls will block indefinitely. Otherwise
+    /// they return an error of kind [`WouldBlock`](io::Error::WouldBlock)
+    /// when the timeout elapses. An [`Err`] is returned if the zero
+    /// [`Duration`] is passed to this method.
+    pub fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        api::ax_udp_set_write_timeout(&self.0, dur)
+    }
+
+    /// Returns the write timeout of this socket.
+    ///
+    /// If the timeout is [`None`], then [`send`](Self::send) and
+    /// [`send_to`](Self::send_to) calls will block indefinitely.
+    pub fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        api::ax_udp_write_timeout(&self.0)
+    }
+
+    /// Sets the value of the `SO_BROADCAST` option for this socket.
+    ///
+    /// When enabled, this socket is allowed to send packets to a broadcast
+    /// address.
+    pub fn set_broadcast(&self, broadcast: bool) -> io::Result<()> {
+        api::ax_udp_set_broadcast(&self.0, broadcast)
+    }
+
+    /// Gets the value of the `SO_BROADCAST` option for this socket.
+    pub fn broadcast(&self) -> io::Result<bool> {
+        api::ax_udp_broadcast(&self.0)
+    }
+
+    /// Sets the value for the `IP_TTL` option on this socket.
+    ///
+    /// This value sets the time-to-live field that is used in every packet
+    /// sent from this socket.
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        match u8::try_from(ttl) {
+            Ok(ttl) => api::ax_udp_set_ttl(&self.0, ttl),
+            Err(_) => axerrno::ax_err!(InvalidInput, "TTL out of range"),
+        }
+    }
+
+    /// Gets the value of the `IP_TTL` option for this socket.
+    pub fn ttl(&self) -> io::Result<u32> {
+        api::ax_udp_ttl(&self.0).map(u32::from)
+    }
+
+    /// Moves this UDP socket into or out of nonblocking mode.
+    ///
+    /// This will result in `recv`, `recv_from`, `send`, and `send_to`
+    /// operations becoming nonblocking, i.e., immediately returning from their
+    /// calls. If the IO operation is successful, `Ok` is returned and no
+    /// further action is required. If the IO operation could not be completed
+    /// and needs to be retried, an error with kind
+    /// [`io::Error::WouldBlock`] is returned.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        api::ax_udp_set_nonblocking(&self.0, nonblocking)
+    }
 }
@@ -6,9 +6,15 @@ use crate::io;
 use alloc::{string::String, sync::Arc};
 use core::{cell::UnsafeCell, num::NonZeroU64};
 
+use arceos_api::modules::axtask;
 use arceos_api::task::{self as api, AxTaskHandle};
 use axerrno::ax_err_type;
 
+/// A thread local storage key which owns its contents, declared by
+/// [`thread_local!`](crate::thread_local). Each thread is a task of ArceOS,
+/// so it is a task-local storage key.
+pub use arceos_api::modules::axtask::TaskLocalKey as LocalKey;
+
 /// A unique identifier for a running thread.
 #[derive(Eq, PartialEq, Clone, Copy, Debug)]
 pub struct ThreadId(NonZeroU64);
@@ -68,6 +74,8 @@ impl Builder {
     }
 
     /// Sets the size of the stack (in bytes) for the new thread.
+    ///
+    /// [`spawn`](Self::spawn) fails if it is too small.
     pub fn stack_size(mut self, size: usize) -> Builder {
         self.stack_size = Some(size);
         self
@@ -115,13 +123,40 @@ impl Builder {
             drop(their_packet);
         };
 
-        let task = api::ax_spawn(main, name, stack_size);
+        let task = api::ax_spawn(main, name, stack_size)?;
         Ok(JoinHandle {
             thread: Thread::from_id(task.id()),
             native: task,
             packet: my_packet,
         })
     }
+
+    /// Spawns a new scoped thread using the settings set through this
+    /// `Builder`, see [`Scope::spawn`].
+    pub fn spawn_scoped<'scope, 'env, F, T>(
+        self,
+        scope: &'scope Scope<'scope, 'env>,
+        f: F,
+    ) -> io::Result<ScopedJoinHandle<'scope, T>>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let stack_size = self
+            .stack_size
+            .unwrap_or(arceos_api::config::TASK_STACK_SIZE);
+        if stack_size < axtask::MIN_STACK_SIZE {
+            return Err(ax_err_type!(InvalidInput, "stack size too small"));
+        }
+        let native = axtask::Builder::new()
+            .name(self.name.unwrap_or_default())
+            .stack_size(stack_size)
+            .spawn_scoped(&scope.0, f);
+        Ok(ScopedJoinHandle {
+            thread: Thread::from_id(native.task().id().as_u64()),
+            native,
+        })
+    }
 }
 
 /// Gets a handle to the thread that invokes it.
@@ -130,6 +165,20 @@ pub fn current() -> Thread {
     Thread::from_id(id)
 }
 
+/// Sets the priority of the current thread.
+///
+/// The range and meaning of the priority depend on the scheduler, e.g., the
+/// nice value from -20 to 19 with `sched_cfs`, or the priority from -16 (the
+/// most urgent) to 15 with `sched_prio`.
+pub fn set_current_priority(prio: isize) -> io::Result<()> {
+    api::ax_set_current_priority(prio)
+}
+
+/// Returns the priority of the current thread, see [`set_current_priority`].
+pub fn current_priority() -> isize {
+    api::ax_current_priority()
+}
+
 /// Spawns a new thread, returning a [`JoinHandle`] for it.
 ///
 /// The join handle provides a [`join`] method that can be used to join the
@@ -173,17 +222,96 @@ impl<T> JoinHandle<T> {
         &self.thread
     }
 
+    /// Sets the priority of the associated thread, see
+    /// [`set_current_priority`].
+    pub fn set_priority(&self, prio: isize) -> io::Result<()> {
+        api::ax_set_priority(&self.native, prio)
+    }
+
     /// Waits for the associated thread to finish.
     ///
     /// This function will return immediately if the associated thread has
-    /// already finished.
+    /// already finished. It returns an error if the thread is terminated
+    /// without returning, e.g., on a stack overflow.
     pub fn join(mut self) -> io::Result<T> {
         api::ax_wait_for_exit(self.native).ok_or_else(|| ax_err_type!(BadState))?;
+        // A terminated thread never releases its packet.
         Arc::get_mut(&mut self.packet)
-            .unwrap()
-            .result
-            .get_mut()
-            .take()
+            .and_then(|packet| packet.result.get_mut().take())
             .ok_or_else(|| ax_err_type!(BadState))
     }
 }
+
+/// A scope to spawn scoped threads in, see [`scope`].
+#[repr(transparent)]
+pub struct Scope<'scope, 'env: 'scope>(axtask::Scope<'scope, 'env>);
+
+/// Creates a scope for spawning scoped threads.
+///
+/// The function passed to `scope` will be provided a [`Scope`] object,
+/// through which scoped threads can be [spawned][`Scope::spawn`]. Unlike
+/// non-scoped threads, scoped threads can borrow non-`'static` data, as the
+/// scope guarantees all threads will be joined at the end of the scope.
+///
+/// All threads spawned within the scope that haven't been manually joined
+/// will be automatically joined before this function returns.
+///
+/// # Panics
+///
+/// If any of the automatically joined threads terminated without returning,
+/// e.g., on a stack overflow, this function will panic.
+pub fn scope<'env, F, T>(f: F) -> T
+where
+    F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+{
+    axtask::scope(|scope| {
+        // SAFETY: `Scope` is a transparent wrapper of `axtask::Scope`.
+        f(unsafe { &*(scope as *const axtask::Scope<'_, 'env>).cast::<Scope<'_, 'env>>() })
+    })
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// Spawns a new thread within a scope, returning a [`ScopedJoinHandle`]
+    /// for it.
+    ///
+    /// Unlike non-scoped threads, threads spawned with this function may
+    /// borrow non-`'static` data from the outside the scope. See [`scope`]
+    /// for details.
+    pub fn spawn<F, T>(&'scope self, f: F) -> ScopedJoinHandle<'scope, T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        Builder::new()
+            .spawn_scoped(self, f)
+            .expect("failed to spawn thread")
+    }
+}
+
+/// An owned permission to join on a scoped thread (block on its
+/// termination).
+pub struct ScopedJoinHandle<'scope, T> {
+    native: axtask::ScopedJoinHandle<'scope, T>,
+    thread: Thread,
+}
+
+impl<T> ScopedJoinHandle<'_, T> {
+    /// Extracts a handle to the underlying thread.
+    pub fn thread(&self) -> &Thread {
+        &self.thread
+    }
+
+    /// Checks if the associated thread has finished running its main
+    /// function.
+    pub fn is_finished(&self) -> bool {
+        self.native.is_finished()
+    }
+
+    /// Waits for the associated thread to finish.
+    ///
+    /// It returns an error if the thread is terminated without returning,
+    /// in which case the scope does not panic for it.
+    pub fn join(self) -> io::Result<T> {
+        self.native.join().ok_or_else(|| ax_err_type!(BadState))
+    }
+}